@@ -18,6 +18,12 @@ const RUN_ARGS: &[&str] = &[
     "usb-mouse",
     "-device",
     "usb-kbd",
+    "-device",
+    "virtio-rng-pci",
+    "-netdev",
+    "user,id=net0,hostfwd=tcp:127.0.0.1:10007-:7,hostfwd=udp:127.0.0.1:10007-:7,hostfwd=tcp:127.0.0.1:10023-:23",
+    "-device",
+    "virtio-net-pci,netdev=net0",
     "-gdb",
     "tcp::1234",
     "-no-reboot",
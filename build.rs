@@ -105,6 +105,29 @@ fn build_fs() -> Result<()> {
     let mut sabios = root_dir.create_file("sabios.txt")?;
     sabios.truncate()?;
     writeln!(&mut sabios, "hello sabios!")?;
+    let mut tz = root_dir.create_file("tz.cfg")?;
+    tz.truncate()?;
+    writeln!(&mut tz, "# offset of the local time from UTC (e.g. +09:00)")?;
+    writeln!(&mut tz, "+00:00")?;
+    let mut font = root_dir.create_file("font.cfg")?;
+    font.truncate()?;
+    writeln!(
+        &mut font,
+        "# font files (PSF2 or packed) in the root directory or \"builtin\", and scale (1-4)"
+    )?;
+    writeln!(
+        &mut font,
+        "# characters missing in a font are looked up in the next one (e.g. ter16.psf,jp16.psf 1)"
+    )?;
+    writeln!(&mut font, "builtin 1")?;
+    let mut theme = root_dir.create_file("theme.cfg")?;
+    theme.truncate()?;
+    writeln!(&mut theme, "# color theme (light or dark)")?;
+    writeln!(
+        &mut theme,
+        "# colors of the preset are overridden by lines of `<name> = #rrggbb` (e.g. desktop_bg = #2d76ed)"
+    )?;
+    writeln!(&mut theme, "preset = light")?;
 
     // create object file
     let mut objcopy_cmd = Command::new(objcopy);
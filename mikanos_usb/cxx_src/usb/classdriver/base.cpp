@@ -1,7 +0,0 @@
-#include "usb/classdriver/base.hpp"
-
-namespace usb {
-ClassDriver::ClassDriver(Device *dev) : dev_{dev} {}
-
-ClassDriver::~ClassDriver() {}
-} // namespace usb
@@ -1,6 +1,14 @@
-use crate::{memory, paging, prelude::*, sync::OnceCell};
-use core::{mem, slice};
-use x86_64::{instructions::port::PortReadOnly, structures::paging::OffsetPageTable, VirtAddr};
+#[cfg(feature = "acpi-s3")]
+use crate::sync::SpinMutex;
+use crate::{
+    cpu, local_apic, memory,
+    paging::{self, Protection},
+    prelude::*,
+    sync::OnceCell,
+};
+use arrayvec::ArrayVec;
+use core::{convert::TryFrom, mem, slice};
+use x86_64::{instructions::port::Port, structures::paging::OffsetPageTable, PhysAddr, VirtAddr};
 
 /// Root System Description Pointer
 #[derive(Debug)]
@@ -99,19 +107,195 @@ impl Xsdt {
     }
 }
 
-/// Extended System Descriptor Table
+/// Multiple APIC Description Table
+#[derive(Debug)]
+#[repr(C)]
+struct Madt {
+    header: DescriptionHeader,
+    local_apic_address: u32,
+    flags: u32,
+}
+static_assertions::const_assert_eq!(mem::size_of::<Madt>(), 44);
+
+impl Madt {
+    /// Returns the type and the body of each interrupt controller structure.
+    fn entries(&self) -> impl Iterator<Item = (u8, &[u8])> {
+        let data = unsafe {
+            slice::from_raw_parts(
+                (self as *const Madt).add(1) as *const u8,
+                self.header.len() - mem::size_of::<Madt>(),
+            )
+        };
+        let mut rest = data;
+        core::iter::from_fn(move || {
+            let (&ty, &len) = (rest.get(0)?, rest.get(1)?);
+            let len = usize::from(len);
+            if len < 2 || len > rest.len() {
+                return None;
+            }
+            let (entry, tail) = rest.split_at(len);
+            rest = tail;
+            Some((ty, &entry[2..]))
+        })
+    }
+}
+
+/// PCI Express memory mapped configuration space base address Description Table
+#[derive(Debug)]
+#[repr(C)]
+struct Mcfg {
+    header: DescriptionHeader,
+    _reserved: [u8; 8],
+}
+static_assertions::const_assert_eq!(mem::size_of::<Mcfg>(), 44);
+
+impl Mcfg {
+    const ENTRY_SIZE: usize = 16;
+
+    fn entries(&self) -> impl Iterator<Item = PciConfigRegion> + '_ {
+        let data = unsafe {
+            slice::from_raw_parts(
+                (self as *const Mcfg).add(1) as *const u8,
+                self.header.len() - mem::size_of::<Mcfg>(),
+            )
+        };
+        data.chunks_exact(Self::ENTRY_SIZE).map(|entry| {
+            let mut base = [0; 8];
+            base.copy_from_slice(&entry[0..8]);
+            PciConfigRegion {
+                base: PhysAddr::new(u64::from_le_bytes(base)),
+                segment: u16::from_le_bytes([entry[8], entry[9]]),
+                start_bus: entry[10],
+                end_bus: entry[11],
+            }
+        })
+    }
+}
+
+/// Fixed ACPI Description Table
 #[derive(Debug)]
 #[repr(C)]
 struct Fadt {
     header: DescriptionHeader,
-    reserved: [u8; 76 - mem::size_of::<DescriptionHeader>()],
+    firmware_ctrl: u32,
+    dsdt: u32,
+    reserved: [u8; 64 - 44],
+    pm1a_cnt_blk: u32,
+    pm1b_cnt_blk: u32,
+    reserved2: [u8; 76 - 72],
     pm_tmr_blk: u32,
-    reserved2: [u8; 112 - 80],
+    reserved3: [u8; 108 - 80],
+    century: u8,
+    reserved4: [u8; 112 - 109],
     flags: u32,
-    reserved3: [u8; 276 - 116],
+    reserved5: [u8; 132 - 116],
+    x_firmware_ctrl: [u8; 8],
+    x_dsdt: [u8; 8],
+    reserved6: [u8; 276 - 148],
+}
+static_assertions::const_assert_eq!(mem::size_of::<Fadt>(), 276);
+
+impl Fadt {
+    #[cfg(feature = "acpi-s3")]
+    fn facs_address(&self) -> u64 {
+        match u64::from_le_bytes(self.x_firmware_ctrl) {
+            0 => u64::from(self.firmware_ctrl),
+            addr => addr,
+        }
+    }
+
+    fn dsdt_address(&self) -> u64 {
+        match u64::from_le_bytes(self.x_dsdt) {
+            0 => u64::from(self.dsdt),
+            addr => addr,
+        }
+    }
 }
 
+#[cfg(feature = "acpi-s3")]
+/// Firmware ACPI Control Structure
+#[derive(Debug)]
+#[repr(C)]
+struct Facs {
+    signature: [u8; 4],
+    length: u32,
+    hardware_signature: u32,
+    firmware_waking_vector: u32,
+    global_lock: u32,
+    flags: u32,
+    x_firmware_waking_vector: u64,
+    version: u8,
+    reserved: [u8; 3],
+    ospm_flags: u32,
+    reserved2: [u8; 24],
+}
+#[cfg(feature = "acpi-s3")]
+static_assertions::const_assert_eq!(mem::size_of::<Facs>(), 64);
+
 static FADT: OnceCell<&Fadt> = OnceCell::uninit();
+#[cfg(feature = "acpi-s3")]
+static FACS: OnceCell<SpinMutex<&'static mut Facs>> = OnceCell::uninit();
+/// AML byte code in Differentiated System Description Table
+static DSDT: OnceCell<&'static [u8]> = OnceCell::uninit();
+
+static PLATFORM_INFO: OnceCell<PlatformInfo> = OnceCell::uninit();
+static PCI_CONFIG_REGIONS: OnceCell<ArrayVec<PciConfigRegion, MAX_PCI_CONFIG_REGIONS>> =
+    OnceCell::uninit();
+
+const MAX_IO_APICS: usize = 8;
+const MAX_INTERRUPT_OVERRIDES: usize = 16;
+const MAX_PCI_CONFIG_REGIONS: usize = 4;
+
+/// Interrupt controllers described by MADT.
+#[derive(Debug, Clone)]
+pub(crate) struct PlatformInfo {
+    pub(crate) local_apic_address: PhysAddr,
+    /// APIC IDs of the usable processors, including the bootstrap processor.
+    pub(crate) local_apic_ids: ArrayVec<u8, { cpu::MAX_CPUS }>,
+    pub(crate) io_apics: ArrayVec<IoApic, MAX_IO_APICS>,
+    pub(crate) interrupt_overrides: ArrayVec<InterruptOverride, MAX_INTERRUPT_OVERRIDES>,
+    /// The legacy 8259 PICs are present and must be masked to use I/O APICs.
+    pub(crate) has_legacy_pics: bool,
+}
+
+impl Default for PlatformInfo {
+    fn default() -> Self {
+        Self {
+            local_apic_address: PhysAddr::new(local_apic::DEFAULT_BASE),
+            local_apic_ids: ArrayVec::new(),
+            io_apics: ArrayVec::new(),
+            interrupt_overrides: ArrayVec::new(),
+            has_legacy_pics: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct IoApic {
+    pub(crate) id: u8,
+    pub(crate) address: PhysAddr,
+    /// The first global system interrupt number handled by the I/O APIC.
+    pub(crate) gsi_base: u32,
+}
+
+/// Mapping of an ISA IRQ to a global system interrupt which differs from the identity mapping.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct InterruptOverride {
+    pub(crate) irq: u8,
+    pub(crate) gsi: u32,
+    /// MPS INTI flags (polarity and trigger mode)
+    pub(crate) flags: u16,
+}
+
+/// The memory-mapped configuration space (ECAM) of a PCI segment group described by MCFG.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PciConfigRegion {
+    /// The address of the configuration space of bus 0, even if `start_bus` is not 0.
+    pub(crate) base: PhysAddr,
+    pub(crate) segment: u16,
+    pub(crate) start_bus: u8,
+    pub(crate) end_bus: u8,
+}
 
 /// # Safety
 ///
@@ -162,35 +346,345 @@ pub(crate) unsafe fn init(mapper: &mut OffsetPageTable, rsdp: VirtAddr) -> Resul
 
     FADT.init_once(|| fadt);
 
+    // without MADT, only the bootstrap processor is used
+    if let Err(err) = unsafe { init_madt(mapper, xsdt) } {
+        warn!("failed to initialize MADT: {}", err);
+        PLATFORM_INFO.init_once(PlatformInfo::default);
+    }
+
+    // without MCFG, PCI configuration space is accessed through I/O ports
+    if let Err(err) = unsafe { init_mcfg(mapper, xsdt) } {
+        warn!("failed to initialize MCFG: {}", err);
+    }
+
+    // DSDT and FACS are needed only for power management, so failures are not fatal
+    if let Err(err) = unsafe { init_dsdt(mapper, fadt) } {
+        warn!("failed to initialize DSDT: {}", err);
+    }
+    #[cfg(feature = "acpi-s3")]
+    if let Err(err) = unsafe { init_facs(mapper, fadt) } {
+        warn!("failed to initialize FACS: {}", err);
+    }
+
     Ok(())
 }
 
-pub(crate) const PM_TIMER_FREQ: u32 = 3579545;
+/// Finds the table of `signature` in XSDT, and maps the whole table.
+///
+/// # Safety
+///
+/// This function is unsafe because the caller must guarantee that `xsdt` points to valid XSDT.
+unsafe fn find_table(
+    mapper: &mut OffsetPageTable,
+    xsdt: &Xsdt,
+    signature: &[u8; 4],
+) -> Result<Option<&'static DescriptionHeader>> {
+    for addr in xsdt.entries() {
+        map_range(mapper, addr, mem::size_of::<DescriptionHeader>())?;
+        #[allow(clippy::unwrap_used)]
+        let header = unsafe { (addr as *const DescriptionHeader).as_ref() }.unwrap();
+        if header.signature == *signature {
+            map_range(mapper, addr, header.len())?;
+            return Ok(Some(header));
+        }
+    }
+    Ok(None)
+}
 
-pub(crate) fn wait_milliseconds(msec: u32) {
-    let fadt = FADT.get();
-    let pm_timer_32 = ((fadt.flags >> 8) & 1) != 0;
+/// # Safety
+///
+/// This function is unsafe because the caller must guarantee that `xsdt` points to valid XSDT.
+unsafe fn init_madt(mapper: &mut OffsetPageTable, xsdt: &Xsdt) -> Result<()> {
+    let header = match unsafe { find_table(mapper, xsdt, b"APIC") }? {
+        Some(header) => header,
+        None => {
+            info!("MADT not found");
+            PLATFORM_INFO.init_once(PlatformInfo::default);
+            return Ok(());
+        }
+    };
+    debug!("MADT: {:x}", header as *const _ as u64);
+    if header.len() < mem::size_of::<Madt>() || !header.is_valid(b"APIC") {
+        bail!(ErrorKind::InvalidMadt);
+    }
+    #[allow(clippy::unwrap_used)]
+    let madt = unsafe { (header as *const DescriptionHeader as *const Madt).as_ref() }.unwrap();
+
+    const PROCESSOR_LOCAL_APIC: u8 = 0;
+    const IO_APIC: u8 = 1;
+    const INTERRUPT_SOURCE_OVERRIDE: u8 = 2;
+    const LOCAL_APIC_ADDRESS_OVERRIDE: u8 = 5;
+    const ENABLED: u32 = 1 << 0;
+    const ONLINE_CAPABLE: u32 = 1 << 1;
+    const PCAT_COMPAT: u32 = 1 << 0;
+    let u32_at = |body: &[u8], pos: usize| {
+        u32::from_le_bytes([body[pos], body[pos + 1], body[pos + 2], body[pos + 3]])
+    };
 
-    let mut port = PortReadOnly::<u32>::new(fadt.pm_tmr_blk as u16);
-    let start = unsafe { port.read() };
-    let mut end = start + PM_TIMER_FREQ * msec / 1000;
-    if !pm_timer_32 {
-        end &= 0x00ffffff;
+    let mut info = PlatformInfo {
+        local_apic_address: PhysAddr::new(u64::from(madt.local_apic_address)),
+        has_legacy_pics: madt.flags & PCAT_COMPAT != 0,
+        ..PlatformInfo::default()
+    };
+    for (ty, body) in madt.entries() {
+        match ty {
+            PROCESSOR_LOCAL_APIC if body.len() >= 6 => {
+                let apic_id = body[1];
+                let flags = u32_at(body, 2);
+                if flags & (ENABLED | ONLINE_CAPABLE) == 0 {
+                    continue;
+                }
+                if info.local_apic_ids.try_push(apic_id).is_err() {
+                    warn!("too many local APICs, APIC ID {} is ignored", apic_id);
+                }
+            }
+            IO_APIC if body.len() >= 10 => {
+                let io_apic = IoApic {
+                    id: body[0],
+                    address: PhysAddr::new(u64::from(u32_at(body, 2))),
+                    gsi_base: u32_at(body, 6),
+                };
+                if info.io_apics.try_push(io_apic).is_err() {
+                    warn!("too many I/O APICs, I/O APIC ID {} is ignored", io_apic.id);
+                }
+            }
+            INTERRUPT_SOURCE_OVERRIDE if body.len() >= 8 => {
+                let interrupt_override = InterruptOverride {
+                    irq: body[1],
+                    gsi: u32_at(body, 2),
+                    flags: u16::from_le_bytes([body[6], body[7]]),
+                };
+                if info
+                    .interrupt_overrides
+                    .try_push(interrupt_override)
+                    .is_err()
+                {
+                    warn!(
+                        "too many interrupt source overrides, IRQ {} is ignored",
+                        interrupt_override.irq
+                    );
+                }
+            }
+            LOCAL_APIC_ADDRESS_OVERRIDE if body.len() >= 10 => {
+                let mut bytes = [0; 8];
+                bytes.copy_from_slice(&body[2..10]);
+                info.local_apic_address = PhysAddr::new(u64::from_le_bytes(bytes));
+            }
+            _ => {}
+        }
     }
+    debug!("{:?}", info);
+    PLATFORM_INFO.init_once(|| info);
+    Ok(())
+}
 
-    if end < start {
-        while unsafe { port.read() } >= start {}
+/// # Safety
+///
+/// This function is unsafe because the caller must guarantee that `xsdt` points to valid XSDT.
+unsafe fn init_mcfg(mapper: &mut OffsetPageTable, xsdt: &Xsdt) -> Result<()> {
+    let header = match unsafe { find_table(mapper, xsdt, b"MCFG") }? {
+        Some(header) => header,
+        None => {
+            info!("MCFG not found");
+            return Ok(());
+        }
+    };
+    debug!("MCFG: {:x}", header as *const _ as u64);
+    if header.len() < mem::size_of::<Mcfg>() || !header.is_valid(b"MCFG") {
+        bail!(ErrorKind::InvalidMcfg);
+    }
+    #[allow(clippy::unwrap_used)]
+    let mcfg = unsafe { (header as *const DescriptionHeader as *const Mcfg).as_ref() }.unwrap();
+
+    let mut regions = ArrayVec::new();
+    for region in mcfg.entries() {
+        debug!("{:?}", region);
+        if regions.try_push(region).is_err() {
+            warn!(
+                "too many PCI configuration regions, segment {} is ignored",
+                region.segment
+            );
+        }
     }
-    while unsafe { port.read() } < end {}
+    PCI_CONFIG_REGIONS.init_once(|| regions);
+    Ok(())
+}
+
+/// # Safety
+///
+/// This function is unsafe because the caller must guarantee that `fadt` points to valid FADT.
+unsafe fn init_dsdt(mapper: &mut OffsetPageTable, fadt: &Fadt) -> Result<()> {
+    let addr = fadt.dsdt_address();
+    debug!("DSDT: {:x}", addr);
+    map_range(mapper, addr, mem::size_of::<DescriptionHeader>())?;
+    #[allow(clippy::unwrap_used)]
+    let header = unsafe { (addr as *const DescriptionHeader).as_ref() }.unwrap();
+    map_range(mapper, addr, header.len())?;
+    if !header.is_valid(b"DSDT") {
+        bail!(ErrorKind::InvalidDsdt);
+    }
+
+    let aml = unsafe {
+        slice::from_raw_parts(
+            (header as *const DescriptionHeader).add(1) as *const u8,
+            header.len() - mem::size_of::<DescriptionHeader>(),
+        )
+    };
+    DSDT.init_once(|| aml);
+    Ok(())
+}
+
+/// # Safety
+///
+/// This function is unsafe because the caller must guarantee that `fadt` points to valid FADT.
+#[cfg(feature = "acpi-s3")]
+unsafe fn init_facs(mapper: &mut OffsetPageTable, fadt: &Fadt) -> Result<()> {
+    let addr = fadt.facs_address();
+    debug!("FACS: {:x}", addr);
+    map_range(mapper, addr, mem::size_of::<Facs>())?;
+    #[allow(clippy::unwrap_used)]
+    let facs = unsafe { (addr as *mut Facs).as_mut() }.unwrap();
+    if facs.signature != *b"FACS" {
+        bail!(ErrorKind::InvalidFacs);
+    }
+    FACS.init_once(move || SpinMutex::new(facs));
+    Ok(())
+}
+
+/// Returns the interrupt controllers described by MADT, or `None` if ACPI is not initialized.
+///
+/// Without MADT, only the default local APIC address is known.
+pub(crate) fn platform_info() -> Option<&'static PlatformInfo> {
+    PLATFORM_INFO.try_get().ok()
+}
+
+/// Returns the ECAM regions described by MCFG, which is empty if MCFG is not found.
+pub(crate) fn pci_config_regions() -> &'static [PciConfigRegion] {
+    PCI_CONFIG_REGIONS
+        .try_get()
+        .map_or(&[], |regions| regions.as_slice())
+}
+
+/// Reads an integer constant of AML.
+fn aml_integer(bytes: &mut impl Iterator<Item = u8>) -> Option<u16> {
+    match bytes.next()? {
+        0x00 => Some(0),                     // ZeroOp
+        0x01 => Some(1),                     // OneOp
+        0x0a => bytes.next().map(u16::from), // BytePrefix
+        0x0b => {
+            // WordPrefix
+            let lo = bytes.next()?;
+            let hi = bytes.next()?;
+            Some(u16::from_le_bytes([lo, hi]))
+        }
+        _ => None,
+    }
+}
+
+/// Returns `SLP_TYPa` and `SLP_TYPb` of the sleep state object `name` (e.g. `_S3_`) in DSDT.
+///
+/// This does not interpret AML, but looks for `Name (_Sx, Package () { a, b, ... })`, which is
+/// how almost all firmwares define sleep states.
+pub(crate) fn sleep_type(name: &[u8; 4]) -> Option<(u16, u16)> {
+    let aml = DSDT.try_get().ok()?;
+    (1..aml.len().saturating_sub(4)).find_map(|pos| {
+        if &aml[pos..pos + 4] != name {
+            return None;
+        }
+        // NameOp, optionally followed by RootChar
+        let name_op = match aml[pos - 1] {
+            b'\\' if pos >= 2 => aml[pos - 2],
+            op => op,
+        };
+        if name_op != 0x08 {
+            return None;
+        }
+
+        let mut bytes = aml[pos + 4..].iter().copied();
+        if bytes.next()? != 0x12 {
+            // not a PackageOp
+            return None;
+        }
+        let lead = bytes.next()?;
+        for _ in 0..(lead >> 6) {
+            // skip following bytes of PkgLength
+            bytes.next()?;
+        }
+        let _num_elements = bytes.next()?;
+        let slp_typ_a = aml_integer(&mut bytes)?;
+        let slp_typ_b = aml_integer(&mut bytes)?;
+        Some((slp_typ_a, slp_typ_b))
+    })
+}
+
+#[cfg(feature = "acpi-s3")]
+/// Sets the address where firmware jumps in real mode on resume from sleep states.
+pub(crate) fn set_waking_vector(addr: PhysAddr) -> Result<()> {
+    let mut facs = FACS.try_get()?.lock();
+    facs.firmware_waking_vector = u32::try_from(addr.as_u64())?;
+    facs.x_firmware_waking_vector = 0;
+    Ok(())
+}
+
+/// Makes the system enter the sleep state by writing `SLP_TYPx` and `SLP_EN` to PM1 control
+/// registers.
+///
+/// This function returns only if the system failed to enter the sleep state.
+pub(crate) fn enter_sleep_state((slp_typ_a, slp_typ_b): (u16, u16)) {
+    const SLP_TYP_SHIFT: u16 = 10;
+    const SLP_TYP_MASK: u16 = 0x7 << SLP_TYP_SHIFT;
+    const SLP_EN: u16 = 1 << 13;
+
+    let fadt = FADT.get();
+    for (blk, slp_typ) in [
+        (fadt.pm1a_cnt_blk, slp_typ_a),
+        (fadt.pm1b_cnt_blk, slp_typ_b),
+    ] {
+        if blk == 0 {
+            continue;
+        }
+        let mut port = Port::<u16>::new(blk as u16);
+        let value = unsafe { port.read() } & !SLP_TYP_MASK;
+        unsafe { port.write(value | (slp_typ << SLP_TYP_SHIFT) | SLP_EN) };
+    }
+}
+
+/// Returns the index of the RTC CMOS register which holds the century, if any.
+pub(crate) fn rtc_century_register() -> Option<u8> {
+    match FADT.try_get().ok()?.century {
+        0 => None,
+        index => Some(index),
+    }
+}
+
+/// Returns the I/O port of the PM timer and whether the counter is 32 bits wide, or `None` if
+/// the PM timer is not supported.
+pub(crate) fn pm_timer_block() -> Option<(u16, bool)> {
+    let fadt = FADT.try_get().ok()?;
+    let is_32bit = ((fadt.flags >> 8) & 1) != 0;
+    let port = u16::try_from(fadt.pm_tmr_blk)
+        .ok()
+        .filter(|port| *port != 0)?;
+    Some((port, is_32bit))
 }
 
 fn map_page(mapper: &mut OffsetPageTable, addr: VirtAddr) -> Result<()> {
-    let mut allocator = memory::lock_memory_manager();
-    paging::make_identity_mapping(
-        mapper,
-        &mut *allocator,
-        addr.align_down(4096u64).as_u64(),
-        1,
-    )?;
+    memory::with_memory_manager(|allocator| {
+        paging::make_identity_mapping(mapper, allocator, addr.align_down(4096u64).as_u64(), 1)
+    })?;
     Ok(())
 }
+
+fn map_range(mapper: &mut OffsetPageTable, addr: u64, len: usize) -> Result<()> {
+    let start = PhysAddr::new(addr).align_down(4096u64);
+    let end = PhysAddr::new(addr + len as u64).align_up(4096u64);
+    memory::with_memory_manager(|allocator| {
+        paging::ensure_identity_mapping(
+            mapper,
+            allocator,
+            start.as_u64(),
+            ((end - start) / 4096) as usize,
+            Protection::Writable,
+        )
+    })
+}
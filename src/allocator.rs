@@ -1,51 +1,170 @@
-use crate::{interrupt, prelude::*, sync::SpinMutex};
+use crate::{
+    cpu, interrupt,
+    memory::{self, BitmapMemoryManager},
+    paging::{self, Protection},
+    prelude::*,
+    random,
+    sync::{OnceCell, SpinMutex, SpinMutexGuard},
+};
+use arrayvec::ArrayVec;
 use core::{
     alloc::{GlobalAlloc, Layout},
-    mem,
+    hint, mem,
+    ops::Range,
     ptr::{self, NonNull},
 };
 use x86_64::{
     instructions::interrupts,
-    structures::paging::{
-        mapper::MapToError, FrameAllocator, Mapper, Page, PageTableFlags, Size4KiB,
-    },
+    structures::paging::{OffsetPageTable, Page, PageSize, PhysFrame, Size2MiB, Size4KiB},
     VirtAddr,
 };
 
+#[cfg(feature = "alloc-tracking")]
+pub(crate) mod tracking;
+
 #[global_allocator]
-static ALLOCATOR: SpinMutex<FixedSizeBlockAllocator> =
-    SpinMutex::new(FixedSizeBlockAllocator::new());
+static ALLOCATOR: Allocator = Allocator::new();
 
-pub const HEAP_START: usize = 0x_4444_4444_0000;
 pub const HEAP_SIZE: usize = 64 * 512 * 4096; // 128MiB
 
-pub(crate) fn init_heap(
-    mapper: &mut impl Mapper<Size4KiB>,
-    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
-) -> Result<()> {
-    let page_range = {
-        let heap_start = VirtAddr::new(HEAP_START as u64);
-        let heap_end = heap_start + HEAP_SIZE - 1u64;
-        let heap_start_page = Page::containing_address(heap_start);
-        let heap_end_page = Page::containing_address(heap_end);
-        Page::range_inclusive(heap_start_page, heap_end_page)
-    };
+/// Level 4 page table entries where the heap may be placed.
+const HEAP_LEVEL_4_ENTRIES: Range<usize> = 128..256;
+const LEVEL_4_ENTRY_SIZE: u64 = 512 * 1024 * 1024 * 1024;
+
+/// The number of frames reserved for heap pages touched while the memory manager is locked.
+const RESERVED_FRAMES: usize = 16;
 
-    for page in page_range {
-        let frame = frame_allocator
-            .allocate_frame()
-            .ok_or(MapToError::FrameAllocationFailed)?;
-        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
-        unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
+static HEAP_START: OnceCell<VirtAddr> = OnceCell::uninit();
+
+/// Frames mapped to the heap when the memory manager is locked by the faulting code or another
+/// CPU, since the page fault handler cannot wait for the lock.
+///
+/// The lock also serializes the heap page faults of all CPUs.
+static FRAME_RESERVE: SpinMutex<ArrayVec<PhysFrame, RESERVED_FRAMES>> =
+    SpinMutex::new(ArrayVec::new_const());
+
+/// Chooses a random 2 MiB aligned heap base in unused level 4 entries.
+fn choose_heap_start(mapper: &mut OffsetPageTable) -> VirtAddr {
+    let level_4_table = mapper.level_4_table();
+    loop {
+        let index =
+            random::range(HEAP_LEVEL_4_ENTRIES.start as u64..HEAP_LEVEL_4_ENTRIES.end as u64);
+        if !level_4_table[index as usize].is_unused() {
+            continue;
+        }
+        let max_offset = (LEVEL_4_ENTRY_SIZE - HEAP_SIZE as u64) / Size2MiB::SIZE;
+        let offset = random::range(0..max_offset + 1) * Size2MiB::SIZE;
+        return VirtAddr::new(index * LEVEL_4_ENTRY_SIZE + offset);
     }
+}
 
+pub(crate) fn init_heap(
+    mapper: &mut OffsetPageTable,
+    allocator: &mut BitmapMemoryManager,
+) -> Result<()> {
+    let heap_start = choose_heap_start(mapper);
+    info!(
+        "heap: {:?}..{:?}",
+        heap_start,
+        heap_start + HEAP_SIZE as u64
+    );
+    // heap pages are mapped on the first access by `map_heap_page`
+    paging::allocate_page_tables(mapper, allocator, heap_start, HEAP_SIZE as u64)?;
+    HEAP_START.init_once(|| heap_start);
+
+    // The allocator writes its header to the first page. The page fault handler cannot map it
+    // because the frame allocator is locked by the caller.
+    map_heap_frame(heap_start, allocator.allocate(1)?.start)?;
+    refill_frame_reserve(allocator);
     unsafe {
-        ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE);
+        ALLOCATOR
+            .depot
+            .lock()
+            .init(heap_start.as_u64() as usize, HEAP_SIZE);
     }
 
     Ok(())
 }
 
+pub(crate) fn is_heap_address(addr: VirtAddr) -> bool {
+    match HEAP_START.try_get() {
+        Ok(start) => (*start..*start + HEAP_SIZE as u64).contains(&addr),
+        Err(_) => false,
+    }
+}
+
+/// Fills up the frames reserved for the heap. This must be called with interrupts disabled.
+pub(crate) fn refill_frame_reserve(allocator: &mut BitmapMemoryManager) {
+    fill_frame_reserve(&mut lock_frame_reserve(), allocator);
+}
+
+/// Locks the frames reserved for the heap, waiting for another CPU to release them.
+///
+/// The lock is never held while touching the heap, so it is never held by the current CPU.
+fn lock_frame_reserve() -> SpinMutexGuard<'static, ArrayVec<PhysFrame, RESERVED_FRAMES>> {
+    loop {
+        if let Ok(reserve) = FRAME_RESERVE.try_lock() {
+            return reserve;
+        }
+        hint::spin_loop();
+    }
+}
+
+fn fill_frame_reserve(
+    reserve: &mut ArrayVec<PhysFrame, RESERVED_FRAMES>,
+    allocator: &mut BitmapMemoryManager,
+) {
+    while !reserve.is_full() {
+        match allocator.allocate(1) {
+            Ok(frames) => reserve.push(frames.start),
+            Err(_) => break,
+        }
+    }
+}
+
+/// Maps a zero-filled frame to the heap page containing `addr`.
+///
+/// Called from the page fault handler when the heap is accessed for the first time.
+pub(crate) fn map_heap_page(addr: VirtAddr) -> Result<()> {
+    assert!(is_heap_address(addr));
+
+    // Another CPU may fault on the same page at the same time. The faults are serialized by the
+    // reserve lock, and the later one finds the page already mapped.
+    let mut reserve = lock_frame_reserve();
+    // Safety: heap page tables are modified only with the reserve lock held
+    if unsafe { paging::is_preallocated_page_mapped(Page::containing_address(addr))? } {
+        return Ok(());
+    }
+
+    // the interrupted code may hold the lock, and waiting for it never ends
+    let frame = match memory::try_lock_memory_manager() {
+        Ok(mut allocator) => {
+            let frame = allocator.allocate(1)?.start;
+            fill_frame_reserve(&mut reserve, &mut *allocator);
+            frame
+        }
+        Err(_) => match reserve.pop() {
+            Some(frame) => frame,
+            None => bail!(ErrorKind::NoEnoughMemory),
+        },
+    };
+    map_heap_frame(addr, frame)
+}
+
+fn map_heap_frame(addr: VirtAddr, frame: PhysFrame) -> Result<()> {
+    unsafe {
+        ptr::write_bytes(
+            paging::phys_to_virt(frame.start_address()).as_mut_ptr::<u8>(),
+            0,
+            Size4KiB::SIZE as usize,
+        )
+    };
+    let page = Page::containing_address(addr);
+    // Safety: heap page tables are modified only here, with the reserve lock held or before
+    // other CPUs are started
+    unsafe { paging::map_preallocated_page(page, frame, Protection::Writable) }
+}
+
 #[alloc_error_handler]
 fn alloc_error_handler(layout: core::alloc::Layout) -> ! {
     panic!("allocation error {:?}", layout)
@@ -61,6 +180,9 @@ struct ListNode {
 /// the block alignment (alignments must be always powers of 2).
 const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 2048];
 
+/// The number of blocks a magazine can hold.
+const MAGAZINE_SIZE: usize = 32;
+
 /// Choose an appropriate block size for the given layout.
 ///
 /// Returns an index into the `BLOCK_SIZES` array.
@@ -139,27 +261,30 @@ impl FixedSizeBlockAllocator {
     /// [`handle_alloc_error`]: alloc::alloc::handle_alloc_error
     unsafe fn alloc(&mut self, layout: Layout) -> *mut u8 {
         match list_index(&layout) {
-            Some(index) => {
-                match self.list_heads[index].take() {
-                    Some(node) => {
-                        self.list_heads[index] = node.next.take();
-                        node as *mut ListNode as *mut u8
-                    }
-                    None => {
-                        // no block exist in list => allocate new block
-                        let block_size = BLOCK_SIZES[index];
-                        // only works if all block sizes are power of 2
-                        let block_align = block_size;
-                        #[allow(clippy::unwrap_used)]
-                        let layout = Layout::from_size_align(block_size, block_align).unwrap();
-                        self.fallback_alloc(layout)
-                    }
-                }
-            }
+            Some(index) => self.alloc_block(index),
             None => self.fallback_alloc(layout),
         }
     }
 
+    /// Allocates a block of `BLOCK_SIZES[index]` bytes.
+    fn alloc_block(&mut self, index: usize) -> *mut u8 {
+        match self.list_heads[index].take() {
+            Some(node) => {
+                self.list_heads[index] = node.next.take();
+                node as *mut ListNode as *mut u8
+            }
+            None => {
+                // no block exist in list => allocate new block
+                let block_size = BLOCK_SIZES[index];
+                // only works if all block sizes are power of 2
+                let block_align = block_size;
+                #[allow(clippy::unwrap_used)]
+                let layout = Layout::from_size_align(block_size, block_align).unwrap();
+                self.fallback_alloc(layout)
+            }
+        }
+    }
+
     /// Deallocate the block of memory at the given `ptr` pointer with the given `layout`.
     ///
     /// # Safety
@@ -174,19 +299,7 @@ impl FixedSizeBlockAllocator {
     ///   to allocate that block of memory.
     unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
         match list_index(&layout) {
-            Some(index) => {
-                let new_node = ListNode {
-                    next: self.list_heads[index].take(),
-                };
-                // verify that block has size and alignment required for storing node
-                assert!(mem::size_of::<ListNode>() <= BLOCK_SIZES[index]);
-                assert!(mem::align_of::<ListNode>() <= BLOCK_SIZES[index]);
-                let new_node_ptr = ptr as *mut ListNode;
-                unsafe {
-                    new_node_ptr.write(new_node);
-                    self.list_heads[index] = Some(&mut *new_node_ptr);
-                }
-            }
+            Some(index) => unsafe { self.dealloc_block(index, ptr) },
             None => {
                 #[allow(clippy::unwrap_used)]
                 let ptr = NonNull::new(ptr).unwrap();
@@ -196,19 +309,173 @@ impl FixedSizeBlockAllocator {
             }
         }
     }
+
+    /// Returns a block of `BLOCK_SIZES[index]` bytes to the free list.
+    ///
+    /// # Safety
+    ///
+    /// This function is unsafe because the caller must guarantee that `ptr` denotes
+    /// a block allocated by [`Self::alloc_block`] with the same `index`.
+    unsafe fn dealloc_block(&mut self, index: usize, ptr: *mut u8) {
+        let new_node = ListNode {
+            next: self.list_heads[index].take(),
+        };
+        // verify that block has size and alignment required for storing node
+        assert!(mem::size_of::<ListNode>() <= BLOCK_SIZES[index]);
+        assert!(mem::align_of::<ListNode>() <= BLOCK_SIZES[index]);
+        let new_node_ptr = ptr as *mut ListNode;
+        unsafe {
+            new_node_ptr.write(new_node);
+            self.list_heads[index] = Some(&mut *new_node_ptr);
+        }
+    }
+
+    /// Refills `magazine` with blocks of `BLOCK_SIZES[index]` bytes up to half of its capacity.
+    fn fill(&mut self, index: usize, magazine: &mut Magazine) {
+        while magazine.len < MAGAZINE_SIZE / 2 {
+            let block = self.alloc_block(index);
+            if block.is_null() {
+                break;
+            }
+            magazine.push(block);
+        }
+    }
+
+    /// Moves blocks in `magazine` back to the free list until it becomes half full.
+    ///
+    /// # Safety
+    ///
+    /// This function is unsafe because the caller must guarantee that all blocks in
+    /// `magazine` were allocated by [`Self::alloc_block`] with the same `index`.
+    unsafe fn flush(&mut self, index: usize, magazine: &mut Magazine) {
+        while magazine.len > MAGAZINE_SIZE / 2 {
+            #[allow(clippy::unwrap_used)]
+            let block = magazine.pop().unwrap();
+            unsafe { self.dealloc_block(index, block) };
+        }
+    }
+}
+
+/// A fixed-size stack of free blocks of the same size.
+#[derive(Debug)]
+struct Magazine {
+    // block addresses are stored as integers so that caches can be shared between CPUs
+    rounds: [usize; MAGAZINE_SIZE],
+    len: usize,
+}
+
+impl Magazine {
+    const fn new() -> Self {
+        Self {
+            rounds: [0; MAGAZINE_SIZE],
+            len: 0,
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        self.len == MAGAZINE_SIZE
+    }
+
+    fn push(&mut self, block: *mut u8) {
+        self.rounds[self.len] = block as usize;
+        self.len += 1;
+    }
+
+    fn pop(&mut self) -> Option<*mut u8> {
+        self.len = self.len.checked_sub(1)?;
+        Some(self.rounds[self.len] as *mut u8)
+    }
+}
+
+/// Free blocks cached by a CPU, one magazine per block size.
+#[derive(Debug)]
+struct CpuCache {
+    magazines: [Magazine; BLOCK_SIZES.len()],
+}
+
+impl CpuCache {
+    const fn new() -> Self {
+        const EMPTY: Magazine = Magazine::new();
+        Self {
+            magazines: [EMPTY; BLOCK_SIZES.len()],
+        }
+    }
+}
+
+/// The global allocator.
+///
+/// Small blocks are served from per-CPU magazine caches, and only refilling or flushing
+/// a magazine and large allocations touch the depot shared between CPUs.
+struct Allocator {
+    caches: [SpinMutex<CpuCache>; cpu::MAX_CPUS],
+    // other CPUs may hold the depot lock, so wait for it instead of reporting a deadlock
+    depot: spin::Mutex<FixedSizeBlockAllocator>,
+}
+
+impl Allocator {
+    const fn new() -> Self {
+        // used only as an array initializer
+        #[allow(clippy::declare_interior_mutable_const)]
+        const EMPTY: SpinMutex<CpuCache> = SpinMutex::new(CpuCache::new());
+        Self {
+            caches: [EMPTY; cpu::MAX_CPUS],
+            depot: spin::Mutex::new(FixedSizeBlockAllocator::new()),
+        }
+    }
+
+    fn alloc(&self, layout: Layout) -> *mut u8 {
+        match list_index(&layout) {
+            Some(index) => {
+                let mut cache = self.caches[cpu::current_index()].lock();
+                let magazine = &mut cache.magazines[index];
+                if magazine.len == 0 {
+                    self.depot.lock().fill(index, magazine);
+                }
+                magazine.pop().unwrap_or(ptr::null_mut())
+            }
+            None => unsafe { self.depot.lock().alloc(layout) },
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        match list_index(&layout) {
+            Some(index) => {
+                let mut cache = self.caches[cpu::current_index()].lock();
+                let magazine = &mut cache.magazines[index];
+                if magazine.is_full() {
+                    unsafe { self.depot.lock().flush(index, magazine) };
+                }
+                magazine.push(ptr);
+            }
+            None => unsafe { self.depot.lock().dealloc(ptr, layout) },
+        }
+    }
 }
 
-unsafe impl GlobalAlloc for SpinMutex<FixedSizeBlockAllocator> {
+unsafe impl GlobalAlloc for Allocator {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         assert!(!interrupt::is_interrupt_context());
 
+        // The call stack is walked from the caller of this function, so the frames of the
+        // allocator itself are not recorded as the call site.
+        #[cfg(feature = "alloc-tracking")]
+        let frames = crate::backtrace::Frames::current();
+
         // Disable interrupts to prevent deadlocks.
         //
         // If a context switch occurs while another task is acquiring a lock,
         // and the task after the switch tries to acquire a lock with interrupts
         // disabled, a deadlock will occur. To prevent this deadlock, disable
-        // interrupts before acquiring the lock.
-        interrupts::without_interrupts(|| unsafe { self.lock().alloc(layout) })
+        // interrupts before acquiring the lock. This also keeps the task on the
+        // current CPU while its cache is used.
+        interrupts::without_interrupts(|| {
+            #[cfg(feature = "alloc-tracking")]
+            return unsafe {
+                tracking::alloc(layout, frames, |layout| Allocator::alloc(self, layout))
+            };
+            #[cfg(not(feature = "alloc-tracking"))]
+            Allocator::alloc(self, layout)
+        })
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
@@ -219,7 +486,34 @@ unsafe impl GlobalAlloc for SpinMutex<FixedSizeBlockAllocator> {
         // If a context switch occurs while another task is acquiring a lock,
         // and the task after the switch tries to acquire a lock with interrupts
         // disabled, a deadlock will occur. To prevent this deadlock, disable
-        // interrupts before acquiring the lock.
-        interrupts::without_interrupts(|| unsafe { self.lock().dealloc(ptr, layout) })
+        // interrupts before acquiring the lock. This also keeps the task on the
+        // current CPU while its cache is used.
+        interrupts::without_interrupts(|| {
+            #[cfg(feature = "alloc-tracking")]
+            unsafe {
+                tracking::dealloc(ptr, layout, |ptr, layout| {
+                    Allocator::dealloc(self, ptr, layout)
+                })
+            };
+            #[cfg(not(feature = "alloc-tracking"))]
+            unsafe {
+                Allocator::dealloc(self, ptr, layout)
+            };
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::boxed::Box;
+
+    #[test_case]
+    #[allow(clippy::unwrap_used)]
+    fn refault_mapped_heap_page() {
+        let value = Box::new(0xdead_beef_u64);
+        let addr = VirtAddr::from_ptr(&*value);
+        interrupts::without_interrupts(|| map_heap_page(addr)).unwrap();
+        assert_eq!(*value, 0xdead_beef);
     }
 }
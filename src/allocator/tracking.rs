@@ -0,0 +1,148 @@
+//! Allocation call site tracking for finding memory leaks.
+//!
+//! Each allocation is prefixed with a header recording the index of its call site, which is
+//! identified by the return addresses of the allocating call stack.
+
+use crate::{backtrace::Frames, sync::SpinMutex};
+use arrayvec::ArrayVec;
+use core::{alloc::Layout, cmp, ptr};
+
+const MAX_SITES: usize = 128;
+pub(crate) const BACKTRACE_DEPTH: usize = 6;
+/// Size of the header placed before each allocation.
+const HEADER_SIZE: usize = 16;
+/// Index of the site which collects allocations not fitting in the table.
+const OVERFLOW_SITE: usize = MAX_SITES - 1;
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Site {
+    pub(crate) backtrace: [u64; BACKTRACE_DEPTH],
+    pub(crate) live_bytes: usize,
+    pub(crate) live_count: usize,
+}
+
+impl Site {
+    const EMPTY: Self = Self {
+        backtrace: [0; BACKTRACE_DEPTH],
+        live_bytes: 0,
+        live_count: 0,
+    };
+}
+
+#[derive(Debug)]
+struct SiteTable {
+    sites: [Site; MAX_SITES],
+    len: usize,
+}
+
+impl SiteTable {
+    const fn new() -> Self {
+        Self {
+            sites: [Site::EMPTY; MAX_SITES],
+            len: 0,
+        }
+    }
+
+    fn find_or_insert(&mut self, backtrace: &[u64; BACKTRACE_DEPTH]) -> usize {
+        if let Some(index) = self.sites[..self.len]
+            .iter()
+            .position(|site| site.backtrace == *backtrace)
+        {
+            return index;
+        }
+        if self.len < OVERFLOW_SITE {
+            self.sites[self.len].backtrace = *backtrace;
+            self.len += 1;
+            return self.len - 1;
+        }
+        OVERFLOW_SITE
+    }
+}
+
+static SITES: SpinMutex<SiteTable> = SpinMutex::new(SiteTable::new());
+
+fn capture_backtrace(frames: Frames) -> [u64; BACKTRACE_DEPTH] {
+    let mut backtrace = [0; BACKTRACE_DEPTH];
+    for (slot, addr) in backtrace.iter_mut().zip(frames) {
+        *slot = addr;
+    }
+    backtrace
+}
+
+/// Returns the layout including the header, and the offset of the user data.
+fn layout_with_header(layout: Layout) -> Option<(Layout, usize)> {
+    let offset = cmp::max(HEADER_SIZE, layout.align());
+    let size = layout.size().checked_add(offset)?;
+    let layout = Layout::from_size_align(size, cmp::max(HEADER_SIZE, layout.align())).ok()?;
+    Some((layout, offset))
+}
+
+/// Allocates memory with `alloc` and records the call site, whose call stack is walked by
+/// `frames` captured at the entry of the allocator.
+///
+/// # Safety
+///
+/// This function is unsafe because the caller must guarantee the same conditions as
+/// [`core::alloc::GlobalAlloc::alloc`], and `frames` must walk the frames of the current call
+/// stack.
+pub(super) unsafe fn alloc(
+    layout: Layout,
+    frames: Frames,
+    alloc: impl FnOnce(Layout) -> *mut u8,
+) -> *mut u8 {
+    let (tracked, offset) = match layout_with_header(layout) {
+        Some(pair) => pair,
+        None => return ptr::null_mut(),
+    };
+    let base = alloc(tracked);
+    if base.is_null() {
+        return base;
+    }
+
+    let backtrace = capture_backtrace(frames);
+    let index = SITES.with_lock(|table| {
+        let index = table.find_or_insert(&backtrace);
+        let site = &mut table.sites[index];
+        site.live_bytes += layout.size();
+        site.live_count += 1;
+        index
+    });
+
+    unsafe {
+        let data = base.add(offset);
+        (data as *mut usize).sub(1).write(index);
+        data
+    }
+}
+
+/// Deallocates memory allocated by [`alloc`] with `dealloc`.
+///
+/// # Safety
+///
+/// This function is unsafe because the caller must guarantee the same conditions as
+/// [`core::alloc::GlobalAlloc::dealloc`].
+pub(super) unsafe fn dealloc(ptr: *mut u8, layout: Layout, dealloc: impl FnOnce(*mut u8, Layout)) {
+    #[allow(clippy::unwrap_used)]
+    let (tracked, offset) = layout_with_header(layout).unwrap();
+    let index = unsafe { (ptr as *mut usize).sub(1).read() };
+    SITES.with_lock(|table| {
+        let site = &mut table.sites[index];
+        site.live_bytes -= layout.size();
+        site.live_count -= 1;
+    });
+    dealloc(unsafe { ptr.sub(offset) }, tracked);
+}
+
+/// Returns the call sites which have live allocations, sorted by live bytes.
+pub(crate) fn live_sites() -> ArrayVec<Site, MAX_SITES> {
+    let mut sites = SITES.with_lock(|table| {
+        table
+            .sites
+            .iter()
+            .filter(|site| site.live_count > 0)
+            .copied()
+            .collect::<ArrayVec<_, MAX_SITES>>()
+    });
+    sites.sort_unstable_by_key(|site| cmp::Reverse(site.live_bytes));
+    sites
+}
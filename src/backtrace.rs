@@ -0,0 +1,47 @@
+//! Stack walking based on frame pointers.
+//!
+//! The kernel is built with `-C force-frame-pointers=yes` (see `.cargo/config.toml`),
+//! so each frame starts with the saved RBP of the caller followed by the return address.
+
+/// Maximum size of a stack frame regarded as valid.
+const MAX_FRAME_SIZE: u64 = 1024 * 1024;
+
+/// Iterator over the return addresses of the current call stack.
+#[derive(Debug, Clone)]
+pub(crate) struct Frames {
+    rbp: u64,
+}
+
+impl Frames {
+    /// Starts walking from the caller of this function.
+    #[inline(always)]
+    pub(crate) fn current() -> Self {
+        let rbp: u64;
+        unsafe { asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack)) };
+        Self { rbp }
+    }
+
+    /// Starts walking from the frame pointed by `rbp`.
+    pub(crate) fn from_frame_pointer(rbp: u64) -> Self {
+        Self { rbp }
+    }
+}
+
+impl Iterator for Frames {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.rbp == 0 || self.rbp % 8 != 0 {
+            return None;
+        }
+        let frame = self.rbp as *const u64;
+        let (next_rbp, return_addr) = unsafe { (frame.read(), frame.add(1).read()) };
+        // the caller's frame must be above the current frame in the same stack
+        self.rbp = if next_rbp > self.rbp && next_rbp - self.rbp <= MAX_FRAME_SIZE {
+            next_rbp
+        } else {
+            0
+        };
+        (return_addr != 0).then(|| return_addr)
+    }
+}
@@ -0,0 +1,255 @@
+//! Micro-benchmarks of the scheduler, synchronization primitives and drawing.
+//!
+//! Benchmarks involving task switches spawn a helper task of the default level, so they must be
+//! run from a task of the same level (e.g. the terminal).
+
+use crate::{
+    graphics::{
+        Color, Draw, Offset, Point, Rectangle, ScaleFilter, ScreenInfo, ShadowBuffer, Size,
+    },
+    prelude::*,
+    sync::{mpsc, Mutex},
+    task::{self, Task},
+    time::{self, Instant},
+};
+use alloc::sync::Arc;
+use core::{
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::Duration,
+};
+use x86_64::instructions::interrupts;
+
+const SWITCH_ROUNDS: u64 = 10_000;
+const MUTEX_ROUNDS: u64 = 1_000;
+const MPSC_CAPACITY: usize = 64;
+const MPSC_BATCHES: u64 = 1_000;
+const DRAW_SIZE: i32 = 256;
+const DRAW_ROUNDS: u64 = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Unit {
+    Nanos,
+    PerSecond,
+    MiBPerSecond,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Measurement {
+    pub(crate) name: &'static str,
+    pub(crate) value: f64,
+    pub(crate) unit: Unit,
+}
+
+impl Measurement {
+    fn nanos_per_op(name: &'static str, elapsed: Duration, ops: u64) -> Self {
+        Self {
+            name,
+            value: elapsed.as_nanos() as f64 / ops as f64,
+            unit: Unit::Nanos,
+        }
+    }
+
+    fn per_second(name: &'static str, elapsed: Duration, ops: u64) -> Self {
+        Self {
+            name,
+            value: ops as f64 / elapsed.as_secs_f64(),
+            unit: Unit::PerSecond,
+        }
+    }
+
+    fn bandwidth(name: &'static str, elapsed: Duration, bytes: u64) -> Self {
+        Self {
+            name,
+            value: bytes as f64 / elapsed.as_secs_f64() / (1024.0 * 1024.0),
+            unit: Unit::MiBPerSecond,
+        }
+    }
+
+    /// Returns how many times better `self` is than `previous`.
+    pub(crate) fn speedup(&self, previous: &Measurement) -> f64 {
+        match self.unit {
+            Unit::Nanos => previous.value / self.value,
+            Unit::PerSecond | Unit::MiBPerSecond => self.value / previous.value,
+        }
+    }
+}
+
+/// Results of the last run, to be compared with the next run.
+static LAST_RESULTS: Mutex<Option<[Measurement; 7]>> = Mutex::new(None);
+
+/// Runs all benchmarks and returns the results with those of the previous run.
+pub(crate) fn run() -> Result<([Measurement; 7], Option<[Measurement; 7]>)> {
+    let results = [
+        context_switch(),
+        mutex_handoff(),
+        mpsc_throughput()?,
+        fill_bandwidth()?,
+        copy_bandwidth()?,
+        scale_bandwidth("scale nearest", ScaleFilter::Nearest)?,
+        scale_bandwidth("scale bilinear", ScaleFilter::Bilinear)?,
+    ];
+    let previous = LAST_RESULTS.lock().replace(results);
+    Ok((results, previous))
+}
+
+fn spawn_helper(f: impl FnOnce() + Send + 'static) {
+    let task = Task::new(task::DEFAULT_STACK_SIZE, async move { f() });
+    // tasks cannot exit yet, so the helper stays asleep after `f` returns
+    interrupts::without_interrupts(|| task::spawn(task));
+}
+
+/// Measures a round trip of yielding to another task and back.
+fn context_switch() -> Measurement {
+    let stop = Arc::new(AtomicBool::new(false));
+    let helper_stop = stop.clone();
+    spawn_helper(move || {
+        while !helper_stop.load(Ordering::Relaxed) {
+            task::yield_now();
+        }
+    });
+
+    let start = Instant::now();
+    for _ in 0..SWITCH_ROUNDS {
+        task::yield_now();
+    }
+    let elapsed = start.elapsed();
+
+    stop.store(true, Ordering::Relaxed);
+    task::yield_now();
+    Measurement::nanos_per_op("context switch", elapsed, SWITCH_ROUNDS)
+}
+
+#[derive(Debug, Default)]
+struct MutexBench {
+    mutex: Mutex<()>,
+    /// When the benchmark task released the mutex, 0 if the helper already took it.
+    released_at: AtomicU64,
+    total_nanos: AtomicU64,
+    handoffs: AtomicU64,
+    stop: AtomicBool,
+}
+
+/// Measures the time from releasing a contended `Mutex` until the waiter acquires it.
+fn mutex_handoff() -> Measurement {
+    let bench = Arc::new(MutexBench::default());
+    let helper_bench = bench.clone();
+    spawn_helper(move || {
+        let bench = helper_bench;
+        while !bench.stop.load(Ordering::Relaxed) {
+            let guard = bench.mutex.lock();
+            let released_at = bench.released_at.swap(0, Ordering::Relaxed);
+            if released_at != 0 {
+                let now = time::read_tsc_ns();
+                bench
+                    .total_nanos
+                    .fetch_add(now.saturating_sub(released_at), Ordering::Relaxed);
+                bench.handoffs.fetch_add(1, Ordering::Relaxed);
+            }
+            drop(guard);
+            task::yield_now();
+        }
+    });
+
+    for _ in 0..MUTEX_ROUNDS {
+        let guard = bench.mutex.lock();
+        // the helper blocks on the mutex
+        task::yield_now();
+        bench
+            .released_at
+            .store(time::read_tsc_ns(), Ordering::Relaxed);
+        drop(guard);
+        // the helper takes the mutex
+        task::yield_now();
+    }
+
+    bench.stop.store(true, Ordering::Relaxed);
+    task::yield_now();
+    let handoffs = u64::max(bench.handoffs.load(Ordering::Relaxed), 1);
+    let total = Duration::from_nanos(bench.total_nanos.load(Ordering::Relaxed));
+    Measurement::nanos_per_op("mutex handoff", total, handoffs)
+}
+
+/// Measures sending and receiving messages through an mpsc channel.
+fn mpsc_throughput() -> Result<Measurement> {
+    let (tx, mut rx) = mpsc::channel(MPSC_CAPACITY);
+    let start = Instant::now();
+    for _ in 0..MPSC_BATCHES {
+        for i in 0..MPSC_CAPACITY {
+            tx.try_send(i)?;
+        }
+        for _ in 0..MPSC_CAPACITY {
+            let _ = task::block_on(rx.next());
+        }
+    }
+    let elapsed = start.elapsed();
+    Ok(Measurement::per_second(
+        "mpsc messages",
+        elapsed,
+        MPSC_BATCHES * MPSC_CAPACITY as u64,
+    ))
+}
+
+fn bench_buffer() -> Result<ShadowBuffer> {
+    ShadowBuffer::new_shadow(Size::new(DRAW_SIZE, DRAW_SIZE), ScreenInfo::get())
+}
+
+fn buffer_bytes() -> u64 {
+    let bytes_per_pixel = ScreenInfo::get().bytes_per_pixel;
+    (DRAW_SIZE * DRAW_SIZE * bytes_per_pixel) as u64
+}
+
+/// Measures filling a shadow buffer with `Draw::fill_rect`.
+fn fill_bandwidth() -> Result<Measurement> {
+    let mut buffer = bench_buffer()?;
+    let area = buffer.area();
+    let start = Instant::now();
+    for i in 0..DRAW_ROUNDS {
+        let color = if i % 2 == 0 {
+            Color::WHITE
+        } else {
+            Color::BLACK
+        };
+        buffer.fill_rect(area, color);
+    }
+    let elapsed = start.elapsed();
+    Ok(Measurement::bandwidth(
+        "fill_rect",
+        elapsed,
+        buffer_bytes() * DRAW_ROUNDS,
+    ))
+}
+
+/// Measures copying a shadow buffer to another.
+fn copy_bandwidth() -> Result<Measurement> {
+    let src = bench_buffer()?;
+    let mut dst = bench_buffer()?;
+    let area = Rectangle::new(Point::new(0, 0), src.size());
+    let start = Instant::now();
+    for _ in 0..DRAW_ROUNDS {
+        dst.copy(Offset::new(0, 0), &src, area);
+    }
+    let elapsed = start.elapsed();
+    Ok(Measurement::bandwidth(
+        "buffer copy",
+        elapsed,
+        buffer_bytes() * DRAW_ROUNDS,
+    ))
+}
+
+/// Measures scaling a quarter of a shadow buffer up to another by `filter`.
+fn scale_bandwidth(name: &'static str, filter: ScaleFilter) -> Result<Measurement> {
+    let src = bench_buffer()?;
+    let mut dst = bench_buffer()?;
+    let src_area = Rectangle::new(Point::new(0, 0), Size::new(DRAW_SIZE / 2, DRAW_SIZE / 2));
+    let dst_area = dst.area();
+    let start = Instant::now();
+    for _ in 0..DRAW_ROUNDS {
+        dst.copy_scaled(dst_area, &src, src_area, filter);
+    }
+    let elapsed = start.elapsed();
+    Ok(Measurement::bandwidth(
+        name,
+        elapsed,
+        buffer_bytes() * DRAW_ROUNDS,
+    ))
+}
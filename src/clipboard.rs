@@ -0,0 +1,22 @@
+//! The clipboard shared by all windows, which holds text.
+//!
+//! Ctrl+C and Ctrl+V on the active window are sent to it as [`WindowEvent::Copy`] and
+//! [`WindowEvent::Paste`], and the window copies to or pastes from the clipboard.
+//!
+//! [`WindowEvent::Copy`]: crate::window::WindowEvent::Copy
+//! [`WindowEvent::Paste`]: crate::window::WindowEvent::Paste
+
+use crate::{prelude::*, sync::Mutex};
+use alloc::string::String;
+
+static CLIPBOARD: Mutex<Option<String>> = Mutex::new(None);
+
+pub(crate) fn set_text(text: String) {
+    debug!("clipboard: {} bytes copied", text.len());
+    *CLIPBOARD.lock() = Some(text);
+}
+
+/// Returns the text copied last.
+pub(crate) fn text() -> Option<String> {
+    CLIPBOARD.lock().clone()
+}
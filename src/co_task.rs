@@ -1,5 +1,7 @@
+use crate::sync::oneshot::{self, Canceled};
 use alloc::boxed::Box;
 use core::{
+    fmt,
     future::Future,
     pin::Pin,
     sync::atomic::{AtomicU64, Ordering},
@@ -7,9 +9,10 @@ use core::{
 };
 use custom_debug_derive::Debug as CustomDebug;
 
-pub(crate) use self::{executor::*, traits::*};
+pub(crate) use self::{executor::*, stats::*, traits::*};
 
 mod executor;
+mod stats;
 mod traits;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
@@ -22,10 +25,18 @@ impl CoTaskId {
     }
 }
 
+impl fmt::Display for CoTaskId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
 /// Cooperative Task
 #[derive(CustomDebug)]
 pub(crate) struct CoTask {
     id: CoTaskId,
+    /// Whether the co-task may be moved to another executor to balance the load.
+    movable: bool,
     #[debug(skip)]
     future: Pin<Box<dyn Future<Output = ()> + Send>>,
 }
@@ -34,11 +45,42 @@ impl CoTask {
     pub(crate) fn new(future: impl Future<Output = ()> + Send + 'static) -> Self {
         Self {
             id: CoTaskId::new(),
+            movable: false,
             future: Box::pin(future),
         }
     }
 
+    /// Creates a co-task and a handle to await its output.
+    pub(crate) fn with_handle<T>(
+        future: impl Future<Output = T> + Send + 'static,
+    ) -> (Self, JoinHandle<T>)
+    where
+        T: Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        let task = Self::new(async move {
+            // the output is discarded if the handle has been dropped
+            let _ = tx.send(future.await);
+        });
+        (task, JoinHandle { rx })
+    }
+
     fn poll(&mut self, cx: &mut Context) -> Poll<()> {
         self.future.as_mut().poll(cx)
     }
 }
+
+/// A future which resolves to the output of a spawned co-task, or [`Canceled`] if the co-task is
+/// dropped before completion.
+#[derive(Debug)]
+pub(crate) struct JoinHandle<T> {
+    rx: oneshot::Receiver<T>,
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = core::result::Result<T, Canceled>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.rx).poll(cx)
+    }
+}
@@ -1,7 +1,20 @@
-use super::{CoTask, CoTaskId};
-use crate::task::{self, TaskId};
-use alloc::{collections::BTreeMap, sync::Arc, task::Wake};
-use core::task::{Context, Poll, Waker};
+use super::{stats::PollStats, CoTask, CoTaskId, JoinHandle};
+use crate::{
+    sync::SpinMutex,
+    task::{self, TaskId},
+    time::Instant,
+};
+use alloc::{
+    collections::BTreeMap,
+    sync::{Arc, Weak},
+    task::Wake,
+    vec::Vec,
+};
+use core::{
+    future::Future,
+    sync::atomic::{AtomicUsize, Ordering},
+    task::{Context, Poll, Waker},
+};
 use crossbeam_queue::ArrayQueue;
 use x86_64::instructions::interrupts;
 
@@ -9,116 +22,241 @@ use x86_64::instructions::interrupts;
 enum Event {
     Spawn(CoTask),
     Wake(CoTaskId),
+    Migrate(CoTaskId, Handle),
 }
 
+/// State of an executor shared with its handles and wakers.
 #[derive(Debug)]
-pub(crate) struct Executor {
+struct Shared {
     task_id: TaskId,
+    task_queue: ArrayQueue<Event>,
+    /// The number of co-tasks owned by the executor.
+    co_tasks: AtomicUsize,
+    /// The co-tasks owned by the executor, including the ones spawned but not received yet, and
+    /// whether each of them is movable.
+    owned: SpinMutex<BTreeMap<CoTaskId, bool>>,
+}
+
+impl Shared {
+    fn owns(&self, co_task_id: CoTaskId) -> bool {
+        interrupts::without_interrupts(|| self.owned.lock().contains_key(&co_task_id))
+    }
+
+    fn add_owned(&self, task: &CoTask) {
+        interrupts::without_interrupts(|| self.owned.lock().insert(task.id, task.movable));
+    }
+
+    fn remove_owned(&self, co_task_id: CoTaskId) {
+        interrupts::without_interrupts(|| self.owned.lock().remove(&co_task_id));
+    }
+
+    /// Returns one of the movable co-tasks owned by the executor.
+    fn movable_co_task(&self) -> Option<CoTaskId> {
+        interrupts::without_interrupts(|| {
+            self.owned
+                .lock()
+                .iter()
+                .find(|(_, movable)| **movable)
+                .map(|(co_task_id, _)| *co_task_id)
+        })
+    }
+
+    fn push(&self, event: Event) {
+        interrupts::without_interrupts(|| {
+            #[allow(clippy::expect_used)]
+            self.task_queue.push(event).expect("task_queue full");
+            task::wake(self.task_id);
+        })
+    }
+}
+
+/// Executors which are alive.
+static REGISTRY: SpinMutex<Vec<Weak<Shared>>> = SpinMutex::new(Vec::new());
+
+/// Maximum number of co-task polls before the executor lets other tasks run.
+const POLL_BUDGET: usize = 64;
+
+#[derive(Debug)]
+pub(crate) struct Executor {
+    shared: Arc<Shared>,
     tasks: BTreeMap<CoTaskId, CoTask>,
-    task_queue: Arc<ArrayQueue<Event>>,
     waker_cache: BTreeMap<CoTaskId, Waker>,
+    stats: BTreeMap<CoTaskId, Arc<PollStats>>,
+    /// Executors which co-tasks were migrated to, used to forward wake-ups from the wakers
+    /// created before the migration. Entries are removed once the target no longer owns the
+    /// co-task.
+    migrated: BTreeMap<CoTaskId, Handle>,
 }
 
 impl Executor {
     pub(crate) fn new(task_id: TaskId) -> Self {
-        Self {
+        let shared = Arc::new(Shared {
             task_id,
+            task_queue: ArrayQueue::new(100),
+            co_tasks: AtomicUsize::new(0),
+            owned: SpinMutex::new(BTreeMap::new()),
+        });
+        interrupts::without_interrupts(|| {
+            REGISTRY.with_lock(|registry| {
+                registry.retain(|shared| shared.strong_count() > 0);
+                registry.push(Arc::downgrade(&shared));
+            })
+        });
+        Self {
+            shared,
             tasks: BTreeMap::new(),
-            task_queue: Arc::new(ArrayQueue::new(100)),
             waker_cache: BTreeMap::new(),
+            stats: BTreeMap::new(),
+            migrated: BTreeMap::new(),
         }
     }
 
     pub(crate) fn handle(&self) -> Handle {
         Handle {
-            task_queue: self.task_queue.clone(),
+            shared: self.shared.clone(),
         }
     }
 
     pub(crate) fn spawn(&mut self, task: CoTask) {
         let task_id = task.id;
+        self.shared.add_owned(&task);
         if self.tasks.insert(task.id, task).is_some() {
             panic!("task with same ID already in tasks");
         }
+        self.shared.co_tasks.fetch_add(1, Ordering::Relaxed);
+        // the co-task may come back after it was migrated from this executor
+        self.migrated.remove(&task_id);
+        self.stats
+            .insert(task_id, PollStats::register(self.shared.task_id, task_id));
         #[allow(clippy::expect_used)]
-        self.task_queue
+        self.shared
+            .task_queue
             .push(Event::Wake(task_id))
             .expect("queue full");
     }
 
+    /// Spawns `future` as a co-task and returns a handle to await its output.
+    pub(crate) fn spawn_with_handle<T>(
+        &mut self,
+        future: impl Future<Output = T> + Send + 'static,
+    ) -> JoinHandle<T>
+    where
+        T: Send + 'static,
+    {
+        let (task, handle) = CoTask::with_handle(future);
+        self.spawn(task);
+        handle
+    }
+
     pub(crate) fn run(&mut self) -> ! {
         loop {
-            self.run_ready_tasks();
-            self.sleep_if_idle();
+            if self.run_ready_tasks() {
+                self.sleep_if_idle();
+            } else {
+                // budget exhausted, give other tasks a chance to run
+                task::yield_now();
+            }
         }
     }
 
     fn wake(&mut self, co_task_id: CoTaskId) {
         // destructure `self` to avoid borrow checker errors
         let Self {
-            task_id,
+            shared,
             tasks,
-            task_queue,
             waker_cache,
+            stats,
+            migrated,
         } = self;
 
         let task = match tasks.get_mut(&co_task_id) {
             Some(task) => task,
-            None => return, // task no longer exists
+            None => {
+                if let Some(handle) = migrated.get(&co_task_id) {
+                    if handle.shared.owns(co_task_id) {
+                        handle.shared.push(Event::Wake(co_task_id));
+                    } else {
+                        // the co-task finished or was migrated again
+                        migrated.remove(&co_task_id);
+                    }
+                }
+                return; // task no longer exists
+            }
         };
 
         let waker = waker_cache
             .entry(co_task_id)
-            .or_insert_with(|| CoTaskWaker::waker(*task_id, co_task_id, task_queue.clone()));
+            .or_insert_with(|| CoTaskWaker::waker(shared.clone(), co_task_id));
         let mut context = Context::from_waker(waker);
-        if let Poll::Ready(()) = task.poll(&mut context) {
-            // task done -> remove it and its cached waker
+        let start = Instant::now();
+        let poll = task.poll(&mut context);
+        if let Some(stats) = stats.get(&co_task_id) {
+            stats.record_poll(start.elapsed());
+        }
+        if let Poll::Ready(()) = poll {
+            // task done -> remove it, its cached waker and stats
             tasks.remove(&co_task_id);
             waker_cache.remove(&co_task_id);
+            stats.remove(&co_task_id);
+            shared.co_tasks.fetch_sub(1, Ordering::Relaxed);
+            shared.remove_owned(co_task_id);
         }
     }
 
-    fn run_ready_tasks(&mut self) {
-        while let Some(event) = self.task_queue.pop() {
-            match event {
-                Event::Spawn(task) => self.spawn(task),
-                Event::Wake(task_id) => self.wake(task_id),
+    fn migrate(&mut self, co_task_id: CoTaskId, target: Handle) {
+        if Arc::ptr_eq(&self.shared, &target.shared) {
+            return;
+        }
+        let task = match self.tasks.remove(&co_task_id) {
+            Some(task) => task,
+            None => return, // task no longer exists
+        };
+        self.waker_cache.remove(&co_task_id);
+        self.stats.remove(&co_task_id);
+        self.shared.co_tasks.fetch_sub(1, Ordering::Relaxed);
+        self.shared.remove_owned(co_task_id);
+        target.spawn(task);
+        self.migrated
+            .retain(|co_task_id, handle| handle.shared.owns(*co_task_id));
+        self.migrated.insert(co_task_id, target);
+    }
+
+    /// Handles queued events within the poll budget.
+    ///
+    /// Returns `false` if the budget is exhausted before the queue becomes empty.
+    fn run_ready_tasks(&mut self) -> bool {
+        for _ in 0..POLL_BUDGET {
+            match self.shared.task_queue.pop() {
+                Some(Event::Spawn(task)) => self.spawn(task),
+                Some(Event::Wake(task_id)) => self.wake(task_id),
+                Some(Event::Migrate(task_id, target)) => self.migrate(task_id, target),
+                None => return true,
             }
         }
+        self.shared.task_queue.is_empty()
     }
 
     fn sleep_if_idle(&self) {
         interrupts::disable();
-        if self.task_queue.is_empty() {
-            task::sleep(self.task_id);
+        if self.shared.task_queue.is_empty() {
+            task::sleep(self.shared.task_id);
         }
         interrupts::enable();
     }
 }
 
 struct CoTaskWaker {
-    task_id: TaskId,
+    shared: Arc<Shared>,
     co_task_id: CoTaskId,
-    task_queue: Arc<ArrayQueue<Event>>,
 }
 
 impl CoTaskWaker {
-    fn waker(task_id: TaskId, co_task_id: CoTaskId, task_queue: Arc<ArrayQueue<Event>>) -> Waker {
-        Waker::from(Arc::new(CoTaskWaker {
-            task_id,
-            co_task_id,
-            task_queue,
-        }))
+    fn waker(shared: Arc<Shared>, co_task_id: CoTaskId) -> Waker {
+        Waker::from(Arc::new(CoTaskWaker { shared, co_task_id }))
     }
 
     fn wake_task(&self) {
-        interrupts::without_interrupts(|| {
-            #[allow(clippy::expect_used)]
-            self.task_queue
-                .push(Event::Wake(self.co_task_id))
-                .expect("task_queue full");
-            task::wake(self.task_id);
-        })
+        self.shared.push(Event::Wake(self.co_task_id));
     }
 }
 
@@ -134,14 +272,151 @@ impl Wake for CoTaskWaker {
 
 #[derive(Debug, Clone)]
 pub(crate) struct Handle {
-    task_queue: Arc<ArrayQueue<Event>>,
+    shared: Arc<Shared>,
 }
 
 impl Handle {
     pub(crate) fn spawn(&self, task: CoTask) {
-        #[allow(clippy::expect_used)]
-        self.task_queue
-            .push(Event::Spawn(task))
-            .expect("queue full");
+        self.shared.add_owned(&task);
+        self.shared.push(Event::Spawn(task));
+    }
+
+    /// Returns the task running the executor.
+    pub(crate) fn task_id(&self) -> TaskId {
+        self.shared.task_id
+    }
+
+    /// Returns the number of co-tasks owned by the executor and the events not handled yet.
+    pub(crate) fn load(&self) -> usize {
+        self.shared.co_tasks.load(Ordering::Relaxed) + self.shared.task_queue.len()
+    }
+
+    /// Moves the co-task `co_task_id` owned by this executor to the executor of `target`.
+    ///
+    /// The co-task is moved when this executor handles the request. Nothing happens if the
+    /// co-task is not owned by this executor at that time.
+    pub(crate) fn migrate(&self, co_task_id: CoTaskId, target: &Handle) {
+        self.shared.push(Event::Migrate(co_task_id, target.clone()));
+    }
+
+    /// Spawns `future` as a co-task and returns a handle to await its output.
+    pub(crate) fn spawn_with_handle<T>(
+        &self,
+        future: impl Future<Output = T> + Send + 'static,
+    ) -> JoinHandle<T>
+    where
+        T: Send + 'static,
+    {
+        let (task, handle) = CoTask::with_handle(future);
+        self.spawn(task);
+        handle
+    }
+}
+
+/// Returns the handles of all living executors.
+pub(crate) fn executors() -> Vec<Handle> {
+    interrupts::without_interrupts(|| {
+        REGISTRY.with_lock(|registry| {
+            registry
+                .iter()
+                .filter_map(Weak::upgrade)
+                .map(|shared| Handle { shared })
+                .collect()
+        })
+    })
+}
+
+/// Spawns `task` on the least loaded executor.
+///
+/// This is for subsystems which create many short-lived co-tasks that don't care which task
+/// runs them. Such co-tasks may be moved to another executor later to keep the loads even.
+pub(crate) fn spawn_balanced(mut task: CoTask) {
+    task.movable = true;
+    let executors = executors();
+    #[allow(clippy::expect_used)]
+    let handle = executors
+        .iter()
+        .min_by_key(|handle| handle.load())
+        .expect("no executor is running");
+    handle.spawn(task);
+    rebalance(&executors);
+}
+
+/// Moves a movable co-task from the most loaded executor to the least loaded one, if their loads
+/// differ by more than one.
+fn rebalance(executors: &[Handle]) {
+    let least = executors.iter().min_by_key(|handle| handle.load());
+    let most = executors.iter().max_by_key(|handle| handle.load());
+    if let (Some(least), Some(most)) = (least, most) {
+        if most.load() > least.load() + 1 {
+            if let Some(co_task_id) = most.shared.movable_co_task() {
+                most.migrate(co_task_id, least);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::sync::atomic::AtomicBool;
+    use futures_util::future;
+
+    #[test_case]
+    fn migrate_back() {
+        let task_id = interrupts::without_interrupts(|| task::current().id());
+        let mut a = Executor::new(task_id);
+        let mut b = Executor::new(task_id);
+
+        let done = Arc::new(AtomicBool::new(false));
+        let co_task = CoTask::new({
+            let done = done.clone();
+            future::poll_fn(move |_| match done.load(Ordering::Relaxed) {
+                true => Poll::Ready(()),
+                false => Poll::Pending,
+            })
+        });
+        let co_task_id = co_task.id;
+        a.spawn(co_task);
+        assert!(a.run_ready_tasks());
+
+        // A -> B -> A
+        a.migrate(co_task_id, b.handle());
+        assert!(b.run_ready_tasks());
+        b.migrate(co_task_id, a.handle());
+        assert!(a.run_ready_tasks());
+        assert!(a.tasks.contains_key(&co_task_id));
+        assert!(!a.migrated.contains_key(&co_task_id));
+
+        // finishes on A
+        done.store(true, Ordering::Relaxed);
+        a.wake(co_task_id);
+        assert!(a.tasks.is_empty());
+
+        // a stale wake-up on B is not forwarded to A, which no longer owns the co-task
+        b.wake(co_task_id);
+        assert!(b.migrated.is_empty());
+        assert!(a.shared.task_queue.is_empty());
+        assert!(b.shared.task_queue.is_empty());
+    }
+    #[test_case]
+    fn rebalance_moves_movable_co_task() {
+        let task_id = interrupts::without_interrupts(|| task::current().id());
+        let mut a = Executor::new(task_id);
+        let mut b = Executor::new(task_id);
+
+        a.spawn(CoTask::new(future::pending()));
+        a.spawn(CoTask::new(future::pending()));
+        let mut movable = CoTask::new(future::pending());
+        movable.movable = true;
+        let movable_id = movable.id;
+        a.spawn(movable);
+        assert!(a.run_ready_tasks());
+
+        rebalance(&[a.handle(), b.handle()]);
+        assert!(a.run_ready_tasks());
+        assert!(b.run_ready_tasks());
+        assert_eq!(a.tasks.len(), 2);
+        assert!(b.tasks.contains_key(&movable_id));
     }
 }
@@ -0,0 +1,86 @@
+use super::CoTaskId;
+use crate::{sync::SpinMutex, task::TaskId};
+use alloc::{
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::{
+    convert::TryFrom,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+use x86_64::instructions::interrupts;
+
+/// Statistics of co-tasks which are alive.
+static REGISTRY: SpinMutex<Vec<Weak<PollStats>>> = SpinMutex::new(Vec::new());
+
+/// Poll statistics of a co-task, updated by the executor running it.
+#[derive(Debug)]
+pub(super) struct PollStats {
+    task_id: TaskId,
+    co_task_id: CoTaskId,
+    polls: AtomicU64,
+    total_nanos: AtomicU64,
+    max_nanos: AtomicU64,
+}
+
+impl PollStats {
+    pub(super) fn register(task_id: TaskId, co_task_id: CoTaskId) -> Arc<Self> {
+        let stats = Arc::new(Self {
+            task_id,
+            co_task_id,
+            polls: AtomicU64::new(0),
+            total_nanos: AtomicU64::new(0),
+            max_nanos: AtomicU64::new(0),
+        });
+        interrupts::without_interrupts(|| {
+            REGISTRY.with_lock(|registry| {
+                registry.retain(|stats| stats.strong_count() > 0);
+                registry.push(Arc::downgrade(&stats));
+            })
+        });
+        stats
+    }
+
+    pub(super) fn record_poll(&self, time: Duration) {
+        let nanos = u64::try_from(time.as_nanos()).unwrap_or(u64::MAX);
+        self.polls.fetch_add(1, Ordering::Relaxed);
+        self.total_nanos.fetch_add(nanos, Ordering::Relaxed);
+        self.max_nanos.fetch_max(nanos, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> CoTaskStats {
+        CoTaskStats {
+            task_id: self.task_id,
+            co_task_id: self.co_task_id,
+            polls: self.polls.load(Ordering::Relaxed),
+            total_time: Duration::from_nanos(self.total_nanos.load(Ordering::Relaxed)),
+            max_time: Duration::from_nanos(self.max_nanos.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct CoTaskStats {
+    /// The task running the executor which owns the co-task.
+    pub(crate) task_id: TaskId,
+    pub(crate) co_task_id: CoTaskId,
+    pub(crate) polls: u64,
+    /// Time spent in polling the co-task.
+    pub(crate) total_time: Duration,
+    /// Time spent in the longest poll.
+    pub(crate) max_time: Duration,
+}
+
+/// Returns the poll statistics of all living co-tasks.
+pub(crate) fn stats() -> Vec<CoTaskStats> {
+    let stats = interrupts::without_interrupts(|| {
+        REGISTRY.with_lock(|registry| {
+            registry
+                .iter()
+                .filter_map(Weak::upgrade)
+                .collect::<Vec<_>>()
+        })
+    });
+    stats.iter().map(|stats| stats.snapshot()).collect()
+}
@@ -1,9 +1,9 @@
 use crate::{
-    desktop,
     graphics::{font, frame_buffer, Color, Draw, FrameBufferDrawer, Point, Rectangle, Size},
     layer,
     prelude::*,
-    sync::{mpsc, SpinMutex, SpinMutexGuard},
+    sync::{mpsc, AsyncMutex, AsyncMutexGuard, SpinMutex, SpinMutexGuard},
+    theme::Theme,
     window::Window,
 };
 use alloc::sync::Arc;
@@ -39,19 +39,23 @@ const ROWS: usize = 25;
 const COLUMNS: usize = 80;
 
 static CONSOLE: SpinMutex<Console> = SpinMutex::new(Console {
-    buffer: [[0; COLUMNS]; ROWS],
-    fg_color: desktop::FG_COLOR,
-    bg_color: desktop::BG_COLOR,
+    buffer: [[font::WIDE_CHAR_TAIL; COLUMNS]; ROWS],
+    fg_color: Theme::LIGHT.desktop_fg,
+    bg_color: Theme::LIGHT.desktop_bg,
     cursor: Point::new(0, 0),
     window: None,
+    refresh_pending: false,
 });
 
 pub(crate) struct Console {
-    buffer: [[u8; COLUMNS]; ROWS],
+    /// The characters of the cells, where [`font::WIDE_CHAR_TAIL`] is also a blank cell.
+    buffer: [[char; COLUMNS]; ROWS],
     fg_color: Color,
     bg_color: Color,
     cursor: Point<usize>,
-    window: Option<(Arc<SpinMutex<Window>>, mpsc::Sender<()>)>,
+    window: Option<(Arc<AsyncMutex<Window>>, mpsc::Sender<()>)>,
+    /// Set when the window was being flushed while writing, so the writes were not drawn.
+    refresh_pending: bool,
 }
 
 #[derive(Debug)]
@@ -104,18 +108,21 @@ impl Console {
     fn write_str(&mut self, s: &str) -> RedrawArea {
         let mut redraw = RedrawArea::new();
         for ch in s.chars() {
-            let byte = font::char_to_byte(ch);
-            if byte == b'\n' {
+            if ch == '\n' {
                 self.newline(&mut redraw);
                 continue;
             }
 
-            if self.cursor.x >= COLUMNS - 1 {
+            let width = font::char_width(ch) as usize;
+            if self.cursor.x + width >= COLUMNS {
                 self.newline(&mut redraw);
             }
+            let row = &mut self.buffer[self.cursor.y];
+            row[self.cursor.x] = ch;
+            row[self.cursor.x + 1..][..width - 1].fill(font::WIDE_CHAR_TAIL);
             redraw.add(self.cursor);
-            self.buffer[self.cursor.y][self.cursor.x] = byte;
-            self.cursor.x += 1;
+            self.cursor.x += width;
+            redraw.add(self.cursor - Point::new(1, 0));
         }
         redraw
     }
@@ -130,13 +137,13 @@ impl Console {
         for (src, dst) in (1..).zip(0..(ROWS - 1)) {
             self.buffer[dst] = self.buffer[src];
         }
-        self.buffer[ROWS - 1].fill(0b0);
+        self.buffer[ROWS - 1].fill(font::WIDE_CHAR_TAIL);
         redraw.scroll();
     }
 
     fn set_window(
         &mut self,
-        window: Option<(Arc<SpinMutex<Window>>, mpsc::Sender<()>)>,
+        window: Option<(Arc<AsyncMutex<Window>>, mpsc::Sender<()>)>,
     ) -> Result<()> {
         self.window = window;
         self.refresh()?;
@@ -144,22 +151,38 @@ impl Console {
     }
 
     fn refresh(&mut self) -> Result<()> {
+        self.refresh_pending = false;
         self.with_writer(|mut writer| {
             writer.redraw(RedrawArea::all(true));
         })
     }
 
+    fn refresh_if_pending(&mut self) -> Result<()> {
+        if self.refresh_pending {
+            self.refresh()?;
+        }
+        Ok(())
+    }
+
     fn with_writer(&'_ mut self, f: impl FnOnce(ConsoleWriter<'_, '_>)) -> Result<()> {
         assert!(!interrupts::are_enabled());
 
         if let Some((window, tx)) = self.window.clone() {
-            let drawer = Drawer::Window(window.lock());
+            // The window is locked while the handler task flushes it. The buffer is updated
+            // anyway and the whole window is redrawn afterwards.
+            let drawer = match window.try_lock() {
+                Ok(window) => Drawer::Window(window),
+                Err(_) => {
+                    self.refresh_pending = true;
+                    Drawer::Deferred
+                }
+            };
             let writer = ConsoleWriter {
                 drawer,
                 console: self,
             };
             f(writer);
-            tx.send(())?;
+            tx.try_send(())?;
         } else {
             let drawer = Drawer::FrameBuffer(frame_buffer::lock_drawer());
             let writer = ConsoleWriter {
@@ -174,21 +197,25 @@ impl Console {
 
 enum Drawer<'a> {
     FrameBuffer(SpinMutexGuard<'static, FrameBufferDrawer>),
-    Window(SpinMutexGuard<'a, Window>),
+    Window(AsyncMutexGuard<'a, Window>),
+    /// Draws nothing. Used while the window is locked by someone else.
+    Deferred,
 }
 
 impl<'a> Drawer<'a> {
-    fn with_drawer<T>(&self, f: impl FnOnce(&dyn Draw) -> T) -> T {
+    fn with_drawer<T>(&self, f: impl FnOnce(&dyn Draw) -> T) -> Option<T> {
         match self {
-            Self::FrameBuffer(drawer) => f(&**drawer),
-            Self::Window(drawer) => f(&**drawer),
+            Self::FrameBuffer(drawer) => Some(f(&**drawer)),
+            Self::Window(drawer) => Some(f(&**drawer)),
+            Self::Deferred => None,
         }
     }
 
-    fn with_drawer_mut<T>(&mut self, f: impl FnOnce(&mut dyn Draw) -> T) -> T {
+    fn with_drawer_mut<T>(&mut self, f: impl FnOnce(&mut dyn Draw) -> T) -> Option<T> {
         match self {
-            Self::FrameBuffer(drawer) => f(&mut **drawer),
-            Self::Window(drawer) => f(&mut **drawer),
+            Self::FrameBuffer(drawer) => Some(f(&mut **drawer)),
+            Self::Window(drawer) => Some(f(&mut **drawer)),
+            Self::Deferred => None,
         }
     }
 }
@@ -196,14 +223,33 @@ impl<'a> Drawer<'a> {
 impl Draw for Drawer<'_> {
     fn size(&self) -> Size<i32> {
         self.with_drawer(|d| d.size())
+            .unwrap_or_else(|| Size::new(0, 0))
     }
 
     fn draw(&mut self, p: Point<i32>, c: Color) {
-        self.with_drawer_mut(|d| d.draw(p, c))
+        self.with_drawer_mut(|d| d.draw(p, c));
     }
 
     fn move_area(&mut self, offset: Point<i32>, src: Rectangle<i32>) {
-        self.with_drawer_mut(|d| d.move_area(offset, src))
+        self.with_drawer_mut(|d| d.move_area(offset, src));
+    }
+
+    fn clip_area(&self) -> Rectangle<i32> {
+        self.with_drawer(|d| d.clip_area())
+            .unwrap_or_else(|| self.area())
+    }
+
+    fn push_clip(&mut self, rect: Rectangle<i32>) {
+        self.with_drawer_mut(|d| d.push_clip(rect));
+    }
+
+    fn pop_clip(&mut self) {
+        self.with_drawer_mut(|d| d.pop_clip());
+    }
+
+    fn draw_char_bg(&mut self, pos: Point<i32>, ch: char, fg: Color, bg: Color) -> Rectangle<i32> {
+        self.with_drawer_mut(|d| d.draw_char_bg(pos, ch, fg, bg))
+            .unwrap_or_else(|| Rectangle::new(pos, font::char_size(ch)))
     }
 }
 
@@ -214,7 +260,7 @@ pub(crate) struct ConsoleWriter<'d, 'c> {
 
 impl<'d, 'c> ConsoleWriter<'d, 'c> {
     fn to_draw_point(&self, p: Point<usize>) -> Point<i32> {
-        let font_size = font::FONT_PIXEL_SIZE;
+        let font_size = font::pixel_size();
         #[allow(clippy::unwrap_used)]
         Point {
             x: i32::try_from(p.x).unwrap() * font_size.x,
@@ -230,6 +276,10 @@ impl<'d, 'c> ConsoleWriter<'d, 'c> {
     }
 
     fn redraw(&mut self, redraw: RedrawArea) {
+        if matches!(self.drawer, Drawer::Deferred) {
+            return;
+        }
+
         if redraw.scroll > 0 {
             let src = self.to_draw_rect(Rectangle {
                 pos: Point::new(0, 0),
@@ -252,12 +302,21 @@ impl<'d, 'c> ConsoleWriter<'d, 'c> {
 
             for console_y in area.y_range() {
                 let x_range = area.x_range();
-                let console_p = Point::new(area.x_start(), console_y);
-
-                let bytes = &self.console.buffer[console_y][x_range];
-                let draw_p = self.to_draw_point(console_p);
-                self.drawer
-                    .draw_byte_str(draw_p, bytes, self.console.fg_color);
+                let row = self.console.buffer[console_y];
+                for (x, ch) in font::visible_cells(&row) {
+                    // a wide character is redrawn if either of its cells is in the area
+                    let width = font::char_width(ch) as usize;
+                    if x + width <= x_range.start || x_range.end <= x {
+                        continue;
+                    }
+                    let draw_p = self.to_draw_point(Point::new(x, console_y));
+                    self.drawer.draw_char_bg(
+                        draw_p,
+                        ch,
+                        self.console.fg_color,
+                        self.console.bg_color,
+                    );
+                }
             }
         }
     }
@@ -272,18 +331,28 @@ impl<'d, 'c> fmt::Write for ConsoleWriter<'d, 'c> {
 }
 
 pub(crate) struct ConsoleInitParam {
-    window: Arc<SpinMutex<Window>>,
+    window: Arc<AsyncMutex<Window>>,
     rx: mpsc::Receiver<()>,
 }
 
+/// Changes the colors of the text, and redraws the whole console.
+pub(crate) fn set_colors(fg_color: Color, bg_color: Color) -> Result<()> {
+    interrupts::without_interrupts(|| {
+        let mut console = CONSOLE.lock();
+        console.fg_color = fg_color;
+        console.bg_color = bg_color;
+        console.refresh()
+    })
+}
+
 pub(crate) fn start_window_mode() -> Result<ConsoleInitParam> {
-    let font_size = font::FONT_PIXEL_SIZE;
+    let font_size = font::pixel_size();
     let window_size = Size::new(COLUMNS as i32 * font_size.x, ROWS as i32 * font_size.y);
     let window = Window::builder()
         .size(window_size)
         .height(layer::CONSOLE_HEIGHT)
         .build()?;
-    let window = Arc::new(SpinMutex::new(window));
+    let window = Arc::new(AsyncMutex::new(window));
     let (tx, rx) = mpsc::channel(100);
     {
         interrupts::without_interrupts(|| {
@@ -296,10 +365,11 @@ pub(crate) fn start_window_mode() -> Result<ConsoleInitParam> {
 
 pub(crate) async fn handler_task(param: ConsoleInitParam) -> Result<()> {
     let ConsoleInitParam { window, mut rx } = param;
-    window.lock().flush().await?;
+    window.lock().await.flush().await?;
 
     while let Some(()) = rx.next().await {
-        window.lock().flush().await?;
+        interrupts::without_interrupts(|| CONSOLE.lock().refresh_if_pending())?;
+        window.lock().await.flush().await?;
     }
 
     Ok(())
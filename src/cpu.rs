@@ -0,0 +1,271 @@
+//! CPU feature detection and control.
+
+use crate::{local_apic, prelude::*, sync::OnceCell};
+use arrayvec::ArrayVec;
+use core::{
+    arch::x86_64::__cpuid_count,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use x86_64::{
+    registers::{
+        control::{Cr0, Cr0Flags, Cr4, Cr4Flags},
+        model_specific::GsBase,
+    },
+    VirtAddr,
+};
+
+/// The maximum number of CPUs supported.
+pub(crate) const MAX_CPUS: usize = 16;
+
+/// The APIC IDs of the CPUs, indexed by the CPU index. The bootstrap processor comes first.
+static APIC_IDS: OnceCell<ArrayVec<u8, MAX_CPUS>> = OnceCell::uninit();
+#[allow(clippy::declare_interior_mutable_const)] // used only as an array initializer
+const OFFLINE: AtomicBool = AtomicBool::new(false);
+static ONLINE: [AtomicBool; MAX_CPUS] = [OFFLINE; MAX_CPUS];
+
+/// Returns the local APIC ID of the current CPU.
+pub(crate) fn lapic_id() -> u8 {
+    local_apic::id()
+}
+
+/// Registers the CPUs found by the firmware, and marks the bootstrap processor online.
+///
+/// `ap_apic_ids` must not contain the APIC ID of the bootstrap processor.
+pub(crate) fn init_cpus(ap_apic_ids: impl IntoIterator<Item = u8>) {
+    APIC_IDS.init_once(|| {
+        let mut ids = ArrayVec::new();
+        ids.push(lapic_id());
+        for id in ap_apic_ids {
+            if ids.try_push(id).is_err() {
+                warn!("too many CPUs, APIC ID {} is ignored", id);
+            }
+        }
+        ids
+    });
+    set_online(0);
+}
+
+/// Returns the APIC IDs of the CPUs, indexed by the CPU index.
+pub(crate) fn apic_ids() -> &'static [u8] {
+    APIC_IDS.try_get().map(|ids| &ids[..]).unwrap_or(&[])
+}
+
+/// Per-CPU data, pointed to by the GS base of each CPU.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct CpuLocal {
+    /// The CPU index, read from `gs:[0]`.
+    index: usize,
+}
+
+const fn cpu_locals() -> [CpuLocal; MAX_CPUS] {
+    let mut locals = [CpuLocal { index: 0 }; MAX_CPUS];
+    let mut i = 0;
+    while i < MAX_CPUS {
+        locals[i].index = i;
+        i += 1;
+    }
+    locals
+}
+
+static CPU_LOCALS: [CpuLocal; MAX_CPUS] = cpu_locals();
+static SSE_ENABLED: AtomicBool = AtomicBool::new(false);
+static CPU_LOCAL_READY: AtomicBool = AtomicBool::new(false);
+
+/// Points the GS base of the current CPU to the per-CPU data of `index`.
+///
+/// Application processors must call this before anything calls [`current_index`].
+pub(crate) fn init_local(index: usize) {
+    GsBase::write(VirtAddr::from_ptr(&CPU_LOCALS[index]));
+    CPU_LOCAL_READY.store(true, Ordering::Release);
+}
+
+/// Returns the index of the current CPU, which is less than [`MAX_CPUS`].
+///
+/// Returns 0 until the bootstrap processor calls [`init_local`].
+pub(crate) fn current_index() -> usize {
+    if !CPU_LOCAL_READY.load(Ordering::Acquire) {
+        return 0;
+    }
+    let index: usize;
+    unsafe {
+        asm!(
+            "mov {}, gs:[0]",
+            out(reg) index,
+            options(nostack, readonly, preserves_flags)
+        )
+    };
+    index
+}
+
+pub(crate) fn set_online(index: usize) {
+    ONLINE[index].store(true, Ordering::Release);
+}
+
+/// Marks the CPU offline, e.g. after it lost its state in S3.
+#[cfg(feature = "acpi-s3")]
+pub(crate) fn set_offline(index: usize) {
+    ONLINE[index].store(false, Ordering::Release);
+}
+
+pub(crate) fn is_online(index: usize) -> bool {
+    ONLINE[index].load(Ordering::Acquire)
+}
+
+/// Returns the number of CPUs which finished their initialization.
+pub(crate) fn online_count() -> usize {
+    ONLINE
+        .iter()
+        .filter(|online| online.load(Ordering::Relaxed))
+        .count()
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Features {
+    pub(crate) tsc: bool,
+    /// Machine-check exception
+    pub(crate) mce: bool,
+    /// Machine-check architecture, which provides the error banks
+    pub(crate) mca: bool,
+    /// TSC runs at a constant rate in all P-, C- and T-states
+    pub(crate) invariant_tsc: bool,
+    /// LAPIC timer can be programmed with a TSC deadline
+    pub(crate) tsc_deadline: bool,
+    pub(crate) rdrand: bool,
+    /// SSE2, and FXSAVE/FXRSTOR required to enable it
+    pub(crate) sse2: bool,
+    /// Supervisor Mode Execution Prevention
+    pub(crate) smep: bool,
+    /// Supervisor Mode Access Prevention
+    pub(crate) smap: bool,
+    pub(crate) monitor_mwait: bool,
+    /// MWAIT can be woken by interrupts even if they are masked
+    pub(crate) mwait_interrupt_break: bool,
+    /// The number of MWAIT sub-states of each C-state, 4 bits per C-state from C0
+    pub(crate) mwait_sub_states: u32,
+    /// Always Running APIC Timer, which keeps counting in deep C-states
+    pub(crate) arat: bool,
+}
+
+impl Features {
+    fn detect() -> Self {
+        let max_leaf = unsafe { __cpuid_count(0, 0) }.eax;
+        let leaf1 = unsafe { __cpuid_count(1, 0) };
+        let leaf7 = (max_leaf >= 7).then(|| unsafe { __cpuid_count(7, 0) });
+        let leaf7_ebx = leaf7.map(|leaf| leaf.ebx).unwrap_or(0);
+        let leaf5 = (max_leaf >= 5).then(|| unsafe { __cpuid_count(5, 0) });
+        let leaf5_ecx = leaf5.map(|leaf| leaf.ecx).unwrap_or(0);
+        let leaf6_eax = (max_leaf >= 6)
+            .then(|| unsafe { __cpuid_count(6, 0) }.eax)
+            .unwrap_or(0);
+        let max_extended_leaf = unsafe { __cpuid_count(0x8000_0000, 0) }.eax;
+        let leaf8000_0007_edx = (max_extended_leaf >= 0x8000_0007)
+            .then(|| unsafe { __cpuid_count(0x8000_0007, 0) }.edx)
+            .unwrap_or(0);
+        // ECX bit 0 tells whether ECX bit 1 and EDX are valid
+        let mwait_extensions = (leaf5_ecx & (1 << 0)) != 0;
+        Self {
+            tsc: (leaf1.edx & (1 << 4)) != 0,
+            mce: (leaf1.edx & (1 << 7)) != 0,
+            mca: (leaf1.edx & (1 << 14)) != 0,
+            invariant_tsc: (leaf8000_0007_edx & (1 << 8)) != 0,
+            tsc_deadline: (leaf1.ecx & (1 << 24)) != 0,
+            rdrand: (leaf1.ecx & (1 << 30)) != 0,
+            sse2: (leaf1.edx & (1 << 26)) != 0 && (leaf1.edx & (1 << 24)) != 0,
+            smep: (leaf7_ebx & (1 << 7)) != 0,
+            smap: (leaf7_ebx & (1 << 20)) != 0,
+            monitor_mwait: (leaf1.ecx & (1 << 3)) != 0,
+            mwait_interrupt_break: mwait_extensions && (leaf5_ecx & (1 << 1)) != 0,
+            mwait_sub_states: leaf5
+                .filter(|_| mwait_extensions)
+                .map(|leaf| leaf.edx)
+                .unwrap_or(0),
+            arat: (leaf6_eax & (1 << 2)) != 0,
+        }
+    }
+}
+
+static FEATURES: OnceCell<Features> = OnceCell::uninit();
+
+pub(crate) fn init() {
+    let features = Features::detect();
+    debug!("CPU features: {:?}", features);
+    FEATURES.init_once(|| features);
+
+    // The kernel must not execute or implicitly access user pages.
+    // No user pages exist yet, so these can be enabled unconditionally.
+    let mut flags = Cr4Flags::empty();
+    if features.smep {
+        flags |= Cr4Flags::SUPERVISOR_MODE_EXECUTION_PROTECTION;
+    }
+    if features.smap {
+        flags |= Cr4Flags::SUPERVISOR_MODE_ACCESS_PREVENTION;
+    }
+    // The kernel is compiled without SSE, so the XMM registers are not saved on task switches.
+    // SSE is only used by code which runs with interrupts disabled (e.g. `graphics::simd`).
+    // Application processors copy CR0 and CR4 of the bootstrap processor.
+    if features.sse2 {
+        flags |= Cr4Flags::OSFXSR | Cr4Flags::OSXMMEXCPT_ENABLE;
+        unsafe {
+            Cr0::update(|cr0| {
+                cr0.remove(Cr0Flags::EMULATE_COPROCESSOR);
+                cr0.insert(Cr0Flags::MONITOR_COPROCESSOR);
+            })
+        };
+    }
+    unsafe { Cr4::update(|cr4| cr4.insert(flags)) };
+    SSE_ENABLED.store(features.sse2, Ordering::Release);
+}
+
+/// Returns `true` if SSE instructions (up to SSE2) can be executed.
+pub(crate) fn is_sse_enabled() -> bool {
+    SSE_ENABLED.load(Ordering::Acquire)
+}
+
+pub(crate) fn features() -> &'static Features {
+    FEATURES.get()
+}
+
+/// Guard which allows the kernel to access user pages while it is alive.
+///
+/// Used by the code which copies data from/to user memory (e.g. system call handlers).
+#[derive(Debug)]
+pub(crate) struct UserAccessGuard {
+    _private: (),
+}
+
+impl UserAccessGuard {
+    // no user memory exists until the system call layer lands
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub(crate) fn new() -> Self {
+        if features().smap {
+            unsafe { asm!("stac", options(nostack)) };
+        }
+        Self { _private: () }
+    }
+}
+
+impl Drop for UserAccessGuard {
+    fn drop(&mut self) {
+        if features().smap {
+            unsafe { asm!("clac", options(nostack)) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use x86_64::registers::rflags::{self, RFlags};
+
+    #[test_case]
+    fn user_access_guard() {
+        let guard = UserAccessGuard::new();
+        assert_eq!(
+            rflags::read().contains(RFlags::ALIGNMENT_CHECK),
+            features().smap
+        );
+        drop(guard);
+        assert!(!rflags::read().contains(RFlags::ALIGNMENT_CHECK));
+    }
+}
@@ -1,32 +1,116 @@
 use crate::{
-    graphics::{Color, Draw, Point, Rectangle, ScreenInfo, Size},
-    layer,
+    graphics::{font, Color, Draw, Point, Rectangle, ScreenInfo, Size},
+    layer::{self, TaskbarItem},
+    mouse::MouseButton,
     prelude::*,
-    window::Window,
+    sync::watch,
+    theme::{self, Theme},
+    time, timer,
+    window::{Window, WindowEvent},
 };
+use alloc::{format, vec::Vec};
+use core::convert::TryFrom;
+use futures_util::{select_biased, FutureExt as _, StreamExt as _};
 
-pub(crate) const BG_COLOR: Color = Color::new(45, 118, 237);
-pub(crate) const FG_COLOR: Color = Color::WHITE;
+pub(crate) const TASKBAR_HEIGHT: i32 = 50;
+const START_BUTTON_WIDTH: i32 = 120;
+const ITEM_WIDTH: i32 = 160;
+const ITEM_HEIGHT: i32 = 34;
+const ITEM_MARGIN: i32 = 4;
+/// Width of "YYYY-MM-DD hh:mm" in characters.
+const CLOCK_LEN: i32 = 16;
+const CLOCK_MARGIN: i32 = 10;
+
+/// Returns the screen area which is not covered by the taskbar.
+pub(crate) fn work_area(screen_size: Size<i32>) -> Rectangle<i32> {
+    Rectangle::new(
+        Point::new(0, 0),
+        Size::new(screen_size.x, screen_size.y - TASKBAR_HEIGHT),
+    )
+}
 
 fn draw(drawer: &mut dyn Draw, size: Size<i32>) {
-    drawer.fill_rect(
-        Rectangle::new(Point::new(0, 0), Size::new(size.x, size.y - 50)),
-        BG_COLOR,
-    );
-    drawer.fill_rect(
-        Rectangle::new(Point::new(0, size.y - 50), Size::new(size.x, 50)),
-        Color::new(1, 8, 17),
-    );
-    drawer.fill_rect(
-        Rectangle::new(Point::new(0, size.y - 50), Size::new(size.x / 5, 50)),
-        Color::new(80, 80, 80),
+    let theme = theme::current();
+    drawer.fill_rect(Rectangle::new(Point::new(0, 0), size), theme.desktop_bg);
+}
+
+/// Draws the taskbar except the items, in a layer of `size`.
+fn draw_taskbar(drawer: &mut dyn Draw, size: Size<i32>, theme: &Theme) {
+    drawer.fill_rect(Rectangle::new(Point::new(0, 0), size), theme.taskbar_bg);
+    drawer.fill_vertical_gradient(
+        Rectangle::new(Point::new(0, 0), Size::new(START_BUTTON_WIDTH, size.y)),
+        theme.start_button.lighten(0x30),
+        theme.start_button.darken(0x30),
     );
     drawer.draw_rect(
-        Rectangle::new(Point::new(10, size.y - 40), Size::new(30, 30)),
+        Rectangle::new(Point::new(10, 10), Size::new(30, 30)),
         Color::new(160, 160, 160),
     );
 }
 
+/// Returns the area of the `index`-th item of the taskbar.
+fn item_area(index: usize) -> Rectangle<i32> {
+    let index = i32::try_from(index).unwrap_or(i32::MAX);
+    Rectangle::new(
+        Point::new(
+            START_BUTTON_WIDTH + ITEM_MARGIN + (ITEM_WIDTH + ITEM_MARGIN).saturating_mul(index),
+            (TASKBAR_HEIGHT - ITEM_HEIGHT) / 2,
+        ),
+        Size::new(ITEM_WIDTH, ITEM_HEIGHT),
+    )
+}
+
+/// Draws the items of the taskbar, and clears the area of the items removed.
+fn draw_items(window: &mut Window, items: &[TaskbarItem], old_len: usize, theme: &Theme) {
+    for (index, item) in items.iter().enumerate() {
+        let area = item_area(index);
+        // the active window looks pressed, and minimized windows are dimmed
+        let light = theme.taskbar_item.lighten(0x40);
+        let (background, top_left, bottom_right) = if item.active {
+            (theme.taskbar_item_active, Color::BLACK, light)
+        } else {
+            (theme.taskbar_item, light, Color::BLACK)
+        };
+        let fg = if item.minimized {
+            theme.desktop_fg.darken(0x60)
+        } else {
+            theme.desktop_fg
+        };
+        window.draw_box(area, background, top_left, bottom_right);
+        let text_pos = Point::new(
+            area.x_start() + 6,
+            area.y_start() + (area.size.y - font::pixel_size().y) / 2,
+        );
+        // long titles are cut at the right end of the item
+        window.push_clip(Rectangle::new(
+            area.pos + Point::new(2, 2),
+            area.size - Size::new(4, 4),
+        ));
+        window.draw_str(text_pos, &item.title, fg);
+        window.pop_clip();
+    }
+    for index in items.len()..old_len {
+        window.fill_rect(item_area(index), theme.taskbar_bg);
+    }
+}
+
+/// Draws the wall clock at the right end of the taskbar.
+fn draw_clock(window: &mut Window, size: Size<i32>, theme: &Theme) {
+    let text_size = font::pixel_size() * Size::new(CLOCK_LEN, 1);
+    let pos = Point::new(
+        size.x - text_size.x - CLOCK_MARGIN,
+        (size.y - text_size.y) / 2,
+    );
+    window.fill_rect(Rectangle::new(pos, text_size), theme.taskbar_bg);
+    if let Some(now) = time::local_now().map(|now| now.date_time) {
+        let text = format!(
+            "{:04}-{:02}-{:02} {:02}:{:02}",
+            now.year, now.month, now.day, now.hour, now.minute
+        );
+        window.draw_str(pos, &text, theme.desktop_fg);
+    }
+}
+
 pub(crate) async fn handler_task() -> Result<()> {
     let screen_info = ScreenInfo::get();
     let mut window = Window::builder()
@@ -37,5 +121,89 @@ pub(crate) async fn handler_task() -> Result<()> {
     draw(&mut window, screen_info.size);
     window.flush().await?;
 
+    // the layer is kept until shutdown
+    while let Some(event) = window.recv_event().await {
+        if let WindowEvent::Redraw = event {
+            draw(&mut window, screen_info.size);
+            window.flush().await?;
+        }
+    }
+
     Ok(())
 }
+
+/// Runs the taskbar, which lists the windows and shows the clock.
+///
+/// Clicking an item of the taskbar restores and activates its window, or minimizes the window
+/// if it is active.
+pub(crate) async fn taskbar_task() -> Result<()> {
+    let screen_info = ScreenInfo::get();
+    let size = Size::new(screen_info.size.x, TASKBAR_HEIGHT);
+    let mut window = Window::builder()
+        .pos(Point::new(0, screen_info.size.y - TASKBAR_HEIGHT))
+        .size(size)
+        .height(usize::MAX)
+        .always_on_top(true)
+        .clickable(true)
+        .build()?;
+
+    let event_tx = layer::event_tx();
+    let (tx, mut rx) = watch::channel(Vec::new());
+    event_tx.watch_taskbar(tx)?;
+
+    let theme = theme::current();
+    draw_taskbar(&mut window, size, &theme);
+    draw_clock(&mut window, size, &theme);
+    window.flush().await?;
+
+    // the clock shows minutes, so redrawing it every second is precise enough
+    let mut interval = timer::lapic::interval(0, timer::lapic::TICKS_PER_SECOND)?;
+    let mut last_minute = None;
+    let mut items = Vec::<TaskbarItem>::new();
+    loop {
+        select_biased! {
+            new_items = rx.next().fuse() => {
+                let new_items = match new_items {
+                    Some(new_items) => new_items,
+                    None => return Ok(()),
+                };
+                let old_len = items.len();
+                items = new_items;
+                draw_items(&mut window, &items, old_len, &theme::current());
+            }
+            event = window.recv_event().fuse() => match event {
+                Some(WindowEvent::Mouse(event)) if event.down.contains(MouseButton::Left) => {
+                    let item = (0..items.len())
+                        .find(|index| item_area(*index).contains(&event.pos))
+                        .map(|index| &items[index]);
+                    match item {
+                        Some(item) if item.active => event_tx.minimize(item.layer_id)?,
+                        Some(item) => event_tx.restore(item.layer_id)?,
+                        None => {}
+                    }
+                }
+                Some(WindowEvent::Redraw) => {
+                    let theme = theme::current();
+                    draw_taskbar(&mut window, size, &theme);
+                    draw_items(&mut window, &items, items.len(), &theme);
+                    draw_clock(&mut window, size, &theme);
+                }
+                Some(_) => {}
+                None => return Ok(()),
+            },
+            timeout = interval.next().fuse() => {
+                let _ = match timeout {
+                    Some(timeout) => timeout?,
+                    None => return Ok(()),
+                };
+                let minute =
+                    time::local_now().map(|now| (now.date_time.hour, now.date_time.minute));
+                if minute != last_minute {
+                    last_minute = minute;
+                    draw_clock(&mut window, size, &theme::current());
+                }
+            }
+        }
+        window.flush().await?;
+    }
+}
@@ -0,0 +1,96 @@
+//! Registry of the devices known to the kernel.
+//!
+//! Devices form a tree. PCI functions are under the PCI root, USB devices under their host
+//! controller, and platform devices (fixed devices found without bus enumeration) under the
+//! platform root. Devices are never removed; unplugged devices are kept in the `Detached` state.
+
+use crate::sync::{OnceCell, SpinMutex};
+use alloc::{string::String, vec::Vec};
+use core::fmt;
+use x86_64::instructions::interrupts;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(crate) struct DeviceId(usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum DeviceState {
+    /// No driver handles the device.
+    Unbound,
+    Bound,
+    /// The driver failed to initialize the device.
+    Failed,
+    /// The device is unplugged.
+    Detached,
+}
+
+impl fmt::Display for DeviceState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            DeviceState::Unbound => "unbound",
+            DeviceState::Bound => "bound",
+            DeviceState::Failed => "failed",
+            DeviceState::Detached => "detached",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct DeviceEntry {
+    pub(crate) id: DeviceId,
+    pub(crate) parent: Option<DeviceId>,
+    pub(crate) name: String,
+    pub(crate) driver: Option<&'static str>,
+    pub(crate) state: DeviceState,
+}
+
+static REGISTRY: SpinMutex<Vec<DeviceEntry>> = SpinMutex::new(Vec::new());
+static PLATFORM_ROOT: OnceCell<DeviceId> = OnceCell::uninit();
+static PCI_ROOT: OnceCell<DeviceId> = OnceCell::uninit();
+
+/// Registers a device. This must be called after the heap is initialized.
+pub(crate) fn register(
+    parent: Option<DeviceId>,
+    name: impl Into<String>,
+    driver: Option<&'static str>,
+    state: DeviceState,
+) -> DeviceId {
+    let name = name.into();
+    interrupts::without_interrupts(|| {
+        let mut registry = REGISTRY.lock();
+        let id = DeviceId(registry.len());
+        registry.push(DeviceEntry {
+            id,
+            parent,
+            name,
+            driver,
+            state,
+        });
+        id
+    })
+}
+
+/// Registers a platform device bound to `driver`.
+pub(crate) fn register_platform(name: impl Into<String>, driver: &'static str) -> DeviceId {
+    let root = *PLATFORM_ROOT.get_or_init(|| register(None, "platform", None, DeviceState::Bound));
+    register(Some(root), name, Some(driver), DeviceState::Bound)
+}
+
+/// Returns the parent of PCI functions.
+pub(crate) fn pci_root() -> DeviceId {
+    *PCI_ROOT.get_or_init(|| register(None, "pci", None, DeviceState::Bound))
+}
+
+pub(crate) fn set_state(id: DeviceId, driver: Option<&'static str>, state: DeviceState) {
+    interrupts::without_interrupts(|| {
+        if let Some(entry) = REGISTRY.lock().get_mut(id.0) {
+            entry.driver = driver;
+            entry.state = state;
+        }
+    })
+}
+
+/// Returns all devices in the order of registration, where parents precede their children.
+pub(crate) fn entries() -> Vec<DeviceEntry> {
+    interrupts::without_interrupts(|| REGISTRY.lock().clone())
+}
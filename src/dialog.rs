@@ -0,0 +1,130 @@
+//! Modal dialogs, which block input to their owner windows until they are dismissed.
+//!
+//! A dialog is centered over its owner, and runs in its own task. The result is sent through the
+//! returned oneshot channel when the dialog is dismissed, and the receiver resolves to
+//! [`Canceled`](crate::sync::oneshot::Canceled) if the dialog fails.
+
+use crate::{
+    layer::LayerId,
+    prelude::*,
+    sync::oneshot,
+    task::{self, Task},
+    ui::{Button, Label, Layout, TextBox, Ui, UiEvent, Widget, WidgetId},
+};
+use alloc::{string::String, vec, vec::Vec};
+use x86_64::instructions::interrupts;
+
+/// The number of cells of the text box of prompts.
+const PROMPT_WIDTH: i32 = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Message,
+    Confirm,
+    Prompt,
+}
+
+#[derive(Debug)]
+struct Dialog {
+    ui: Ui,
+    text_box: Option<WidgetId>,
+    ok: WidgetId,
+    cancel: Option<WidgetId>,
+}
+
+impl Dialog {
+    fn new(owner: LayerId, kind: Kind, title: String, message: &str) -> Result<Self> {
+        let mut builder = Ui::builder(title).modal_for(owner);
+        let mut children = message
+            .lines()
+            .map(|line| builder.add(Label::new(line.into())))
+            .collect::<Vec<_>>();
+        let text_box = (kind == Kind::Prompt).then(|| builder.add(TextBox::new(PROMPT_WIDTH)));
+        children.extend(text_box);
+
+        let ok = builder.add(Button::new("OK".into()));
+        let cancel = (kind != Kind::Message).then(|| builder.add(Button::new("Cancel".into())));
+        let mut buttons = vec![ok];
+        buttons.extend(cancel);
+        children.push(builder.add(Layout::horizontal(buttons)));
+
+        let root = builder.add(Layout::vertical(children));
+        Ok(Self {
+            ui: builder.build(root)?,
+            text_box,
+            ok,
+            cancel,
+        })
+    }
+
+    /// Runs the dialog until it is dismissed, and returns the text entered if it is accepted.
+    async fn run(mut self) -> Result<Option<String>> {
+        loop {
+            self.ui.flush().await?;
+            let event = match self.ui.recv_event().await {
+                Some(event) => event?,
+                None => return Ok(None),
+            };
+            match event {
+                UiEvent::Clicked(id) if id == self.ok => break,
+                UiEvent::Submitted(id) if Some(id) == self.text_box => break,
+                UiEvent::Clicked(id) if Some(id) == self.cancel => return Ok(None),
+                UiEvent::CloseRequested => return Ok(None),
+                _ => {}
+            }
+        }
+        let text = match self.text_box.and_then(|id| self.ui.widget(id)) {
+            Some(Widget::TextBox(text_box)) => text_box.text(),
+            _ => String::new(),
+        };
+        Ok(Some(text))
+    }
+
+    /// Runs the dialog in a new task, which sends the result converted by `f`.
+    fn spawn<T>(self, f: impl FnOnce(Option<String>) -> T + Send + 'static) -> oneshot::Receiver<T>
+    where
+        T: Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        let task = Task::new(task::DEFAULT_STACK_SIZE, async move {
+            match self.run().await {
+                Ok(result) => {
+                    let _ = tx.send(f(result));
+                }
+                Err(err) => warn!("dialog: {}", err),
+            }
+        });
+        interrupts::without_interrupts(|| task::spawn(task));
+        rx
+    }
+}
+
+/// Shows `message` with an OK button.
+pub(crate) fn message_box(
+    owner: LayerId,
+    title: String,
+    message: &str,
+) -> Result<oneshot::Receiver<()>> {
+    let dialog = Dialog::new(owner, Kind::Message, title, message)?;
+    Ok(dialog.spawn(|_| ()))
+}
+
+/// Asks the user to confirm `message`, and sends `true` if OK is clicked.
+pub(crate) fn confirm(
+    owner: LayerId,
+    title: String,
+    message: &str,
+) -> Result<oneshot::Receiver<bool>> {
+    let dialog = Dialog::new(owner, Kind::Confirm, title, message)?;
+    Ok(dialog.spawn(|result| result.is_some()))
+}
+
+/// Asks the user to enter a line of text, and sends `None` if the prompt is cancelled.
+pub(crate) fn prompt(
+    owner: LayerId,
+    title: String,
+    message: &str,
+) -> Result<oneshot::Receiver<Option<String>>> {
+    let dialog = Dialog::new(owner, Kind::Prompt, title, message)?;
+    Ok(dialog.spawn(|result| result))
+}
@@ -0,0 +1,108 @@
+//! Physically contiguous buffers for device DMA.
+
+use crate::{memory, paging, prelude::*};
+use core::{ptr, slice};
+use x86_64::{
+    structures::paging::{frame::PhysFrameRange, PageSize, Size4KiB},
+    PhysAddr, VirtAddr,
+};
+
+/// Physical address limit for devices which support only 32-bit addressing.
+const LIMIT_4GIB: u64 = 0x1_0000_0000;
+
+#[derive(Debug)]
+pub(crate) struct Builder {
+    size: usize,
+    align: usize,
+    limit: Option<PhysAddr>,
+}
+
+impl Builder {
+    fn new(size: usize) -> Self {
+        Self {
+            size,
+            align: Size4KiB::SIZE as usize,
+            limit: None,
+        }
+    }
+
+    /// Sets the alignment of the physical address, which must be a power of two.
+    ///
+    /// Buffers are always aligned to at least 4 KiB.
+    pub(crate) fn align(mut self, align: usize) -> Self {
+        assert!(align.is_power_of_two());
+        self.align = usize::max(align, Size4KiB::SIZE as usize);
+        self
+    }
+
+    /// Places the buffer below 4 GiB for devices that support only 32-bit addressing.
+    pub(crate) fn below_4gib(mut self) -> Self {
+        self.limit = Some(PhysAddr::new(LIMIT_4GIB));
+        self
+    }
+
+    pub(crate) fn build(self) -> Result<DmaBuffer> {
+        let page_size = Size4KiB::SIZE as usize;
+        let num_frames = (self.size + page_size - 1) / page_size;
+        let align_frames = self.align / page_size;
+
+        let frames = memory::with_memory_manager(|allocator| match self.limit {
+            Some(limit) => allocator.allocate_below(num_frames, align_frames, limit),
+            None => allocator.allocate_aligned(num_frames, align_frames),
+        })?;
+
+        let phys_addr = frames.start.start_address();
+        let virt_addr = paging::phys_to_virt(phys_addr);
+        unsafe { ptr::write_bytes(virt_addr.as_mut_ptr::<u8>(), 0, num_frames * page_size) };
+
+        Ok(DmaBuffer {
+            frames,
+            phys_addr,
+            virt_addr,
+            len: self.size,
+        })
+    }
+}
+
+/// Zero-filled, physically contiguous buffer, which is freed on drop.
+#[derive(Debug)]
+pub(crate) struct DmaBuffer {
+    frames: PhysFrameRange,
+    phys_addr: PhysAddr,
+    virt_addr: VirtAddr,
+    len: usize,
+}
+
+impl DmaBuffer {
+    pub(crate) fn builder(size: usize) -> Builder {
+        Builder::new(size)
+    }
+
+    /// Returns the address to be passed to devices.
+    pub(crate) fn phys_addr(&self) -> PhysAddr {
+        self.phys_addr
+    }
+
+    /// Returns the address to be accessed by CPU.
+    pub(crate) fn virt_addr(&self) -> VirtAddr {
+        self.virt_addr
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    pub(crate) fn as_slice(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.virt_addr.as_ptr(), self.len) }
+    }
+
+    pub(crate) fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.virt_addr.as_mut_ptr(), self.len) }
+    }
+}
+
+impl Drop for DmaBuffer {
+    fn drop(&mut self) {
+        memory::with_memory_manager(|allocator| allocator.free(self.frames));
+    }
+}
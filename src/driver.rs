@@ -0,0 +1,160 @@
+//! PCI device drivers.
+//!
+//! Each driver lists match rules for the devices it handles. After the PCI bus is scanned,
+//! [`probe_all`] probes the drivers with the matching devices, and records which driver is bound
+//! to each device, also in the [`device`] registry. A device is bound to at most one driver.
+
+use crate::{
+    device::{self, DeviceId, DeviceState},
+    pci::Device,
+    prelude::*,
+    sync::OnceCell,
+    virtio, xhc,
+};
+use alloc::{format, vec::Vec};
+use custom_debug_derive::Debug as CustomDebug;
+use x86_64::structures::paging::OffsetPageTable;
+
+/// Matches devices by vendor/device IDs and class code. Unset fields match any device.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct MatchRule {
+    vendor_id: Option<u16>,
+    device_id: Option<u16>,
+    class_code: Option<(u8, u8, u8)>,
+}
+
+impl MatchRule {
+    pub(crate) const fn class(base: u8, sub: u8, interface: u8) -> Self {
+        Self {
+            vendor_id: None,
+            device_id: None,
+            class_code: Some((base, sub, interface)),
+        }
+    }
+
+    pub(crate) const fn id(vendor_id: u16, device_id: u16) -> Self {
+        Self {
+            vendor_id: Some(vendor_id),
+            device_id: Some(device_id),
+            class_code: None,
+        }
+    }
+
+    pub(crate) const fn vendor(self, vendor_id: u16) -> Self {
+        Self {
+            vendor_id: Some(vendor_id),
+            ..self
+        }
+    }
+
+    fn matches(&self, dev: &Device) -> bool {
+        self.vendor_id.map_or(true, |id| id == dev.vendor_id)
+            && self.device_id.map_or(true, |id| id == dev.device_id)
+            && self.class_code.map_or(true, |(base, sub, interface)| {
+                dev.class_code.test3(base, sub, interface)
+            })
+    }
+}
+
+/// Resources passed to probe callbacks.
+#[derive(Debug)]
+pub(crate) struct ProbeContext<'a> {
+    pub(crate) mapper: &'a mut OffsetPageTable<'static>,
+    /// All devices found by scanning.
+    pub(crate) devices: &'a [Device],
+    /// The registry entry of the probed device, which is the parent of devices behind it.
+    pub(crate) device_id: DeviceId,
+}
+
+#[derive(CustomDebug)]
+pub(crate) struct Driver {
+    pub(crate) name: &'static str,
+    /// Devices matching earlier rules are probed first.
+    pub(crate) rules: &'static [MatchRule],
+    /// The number of devices the driver can be bound to.
+    pub(crate) max_devices: usize,
+    /// Initializes the driver for the device. The device is bound to the driver on success.
+    #[debug(skip)]
+    pub(crate) probe: fn(&Device, &mut ProbeContext<'_>) -> Result<()>,
+}
+
+/// Registered drivers, probed in this order.
+static DRIVERS: &[&Driver] = &[&xhc::DRIVER, &virtio::net::DRIVER, &virtio::rng::DRIVER];
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Binding {
+    pub(crate) device: Device,
+    pub(crate) driver: &'static str,
+}
+
+static BINDINGS: OnceCell<Vec<Binding>> = OnceCell::uninit();
+
+fn same_device(a: &Device, b: &Device) -> bool {
+    (a.bus, a.device, a.function) == (b.bus, b.device, b.function)
+}
+
+/// Registers the devices to the device registry, and probes the registered drivers with the
+/// matching devices.
+pub(crate) fn probe_all(devices: &[Device], mapper: &mut OffsetPageTable<'static>) {
+    let pci_root = device::pci_root();
+    let ids = devices
+        .iter()
+        .map(|dev| {
+            let name = format!(
+                "{:02x}.{:02x}.{:02x} {:04x}:{:04x} class={}",
+                dev.bus, dev.device, dev.function, dev.vendor_id, dev.device_id, dev.class_code
+            );
+            device::register(Some(pci_root), name, None, DeviceState::Unbound)
+        })
+        .collect::<Vec<_>>();
+
+    let mut bindings = Vec::<Binding>::new();
+    let mut cx = ProbeContext {
+        mapper,
+        devices,
+        device_id: pci_root,
+    };
+    for driver in DRIVERS {
+        let mut bound = 0;
+        for rule in driver.rules {
+            for (dev, id) in devices.iter().zip(&ids) {
+                if bound >= driver.max_devices {
+                    break;
+                }
+                if !rule.matches(dev) || bindings.iter().any(|b| same_device(&b.device, dev)) {
+                    continue;
+                }
+                cx.device_id = *id;
+                match (driver.probe)(dev, &mut cx) {
+                    Ok(()) => {
+                        info!("{}: bound to {}", driver.name, dev);
+                        device::set_state(*id, Some(driver.name), DeviceState::Bound);
+                        bindings.push(Binding {
+                            device: *dev,
+                            driver: driver.name,
+                        });
+                        bound += 1;
+                    }
+                    Err(err) => {
+                        warn!("{}: failed to probe {}: {}", driver.name, dev, err);
+                        device::set_state(*id, Some(driver.name), DeviceState::Failed);
+                    }
+                }
+            }
+        }
+        if bound == 0 {
+            info!("{}: no device found", driver.name);
+        }
+    }
+    BINDINGS.init_once(|| bindings);
+}
+
+/// Returns the name of the driver bound to the device.
+pub(crate) fn bound_driver(dev: &Device) -> Option<&'static str> {
+    BINDINGS
+        .try_get()
+        .ok()?
+        .iter()
+        .find(|b| same_device(&b.device, dev))
+        .map(|b| b.driver)
+}
@@ -24,23 +24,32 @@ pub(crate) struct EmergencyConsole<'a> {
     drawer: &'a mut FrameBufferDrawer,
 }
 
+impl EmergencyConsole<'_> {
+    fn newline(&mut self) {
+        self.pos.y += font::pixel_size().y;
+        self.pos.x = 0;
+    }
+}
+
 impl fmt::Write for EmergencyConsole<'_> {
     fn write_str(&mut self, s: &str) -> fmt::Result {
         serial_print!("{}", s);
 
         for ch in s.chars() {
             if ch != '\n' {
-                self.drawer.fill_rect(
-                    Rectangle::new(self.pos, font::FONT_PIXEL_SIZE),
-                    Color::WHITE,
-                );
-                self.drawer.draw_char(self.pos, ch, Color::RED);
-                self.pos.x += font::FONT_PIXEL_SIZE.x;
+                // a wide character may not fit in the rest of the line
+                if self.pos.x + font::char_size(ch).x > self.screen_info.size.x {
+                    self.newline();
+                }
+                // not `draw_char_bg`, which locks the glyph cache
+                self.drawer
+                    .fill_rect(Rectangle::new(self.pos, font::char_size(ch)), Color::WHITE);
+                let rect = self.drawer.draw_char(self.pos, ch, Color::RED);
+                self.pos.x = rect.x_end();
             }
 
-            if ch == '\n' || self.pos.x + font::FONT_PIXEL_SIZE.x > self.screen_info.size.x {
-                self.pos.y += font::FONT_PIXEL_SIZE.y;
-                self.pos.x = 0;
+            if ch == '\n' || self.pos.x + font::pixel_size().x > self.screen_info.size.x {
+                self.newline();
             }
         }
         Ok(())
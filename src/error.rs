@@ -1,8 +1,16 @@
+use crate::{sync::oneshot::Canceled, usb::xhci::CompletionCode};
 use bootloader::boot_info::PixelFormat;
 use conquer_once::{TryGetError, TryInitError};
 use core::{fmt, num::TryFromIntError, panic::Location};
-use mikanos_usb::CxxError;
-use x86_64::structures::paging::{mapper::MapToError, page::AddressNotAligned, Size4KiB};
+use x86_64::{
+    structures::paging::{
+        mapper::{FlagUpdateError, MapToError, UnmapError},
+        page::AddressNotAligned,
+        page_table::FrameError,
+        Size4KiB,
+    },
+    PhysAddr, VirtAddr,
+};
 
 pub(crate) type Result<T> = core::result::Result<T, Error>;
 
@@ -46,37 +54,99 @@ impl fmt::Display for Error {
 pub(crate) enum ErrorKind {
     AddressNotAligned(AddressNotAligned),
     MapTo(MapToError<Size4KiB>),
+    Unmap(UnmapError),
+    FlagUpdate(FlagUpdateError),
+    InvalidFrameAddress(PhysAddr),
+    PartialHugePage,
+    PageTableNotAllocated(VirtAddr),
+    FrameError(FrameError),
     TryInit(TryInitError),
     TryGet(TryGetError),
     TryFromInt(TryFromIntError),
+    Canceled(Canceled),
     FrameBufferNotSupported,
     PhysicalMemoryNotMapped,
     RsdpNotMapped,
     InvalidRsdp,
     InvalidXsdt,
     FadtNotFound,
+    #[cfg(feature = "acpi-s3")]
+    InvalidFacs,
+    InvalidDsdt,
+    InvalidMadt,
+    InvalidMcfg,
+    FileNotFound,
+    BrokenClusterChain,
+    InvalidFileName,
+    IsADirectory,
+    DirectoryFull,
+    DiskFull,
+    #[cfg(feature = "acpi-s3")]
+    SleepStateNotSupported,
+    SleepFailed,
+    UnsupportedPageTableAddress,
     UnsupportedPixelFormat(PixelFormat),
     Deadlock,
     Full,
+    NoPermit,
     NoEnoughMemory,
-    XhcNotFound,
     IndexOutOfRange,
-    InvalidSlotID,
-    InvalidEndpointNumber,
-    TransferRingNotSet,
-    AlreadyAllocated,
+    InvalidSlotID(u8),
+    InvalidEndpointNumber(u8),
+    TransferRingNotSet {
+        slot_id: u8,
+        dci: u8,
+    },
     NotImplemented,
-    InvalidDescriptor,
-    BufferTooSmall,
-    UnknownDevice,
-    NoCorrespondingSetupStage,
-    TransferFailed,
+    InvalidDescriptor {
+        slot_id: u8,
+        desc_type: u8,
+    },
+    NoCorrespondingTrb {
+        slot_id: u8,
+        dci: u8,
+        trb_addr: u64,
+    },
+    TransferFailed {
+        slot_id: u8,
+        dci: u8,
+        code: CompletionCode,
+    },
     InvalidPhase,
     UnknownXHCISpeedID,
     NoWaiter,
-    EndpointNotInCharge,
+    EndpointNotInCharge {
+        slot_id: u8,
+        ep_addr: u8,
+    },
+    NoInterruptInEndpoint {
+        interface: u8,
+    },
+    CommandFailed {
+        command: &'static str,
+        slot_id: u8,
+        code: CompletionCode,
+    },
     NoPciMsi,
-    Unknown,
+    NoFreeInterruptVector,
+    InvalidInterruptVector(u8),
+    InvalidBar,
+    MissingVirtioCapability,
+    VirtioFeaturesNotAccepted,
+    VirtqueueNotAvailable,
+    DeviceTimeout,
+    InvalidIpv4Addr,
+    NoRoute,
+    PacketTooLarge,
+    AddrInUse(u16),
+    NoFreePort,
+    ConnectionRefused,
+    ConnectionReset,
+    ConnectionTimedOut,
+    NotConnected,
+    InvalidUrl,
+    InvalidHttpResponse,
+    InvalidFont,
 }
 
 impl fmt::Display for ErrorKind {
@@ -84,12 +154,65 @@ impl fmt::Display for ErrorKind {
         match self {
             ErrorKind::AddressNotAligned(err) => write!(f, "{}", err),
             ErrorKind::MapTo(err) => write!(f, "{:?}", err),
+            ErrorKind::Unmap(err) => write!(f, "{:?}", err),
+            ErrorKind::FlagUpdate(err) => write!(f, "{:?}", err),
             ErrorKind::TryInit(err) => write!(f, "{}", err),
             ErrorKind::TryGet(err) => write!(f, "{}", err),
+            ErrorKind::Canceled(err) => write!(f, "{}", err),
             ErrorKind::UnsupportedPixelFormat(pixel_format) => {
                 write!(f, "unsupported pixel format: {:?}", pixel_format)
             }
             ErrorKind::Full => write!(f, "buffer full"),
+            ErrorKind::InvalidSlotID(slot_id) => write!(f, "invalid slot ID {}", slot_id),
+            ErrorKind::InvalidEndpointNumber(number) => {
+                write!(f, "invalid endpoint number {}", number)
+            }
+            ErrorKind::TransferRingNotSet { slot_id, dci } => write!(
+                f,
+                "no transfer ring for slot {}, endpoint DCI {}",
+                slot_id, dci
+            ),
+            ErrorKind::InvalidDescriptor { slot_id, desc_type } => write!(
+                f,
+                "invalid descriptor of type {} from slot {}",
+                desc_type, slot_id
+            ),
+            ErrorKind::NoCorrespondingTrb {
+                slot_id,
+                dci,
+                trb_addr,
+            } => write!(
+                f,
+                "no transfer issued the TRB at {:#x} (slot {}, endpoint DCI {})",
+                trb_addr, slot_id, dci
+            ),
+            ErrorKind::TransferFailed { slot_id, dci, code } => write!(
+                f,
+                "USB transfer failed on slot {}, endpoint DCI {}: completion code {}",
+                slot_id, dci, code
+            ),
+            ErrorKind::EndpointNotInCharge { slot_id, ep_addr } => write!(
+                f,
+                "no class driver is in charge of endpoint {:#04x} of slot {}",
+                ep_addr, slot_id
+            ),
+            ErrorKind::NoInterruptInEndpoint { interface } => {
+                write!(f, "interface {} has no interrupt IN endpoint", interface)
+            }
+            ErrorKind::CommandFailed {
+                command,
+                slot_id,
+                code,
+            } => write!(
+                f,
+                "xHC {} command failed on slot {}: completion code {}",
+                command, slot_id, code
+            ),
+            ErrorKind::AddrInUse(port) => write!(f, "port {} is already in use", port),
+            ErrorKind::InvalidUrl => write!(
+                f,
+                "invalid URL (expected http://<IPv4 address>[:port]/path)"
+            ),
             _ => write!(f, "{:?}", self),
         }
     }
@@ -109,6 +232,27 @@ impl From<MapToError<Size4KiB>> for Error {
     }
 }
 
+impl From<UnmapError> for Error {
+    #[track_caller]
+    fn from(err: UnmapError) -> Self {
+        Error::from(ErrorKind::Unmap(err))
+    }
+}
+
+impl From<FlagUpdateError> for Error {
+    #[track_caller]
+    fn from(err: FlagUpdateError) -> Self {
+        Error::from(ErrorKind::FlagUpdate(err))
+    }
+}
+
+impl From<FrameError> for Error {
+    #[track_caller]
+    fn from(err: FrameError) -> Self {
+        Error::from(ErrorKind::FrameError(err))
+    }
+}
+
 impl From<TryInitError> for Error {
     #[track_caller]
     fn from(err: TryInitError) -> Self {
@@ -130,29 +274,10 @@ impl From<TryFromIntError> for Error {
     }
 }
 
-impl From<CxxError> for Error {
+impl From<Canceled> for Error {
     #[track_caller]
-    fn from(err: CxxError) -> Self {
-        use ErrorKind::*;
-        let kind = match err.0 {
-            1 => NoEnoughMemory,
-            2 => InvalidSlotID,
-            3 => InvalidEndpointNumber,
-            4 => TransferRingNotSet,
-            5 => AlreadyAllocated,
-            6 => NotImplemented,
-            7 => InvalidDescriptor,
-            8 => BufferTooSmall,
-            9 => UnknownDevice,
-            10 => NoCorrespondingSetupStage,
-            11 => TransferFailed,
-            12 => InvalidPhase,
-            13 => UnknownXHCISpeedID,
-            14 => NoWaiter,
-            15 => EndpointNotInCharge,
-            _ => Unknown,
-        };
-        Error::from(kind)
+    fn from(err: Canceled) -> Self {
+        Error::from(ErrorKind::Canceled(err))
     }
 }
 
@@ -2,7 +2,10 @@ pub(crate) use self::{bpb::*, cluster_chain::*, directory::*, directory_entry::*
 use crate::{
     prelude::*,
     sync::{Mutex, MutexGuard, OnceCell},
+    time,
 };
+use alloc::vec::Vec;
+use core::{convert::TryFrom, mem, slice};
 
 mod bpb;
 mod cluster_chain;
@@ -34,3 +37,180 @@ pub(crate) fn init() {
 pub(crate) fn lock() -> MutexGuard<'static, &'static mut dyn BiosParameterBlock> {
     FILESYSTEM.get().lock()
 }
+
+/// Finds a file in the root directory by its 8.3 name, ignoring case.
+pub(crate) fn find_file<'a>(
+    bpb: &'a dyn BiosParameterBlock,
+    name: &str,
+) -> Result<&'a DirectoryEntry> {
+    let (basename, extension) = name.split_once('.').unwrap_or((name, ""));
+    for entry in bpb.root_dir().entries() {
+        let entry = entry.map_err(|_| ErrorKind::BrokenClusterChain)?;
+        if entry.basename().eq_ignore_ascii_case(basename.as_bytes())
+            && entry.extension().eq_ignore_ascii_case(extension.as_bytes())
+        {
+            return Ok(entry);
+        }
+    }
+    bail!(ErrorKind::FileNotFound)
+}
+
+/// Reads the whole contents of a file.
+pub(crate) fn read_file(bpb: &dyn BiosParameterBlock, entry: &DirectoryEntry) -> Result<Vec<u8>> {
+    let file_size = usize::try_from(entry.file_size())?;
+    let bytes_per_cluster =
+        usize::from(bpb.sectors_per_cluster()) * usize::from(bpb.bytes_per_sector());
+    let mut data = Vec::with_capacity(file_size);
+    if file_size == 0 {
+        return Ok(data);
+    }
+    for cluster in ClusterChain::new(bpb, entry.first_cluster()) {
+        let cluster = cluster.map_err(|_| ErrorKind::BrokenClusterChain)?;
+        let len = (file_size - data.len()).min(bytes_per_cluster);
+        let sector = bpb.cluster_sector(cluster);
+        data.extend_from_slice(unsafe { slice::from_raw_parts(bpb.sector_ptr(sector), len) });
+        if data.len() == file_size {
+            return Ok(data);
+        }
+    }
+    bail!(ErrorKind::BrokenClusterChain)
+}
+
+/// Converts `name` to the 8.3 name stored in a directory entry.
+fn short_name(name: &str) -> Result<[u8; 11]> {
+    let (basename, extension) = name.split_once('.').unwrap_or((name, ""));
+    if basename.is_empty() || basename.len() > 8 || extension.len() > 3 {
+        bail!(ErrorKind::InvalidFileName);
+    }
+
+    let mut short_name = [b' '; 11];
+    let (short_basename, short_extension) = short_name.split_at_mut(8);
+    let pairs = short_basename
+        .iter_mut()
+        .zip(basename.bytes())
+        .chain(short_extension.iter_mut().zip(extension.bytes()));
+    for (dst, src) in pairs {
+        if !src.is_ascii_alphanumeric() && !b"!#$%&'()-@^_`{}~".contains(&src) {
+            bail!(ErrorKind::InvalidFileName);
+        }
+        *dst = src.to_ascii_uppercase();
+    }
+    Ok(short_name)
+}
+
+fn entry_mut(bpb: &mut dyn BiosParameterBlock, offset: usize) -> &mut DirectoryEntry {
+    let bytes = &mut bpb.as_bytes_mut()[offset..offset + mem::size_of::<DirectoryEntry>()];
+    unsafe { &mut *bytes.as_mut_ptr().cast() }
+}
+
+/// Finds the entry of `short_name` in the root directory, or a free entry if it does not exist.
+///
+/// Returns the byte offset of the entry in the volume, and whether the file exists.
+fn find_slot(bpb: &dyn BiosParameterBlock, short_name: &[u8; 11]) -> Result<(usize, bool)> {
+    let base = bpb.as_bytes().as_ptr() as usize;
+    let mut free = None;
+    for entry in bpb.root_dir().slots() {
+        let entry = entry.map_err(|_| ErrorKind::BrokenClusterChain)?;
+        let offset = entry as *const DirectoryEntry as usize - base;
+        if entry.is_free() {
+            free.get_or_insert(offset);
+            if entry.is_end() {
+                break;
+            }
+            continue;
+        }
+        if entry.attr() == FileAttribute::LFN || entry.name() != *short_name {
+            continue;
+        }
+        if entry.attr().contains(FileAttribute::Directory)
+            || entry.attr().contains(FileAttribute::VolumeId)
+        {
+            bail!(ErrorKind::IsADirectory);
+        }
+        return Ok((offset, true));
+    }
+    let offset = free.ok_or(ErrorKind::DirectoryFull)?;
+    Ok((offset, false))
+}
+
+/// Allocates a chain of `count` free clusters, and returns the clusters in the chain order.
+fn allocate_chain(bpb: &mut dyn BiosParameterBlock, count: usize) -> Result<Vec<u32>> {
+    let clusters = (2..bpb.cluster_count() + 2)
+        .filter(|cluster| bpb.fat_entry(*cluster) == FatEntry::Unused)
+        .take(count)
+        .collect::<Vec<_>>();
+    if clusters.len() < count {
+        bail!(ErrorKind::DiskFull);
+    }
+    for (i, cluster) in clusters.iter().enumerate() {
+        let entry = match clusters.get(i + 1) {
+            Some(next) => FatEntry::Used(*next),
+            None => FatEntry::UsedEof(0),
+        };
+        bpb.set_fat_entry(*cluster, entry);
+    }
+    Ok(clusters)
+}
+
+fn free_chain(bpb: &mut dyn BiosParameterBlock, first_cluster: u32) -> Result<()> {
+    if first_cluster == 0 {
+        return Ok(());
+    }
+    let clusters = ClusterChain::new(bpb, first_cluster)
+        .collect::<core::result::Result<Vec<_>, _>>()
+        .map_err(|_| ErrorKind::BrokenClusterChain)?;
+    for cluster in clusters {
+        bpb.set_fat_entry(cluster, FatEntry::Unused);
+    }
+    Ok(())
+}
+
+/// Creates a file in the root directory by its 8.3 name, or overwrites it if it exists.
+///
+/// The volume is kept in memory, so written files are lost on reboot.
+pub(crate) fn write_file(bpb: &mut dyn BiosParameterBlock, name: &str, data: &[u8]) -> Result<()> {
+    let short_name = short_name(name)?;
+    let file_size = u32::try_from(data.len())?;
+    let (offset, exists) = find_slot(bpb, &short_name)?;
+
+    // the new contents are written before the old ones are freed
+    let bytes_per_cluster =
+        usize::from(bpb.sectors_per_cluster()) * usize::from(bpb.bytes_per_sector());
+    let count = (data.len() + bytes_per_cluster - 1) / bytes_per_cluster;
+    let clusters = allocate_chain(bpb, count)?;
+    for (cluster, chunk) in clusters.iter().zip(data.chunks(bytes_per_cluster)) {
+        let start = usize::try_from(bpb.sector_offset(bpb.cluster_sector(*cluster)))?;
+        bpb.as_bytes_mut()[start..start + chunk.len()].copy_from_slice(chunk);
+    }
+    let first_cluster = clusters.first().copied().unwrap_or(0);
+
+    if exists {
+        let old_cluster = entry_mut(bpb, offset).first_cluster();
+        free_chain(bpb, old_cluster)?;
+    }
+
+    let entry = entry_mut(bpb, offset);
+    if exists {
+        entry.set_first_cluster(first_cluster);
+        entry.set_file_size(file_size);
+    } else {
+        entry.init_file(short_name, first_cluster, file_size);
+    }
+    if let Some(now) = time::local_now() {
+        entry.set_modified(now.date_time);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test_case]
+    fn short_name() {
+        assert_eq!(super::short_name("index.htm").ok(), Some(*b"INDEX   HTM"));
+        assert_eq!(super::short_name("README").ok(), Some(*b"README     "));
+        assert!(super::short_name("too_long_name.txt").is_err());
+        assert!(super::short_name("a.html").is_err());
+        assert!(super::short_name(".cfg").is_err());
+        assert!(super::short_name("a b.txt").is_err());
+    }
+}
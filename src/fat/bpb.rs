@@ -202,6 +202,8 @@ pub(crate) trait BiosParameterBlock: fmt::Debug + Send {
     fn as_common(&self) -> &BpbCommon;
     fn fat_size(&self) -> u32;
     fn fat_entry(&self, cluster: u32) -> FatEntry;
+    /// Updates the entry of `cluster` in all FATs.
+    fn set_fat_entry(&mut self, cluster: u32, entry: FatEntry);
     fn root_dir(&self) -> Directory;
 
     fn as_bytes(&self) -> &[u8] {
@@ -214,6 +216,16 @@ pub(crate) trait BiosParameterBlock: fmt::Debug + Send {
         unsafe { slice::from_raw_parts(data, len) }
     }
 
+    fn as_bytes_mut(&mut self) -> &mut [u8] {
+        let bytes_per_sector = usize::from(self.bytes_per_sector());
+        #[allow(clippy::unwrap_used)]
+        let total_sectors = usize::try_from(self.total_sectors()).unwrap();
+
+        let data = self as *mut Self as *mut u8;
+        let len = total_sectors * bytes_per_sector;
+        unsafe { slice::from_raw_parts_mut(data, len) }
+    }
+
     fn sectors_bytes(&self, sector_range: Range<u32>) -> &[u8] {
         assert!(sector_range.end <= self.total_sectors());
 
@@ -265,6 +277,21 @@ pub(crate) trait BiosParameterBlock: fmt::Debug + Send {
         self.sectors_bytes(start..end)
     }
 
+    /// Returns the `index`-th copy of the FAT.
+    fn fat_bytes_mut(&mut self, index: u8) -> &mut [u8] {
+        assert!(index < self.num_fats());
+
+        let bytes_per_sector = usize::from(self.bytes_per_sector());
+        #[allow(clippy::unwrap_used)]
+        let fat_size = usize::try_from(self.fat_size()).unwrap();
+        #[allow(clippy::unwrap_used)]
+        let start =
+            usize::try_from(self.fat_start_sector()).unwrap() + usize::from(index) * fat_size;
+
+        &mut self.as_bytes_mut()
+            [(start * bytes_per_sector)..((start + fat_size) * bytes_per_sector)]
+    }
+
     fn root_dir_start_sector_16(&self) -> u32 {
         let num_fats = u32::from(self.num_fats());
         let fat_size = self.fat_size();
@@ -289,6 +316,12 @@ pub(crate) trait BiosParameterBlock: fmt::Debug + Send {
         self.root_dir_start_sector_16() + root_dir_sectors
     }
 
+    /// Returns the number of clusters in the data region, which are numbered from 2.
+    fn cluster_count(&self) -> u32 {
+        let data_sectors = self.total_sectors() - self.data_start_sector();
+        data_sectors / u32::from(self.sectors_per_cluster())
+    }
+
     fn cluster_sector(&self, cluster: u32) -> u32 {
         let cluster = cluster;
         let sectors_per_cluster = u32::from(self.sectors_per_cluster());
@@ -352,11 +385,28 @@ impl BiosParameterBlock for BpbFat12 {
         let value = if cluster % 2 == 0 {
             a | ((b & 0xf) << 8)
         } else {
-            (a >> 4) & (b << 4)
+            (a >> 4) | (b << 4)
         };
         FatEntry::from_fat12(value)
     }
 
+    fn set_fat_entry(&mut self, cluster: u32, entry: FatEntry) {
+        #[allow(clippy::unwrap_used)]
+        let cluster = usize::try_from(cluster).unwrap();
+        let value = entry.to_fat12();
+
+        for index in 0..self.num_fats() {
+            let bytes = &mut self.fat_bytes_mut(index)[cluster + cluster / 2..];
+            if cluster % 2 == 0 {
+                bytes[0] = value as u8;
+                bytes[1] = (bytes[1] & 0xf0) | ((value >> 8) as u8 & 0x0f);
+            } else {
+                bytes[0] = (bytes[0] & 0x0f) | ((value << 4) as u8);
+                bytes[1] = (value >> 4) as u8;
+            }
+        }
+    }
+
     fn root_dir(&self) -> Directory {
         Directory::new_root_dir(self.root_dir_entries_16())
     }
@@ -385,6 +435,17 @@ impl BiosParameterBlock for BpbFat16 {
         FatEntry::from_fat16(value)
     }
 
+    fn set_fat_entry(&mut self, cluster: u32, entry: FatEntry) {
+        #[allow(clippy::unwrap_used)]
+        let cluster = usize::try_from(cluster).unwrap();
+        let value = entry.to_fat16();
+
+        for index in 0..self.num_fats() {
+            let bytes = &mut self.fat_bytes_mut(index)[cluster * mem::size_of::<u16>()..];
+            bytes[..2].copy_from_slice(&value.to_le_bytes());
+        }
+    }
+
     fn root_dir(&self) -> Directory {
         Directory::new_root_dir(self.root_dir_entries_16())
     }
@@ -413,6 +474,20 @@ impl BiosParameterBlock for BpbFat32 {
         FatEntry::from_fat32(value)
     }
 
+    fn set_fat_entry(&mut self, cluster: u32, entry: FatEntry) {
+        #[allow(clippy::unwrap_used)]
+        let cluster = usize::try_from(cluster).unwrap();
+        let value = entry.to_fat32();
+
+        for index in 0..self.num_fats() {
+            let bytes = &mut self.fat_bytes_mut(index)[cluster * mem::size_of::<u32>()..];
+            // the upper 4 bits are reserved, and must be preserved
+            let old = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+            let value = (old & 0xf000_0000) | value;
+            bytes[..4].copy_from_slice(&value.to_le_bytes());
+        }
+    }
+
     fn root_dir(&self) -> Directory {
         Directory::new_cluster_chain(self.cluster_chain(self.root_cluster()))
     }
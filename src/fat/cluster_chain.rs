@@ -29,9 +29,10 @@ impl Iterator for ClusterChain<'_> {
                 self.next_entry = Some(self.bpb.fat_entry(next_cluster));
                 Ok(next_cluster)
             }
-            FatEntry::UsedEof(next_cluster) => {
+            // the previous cluster was the last one
+            FatEntry::UsedEof(_) => {
                 self.next_entry = None;
-                Ok(next_cluster)
+                return None;
             }
             FatEntry::Unused | FatEntry::Reserved | FatEntry::Bad => {
                 self.next_entry = None;
@@ -18,14 +18,25 @@ impl<'a> Directory<'a> {
     }
 
     pub(crate) fn entries(&self) -> DirectoryEntries<'a> {
+        self.iter(false)
+    }
+
+    /// Returns all entries including the free ones, to find a place for a new entry.
+    pub(super) fn slots(&self) -> DirectoryEntries<'a> {
+        self.iter(true)
+    }
+
+    fn iter(&self, include_free: bool) -> DirectoryEntries<'a> {
         match self {
             Directory::RootDir(entries) => DirectoryEntries {
                 iter: entries.iter(),
                 chain: None,
+                include_free,
             },
             Directory::ClusterChain(chain) => DirectoryEntries {
                 iter: [].iter(),
                 chain: Some(chain.clone()),
+                include_free,
             },
         }
     }
@@ -35,6 +46,7 @@ impl<'a> Directory<'a> {
 pub(crate) struct DirectoryEntries<'a> {
     iter: slice::Iter<'a, DirectoryEntry>,
     chain: Option<ClusterChain<'a>>,
+    include_free: bool,
 }
 
 impl<'a> Iterator for DirectoryEntries<'a> {
@@ -43,6 +55,9 @@ impl<'a> Iterator for DirectoryEntries<'a> {
     fn next(&mut self) -> Option<Self::Item> {
         loop {
             for entry in &mut self.iter {
+                if self.include_free {
+                    return Some(Ok(entry));
+                }
                 if entry.name()[0] == 0x00 {
                     // stop iteration
                     self.iter = [].iter();
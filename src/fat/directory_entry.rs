@@ -1,4 +1,4 @@
-use crate::byte_getter;
+use crate::{byte_getter, byte_setter, rtc::DateTime};
 use core::{fmt, mem};
 use enumflags2::{bitflags, make_bitflags, BitFlags};
 
@@ -50,7 +50,14 @@ impl DirectoryEntry {
     byte_getter!(write_time: u16);
     byte_getter!(write_date: u16);
     byte_getter!(first_cluster_low: u16);
-    byte_getter!(file_size: u32);
+    byte_getter!(pub(crate) file_size: u32);
+
+    byte_setter!(attr: u8 => set_attr);
+    byte_setter!(first_cluster_high: u16 => set_first_cluster_high);
+    byte_setter!(write_time: u16 => set_write_time);
+    byte_setter!(write_date: u16 => set_write_date);
+    byte_setter!(first_cluster_low: u16 => set_first_cluster_low);
+    byte_setter!(pub(super) file_size: u32 => set_file_size);
 }
 
 impl fmt::Debug for DirectoryEntry {
@@ -89,3 +96,71 @@ impl DirectoryEntry {
         trim_trailing(&self.name[8..], 0x20)
     }
 }
+
+impl DirectoryEntry {
+    /// Returns `true` if the entry is deleted or is past the end of the directory.
+    pub(super) fn is_free(&self) -> bool {
+        self.name[0] == 0x00 || self.name[0] == 0xe5
+    }
+
+    /// Returns `true` if this and all following entries are unused.
+    pub(super) fn is_end(&self) -> bool {
+        self.name[0] == 0x00
+    }
+
+    pub(crate) fn first_cluster(&self) -> u32 {
+        (u32::from(self.first_cluster_high()) << 16) | u32::from(self.first_cluster_low())
+    }
+
+    /// Returns the last modified time, which FAT records in the local time.
+    pub(crate) fn modified(&self) -> DateTime {
+        let date = self.write_date();
+        let time = self.write_time();
+        DateTime {
+            year: 1980 + (date >> 9),
+            month: ((date >> 5) & 0xf) as u8,
+            day: (date & 0x1f) as u8,
+            hour: (time >> 11) as u8,
+            minute: ((time >> 5) & 0x3f) as u8,
+            second: ((time & 0x1f) * 2) as u8,
+        }
+    }
+
+    /// Initializes the entry as a regular file.
+    pub(super) fn init_file(&mut self, name: [u8; 11], first_cluster: u32, file_size: u32) {
+        *self = Self {
+            name,
+            attr: [0; 1],
+            nt_res: [0; 1],
+            create_time_tenth: [0; 1],
+            create_time: [0; 2],
+            create_date: [0; 2],
+            last_access_date: [0; 2],
+            first_cluster_high: [0; 2],
+            write_time: [0; 2],
+            write_date: [0; 2],
+            first_cluster_low: [0; 2],
+            file_size: [0; 4],
+        };
+        self.set_attr(FileAttribute::Archive as u8);
+        self.set_first_cluster(first_cluster);
+        self.set_file_size(file_size);
+    }
+
+    pub(super) fn set_first_cluster(&mut self, cluster: u32) {
+        self.set_first_cluster_high((cluster >> 16) as u16);
+        self.set_first_cluster_low(cluster as u16);
+    }
+
+    /// Sets the last modified time, which is in the local time.
+    pub(super) fn set_modified(&mut self, modified: DateTime) {
+        let date = (modified.year.saturating_sub(1980) << 9)
+            | (u16::from(modified.month) << 5)
+            | u16::from(modified.day);
+        let time = (u16::from(modified.hour) << 11)
+            | (u16::from(modified.minute) << 5)
+            | u16::from(modified.second / 2);
+        self.set_write_date(date);
+        self.set_write_time(time);
+    }
+}
@@ -19,15 +19,15 @@ impl FatEntry {
         }
     }
 
-    // fn to_fat12(&self) -> u16 {
-    //     match self {
-    //         FatEntry::Unused => 0x000,
-    //         FatEntry::Reserved => 0x001,
-    //         FatEntry::Used(value) => *value as u16,
-    //         FatEntry::UsedEof(value) => *value as u16,
-    //         FatEntry::Bad => 0xff7,
-    //     }
-    // }
+    pub(super) fn to_fat12(self) -> u16 {
+        match self {
+            FatEntry::Unused => 0x000,
+            FatEntry::Reserved => 0x001,
+            FatEntry::Used(value) => value as u16,
+            FatEntry::UsedEof(_) => 0xfff,
+            FatEntry::Bad => 0xff7,
+        }
+    }
 
     pub(super) fn from_fat16(value: u16) -> Self {
         match value {
@@ -39,15 +39,15 @@ impl FatEntry {
         }
     }
 
-    // fn to_fat16(&self) -> u16 {
-    //     match self {
-    //         FatEntry::Unused => 0x0000,
-    //         FatEntry::Reserved => 0x0001,
-    //         FatEntry::Used(value) => *value as u16,
-    //         FatEntry::UsedEof(value) => *value as u16,
-    //         FatEntry::Bad => 0xfff7,
-    //     }
-    // }
+    pub(super) fn to_fat16(self) -> u16 {
+        match self {
+            FatEntry::Unused => 0x0000,
+            FatEntry::Reserved => 0x0001,
+            FatEntry::Used(value) => value as u16,
+            FatEntry::UsedEof(_) => 0xffff,
+            FatEntry::Bad => 0xfff7,
+        }
+    }
 
     pub(super) fn from_fat32(value: u32) -> Self {
         match value {
@@ -60,13 +60,13 @@ impl FatEntry {
         }
     }
 
-    // fn to_fat32(&self) -> u32 {
-    //     match self {
-    //         FatEntry::Unused => 0x0000_0000,
-    //         FatEntry::Reserved => 0x0000_0001,
-    //         FatEntry::Used(value) => *value,
-    //         FatEntry::UsedEof(value) => *value,
-    //         FatEntry::Bad => 0xffff_fff7,
-    //     }
-    // }
+    pub(super) fn to_fat32(self) -> u32 {
+        match self {
+            FatEntry::Unused => 0x0000_0000,
+            FatEntry::Reserved => 0x0000_0001,
+            FatEntry::Used(value) => value,
+            FatEntry::UsedEof(_) => 0x0fff_ffff,
+            FatEntry::Bad => 0x0fff_fff7,
+        }
+    }
 }
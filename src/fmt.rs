@@ -45,3 +45,25 @@ impl fmt::Display for ByteString<'_> {
         Ok(())
     }
 }
+
+/// Displays bytes in JIS X 0201, the single-byte part of Shift_JIS, whose half-width katakana
+/// may appear in short file names. The other non-ASCII bytes are escaped.
+pub(crate) struct Jisx0201String<'a>(pub(crate) &'a [u8]);
+
+impl fmt::Display for Jisx0201String<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for &byte in self.0 {
+            match byte {
+                0xa1..=0xdf => {
+                    f.write_char(char::from_u32(0xff61 + u32::from(byte - 0xa1)).unwrap_or('?'))?
+                }
+                _ => {
+                    for byte in ascii::escape_default(byte) {
+                        f.write_char(byte as char)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
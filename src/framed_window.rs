@@ -1,7 +1,10 @@
 use crate::{
-    graphics::{Color, Draw, Point, Rectangle, Size},
+    graphics::{font, Color, Draw, Point, Rectangle, Size},
     keyboard::KeyboardEvent,
+    layer::LayerId,
+    mouse::{MouseButton, MouseEvent},
     prelude::*,
+    theme,
     window::WindowEvent,
     window::{self, Window},
 };
@@ -26,6 +29,7 @@ impl Builder {
         let mut inner = window::Builder::new();
         inner.draggable(true);
         inner.height(usize::MAX);
+        inner.title(title.clone());
         Self { title, inner }
     }
 
@@ -34,8 +38,24 @@ impl Builder {
         self
     }
 
+    /// Makes the window a modal dialog of `owner`, which is centered over the owner.
+    pub(crate) fn modal_for(mut self, owner: LayerId) -> Self {
+        self.inner.modal_for(owner);
+        self
+    }
+
     pub(crate) fn size(mut self, size: Size<i32>) -> Self {
-        self.inner.size(size + PADDING_SIZE);
+        let size = size + PADDING_SIZE;
+        self.inner.size(size);
+        self.inner.drag_area(drag_area(size));
+        self
+    }
+
+    /// Resizes the window to fill the layout to which it is snapped.
+    ///
+    /// The content area should be redrawn on [`FramedWindowEvent::Resized`].
+    pub(crate) fn resizable(mut self, resizable: bool) -> Self {
+        self.inner.resizable(resizable);
         self
     }
 
@@ -44,6 +64,7 @@ impl Builder {
         let mut window = FramedWindow {
             title: self.title,
             active: false,
+            pressed_button: None,
             window,
         };
         window.draw_frame();
@@ -54,12 +75,27 @@ impl Builder {
 #[derive(Debug)]
 pub(crate) enum FramedWindowEvent {
     Keyboard(KeyboardEvent),
+    /// Mouse event whose position is relative to the window content area.
+    Mouse(MouseEvent),
+    /// The close button is clicked, or the window is requested to be closed by the hotkey.
+    CloseRequested,
+    /// Copying to the clipboard is requested by Ctrl+C.
+    Copy,
+    /// Pasting from the clipboard is requested by Ctrl+V.
+    Paste,
+    /// The frame is redrawn in the colors of the new theme, and the content area should be too.
+    Redraw,
+    /// The window is resized and its frame is redrawn, and the content area should be redrawn in
+    /// the new size.
+    Resized,
 }
 
 #[derive(Debug)]
 pub(crate) struct FramedWindow {
     title: String,
     active: bool,
+    /// The button of the title bar on which the left button is pressed.
+    pressed_button: Option<TitleButton>,
     window: Window,
 }
 
@@ -89,6 +125,34 @@ impl Draw for FramedWindow {
             Some(())
         })();
     }
+
+    fn clip_area(&self) -> Rectangle<i32> {
+        let clip = self.window.clip_area();
+        let clip = Rectangle::new(clip.pos - PADDING_POS, clip.size);
+        (clip & self.area()).unwrap_or_else(|| Rectangle::new(clip.pos, Size::new(0, 0)))
+    }
+
+    fn push_clip(&mut self, rect: Rectangle<i32>) {
+        let rect =
+            (rect & self.area()).unwrap_or_else(|| Rectangle::new(rect.pos, Size::new(0, 0)));
+        self.window
+            .push_clip(Rectangle::new(rect.pos + PADDING_POS, rect.size))
+    }
+
+    fn pop_clip(&mut self) {
+        self.window.pop_clip()
+    }
+
+    fn draw_char_bg(&mut self, pos: Point<i32>, ch: char, fg: Color, bg: Color) -> Rectangle<i32> {
+        let rect = Rectangle::new(pos, font::char_size(ch));
+        if (rect & self.area()) == Some(rect) {
+            self.window.draw_char_bg(pos + PADDING_POS, ch, fg, bg);
+        } else {
+            self.fill_rect(rect, bg);
+            self.draw_char(pos, ch, fg);
+        }
+        rect
+    }
 }
 
 impl FramedWindow {
@@ -107,14 +171,66 @@ impl FramedWindow {
                     }
                     continue;
                 }
+                WindowEvent::CloseRequested => return Some(Ok(FramedWindowEvent::CloseRequested)),
+                WindowEvent::Copy => return Some(Ok(FramedWindowEvent::Copy)),
+                WindowEvent::Paste => return Some(Ok(FramedWindowEvent::Paste)),
+                WindowEvent::Redraw => {
+                    self.draw_frame();
+                    return Some(Ok(FramedWindowEvent::Redraw));
+                }
+                WindowEvent::Resize(size) => {
+                    if let Err(err) = self.resize(size) {
+                        return Some(Err(err));
+                    }
+                    return Some(Ok(FramedWindowEvent::Resized));
+                }
                 WindowEvent::Keyboard(event) => {
                     return Some(Ok(FramedWindowEvent::Keyboard(event)))
                 }
+                WindowEvent::Mouse(event) => {
+                    // the buttons work when the left button is pressed and released on them
+                    let button = TitleButton::at(self.window.size(), event.pos);
+                    if event.down.contains(MouseButton::Left) {
+                        self.pressed_button = button;
+                    }
+                    if event.up.contains(MouseButton::Left) {
+                        match self.pressed_button.take() {
+                            Some(pressed) if Some(pressed) == button => match pressed {
+                                TitleButton::Minimize => {
+                                    if let Err(err) = self.window.minimize() {
+                                        return Some(Err(err));
+                                    }
+                                    continue;
+                                }
+                                TitleButton::Close => {
+                                    return Some(Ok(FramedWindowEvent::CloseRequested))
+                                }
+                            },
+                            Some(_) => continue,
+                            None => {}
+                        }
+                    }
+                    if self.pressed_button.is_some() {
+                        continue;
+                    }
+                    let event = MouseEvent {
+                        pos: event.pos - PADDING_POS,
+                        ..event
+                    };
+                    return Some(Ok(FramedWindowEvent::Mouse(event)));
+                }
             }
         }
         None
     }
 
+    fn resize(&mut self, size: Size<i32>) -> Result<()> {
+        self.window.resize(size)?;
+        self.window.set_drag_area(drag_area(size))?;
+        self.draw_frame();
+        Ok(())
+    }
+
     async fn activate(&mut self) -> Result<()> {
         if !self.active {
             self.draw_title_bar(true);
@@ -134,9 +250,72 @@ impl FramedWindow {
     }
 }
 
-const CLOSE_BUTTON_WIDTH: usize = 16;
-const CLOSE_BUTTON_HEIGHT: usize = 14;
-const CLOSE_BUTTON: [[u8; CLOSE_BUTTON_WIDTH]; CLOSE_BUTTON_HEIGHT] = [
+/// Returns the area which starts dragging a window of `win_size`.
+///
+/// Only the title bar starts window dragging, and clicks on the buttons are sent to the window.
+fn drag_area(win_size: Size<i32>) -> Rectangle<i32> {
+    Rectangle::new(
+        Point::new(0, 0),
+        Size::new(TitleButton::Minimize.area(win_size).x_start(), PADDING_TOP),
+    )
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TitleButton {
+    Minimize,
+    Close,
+}
+
+impl TitleButton {
+    const ALL: [Self; 2] = [Self::Minimize, Self::Close];
+
+    fn at(win_size: Size<i32>, pos: Point<i32>) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|button| button.area(win_size).contains(&pos))
+    }
+
+    /// Returns the area of the button in a window of `win_size`.
+    fn area(self, win_size: Size<i32>) -> Rectangle<i32> {
+        // the buttons are placed from the right end with gaps of 2 pixels
+        let index = match self {
+            Self::Minimize => 2,
+            Self::Close => 1,
+        };
+        Rectangle::new(
+            Point::new(win_size.x - 3 - (BUTTON_WIDTH as i32 + 2) * index, 5),
+            Size::new(BUTTON_WIDTH as i32, BUTTON_HEIGHT as i32),
+        )
+    }
+
+    fn shape(self) -> &'static [[u8; BUTTON_WIDTH]; BUTTON_HEIGHT] {
+        match self {
+            Self::Minimize => &MINIMIZE_BUTTON,
+            Self::Close => &CLOSE_BUTTON,
+        }
+    }
+}
+
+const BUTTON_WIDTH: usize = 16;
+const BUTTON_HEIGHT: usize = 14;
+const MINIMIZE_BUTTON: [[u8; BUTTON_WIDTH]; BUTTON_HEIGHT] = [
+    *b"...............@",
+    *b".:::::::::::::$@",
+    *b".:::::::::::::$@",
+    *b".:::::::::::::$@",
+    *b".:::::::::::::$@",
+    *b".:::::::::::::$@",
+    *b".:::::::::::::$@",
+    *b".:::::::::::::$@",
+    *b".:::@@@@@@::::$@",
+    *b".:::@@@@@@::::$@",
+    *b".:::::::::::::$@",
+    *b".:::::::::::::$@",
+    *b".$$$$$$$$$$$$$$@",
+    *b"@@@@@@@@@@@@@@@@",
+];
+const CLOSE_BUTTON: [[u8; BUTTON_WIDTH]; BUTTON_HEIGHT] = [
     *b"...............@",
     *b".:::::::::::::$@",
     *b".:::::::::::::$@",
@@ -153,34 +332,54 @@ const CLOSE_BUTTON: [[u8; CLOSE_BUTTON_WIDTH]; CLOSE_BUTTON_HEIGHT] = [
     *b"@@@@@@@@@@@@@@@@",
 ];
 
-const EDGE_DARK: Color = Color::from_code(0x848484);
-const EDGE_LIGHT: Color = Color::from_code(0xc6c6c6);
-const ACTIVE_BACKGROUND: Color = Color::from_code(0x000084);
-const INACTIVE_BACKGROUND: Color = Color::from_code(0x848484);
-
 impl FramedWindow {
     pub(crate) fn builder(title: String) -> Builder {
         Builder::new(title)
     }
 
+    pub(crate) fn layer_id(&self) -> LayerId {
+        self.window.layer_id()
+    }
+
     pub(crate) async fn flush(&mut self) -> Result<()> {
         self.window.flush().await
     }
 
+    pub(crate) fn hide(&self) -> Result<()> {
+        self.window.hide()
+    }
+
+    pub(crate) fn show(&self) -> Result<()> {
+        self.window.show()
+    }
+
+    pub(crate) fn raise(&self) -> Result<()> {
+        self.window.raise()
+    }
+
+    pub(crate) fn lower(&self) -> Result<()> {
+        self.window.lower()
+    }
+
+    pub(crate) fn set_always_on_top(&self, always_on_top: bool) -> Result<()> {
+        self.window.set_always_on_top(always_on_top)
+    }
+
     fn draw_frame(&mut self) {
         let win_size = self.window.size();
         let (wx, wy) = (win_size.x, win_size.y);
+        let theme = theme::current();
 
         let data = &[
-            ((0, 0), (wx, 1), EDGE_LIGHT),
-            ((1, 1), (wx - 2, 1), Color::WHITE),
-            ((0, 0), (1, wy), EDGE_LIGHT),
-            ((1, 1), (1, wy - 2), Color::WHITE),
-            ((wx - 2, 1), (1, wy - 2), EDGE_DARK),
-            ((wx - 1, 0), (1, wy), Color::BLACK),
-            ((2, 2), (wx - 4, wy - 4), EDGE_LIGHT),
-            ((1, wy - 2), (wx - 2, 1), EDGE_DARK),
-            ((0, wy - 1), (wx, 1), Color::BLACK),
+            ((0, 0), (wx, 1), theme.face),
+            ((1, 1), (wx - 2, 1), theme.highlight),
+            ((0, 0), (1, wy), theme.face),
+            ((1, 1), (1, wy - 2), theme.highlight),
+            ((wx - 2, 1), (1, wy - 2), theme.shadow),
+            ((wx - 1, 0), (1, wy), theme.dark_shadow),
+            ((2, 2), (wx - 4, wy - 4), theme.face),
+            ((1, wy - 2), (wx - 2, 1), theme.shadow),
+            ((0, wy - 1), (wx, 1), theme.dark_shadow),
         ];
 
         for (pos, size, color) in data {
@@ -190,17 +389,18 @@ impl FramedWindow {
             );
         }
 
-        self.draw_title_bar(false);
+        self.draw_title_bar(self.active);
     }
 
     fn draw_title_bar(&mut self, active: bool) {
         let win_size = self.window.size();
         let (wx, _wy) = (win_size.x, win_size.y);
 
+        let theme = theme::current();
         let background = if active {
-            ACTIVE_BACKGROUND
+            theme.title_active
         } else {
-            INACTIVE_BACKGROUND
+            theme.title_inactive
         };
 
         self.window.fill_rect(
@@ -208,19 +408,21 @@ impl FramedWindow {
             background,
         );
         self.window
-            .draw_str(Point::new(24, 4), &self.title, Color::WHITE);
-
-        for (y, row) in (0..).zip(CLOSE_BUTTON) {
-            for (x, ch) in (0..).zip(row) {
-                let c = match ch {
-                    b'@' => Color::BLACK,
-                    b'$' => EDGE_DARK,
-                    b':' => EDGE_LIGHT,
-                    b'.' => Color::WHITE,
-                    _ => panic!("invalid char: {}", ch),
-                };
-                self.window
-                    .draw(Point::new(wx - 5 - CLOSE_BUTTON_WIDTH as i32 + x, 5 + y), c);
+            .draw_str_bg(Point::new(24, 4), &self.title, theme.title_text, background);
+
+        for button in TitleButton::ALL {
+            let button_pos = button.area(win_size).pos;
+            for (y, row) in (0..).zip(button.shape()) {
+                for (x, ch) in (0..).zip(row) {
+                    let c = match ch {
+                        b'@' => theme.dark_shadow,
+                        b'$' => theme.shadow,
+                        b':' => theme.face,
+                        b'.' => theme.highlight,
+                        _ => panic!("invalid char: {}", ch),
+                    };
+                    self.window.draw(button_pos + Point::new(x, y), c);
+                }
             }
         }
     }
@@ -1,45 +1,130 @@
-use crate::sync::OnceCell;
+use crate::{cpu, sync::OnceCell};
+use alloc::vec;
 use x86_64::{
-    instructions::segmentation,
-    structures::gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector},
+    instructions::{segmentation, tables},
+    structures::{
+        gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector},
+        tss::TaskStateSegment,
+    },
+    VirtAddr,
 };
 
+pub(crate) const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+pub(crate) const NMI_IST_INDEX: u16 = 1;
+pub(crate) const MACHINE_CHECK_IST_INDEX: u16 = 2;
+
+const IST_STACK_SIZE: usize = 4096 * 5;
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C, align(16))]
+struct IstStackElement {
+    _dummy: [u8; 16],
+}
+
 #[derive(Debug)]
 pub(crate) struct Selectors {
     pub(crate) kernel_code_selector: SegmentSelector,
     pub(crate) kernel_stack_selector: SegmentSelector,
+    pub(crate) tss_selector: SegmentSelector,
 }
 
-static GDT: OnceCell<GlobalDescriptorTable> = OnceCell::uninit();
-static SELECTORS: OnceCell<Selectors> = OnceCell::uninit();
+// used only as array initializers
+#[allow(clippy::declare_interior_mutable_const)]
+const UNINIT_TSS: OnceCell<TaskStateSegment> = OnceCell::uninit();
+#[allow(clippy::declare_interior_mutable_const)]
+const UNINIT_GDT: OnceCell<GlobalDescriptorTable> = OnceCell::uninit();
+#[allow(clippy::declare_interior_mutable_const)]
+const UNINIT_SELECTORS: OnceCell<Selectors> = OnceCell::uninit();
+
+// Each CPU has its own TSS (and IST stacks), since a TSS is marked busy when it is loaded.
+static TSS: [OnceCell<TaskStateSegment>; cpu::MAX_CPUS] = [UNINIT_TSS; cpu::MAX_CPUS];
+static GDT: [OnceCell<GlobalDescriptorTable>; cpu::MAX_CPUS] = [UNINIT_GDT; cpu::MAX_CPUS];
+static SELECTORS: [OnceCell<Selectors>; cpu::MAX_CPUS] = [UNINIT_SELECTORS; cpu::MAX_CPUS];
 
+fn allocate_ist_stack() -> VirtAddr {
+    // zero-initialization touches the lazily mapped heap pages, so exceptions never fault on
+    // the stack
+    let stack = vec![
+        IstStackElement { _dummy: [0; 16] };
+        IST_STACK_SIZE / core::mem::size_of::<IstStackElement>()
+    ]
+    .leak();
+    // stack grows downwards, so IST entry points to the end of the stack
+    VirtAddr::from_ptr(stack.as_ptr()) + IST_STACK_SIZE
+}
+
+/// Initializes and loads GDT and TSS of the bootstrap processor.
 pub(crate) fn init() {
+    init_cpu(0);
+    load(0);
+}
+
+/// Builds GDT and TSS of the CPU `cpu_index`.
+///
+/// This allocates the IST stacks from the heap, so it is called by the bootstrap processor
+/// before the CPU starts.
+pub(crate) fn init_cpu(cpu_index: usize) {
+    let tss = &TSS[cpu_index];
+    tss.init_once(|| {
+        let mut tss = TaskStateSegment::new();
+        tss.interrupt_stack_table[usize::from(DOUBLE_FAULT_IST_INDEX)] = allocate_ist_stack();
+        tss.interrupt_stack_table[usize::from(NMI_IST_INDEX)] = allocate_ist_stack();
+        tss.interrupt_stack_table[usize::from(MACHINE_CHECK_IST_INDEX)] = allocate_ist_stack();
+        tss
+    });
+
     let null_segment = SegmentSelector(0);
     let mut selectors = Selectors {
         kernel_code_selector: null_segment,
         kernel_stack_selector: null_segment,
+        tss_selector: null_segment,
     };
-    GDT.init_once(|| {
+    GDT[cpu_index].init_once(|| {
         let mut gdt = GlobalDescriptorTable::new();
         selectors.kernel_code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
         selectors.kernel_stack_selector = gdt.add_entry(Descriptor::kernel_data_segment());
+        selectors.tss_selector = gdt.add_entry(Descriptor::tss_segment(tss.get()));
         gdt
     });
-    GDT.get().load();
+    SELECTORS[cpu_index].init_once(|| selectors);
+}
+
+/// Loads GDT, segment registers and TSS of the CPU `cpu_index` on the current CPU.
+pub(crate) fn load(cpu_index: usize) {
+    let selectors = SELECTORS[cpu_index].get();
+    GDT[cpu_index].get().load();
 
+    // GS is not reloaded, since loading a selector clears the GS base (which points to the
+    // per-CPU data) on some CPUs
+    let null_segment = SegmentSelector(0);
     unsafe {
         segmentation::load_ds(null_segment);
         segmentation::load_es(null_segment);
         segmentation::load_fs(null_segment);
-        segmentation::load_gs(null_segment);
     }
 
     unsafe { segmentation::load_ss(selectors.kernel_stack_selector) };
     unsafe { segmentation::set_cs(selectors.kernel_code_selector) };
+    unsafe { tables::load_tss(selectors.tss_selector) };
+}
+
+/// Reloads GDT, segment registers and TSS after the CPU state was lost (e.g. resume from S3).
+#[cfg(feature = "acpi-s3")]
+pub(crate) fn reload() {
+    let cpu_index = cpu::current_index();
+    let selectors = selectors();
+
+    // `ltr` faults if the TSS descriptor is marked busy by the previous `ltr`
+    const TSS_BUSY: u64 = 1 << 41;
+    GDT[cpu_index].get().load();
+    let gdtr = tables::sgdt();
+    let tss_entry = gdtr.base + u64::from(selectors.tss_selector.index()) * 8;
+    unsafe { *tss_entry.as_mut_ptr::<u64>() &= !TSS_BUSY };
 
-    SELECTORS.init_once(|| selectors);
+    load(cpu_index);
 }
 
+/// Returns the selectors of the current CPU.
 pub(crate) fn selectors() -> &'static Selectors {
-    SELECTORS.get()
+    SELECTORS[cpu::current_index()].get()
 }
@@ -8,6 +8,8 @@ mod color;
 pub(crate) mod font;
 pub(crate) mod frame_buffer;
 mod geometry;
+mod glyph_cache;
+mod simd;
 mod traits;
 
 static SCREEN_INFO: OnceCell<ScreenInfo> = OnceCell::uninit();
@@ -1,3 +1,4 @@
+use super::{color, font, glyph_cache, simd};
 use crate::{
     graphics::{Color, Draw, Offset, Point, Rectangle, ScreenInfo, Size},
     prelude::*,
@@ -46,9 +47,20 @@ pub(crate) struct BufferDrawer<B> {
     pixel_format: PixelFormat,
     #[debug(skip)]
     pixel_drawer: &'static (dyn PixelDraw + Send + Sync),
+    /// The stack of clip rectangles, each of which is within the previous one.
+    clip: Vec<Rectangle<i32>>,
     buffer: B,
 }
 
+/// The filter used to sample the source of [`BufferDrawer::copy_scaled`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ScaleFilter {
+    /// Takes the nearest source pixel, which keeps hard edges.
+    Nearest,
+    /// Interpolates the four nearest source pixels, which smooths photos and thumbnails.
+    Bilinear,
+}
+
 impl<B> BufferDrawer<B> {
     fn new_common(
         size: Size<i32>,
@@ -64,6 +76,7 @@ impl<B> BufferDrawer<B> {
             bytes_per_pixel,
             pixel_format,
             pixel_drawer,
+            clip: Vec::new(),
             buffer,
         })
     }
@@ -101,11 +114,81 @@ where
         self.size
     }
 
+    /// Draws `c` at `p`. A translucent color is blended with the pixel already drawn.
     fn draw(&mut self, p: crate::graphics::Point<i32>, c: crate::graphics::Color) {
+        if !self.clip_area().contains(&p) {
+            return;
+        }
         if let Some(pixel_index) = self.pixel_index(p) {
-            self.pixel_drawer
-                .pixel_draw(self.buffer.buffer_mut(), pixel_index, c)
+            let buffer = self.buffer.buffer_mut();
+            if c.is_opaque() {
+                self.pixel_drawer.pixel_draw(buffer, pixel_index, c)
+            } else {
+                self.pixel_drawer.pixel_blend(buffer, pixel_index, c)
+            }
+        }
+    }
+
+    fn fill_rect(&mut self, rect: Rectangle<i32>, c: Color) {
+        let bytes_per_pixel = self.bytes_per_pixel as usize;
+        let mut pixel = [0; 4];
+        if !c.is_opaque() || bytes_per_pixel > pixel.len() {
+            for p in rect.points() {
+                self.draw(p, c);
+            }
+            return;
         }
+        self.pixel_drawer.pixel_draw(&mut pixel, 0, c);
+        let pixel = &pixel[..bytes_per_pixel];
+
+        (|| {
+            let rect = (rect & self.clip_area())?;
+            let bytes_per_line = bytes_per_pixel * rect.size.x as usize;
+            for y in rect.y_range() {
+                let idx = self.pixel_index(Point::new(rect.x_start(), y))?;
+                simd::fill_row(
+                    &mut self.buffer.buffer_mut()[idx..][..bytes_per_line],
+                    pixel,
+                );
+            }
+            Some(())
+        })();
+    }
+
+    fn draw_char_bg(&mut self, pos: Point<i32>, ch: char, fg: Color, bg: Color) -> Rectangle<i32> {
+        let rect = Rectangle::new(pos, font::char_size(ch));
+        let clipped = match rect & self.clip_area() {
+            Some(clipped) => clipped,
+            None => return rect,
+        };
+        if fg.is_opaque() && bg.is_opaque() {
+            let bytes_per_pixel = self.bytes_per_pixel as usize;
+            let glyph_stride = rect.size.x as usize * bytes_per_pixel;
+            let src_x = (clipped.x_start() - pos.x) as usize * bytes_per_pixel;
+            let len = clipped.size.x as usize * bytes_per_pixel;
+            let (pixel_format, pixel_drawer) = (self.pixel_format, self.pixel_drawer);
+            let drawn = glyph_cache::with_glyph(
+                pixel_format,
+                pixel_drawer,
+                bytes_per_pixel,
+                ch,
+                fg,
+                bg,
+                |glyph| {
+                    for y in clipped.y_range() {
+                        let src_idx = (y - pos.y) as usize * glyph_stride + src_x;
+                        if let Some(dst_idx) = self.pixel_index(Point::new(clipped.x_start(), y)) {
+                            self.buffer.buffer_mut()[dst_idx..][..len]
+                                .copy_from_slice(&glyph[src_idx..][..len]);
+                        }
+                    }
+                },
+            );
+            if drawn.is_some() {
+                return rect;
+            }
+        }
+        font::draw_char_bg(self, pos, ch, fg, bg)
     }
 
     fn move_area(&mut self, offset: Point<i32>, src: Rectangle<i32>) {
@@ -114,7 +197,7 @@ where
         }
 
         (|| {
-            let dst = (((src & self.area())? + offset) & self.area())?;
+            let dst = (((src & self.area())? + offset) & self.clip_area())?;
             let src = dst - offset;
 
             assert_eq!(dst.size, src.size);
@@ -159,6 +242,20 @@ where
             Some(())
         })();
     }
+
+    fn clip_area(&self) -> Rectangle<i32> {
+        self.clip.last().copied().unwrap_or_else(|| self.area())
+    }
+
+    fn push_clip(&mut self, rect: Rectangle<i32>) {
+        let clip =
+            (rect & self.clip_area()).unwrap_or_else(|| Rectangle::new(rect.pos, Size::new(0, 0)));
+        self.clip.push(clip);
+    }
+
+    fn pop_clip(&mut self) {
+        self.clip.pop();
+    }
 }
 
 impl<B> BufferDrawer<B>
@@ -173,18 +270,123 @@ where
         }
     }
 
-    pub(crate) fn color_at(&self, p: Point<i32>) -> Option<Color> {
-        self.pixel_index(p).map(|pixel_index| {
-            self.pixel_drawer
-                .color_at(self.buffer.buffer(), pixel_index)
-        })
+    pub(crate) fn copy<C>(
+        &mut self,
+        src_dst_offset: Offset<i32>,
+        src: &BufferDrawer<C>,
+        src_area: Rectangle<i32>,
+    ) where
+        C: Buffer,
+    {
+        self.for_each_row(src_dst_offset, src, src_area, |dst, src, _| {
+            simd::copy_row(dst, src)
+        });
     }
 
-    pub(crate) fn copy<C>(
+    /// Blends `src_area` of `src` by the alpha values in `alpha`, a grayscale buffer of the same
+    /// size as `src`.
+    pub(crate) fn blend<C, A>(
+        &mut self,
+        src_dst_offset: Offset<i32>,
+        src: &BufferDrawer<C>,
+        alpha: &BufferDrawer<A>,
+        src_area: Rectangle<i32>,
+    ) where
+        C: Buffer,
+        A: Buffer,
+    {
+        assert_eq!(alpha.size, src.size);
+        assert_eq!(alpha.bytes_per_pixel, 1);
+
+        let bytes_per_pixel = self.bytes_per_pixel as usize;
+        self.for_each_row(src_dst_offset, src, src_area, |dst, src, src_pos| {
+            let width = dst.len() / bytes_per_pixel;
+            if let Some(idx) = alpha.pixel_index(src_pos) {
+                let alpha = &alpha.buffer.buffer()[idx..][..width];
+                simd::blend_row(dst, src, alpha, bytes_per_pixel);
+            }
+        });
+    }
+
+    /// Copies `src_area` of `src` to `dst_area`, scaling it to the size of `dst_area`.
+    ///
+    /// The part of `src_area` outside `src` is ignored.
+    pub(crate) fn copy_scaled<C>(
+        &mut self,
+        dst_area: Rectangle<i32>,
+        src: &BufferDrawer<C>,
+        src_area: Rectangle<i32>,
+        filter: ScaleFilter,
+    ) where
+        C: Buffer,
+    {
+        assert_eq!(self.pixel_format, src.pixel_format);
+        assert_eq!(self.bytes_per_pixel, src.bytes_per_pixel);
+
+        (|| {
+            let src_area = (src_area & src.area())?;
+            let clipped = (dst_area & self.clip_area())?;
+            let bytes_per_pixel = self.bytes_per_pixel as usize;
+            let to_dst = |p: Point<i32>| p - dst_area.pos;
+            match filter {
+                ScaleFilter::Nearest => {
+                    // the source column of each destination column, which is the same in all rows
+                    let src_xs = clipped
+                        .x_range()
+                        .map(|x| {
+                            let x = scale_nearest(
+                                to_dst(Point::new(x, 0)).x,
+                                dst_area.size.x,
+                                src_area.size.x,
+                            );
+                            (src_area.x_start() + x) as usize * bytes_per_pixel
+                        })
+                        .collect::<Vec<_>>();
+                    let bytes_per_line = clipped.size.x as usize * bytes_per_pixel;
+                    for y in clipped.y_range() {
+                        let sy = scale_nearest(
+                            to_dst(Point::new(0, y)).y,
+                            dst_area.size.y,
+                            src_area.size.y,
+                        );
+                        let src_idx = src.pixel_index(Point::new(0, src_area.y_start() + sy))?;
+                        let dst_idx = self.pixel_index(Point::new(clipped.x_start(), y))?;
+                        let src_row = &src.buffer.buffer()[src_idx..];
+                        let dst_row = &mut self.buffer.buffer_mut()[dst_idx..][..bytes_per_line];
+                        for (dst, sx) in dst_row.chunks_exact_mut(bytes_per_pixel).zip(&src_xs) {
+                            dst.copy_from_slice(&src_row[*sx..][..bytes_per_pixel]);
+                        }
+                    }
+                }
+                ScaleFilter::Bilinear => {
+                    for p in clipped.points() {
+                        let dp = to_dst(p);
+                        let (x0, x1, tx) = scale_linear(dp.x, dst_area.size.x, src_area.size.x);
+                        let (y0, y1, ty) = scale_linear(dp.y, dst_area.size.y, src_area.size.y);
+                        let color_at = |x, y| src.color_at(src_area.pos + Offset::new(x, y));
+                        let top = color_at(x0, y0)?.interpolate(color_at(x1, y0)?, tx);
+                        let bottom = color_at(x0, y1)?.interpolate(color_at(x1, y1)?, tx);
+                        let idx = self.pixel_index(p)?;
+                        self.pixel_drawer.pixel_draw(
+                            self.buffer.buffer_mut(),
+                            idx,
+                            top.interpolate(bottom, ty),
+                        );
+                    }
+                }
+            }
+            Some(())
+        })();
+    }
+
+    /// Calls `f` with each row of `src_area` of `src` and the corresponding row of `self`, and
+    /// the position of the row in `src`.
+    fn for_each_row<C>(
         &mut self,
         src_dst_offset: Offset<i32>,
         src: &BufferDrawer<C>,
         src_area: Rectangle<i32>,
+        mut f: impl FnMut(&mut [u8], &[u8], Point<i32>),
     ) where
         C: Buffer,
     {
@@ -194,7 +396,7 @@ where
         (|| {
             // trim overflow area
             let src_area = (src_area & src.area())?;
-            let dst_area = ((src_area + src_dst_offset) & self.area())?;
+            let dst_area = ((src_area + src_dst_offset) & self.clip_area())?;
             let src_area = dst_area - src_dst_offset;
             assert_eq!(dst_area.size, src_area.size);
 
@@ -212,7 +414,7 @@ where
                     [..bytes_per_copy_line];
                 let src =
                     &src_buf[(bytes_per_pixel * dy * src.stride) as usize..][..bytes_per_copy_line];
-                dst.copy_from_slice(src);
+                f(dst, src, src_area.pos + Offset::new(0, dy));
             }
             Some(())
         })();
@@ -224,11 +426,46 @@ where
         }
         usize::try_from((p.y * self.stride + p.x) * self.bytes_per_pixel).ok()
     }
+
+    fn color_at(&self, p: Point<i32>) -> Option<Color> {
+        let pixel_index = self.pixel_index(p)?;
+        Some(
+            self.pixel_drawer
+                .color_at(self.buffer.buffer(), pixel_index),
+        )
+    }
+}
+
+/// Maps the `i`-th of `dst_len` pixels to the source pixel nearest to its center.
+fn scale_nearest(i: i32, dst_len: i32, src_len: i32) -> i32 {
+    let x = (2 * i64::from(i) + 1) * i64::from(src_len) / (2 * i64::from(dst_len));
+    i32::try_from(x).map_or(src_len - 1, |x| i32::min(x, src_len - 1))
+}
+
+/// Maps the `i`-th of `dst_len` pixels to the two source pixels around its center, and the
+/// weight of the second one.
+fn scale_linear(i: i32, dst_len: i32, src_len: i32) -> (i32, i32, u8) {
+    // the center of the pixel in the source, in 1/256 pixels from the center of the first pixel
+    let pos = (2 * i64::from(i) + 1) * i64::from(src_len) * 128 / i64::from(dst_len) - 128;
+    let pos = i64::max(pos, 0);
+    let x0 = i32::try_from(pos >> 8).map_or(src_len - 1, |x| i32::min(x, src_len - 1));
+    let x1 = i32::min(x0 + 1, src_len - 1);
+    #[allow(clippy::unwrap_used)] // this never panics
+    let t = u8::try_from((pos & 0xff) * 255 / 256).unwrap();
+    (x0, x1, t)
 }
 
 pub(crate) trait PixelDraw {
+    /// Writes `c` to the pixel, ignoring its alpha value.
     fn pixel_draw(&self, buffer: &mut [u8], pixel_index: usize, c: Color);
+    /// Returns the color of the pixel, which is always opaque.
     fn color_at(&self, buffer: &[u8], pixel_index: usize) -> Color;
+
+    /// Composites `c` over the pixel.
+    fn pixel_blend(&self, buffer: &mut [u8], pixel_index: usize, c: Color) {
+        let dst = self.color_at(buffer, pixel_index);
+        self.pixel_draw(buffer, pixel_index, c.blend_over(dst));
+    }
 }
 
 fn select_pixel_drawer(
@@ -259,6 +496,13 @@ impl PixelDraw for RgbPixelDrawer {
             buffer[pixel_index + 2],
         )
     }
+
+    fn pixel_blend(&self, buffer: &mut [u8], pixel_index: usize, c: Color) {
+        let pixel = &mut buffer[pixel_index..][..3];
+        for (dst, src) in pixel.iter_mut().zip([c.r, c.g, c.b]) {
+            *dst = color::mix_component(src, *dst, c.a);
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -278,6 +522,13 @@ impl PixelDraw for BgrPixelDrawer {
             buffer[pixel_index],
         )
     }
+
+    fn pixel_blend(&self, buffer: &mut [u8], pixel_index: usize, c: Color) {
+        let pixel = &mut buffer[pixel_index..][..3];
+        for (dst, src) in pixel.iter_mut().zip([c.b, c.g, c.r]) {
+            *dst = color::mix_component(src, *dst, c.a);
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -291,4 +542,9 @@ impl PixelDraw for U8PixelDrawer {
     fn color_at(&self, buffer: &[u8], pixel_index: usize) -> Color {
         Color::from_grayscale(buffer[pixel_index])
     }
+
+    fn pixel_blend(&self, buffer: &mut [u8], pixel_index: usize, c: Color) {
+        let dst = &mut buffer[pixel_index];
+        *dst = color::mix_component(c.to_grayscale(), *dst, c.a);
+    }
 }
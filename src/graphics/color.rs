@@ -1,10 +1,20 @@
 use core::{convert::TryFrom, fmt};
 
+/// Mixes a color component `src` of opacity `alpha` with `dst`.
+pub(crate) fn mix_component(src: u8, dst: u8, alpha: u8) -> u8 {
+    let (src, dst, alpha) = (u16::from(src), u16::from(dst), u16::from(alpha));
+    let v = (src * alpha + dst * (255 - alpha) + 127) / 255;
+    #[allow(clippy::unwrap_used)] // this never panics
+    u8::try_from(v).unwrap()
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub(crate) struct Color {
     pub(crate) r: u8,
     pub(crate) g: u8,
     pub(crate) b: u8,
+    /// The opacity, where 0 is fully transparent and 255 is opaque.
+    pub(crate) a: u8,
 }
 
 #[allow(dead_code)]
@@ -14,11 +24,12 @@ impl Color {
     pub(crate) const BLUE: Self = Color::new(0, 0, 255);
     pub(crate) const BLACK: Self = Color::new(0, 0, 0);
     pub(crate) const WHITE: Self = Color::new(255, 255, 255);
+    pub(crate) const TRANSPARENT: Self = Color::BLACK.with_alpha(0);
 }
 
 impl Color {
     pub(crate) const fn new(r: u8, g: u8, b: u8) -> Self {
-        Color { r, g, b }
+        Color { r, g, b, a: 255 }
     }
 
     pub(crate) const fn from_code(code: u32) -> Self {
@@ -26,9 +37,53 @@ impl Color {
             r: ((code >> 16) & 0xff) as u8,
             g: ((code >> 8) & 0xff) as u8,
             b: (code & 0xff) as u8,
+            a: 255,
+        }
+    }
+
+    pub(crate) const fn with_alpha(self, a: u8) -> Self {
+        Self { a, ..self }
+    }
+
+    pub(crate) const fn is_opaque(self) -> bool {
+        self.a == 255
+    }
+
+    /// Composites `self` over `dst` (the "over" operator of Porter-Duff).
+    ///
+    /// The color components of a translucent `dst` are mixed as if it were opaque, which is
+    /// exact for the pixels of drawers.
+    pub(crate) fn blend_over(self, dst: Color) -> Color {
+        let a = self.a;
+        Color {
+            r: mix_component(self.r, dst.r, a),
+            g: mix_component(self.g, dst.g, a),
+            b: mix_component(self.b, dst.b, a),
+            a: mix_component(255, dst.a, a),
+        }
+    }
+
+    /// Returns the color between `self` and `to`, which is `self` when `t` is 0 and `to` when
+    /// `t` is 255. All components including alpha are interpolated.
+    pub(crate) fn interpolate(self, to: Color, t: u8) -> Color {
+        Color {
+            r: mix_component(to.r, self.r, t),
+            g: mix_component(to.g, self.g, t),
+            b: mix_component(to.b, self.b, t),
+            a: mix_component(to.a, self.a, t),
         }
     }
 
+    /// Mixes white into the color by `amount`, keeping its alpha value.
+    pub(crate) fn lighten(self, amount: u8) -> Color {
+        self.interpolate(Color::WHITE.with_alpha(self.a), amount)
+    }
+
+    /// Mixes black into the color by `amount`, keeping its alpha value.
+    pub(crate) fn darken(self, amount: u8) -> Color {
+        self.interpolate(Color::BLACK.with_alpha(self.a), amount)
+    }
+
     pub(crate) const fn from_grayscale(v: u8) -> Self {
         Color::new(v, v, v)
     }
@@ -41,6 +96,43 @@ impl Color {
 
 impl fmt::Display for Color {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "#{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+        write!(f, "#{:02x}{:02x}{:02x}", self.r, self.g, self.b)?;
+        if !self.is_opaque() {
+            write!(f, "{:02x}", self.a)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn blend_over() {
+        let dst = Color::new(0, 100, 255);
+        assert_eq!(Color::RED.blend_over(dst), Color::RED);
+        assert_eq!(Color::TRANSPARENT.blend_over(dst), dst);
+        assert_eq!(
+            Color::WHITE.with_alpha(128).blend_over(dst),
+            Color::new(128, 178, 255)
+        );
+        assert_eq!(
+            Color::BLACK.with_alpha(128).blend_over(Color::TRANSPARENT),
+            Color::BLACK.with_alpha(128)
+        );
+    }
+
+    #[test_case]
+    fn interpolate() {
+        let from = Color::new(0, 100, 255);
+        assert_eq!(from.interpolate(Color::WHITE, 0), from);
+        assert_eq!(from.interpolate(Color::WHITE, 255), Color::WHITE);
+        assert_eq!(
+            from.interpolate(Color::TRANSPARENT, 128),
+            Color::new(0, 50, 127).with_alpha(127)
+        );
+        assert_eq!(from.lighten(128), Color::new(128, 178, 255));
+        assert_eq!(from.with_alpha(10).darken(255), Color::BLACK.with_alpha(10));
     }
 }
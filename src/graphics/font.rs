@@ -1,57 +1,314 @@
-use crate::graphics::{Color, Draw, Point, Rectangle, Size};
-use core::convert::TryFrom;
+//! Fonts used to draw text.
+//!
+//! Characters are looked up in a chain of fonts, the first of which determines the size of a
+//! character cell. A character whose glyph is wider than a cell is a wide character, which
+//! occupies two cells. Characters without glyphs in any font are drawn as a box.
+//!
+//! The 8x16 font built in by `build.rs` is used until [`init`] loads the fonts selected by the
+//! configuration file, and it is always the last font of the chain. The fonts cannot be changed
+//! after that, because windows are laid out by the size of the cells.
 
-pub(crate) const FONT_PIXEL_SIZE: Size<i32> = Size::new(8, 16);
+use self::{packed::PackedFont, psf::Psf2Font};
+use crate::{
+    fat,
+    graphics::{Color, Draw, Point, Rectangle, Size},
+    prelude::*,
+    sync::OnceCell,
+};
+use alloc::{boxed::Box, vec::Vec};
+use core::{convert::TryFrom, fmt, str};
+
+mod packed;
+mod psf;
 
 include!(concat!(env!("OUT_DIR"), "/ascii_font.rs"));
 
-type Font = [u8; 16];
+/// The file in the root directory of the file system which selects the fonts.
+const FONT_CONFIG_FILE: &str = "font.cfg";
+const MAX_SCALE: i32 = 4;
+
+/// The cell following a wide character in a grid of cells, which also pads the end of a line
+/// where a wide character does not fit. It is drawn as a blank cell.
+pub(crate) const WIDE_CHAR_TAIL: char = '\0';
+
+static BUILTIN_FONT: ActiveFont = ActiveFont {
+    providers: &[&BuiltinFont],
+    scale: 1,
+};
+static ACTIVE_FONT: OnceCell<ActiveFont> = OnceCell::uninit();
+
+/// A source of glyph bitmaps.
+pub(crate) trait FontProvider: fmt::Debug + Send + Sync {
+    /// Returns the size of the glyphs in pixels.
+    fn glyph_size(&self) -> Size<i32>;
+
+    /// Returns the bitmap of `ch`, whose rows are `(width + 7) / 8` bytes with the leftmost
+    /// pixel in the most significant bit.
+    fn glyph(&self, ch: char) -> Option<&[u8]>;
+}
+
+/// The font built in by `build.rs`, which has the glyphs of ASCII and half-width katakana.
+#[derive(Debug)]
+struct BuiltinFont;
+
+impl FontProvider for BuiltinFont {
+    fn glyph_size(&self) -> Size<i32> {
+        Size::new(8, 16)
+    }
+
+    fn glyph(&self, ch: char) -> Option<&[u8]> {
+        static_assertions::const_assert_eq!(ASCII_FONT.len(), 256);
+        // the glyphs are indexed by JIS X 0201
+        let index = match ch {
+            ' '..='~' => u32::from(ch),
+            '\u{ff61}'..='\u{ff9f}' => u32::from(ch) - 0xff61 + 0xa1,
+            _ => return None,
+        };
+        ASCII_FONT
+            .get(usize::try_from(index).ok()?)
+            .map(|glyph| &glyph[..])
+    }
+}
 
-fn get_ascii_font(ch: u8) -> &'static Font {
-    static_assertions::const_assert_eq!(ASCII_FONT.len(), 256);
-    &ASCII_FONT[usize::from(ch)]
+#[derive(Debug, Clone, Copy)]
+enum Glyph {
+    Bitmap {
+        bitmap: &'static [u8],
+        size: Size<i32>,
+    },
+    Blank,
+    Missing,
 }
 
-pub(super) fn draw_byte_char<D>(
+#[derive(Debug)]
+struct ActiveFont {
+    providers: &'static [&'static dyn FontProvider],
+    /// Each pixel of the glyphs is drawn as `scale` x `scale` pixels.
+    scale: i32,
+}
+
+impl ActiveFont {
+    fn pixel_size(&self) -> Size<i32> {
+        let size = self
+            .providers
+            .first()
+            .map(|provider| provider.glyph_size())
+            .unwrap_or_else(|| BuiltinFont.glyph_size());
+        Size::new(size.x * self.scale, size.y * self.scale)
+    }
+
+    fn glyph(&self, ch: char) -> Glyph {
+        if ch.is_control() {
+            return Glyph::Blank;
+        }
+        self.providers
+            .iter()
+            .find_map(|provider| {
+                let bitmap = provider.glyph(ch)?;
+                Some(Glyph::Bitmap {
+                    bitmap,
+                    size: provider.glyph_size(),
+                })
+            })
+            .unwrap_or(Glyph::Missing)
+    }
+
+    /// Returns the number of cells `ch` occupies, which is 1 or 2.
+    fn char_width(&self, ch: char) -> i32 {
+        match self.glyph(ch) {
+            Glyph::Bitmap { size, .. } => {
+                let cell_width = self.pixel_size().x;
+                ((size.x * self.scale + cell_width - 1) / cell_width).clamp(1, 2)
+            }
+            Glyph::Blank | Glyph::Missing => 1,
+        }
+    }
+
+    /// Returns whether the pixel at `p` of the cells of `ch`, whose size is `size`, is drawn in
+    /// the foreground color.
+    fn pixel_fn(&self, ch: char, size: Size<i32>) -> impl Fn(Point<i32>) -> bool + '_ {
+        let glyph = self.glyph(ch);
+        move |p| match glyph {
+            Glyph::Bitmap { bitmap, size } => {
+                let (x, y) = (p.x / self.scale, p.y / self.scale);
+                let bytes_per_row = (size.x + 7) / 8;
+                x < size.x
+                    && y < size.y
+                    && usize::try_from(y * bytes_per_row + x / 8)
+                        .ok()
+                        .and_then(|index| bitmap.get(index))
+                        .map(|bits| (bits << (x % 8)) & 0x80 != 0)
+                        .unwrap_or(false)
+            }
+            Glyph::Blank => false,
+            // a box inset by a pixel
+            Glyph::Missing => {
+                let (x, y) = (p.x / self.scale, p.y / self.scale);
+                let (right, bottom) = (size.x / self.scale - 2, size.y / self.scale - 2);
+                (1..=right).contains(&x)
+                    && (1..=bottom).contains(&y)
+                    && (x == 1 || x == right || y == 1 || y == bottom)
+            }
+        }
+    }
+}
+
+fn active_font() -> &'static ActiveFont {
+    ACTIVE_FONT.try_get().unwrap_or(&BUILTIN_FONT)
+}
+
+/// Returns the size of a character cell of the active font.
+pub(crate) fn pixel_size() -> Size<i32> {
+    active_font().pixel_size()
+}
+
+/// Returns the number of cells `ch` occupies, which is 1 or 2.
+pub(crate) fn char_width(ch: char) -> i32 {
+    active_font().char_width(ch)
+}
+
+/// Returns the size of the cells `ch` occupies in pixels.
+pub(crate) fn char_size(ch: char) -> Size<i32> {
+    let font = active_font();
+    let size = font.pixel_size();
+    Size::new(size.x * font.char_width(ch), size.y)
+}
+
+/// Returns the characters to draw in a line of cells and their positions in cells, skipping the
+/// tails of wide characters.
+pub(crate) fn visible_cells(cells: &[char]) -> impl Iterator<Item = (usize, char)> + '_ {
+    let mut covered = false;
+    cells.iter().enumerate().filter_map(move |(x, ch)| {
+        if *ch == WIDE_CHAR_TAIL && covered {
+            covered = false;
+            return None;
+        }
+        covered = char_width(*ch) == 2;
+        Some((x, *ch))
+    })
+}
+
+/// Calls `f` with each pixel of the cells of `ch`, and whether the pixel is drawn in the
+/// foreground color.
+pub(super) fn for_each_pixel(ch: char, mut f: impl FnMut(Point<i32>, bool)) {
+    let font = active_font();
+    let size = char_size(ch);
+    let is_set = font.pixel_fn(ch, size);
+    for p in Rectangle::new(Point::new(0, 0), size).points() {
+        f(p, is_set(p));
+    }
+}
+
+/// Loads the fonts selected by the configuration file, whose first line other than `#` comments
+/// is the comma-separated names of font files in the root directory (or `builtin`), and an
+/// optional scale. The font files are PSF2 fonts or packed fonts, which are told apart by their
+/// magic numbers.
+///
+/// This must be called after the file system is initialized, and before any window is created.
+/// The built-in font is used if the file is missing or invalid.
+pub(crate) fn init() {
+    let font = match load_config() {
+        Ok(Some(font)) => font,
+        Ok(None) => return,
+        Err(err) => {
+            warn!("font: {} is not loaded: {}", FONT_CONFIG_FILE, err);
+            return;
+        }
+    };
+    let size = font.pixel_size();
+    let count = font.providers.len();
+    ACTIVE_FONT.init_once(|| font);
+    // glyphs drawn by the built-in font may be cached while booting
+    super::glyph_cache::clear();
+    info!("font: {} fonts, {}x{} pixels", count, size.x, size.y);
+}
+
+fn load_config() -> Result<Option<ActiveFont>> {
+    let fs = fat::lock();
+    let data = match fat::find_file(&**fs, FONT_CONFIG_FILE) {
+        Ok(entry) => fat::read_file(&**fs, entry)?,
+        Err(_) => return Ok(None),
+    };
+    let line = str::from_utf8(&data).ok().and_then(|s| {
+        s.lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty() && !line.starts_with('#'))
+    });
+    let mut words = line.ok_or(ErrorKind::InvalidFont)?.split_whitespace();
+    let names = words.next().ok_or(ErrorKind::InvalidFont)?;
+    let scale = match words.next() {
+        Some(scale) => scale.parse().map_err(|_| ErrorKind::InvalidFont)?,
+        None => 1,
+    };
+    if !(1..=MAX_SCALE).contains(&scale) {
+        bail!(ErrorKind::InvalidFont);
+    }
+
+    let mut providers = Vec::<&'static dyn FontProvider>::new();
+    for name in names.split(',') {
+        if name == "builtin" {
+            providers.push(&BuiltinFont);
+            continue;
+        }
+        let entry = fat::find_file(&**fs, name)?;
+        let data = fat::read_file(&**fs, entry)?;
+        // the fonts are used until shutdown
+        if data.starts_with(packed::MAGIC) {
+            providers.push(Box::leak(Box::new(PackedFont::parse(&data)?)));
+        } else {
+            providers.push(Box::leak(Box::new(Psf2Font::parse(&data)?)));
+        }
+    }
+    if !names.split(',').any(|name| name == "builtin") {
+        providers.push(&BuiltinFont);
+    }
+    Ok(Some(ActiveFont {
+        providers: Box::leak(providers.into_boxed_slice()),
+        scale,
+    }))
+}
+
+pub(super) fn draw_char<D>(
     drawer: &mut D,
     pos: Point<i32>,
-    byte: u8,
+    ch: char,
     color: Color,
 ) -> Rectangle<i32>
 where
-    D: Draw,
+    D: Draw + ?Sized,
 {
-    let font = get_ascii_font(byte);
-    let draw_rect = Rectangle {
-        pos,
-        size: FONT_PIXEL_SIZE,
-    };
-
-    for (font_y, draw_y) in draw_rect.y_range().enumerate() {
-        for (font_x, draw_x) in draw_rect.x_range().enumerate() {
-            if (font[font_y] << font_x) & 0x80 != 0 {
-                drawer.draw(Point::new(draw_x, draw_y), color);
-            }
+    let draw_rect = Rectangle::new(pos, char_size(ch));
+    for_each_pixel(ch, |p, is_set| {
+        if is_set {
+            drawer.draw(pos + p, color);
         }
-    }
-
+    });
     draw_rect
 }
 
-pub(super) fn draw_byte_str<D>(
+pub(super) fn draw_char_bg<D>(
     drawer: &mut D,
     pos: Point<i32>,
-    bytes: &[u8],
-    color: Color,
+    ch: char,
+    fg: Color,
+    bg: Color,
 ) -> Rectangle<i32>
+where
+    D: Draw + ?Sized,
+{
+    drawer.fill_rect(Rectangle::new(pos, char_size(ch)), bg);
+    draw_char(drawer, pos, ch, fg)
+}
+
+pub(super) fn draw_str<D>(drawer: &mut D, pos: Point<i32>, s: &str, color: Color) -> Rectangle<i32>
 where
     D: Draw,
 {
     let start_pos = pos;
     let mut end_pos = start_pos;
     let mut pos = start_pos;
-    for byte in bytes {
-        let rect = draw_byte_char(drawer, pos, *byte, color);
+    for ch in s.chars() {
+        let rect = drawer.draw_char(pos, ch, color);
         pos.x = rect.x_end();
         end_pos = Point::elem_max(end_pos, rect.end_pos());
     }
@@ -59,25 +316,13 @@ where
     Rectangle::new(start_pos, size)
 }
 
-pub(crate) fn char_to_byte(ch: char) -> u8 {
-    let codepoint = u32::from(ch);
-    u8::try_from(codepoint).unwrap_or(b'?')
-}
-
-pub(super) fn draw_char<D>(
+pub(super) fn draw_str_bg<D>(
     drawer: &mut D,
     pos: Point<i32>,
-    ch: char,
-    color: Color,
+    s: &str,
+    fg: Color,
+    bg: Color,
 ) -> Rectangle<i32>
-where
-    D: Draw,
-{
-    let byte = char_to_byte(ch);
-    draw_byte_char(drawer, pos, byte, color)
-}
-
-pub(super) fn draw_str<D>(drawer: &mut D, pos: Point<i32>, s: &str, color: Color) -> Rectangle<i32>
 where
     D: Draw,
 {
@@ -85,10 +330,28 @@ where
     let mut end_pos = start_pos;
     let mut pos = start_pos;
     for ch in s.chars() {
-        let rect = draw_char(drawer, pos, ch, color);
+        let rect = drawer.draw_char_bg(pos, ch, fg, bg);
         pos.x = rect.x_end();
         end_pos = Point::elem_max(end_pos, rect.end_pos());
     }
     let size = end_pos - start_pos;
     Rectangle::new(start_pos, size)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn visible_cells() {
+        let font = active_font();
+        assert_eq!(font.char_width('a'), 1);
+        assert_eq!(font.char_width('\u{ff71}'), 1);
+        assert!(matches!(font.glyph('\u{3042}'), Glyph::Missing));
+        assert!(matches!(font.glyph(WIDE_CHAR_TAIL), Glyph::Blank));
+
+        let cells = ['a', WIDE_CHAR_TAIL, 'b'];
+        let visible = super::visible_cells(&cells).collect::<Vec<_>>();
+        assert_eq!(visible, [(0, 'a'), (1, WIDE_CHAR_TAIL), (2, 'b')]);
+    }
+}
@@ -0,0 +1,111 @@
+//! Packed bitmap fonts, a simple format for large fonts such as 16x16 CJK fonts.
+//!
+//! The file starts with the magic `PKFN`, the width and height of the glyphs as 16-bit values,
+//! and the number of glyphs as a 32-bit value. Each glyph follows as the code point of its
+//! character as a 32-bit value and its bitmap. The glyphs are sorted by the code points, and all
+//! values are little-endian.
+
+use super::FontProvider;
+use crate::{graphics::Size, prelude::*};
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+
+pub(super) const MAGIC: &[u8; 4] = b"PKFN";
+const HEADER_LEN: usize = 12;
+
+#[derive(Debug)]
+pub(super) struct PackedFont {
+    glyph_size: Size<i32>,
+    bytes_per_glyph: usize,
+    /// The characters of the glyphs in ascending order.
+    chars: Vec<char>,
+    glyphs: Vec<u8>,
+}
+
+impl PackedFont {
+    pub(super) fn parse(data: &[u8]) -> Result<Self> {
+        let header = data.get(..HEADER_LEN).ok_or(ErrorKind::InvalidFont)?;
+        if !header.starts_with(MAGIC) {
+            bail!(ErrorKind::InvalidFont);
+        }
+        let width = u16::from_le_bytes([header[4], header[5]]);
+        let height = u16::from_le_bytes([header[6], header[7]]);
+        let num_glyphs = u32::from_le_bytes([header[8], header[9], header[10], header[11]]);
+        if width == 0 || height == 0 {
+            bail!(ErrorKind::InvalidFont);
+        }
+
+        let bytes_per_glyph = (usize::from(width) + 7) / 8 * usize::from(height);
+        let record_len = 4 + bytes_per_glyph;
+        let num_glyphs = usize::try_from(num_glyphs)?;
+        let records_end = num_glyphs
+            .checked_mul(record_len)
+            .and_then(|len| len.checked_add(HEADER_LEN))
+            .ok_or(ErrorKind::InvalidFont)?;
+        let records = data
+            .get(HEADER_LEN..records_end)
+            .ok_or(ErrorKind::InvalidFont)?;
+
+        let mut chars = Vec::with_capacity(num_glyphs);
+        let mut glyphs = Vec::with_capacity(num_glyphs * bytes_per_glyph);
+        for record in records.chunks_exact(record_len) {
+            let code = u32::from_le_bytes([record[0], record[1], record[2], record[3]]);
+            let ch = char::from_u32(code).ok_or(ErrorKind::InvalidFont)?;
+            if chars.last().map(|last| *last >= ch).unwrap_or(false) {
+                bail!(ErrorKind::InvalidFont);
+            }
+            chars.push(ch);
+            glyphs.extend_from_slice(&record[4..]);
+        }
+
+        Ok(Self {
+            glyph_size: Size::new(i32::from(width), i32::from(height)),
+            bytes_per_glyph,
+            chars,
+            glyphs,
+        })
+    }
+}
+
+impl FontProvider for PackedFont {
+    fn glyph_size(&self) -> Size<i32> {
+        self.glyph_size
+    }
+
+    fn glyph(&self, ch: char) -> Option<&[u8]> {
+        let index = self.chars.binary_search(&ch).ok()?;
+        self.glyphs
+            .get(index * self.bytes_per_glyph..)?
+            .get(..self.bytes_per_glyph)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[allow(clippy::unwrap_used)]
+    #[test_case]
+    fn parse() {
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&9u16.to_le_bytes());
+        data.extend_from_slice(&1u16.to_le_bytes());
+        data.extend_from_slice(&2u32.to_le_bytes());
+        data.extend_from_slice(&u32::from('あ').to_le_bytes());
+        data.extend_from_slice(&[0xff, 0x80]);
+        data.extend_from_slice(&u32::from('漢').to_le_bytes());
+        data.extend_from_slice(&[0x55, 0x00]);
+
+        let font = PackedFont::parse(&data).unwrap();
+        assert_eq!(font.glyph_size(), Size::new(9, 1));
+        assert_eq!(font.glyph('あ'), Some(&[0xff, 0x80][..]));
+        assert_eq!(font.glyph('漢'), Some(&[0x55, 0x00][..]));
+        assert_eq!(font.glyph('い'), None);
+
+        // the first glyph is changed to U+7000, which is after '漢' (U+6F22)
+        let mut unsorted = data.clone();
+        unsorted[12..16].copy_from_slice(&0x7000u32.to_le_bytes());
+        assert!(PackedFont::parse(&unsorted).is_err());
+        assert!(PackedFont::parse(&data[..data.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,153 @@
+//! PC Screen Font version 2, the console font format of Linux.
+//!
+//! The Unicode table maps characters to glyphs. Sequences of combining characters in the table
+//! are ignored. Without the table, the glyphs are indexed by the code points.
+
+use super::FontProvider;
+use crate::{graphics::Size, prelude::*};
+use alloc::{collections::BTreeMap, vec::Vec};
+use core::{convert::TryFrom, str};
+
+const MAGIC: u32 = 0x864a_b572;
+const HEADER_LEN: usize = 32;
+const FLAG_HAS_UNICODE_TABLE: u32 = 0x01;
+/// Separates the entries of glyphs in the Unicode table.
+const SEPARATOR: u8 = 0xff;
+/// Starts a sequence of combining characters in the Unicode table.
+const SEQUENCE_START: u8 = 0xfe;
+
+#[derive(Debug)]
+pub(super) struct Psf2Font {
+    glyph_size: Size<i32>,
+    bytes_per_glyph: usize,
+    glyphs: Vec<u8>,
+    /// Maps characters to glyph indices, or `None` if the font has no Unicode table.
+    unicode_table: Option<BTreeMap<char, usize>>,
+}
+
+impl Psf2Font {
+    pub(super) fn parse(data: &[u8]) -> Result<Self> {
+        let field = |index: usize| -> Result<u32> {
+            let bytes = data
+                .get(index * 4..index * 4 + 4)
+                .ok_or(ErrorKind::InvalidFont)?;
+            #[allow(clippy::unwrap_used)] // the slice has 4 bytes
+            Ok(u32::from_le_bytes(<[u8; 4]>::try_from(bytes).unwrap()))
+        };
+        let [magic, _version, header_len, flags, num_glyphs, bytes_per_glyph, height, width] = [
+            field(0)?,
+            field(1)?,
+            field(2)?,
+            field(3)?,
+            field(4)?,
+            field(5)?,
+            field(6)?,
+            field(7)?,
+        ];
+        if magic != MAGIC {
+            bail!(ErrorKind::InvalidFont);
+        }
+
+        let header_len = usize::try_from(header_len)?;
+        let num_glyphs = usize::try_from(num_glyphs)?;
+        let bytes_per_glyph = usize::try_from(bytes_per_glyph)?;
+        let glyph_size = Size::new(i32::try_from(width)?, i32::try_from(height)?);
+        let bytes_per_row = (usize::try_from(width)? + 7) / 8;
+        if header_len < HEADER_LEN
+            || width == 0
+            || height == 0
+            || bytes_per_glyph < bytes_per_row * usize::try_from(height)?
+        {
+            bail!(ErrorKind::InvalidFont);
+        }
+
+        let glyphs_end = num_glyphs
+            .checked_mul(bytes_per_glyph)
+            .and_then(|len| len.checked_add(header_len))
+            .ok_or(ErrorKind::InvalidFont)?;
+        let glyphs = data
+            .get(header_len..glyphs_end)
+            .ok_or(ErrorKind::InvalidFont)?
+            .to_vec();
+        let unicode_table = if flags & FLAG_HAS_UNICODE_TABLE != 0 {
+            Some(parse_unicode_table(&data[glyphs_end..], num_glyphs)?)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            glyph_size,
+            bytes_per_glyph,
+            glyphs,
+            unicode_table,
+        })
+    }
+}
+
+fn parse_unicode_table(table: &[u8], num_glyphs: usize) -> Result<BTreeMap<char, usize>> {
+    let mut map = BTreeMap::new();
+    let mut entries = table.split(|b| *b == SEPARATOR);
+    for index in 0..num_glyphs {
+        let entry = entries.next().ok_or(ErrorKind::InvalidFont)?;
+        let chars = match entry.iter().position(|b| *b == SEQUENCE_START) {
+            Some(end) => &entry[..end],
+            None => entry,
+        };
+        let chars = str::from_utf8(chars).map_err(|_| ErrorKind::InvalidFont)?;
+        for ch in chars.chars() {
+            map.entry(ch).or_insert(index);
+        }
+    }
+    Ok(map)
+}
+
+impl FontProvider for Psf2Font {
+    fn glyph_size(&self) -> Size<i32> {
+        self.glyph_size
+    }
+
+    fn glyph(&self, ch: char) -> Option<&[u8]> {
+        let index = match &self.unicode_table {
+            Some(table) => *table.get(&ch)?,
+            None => usize::try_from(u32::from(ch)).ok()?,
+        };
+        self.glyphs
+            .get(index * self.bytes_per_glyph..)?
+            .get(..self.bytes_per_glyph)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[allow(clippy::unwrap_used)]
+    #[test_case]
+    fn parse() {
+        let header = [MAGIC, 0, 32, FLAG_HAS_UNICODE_TABLE, 2, 2, 2, 3];
+        let mut data = header
+            .iter()
+            .flat_map(|field| field.to_le_bytes())
+            .collect::<Vec<_>>();
+        data.extend_from_slice(&[0xa0, 0x40, 0xe0, 0xe0]);
+        // glyph 0 is 'A' and 'Ä', glyph 1 is 'B' and a sequence of 'B' + U+0301
+        data.extend_from_slice("AÄ".as_bytes());
+        data.push(SEPARATOR);
+        data.push(b'B');
+        data.push(SEQUENCE_START);
+        data.extend_from_slice("B\u{301}".as_bytes());
+        data.push(SEPARATOR);
+
+        let font = Psf2Font::parse(&data).unwrap();
+        assert_eq!(font.glyph_size(), Size::new(3, 2));
+        assert_eq!(font.glyph('A'), Some(&[0xa0, 0x40][..]));
+        assert_eq!(font.glyph('Ä'), Some(&[0xa0, 0x40][..]));
+        assert_eq!(font.glyph('B'), Some(&[0xe0, 0xe0][..]));
+        assert_eq!(font.glyph('C'), None);
+        assert_eq!(font.glyph('\u{301}'), None);
+
+        data[0] = 0;
+        assert!(Psf2Font::parse(&data).is_err());
+        assert!(Psf2Font::parse(&[0; 16]).is_err());
+    }
+}
@@ -1,8 +1,8 @@
 use crate::{
-    desktop,
     graphics::{Draw, FrameBufferDrawer, ScreenInfo},
     prelude::*,
     sync::{OnceCell, SpinMutex, SpinMutexGuard},
+    theme,
 };
 use bootloader::boot_info::FrameBuffer;
 
@@ -11,7 +11,7 @@ static DRAWER: OnceCell<SpinMutex<FrameBufferDrawer>> = OnceCell::uninit();
 pub(super) fn init(frame_buffer: FrameBuffer) -> Result<ScreenInfo> {
     let mut drawer = FrameBufferDrawer::new_frame_buffer(frame_buffer)?;
     let info = drawer.info();
-    drawer.fill_rect(info.area(), desktop::BG_COLOR);
+    drawer.fill_rect(info.area(), theme::current().desktop_bg);
 
     DRAWER.init_once(|| SpinMutex::new(drawer));
 
@@ -146,6 +146,12 @@ impl<T> Rectangle<T> {
     pub(crate) const fn new(pos: Point<T>, size: Size<T>) -> Self {
         Self { pos, size }
     }
+
+    /// Creates a rectangle at (`x`, `y`) of `w` x `h`, which keeps test cases short.
+    #[cfg(test)]
+    pub(crate) const fn from_xywh(x: T, y: T, w: T, h: T) -> Self {
+        Self::new(Point::new(x, y), Size::new(w, h))
+    }
 }
 
 impl<T> Rectangle<T>
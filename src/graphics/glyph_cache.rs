@@ -0,0 +1,127 @@
+//! Glyphs rendered in the pixel format of buffers, which are drawn by copying their rows.
+//!
+//! An entry of the cache holds the glyphs of a pair of foreground and background colors in a
+//! pixel format, and each glyph is rendered when it is first drawn. The least recently used
+//! entry is evicted when the cache is full.
+
+use super::{font, Color, PixelDraw};
+use crate::{interrupt, sync::SpinMutex};
+use alloc::{boxed::Box, collections::BTreeMap, vec, vec::Vec};
+use bootloader::boot_info::PixelFormat;
+use x86_64::instructions::interrupts;
+
+const MAX_ENTRIES: usize = 8;
+
+static CACHE: SpinMutex<GlyphCache> = SpinMutex::new(GlyphCache::new());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Key {
+    pixel_format: PixelFormat,
+    fg: Color,
+    bg: Color,
+}
+
+#[derive(Debug)]
+struct Entry {
+    key: Key,
+    last_used: u64,
+    glyphs: BTreeMap<char, Box<[u8]>>,
+}
+
+#[derive(Debug)]
+struct GlyphCache {
+    entries: Vec<Entry>,
+    clock: u64,
+}
+
+impl GlyphCache {
+    const fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+            clock: 0,
+        }
+    }
+
+    /// Returns the entry of `key`, which is created if `create` is `true`.
+    fn entry(&mut self, key: Key, create: bool) -> Option<&mut Entry> {
+        self.clock += 1;
+        let index = match self.entries.iter().position(|entry| entry.key == key) {
+            Some(index) => index,
+            None if !create => return None,
+            None => {
+                if self.entries.len() == MAX_ENTRIES {
+                    if let Some(lru) =
+                        (0..self.entries.len()).min_by_key(|index| self.entries[*index].last_used)
+                    {
+                        self.entries.swap_remove(lru);
+                    }
+                }
+                self.entries.push(Entry {
+                    key,
+                    last_used: 0,
+                    glyphs: BTreeMap::new(),
+                });
+                self.entries.len() - 1
+            }
+        };
+        let entry = &mut self.entries[index];
+        entry.last_used = self.clock;
+        Some(entry)
+    }
+}
+
+fn render(
+    pixel_drawer: &dyn PixelDraw,
+    bytes_per_pixel: usize,
+    ch: char,
+    fg: Color,
+    bg: Color,
+) -> Box<[u8]> {
+    let size = font::char_size(ch);
+    let width = size.x as usize;
+    let mut glyph = vec![0; width * size.y as usize * bytes_per_pixel];
+    font::for_each_pixel(ch, |p, is_set| {
+        let c = if is_set { fg } else { bg };
+        let index = (p.y as usize * width + p.x as usize) * bytes_per_pixel;
+        pixel_drawer.pixel_draw(&mut glyph, index, c);
+    });
+    glyph.into_boxed_slice()
+}
+
+/// Calls `f` with the glyph of `ch`, whose rows of [`font::char_size`] pixels are drawn
+/// by `pixel_drawer`.
+///
+/// Returns `None` if the glyph is not cached and cannot be rendered, because memory cannot be
+/// allocated in interrupt context.
+pub(super) fn with_glyph<T>(
+    pixel_format: PixelFormat,
+    pixel_drawer: &dyn PixelDraw,
+    bytes_per_pixel: usize,
+    ch: char,
+    fg: Color,
+    bg: Color,
+    f: impl FnOnce(&[u8]) -> T,
+) -> Option<T> {
+    let can_render = !interrupt::is_interrupt_context();
+    interrupts::without_interrupts(|| {
+        let mut cache = CACHE.lock();
+        let key = Key {
+            pixel_format,
+            fg,
+            bg,
+        };
+        let glyphs = &mut cache.entry(key, can_render)?.glyphs;
+        if !glyphs.contains_key(&ch) && can_render {
+            glyphs.insert(ch, render(pixel_drawer, bytes_per_pixel, ch, fg, bg));
+        }
+        glyphs.get(&ch).map(|glyph| f(glyph))
+    })
+}
+
+/// Drops all glyphs, which must be called when the font is changed.
+pub(super) fn clear() {
+    interrupts::without_interrupts(|| {
+        let mut cache = CACHE.lock();
+        cache.entries.clear();
+    })
+}
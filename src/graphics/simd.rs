@@ -0,0 +1,189 @@
+//! Row operations of [`BufferDrawer`](super::BufferDrawer), with SSE2 fast paths.
+//!
+//! The kernel is compiled without SSE, so the XMM registers are not saved on task switches. The
+//! SSE2 code runs with interrupts disabled, one row at a time, so that no other code uses the
+//! registers meanwhile. The fast paths handle 4-byte pixels, and the other pixel formats and CPUs
+//! without SSE2 use the scalar code.
+
+use super::color::mix_component as mix;
+use crate::cpu;
+use core::arch::x86_64::*;
+use x86_64::instructions::interrupts;
+
+fn use_sse2(bytes_per_pixel: usize) -> bool {
+    bytes_per_pixel == 4 && cpu::is_sse_enabled()
+}
+
+pub(super) fn copy_row(dst: &mut [u8], src: &[u8]) {
+    assert_eq!(dst.len(), src.len());
+    if cpu::is_sse_enabled() {
+        interrupts::without_interrupts(|| unsafe { copy_row_sse2(dst, src) })
+    } else {
+        dst.copy_from_slice(src)
+    }
+}
+
+/// Fills `dst` with `pixel`.
+pub(super) fn fill_row(dst: &mut [u8], pixel: &[u8]) {
+    if use_sse2(pixel.len()) {
+        let pixel = [pixel[0], pixel[1], pixel[2], pixel[3]];
+        interrupts::without_interrupts(|| unsafe { fill_row_sse2(dst, pixel) })
+    } else {
+        fill_row_scalar(dst, pixel)
+    }
+}
+
+/// Blends the pixels of `src` over `dst` by the alpha values of the pixels.
+pub(super) fn blend_row(dst: &mut [u8], src: &[u8], alpha: &[u8], bytes_per_pixel: usize) {
+    assert_eq!(dst.len(), src.len());
+    assert_eq!(dst.len(), alpha.len() * bytes_per_pixel);
+    if use_sse2(bytes_per_pixel) {
+        interrupts::without_interrupts(|| unsafe { blend_row_sse2(dst, src, alpha) })
+    } else {
+        blend_row_scalar(dst, src, alpha, bytes_per_pixel)
+    }
+}
+
+fn fill_row_scalar(dst: &mut [u8], pixel: &[u8]) {
+    for dst in dst.chunks_exact_mut(pixel.len()) {
+        dst.copy_from_slice(pixel);
+    }
+}
+
+fn blend_row_scalar(dst: &mut [u8], src: &[u8], alpha: &[u8], bytes_per_pixel: usize) {
+    let pixels = dst
+        .chunks_exact_mut(bytes_per_pixel)
+        .zip(src.chunks_exact(bytes_per_pixel))
+        .zip(alpha);
+    for ((dst, src), alpha) in pixels {
+        match *alpha {
+            0 => {}
+            255 => dst.copy_from_slice(src),
+            alpha => {
+                for (dst, src) in dst.iter_mut().zip(src) {
+                    *dst = mix(*src, *dst, alpha);
+                }
+            }
+        }
+    }
+}
+
+// The intrinsics are unsafe only because they require SSE2, which the callers check.
+
+#[target_feature(enable = "sse2")]
+unsafe fn copy_row_sse2(dst: &mut [u8], src: &[u8]) {
+    let mut dst_chunks = dst.chunks_exact_mut(16);
+    let mut src_chunks = src.chunks_exact(16);
+    for (dst, src) in (&mut dst_chunks).zip(&mut src_chunks) {
+        unsafe {
+            let v = _mm_loadu_si128(src.as_ptr().cast());
+            _mm_storeu_si128(dst.as_mut_ptr().cast(), v);
+        }
+    }
+    dst_chunks
+        .into_remainder()
+        .copy_from_slice(src_chunks.remainder());
+}
+
+#[target_feature(enable = "sse2")]
+unsafe fn fill_row_sse2(dst: &mut [u8], pixel: [u8; 4]) {
+    let mut chunks = dst.chunks_exact_mut(16);
+    unsafe {
+        let v = _mm_set1_epi32(i32::from_le_bytes(pixel));
+        for dst in &mut chunks {
+            _mm_storeu_si128(dst.as_mut_ptr().cast(), v);
+        }
+    }
+    fill_row_scalar(chunks.into_remainder(), &pixel);
+}
+
+#[target_feature(enable = "sse2")]
+unsafe fn blend_row_sse2(dst: &mut [u8], src: &[u8], alpha: &[u8]) {
+    let mut dst_chunks = dst.chunks_exact_mut(16);
+    let mut src_chunks = src.chunks_exact(16);
+    let mut alpha_chunks = alpha.chunks_exact(4);
+    let pixels = (&mut dst_chunks)
+        .zip(&mut src_chunks)
+        .zip(&mut alpha_chunks);
+    unsafe {
+        let zero = _mm_setzero_si128();
+        for ((dst, src), alpha) in pixels {
+            let s = _mm_loadu_si128(src.as_ptr().cast());
+            let d = _mm_loadu_si128(dst.as_ptr().cast());
+            // spread the alpha value of each pixel to its four bytes
+            let a = _mm_cvtsi32_si128(i32::from_le_bytes([alpha[0], alpha[1], alpha[2], alpha[3]]));
+            let a = _mm_unpacklo_epi8(a, a);
+            let a = _mm_unpacklo_epi16(a, a);
+            let lo = blend_u16x8(
+                _mm_unpacklo_epi8(s, zero),
+                _mm_unpacklo_epi8(d, zero),
+                _mm_unpacklo_epi8(a, zero),
+            );
+            let hi = blend_u16x8(
+                _mm_unpackhi_epi8(s, zero),
+                _mm_unpackhi_epi8(d, zero),
+                _mm_unpackhi_epi8(a, zero),
+            );
+            _mm_storeu_si128(dst.as_mut_ptr().cast(), _mm_packus_epi16(lo, hi));
+        }
+    }
+    blend_row_scalar(
+        dst_chunks.into_remainder(),
+        src_chunks.remainder(),
+        alpha_chunks.remainder(),
+        4,
+    );
+}
+
+/// Blends 16-bit lanes holding 8-bit values.
+#[target_feature(enable = "sse2")]
+unsafe fn blend_u16x8(src: __m128i, dst: __m128i, alpha: __m128i) -> __m128i {
+    unsafe {
+        let inv_alpha = _mm_sub_epi16(_mm_set1_epi16(255), alpha);
+        let x = _mm_add_epi16(_mm_mullo_epi16(src, alpha), _mm_mullo_epi16(dst, inv_alpha));
+        // (x + 127) / 255 for x <= 255 * 255, which equals (t + (t >> 8)) >> 8 with t = x + 128
+        let t = _mm_add_epi16(x, _mm_set1_epi16(128));
+        _mm_srli_epi16(_mm_add_epi16(t, _mm_srli_epi16(t, 8)), 8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    fn pixels(seed: u32) -> Vec<u8> {
+        (0..23 * 4)
+            .map(|i| {
+                (i as u32 * 97 + seed)
+                    .wrapping_mul(2_654_435_761)
+                    .to_le_bytes()[3]
+            })
+            .collect()
+    }
+
+    #[test_case]
+    fn blend_row() {
+        let src = pixels(1);
+        let dst = pixels(2);
+        let alpha = pixels(3)[..23].to_vec();
+
+        let mut expected = dst.clone();
+        for (i, byte) in expected.iter_mut().enumerate() {
+            *byte = match alpha[i / 4] {
+                0 => *byte,
+                255 => src[i],
+                a => mix(src[i], *byte, a),
+            };
+        }
+
+        let mut scalar = dst.clone();
+        blend_row_scalar(&mut scalar, &src, &alpha, 4);
+        assert_eq!(scalar, expected);
+        if cpu::is_sse_enabled() {
+            let mut simd = dst;
+            interrupts::without_interrupts(|| unsafe { blend_row_sse2(&mut simd, &src, &alpha) });
+            assert_eq!(simd, expected);
+        }
+    }
+}
@@ -1,9 +1,17 @@
 use super::{font, Color, Offset, Point, Rectangle, Size};
+use core::convert::TryFrom;
 
 pub(crate) trait Draw {
     fn size(&self) -> Size<i32>;
     fn draw(&mut self, p: Point<i32>, c: Color);
     fn move_area(&mut self, offset: Point<i32>, src: Rectangle<i32>);
+    /// Returns the area drawing is clipped to, which is the innermost clip rectangle.
+    fn clip_area(&self) -> Rectangle<i32>;
+    /// Clips drawing to `rect` within the current clip area until the matching [`pop_clip`].
+    ///
+    /// [`pop_clip`]: Draw::pop_clip
+    fn push_clip(&mut self, rect: Rectangle<i32>);
+    fn pop_clip(&mut self);
 
     fn area(&self) -> Rectangle<i32> {
         Rectangle::new(Point::new(0, 0), self.size())
@@ -15,6 +23,28 @@ pub(crate) trait Draw {
         }
     }
 
+    /// Fills `rect` with the colors from `from` at the left edge to `to` at the right edge.
+    fn fill_horizontal_gradient(&mut self, rect: Rectangle<i32>, from: Color, to: Color) {
+        for (i, x) in rect.x_range().enumerate() {
+            let c = from.interpolate(to, gradient_step(i, rect.size.x));
+            self.fill_rect(
+                Rectangle::new(Point::new(x, rect.y_start()), Size::new(1, rect.size.y)),
+                c,
+            );
+        }
+    }
+
+    /// Fills `rect` with the colors from `from` at the top edge to `to` at the bottom edge.
+    fn fill_vertical_gradient(&mut self, rect: Rectangle<i32>, from: Color, to: Color) {
+        for (i, y) in rect.y_range().enumerate() {
+            let c = from.interpolate(to, gradient_step(i, rect.size.y));
+            self.fill_rect(
+                Rectangle::new(Point::new(rect.x_start(), y), Size::new(rect.size.x, 1)),
+                c,
+            );
+        }
+    }
+
     fn draw_rect(&mut self, rect: Rectangle<i32>, c: Color) {
         if rect.size.x == 0 || rect.size.y == 0 {
             return;
@@ -30,32 +60,33 @@ pub(crate) trait Draw {
         }
     }
 
-    fn draw_byte_char(&mut self, pos: Point<i32>, byte: u8, color: Color) -> Rectangle<i32>
+    fn draw_char(&mut self, pos: Point<i32>, ch: char, color: Color) -> Rectangle<i32>
     where
         Self: Sized,
     {
-        font::draw_byte_char(self, pos, byte, color)
+        font::draw_char(self, pos, ch, color)
     }
 
-    fn draw_byte_str(&mut self, pos: Point<i32>, bytes: &[u8], color: Color) -> Rectangle<i32>
-    where
-        Self: Sized,
-    {
-        font::draw_byte_str(self, pos, bytes, color)
+    /// Draws `ch` in `fg` on its cells filled with `bg`.
+    ///
+    /// Buffers draw this by copying a cached glyph, which is faster than filling the cells and
+    /// drawing the character.
+    fn draw_char_bg(&mut self, pos: Point<i32>, ch: char, fg: Color, bg: Color) -> Rectangle<i32> {
+        font::draw_char_bg(self, pos, ch, fg, bg)
     }
 
-    fn draw_char(&mut self, pos: Point<i32>, ch: char, color: Color) -> Rectangle<i32>
+    fn draw_str(&mut self, pos: Point<i32>, s: &str, color: Color) -> Rectangle<i32>
     where
         Self: Sized,
     {
-        font::draw_char(self, pos, ch, color)
+        font::draw_str(self, pos, s, color)
     }
 
-    fn draw_str(&mut self, pos: Point<i32>, s: &str, color: Color) -> Rectangle<i32>
+    fn draw_str_bg(&mut self, pos: Point<i32>, s: &str, fg: Color, bg: Color) -> Rectangle<i32>
     where
         Self: Sized,
     {
-        font::draw_str(self, pos, s, color)
+        font::draw_str_bg(self, pos, s, fg, bg)
     }
 
     fn draw_box(
@@ -97,3 +128,12 @@ pub(crate) trait Draw {
     }
 }
 static_assertions::assert_obj_safe!(Draw);
+
+/// Returns the interpolation parameter of the `i`-th of `len` lines of a gradient.
+fn gradient_step(i: usize, len: i32) -> u8 {
+    let last = usize::try_from(len - 1).unwrap_or(0);
+    if last == 0 {
+        return 0;
+    }
+    u8::try_from(i * 255 / last).unwrap_or(u8::MAX)
+}
@@ -0,0 +1,119 @@
+//! Idle governor.
+//!
+//! The idle task waits for interrupts with MONITOR/MWAIT if the CPU supports it, or HLT
+//! otherwise, and records how long the CPU stayed idle.
+
+use crate::{cpu, prelude::*, sync::OnceCell, task};
+use core::{
+    convert::TryFrom,
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+use x86_64::instructions::interrupts;
+
+/// The deepest C-state requested by MWAIT.
+///
+/// States deeper than C1 may stop the LAPIC timer, so they are used only if the CPU has ARAT.
+const MAX_C_STATE: u32 = 7;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Method {
+    Mwait { hint: u32 },
+    Hlt,
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Method::Mwait { hint } => write!(f, "MWAIT (C{})", (hint >> 4) + 1),
+            Method::Hlt => write!(f, "HLT"),
+        }
+    }
+}
+
+impl Method {
+    fn select() -> Self {
+        let features = cpu::features();
+        // MWAIT is entered with interrupts disabled so that the wake-up interrupt
+        // is not handled before the idle time is recorded.
+        if !features.monitor_mwait || !features.mwait_interrupt_break {
+            return Method::Hlt;
+        }
+        let max_c_state = if features.arat { MAX_C_STATE } else { 1 };
+        let c_state = (1..=max_c_state)
+            .rev()
+            .find(|c_state| (features.mwait_sub_states >> (c_state * 4)) & 0xf != 0)
+            .unwrap_or(1);
+        Method::Mwait {
+            hint: (c_state - 1) << 4,
+        }
+    }
+
+    /// Waits for an interrupt.
+    ///
+    /// Interrupts must be disabled before calling this, and are disabled again on return.
+    fn wait(&self) {
+        match self {
+            Method::Mwait { hint } => unsafe {
+                // Nobody writes to the monitored line, so only interrupts wake the CPU.
+                asm!(
+                    "monitor",
+                    in("rax") &MONITOR_LINE,
+                    in("ecx") 0,
+                    in("edx") 0,
+                    options(nostack),
+                );
+                // ECX bit 0: treat masked interrupts as break events
+                asm!("mwait", in("eax") *hint, in("ecx") 1, options(nostack));
+            },
+            Method::Hlt => {
+                interrupts::enable_and_hlt();
+                interrupts::disable();
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct IdleStats {
+    pub(crate) method: Method,
+    /// The time the idle task spent waiting for interrupts.
+    pub(crate) idle_time: Duration,
+    pub(crate) wakeups: u64,
+}
+
+static METHOD: OnceCell<Method> = OnceCell::uninit();
+static MONITOR_LINE: AtomicU64 = AtomicU64::new(0);
+static IDLE_NANOS: AtomicU64 = AtomicU64::new(0);
+static WAKEUPS: AtomicU64 = AtomicU64::new(0);
+
+/// Runs the idle loop. This is the body of the idle task.
+pub(crate) fn run() -> ! {
+    let method = Method::select();
+    info!("idle method: {}", method);
+    METHOD.init_once(|| method);
+
+    loop {
+        interrupts::disable();
+        // The CPU time of the idle task excludes the time other tasks ran
+        // when an interrupt handler switched away from it.
+        let start = task::current_cpu_time();
+        method.wait();
+        let idle = task::current_cpu_time().saturating_sub(start);
+        let nanos = u64::try_from(idle.as_nanos()).unwrap_or(u64::MAX);
+        IDLE_NANOS.fetch_add(nanos, Ordering::Relaxed);
+        WAKEUPS.fetch_add(1, Ordering::Relaxed);
+        interrupts::enable();
+    }
+}
+
+/// Returns the idle residency statistics, or `None` if the idle task has not run yet.
+pub(crate) fn stats() -> Option<IdleStats> {
+    let method = *METHOD.try_get().ok()?;
+    Some(IdleStats {
+        method,
+        idle_time: Duration::from_nanos(IDLE_NANOS.load(Ordering::Relaxed)),
+        wakeups: WAKEUPS.load(Ordering::Relaxed),
+    })
+}
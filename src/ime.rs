@@ -0,0 +1,217 @@
+//! A minimal input method, which converts romaji typed on the keyboard into hiragana.
+//!
+//! The input method is toggled by Ctrl+Space, and turned on and off by the Henkan and Muhenkan
+//! keys of Japanese keyboards. While it is on, letters are composed into kana, which are sent as
+//! keyboard events of the kana. The letters being composed are not displayed, and there is no
+//! kanji conversion.
+
+use crate::{
+    keyboard::{KeyboardEvent, Modifier},
+    prelude::*,
+};
+use alloc::{string::String, vec::Vec};
+use core::mem;
+use enumflags2::BitFlags;
+
+const KEYCODE_SPACE: u8 = 0x2c;
+const KEYCODE_HENKAN: u8 = 0x8a;
+const KEYCODE_MUHENKAN: u8 = 0x8b;
+
+#[rustfmt::skip]
+const ROMAJI_TABLE: &[(&str, &str)] = &[
+    ("a", "あ"), ("i", "い"), ("u", "う"), ("e", "え"), ("o", "お"),
+    ("ka", "か"), ("ki", "き"), ("ku", "く"), ("ke", "け"), ("ko", "こ"),
+    ("ga", "が"), ("gi", "ぎ"), ("gu", "ぐ"), ("ge", "げ"), ("go", "ご"),
+    ("sa", "さ"), ("si", "し"), ("shi", "し"), ("su", "す"), ("se", "せ"), ("so", "そ"),
+    ("za", "ざ"), ("zi", "じ"), ("ji", "じ"), ("zu", "ず"), ("ze", "ぜ"), ("zo", "ぞ"),
+    ("ta", "た"), ("ti", "ち"), ("chi", "ち"), ("tu", "つ"), ("tsu", "つ"), ("te", "て"),
+    ("to", "と"),
+    ("da", "だ"), ("di", "ぢ"), ("du", "づ"), ("de", "で"), ("do", "ど"),
+    ("na", "な"), ("ni", "に"), ("nu", "ぬ"), ("ne", "ね"), ("no", "の"), ("nn", "ん"),
+    ("n'", "ん"),
+    ("ha", "は"), ("hi", "ひ"), ("hu", "ふ"), ("fu", "ふ"), ("he", "へ"), ("ho", "ほ"),
+    ("ba", "ば"), ("bi", "び"), ("bu", "ぶ"), ("be", "べ"), ("bo", "ぼ"),
+    ("pa", "ぱ"), ("pi", "ぴ"), ("pu", "ぷ"), ("pe", "ぺ"), ("po", "ぽ"),
+    ("ma", "ま"), ("mi", "み"), ("mu", "む"), ("me", "め"), ("mo", "も"),
+    ("ya", "や"), ("yu", "ゆ"), ("yo", "よ"),
+    ("ra", "ら"), ("ri", "り"), ("ru", "る"), ("re", "れ"), ("ro", "ろ"),
+    ("wa", "わ"), ("wo", "を"), ("vu", "ゔ"),
+    ("kya", "きゃ"), ("kyu", "きゅ"), ("kyo", "きょ"),
+    ("gya", "ぎゃ"), ("gyu", "ぎゅ"), ("gyo", "ぎょ"),
+    ("sya", "しゃ"), ("syu", "しゅ"), ("syo", "しょ"),
+    ("sha", "しゃ"), ("shu", "しゅ"), ("she", "しぇ"), ("sho", "しょ"),
+    ("zya", "じゃ"), ("zyu", "じゅ"), ("zyo", "じょ"),
+    ("ja", "じゃ"), ("ju", "じゅ"), ("je", "じぇ"), ("jo", "じょ"),
+    ("tya", "ちゃ"), ("tyu", "ちゅ"), ("tyo", "ちょ"),
+    ("cha", "ちゃ"), ("chu", "ちゅ"), ("che", "ちぇ"), ("cho", "ちょ"),
+    ("dya", "ぢゃ"), ("dyu", "ぢゅ"), ("dyo", "ぢょ"),
+    ("nya", "にゃ"), ("nyu", "にゅ"), ("nyo", "にょ"),
+    ("hya", "ひゃ"), ("hyu", "ひゅ"), ("hyo", "ひょ"),
+    ("fa", "ふぁ"), ("fi", "ふぃ"), ("fe", "ふぇ"), ("fo", "ふぉ"),
+    ("bya", "びゃ"), ("byu", "びゅ"), ("byo", "びょ"),
+    ("pya", "ぴゃ"), ("pyu", "ぴゅ"), ("pyo", "ぴょ"),
+    ("mya", "みゃ"), ("myu", "みゅ"), ("myo", "みょ"),
+    ("rya", "りゃ"), ("ryu", "りゅ"), ("ryo", "りょ"),
+    ("xa", "ぁ"), ("xi", "ぃ"), ("xu", "ぅ"), ("xe", "ぇ"), ("xo", "ぉ"),
+    ("xya", "ゃ"), ("xyu", "ゅ"), ("xyo", "ょ"), ("xtu", "っ"), ("xwa", "ゎ"),
+    ("-", "ー"), (",", "、"), (".", "。"), ("[", "「"), ("]", "」"),
+];
+
+/// Converts romaji into hiragana as the letters are typed.
+#[derive(Debug, Default)]
+struct RomajiConverter {
+    /// The letters which may be a prefix of romaji.
+    pending: String,
+}
+
+impl RomajiConverter {
+    /// Adds `ch` to the pending letters, and returns the characters converted.
+    fn push(&mut self, ch: char) -> String {
+        self.pending.push(ch.to_ascii_lowercase());
+        let mut output = String::new();
+        while !self.pending.is_empty() {
+            if let Some((_, kana)) = ROMAJI_TABLE
+                .iter()
+                .find(|(romaji, _)| *romaji == self.pending)
+            {
+                output.push_str(kana);
+                self.pending.clear();
+                break;
+            }
+            if ROMAJI_TABLE
+                .iter()
+                .any(|(romaji, _)| romaji.starts_with(&self.pending))
+            {
+                break;
+            }
+            // the first letter cannot start romaji with the following ones
+            let first = self.pending.remove(0);
+            match first {
+                // `n` followed by a consonant, as in "kanji"
+                'n' => output.push('ん'),
+                // a doubled consonant, as in "kitte"
+                _ if first.is_ascii_alphabetic() && self.pending.starts_with(first) => {
+                    output.push('っ')
+                }
+                _ => output.push(first),
+            }
+        }
+        output
+    }
+
+    /// Takes the pending letters as they are, except a trailing `n`, which is converted.
+    fn flush(&mut self) -> String {
+        if self.pending == "n" {
+            self.pending.clear();
+            return "ん".into();
+        }
+        mem::take(&mut self.pending)
+    }
+
+    /// Removes the last pending letter, and returns `false` if there is none.
+    fn pop(&mut self) -> bool {
+        self.pending.pop().is_some()
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct InputMethod {
+    enabled: bool,
+    converter: RomajiConverter,
+}
+
+impl InputMethod {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Processes `event`, and returns the events to send instead.
+    pub(crate) fn process(&mut self, event: KeyboardEvent) -> Vec<KeyboardEvent> {
+        let mut events = Vec::new();
+        if !event.press {
+            events.push(event);
+            return events;
+        }
+
+        let control = event
+            .modifier
+            .intersects(Modifier::LControl | Modifier::RControl);
+        let enabled = match event.keycode {
+            KEYCODE_SPACE if control => Some(!self.enabled),
+            KEYCODE_HENKAN => Some(true),
+            KEYCODE_MUHENKAN => Some(false),
+            _ => None,
+        };
+        if let Some(enabled) = enabled {
+            let text = self.converter.flush();
+            push_chars(&mut events, event, &text);
+            if self.enabled != enabled {
+                info!("ime: {}", if enabled { "hiragana" } else { "direct" });
+                self.enabled = enabled;
+            }
+            return events;
+        }
+        if !self.enabled {
+            events.push(event);
+            return events;
+        }
+
+        let shortcut_modifiers: BitFlags<Modifier> = Modifier::LControl
+            | Modifier::RControl
+            | Modifier::LAlt
+            | Modifier::RAlt
+            | Modifier::LGui
+            | Modifier::RGui;
+        match event.ascii {
+            ch if ch.is_ascii_graphic() && !event.modifier.intersects(shortcut_modifiers) => {
+                let text = self.converter.push(ch);
+                push_chars(&mut events, event, &text);
+            }
+            // Backspace deletes the pending letters first
+            '\x08' if self.converter.pop() => {}
+            // Enter commits the pending letters
+            '\n' if !self.converter.pending.is_empty() => {
+                let text = self.converter.flush();
+                push_chars(&mut events, event, &text);
+            }
+            _ => {
+                let text = self.converter.flush();
+                push_chars(&mut events, event, &text);
+                events.push(event);
+            }
+        }
+        events
+    }
+}
+
+/// Pushes key press events of the characters of `text`, which are typed by `event`.
+fn push_chars(events: &mut Vec<KeyboardEvent>, event: KeyboardEvent, text: &str) {
+    events.extend(text.chars().map(|ascii| KeyboardEvent { ascii, ..event }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn convert(input: &str) -> String {
+        let mut converter = RomajiConverter::default();
+        let mut output = input
+            .chars()
+            .map(|ch| converter.push(ch))
+            .collect::<String>();
+        output.push_str(&converter.flush());
+        output
+    }
+
+    #[test_case]
+    fn romaji() {
+        assert_eq!(convert("konnnichiha"), "こんにちは");
+        assert_eq!(convert("kanji"), "かんじ");
+        assert_eq!(convert("kitte"), "きって");
+        assert_eq!(convert("Kyouto"), "きょうと");
+        assert_eq!(convert("ra-men."), "らーめん。");
+        assert_eq!(convert("2ko"), "2こ");
+        assert_eq!(convert("qa"), "qあ");
+        assert_eq!(convert("sh"), "sh");
+    }
+}
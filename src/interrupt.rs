@@ -1,16 +1,31 @@
-use crate::{emergency_console, println, sync::OnceCell, timer, xhc};
+use crate::{
+    allocator, cpu, emergency_console, gdt, local_apic, mce,
+    prelude::*,
+    println, smp,
+    sync::{OnceCell, SpinMutex},
+    time, timer,
+};
+use alloc::vec::Vec;
 use core::{
     fmt::Write as _,
-    sync::atomic::{AtomicBool, Ordering},
+    ops::RangeInclusive,
+    sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+    time::Duration,
+};
+use x86_64::{
+    instructions::{interrupts, port::PortReadOnly},
+    structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode},
 };
-use volatile::Volatile;
-use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
 
+/// Vectors with fixed handlers. Drivers get other vectors from [`allocate_vectors`].
 #[derive(Debug, Clone, Copy)]
 #[repr(u8)]
 pub(crate) enum InterruptIndex {
-    Xhci = 0x40,
     Timer = 0x41,
+    TlbShootdown = 0xfd,
+    LapicError = 0xfe,
+    /// The reset value of the vector in the LAPIC spurious interrupt vector register.
+    Spurious = 0xff,
 }
 
 impl InterruptIndex {
@@ -18,16 +33,12 @@ impl InterruptIndex {
         self as u8
     }
 
-    pub(crate) fn as_u32(self) -> u32 {
-        u32::from(self.as_u8())
-    }
-
     pub(crate) fn as_usize(self) -> usize {
         usize::from(self.as_u8())
     }
 }
 
-static IDT: OnceCell<InterruptDescriptorTable> = OnceCell::uninit();
+static IDT: OnceCell<SpinMutex<InterruptDescriptorTable>> = OnceCell::uninit();
 
 pub(crate) fn init() {
     IDT.init_once(|| {
@@ -38,25 +49,177 @@ pub(crate) fn init() {
             .set_handler_fn(general_protection_fault_handler);
         idt.segment_not_present
             .set_handler_fn(segment_not_present_handler);
-        idt.double_fault.set_handler_fn(double_fault_handler);
-        idt[InterruptIndex::Xhci.as_usize()].set_handler_fn(xhc::interrupt_handler);
+        unsafe {
+            idt.double_fault
+                .set_handler_fn(double_fault_handler)
+                .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+            // NMI and #MC can interrupt any code, including the entry of other handlers
+            idt.non_maskable_interrupt
+                .set_handler_fn(nmi_handler)
+                .set_stack_index(gdt::NMI_IST_INDEX);
+            idt.machine_check
+                .set_handler_fn(machine_check_handler)
+                .set_stack_index(gdt::MACHINE_CHECK_IST_INDEX);
+        }
         idt[InterruptIndex::Timer.as_usize()].set_handler_fn(timer::lapic::interrupt_handler);
-        idt
+        idt[InterruptIndex::TlbShootdown.as_usize()].set_handler_fn(smp::tlb_shootdown_handler);
+        idt[InterruptIndex::LapicError.as_usize()].set_handler_fn(lapic_error_handler);
+        idt[InterruptIndex::Spurious.as_usize()].set_handler_fn(spurious_interrupt_handler);
+        SpinMutex::new(idt)
+    });
+    reload();
+}
+
+/// Loads IDT on a CPU other than the one which called [`init`], or after the CPU state was lost
+/// (e.g. resume from S3).
+pub(crate) fn reload() {
+    interrupts::without_interrupts(|| {
+        let idt = IDT.get().lock();
+        // the table lives in a static, so it stays valid after the lock is released
+        unsafe { idt.load_unsafe() };
     });
-    IDT.get().load();
 }
 
-static INTERRUPT_CONTEXT: AtomicBool = AtomicBool::new(false);
+pub(crate) type Handler = extern "x86-interrupt" fn(InterruptStackFrame);
+
+/// Vectors handed out by [`allocate_vectors`]. The vectors below are reserved for exceptions and
+/// the legacy PIC, and the vectors above for the fixed handlers of [`InterruptIndex`].
+const DYNAMIC_VECTORS: RangeInclusive<u8> = 0x50..=0xef;
+
+/// Names of the allocated vectors, or `None` if the vector is free.
+static ALLOCATED_VECTORS: SpinMutex<[Option<&'static str>; 256]> = SpinMutex::new([None; 256]);
+
+/// Allocates `1 << count_exponent` contiguous vectors aligned to their count, as multiple message
+/// MSI requires. Returns the first vector, and the vectors are shown as `name` in the statistics.
+///
+/// The vectors have no handler until [`register_handler`] is called, so the device must not be
+/// configured to raise them before that.
+pub(crate) fn allocate_vectors(name: &'static str, count_exponent: u8) -> Result<u8> {
+    let count = 1_u16 << count_exponent;
+    let start = u16::from(*DYNAMIC_VECTORS.start());
+    let end = u16::from(*DYNAMIC_VECTORS.end());
+    let first = (start + count - 1) & !(count - 1);
+    interrupts::without_interrupts(|| {
+        let mut allocated = ALLOCATED_VECTORS.lock();
+        let base = (first..=end + 1 - count)
+            .step_by(usize::from(count))
+            .find(|base| {
+                (*base..*base + count).all(|vector| allocated[usize::from(vector)].is_none())
+            })
+            .ok_or(ErrorKind::NoFreeInterruptVector)?;
+        for vector in base..base + count {
+            allocated[usize::from(vector)] = Some(name);
+        }
+        Ok(base as u8)
+    })
+}
+
+/// Sets the handler of `vector`, which must have been allocated by [`allocate_vectors`].
+///
+/// The IDT is shared by all CPUs, so the handler takes effect on every CPU immediately.
+pub(crate) fn register_handler(vector: u8, handler: Handler) -> Result<()> {
+    interrupts::without_interrupts(|| {
+        if ALLOCATED_VECTORS.lock()[usize::from(vector)].is_none() {
+            bail!(ErrorKind::InvalidInterruptVector(vector));
+        }
+        let _ = IDT.get().lock()[usize::from(vector)].set_handler_fn(handler);
+        Ok(())
+    })
+}
+
+const NMI_VECTOR: u8 = 2;
+const BREAKPOINT_VECTOR: u8 = 3;
+const DOUBLE_FAULT_VECTOR: u8 = 8;
+const SEGMENT_NOT_PRESENT_VECTOR: u8 = 11;
+const GENERAL_PROTECTION_FAULT_VECTOR: u8 = 13;
+const PAGE_FAULT_VECTOR: u8 = 14;
+const MACHINE_CHECK_VECTOR: u8 = 18;
+
+/// Vectors which have handlers, listed by [`stats`] even if they never occurred.
+const NAMED_VECTORS: [(u8, &str); 11] = [
+    (NMI_VECTOR, "nmi"),
+    (BREAKPOINT_VECTOR, "breakpoint"),
+    (DOUBLE_FAULT_VECTOR, "double fault"),
+    (SEGMENT_NOT_PRESENT_VECTOR, "segment not present"),
+    (GENERAL_PROTECTION_FAULT_VECTOR, "general protection"),
+    (PAGE_FAULT_VECTOR, "page fault"),
+    (MACHINE_CHECK_VECTOR, "machine check"),
+    (InterruptIndex::Timer as u8, "timer"),
+    (InterruptIndex::TlbShootdown as u8, "tlb shootdown"),
+    (InterruptIndex::LapicError as u8, "lapic error"),
+    (InterruptIndex::Spurious as u8, "spurious"),
+];
+
+#[allow(clippy::declare_interior_mutable_const)] // used only as an array initializer
+const ZERO: AtomicU64 = AtomicU64::new(0);
+static COUNTS: [AtomicU64; 256] = [ZERO; 256];
+/// Nanoseconds from the clock initialization to the last occurrence of each vector.
+static LAST_SEEN: [AtomicU64; 256] = [ZERO; 256];
+
+/// Records an occurrence of `vector`. This must be called at the beginning of each handler.
+pub(crate) fn record(vector: u8) {
+    let now = time::read_tsc_ns();
+    COUNTS[usize::from(vector)].fetch_add(1, Ordering::Relaxed);
+    LAST_SEEN[usize::from(vector)].store(now, Ordering::Relaxed);
+}
+
+/// Returns the number of interrupts and exceptions since boot.
+pub(crate) fn total_count() -> u64 {
+    COUNTS
+        .iter()
+        .map(|count| count.load(Ordering::Relaxed))
+        .sum()
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct VectorStats {
+    pub(crate) vector: u8,
+    pub(crate) name: &'static str,
+    pub(crate) count: u64,
+    /// Time from the clock initialization to the last occurrence.
+    pub(crate) last_seen: Option<Duration>,
+}
+
+/// Returns the statistics of the vectors which have handlers or have occurred, ordered by vector.
+pub(crate) fn stats() -> Vec<VectorStats> {
+    let allocated = interrupts::without_interrupts(|| *ALLOCATED_VECTORS.lock());
+    (0..=u8::MAX)
+        .filter_map(|vector| {
+            let name = NAMED_VECTORS
+                .iter()
+                .find(|(named, _)| *named == vector)
+                .map(|(_, name)| *name)
+                .or_else(|| allocated[usize::from(vector)]);
+            let count = COUNTS[usize::from(vector)].load(Ordering::Relaxed);
+            if name.is_none() && count == 0 {
+                return None;
+            }
+            let last_seen = (count > 0).then(|| {
+                Duration::from_nanos(LAST_SEEN[usize::from(vector)].load(Ordering::Relaxed))
+            });
+            Some(VectorStats {
+                vector,
+                name: name.unwrap_or("unknown"),
+                count,
+                last_seen,
+            })
+        })
+        .collect()
+}
+
+#[allow(clippy::declare_interior_mutable_const)] // used only as an array initializer
+const NOT_INTERRUPT_CONTEXT: AtomicBool = AtomicBool::new(false);
+static INTERRUPT_CONTEXT: [AtomicBool; cpu::MAX_CPUS] = [NOT_INTERRUPT_CONTEXT; cpu::MAX_CPUS];
 
 pub(crate) fn is_interrupt_context() -> bool {
-    INTERRUPT_CONTEXT.load(Ordering::Relaxed)
+    INTERRUPT_CONTEXT[cpu::current_index()].load(Ordering::Relaxed)
 }
 
 pub(crate) struct InterruptContextGuard {}
 
 impl InterruptContextGuard {
     pub(crate) fn new() -> Self {
-        let old_value = INTERRUPT_CONTEXT.swap(true, Ordering::Relaxed);
+        let old_value = INTERRUPT_CONTEXT[cpu::current_index()].swap(true, Ordering::Relaxed);
         assert!(!old_value);
         Self {}
     }
@@ -64,12 +227,13 @@ impl InterruptContextGuard {
 
 impl Drop for InterruptContextGuard {
     fn drop(&mut self) {
-        let old_value = INTERRUPT_CONTEXT.swap(false, Ordering::Relaxed);
+        let old_value = INTERRUPT_CONTEXT[cpu::current_index()].swap(false, Ordering::Relaxed);
         assert!(old_value);
     }
 }
 
 extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
+    record(BREAKPOINT_VECTOR);
     let _guard = InterruptContextGuard::new();
     println!("EXCEPTION: BREAKPOINT");
     println!("{:#?}", stack_frame);
@@ -79,12 +243,30 @@ extern "x86-interrupt" fn page_fault_handler(
     stack_frame: InterruptStackFrame,
     error_code: PageFaultErrorCode,
 ) {
+    record(PAGE_FAULT_VECTOR);
     use x86_64::registers::control::Cr2;
 
+    let addr = Cr2::read();
+    // Heap pages are mapped lazily. Interrupt handlers must not touch the heap, so faults in
+    // interrupt context are never handled.
+    let mut heap_error = None;
+    if !is_interrupt_context()
+        && !error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION)
+        && allocator::is_heap_address(addr)
+    {
+        match allocator::map_heap_page(addr) {
+            Ok(()) => return,
+            Err(err) => heap_error = Some(err),
+        }
+    }
+
     let _guard = InterruptContextGuard::new();
     emergency_console::with_console(|console| {
         let _ = writeln!(console, "EXCEPTION: PAGE FAULT");
-        let _ = writeln!(console, "Accessed Address: {:?}", Cr2::read());
+        let _ = writeln!(console, "Accessed Address: {:?}", addr);
+        if let Some(err) = &heap_error {
+            let _ = writeln!(console, "Failed to map heap page: {}", err);
+        }
         let _ = writeln!(console, "Error Code: {:x}", error_code);
         let _ = writeln!(console, "{:#?}", stack_frame);
     });
@@ -94,6 +276,7 @@ extern "x86-interrupt" fn general_protection_fault_handler(
     stack_frame: InterruptStackFrame,
     error_code: u64,
 ) {
+    record(GENERAL_PROTECTION_FAULT_VECTOR);
     let _guard = InterruptContextGuard::new();
     emergency_console::with_console(|console| {
         let _ = writeln!(console, "EXCEPTION: GENERAL PROTECTION FAULT");
@@ -106,6 +289,7 @@ extern "x86-interrupt" fn segment_not_present_handler(
     stack_frame: InterruptStackFrame,
     error_code: u64,
 ) {
+    record(SEGMENT_NOT_PRESENT_VECTOR);
     let _guard = InterruptContextGuard::new();
     emergency_console::with_console(|console| {
         let _ = writeln!(console, "EXCEPTION: STACK NOT PRESENT");
@@ -118,6 +302,7 @@ extern "x86-interrupt" fn double_fault_handler(
     stack_frame: InterruptStackFrame,
     error_code: u64,
 ) -> ! {
+    record(DOUBLE_FAULT_VECTOR);
     let _guard = InterruptContextGuard::new();
     emergency_console::with_console(|console| {
         let _ = writeln!(console, "EXCEPTION: DOUBLE FAULT",);
@@ -126,10 +311,70 @@ extern "x86-interrupt" fn double_fault_handler(
     });
 }
 
+/// The system control port B, which tells the source of legacy NMIs.
+const NMI_STATUS_PORT: u16 = 0x61;
+/// PCI SERR# was asserted
+const NMI_STATUS_SERR: u8 = 1 << 7;
+/// I/O channel check (IOCHK#) was asserted
+const NMI_STATUS_IOCHK: u8 = 1 << 6;
+
+/// The kernel never sends NMIs, so an NMI reports a hardware error.
+///
+/// The interrupt context guard is not taken, since an NMI may arrive inside another handler.
+extern "x86-interrupt" fn nmi_handler(stack_frame: InterruptStackFrame) {
+    record(NMI_VECTOR);
+    let status = unsafe { PortReadOnly::<u8>::new(NMI_STATUS_PORT).read() };
+    emergency_console::with_console(|console| {
+        let _ = writeln!(console, "NMI on CPU {}", cpu::current_index());
+        let _ = writeln!(
+            console,
+            "Port 61h: {:02x} (SERR={}, IOCHK={})",
+            status,
+            status & NMI_STATUS_SERR != 0,
+            status & NMI_STATUS_IOCHK != 0
+        );
+        let _ = writeln!(console, "{:#?}", stack_frame);
+    });
+}
+
+extern "x86-interrupt" fn machine_check_handler(stack_frame: InterruptStackFrame) -> ! {
+    record(MACHINE_CHECK_VECTOR);
+    emergency_console::with_console(|console| {
+        let _ = writeln!(
+            console,
+            "EXCEPTION: MACHINE CHECK on CPU {}",
+            cpu::current_index()
+        );
+        let _ = mce::dump(console);
+        let _ = writeln!(console, "{:#?}", stack_frame);
+    });
+}
+
+/// Bits of the LAPIC error status register reported since boot, on any CPU.
+static LAPIC_ERRORS: AtomicU32 = AtomicU32::new(0);
+
+/// Returns the LAPIC errors reported since boot.
+///
+/// Typical causes are vectors below 16 (bit 5 or 6) and illegal register accesses (bit 7).
+pub(crate) fn lapic_errors() -> u32 {
+    LAPIC_ERRORS.load(Ordering::Relaxed)
+}
+
+extern "x86-interrupt" fn lapic_error_handler(_stack_frame: InterruptStackFrame) {
+    record(InterruptIndex::LapicError.as_u8());
+    let _guard = InterruptContextGuard::new();
+    let status = local_apic::registers().error_status();
+    LAPIC_ERRORS.fetch_or(status, Ordering::Relaxed);
+    notify_end_of_interrupt();
+}
+
+/// Spurious interrupts must not be acknowledged with EOI.
+extern "x86-interrupt" fn spurious_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    record(InterruptIndex::Spurious.as_u8());
+}
+
 pub(crate) fn notify_end_of_interrupt() {
     assert!(is_interrupt_context());
 
-    #[allow(clippy::unwrap_used)]
-    let mut memory = Volatile::new(unsafe { (0xfee000b0 as *mut u32).as_mut().unwrap() });
-    memory.write(0);
+    local_apic::registers().end_of_interrupt();
 }
@@ -1,7 +1,10 @@
 use crate::{
+    device::DeviceId,
+    ime::InputMethod,
     layer,
     prelude::*,
     sync::{mpsc, OnceCell},
+    xhc,
 };
 use core::future::Future;
 use enumflags2::{bitflags, BitFlags};
@@ -90,25 +93,51 @@ pub(crate) enum Modifier {
     RGui = 0b10000000,
 }
 
+/// Lock keys, in the order of the bits of the LED output report of the keyboard.
+#[bitflags]
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Lock {
+    Num = 0b001,
+    Caps = 0b010,
+    Scroll = 0b100,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 struct RawKeyboardEvent {
     modifier: BitFlags<Modifier>,
     keycode: u8,
+    press: bool,
+    locks: BitFlags<Lock>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub(crate) struct KeyboardEvent {
     pub(crate) modifier: BitFlags<Modifier>,
     pub(crate) keycode: u8,
+    /// The character typed, which is not ASCII if it is composed by the input method.
     pub(crate) ascii: char,
+    /// `true` if the key is pressed, `false` if released.
+    pub(crate) press: bool,
+    pub(crate) locks: BitFlags<Lock>,
 }
 
 static KEYBOARD_EVENT_TX: OnceCell<mpsc::Sender<RawKeyboardEvent>> = OnceCell::uninit();
+static DEVICE_ID: OnceCell<DeviceId> = OnceCell::uninit();
 
-pub(crate) extern "C" fn observer(modifier: u8, keycode: u8) {
+pub(crate) fn observer(modifier: u8, keycode: u8, press: bool, leds: u8) {
     let modifier = BitFlags::<Modifier>::from_bits_truncate(modifier);
-    let event = RawKeyboardEvent { modifier, keycode };
-    let res = KEYBOARD_EVENT_TX.try_get().and_then(|tx| tx.send(event));
+    let locks = BitFlags::<Lock>::from_bits_truncate(leds);
+    let event = RawKeyboardEvent {
+        modifier,
+        keycode,
+        press,
+        locks,
+    };
+    DEVICE_ID.get_or_init(|| xhc::register_usb_device("USB keyboard", "usb-hid-keyboard"));
+    let res = KEYBOARD_EVENT_TX
+        .try_get()
+        .and_then(|tx| tx.try_send(event));
 
     if let Err(err) = res {
         error!("failed to enqueue to the queue: {}", err);
@@ -117,17 +146,23 @@ pub(crate) extern "C" fn observer(modifier: u8, keycode: u8) {
 
 pub(crate) fn handler_task() -> impl Future<Output = Result<()>> {
     // Initialize KEYBOARD_EVENT_TX before co-task starts
-    let (tx, mut rx) = mpsc::channel(100);
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    tx.register("keyboard");
     KEYBOARD_EVENT_TX.init_once(|| tx);
 
     async move {
         let tx = layer::event_tx();
+        let mut ime = InputMethod::new();
 
         while let Some(event) = rx.next().await {
-            let ascii = if event
+            let mut shift = event
                 .modifier
-                .intersects(Modifier::LShift | Modifier::RShift)
-            {
+                .intersects(Modifier::LShift | Modifier::RShift);
+            let is_letter = KEYCODE_MAP[usize::from(event.keycode)].is_ascii_lowercase();
+            if is_letter && event.locks.contains(Lock::Caps) {
+                shift = !shift;
+            }
+            let ascii = if shift {
                 KEYCODE_MAP_SHIFT[usize::from(event.keycode)]
             } else {
                 KEYCODE_MAP[usize::from(event.keycode)]
@@ -136,8 +171,12 @@ pub(crate) fn handler_task() -> impl Future<Output = Result<()>> {
                 modifier: event.modifier,
                 keycode: event.keycode,
                 ascii,
+                press: event.press,
+                locks: event.locks,
             };
-            tx.keyboard_event(event).await?;
+            for event in ime.process(event) {
+                tx.keyboard_event(event).await?;
+            }
         }
         Ok(())
     }
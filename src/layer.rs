@@ -1,22 +1,32 @@
+use self::{damage::Damage, drag::Drag, snap::Snap};
 use crate::{
+    desktop,
     graphics::{
-        frame_buffer, Buffer, BufferDrawer, Color, Draw, FrameBufferDrawer, Offset, Point,
+        font, frame_buffer, Buffer, BufferDrawer, Color, Draw, FrameBufferDrawer, Offset, Point,
         Rectangle, ScreenInfo, ShadowBuffer, Size,
     },
-    keyboard::KeyboardEvent,
+    keyboard::{KeyboardEvent, Modifier},
     mouse::{MouseButton, MouseEvent},
     prelude::*,
-    sync::{mpsc, oneshot, OnceCell, SpinMutexGuard},
+    sync::{mpsc, oneshot, watch, OnceCell, SpinMutex, SpinMutexGuard},
     triple_buffer::Consumer,
+    watchdog::{self, Heartbeat},
     window::WindowEvent,
 };
-use alloc::{collections::BTreeMap, vec, vec::Vec};
+use alloc::{collections::BTreeMap, string::String, vec, vec::Vec};
+use bootloader::boot_info::PixelFormat;
 use core::{
-    future::Future,
     sync::atomic::{AtomicU32, Ordering},
+    time::Duration,
 };
 use custom_debug_derive::Debug as CustomDebug;
 use derivative::Derivative;
+use enumflags2::BitFlags;
+use x86_64::instructions::interrupts;
+
+mod damage;
+mod drag;
+mod snap;
 
 pub(crate) const DESKTOP_HEIGHT: usize = 0;
 pub(crate) const CONSOLE_HEIGHT: usize = 1;
@@ -24,6 +34,48 @@ pub(crate) const CONSOLE_HEIGHT: usize = 1;
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub(crate) struct LayerId(u32);
 
+/// The bands of the layer stack from the bottom. The heights of layers are limited to the range
+/// of their bands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Band {
+    Normal,
+    AlwaysOnTop,
+    /// The band of the mouse cursor, which is above all other layers.
+    Cursor,
+}
+
+/// Returns `true` if `event` is the hotkey minimizing the active window (Gui+Down).
+fn is_minimize_hotkey(event: &KeyboardEvent) -> bool {
+    event.press
+        && event.modifier.intersects(Modifier::LGui | Modifier::RGui)
+        && event.keycode == 0x51 // down arrow
+}
+
+/// Returns the request to copy or paste by Ctrl+C or Ctrl+V.
+fn clipboard_request(event: &KeyboardEvent) -> Option<WindowEvent> {
+    let others = Modifier::LAlt | Modifier::RAlt | Modifier::LGui | Modifier::RGui;
+    if !event.press
+        || !event
+            .modifier
+            .intersects(Modifier::LControl | Modifier::RControl)
+        || event.modifier.intersects(others)
+    {
+        return None;
+    }
+    match event.keycode {
+        0x06 => Some(WindowEvent::Copy),  // c
+        0x19 => Some(WindowEvent::Paste), // v
+        _ => None,
+    }
+}
+
+/// Returns `true` if `event` is the hotkey closing the active window (Alt+F4).
+fn is_close_hotkey(event: &KeyboardEvent) -> bool {
+    event.press
+        && event.modifier.intersects(Modifier::LAlt | Modifier::RAlt)
+        && event.keycode == 0x3d // F4
+}
+
 impl LayerId {
     fn new() -> Self {
         static NEXT_ID: AtomicU32 = AtomicU32::new(0);
@@ -35,7 +87,9 @@ impl LayerId {
 #[derivative(Clone(clone_from = "true"))]
 #[derive(CustomDebug)]
 pub(crate) struct LayerBuffer {
-    transparent_color: Option<Color>,
+    /// The alpha value of each pixel, stored as a grayscale buffer.
+    #[debug(skip)]
+    alpha: Option<ShadowBuffer>,
     #[debug(skip)]
     buffer: ShadowBuffer,
 }
@@ -45,25 +99,102 @@ impl Draw for LayerBuffer {
         self.buffer.size()
     }
 
+    /// Draws `c` at `p`.
+    ///
+    /// If the layer has per-pixel alpha, the color replaces the pixel including its alpha value.
+    /// Otherwise, a translucent color is blended with the pixel.
     fn draw(&mut self, p: Point<i32>, c: Color) {
-        self.buffer.draw(p, c)
+        match &mut self.alpha {
+            Some(alpha) => {
+                alpha.draw(p, Color::from_grayscale(c.a));
+                self.buffer.draw(p, c.with_alpha(255));
+            }
+            None => self.buffer.draw(p, c),
+        }
+    }
+
+    fn fill_rect(&mut self, rect: Rectangle<i32>, c: Color) {
+        match &mut self.alpha {
+            Some(alpha) => {
+                alpha.fill_rect(rect, Color::from_grayscale(c.a));
+                self.buffer.fill_rect(rect, c.with_alpha(255));
+            }
+            None => self.buffer.fill_rect(rect, c),
+        }
     }
 
     fn move_area(&mut self, offset: Point<i32>, src: Rectangle<i32>) {
+        if let Some(alpha) = &mut self.alpha {
+            alpha.move_area(offset, src);
+        }
         self.buffer.move_area(offset, src)
     }
+
+    fn clip_area(&self) -> Rectangle<i32> {
+        self.buffer.clip_area()
+    }
+
+    fn push_clip(&mut self, rect: Rectangle<i32>) {
+        if let Some(alpha) = &mut self.alpha {
+            alpha.push_clip(rect);
+        }
+        self.buffer.push_clip(rect)
+    }
+
+    fn pop_clip(&mut self) {
+        if let Some(alpha) = &mut self.alpha {
+            alpha.pop_clip();
+        }
+        self.buffer.pop_clip()
+    }
+
+    fn draw_char_bg(&mut self, pos: Point<i32>, ch: char, fg: Color, bg: Color) -> Rectangle<i32> {
+        match &mut self.alpha {
+            Some(alpha) if fg.is_opaque() && bg.is_opaque() => {
+                alpha.fill_rect(
+                    Rectangle::new(pos, font::char_size(ch)),
+                    Color::from_grayscale(255),
+                );
+                self.buffer.draw_char_bg(pos, ch, fg, bg)
+            }
+            Some(_) => {
+                self.fill_rect(Rectangle::new(pos, font::char_size(ch)), bg);
+                self.draw_char(pos, ch, fg)
+            }
+            None => self.buffer.draw_char_bg(pos, ch, fg, bg),
+        }
+    }
 }
 
 impl LayerBuffer {
     pub(crate) fn new(size: Size<i32>, screen_info: ScreenInfo) -> Result<Self> {
         Ok(Self {
-            transparent_color: None,
+            alpha: None,
             buffer: ShadowBuffer::new_shadow(size, screen_info)?,
         })
     }
 
-    pub(crate) fn set_transparent_color(&mut self, tc: Option<Color>) {
-        self.transparent_color = tc;
+    /// Enables per-pixel alpha.
+    ///
+    /// All pixels are initially transparent.
+    pub(crate) fn enable_alpha(&mut self) -> Result<()> {
+        let info = ScreenInfo {
+            size: self.size(),
+            bytes_per_pixel: 1,
+            pixel_format: PixelFormat::U8,
+        };
+        // a new buffer is filled with zeros, which is fully transparent
+        self.alpha = Some(ShadowBuffer::new_shadow(self.size(), info)?);
+        Ok(())
+    }
+
+    /// Creates a buffer of `size` in the same pixel format, whose pixels are initially cleared.
+    pub(crate) fn resized(&self, size: Size<i32>) -> Result<Self> {
+        let mut buffer = Self::new(size, self.buffer.info())?;
+        if self.alpha.is_some() {
+            buffer.enable_alpha()?;
+        }
+        Ok(buffer)
     }
 
     fn draw_to<B>(
@@ -75,14 +206,8 @@ impl LayerBuffer {
         B: Buffer,
     {
         if let Some(src_area) = src_area & self.buffer.area() {
-            if let Some(tc) = self.transparent_color {
-                for p in src_area.points() {
-                    if let Some(c) = self.buffer.color_at(p) {
-                        if tc != c {
-                            drawer.draw(p + src_dst_offset, c);
-                        }
-                    }
-                }
+            if let Some(alpha) = &self.alpha {
+                drawer.blend(src_dst_offset, &self.buffer, alpha, src_area);
             } else {
                 drawer.copy(src_dst_offset, &self.buffer, src_area);
             }
@@ -95,6 +220,18 @@ pub(crate) struct Layer {
     id: LayerId,
     pos: Point<i32>,
     draggable: bool,
+    drag_area: Option<Rectangle<i32>>,
+    /// `true` if the layer receives clicks without being draggable, like the taskbar.
+    clickable: bool,
+    /// The title of the window listed in the taskbar.
+    title: Option<String>,
+    /// `true` if the window redraws itself in the size requested by [`WindowEvent::Resize`].
+    resizable: bool,
+    /// `true` if the layer is removed from the layer stack until it is restored.
+    minimized: bool,
+    always_on_top: bool,
+    /// The window whose input is blocked while this modal dialog is open.
+    owner: Option<LayerId>,
     consumer: Consumer<LayerBuffer>,
     tx: mpsc::Sender<WindowEvent>,
 }
@@ -105,6 +242,13 @@ impl Layer {
             id: LayerId::new(),
             pos: Point::new(0, 0),
             draggable: false,
+            drag_area: None,
+            clickable: false,
+            title: None,
+            resizable: false,
+            minimized: false,
+            always_on_top: false,
+            owner: None,
             consumer,
             tx,
         }
@@ -118,6 +262,31 @@ impl Layer {
         self.draggable = draggable;
     }
 
+    pub(crate) fn set_drag_area(&mut self, area: Rectangle<i32>) {
+        self.drag_area = Some(area);
+    }
+
+    pub(crate) fn set_clickable(&mut self, clickable: bool) {
+        self.clickable = clickable;
+    }
+
+    pub(crate) fn set_title(&mut self, title: String) {
+        self.title = Some(title);
+    }
+
+    pub(crate) fn set_resizable(&mut self, resizable: bool) {
+        self.resizable = resizable;
+    }
+
+    pub(crate) fn set_always_on_top(&mut self, always_on_top: bool) {
+        self.always_on_top = always_on_top;
+    }
+
+    /// Makes the layer a modal dialog of `owner`, which is centered over the owner.
+    pub(crate) fn set_owner(&mut self, owner: LayerId) {
+        self.owner = Some(owner);
+    }
+
     pub(crate) fn move_to(&mut self, pos: Point<i32>) {
         self.pos = pos;
     }
@@ -132,6 +301,14 @@ impl Layer {
         Rectangle { pos, size }
     }
 
+    fn is_drag_point(&self, pos: Point<i32>) -> bool {
+        self.draggable
+            && self
+                .drag_area
+                .map(|area| area.contains(&(pos - self.pos)))
+                .unwrap_or(true)
+    }
+
     fn draw_to<B>(&self, drawer: &mut BufferDrawer<B>, dst_area: Rectangle<i32>)
     where
         B: Buffer,
@@ -145,15 +322,19 @@ impl Layer {
     }
 
     fn send_event(&self, event: WindowEvent) -> Result<()> {
-        self.tx.send(event)
+        self.tx.try_send(event)
     }
 }
 
 struct LayerManager {
     layers: BTreeMap<LayerId, Layer>,
+    /// The layers from the bottom, which are sorted by their bands.
     layer_stack: Vec<LayerId>,
+    cursor_layer: Option<LayerId>,
     frame_buffer: SpinMutexGuard<'static, FrameBufferDrawer>,
     back_buffer: ShadowBuffer,
+    /// The areas of the screen to be composited by [`Self::flush`].
+    damage: Damage,
 }
 
 impl LayerManager {
@@ -163,46 +344,153 @@ impl LayerManager {
         Ok(Self {
             layers: BTreeMap::new(),
             layer_stack: vec![],
+            cursor_layer: None,
             frame_buffer,
             back_buffer,
+            damage: Damage::new(),
         })
     }
 
-    fn register(&mut self, layer: Layer) {
+    fn register(&mut self, mut layer: Layer) {
+        if let Some(owner) = layer.owner.and_then(|owner| self.layers.get(&owner)) {
+            let owner_area = owner.area();
+            let size = layer.area().size;
+            let x = owner_area.x_start() + (owner_area.size.x - size.x) / 2;
+            let y = owner_area.y_start() + (owner_area.size.y - size.y) / 2;
+            layer.pos = Point::new(x.max(0), y.max(0));
+        }
         let id = layer.id;
         self.layers.insert(id, layer);
     }
 
-    fn draw_area(&mut self, dst_area: Rectangle<i32>) {
-        if let Some(dst_area) = dst_area & self.frame_buffer.area() {
-            // destructure `self` to avoid borrow checker errors
-            let Self {
-                layers,
-                layer_stack,
-                back_buffer,
-                ..
-            } = self;
+    /// Returns the modal dialog open over the layer, or the layer itself if it has none.
+    fn modal_dialog(&self, mut id: LayerId) -> LayerId {
+        while let Some(dialog) = self.layers.values().find(|layer| layer.owner == Some(id)) {
+            id = dialog.id;
+        }
+        id
+    }
 
-            let layers = layer_stack.iter().filter_map(|id| layers.get(id));
-            for layer in layers {
-                layer.draw_to(back_buffer, dst_area);
-            }
+    /// Returns `true` if the input to the layer is blocked by its modal dialog.
+    fn is_blocked(&self, id: LayerId) -> bool {
+        self.modal_dialog(id) != id
+    }
+
+    /// Puts the layer and the windows it blocks as a modal dialog on the top, showing them if
+    /// they are hidden.
+    fn raise_with_owners(&mut self, id: LayerId) {
+        let mut layers = vec![id];
+        while let Some(owner) = layers
+            .last()
+            .and_then(|id| self.layers.get(id))
+            .and_then(|layer| layer.owner)
+        {
+            layers.push(owner);
+        }
+        for id in layers.into_iter().rev() {
+            self.show(id);
+            self.raise(id);
         }
+    }
 
-        self.finish_draw(dst_area);
+    /// Removes the layer from the layer stack, keeping its buffer and task, and repaints the
+    /// area it covered.
+    fn hide(&mut self, id: LayerId) {
+        let area = match self.layers.get(&id) {
+            Some(layer) => layer.area(),
+            None => return,
+        };
+        if self.layer_height(id).is_some() {
+            self.layer_stack.retain(|elem| *elem != id);
+            self.damage_area(area);
+        }
     }
 
-    fn draw_layer(&mut self, layer_id: LayerId, layer_area: Option<Rectangle<i32>>) {
-        (|| {
-            let target_layer = self.layers.get_mut(&layer_id)?;
-            target_layer.load();
+    /// Puts the hidden or minimized layer back to the top of its band, and returns `false` if
+    /// the layer is not registered.
+    fn show(&mut self, id: LayerId) -> bool {
+        match self.layers.get_mut(&id) {
+            Some(layer) => layer.minimized = false,
+            None => return false,
+        }
+        if self.layer_height(id).is_none() {
+            self.raise(id);
+        }
+        true
+    }
 
-            let dst_area = match layer_area {
-                Some(layer_area) => (target_layer.area() & (layer_area + target_layer.pos))?,
-                None => target_layer.area(),
-            };
-            let dst_area = (dst_area & self.frame_buffer.area())?;
+    fn minimize(&mut self, id: LayerId) {
+        if let Some(layer) = self.layers.get_mut(&id) {
+            layer.minimized = true;
+        }
+        self.hide(id);
+    }
+
+    /// Returns the items of the windows, except those hidden by their tasks.
+    fn taskbar_items(&self, active_layer: Option<LayerId>) -> Vec<TaskbarItem> {
+        self.layers
+            .values()
+            .filter(|layer| layer.minimized || self.layer_height(layer.id).is_some())
+            .filter_map(|layer| {
+                Some(TaskbarItem {
+                    layer_id: layer.id,
+                    title: layer.title.clone()?,
+                    active: active_layer == Some(layer.id),
+                    minimized: layer.minimized,
+                })
+            })
+            .collect()
+    }
+
+    fn unregister(&mut self, id: LayerId) {
+        if self.cursor_layer == Some(id) {
+            self.cursor_layer = None;
+        }
+        self.layer_stack.retain(|elem| *elem != id);
+        if let Some(layer) = self.layers.remove(&id) {
+            self.damage_area(layer.area());
+        }
+    }
+
+    /// Marks `area` of the screen to be composited at the end of the frame.
+    fn damage_area(&mut self, area: Rectangle<i32>) {
+        if let Some(area) = area & self.frame_buffer.area() {
+            self.damage.add(area);
+        }
+    }
 
+    /// Loads the latest buffer of the layer, and marks `layer_area` of it (or the whole layer)
+    /// to be composited.
+    fn draw_layer(&mut self, layer_id: LayerId, layer_area: Option<Rectangle<i32>>) {
+        let (old_area, area) = match self.layers.get_mut(&layer_id) {
+            Some(layer) => {
+                let old_area = layer.area();
+                layer.load();
+                (old_area, layer.area())
+            }
+            None => return,
+        };
+        let dst_area = if area.size != old_area.size {
+            // the buffer of the resized layer is drawn entirely, and uncovers the old area
+            self.damage_area(old_area);
+            Some(area)
+        } else {
+            match layer_area {
+                Some(layer_area) => area & (layer_area + area.pos),
+                None => Some(area),
+            }
+        };
+        if let Some(dst_area) = dst_area {
+            self.damage_area(dst_area);
+        }
+    }
+
+    /// Composites the damaged areas, and copies them to the frame buffer.
+    ///
+    /// All layers are drawn from the bottom, so that translucent layers are not blended over
+    /// their previous contents.
+    fn flush(&mut self) {
+        for dst_area in self.damage.take() {
             // destructure `self` to avoid borrow checker errors
             let Self {
                 layers,
@@ -211,65 +499,153 @@ impl LayerManager {
                 ..
             } = self;
 
-            let layers = layer_stack
-                .iter()
-                .skip_while(|id| **id != layer_id)
-                .filter_map(|id| layers.get(id));
+            let layers = layer_stack.iter().filter_map(|id| layers.get(id));
             for layer in layers {
                 layer.draw_to(back_buffer, dst_area);
             }
 
-            self.finish_draw(dst_area);
-
-            Some(())
-        })();
-    }
-
-    fn finish_draw(&mut self, area: Rectangle<i32>) {
-        self.frame_buffer
-            .copy(Offset::new(0, 0), &self.back_buffer, area);
+            self.frame_buffer
+                .copy(Offset::new(0, 0), &self.back_buffer, dst_area);
+        }
     }
 
     fn move_to(&mut self, id: LayerId, pos: Point<i32>) {
         if let Some(layer) = self.layers.get_mut(&id) {
-            let layer_id = layer.id();
             let old_area = layer.area();
             layer.move_to(pos);
-            self.draw_area(old_area);
-            self.draw_layer(layer_id, None);
+            let new_area = layer.area();
+            self.damage_area(old_area);
+            self.damage_area(new_area);
         }
     }
 
-    fn move_relative(&mut self, id: LayerId, offset: Offset<i32>) {
-        if let Some(layer) = self.layers.get_mut(&id) {
-            let layer_id = layer.id();
-            let old_area = layer.area();
-            layer.move_to(layer.pos + offset);
-            self.draw_area(old_area);
-            self.draw_layer(layer_id, None)
+    /// Moves the dragged window by `offset`, snapping it to the edges of the work area and the
+    /// other windows.
+    fn drag_by(&mut self, drag: &mut Drag, offset: Offset<i32>) {
+        drag.raw_pos += offset;
+        let layer = match self.layers.get(&drag.layer_id) {
+            Some(layer) => layer,
+            None => return,
+        };
+        let area = Rectangle::new(drag.raw_pos, layer.area().size);
+        let drag_area = layer
+            .drag_area
+            .unwrap_or_else(|| Rectangle::new(Point::new(0, 0), area.size));
+        let work_area = desktop::work_area(self.frame_buffer.size());
+        let others = self
+            .layer_stack
+            .iter()
+            .filter(|id| **id != drag.layer_id)
+            .filter_map(|id| self.layers.get(id))
+            .filter(|layer| layer.draggable)
+            .map(|layer| layer.area())
+            .collect::<Vec<_>>();
+        let pos = drag::constrain(area, drag_area, work_area, &others);
+        self.move_to(drag.layer_id, pos);
+    }
+
+    /// Moves the window to the area of the snap layout, and requests the window to resize
+    /// itself to fill it. Windows which cannot be resized are not snapped.
+    fn snap(&mut self, id: LayerId, snap: Snap) {
+        let area = snap.area(desktop::work_area(self.frame_buffer.size()));
+        match self.layers.get(&id) {
+            Some(layer) if layer.resizable => {
+                if let Err(err) = layer.send_event(WindowEvent::Resize(area.size)) {
+                    warn!("failed to request resizing: {}", err);
+                    return;
+                }
+            }
+            _ => return,
         }
+        self.move_to(id, area.pos);
     }
 
-    fn height(&self) -> usize {
-        self.layer_stack.len()
+    fn set_drag_area(&mut self, id: LayerId, area: Rectangle<i32>) {
+        if let Some(layer) = self.layers.get_mut(&id) {
+            layer.set_drag_area(area);
+        }
     }
 
     fn layer_height(&self, id: LayerId) -> Option<usize> {
         self.layer_stack.iter().position(|elem| *elem == id)
     }
 
-    fn set_layer_height(&mut self, id: LayerId, height: usize) {
-        if !self.layers.contains_key(&id) {
-            return;
+    fn band(&self, id: LayerId) -> Band {
+        if self.cursor_layer == Some(id) {
+            Band::Cursor
+        } else if self
+            .layers
+            .get(&id)
+            .map_or(false, |layer| layer.always_on_top)
+        {
+            Band::AlwaysOnTop
+        } else {
+            Band::Normal
         }
+    }
+
+    /// Moves the layer to `height`, which is limited to the range of the band of the layer.
+    fn set_layer_height(&mut self, id: LayerId, height: usize) {
+        let area = match self.layers.get(&id) {
+            Some(layer) => layer.area(),
+            None => return,
+        };
+        let old_height = self.layer_height(id);
         self.layer_stack.retain(|elem| *elem != id);
-        let height = usize::min(height, self.layer_stack.len());
+        let band = self.band(id);
+        let band_start = self
+            .layer_stack
+            .iter()
+            .take_while(|elem| self.band(**elem) < band)
+            .count();
+        let band_end = self
+            .layer_stack
+            .iter()
+            .take_while(|elem| self.band(**elem) <= band)
+            .count();
+        let height = height.clamp(band_start, band_end);
         self.layer_stack.insert(height, id);
+        if old_height != Some(height) {
+            self.damage_area(area);
+        }
     }
 
-    // fn hide(&mut self, id: LayerId) {
-    //     self.layer_stack.retain(|elem| *elem != id);
-    // }
+    fn set_cursor_layer(&mut self, id: LayerId) {
+        if self.cursor_layer != Some(id) {
+            self.cursor_layer = Some(id);
+            self.set_layer_height(id, usize::MAX);
+        }
+    }
+
+    /// Moves the layer to the top of its band.
+    fn raise(&mut self, id: LayerId) {
+        self.set_layer_height(id, usize::MAX);
+    }
+
+    /// Moves the layer below the other draggable layers of its band, which keeps windows above
+    /// the desktop and the console.
+    fn lower(&mut self, id: LayerId) {
+        let band = self.band(id);
+        let height = self
+            .layer_stack
+            .iter()
+            .filter(|elem| **elem != id)
+            .position(|elem| {
+                self.band(*elem) == band
+                    && self.layers.get(elem).map_or(false, |layer| layer.draggable)
+            });
+        self.set_layer_height(id, height.unwrap_or(usize::MAX));
+    }
+
+    fn set_always_on_top(&mut self, id: LayerId, always_on_top: bool) {
+        if let Some(layer) = self.layers.get_mut(&id) {
+            layer.always_on_top = always_on_top;
+            // the stack is sorted again
+            if self.layer_height(id).is_some() {
+                self.raise(id);
+            }
+        }
+    }
 
     fn layers_by_pos(&self, pos: Point<i32>) -> impl Iterator<Item = &Layer> {
         self.layer_stack
@@ -297,18 +673,63 @@ impl LayerManager {
         Ok(())
     }
 
+    /// Sends a request of the user, like closing the window or pasting.
+    fn notify_request(&self, layer_id: LayerId, request: WindowEvent) -> Result<()> {
+        if let Some(layer) = self.layers.get(&layer_id) {
+            layer.send_event(request)?;
+        }
+        Ok(())
+    }
+
     fn notify_keyboard_event(&self, layer_id: LayerId, event: KeyboardEvent) -> Result<()> {
         if let Some(layer) = self.layers.get(&layer_id) {
             layer.send_event(WindowEvent::Keyboard(event))?;
         }
         Ok(())
     }
+
+    fn notify_mouse_event(&self, layer_id: LayerId, event: MouseEvent) -> Result<()> {
+        if let Some(layer) = self.layers.get(&layer_id) {
+            let event = MouseEvent {
+                pos: event.pos - layer.pos,
+                ..event
+            };
+            layer.send_event(WindowEvent::Mouse(event))?;
+        }
+        Ok(())
+    }
+}
+
+/// A window listed in the taskbar.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct TaskbarItem {
+    pub(crate) layer_id: LayerId,
+    pub(crate) title: String,
+    pub(crate) active: bool,
+    pub(crate) minimized: bool,
+}
+
+/// Sends the items of the taskbar when they are changed.
+#[derive(Debug)]
+struct TaskbarWatcher {
+    tx: watch::Sender<Vec<TaskbarItem>>,
+    sent: Vec<TaskbarItem>,
+}
+
+impl TaskbarWatcher {
+    fn update(&mut self, items: Vec<TaskbarItem>) {
+        if self.sent == items {
+            return;
+        }
+        // the compositor does not wait for the taskbar, which only sees the latest items
+        self.tx.send(items.clone());
+        self.sent = items;
+    }
 }
 
 #[derive(Debug, Default)]
 struct ActiveLayer {
     active_layer: Option<LayerId>,
-    mouse_layer: Option<LayerId>,
 }
 
 impl ActiveLayer {
@@ -320,20 +741,9 @@ impl ActiveLayer {
         self.active_layer
     }
 
-    fn set_mouse_layer(&mut self, layer_manager: &mut LayerManager, layer_id: Option<LayerId>) {
-        self.mouse_layer = layer_id;
-        if let Some(layer_id) = self.mouse_layer {
-            let height = layer_manager.height();
-            layer_manager.set_layer_height(layer_id, height);
-        }
-        if let Some(layer_id) = self.active_layer {
-            let height = self.active_height(layer_manager);
-            layer_manager.set_layer_height(layer_id, height);
-            layer_manager.draw_layer(layer_id, None);
-        }
-    }
-
     fn activate(&mut self, layer_manager: &mut LayerManager, layer_id: Option<LayerId>) {
+        // a window blocked by a modal dialog activates the dialog instead
+        let layer_id = layer_id.map(|id| layer_manager.modal_dialog(id));
         if self.active_layer == layer_id {
             return;
         }
@@ -348,8 +758,7 @@ impl ActiveLayer {
         self.active_layer = layer_id;
 
         if let Some(layer_id) = self.active_layer {
-            let height = self.active_height(layer_manager);
-            layer_manager.set_layer_height(layer_id, height);
+            layer_manager.raise_with_owners(layer_id);
             if let Err(err) = layer_manager.notify_activated(layer_id) {
                 warn!("failed to notify_activated: {}", err);
             }
@@ -357,11 +766,18 @@ impl ActiveLayer {
         }
     }
 
-    fn active_height(&self, layer_manager: &mut LayerManager) -> usize {
-        self.mouse_layer
-            .and_then(|layer_id| layer_manager.layer_height(layer_id))
-            .map(|mouse_height| mouse_height - 1)
-            .unwrap_or_else(|| layer_manager.height())
+    /// Deactivates the layer if it is active.
+    fn deactivate(&mut self, layer_manager: &mut LayerManager, layer_id: LayerId) {
+        if self.active_layer == Some(layer_id) {
+            self.activate(layer_manager, None);
+        }
+    }
+
+    /// Forgets the layer unregistered.
+    fn remove(&mut self, layer_id: LayerId) {
+        if self.active_layer == Some(layer_id) {
+            self.active_layer = None;
+        }
     }
 }
 
@@ -370,6 +786,13 @@ enum LayerEvent {
     Register {
         layer: Layer,
     },
+    Unregister {
+        layer_id: LayerId,
+    },
+    WatchTaskbar {
+        tx: watch::Sender<Vec<TaskbarItem>>,
+    },
+    RedrawAll,
     DrawLayer {
         layer_id: LayerId,
         layer_area: Rectangle<i32>,
@@ -384,9 +807,32 @@ enum LayerEvent {
         layer_id: LayerId,
         height: usize,
     },
-    // Hide {
-    //     layer_id: LayerId,
-    // },
+    Raise {
+        layer_id: LayerId,
+    },
+    Lower {
+        layer_id: LayerId,
+    },
+    SetAlwaysOnTop {
+        layer_id: LayerId,
+        always_on_top: bool,
+    },
+    SetDragArea {
+        layer_id: LayerId,
+        area: Rectangle<i32>,
+    },
+    Minimize {
+        layer_id: LayerId,
+    },
+    Restore {
+        layer_id: LayerId,
+    },
+    Hide {
+        layer_id: LayerId,
+    },
+    Show {
+        layer_id: LayerId,
+    },
     MouseEvent {
         cursor_layer_id: LayerId,
         event: MouseEvent,
@@ -398,12 +844,31 @@ enum LayerEvent {
     },
 }
 
-static LAYER_EVENT_TX: OnceCell<mpsc::Sender<LayerEvent>> = OnceCell::uninit();
+type EventChannel = (
+    mpsc::Sender<LayerEvent>,
+    SpinMutex<Option<mpsc::Receiver<LayerEvent>>>,
+);
+
+/// Created by the first sender. The receiver is taken by [`handler_task`], which waits until then.
+static EVENT_CHANNEL: OnceCell<EventChannel> = OnceCell::uninit();
+static HEARTBEAT: Heartbeat = Heartbeat::new("layer");
+
+/// Time the compositor may take to handle events after it is woken, about a frame at 60 fps.
+pub(crate) const FRAME_DEADLINE: Duration = Duration::from_millis(16);
+
+fn event_channel() -> &'static EventChannel {
+    EVENT_CHANNEL.get_or_init(|| {
+        // async senders wait for the compositor, and the rest (registering layers and changing
+        // heights) are rare
+        let (tx, rx) = mpsc::channel(100);
+        tx.register("layer");
+        (tx, SpinMutex::new(Some(rx)))
+    })
+}
 
-#[track_caller]
 pub(crate) fn event_tx() -> EventSender {
     EventSender {
-        tx: LAYER_EVENT_TX.get().clone(),
+        tx: event_channel().0.clone(),
     }
 }
 
@@ -413,12 +878,34 @@ pub(crate) struct EventSender {
 }
 
 impl EventSender {
-    fn send(&self, event: LayerEvent) -> Result<()> {
-        self.tx.send(event)
+    fn try_send(&self, event: LayerEvent) -> Result<()> {
+        self.tx.try_send(event)?;
+        HEARTBEAT.expect();
+        Ok(())
+    }
+
+    /// Sends `event`, waiting while the compositor is behind.
+    async fn send(&self, event: LayerEvent) {
+        self.tx.send_async(event).await;
+        HEARTBEAT.expect();
     }
 
     pub(crate) fn register(&self, layer: Layer) -> Result<()> {
-        self.send(LayerEvent::Register { layer })
+        self.try_send(LayerEvent::Register { layer })
+    }
+
+    pub(crate) fn unregister(&self, layer_id: LayerId) -> Result<()> {
+        self.try_send(LayerEvent::Unregister { layer_id })
+    }
+
+    /// Requests all windows to redraw themselves.
+    pub(crate) fn redraw_all(&self) -> Result<()> {
+        self.try_send(LayerEvent::RedrawAll)
+    }
+
+    /// Sends the items of the taskbar to `tx` whenever they are changed.
+    pub(crate) fn watch_taskbar(&self, tx: watch::Sender<Vec<TaskbarItem>>) -> Result<()> {
+        self.try_send(LayerEvent::WatchTaskbar { tx })
     }
 
     pub(crate) async fn draw_layer(
@@ -431,25 +918,65 @@ impl EventSender {
             layer_id,
             layer_area,
             tx,
-        })?;
-        rx.await;
+        })
+        .await;
+        rx.await?;
         Ok(())
     }
 
     pub(crate) async fn move_to(&self, layer_id: LayerId, pos: Point<i32>) -> Result<()> {
         let (tx, rx) = oneshot::channel();
-        self.send(LayerEvent::MoveTo { layer_id, pos, tx })?;
-        rx.await;
+        self.send(LayerEvent::MoveTo { layer_id, pos, tx }).await;
+        rx.await?;
         Ok(())
     }
 
     pub(crate) fn set_height(&self, layer_id: LayerId, height: usize) -> Result<()> {
-        self.send(LayerEvent::SetHeight { layer_id, height })
+        self.try_send(LayerEvent::SetHeight { layer_id, height })
+    }
+
+    /// Moves the layer to the top of its band.
+    pub(crate) fn raise(&self, layer_id: LayerId) -> Result<()> {
+        self.try_send(LayerEvent::Raise { layer_id })
     }
 
-    // pub(crate) fn hide(&self, layer_id: LayerId) -> Result<()> {
-    //     self.send(LayerEvent::Hide { layer_id })
-    // }
+    /// Moves the layer below the other windows of its band.
+    pub(crate) fn lower(&self, layer_id: LayerId) -> Result<()> {
+        self.try_send(LayerEvent::Lower { layer_id })
+    }
+
+    /// Moves the layer to the always-on-top band, which is above the normal windows, or back to
+    /// the normal band.
+    pub(crate) fn set_always_on_top(&self, layer_id: LayerId, always_on_top: bool) -> Result<()> {
+        self.try_send(LayerEvent::SetAlwaysOnTop {
+            layer_id,
+            always_on_top,
+        })
+    }
+
+    /// Changes the area which starts dragging the layer (relative to the layer).
+    pub(crate) fn set_drag_area(&self, layer_id: LayerId, area: Rectangle<i32>) -> Result<()> {
+        self.try_send(LayerEvent::SetDragArea { layer_id, area })
+    }
+
+    pub(crate) fn minimize(&self, layer_id: LayerId) -> Result<()> {
+        self.try_send(LayerEvent::Minimize { layer_id })
+    }
+
+    /// Restores the minimized layer, and activates it.
+    pub(crate) fn restore(&self, layer_id: LayerId) -> Result<()> {
+        self.try_send(LayerEvent::Restore { layer_id })
+    }
+
+    /// Hides the layer, which is not listed in the taskbar unlike minimized layers.
+    pub(crate) fn hide(&self, layer_id: LayerId) -> Result<()> {
+        self.try_send(LayerEvent::Hide { layer_id })
+    }
+
+    /// Shows the hidden or minimized layer at the top of its band, without activating it.
+    pub(crate) fn show(&self, layer_id: LayerId) -> Result<()> {
+        self.try_send(LayerEvent::Show { layer_id })
+    }
 
     pub(crate) async fn mouse_event(
         &self,
@@ -461,87 +988,227 @@ impl EventSender {
             cursor_layer_id,
             event,
             tx,
-        })?;
-        rx.await;
+        })
+        .await;
+        rx.await?;
         Ok(())
     }
 
     pub(crate) async fn keyboard_event(&self, event: KeyboardEvent) -> Result<()> {
         let (tx, rx) = oneshot::channel();
-        self.send(LayerEvent::KeyboardEvent { event, tx })?;
-        rx.await;
+        self.send(LayerEvent::KeyboardEvent { event, tx }).await;
+        rx.await?;
         Ok(())
     }
 }
 
-pub(crate) fn handler_task() -> impl Future<Output = Result<()>> {
-    // Initialize LAYER_EVENT_TX before co-task starts
-    let (tx, mut rx) = mpsc::channel(100);
-    LAYER_EVENT_TX.init_once(|| tx);
-
-    async move {
-        let mut lm = LayerManager::new()?;
-        let mut am = ActiveLayer::new();
-
-        let mut drag_layer_id = None;
-        while let Some(event) = rx.next().await {
-            match event {
-                LayerEvent::Register { layer } => lm.register(layer),
-                LayerEvent::DrawLayer {
-                    layer_id,
-                    layer_area,
-                    tx,
-                } => {
-                    lm.draw_layer(layer_id, Some(layer_area));
-                    tx.send(());
+pub(crate) async fn handler_task() -> Result<()> {
+    // nothing is composed until the first layer or event is sent
+    let channel = EVENT_CHANNEL.wait().await;
+    #[allow(clippy::expect_used)]
+    let mut rx = interrupts::without_interrupts(|| channel.1.lock().take())
+        .expect("layer handler task started twice");
+    watchdog::register(&HEARTBEAT);
+    let mut lm = LayerManager::new()?;
+    let mut am = ActiveLayer::new();
+
+    let mut drag: Option<Drag> = None;
+    let mut capture_layer_id = None;
+    let mut taskbar_watcher = None;
+    while let Some(event) = rx.next().await {
+        HEARTBEAT.beat();
+        match event {
+            LayerEvent::Register { layer } => {
+                let (layer_id, owner) = (layer.id, layer.owner);
+                lm.register(layer);
+                if owner.is_some() {
+                    am.activate(&mut lm, Some(layer_id));
                 }
-                LayerEvent::MoveTo { layer_id, pos, tx } => {
-                    lm.move_to(layer_id, pos);
-                    tx.send(());
+            }
+            LayerEvent::Unregister { layer_id } => {
+                let owner = lm.layers.get(&layer_id).and_then(|layer| layer.owner);
+                let active = am.active_layer() == Some(layer_id);
+                lm.unregister(layer_id);
+                am.remove(layer_id);
+                // the owner of a closed dialog gets the focus back
+                if active && owner.is_some() {
+                    am.activate(&mut lm, owner);
                 }
-                LayerEvent::SetHeight { layer_id, height } => lm.set_layer_height(layer_id, height),
-                // LayerEvent::Hide { layer_id } => lm.hide(layer_id),
-                LayerEvent::MouseEvent {
-                    cursor_layer_id,
-                    event,
+                drag = drag.filter(|drag| drag.layer_id != layer_id);
+                capture_layer_id = capture_layer_id.filter(|id| *id != layer_id);
+            }
+            LayerEvent::RedrawAll => {
+                for layer in lm.layers.values() {
+                    // some layers, like the mouse cursor, don't read their events
+                    let _ = layer.send_event(WindowEvent::Redraw);
+                }
+            }
+            LayerEvent::WatchTaskbar { tx } => {
+                taskbar_watcher = Some(TaskbarWatcher {
                     tx,
-                } => {
-                    am.set_mouse_layer(&mut lm, Some(cursor_layer_id));
-                    let MouseEvent {
-                        down,
-                        up,
-                        pos,
-                        pos_diff,
-                    } = event;
-                    if up.contains(MouseButton::Left) {
-                        drag_layer_id = None;
+                    sent: Vec::new(),
+                });
+            }
+            LayerEvent::DrawLayer {
+                layer_id,
+                layer_area,
+                tx,
+            } => {
+                lm.draw_layer(layer_id, Some(layer_area));
+                let _ = tx.send(());
+            }
+            LayerEvent::MoveTo { layer_id, pos, tx } => {
+                lm.move_to(layer_id, pos);
+                let _ = tx.send(());
+            }
+            LayerEvent::SetHeight { layer_id, height } => lm.set_layer_height(layer_id, height),
+            LayerEvent::Raise { layer_id } => lm.raise(layer_id),
+            LayerEvent::Lower { layer_id } => {
+                // a window behind the others loses the focus
+                am.deactivate(&mut lm, layer_id);
+                lm.lower(layer_id);
+            }
+            LayerEvent::SetAlwaysOnTop {
+                layer_id,
+                always_on_top,
+            } => lm.set_always_on_top(layer_id, always_on_top),
+            LayerEvent::SetDragArea { layer_id, area } => lm.set_drag_area(layer_id, area),
+            LayerEvent::Minimize { layer_id } => {
+                am.deactivate(&mut lm, layer_id);
+                lm.minimize(layer_id);
+                drag = drag.filter(|drag| drag.layer_id != layer_id);
+                capture_layer_id = capture_layer_id.filter(|id| *id != layer_id);
+            }
+            LayerEvent::Hide { layer_id } => {
+                am.deactivate(&mut lm, layer_id);
+                lm.hide(layer_id);
+                drag = drag.filter(|drag| drag.layer_id != layer_id);
+                capture_layer_id = capture_layer_id.filter(|id| *id != layer_id);
+            }
+            LayerEvent::Show { layer_id } => {
+                lm.show(layer_id);
+            }
+            LayerEvent::Restore { layer_id } => {
+                if lm.show(layer_id) {
+                    am.activate(&mut lm, Some(layer_id));
+                }
+            }
+            LayerEvent::MouseEvent {
+                cursor_layer_id,
+                event,
+                tx,
+            } => {
+                lm.set_cursor_layer(cursor_layer_id);
+                let MouseEvent {
+                    buttons,
+                    down,
+                    up,
+                    pos,
+                    pos_diff,
+                    wheel,
+                } = event;
+                if up.contains(MouseButton::Left) {
+                    if let Some(drag) = drag {
+                        let screen_area = lm.frame_buffer.area();
+                        if let Some(snap) = Snap::from_drop_pos(pos, screen_area) {
+                            lm.snap(drag.layer_id, snap);
+                        }
                     }
-                    if let Some(layer_id) = drag_layer_id {
-                        lm.move_relative(layer_id, pos_diff);
+                    drag = None;
+                }
+                if let Some(drag) = &mut drag {
+                    lm.drag_by(drag, pos_diff);
+                }
+                if down.contains(MouseButton::Left) && capture_layer_id.is_none() {
+                    let window_layer = lm
+                        .layers_by_pos(pos)
+                        .find(|layer| layer.id != cursor_layer_id)
+                        .filter(|layer| layer.draggable);
+                    drag = window_layer
+                        .filter(|layer| layer.is_drag_point(pos) && !lm.is_blocked(layer.id))
+                        .map(|layer| Drag {
+                            layer_id: layer.id,
+                            raw_pos: layer.pos,
+                        });
+                    let window_layer_id = window_layer.map(|layer| layer.id());
+                    am.activate(&mut lm, window_layer_id);
+                }
+                if !down.is_empty() && drag.is_none() && capture_layer_id.is_none() {
+                    // deliver subsequent mouse events to the window under the cursor
+                    // until all buttons are released
+                    capture_layer_id = lm
+                        .layers_by_pos(pos)
+                        .find(|layer| layer.id != cursor_layer_id)
+                        .filter(|layer| layer.draggable || layer.clickable)
+                        .filter(|layer| !lm.is_blocked(layer.id))
+                        .map(|layer| layer.id());
+                }
+                // the wheel scrolls the active window, wherever the cursor is
+                let wheel_layer_id = am.active_layer().filter(|_| wheel != 0);
+                if let Some(layer_id) = capture_layer_id {
+                    let wheel = if Some(layer_id) == wheel_layer_id {
+                        wheel
+                    } else {
+                        0
+                    };
+                    if let Err(err) = lm.notify_mouse_event(layer_id, MouseEvent { wheel, ..event })
+                    {
+                        warn!("failed to notify_mouse_event: {}", err);
                     }
-                    if down.contains(MouseButton::Left) {
-                        drag_layer_id = lm
-                            .layers_by_pos(pos)
-                            .find(|layer| layer.id != cursor_layer_id)
-                            .filter(|layer| layer.draggable)
-                            .map(|layer| layer.id());
-                        am.activate(&mut lm, drag_layer_id);
+                }
+                if let Some(layer_id) = wheel_layer_id.filter(|id| Some(*id) != capture_layer_id) {
+                    let event = MouseEvent {
+                        down: BitFlags::empty(),
+                        up: BitFlags::empty(),
+                        ..event
+                    };
+                    if let Err(err) = lm.notify_mouse_event(layer_id, event) {
+                        warn!("failed to notify_mouse_event: {}", err);
                     }
-                    tx.send(());
                 }
-                LayerEvent::KeyboardEvent { event, tx } => {
+                if buttons.is_empty() {
+                    capture_layer_id = None;
+                }
+                let _ = tx.send(());
+            }
+            LayerEvent::KeyboardEvent { event, tx } => {
+                if let Some(snap) = Snap::from_hotkey(&event) {
+                    if let Some(layer_id) = am.active_layer() {
+                        lm.snap(layer_id, snap);
+                    }
+                } else if is_minimize_hotkey(&event) {
                     if let Some(layer_id) = am.active_layer() {
-                        if let Err(err) = lm.notify_keyboard_event(layer_id, event) {
-                            warn!("failed to notify_keyboard_event: {}", err);
+                        am.deactivate(&mut lm, layer_id);
+                        lm.minimize(layer_id);
+                    }
+                } else if let Some(request) = is_close_hotkey(&event)
+                    .then(|| WindowEvent::CloseRequested)
+                    .or_else(|| clipboard_request(&event))
+                {
+                    if let Some(layer_id) = am.active_layer() {
+                        if let Err(err) = lm.notify_request(layer_id, request) {
+                            warn!("failed to notify_request: {}", err);
                         }
-                    } else {
-                        crate::println!("key push not handled: {:?}", event);
                     }
-                    tx.send(());
+                } else if let Some(layer_id) = am.active_layer() {
+                    if let Err(err) = lm.notify_keyboard_event(layer_id, event) {
+                        warn!("failed to notify_keyboard_event: {}", err);
+                    }
+                } else if event.press {
+                    crate::println!("key push not handled: {:?}", event);
                 }
+                let _ = tx.send(());
             }
         }
 
-        Ok(())
+        // composite once for all the events queued in a frame
+        if rx.is_empty() {
+            if let Some(watcher) = &mut taskbar_watcher {
+                watcher.update(lm.taskbar_items(am.active_layer()));
+            }
+            lm.flush();
+        }
     }
+
+    Ok(())
 }
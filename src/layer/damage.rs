@@ -0,0 +1,93 @@
+//! Damaged areas of the screen, which are composited at the end of a frame.
+//!
+//! Overlapping or adjacent rectangles are merged when the merged rectangle is not larger than
+//! the two rectangles, so that moving a layer by a few pixels composites the union of its old
+//! and new areas once.
+
+use crate::graphics::Rectangle;
+use alloc::vec::Vec;
+use core::mem;
+
+/// The number of rectangles kept. More rectangles are merged into their bounding box.
+const MAX_RECTS: usize = 8;
+
+fn area(rect: Rectangle<i32>) -> i64 {
+    i64::from(rect.size.x) * i64::from(rect.size.y)
+}
+
+#[derive(Debug, Default)]
+pub(super) struct Damage {
+    rects: Vec<Rectangle<i32>>,
+}
+
+impl Damage {
+    pub(super) fn new() -> Self {
+        Self::default()
+    }
+
+    pub(super) fn add(&mut self, rect: Rectangle<i32>) {
+        if rect.size.x <= 0 || rect.size.y <= 0 {
+            return;
+        }
+        let mut rect = rect;
+        // a merged rectangle may be merged again with the others
+        while let Some(index) = self
+            .rects
+            .iter()
+            .position(|r| area(*r | rect) <= area(*r) + area(rect))
+        {
+            rect = rect | self.rects.swap_remove(index);
+        }
+        if self.rects.len() == MAX_RECTS {
+            rect = self.rects.drain(..).fold(rect, |acc, r| acc | r);
+        }
+        self.rects.push(rect);
+    }
+
+    /// Takes the damaged rectangles.
+    pub(super) fn take(&mut self) -> Vec<Rectangle<i32>> {
+        mem::take(&mut self.rects)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn merge() {
+        let mut damage = Damage::new();
+        // the old and new areas of a moved layer
+        damage.add(Rectangle::from_xywh(0, 0, 10, 10));
+        damage.add(Rectangle::from_xywh(2, 1, 10, 10));
+        // a distant area
+        damage.add(Rectangle::from_xywh(100, 100, 5, 5));
+        // an adjacent area
+        damage.add(Rectangle::from_xywh(100, 105, 5, 5));
+        damage.add(Rectangle::from_xywh(0, 0, 0, 10));
+        let mut rects = damage.take();
+        rects.sort_by_key(|r| (r.pos.x, r.pos.y));
+        assert_eq!(
+            rects,
+            [
+                Rectangle::from_xywh(0, 0, 12, 11),
+                Rectangle::from_xywh(100, 100, 5, 10)
+            ]
+        );
+        assert!(damage.take().is_empty());
+    }
+
+    #[test_case]
+    fn bounding_box() {
+        let mut damage = Damage::new();
+        for i in 0..=MAX_RECTS as i32 {
+            damage.add(Rectangle::from_xywh(i * 10, i * 10, 1, 1));
+        }
+        let rects = damage.take();
+        assert_eq!(rects.len(), 1);
+        assert_eq!(
+            rects[0],
+            Rectangle::from_xywh(0, 0, MAX_RECTS as i32 * 10 + 1, MAX_RECTS as i32 * 10 + 1)
+        );
+    }
+}
@@ -0,0 +1,118 @@
+//! Constraints of windows dragged by the mouse.
+//!
+//! A dragged window snaps to the edges of the work area and of the other windows within
+//! [`SNAP_DISTANCE`] pixels, and is kept where its drag area (the title bar) can be grabbed
+//! again.
+
+use super::LayerId;
+use crate::graphics::{Point, Rectangle};
+
+const SNAP_DISTANCE: i32 = 8;
+/// The width and height of the drag area kept in the work area.
+const MIN_VISIBLE: i32 = 32;
+
+/// A window dragged by the mouse.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Drag {
+    pub(super) layer_id: LayerId,
+    /// The position following the mouse, before it is snapped and clamped.
+    pub(super) raw_pos: Point<i32>,
+}
+
+/// Returns the offset moving the edges of `start..end` to the nearest target within
+/// [`SNAP_DISTANCE`].
+fn snap_offset(start: i32, end: i32, targets: impl Iterator<Item = i32>) -> i32 {
+    targets
+        .flat_map(|target| [target - start, target - end])
+        .filter(|offset| offset.abs() <= SNAP_DISTANCE)
+        .min_by_key(|offset| offset.abs())
+        .unwrap_or(0)
+}
+
+/// Returns the position of the window of `area`, which is snapped to the edges of `work_area`
+/// and `others`, and clamped so that its `drag_area` (relative to the window) remains visible.
+pub(super) fn constrain(
+    area: Rectangle<i32>,
+    drag_area: Rectangle<i32>,
+    work_area: Rectangle<i32>,
+    others: &[Rectangle<i32>],
+) -> Point<i32> {
+    // windows snap to the edges of other windows beside them
+    let x_targets = others
+        .iter()
+        .filter(|other| {
+            other.y_start() <= area.y_end() + SNAP_DISTANCE
+                && area.y_start() <= other.y_end() + SNAP_DISTANCE
+        })
+        .flat_map(|other| [other.x_start(), other.x_end()]);
+    let y_targets = others
+        .iter()
+        .filter(|other| {
+            other.x_start() <= area.x_end() + SNAP_DISTANCE
+                && area.x_start() <= other.x_end() + SNAP_DISTANCE
+        })
+        .flat_map(|other| [other.y_start(), other.y_end()]);
+    let dx = snap_offset(
+        area.x_start(),
+        area.x_end(),
+        [work_area.x_start(), work_area.x_end()]
+            .iter()
+            .copied()
+            .chain(x_targets),
+    );
+    let dy = snap_offset(
+        area.y_start(),
+        area.y_end(),
+        [work_area.y_start(), work_area.y_end()]
+            .iter()
+            .copied()
+            .chain(y_targets),
+    );
+    let pos = area.pos + Point::new(dx, dy);
+
+    // `max` and `min` are used instead of `clamp`, which panics if the work area is too small
+    let visible_x = i32::min(MIN_VISIBLE, drag_area.size.x);
+    let visible_y = i32::min(MIN_VISIBLE, drag_area.size.y);
+    let x = pos
+        .x
+        .max(work_area.x_start() + visible_x - drag_area.x_end())
+        .min(work_area.x_end() - visible_x - drag_area.x_start());
+    // the top of the drag area does not go above the work area
+    let y = pos
+        .y
+        .min(work_area.y_end() - visible_y - drag_area.y_start())
+        .max(work_area.y_start() - drag_area.y_start());
+    Point::new(x, y)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn constrain() {
+        let work_area = Rectangle::from_xywh(0, 0, 800, 550);
+        let title_bar = Rectangle::from_xywh(0, 0, 100, 24);
+        let others = [Rectangle::from_xywh(300, 100, 100, 100)];
+        let check = |x, y| {
+            super::constrain(
+                Rectangle::from_xywh(x, y, 100, 80),
+                title_bar,
+                work_area,
+                &others,
+            )
+        };
+
+        // snapped to the edges of the work area
+        assert_eq!(check(5, 200), Point::new(0, 200));
+        assert_eq!(check(695, 466), Point::new(700, 470));
+        // snapped to the edges of the other window beside it
+        assert_eq!(check(195, 110), Point::new(200, 110));
+        assert_eq!(check(405, 95), Point::new(400, 100));
+        // not snapped to the edges of the other window far from it
+        assert_eq!(check(195, 400), Point::new(195, 400));
+        // the title bar is kept in the work area
+        assert_eq!(check(-500, -50), Point::new(-68, 0));
+        assert_eq!(check(900, 600), Point::new(768, 526));
+    }
+}
@@ -0,0 +1,77 @@
+//! Layouts to which windows snap by the hotkeys (Gui+Left/Right/Up) or by being dropped at the
+//! edges of the screen.
+//!
+//! A snapped window is moved to the area of the layout in the work area, and is requested to
+//! resize itself to fill it.
+
+use crate::{
+    graphics::{Point, Rectangle, Size},
+    keyboard::{KeyboardEvent, Modifier},
+};
+
+/// Layout to which a window snaps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Snap {
+    LeftHalf,
+    RightHalf,
+    Maximized,
+}
+
+impl Snap {
+    pub(super) fn from_hotkey(event: &KeyboardEvent) -> Option<Self> {
+        if !event.press || !event.modifier.intersects(Modifier::LGui | Modifier::RGui) {
+            return None;
+        }
+        match event.keycode {
+            0x4f => Some(Self::RightHalf), // right arrow
+            0x50 => Some(Self::LeftHalf),  // left arrow
+            0x52 => Some(Self::Maximized), // up arrow
+            _ => None,
+        }
+    }
+
+    pub(super) fn from_drop_pos(pos: Point<i32>, screen_area: Rectangle<i32>) -> Option<Self> {
+        if pos.x <= screen_area.x_start() {
+            Some(Self::LeftHalf)
+        } else if pos.x >= screen_area.x_end() - 1 {
+            Some(Self::RightHalf)
+        } else if pos.y <= screen_area.y_start() {
+            Some(Self::Maximized)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the area of the window snapped in `work_area`.
+    pub(super) fn area(self, work_area: Rectangle<i32>) -> Rectangle<i32> {
+        let half = Size::new(work_area.size.x / 2, work_area.size.y);
+        match self {
+            Self::LeftHalf => Rectangle::new(work_area.pos, half),
+            // the right half takes the odd pixel
+            Self::RightHalf => Rectangle::new(
+                Point::new(work_area.x_start() + half.x, work_area.y_start()),
+                Size::new(work_area.size.x - half.x, work_area.size.y),
+            ),
+            Self::Maximized => work_area,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn area() {
+        let work_area = Rectangle::from_xywh(0, 0, 801, 550);
+        assert_eq!(
+            Snap::LeftHalf.area(work_area),
+            Rectangle::from_xywh(0, 0, 400, 550)
+        );
+        assert_eq!(
+            Snap::RightHalf.area(work_area),
+            Rectangle::from_xywh(400, 0, 401, 550)
+        );
+        assert_eq!(Snap::Maximized.area(work_area), work_area);
+    }
+}
@@ -0,0 +1,227 @@
+//! Local APIC registers.
+//!
+//! The registers are identity-mapped. Until ACPI is initialized, they are accessed at the
+//! architectural default address, which is mapped early at boot. [`init`] switches to the address
+//! reported by MADT if it differs.
+
+use crate::{interrupt::InterruptIndex, memory, paging, prelude::*};
+use core::{
+    mem,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use volatile::Volatile;
+use x86_64::{structures::paging::OffsetPageTable, PhysAddr, VirtAddr};
+
+/// The address of the local APIC registers after reset.
+pub(crate) const DEFAULT_BASE: u64 = 0xfee0_0000;
+/// The size of the register page.
+pub(crate) const SIZE: u64 = 4096;
+
+static BASE: AtomicU64 = AtomicU64::new(DEFAULT_BASE);
+
+/// A 32-bit register, placed at a 16-byte boundary.
+#[derive(Debug)]
+#[repr(C, align(16))]
+struct Register {
+    value: u32,
+}
+
+impl Register {
+    fn volatile(&mut self) -> Volatile<&mut u32> {
+        Volatile::new(&mut self.value)
+    }
+}
+
+/// The local APIC register page.
+#[derive(Debug)]
+#[repr(C)]
+pub(crate) struct Registers {
+    _reserved0: [Register; 2],
+    id: Register,
+    version: Register,
+    _reserved1: [Register; 7],
+    eoi: Register,
+    _reserved2: [Register; 3],
+    spurious_interrupt_vector: Register,
+    // ISR, TMR and IRR
+    _reserved3: [Register; 24],
+    error_status: Register,
+    // LVT CMCI
+    _reserved4: [Register; 7],
+    icr_low: Register,
+    icr_high: Register,
+    lvt_timer: Register,
+    lvt_thermal_sensor: Register,
+    lvt_performance_counter: Register,
+    lvt_lint0: Register,
+    lvt_lint1: Register,
+    lvt_error: Register,
+    initial_count: Register,
+    current_count: Register,
+    _reserved5: [Register; 4],
+    divide_config: Register,
+    _reserved6: Register,
+}
+static_assertions::const_assert_eq!(mem::size_of::<Registers>(), 0x400);
+
+/// Delivery mode of the interrupt command register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum IpiDelivery {
+    Fixed(u8),
+    Init,
+    Startup(u8),
+}
+
+impl IpiDelivery {
+    fn command(self) -> u32 {
+        match self {
+            IpiDelivery::Fixed(vector) => u32::from(vector),
+            IpiDelivery::Init => (0b101 << 8) | ICR_LEVEL_ASSERT,
+            IpiDelivery::Startup(vector) => (0b110 << 8) | u32::from(vector),
+        }
+    }
+}
+
+/// Operating mode of the LVT timer entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TimerMode {
+    OneShot,
+    Periodic,
+    TscDeadline,
+}
+
+const SVR_APIC_ENABLE: u32 = 1 << 8;
+const ICR_DELIVERY_PENDING: u32 = 1 << 12;
+const ICR_LEVEL_ASSERT: u32 = 1 << 14;
+const LVT_MASKED: u32 = 1 << 16;
+
+impl Registers {
+    pub(crate) fn id(&mut self) -> u8 {
+        (self.id.volatile().read() >> 24) as u8
+    }
+
+    pub(crate) fn end_of_interrupt(&mut self) {
+        self.eoi.volatile().write(0);
+    }
+
+    #[cfg(feature = "acpi-s3")]
+    pub(crate) fn spurious_interrupt_vector(&mut self) -> Volatile<&mut u32> {
+        self.spurious_interrupt_vector.volatile()
+    }
+
+    /// Software-enables the local APIC, and delivers spurious interrupts to `vector`.
+    pub(crate) fn enable(&mut self, spurious_vector: u8) {
+        self.spurious_interrupt_vector
+            .volatile()
+            .write(SVR_APIC_ENABLE | u32::from(spurious_vector));
+    }
+
+    pub(crate) fn set_error_vector(&mut self, vector: u8) {
+        self.lvt_error.volatile().write(u32::from(vector));
+    }
+
+    /// Returns the errors detected since the last call.
+    ///
+    /// The error status register is updated by a write, which also clears the errors.
+    pub(crate) fn error_status(&mut self) -> u32 {
+        self.error_status.volatile().write(0);
+        self.error_status.volatile().read()
+    }
+
+    /// Sends an IPI to the CPU of `apic_id` and waits until it is accepted.
+    pub(crate) fn send_ipi(&mut self, apic_id: u8, delivery: IpiDelivery) {
+        self.icr_high.volatile().write(u32::from(apic_id) << 24);
+        // writing the low dword sends the IPI
+        self.icr_low.volatile().write(delivery.command());
+        while self.icr_low.volatile().read() & ICR_DELIVERY_PENDING != 0 {
+            core::hint::spin_loop();
+        }
+    }
+
+    #[cfg(feature = "acpi-s3")]
+    pub(crate) fn lvt_timer(&mut self) -> Volatile<&mut u32> {
+        self.lvt_timer.volatile()
+    }
+
+    /// Sets the LVT timer entry.
+    pub(crate) fn set_timer(&mut self, mode: TimerMode, vector: u8, masked: bool) {
+        let mode = match mode {
+            TimerMode::OneShot => 0b00,
+            TimerMode::Periodic => 0b01,
+            TimerMode::TscDeadline => 0b10,
+        };
+        let masked = if masked { LVT_MASKED } else { 0 };
+        self.lvt_timer
+            .volatile()
+            .write((mode << 17) | masked | u32::from(vector));
+    }
+
+    /// Returns the mode, vector and mask of the LVT timer entry, or `None` if the mode is
+    /// reserved.
+    pub(crate) fn timer(&mut self) -> Option<(TimerMode, u8, bool)> {
+        let value = self.lvt_timer.volatile().read();
+        let mode = match (value >> 17) & 0b11 {
+            0b00 => TimerMode::OneShot,
+            0b01 => TimerMode::Periodic,
+            0b10 => TimerMode::TscDeadline,
+            _ => return None,
+        };
+        Some((mode, value as u8, value & LVT_MASKED != 0))
+    }
+
+    pub(crate) fn initial_count(&mut self) -> Volatile<&mut u32> {
+        self.initial_count.volatile()
+    }
+
+    pub(crate) fn current_count(&mut self) -> Volatile<&mut u32> {
+        self.current_count.volatile()
+    }
+
+    pub(crate) fn divide_config(&mut self) -> Volatile<&mut u32> {
+        self.divide_config.volatile()
+    }
+}
+
+/// Maps the registers at `base` reported by the firmware, and uses them from now on.
+///
+/// The window at the default address is unmapped if the registers are relocated.
+pub(crate) fn init(mapper: &mut OffsetPageTable, base: PhysAddr) -> Result<()> {
+    let old_base = BASE.load(Ordering::Relaxed);
+    if base.as_u64() == old_base {
+        return Ok(());
+    }
+    info!("local APIC is relocated to {:x}", base.as_u64());
+    memory::with_memory_manager(|allocator| paging::map_mmio(mapper, allocator, base, SIZE))?;
+    BASE.store(base.as_u64(), Ordering::Relaxed);
+    paging::unmap_range(mapper, VirtAddr::new(old_base), SIZE)?.flush();
+    Ok(())
+}
+
+/// Enables the local APIC of the current CPU, and routes spurious interrupts and errors to their
+/// handlers.
+///
+/// This must be called on each CPU after the IDT is loaded, and again after the CPU state was lost.
+pub(crate) fn init_local() {
+    let registers = registers();
+    registers.enable(InterruptIndex::Spurious.as_u8());
+    registers.set_error_vector(InterruptIndex::LapicError.as_u8());
+    // discard errors latched before the handler was set
+    let _ = registers.error_status();
+}
+
+/// Returns the registers of the local APIC of the current CPU.
+///
+/// Each CPU sees its own local APIC at the same address.
+pub(crate) fn registers() -> &'static mut Registers {
+    #[allow(clippy::unwrap_used)]
+    unsafe {
+        (BASE.load(Ordering::Relaxed) as *mut Registers)
+            .as_mut()
+            .unwrap()
+    }
+}
+
+/// Returns the local APIC ID of the current CPU.
+pub(crate) fn id() -> u8 {
+    registers().id()
+}
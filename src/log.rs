@@ -1,4 +1,7 @@
-use crate::{print, println, serial_print, serial_println};
+use crate::{
+    print, println, serial_print, serial_println,
+    time::{self, Instant},
+};
 use core::fmt;
 
 static CONSOLE_LOG_LEVEL: spin::RwLock<Level> = spin::RwLock::new(Level::Warn);
@@ -31,6 +34,18 @@ pub(crate) fn set_level(console_level: Level, serial_level: Level) {
     *SERIAL_LOG_LEVEL.write() = serial_level;
 }
 
+/// The local time once the wall clock is set, or the time since the clock initialization.
+struct Timestamp;
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match time::local_now() {
+            Some(now) => write!(f, "{:#}", now),
+            None => write!(f, "{}", Instant::now()),
+        }
+    }
+}
+
 #[doc(hidden)]
 pub(crate) fn _log(
     level: Level,
@@ -44,8 +59,12 @@ pub(crate) fn _log(
         match (cont_line, newline) {
             (true, true) => serial_println!("{}", args),
             (true, false) => serial_print!("{}", args),
-            (false, true) => serial_println!("[{}] {}:{} {}", level, file, line, args),
-            (false, false) => serial_print!("[{}] {}:{} {}", level, file, line, args),
+            (false, true) => {
+                serial_println!("[{}] [{}] {}:{} {}", Timestamp, level, file, line, args)
+            }
+            (false, false) => {
+                serial_print!("[{}] [{}] {}:{} {}", Timestamp, level, file, line, args)
+            }
         }
     }
     if level <= *CONSOLE_LOG_LEVEL.read() {
@@ -11,3 +11,17 @@ macro_rules! byte_getter {
         }
     };
 }
+
+#[macro_export]
+macro_rules! byte_setter {
+    ($vis:vis $field:ident : [u8; $n:expr] => $setter:ident) => {
+        $vis fn $setter(&mut self, value: [u8; $n]) {
+            self.$field = value;
+        }
+    };
+    ($vis:vis $field:ident : $ty:ident => $setter:ident) => {
+        $vis fn $setter(&mut self, value: $ty) {
+            self.$field = value.to_le_bytes();
+        }
+    };
+}
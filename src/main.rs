@@ -6,8 +6,10 @@
 #![feature(alloc_error_handler)]
 #![feature(const_mut_refs)]
 #![feature(custom_test_frameworks)]
+#![feature(global_asm)]
 #![feature(lang_items)]
 #![feature(naked_functions)]
+#![feature(try_reserve)]
 #![no_std]
 #![no_main]
 #![test_runner(test_runner)]
@@ -28,14 +30,21 @@ use bootloader::{
     entry_point, BootInfo,
 };
 use core::{mem, panic::PanicInfo};
-use x86_64::VirtAddr;
+use x86_64::{PhysAddr, VirtAddr};
 
 mod acpi;
 mod allocator;
+mod backtrace;
+mod bench;
+mod clipboard;
 mod co_task;
 mod console;
-mod cxx_support;
+mod cpu;
 mod desktop;
+mod device;
+mod dialog;
+mod dma;
+mod driver;
 mod emergency_console;
 mod error;
 mod fat;
@@ -43,23 +52,46 @@ mod fmt;
 mod framed_window;
 mod gdt;
 mod graphics;
+mod idle;
+mod ime;
 mod interrupt;
 mod keyboard;
 mod layer;
+mod local_apic;
 mod log;
 mod macros;
+mod mce;
 mod memory;
+mod memtest;
+mod mmio;
 mod mouse;
+mod net;
 mod paging;
 mod pci;
+mod pm_timer;
 mod prelude;
+mod random;
+mod rtc;
 mod serial;
+mod shutdown;
+mod smp;
+mod softirq;
+mod stress;
+#[cfg(feature = "acpi-s3")]
+mod suspend;
 mod sync;
 mod task;
 mod terminal;
 mod text_window;
+mod theme;
+mod time;
 mod timer;
+mod trampoline;
 mod triple_buffer;
+mod ui;
+mod usb;
+mod virtio;
+mod watchdog;
 mod window;
 mod xhc;
 
@@ -83,33 +115,62 @@ fn init(boot_info: &'static mut BootInfo) -> Result<()> {
     // Initialize graphics for boot log
     graphics::init(frame_buffer)?;
 
+    // Detect and enable CPU features
+    cpu::init();
+    mce::init();
+    random::init();
+
     // Initialize memory mapping / frame allocator / heap
     let mut mapper = unsafe { paging::init(physical_memory_offset) };
-    {
-        let mut allocator = memory::lock_memory_manager();
-
+    memory::with_memory_manager(|allocator| -> Result<()> {
         allocator.init(&*boot_info.memory_regions)?;
 
         // Map CPU register addresses as identity mapping
-        paging::make_identity_mapping(&mut mapper, &mut *allocator, 0xfee00000, 1)?;
-
-        allocator::init_heap(&mut mapper, &mut *allocator)?;
-    }
+        // (LAPIC registers are accessed through the default address until MADT is parsed)
+        paging::map_mmio(
+            &mut mapper,
+            allocator,
+            PhysAddr::new(local_apic::DEFAULT_BASE),
+            local_apic::SIZE,
+        )?;
+
+        allocator::init_heap(&mut mapper, allocator)?;
+        Ok(())
+    })?;
+    // the serial port is initialized on the first output, before the device registry is usable
+    device::register_platform("COM1", "serial");
 
     // Initialize GDT/IDT
     gdt::init();
+    cpu::init_local(0);
     interrupt::init();
 
-    // Initialize PCI devices
+    // Initialize ACPI and PCI devices
+    unsafe { acpi::init(&mut mapper, rsdp) }?;
+    pci::init(&mut mapper)?;
     let devices = pci::scan_all_bus()?;
-    xhc::init(&devices, &mut mapper)?;
+    pci::init_resource_map(&devices);
+    net::init();
+    driver::probe_all(&devices, &mut mapper);
 
     // Initialize LAPIC timer
-    unsafe { acpi::init(&mut mapper, rsdp) }?;
+    pm_timer::init();
+    if let Some(info) = acpi::platform_info() {
+        local_apic::init(&mut mapper, info.local_apic_address)?;
+    }
+    local_apic::init_local();
+    time::init();
     timer::lapic::init();
+    rtc::init();
+    smp::init(&mut mapper)?;
+    #[cfg(feature = "acpi-s3")]
+    suspend::init(&mut mapper)?;
 
     // Initialize file system
     fat::init();
+    time::init_timezone();
+    graphics::font::init();
+    theme::init();
 
     task::init();
 
@@ -127,16 +188,28 @@ fn start_window() -> ! {
 
     // Initialize executor & co-tasks
     let mut executor = Executor::new(task_id);
-    executor.spawn(CoTask::new(xhc::handler_task()));
+    executor.spawn(CoTask::new(softirq::handler_task()));
     executor.spawn(CoTask::new(timer::lapic::handler_task()));
+    executor.spawn(CoTask::new(net::tcp::handler_task().unwrap()));
+    executor.spawn(CoTask::new(net::echo::handler_task().unwrap()));
+    executor.spawn(CoTask::new(net::echo::udp_handler_task().unwrap()));
+    executor.spawn(CoTask::new(net::remote_shell::handler_task().unwrap()));
     executor.spawn(CoTask::new(mouse::handler_task().unwrap()));
     executor.spawn(CoTask::new(keyboard::handler_task().unwrap()));
+    executor.spawn(CoTask::new(xhc::hotplug_task().unwrap()));
     executor.spawn(CoTask::new(desktop::handler_task().unwrap()));
+    executor.spawn(CoTask::new(desktop::taskbar_task().unwrap()));
     executor.spawn(CoTask::new(console::handler_task(console_param).unwrap()));
-    executor.spawn(CoTask::new(layer_task));
+
+    // the compositor runs in the real-time class to keep the UI responsive under load
+    task::spawn_realtime(
+        Task::new(task::DEFAULT_STACK_SIZE, layer_task),
+        layer::FRAME_DEADLINE,
+    );
 
     #[allow(clippy::unwrap_used)]
     task::spawn(Task::new(
+        task::DEFAULT_STACK_SIZE,
         TextWindow::new("Text Box test".into(), Point::new(500, 100))
             .unwrap()
             .run()
@@ -144,6 +217,7 @@ fn start_window() -> ! {
     ));
     #[allow(clippy::unwrap_used)]
     task::spawn(Task::new(
+        task::DEFAULT_STACK_SIZE,
         Terminal::new(
             "sabios Terminal".into(),
             Point::new(100, 200),
@@ -0,0 +1,129 @@
+//! Machine check architecture.
+//!
+//! Machine-check banks latch hardware errors such as uncorrectable memory or bus errors. Errors
+//! which the hardware cannot recover from raise the machine-check exception, whose handler dumps
+//! the banks with [`dump`].
+
+use crate::{cpu, prelude::*};
+use core::fmt;
+use x86_64::registers::{
+    control::{Cr4, Cr4Flags},
+    model_specific::Msr,
+};
+
+const IA32_MCG_CAP: u32 = 0x179;
+const IA32_MCG_STATUS: u32 = 0x17a;
+const IA32_MCG_CTL: u32 = 0x17b;
+/// The first register of bank 0. Each bank has CTL, STATUS, ADDR and MISC registers in order.
+const IA32_MC0_CTL: u32 = 0x400;
+
+const MCG_CAP_COUNT: u64 = 0xff;
+const MCG_CAP_CTL_PRESENT: u64 = 1 << 8;
+/// Restart IP Valid
+const MCG_STATUS_RIPV: u64 = 1 << 0;
+/// Machine Check In Progress
+const MCG_STATUS_MCIP: u64 = 1 << 2;
+
+const MCI_STATUS_VAL: u64 = 1 << 63;
+const MCI_STATUS_UC: u64 = 1 << 61;
+const MCI_STATUS_MISCV: u64 = 1 << 59;
+const MCI_STATUS_ADDRV: u64 = 1 << 58;
+
+fn read(msr: u32) -> u64 {
+    unsafe { Msr::new(msr).read() }
+}
+
+fn write(msr: u32, value: u64) {
+    unsafe { Msr::new(msr).write(value) }
+}
+
+fn bank_msr(bank: u32, offset: u32) -> u32 {
+    IA32_MC0_CTL + bank * 4 + offset
+}
+
+fn bank_count() -> u32 {
+    (read(IA32_MCG_CAP) & MCG_CAP_COUNT) as u32
+}
+
+/// An error latched in a machine-check bank.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct BankError {
+    bank: u32,
+    status: u64,
+    addr: Option<u64>,
+    misc: Option<u64>,
+}
+
+impl fmt::Display for BankError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "MC{}: status={:016x}", self.bank, self.status)?;
+        if self.status & MCI_STATUS_UC != 0 {
+            write!(f, " (uncorrected)")?;
+        }
+        if let Some(addr) = self.addr {
+            write!(f, " addr={:016x}", addr)?;
+        }
+        if let Some(misc) = self.misc {
+            write!(f, " misc={:016x}", misc)?;
+        }
+        Ok(())
+    }
+}
+
+fn bank_errors() -> impl Iterator<Item = BankError> {
+    (0..bank_count()).filter_map(|bank| {
+        let status = read(bank_msr(bank, 1));
+        if status & MCI_STATUS_VAL == 0 {
+            return None;
+        }
+        Some(BankError {
+            bank,
+            status,
+            addr: (status & MCI_STATUS_ADDRV != 0).then(|| read(bank_msr(bank, 2))),
+            misc: (status & MCI_STATUS_MISCV != 0).then(|| read(bank_msr(bank, 3))),
+        })
+    })
+}
+
+/// Enables the machine-check banks and exception, and reports errors latched before boot (e.g.
+/// the cause of the last reset).
+///
+/// This is called on the bootstrap processor. Application processors inherit CR4 through the
+/// trampoline.
+pub(crate) fn init() {
+    let features = cpu::features();
+    if !features.mce || !features.mca {
+        info!("machine check architecture is not available");
+        return;
+    }
+    for error in bank_errors() {
+        warn!("machine check before boot: {}", error);
+    }
+    if read(IA32_MCG_CAP) & MCG_CAP_CTL_PRESENT != 0 {
+        write(IA32_MCG_CTL, u64::MAX);
+    }
+    for bank in 0..bank_count() {
+        write(bank_msr(bank, 0), u64::MAX);
+        write(bank_msr(bank, 1), 0);
+    }
+    unsafe { Cr4::update(|cr4| cr4.insert(Cr4Flags::MACHINE_CHECK_EXCEPTION)) };
+}
+
+/// Writes the global status and the latched errors, for the machine-check exception handler.
+pub(crate) fn dump(w: &mut impl fmt::Write) -> fmt::Result {
+    if !cpu::features().mca {
+        return writeln!(w, "machine check banks are not available");
+    }
+    let status = read(IA32_MCG_STATUS);
+    writeln!(
+        w,
+        "MCG_STATUS={:016x} (RIPV={}, MCIP={})",
+        status,
+        status & MCG_STATUS_RIPV != 0,
+        status & MCG_STATUS_MCIP != 0
+    )?;
+    for error in bank_errors() {
+        writeln!(w, "{}", error)?;
+    }
+    Ok(())
+}
@@ -1,10 +1,13 @@
 use crate::{
+    allocator,
     prelude::*,
-    sync::{SpinMutex, SpinMutexGuard},
+    sync::{OnceCell, SpinMutex, SpinMutexGuard},
 };
+use arrayvec::ArrayVec;
 use bootloader::boot_info::{MemoryRegion, MemoryRegionKind};
 use core::cmp;
 use x86_64::{
+    instructions::interrupts,
     structures::paging::{frame::PhysFrameRange, FrameAllocator, PhysFrame, Size4KiB},
     PhysAddr,
 };
@@ -27,6 +30,22 @@ type MapLine = u64;
 const BITS_PER_MAP_LINE: u64 = MapLine::BITS as u64;
 const ALLOC_MAP_LEN: usize = (FRAME_COUNT / (BITS_PER_MAP_LINE as u64)) as usize;
 
+const MAX_REGIONS: usize = 128;
+
+static REGIONS: OnceCell<ArrayVec<MemoryRegion, MAX_REGIONS>> = OnceCell::uninit();
+static RESERVED_RANGES: OnceCell<ArrayVec<PhysFrameRange, MAX_REGIONS>> = OnceCell::uninit();
+
+/// Returns the boot memory regions, with adjacent regions of the same kind merged.
+pub(crate) fn regions() -> &'static [MemoryRegion] {
+    REGIONS.get()
+}
+
+/// Returns the frame ranges marked as allocated at boot because they are not usable
+/// (non-usable regions and holes between regions).
+pub(crate) fn reserved_ranges() -> &'static [PhysFrameRange] {
+    RESERVED_RANGES.get()
+}
+
 pub(crate) struct BitmapMemoryManager {
     alloc_map: [MapLine; ALLOC_MAP_LEN],
     range: PhysFrameRange,
@@ -44,8 +63,23 @@ static MEMORY_MANAGER: SpinMutex<BitmapMemoryManager> = SpinMutex::new(BitmapMem
     },
 });
 
-pub(crate) fn lock_memory_manager() -> SpinMutexGuard<'static, BitmapMemoryManager> {
-    MEMORY_MANAGER.lock()
+/// Runs `f` with the memory manager locked.
+///
+/// Interrupts are disabled meanwhile, so that no task is switched to while the lock is held. The
+/// frames reserved for the heap are refilled before the lock is released.
+pub(crate) fn with_memory_manager<R>(f: impl FnOnce(&mut BitmapMemoryManager) -> R) -> R {
+    interrupts::without_interrupts(|| {
+        let mut manager = MEMORY_MANAGER.lock();
+        let res = f(&mut *manager);
+        allocator::refill_frame_reserve(&mut *manager);
+        res
+    })
+}
+
+/// Locks the memory manager if it is not locked. This must be called with interrupts disabled.
+#[track_caller]
+pub(crate) fn try_lock_memory_manager() -> Result<SpinMutexGuard<'static, BitmapMemoryManager>> {
+    MEMORY_MANAGER.try_lock()
 }
 
 impl BitmapMemoryManager {
@@ -53,9 +87,14 @@ impl BitmapMemoryManager {
         let regions = MergedMemoryRegion::new(regions);
         let frame_size = 4096u64;
 
+        let mut merged_regions = ArrayVec::new();
+        let mut reserved_ranges = ArrayVec::new();
         let mut available_start = self.range.start;
         let mut available_end = self.range.end;
         for region in regions {
+            if merged_regions.try_push(region).is_err() {
+                warn!("too many memory regions, {:?} is not recorded", region);
+            }
             let usable = region.kind == MemoryRegionKind::Usable;
 
             let start = PhysAddr::new(region.start);
@@ -73,18 +112,24 @@ impl BitmapMemoryManager {
             let end = PhysFrame::from_start_address(end)?;
 
             if available_end < start {
-                self.mark_allocated(PhysFrame::range(available_end, start));
+                let hole = PhysFrame::range(available_end, start);
+                self.mark_allocated(hole);
+                let _ = reserved_ranges.try_push(hole);
             }
 
             if usable {
                 available_start = cmp::min(available_start, start);
                 available_end = cmp::max(available_end, end);
             } else {
-                self.mark_allocated(PhysFrame::range(start, end));
+                let range = PhysFrame::range(start, end);
+                self.mark_allocated(range);
+                let _ = reserved_ranges.try_push(range);
             }
         }
 
         self.range = PhysFrame::range(available_start, available_end);
+        REGIONS.init_once(|| merged_regions);
+        RESERVED_RANGES.init_once(|| reserved_ranges);
         Ok(())
     }
 
@@ -101,21 +146,46 @@ impl BitmapMemoryManager {
         }
     }
 
-    // fn mark_freed(&mut self, range: PhysFrameRange) {
-    //     for frame in range {
-    //         self.set_bit(frame, false)
-    //     }
-    //     // update range if needed
-    //     if self.range.start <= range.end {
-    //         self.range.start = range.start;
-    //     }
-    // }
-
     pub(crate) fn allocate(&mut self, num_frames: usize) -> Result<PhysFrameRange> {
+        self.allocate_aligned(num_frames, 1)
+    }
+
+    /// Allocates `num_frames` contiguous frames whose first frame is aligned to `align_frames`.
+    pub(crate) fn allocate_aligned(
+        &mut self,
+        num_frames: usize,
+        align_frames: usize,
+    ) -> Result<PhysFrameRange> {
+        self.allocate_within(num_frames, align_frames, self.range.end)
+    }
+
+    /// Allocates `num_frames` contiguous frames which are aligned to `align_frames` and end
+    /// below `limit`.
+    ///
+    /// This is for devices and CPU modes that cannot address the whole physical memory.
+    pub(crate) fn allocate_below(
+        &mut self,
+        num_frames: usize,
+        align_frames: usize,
+        limit: PhysAddr,
+    ) -> Result<PhysFrameRange> {
+        let limit = cmp::min(self.range.end, PhysFrame::containing_address(limit));
+        self.allocate_within(num_frames, align_frames, limit)
+    }
+
+    fn allocate_within(
+        &mut self,
+        num_frames: usize,
+        align_frames: usize,
+        limit: PhysFrame,
+    ) -> Result<PhysFrameRange> {
+        let align = align_frames as u64 * BYTES_PER_FRAME;
         let mut start_frame = self.range.start;
         loop {
+            start_frame =
+                PhysFrame::containing_address(start_frame.start_address().align_up(align));
             let end_frame = start_frame + num_frames as u64;
-            if end_frame > self.range.end {
+            if end_frame > limit {
                 bail!(ErrorKind::NoEnoughMemory);
             }
 
@@ -130,11 +200,23 @@ impl BitmapMemoryManager {
         }
     }
 
-    // pub(crate) fn free(&mut self, range: PhysFrameRange) {
-    //     for frame in range {
-    //         self.set_bit(frame, false)
-    //     }
-    // }
+    pub(crate) fn free(&mut self, range: PhysFrameRange) {
+        for frame in range {
+            self.set_bit(frame, false)
+        }
+        // update range for faster allocation
+        if range.start < self.range.start {
+            self.range.start = range.start;
+        }
+    }
+
+    /// Returns the number of free frames.
+    pub(crate) fn count_free_frames(&self) -> u64 {
+        self.range
+            .into_iter()
+            .filter(|frame| !self.get_bit(*frame))
+            .count() as u64
+    }
 
     fn get_bit(&self, frame: PhysFrame) -> bool {
         let frame_index = frame.start_address().as_u64() / BYTES_PER_FRAME;
@@ -0,0 +1,115 @@
+//! Basic RAM sanity check on free physical frames.
+
+use crate::{memory, paging, time::Instant};
+use alloc::vec::Vec;
+use arrayvec::ArrayVec;
+use core::{mem, time::Duration};
+use x86_64::{structures::paging::frame::PhysFrameRange, PhysAddr};
+
+/// The number of frames allocated and tested at once (1 MiB).
+const BATCH_FRAMES: usize = 256;
+const PATTERNS: [u64; 4] = [
+    0x0000_0000_0000_0000,
+    0xffff_ffff_ffff_ffff,
+    0x5555_5555_5555_5555,
+    0xaaaa_aaaa_aaaa_aaaa,
+];
+const MAX_RECORDED_MISMATCHES: usize = 8;
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Mismatch {
+    pub(crate) addr: PhysAddr,
+    pub(crate) expected: u64,
+    pub(crate) actual: u64,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Report {
+    pub(crate) tested_bytes: u64,
+    /// Bytes written and read back in total.
+    pub(crate) transferred_bytes: u64,
+    pub(crate) elapsed: Duration,
+    pub(crate) mismatch_count: u64,
+    /// The first mismatches found.
+    pub(crate) mismatches: ArrayVec<Mismatch, MAX_RECORDED_MISMATCHES>,
+}
+
+impl Report {
+    fn record(&mut self, addr: PhysAddr, expected: u64, actual: u64) {
+        self.mismatch_count += 1;
+        let _ = self.mismatches.try_push(Mismatch {
+            addr,
+            expected,
+            actual,
+        });
+    }
+}
+
+/// Tests up to `limit` bytes of free physical memory.
+///
+/// Frames are allocated in batches until `limit` bytes are tested or no free frames remain,
+/// and all of them are freed at the end.
+pub(crate) fn run(limit: u64) -> Report {
+    let mut report = Report::default();
+    let mut batches = Vec::new();
+    let start = Instant::now();
+
+    while report.tested_bytes < limit {
+        let frames = match memory::with_memory_manager(|allocator| allocator.allocate(BATCH_FRAMES))
+        {
+            Ok(frames) => frames,
+            Err(_) => break,
+        };
+        test_frames(frames, &mut report);
+        batches.push(frames);
+    }
+
+    report.elapsed = start.elapsed();
+
+    memory::with_memory_manager(|allocator| {
+        for frames in batches {
+            allocator.free(frames);
+        }
+    });
+
+    report
+}
+
+fn test_frames(frames: PhysFrameRange, report: &mut Report) {
+    let start = frames.start.start_address();
+    let len = (frames.end - frames.start) * memory::BYTES_PER_FRAME;
+    let words = unsafe {
+        core::slice::from_raw_parts_mut(
+            paging::phys_to_virt(start).as_mut_ptr::<u64>(),
+            (len as usize) / mem::size_of::<u64>(),
+        )
+    };
+    let word_addr = |i: usize| start + i * mem::size_of::<u64>();
+
+    for pattern in PATTERNS {
+        for word in words.iter_mut() {
+            unsafe { (word as *mut u64).write_volatile(pattern) };
+        }
+        for (i, word) in words.iter().enumerate() {
+            let actual = unsafe { (word as *const u64).read_volatile() };
+            if actual != pattern {
+                report.record(word_addr(i), pattern, actual);
+            }
+        }
+    }
+
+    // each word holds its own address to detect address line faults
+    for (i, word) in words.iter_mut().enumerate() {
+        unsafe { (word as *mut u64).write_volatile(word_addr(i).as_u64()) };
+    }
+    for (i, word) in words.iter().enumerate() {
+        let expected = word_addr(i).as_u64();
+        let actual = unsafe { (word as *const u64).read_volatile() };
+        if actual != expected {
+            report.record(word_addr(i), expected, actual);
+        }
+    }
+
+    report.tested_bytes += len;
+    report.transferred_bytes += len * 2 * (PATTERNS.len() as u64 + 1);
+}
@@ -0,0 +1,51 @@
+use core::{mem, ptr};
+use x86_64::VirtAddr;
+
+/// Handle to a memory-mapped I/O register range.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct VolatileMmio {
+    base: VirtAddr,
+    size: u64,
+}
+
+impl VolatileMmio {
+    /// Creates a handle for the register range `[base, base + size)`.
+    ///
+    /// # Safety
+    ///
+    /// The caller must guarantee that the range is mapped to the device registers with
+    /// caching disabled, and that the mapping is kept while the handle is used.
+    pub(crate) unsafe fn new(base: VirtAddr, size: u64) -> Self {
+        Self { base, size }
+    }
+
+    pub(crate) fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Returns a handle for `size` bytes at `offset` bytes from the base address.
+    pub(crate) fn subrange(&self, offset: u64, size: u64) -> Self {
+        assert!(offset + size <= self.size);
+        Self {
+            base: self.base + offset,
+            size,
+        }
+    }
+
+    fn ptr<T>(&self, offset: u64) -> *mut T {
+        assert!(offset + mem::size_of::<T>() as u64 <= self.size);
+        let addr = self.base + offset;
+        assert!(addr.is_aligned(mem::align_of::<T>() as u64));
+        addr.as_mut_ptr()
+    }
+
+    /// Reads the register at `offset` bytes from the base address.
+    pub(crate) fn read<T: Copy>(&self, offset: u64) -> T {
+        unsafe { ptr::read_volatile(self.ptr(offset)) }
+    }
+
+    /// Writes the register at `offset` bytes from the base address.
+    pub(crate) fn write<T: Copy>(&self, offset: u64, value: T) {
+        unsafe { ptr::write_volatile(self.ptr(offset), value) }
+    }
+}
@@ -1,14 +1,17 @@
 use crate::{
+    device::DeviceId,
     graphics::{Color, Draw, Offset, Point, ScreenInfo},
     layer,
     prelude::*,
     sync::{mpsc, OnceCell},
     window::Window,
+    xhc,
 };
-use core::future::Future;
+use core::{convert::TryFrom, future::Future};
 use enumflags2::{bitflags, BitFlags};
 
-const TRANSPARENT_COLOR: Color = Color::RED;
+/// The drop shadow to the lower right of the cursor.
+const SHADOW_COLOR: Color = Color::BLACK.with_alpha(0x60);
 const MOUSE_CURSOR_WIDTH: usize = 15;
 const MOUSE_CURSOR_HEIGHT: usize = 24;
 const MOUSE_CURSOR_SIZE: Point<i32> =
@@ -45,35 +48,44 @@ const MOUSE_CURSOR_SHAPE: [[u8; MOUSE_CURSOR_WIDTH]; MOUSE_CURSOR_HEIGHT] = [
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub(crate) enum MouseButton {
-    Left = 0b001,
-    Right = 0b010,
-    Middle = 0b100,
+    Left = 0b00001,
+    Right = 0b00010,
+    Middle = 0b00100,
+    Back = 0b01000,
+    Forward = 0b10000,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 struct RawMouseEvent {
     buttons: BitFlags<MouseButton>,
     displacement: Offset<i32>,
+    wheel: i32,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub(crate) struct MouseEvent {
+    pub(crate) buttons: BitFlags<MouseButton>,
     pub(crate) down: BitFlags<MouseButton>,
     pub(crate) up: BitFlags<MouseButton>,
     pub(crate) pos: Point<i32>,
     pub(crate) pos_diff: Offset<i32>,
+    /// Wheel delta, positive when the wheel is rotated away from the user.
+    pub(crate) wheel: i32,
 }
 
 static MOUSE_EVENT_TX: OnceCell<mpsc::Sender<RawMouseEvent>> = OnceCell::uninit();
+static DEVICE_ID: OnceCell<DeviceId> = OnceCell::uninit();
 
-pub(crate) extern "C" fn observer(buttons: u8, displacement_x: i8, displacement_y: i8) {
+pub(crate) fn observer(buttons: u8, displacement_x: i8, displacement_y: i8, wheel: i8) {
     let buttons = BitFlags::<MouseButton>::from_bits_truncate(buttons);
     let event = RawMouseEvent {
         buttons,
         displacement: Offset::new(i32::from(displacement_x), i32::from(displacement_y)),
+        wheel: i32::from(wheel),
     };
 
-    let res = MOUSE_EVENT_TX.try_get().and_then(|tx| tx.send(event));
+    DEVICE_ID.get_or_init(|| xhc::register_usb_device("USB mouse", "usb-hid-mouse"));
+    let res = MOUSE_EVENT_TX.try_get().and_then(|tx| tx.try_send(event));
 
     if let Err(err) = res {
         error!("failed to enqueue to the queue: {}", err);
@@ -87,16 +99,28 @@ fn draw(drawer: &mut dyn Draw) {
             match ch {
                 b'@' => drawer.draw(p, Color::BLACK),
                 b'.' => drawer.draw(p, Color::WHITE),
-                b' ' => drawer.draw(p, TRANSPARENT_COLOR),
+                b' ' if is_cursor_pixel(dx - 1, dy - 1) => drawer.draw(p, SHADOW_COLOR),
+                b' ' => drawer.draw(p, Color::TRANSPARENT),
                 _ => {}
             }
         }
     }
 }
 
+fn is_cursor_pixel(x: i32, y: i32) -> bool {
+    let row = usize::try_from(y)
+        .ok()
+        .and_then(|y| MOUSE_CURSOR_SHAPE.get(y));
+    let x = usize::try_from(x).ok();
+    row.zip(x)
+        .and_then(|(row, x)| row.get(x))
+        .map_or(false, |ch| *ch != b' ')
+}
+
 pub(crate) fn handler_task() -> impl Future<Output = Result<()>> {
     // Initialize MOUSE_EVENT_TX before co-task starts
-    let (tx, mut rx) = mpsc::channel(100);
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    tx.register("mouse");
     MOUSE_EVENT_TX.init_once(|| tx);
 
     async move {
@@ -106,7 +130,7 @@ pub(crate) fn handler_task() -> impl Future<Output = Result<()>> {
         let mut window = Window::builder()
             .pos(cursor_pos)
             .size(MOUSE_CURSOR_SIZE)
-            .transparent_color(Some(TRANSPARENT_COLOR))
+            .alpha(true)
             .height(usize::MAX)
             .build()?;
 
@@ -120,10 +144,12 @@ pub(crate) fn handler_task() -> impl Future<Output = Result<()>> {
         tx.mouse_event(
             cursor_layer_id,
             MouseEvent {
+                buttons: BitFlags::empty(),
                 down: BitFlags::empty(),
                 up: BitFlags::empty(),
                 pos: cursor_pos,
                 pos_diff: Offset::new(0, 0),
+                wheel: 0,
             },
         )
         .await?;
@@ -148,10 +174,12 @@ pub(crate) fn handler_task() -> impl Future<Output = Result<()>> {
             tx.mouse_event(
                 cursor_layer_id,
                 MouseEvent {
+                    buttons,
                     down,
                     up,
                     pos: cursor_pos,
                     pos_diff,
+                    wheel: event.wheel,
                 },
             )
             .await?;
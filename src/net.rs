@@ -0,0 +1,363 @@
+//! Network stack.
+//!
+//! Device drivers queue received frames and call [`notify_received`], which schedules the
+//! processing of the frames by the softirq co-task. The frames are passed up through the link
+//! layer, IPv4 and the transport protocols. Packets are sent down to the device from the calling
+//! task.
+//!
+//! Only static IPv4 configuration is supported, and fragmented packets are dropped.
+
+pub(crate) use self::ipv4::Ipv4Addr;
+
+use crate::{
+    prelude::*,
+    softirq::{self, SoftIrq},
+    sync::SpinMutex,
+};
+use alloc::{boxed::Box, vec::Vec};
+use core::{
+    fmt, ptr,
+    sync::atomic::{AtomicU16, AtomicU64, Ordering},
+};
+use custom_debug_derive::Debug as CustomDebug;
+use x86_64::instructions::interrupts;
+
+mod arp;
+pub(crate) mod capture;
+pub(crate) mod echo;
+mod ethernet;
+pub(crate) mod http;
+pub(crate) mod icmp;
+pub(crate) mod ipv4;
+mod loopback;
+pub(crate) mod remote_shell;
+pub(crate) mod tcp;
+pub(crate) mod udp;
+
+/// The address of the guest on QEMU's user networking, which Ethernet interfaces use.
+pub(crate) const DEFAULT_ADDR: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 15);
+pub(crate) const DEFAULT_PREFIX_LEN: u8 = 24;
+pub(crate) const DEFAULT_GATEWAY: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 2);
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) struct MacAddr(pub(crate) [u8; 6]);
+
+impl MacAddr {
+    pub(crate) const BROADCAST: Self = Self([0xff; 6]);
+}
+
+impl fmt::Display for MacAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, byte) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, ":")?;
+            }
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for MacAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// Ports assigned to sockets bound to port 0 (RFC 6335).
+const FIRST_EPHEMERAL_PORT: u16 = 49152;
+
+/// Returns the first ephemeral port from `next` on which `is_used` returns `false`, and
+/// advances `next` past it.
+fn allocate_ephemeral_port(next: &AtomicU16, is_used: impl Fn(u16) -> bool) -> Result<u16> {
+    for _ in FIRST_EPHEMERAL_PORT..=u16::MAX {
+        let port = next.load(Ordering::Relaxed);
+        let following = port.checked_add(1).unwrap_or(FIRST_EPHEMERAL_PORT);
+        next.store(following, Ordering::Relaxed);
+        if !is_used(port) {
+            return Ok(port);
+        }
+    }
+    bail!(ErrorKind::NoFreePort)
+}
+
+/// An IPv4 address and a port number.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(crate) struct SocketAddrV4 {
+    addr: Ipv4Addr,
+    port: u16,
+}
+
+impl SocketAddrV4 {
+    pub(crate) const fn new(addr: Ipv4Addr, port: u16) -> Self {
+        Self { addr, port }
+    }
+
+    pub(crate) fn addr(&self) -> Ipv4Addr {
+        self.addr
+    }
+
+    pub(crate) fn port(&self) -> u16 {
+        self.port
+    }
+}
+
+impl fmt::Display for SocketAddrV4 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.addr, self.port)
+    }
+}
+
+impl fmt::Debug for SocketAddrV4 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// A network device driver.
+///
+/// The methods are called from tasks and the softirq co-task, so implementations lock their
+/// state with interrupts disabled.
+pub(crate) trait NetDevice: Send + Sync {
+    /// Sends a frame, which starts with the link-layer header.
+    fn transmit(&self, frame: &[u8]) -> Result<()>;
+    /// Takes the frames received since the last call.
+    fn receive(&self) -> Vec<Vec<u8>>;
+    /// Returns `true` if the link is up, which is assumed if the device cannot tell.
+    fn link_up(&self) -> bool {
+        true
+    }
+}
+
+/// The link layer of an interface.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Link {
+    /// Frames are bare IPv4 packets.
+    Loopback,
+    Ethernet(MacAddr),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Ipv4Config {
+    pub(crate) addr: Ipv4Addr,
+    pub(crate) prefix_len: u8,
+    pub(crate) gateway: Option<Ipv4Addr>,
+}
+
+impl Ipv4Config {
+    /// Returns `true` if `addr` is in the subnet of the interface.
+    pub(crate) fn contains(&self, addr: Ipv4Addr) -> bool {
+        addr.in_subnet(self.addr, self.prefix_len)
+    }
+
+    pub(crate) fn broadcast(&self) -> Ipv4Addr {
+        self.addr.subnet_broadcast(self.prefix_len)
+    }
+}
+
+/// Traffic counters of an interface.
+#[derive(Debug, Default)]
+pub(crate) struct Stats {
+    rx_packets: AtomicU64,
+    rx_bytes: AtomicU64,
+    tx_packets: AtomicU64,
+    tx_bytes: AtomicU64,
+}
+
+impl Stats {
+    pub(crate) fn rx(&self) -> (u64, u64) {
+        (
+            self.rx_packets.load(Ordering::Relaxed),
+            self.rx_bytes.load(Ordering::Relaxed),
+        )
+    }
+
+    pub(crate) fn tx(&self) -> (u64, u64) {
+        (
+            self.tx_packets.load(Ordering::Relaxed),
+            self.tx_bytes.load(Ordering::Relaxed),
+        )
+    }
+
+    fn count(packets: &AtomicU64, bytes: &AtomicU64, len: usize) {
+        packets.fetch_add(1, Ordering::Relaxed);
+        bytes.fetch_add(len as u64, Ordering::Relaxed);
+    }
+}
+
+#[derive(CustomDebug)]
+pub(crate) struct Interface {
+    pub(crate) name: &'static str,
+    pub(crate) link: Link,
+    #[debug(skip)]
+    device: Box<dyn NetDevice>,
+    config: SpinMutex<Ipv4Config>,
+    pub(crate) stats: Stats,
+}
+
+impl Interface {
+    pub(crate) fn config(&self) -> Ipv4Config {
+        interrupts::without_interrupts(|| *self.config.lock())
+    }
+
+    pub(crate) fn link_up(&self) -> bool {
+        self.device.link_up()
+    }
+
+    fn transmit(&self, frame: &[u8]) -> Result<()> {
+        capture::tap(self, capture::Direction::Out, frame);
+        self.device.transmit(frame)?;
+        Stats::count(&self.stats.tx_packets, &self.stats.tx_bytes, frame.len());
+        Ok(())
+    }
+
+    fn is_loopback(&self) -> bool {
+        self.link == Link::Loopback
+    }
+}
+
+static INTERFACES: SpinMutex<Vec<&'static Interface>> = SpinMutex::new(Vec::new());
+
+/// Registers a network interface, which is never removed.
+pub(crate) fn register(
+    name: &'static str,
+    link: Link,
+    device: Box<dyn NetDevice>,
+    config: Ipv4Config,
+) -> &'static Interface {
+    let iface: &'static Interface = Box::leak(Box::new(Interface {
+        name,
+        link,
+        device,
+        config: SpinMutex::new(config),
+        stats: Stats::default(),
+    }));
+    interrupts::without_interrupts(|| INTERFACES.lock().push(iface));
+    info!(
+        "net: {} {}/{} ({:?})",
+        name, config.addr, config.prefix_len, link
+    );
+    iface
+}
+
+pub(crate) fn interfaces() -> Vec<&'static Interface> {
+    interrupts::without_interrupts(|| INTERFACES.lock().clone())
+}
+
+/// Returns `true` if `addr` is assigned to an interface.
+pub(crate) fn is_own_addr(addr: Ipv4Addr) -> bool {
+    interfaces().iter().any(|iface| iface.config().addr == addr)
+}
+
+/// Returns `true` if packets to `addr` are received by this host.
+pub(crate) fn is_local(addr: Ipv4Addr) -> bool {
+    addr.is_loopback()
+        || addr.is_broadcast()
+        || interfaces().iter().any(|iface| {
+            let config = iface.config();
+            addr == config.addr || addr == config.broadcast()
+        })
+}
+
+/// Returns the interface to send packets to `dst` through, and the next hop.
+///
+/// Packets to the own addresses go through the loopback interface.
+pub(crate) fn route(dst: Ipv4Addr) -> Option<(&'static Interface, Ipv4Addr)> {
+    let interfaces = interfaces();
+    if dst.is_loopback() || is_own_addr(dst) {
+        return interfaces
+            .into_iter()
+            .find(|iface| iface.is_loopback())
+            .map(|iface| (iface, dst));
+    }
+    let external = || interfaces.iter().filter(|iface| !iface.is_loopback());
+    if let Some(iface) = external().find(|iface| iface.config().contains(dst) || dst.is_broadcast())
+    {
+        return Some((iface, dst));
+    }
+    external().find_map(|iface| iface.config().gateway.map(|gateway| (*iface, gateway)))
+}
+
+/// Sends an IPv4 packet to the next hop through `iface`.
+fn transmit_ipv4(iface: &'static Interface, next_hop: Ipv4Addr, packet: Vec<u8>) -> Result<()> {
+    match iface.link {
+        Link::Loopback => iface.transmit(&packet),
+        Link::Ethernet(mac) => arp::send_ipv4(iface, mac, next_hop, packet),
+    }
+}
+
+fn same_interface(a: &Interface, b: &Interface) -> bool {
+    ptr::eq(a, b)
+}
+
+fn process(iface: &'static Interface, frame: &[u8]) {
+    Stats::count(&iface.stats.rx_packets, &iface.stats.rx_bytes, frame.len());
+    capture::tap(iface, capture::Direction::In, frame);
+    match iface.link {
+        Link::Loopback => ipv4::receive(frame),
+        Link::Ethernet(mac) => ethernet::receive(iface, mac, frame),
+    }
+}
+
+/// Processes the received frames of all interfaces. This runs as the softirq handler.
+fn poll() {
+    for iface in interfaces() {
+        for frame in iface.device.receive() {
+            process(iface, &frame);
+        }
+    }
+}
+
+/// Schedules the processing of received frames.
+///
+/// Drivers call this when frames are received, also from interrupt handlers.
+pub(crate) fn notify_received() {
+    softirq::raise(SoftIrq::Net);
+}
+
+/// Registers the loopback interface. Other interfaces are registered by the device drivers.
+pub(crate) fn init() {
+    softirq::register(SoftIrq::Net, poll);
+    loopback::init();
+}
+
+/// The Internet checksum (RFC 1071), computed over data added in pieces.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Checksum {
+    sum: u64,
+    /// The data added so far has an odd length.
+    odd: bool,
+}
+
+impl Checksum {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn add(&mut self, data: &[u8]) -> &mut Self {
+        for &byte in data {
+            let value = if self.odd {
+                u64::from(byte)
+            } else {
+                u64::from(byte) << 8
+            };
+            self.sum += value;
+            self.odd = !self.odd;
+        }
+        self
+    }
+
+    /// Returns the checksum, which is zero if the data contains a valid checksum.
+    pub(crate) fn finish(&self) -> u16 {
+        let mut sum = self.sum;
+        while sum > 0xffff {
+            sum = (sum & 0xffff) + (sum >> 16);
+        }
+        !(sum as u16)
+    }
+}
+
+/// Computes the checksum of `data`.
+pub(crate) fn checksum(data: &[u8]) -> u16 {
+    Checksum::new().add(data).finish()
+}
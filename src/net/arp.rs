@@ -0,0 +1,156 @@
+//! Address Resolution Protocol.
+//!
+//! Resolved addresses are cached without expiry. Packets to unresolved addresses wait for the
+//! reply in a small queue, which drops the oldest packet when full.
+
+use super::{ethernet, Interface, Ipv4Addr, MacAddr};
+use crate::{prelude::*, sync::SpinMutex};
+use alloc::vec::Vec;
+use x86_64::instructions::interrupts;
+
+const HTYPE_ETHERNET: u16 = 1;
+const OP_REQUEST: u16 = 1;
+const OP_REPLY: u16 = 2;
+const PACKET_LEN: usize = 28;
+
+const MAX_ENTRIES: usize = 64;
+const MAX_PENDING: usize = 16;
+
+#[derive(Debug)]
+struct Pending {
+    iface: &'static Interface,
+    next_hop: Ipv4Addr,
+    packet: Vec<u8>,
+}
+
+static CACHE: SpinMutex<Vec<(Ipv4Addr, MacAddr)>> = SpinMutex::new(Vec::new());
+static PENDING: SpinMutex<Vec<Pending>> = SpinMutex::new(Vec::new());
+
+fn lookup(addr: Ipv4Addr) -> Option<MacAddr> {
+    interrupts::without_interrupts(|| {
+        CACHE
+            .lock()
+            .iter()
+            .find(|(cached, _)| *cached == addr)
+            .map(|(_, mac)| *mac)
+    })
+}
+
+/// Updates the entry of `addr`, or inserts it if `insert` is `true`.
+fn update(addr: Ipv4Addr, mac: MacAddr, insert: bool) {
+    interrupts::without_interrupts(|| {
+        let mut cache = CACHE.lock();
+        if let Some(entry) = cache.iter_mut().find(|(cached, _)| *cached == addr) {
+            entry.1 = mac;
+        } else if insert {
+            if cache.len() >= MAX_ENTRIES {
+                cache.remove(0);
+            }
+            cache.push((addr, mac));
+        }
+    });
+}
+
+fn send(
+    iface: &Interface,
+    mac: MacAddr,
+    op: u16,
+    target_mac: MacAddr,
+    target_addr: Ipv4Addr,
+) -> Result<()> {
+    let mut packet = Vec::with_capacity(PACKET_LEN);
+    packet.extend_from_slice(&HTYPE_ETHERNET.to_be_bytes());
+    packet.extend_from_slice(&ethernet::ETHERTYPE_IPV4.to_be_bytes());
+    packet.extend_from_slice(&[6, 4]);
+    packet.extend_from_slice(&op.to_be_bytes());
+    packet.extend_from_slice(&mac.0);
+    packet.extend_from_slice(&iface.config().addr.octets());
+    packet.extend_from_slice(&target_mac.0);
+    packet.extend_from_slice(&target_addr.octets());
+    let dst = if op == OP_REQUEST {
+        MacAddr::BROADCAST
+    } else {
+        target_mac
+    };
+    ethernet::send(iface, mac, dst, ethernet::ETHERTYPE_ARP, &packet)
+}
+
+/// Sends an IPv4 packet to `next_hop`, resolving its MAC address first if needed.
+pub(super) fn send_ipv4(
+    iface: &'static Interface,
+    mac: MacAddr,
+    next_hop: Ipv4Addr,
+    packet: Vec<u8>,
+) -> Result<()> {
+    let dst = if next_hop.is_broadcast() || next_hop == iface.config().broadcast() {
+        Some(MacAddr::BROADCAST)
+    } else {
+        lookup(next_hop)
+    };
+    if let Some(dst) = dst {
+        return ethernet::send(iface, mac, dst, ethernet::ETHERTYPE_IPV4, &packet);
+    }
+
+    interrupts::without_interrupts(|| {
+        let mut pending = PENDING.lock();
+        if pending.len() >= MAX_PENDING {
+            pending.remove(0);
+        }
+        pending.push(Pending {
+            iface,
+            next_hop,
+            packet,
+        });
+    });
+    send(iface, mac, OP_REQUEST, MacAddr([0; 6]), next_hop)
+}
+
+pub(super) fn receive(iface: &'static Interface, mac: MacAddr, packet: &[u8]) {
+    if packet.len() < PACKET_LEN {
+        return;
+    }
+    let read_u16 = |offset: usize| u16::from_be_bytes([packet[offset], packet[offset + 1]]);
+    if read_u16(0) != HTYPE_ETHERNET
+        || read_u16(2) != ethernet::ETHERTYPE_IPV4
+        || packet[4] != 6
+        || packet[5] != 4
+    {
+        return;
+    }
+    let op = read_u16(6);
+    let sender_mac = MacAddr([
+        packet[8], packet[9], packet[10], packet[11], packet[12], packet[13],
+    ]);
+    let sender_addr = Ipv4Addr::from_bytes(&packet[14..18]);
+    let target_addr = Ipv4Addr::from_bytes(&packet[24..28]);
+
+    // RFC 826: the sender is always cached if it is known, and added if the packet is for us
+    let for_us = target_addr == iface.config().addr;
+    update(sender_addr, sender_mac, for_us);
+    if for_us && op == OP_REQUEST {
+        if let Err(err) = send(iface, mac, OP_REPLY, sender_mac, sender_addr) {
+            debug!("arp: failed to reply to {}: {}", sender_addr, err);
+        }
+    }
+
+    let resolved = interrupts::without_interrupts(|| {
+        let mut pending = PENDING.lock();
+        let (resolved, rest) = pending.drain(..).partition::<Vec<_>, _>(|pending| {
+            pending.next_hop == sender_addr && super::same_interface(pending.iface, iface)
+        });
+        *pending = rest;
+        resolved
+    });
+    for pending in resolved {
+        let result = ethernet::send(
+            iface,
+            mac,
+            sender_mac,
+            ethernet::ETHERTYPE_IPV4,
+            &pending.packet,
+        );
+        if let Err(err) = result {
+            debug!("arp: failed to send a packet to {}: {}", sender_addr, err);
+        }
+    }
+}
@@ -0,0 +1,369 @@
+//! Packet capture.
+//!
+//! Frames are tapped when an interface sends them and when the device delivers them, before they
+//! are filtered by the destination address. Each [`Capture`] has its own queue; frames arriving
+//! while the queue is full are dropped and counted.
+
+use super::{
+    ethernet::{self, ETHERTYPE_ARP, ETHERTYPE_IPV4},
+    ipv4, Interface, Ipv4Addr, Link, MacAddr,
+};
+use crate::{
+    sync::{SpinMutex, WaitQueue},
+    time,
+};
+use alloc::{collections::VecDeque, format, string::String, sync::Arc, vec::Vec};
+use core::{
+    fmt::Write as _,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    time::Duration,
+};
+use x86_64::instructions::interrupts;
+
+const MAX_QUEUED: usize = 256;
+
+/// pcap link type of Ethernet frames.
+const LINKTYPE_ETHERNET: u32 = 1;
+const SNAPLEN: u32 = 65535;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Direction {
+    In,
+    Out,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Packet {
+    pub(crate) iface: &'static str,
+    pub(crate) link: Link,
+    pub(crate) direction: Direction,
+    /// The time elapsed from the Unix epoch, or from boot if the wall clock is not set.
+    pub(crate) timestamp: Duration,
+    pub(crate) data: Vec<u8>,
+}
+
+#[derive(Debug, Default)]
+struct Tap {
+    iface: Option<&'static str>,
+    queue: SpinMutex<VecDeque<Packet>>,
+    wait_queue: WaitQueue,
+    dropped: AtomicU64,
+}
+
+static TAPS: SpinMutex<Vec<Arc<Tap>>> = SpinMutex::new(Vec::new());
+/// The number of open captures, checked without locking on every frame.
+static TAP_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// A capture of the frames of an interface, or of all interfaces. Capturing stops on drop.
+#[derive(Debug)]
+pub(crate) struct Capture {
+    tap: Arc<Tap>,
+}
+
+impl Capture {
+    pub(crate) fn open(iface: Option<&'static str>) -> Self {
+        let tap = Arc::new(Tap {
+            iface,
+            ..Tap::default()
+        });
+        interrupts::without_interrupts(|| {
+            TAPS.lock().push(tap.clone());
+            TAP_COUNT.fetch_add(1, Ordering::Relaxed);
+        });
+        Self { tap }
+    }
+
+    /// Returns the next captured frame, waiting until one arrives.
+    pub(crate) async fn next(&self) -> Packet {
+        let tap = &self.tap;
+        tap.wait_queue
+            .wait_until(|| interrupts::without_interrupts(|| tap.queue.lock().pop_front()))
+            .await
+    }
+
+    /// Returns the number of frames dropped since the queue was full.
+    pub(crate) fn dropped(&self) -> u64 {
+        self.tap.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for Capture {
+    fn drop(&mut self) {
+        interrupts::without_interrupts(|| {
+            TAPS.lock().retain(|tap| !Arc::ptr_eq(tap, &self.tap));
+            TAP_COUNT.fetch_sub(1, Ordering::Relaxed);
+        });
+    }
+}
+
+/// Passes a frame sent or received by `iface` to the open captures.
+pub(super) fn tap(iface: &Interface, direction: Direction, frame: &[u8]) {
+    if TAP_COUNT.load(Ordering::Relaxed) == 0 {
+        return;
+    }
+    let taps = interrupts::without_interrupts(|| TAPS.lock().clone());
+    let timestamp = time::wall_clock().unwrap_or_else(time::uptime);
+    for tap in taps {
+        if tap.iface.map_or(false, |name| name != iface.name) {
+            continue;
+        }
+        let packet = Packet {
+            iface: iface.name,
+            link: iface.link,
+            direction,
+            timestamp,
+            data: frame.into(),
+        };
+        let queued = interrupts::without_interrupts(|| {
+            let mut queue = tap.queue.lock();
+            if queue.len() >= MAX_QUEUED {
+                return false;
+            }
+            queue.push_back(packet);
+            true
+        });
+        if queued {
+            tap.wait_queue.notify_all();
+        } else {
+            tap.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Returns the global header of a pcap file.
+pub(crate) fn pcap_header() -> Vec<u8> {
+    let mut header = Vec::with_capacity(24);
+    header.extend_from_slice(&0xa1b2_c3d4_u32.to_le_bytes());
+    header.extend_from_slice(&2_u16.to_le_bytes()); // version 2.4
+    header.extend_from_slice(&4_u16.to_le_bytes());
+    header.extend_from_slice(&0_i32.to_le_bytes()); // UTC
+    header.extend_from_slice(&0_u32.to_le_bytes());
+    header.extend_from_slice(&SNAPLEN.to_le_bytes());
+    header.extend_from_slice(&LINKTYPE_ETHERNET.to_le_bytes());
+    header
+}
+
+/// Appends a pcap record of `packet` to `buf`.
+///
+/// Loopback frames are given a dummy Ethernet header, since a pcap file has a single link type.
+pub(crate) fn write_pcap_record(buf: &mut Vec<u8>, packet: &Packet) {
+    let mut frame = Vec::new();
+    let data = match packet.link {
+        Link::Ethernet(_) => &packet.data,
+        Link::Loopback => {
+            frame.extend_from_slice(&[0; 12]);
+            frame.extend_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+            frame.extend_from_slice(&packet.data);
+            &frame
+        }
+    };
+    let len = data.len() as u32;
+    buf.extend_from_slice(&(packet.timestamp.as_secs() as u32).to_le_bytes());
+    buf.extend_from_slice(&packet.timestamp.subsec_micros().to_le_bytes());
+    buf.extend_from_slice(&len.to_le_bytes());
+    buf.extend_from_slice(&len.to_le_bytes());
+    buf.extend_from_slice(data);
+}
+
+/// Returns a one-line summary of the headers of `packet`, like `tcpdump`.
+pub(crate) fn summary(packet: &Packet) -> String {
+    match packet.link {
+        Link::Loopback => ipv4_summary(&packet.data),
+        Link::Ethernet(_) => ethernet_summary(&packet.data),
+    }
+}
+
+fn mac_at(data: &[u8]) -> MacAddr {
+    MacAddr([data[0], data[1], data[2], data[3], data[4], data[5]])
+}
+
+fn ipv4_addr_at(data: &[u8]) -> Ipv4Addr {
+    Ipv4Addr::from_bytes(&data[..4])
+}
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([data[offset], data[offset + 1]])
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+fn ethernet_summary(frame: &[u8]) -> String {
+    if frame.len() < ethernet::HEADER_LEN {
+        return format!("truncated frame, length {}", frame.len());
+    }
+    let dst = mac_at(&frame[0..]);
+    let src = mac_at(&frame[6..]);
+    let payload = &frame[ethernet::HEADER_LEN..];
+    let summary = match u16_at(frame, 12) {
+        ETHERTYPE_IPV4 => ipv4_summary(payload),
+        ETHERTYPE_ARP => arp_summary(payload),
+        ethertype => format!("ethertype {:#06x}, length {}", ethertype, payload.len()),
+    };
+    format!("{} > {}, {}", src, dst, summary)
+}
+
+fn arp_summary(packet: &[u8]) -> String {
+    if packet.len() < 28 {
+        return format!("ARP, truncated, length {}", packet.len());
+    }
+    let sender_mac = mac_at(&packet[8..]);
+    let sender = ipv4_addr_at(&packet[14..]);
+    let target = ipv4_addr_at(&packet[24..]);
+    match u16_at(packet, 6) {
+        1 => format!("ARP, Request who-has {} tell {}", target, sender),
+        2 => format!("ARP, Reply {} is-at {}", sender, sender_mac),
+        op => format!("ARP, operation {}", op),
+    }
+}
+
+fn ipv4_summary(packet: &[u8]) -> String {
+    if packet.len() < ipv4::HEADER_LEN || packet[0] >> 4 != 4 {
+        return format!("IP, truncated, length {}", packet.len());
+    }
+    let header_len = usize::from(packet[0] & 0xf) * 4;
+    let total_len = usize::from(u16_at(packet, 2));
+    if header_len < ipv4::HEADER_LEN || total_len < header_len || packet.len() < total_len {
+        return format!("IP, bad length {}", packet.len());
+    }
+    let src = ipv4_addr_at(&packet[12..]);
+    let dst = ipv4_addr_at(&packet[16..]);
+    let payload = &packet[header_len..total_len];
+    let fragmented = u16_at(packet, 6) & 0x3fff != 0;
+
+    match packet[9] {
+        _ if fragmented => format!("IP {} > {}: fragment, length {}", src, dst, payload.len()),
+        ipv4::PROTOCOL_ICMP => format!("IP {} > {}: {}", src, dst, icmp_summary(payload)),
+        ipv4::PROTOCOL_UDP if payload.len() >= 8 => format!(
+            "IP {}.{} > {}.{}: UDP, length {}",
+            src,
+            u16_at(payload, 0),
+            dst,
+            u16_at(payload, 2),
+            payload.len() - 8
+        ),
+        ipv4::PROTOCOL_TCP if payload.len() >= 20 => format!(
+            "IP {}.{} > {}.{}: {}",
+            src,
+            u16_at(payload, 0),
+            dst,
+            u16_at(payload, 2),
+            tcp_summary(payload)
+        ),
+        protocol => format!(
+            "IP {} > {}: protocol {}, length {}",
+            src,
+            dst,
+            protocol,
+            payload.len()
+        ),
+    }
+}
+
+fn icmp_summary(message: &[u8]) -> String {
+    if message.len() < 8 {
+        return format!("ICMP, truncated, length {}", message.len());
+    }
+    let (id, seq) = (u16_at(message, 4), u16_at(message, 6));
+    match message[0] {
+        0 => format!(
+            "ICMP echo reply, id {}, seq {}, length {}",
+            id,
+            seq,
+            message.len()
+        ),
+        8 => format!(
+            "ICMP echo request, id {}, seq {}, length {}",
+            id,
+            seq,
+            message.len()
+        ),
+        3 => format!("ICMP destination unreachable, code {}", message[1]),
+        ty => format!("ICMP type {}, length {}", ty, message.len()),
+    }
+}
+
+fn tcp_summary(segment: &[u8]) -> String {
+    const FLAGS: [(u8, char); 5] = [
+        (0x02, 'S'),
+        (0x01, 'F'),
+        (0x08, 'P'),
+        (0x04, 'R'),
+        (0x10, '.'),
+    ];
+
+    let data_offset = usize::from(segment[12] >> 4) * 4;
+    let bits = segment[13];
+    let mut flags = String::new();
+    for (bit, ch) in FLAGS {
+        if bits & bit != 0 {
+            flags.push(ch);
+        }
+    }
+    let mut summary = format!("Flags [{}], seq {}", flags, u32_at(segment, 4));
+    if bits & 0x10 != 0 {
+        let _ = write!(summary, ", ack {}", u32_at(segment, 8));
+    }
+    let _ = write!(
+        summary,
+        ", win {}, length {}",
+        u16_at(segment, 14),
+        segment.len().saturating_sub(data_offset)
+    );
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    fn packet(link: Link, data: &[u8]) -> Packet {
+        Packet {
+            iface: "test",
+            link,
+            direction: Direction::In,
+            timestamp: Duration::from_micros(1_500_000),
+            data: data.into(),
+        }
+    }
+
+    #[test_case]
+    fn arp_request() {
+        let mut frame = Vec::new();
+        frame.extend_from_slice(&[0xff; 6]);
+        frame.extend_from_slice(&[0x52, 0x54, 0, 0x12, 0x34, 0x56]);
+        frame.extend_from_slice(&ETHERTYPE_ARP.to_be_bytes());
+        frame.extend_from_slice(&[0, 1, 8, 0, 6, 4, 0, 1]);
+        frame.extend_from_slice(&[0x52, 0x54, 0, 0x12, 0x34, 0x56, 10, 0, 2, 15]);
+        frame.extend_from_slice(&[0, 0, 0, 0, 0, 0, 10, 0, 2, 2]);
+        assert_eq!(
+            summary(&packet(Link::Ethernet(MacAddr([0; 6])), &frame)),
+            "52:54:00:12:34:56 > ff:ff:ff:ff:ff:ff, ARP, Request who-has 10.0.2.2 tell 10.0.2.15"
+        );
+    }
+
+    #[test_case]
+    fn loopback_udp() {
+        let mut data = vec![0x45, 0, 0, 32, 0, 0, 0x40, 0, 64, ipv4::PROTOCOL_UDP, 0, 0];
+        data.extend_from_slice(&[127, 0, 0, 1, 127, 0, 0, 1]);
+        data.extend_from_slice(&[0xc0, 0x00, 0, 7, 0, 12, 0, 0, b'p', b'i', b'n', b'g']);
+        let packet = packet(Link::Loopback, &data);
+        assert_eq!(
+            summary(&packet),
+            "IP 127.0.0.1.49152 > 127.0.0.1.7: UDP, length 4"
+        );
+
+        let mut pcap = Vec::new();
+        write_pcap_record(&mut pcap, &packet);
+        assert_eq!(pcap.len(), 16 + 14 + data.len());
+        assert_eq!(pcap[0..8], [1, 0, 0, 0, 0x20, 0xa1, 0x07, 0]);
+        assert_eq!(pcap[16 + 12..16 + 14], [0x08, 0x00]);
+    }
+}
@@ -0,0 +1,66 @@
+//! Echo service (RFC 862) on TCP and UDP port 7, for testing the network stack.
+//!
+//! With QEMU's user networking, the service is forwarded to port 10007 of the host.
+
+use super::{
+    tcp::{TcpListener, TcpStream},
+    udp::{self, UdpSocket},
+};
+use crate::{
+    co_task::{self, CoTask},
+    prelude::*,
+    sync::Semaphore,
+};
+use core::future::Future;
+
+const PORT: u16 = 7;
+const BUFFER_SIZE: usize = 1024;
+const MAX_CONNECTIONS: usize = 8;
+
+static CONNECTIONS: Semaphore = Semaphore::new(MAX_CONNECTIONS);
+
+async fn serve(stream: TcpStream) -> Result<()> {
+    let mut buf = [0; BUFFER_SIZE];
+    loop {
+        let len = stream.read(&mut buf).await?;
+        if len == 0 {
+            return Ok(());
+        }
+        stream.write(&buf[..len]).await?;
+    }
+}
+
+pub(crate) fn handler_task() -> impl Future<Output = Result<()>> {
+    let listener = TcpListener::bind(PORT);
+    async move {
+        let listener = listener?;
+        loop {
+            // connections beyond the limit wait in the backlog of the listener
+            let permit = CONNECTIONS.acquire_async().await;
+            let stream = listener.accept().await;
+            let peer = stream.peer_addr();
+            debug!("echo: connected from {}", peer);
+            co_task::spawn_balanced(CoTask::new(async move {
+                if let Err(err) = serve(stream).await {
+                    debug!("echo: connection from {} failed: {}", peer, err);
+                }
+                drop(permit);
+            }));
+        }
+    }
+}
+
+/// Sends each datagram back to its sender.
+pub(crate) fn udp_handler_task() -> impl Future<Output = Result<()>> {
+    let socket = UdpSocket::bind(PORT);
+    async move {
+        let socket = socket?;
+        let mut buf = [0; udp::MAX_PAYLOAD_LEN];
+        loop {
+            let (len, peer) = socket.recv_from(&mut buf).await;
+            if let Err(err) = socket.send_to(&buf[..len], peer) {
+                debug!("echo: reply to {} failed: {}", peer, err);
+            }
+        }
+    }
+}
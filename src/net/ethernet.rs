@@ -0,0 +1,44 @@
+//! Ethernet II framing.
+
+use super::{arp, ipv4, Interface, MacAddr};
+use crate::prelude::*;
+use alloc::vec::Vec;
+
+pub(super) const ETHERTYPE_IPV4: u16 = 0x0800;
+pub(super) const ETHERTYPE_ARP: u16 = 0x0806;
+
+pub(super) const HEADER_LEN: usize = 14;
+/// Shorter frames are padded, since the FCS is not counted.
+const MIN_FRAME_LEN: usize = 60;
+
+pub(super) fn send(
+    iface: &Interface,
+    src: MacAddr,
+    dst: MacAddr,
+    ethertype: u16,
+    payload: &[u8],
+) -> Result<()> {
+    let mut frame = Vec::with_capacity(usize::max(HEADER_LEN + payload.len(), MIN_FRAME_LEN));
+    frame.extend_from_slice(&dst.0);
+    frame.extend_from_slice(&src.0);
+    frame.extend_from_slice(&ethertype.to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame.resize(usize::max(frame.len(), MIN_FRAME_LEN), 0);
+    iface.transmit(&frame)
+}
+
+pub(super) fn receive(iface: &'static Interface, mac: MacAddr, frame: &[u8]) {
+    if frame.len() < HEADER_LEN {
+        return;
+    }
+    let dst = MacAddr([frame[0], frame[1], frame[2], frame[3], frame[4], frame[5]]);
+    if dst != mac && dst != MacAddr::BROADCAST {
+        return;
+    }
+    let payload = &frame[HEADER_LEN..];
+    match u16::from_be_bytes([frame[12], frame[13]]) {
+        ETHERTYPE_IPV4 => ipv4::receive(payload),
+        ETHERTYPE_ARP => arp::receive(iface, mac, payload),
+        _ => {}
+    }
+}
@@ -0,0 +1,239 @@
+//! A minimal HTTP/1.1 client.
+//!
+//! Only `GET` of `http` URLs is supported. There is no name resolution, so the host must be an
+//! IPv4 address or `localhost`. The connection is closed after each request, and redirects are
+//! not followed.
+
+use super::{tcp::TcpStream, Ipv4Addr, SocketAddrV4};
+use crate::prelude::*;
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::{fmt, str};
+
+const DEFAULT_PORT: u16 = 80;
+/// Responses longer than this are rejected, since the whole response is kept in memory.
+const MAX_RESPONSE_LEN: usize = 4 * 1024 * 1024;
+
+/// An `http` URL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Url {
+    host: String,
+    addr: Ipv4Addr,
+    port: u16,
+    path: String,
+}
+
+impl Url {
+    pub(crate) fn parse(s: &str) -> Result<Self> {
+        let rest = s.strip_prefix("http://").ok_or(ErrorKind::InvalidUrl)?;
+        let (authority, path) = match rest.find('/') {
+            Some(index) => rest.split_at(index),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.split_once(':') {
+            Some((host, port)) => (host, port.parse().map_err(|_| ErrorKind::InvalidUrl)?),
+            None => (authority, DEFAULT_PORT),
+        };
+        let addr = if host.eq_ignore_ascii_case("localhost") {
+            Ipv4Addr::LOCALHOST
+        } else {
+            host.parse().map_err(|_| ErrorKind::InvalidUrl)?
+        };
+        Ok(Self {
+            host: host.to_string(),
+            addr,
+            port,
+            path: path.to_string(),
+        })
+    }
+
+    pub(crate) fn socket_addr(&self) -> SocketAddrV4 {
+        SocketAddrV4::new(self.addr, self.port)
+    }
+}
+
+impl fmt::Display for Url {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "http://{}", self.host)?;
+        if self.port != DEFAULT_PORT {
+            write!(f, ":{}", self.port)?;
+        }
+        write!(f, "{}", self.path)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Response {
+    pub(crate) status: u16,
+    pub(crate) reason: String,
+    pub(crate) body: Vec<u8>,
+}
+
+impl Response {
+    pub(crate) fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+}
+
+/// Sends a `GET` request, and reads the whole response.
+pub(crate) async fn get(url: &Url) -> Result<Response> {
+    let stream = TcpStream::connect(url.socket_addr()).await?;
+
+    let host = if url.port == DEFAULT_PORT {
+        url.host.clone()
+    } else {
+        format!("{}:{}", url.host, url.port)
+    };
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: sabios\r\nAccept: */*\r\nConnection: close\r\n\r\n",
+        url.path, host
+    );
+    stream.write(request.as_bytes()).await?;
+
+    let mut data = Vec::new();
+    let mut buf = [0; 1024];
+    loop {
+        let len = stream.read(&mut buf).await?;
+        if len == 0 {
+            break;
+        }
+        if data.len() + len > MAX_RESPONSE_LEN {
+            bail!(ErrorKind::Full);
+        }
+        data.extend_from_slice(&buf[..len]);
+    }
+    parse_response(&data)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn parse_response(data: &[u8]) -> Result<Response> {
+    let header_end = find(data, b"\r\n\r\n").ok_or(ErrorKind::InvalidHttpResponse)?;
+    let head = str::from_utf8(&data[..header_end]).map_err(|_| ErrorKind::InvalidHttpResponse)?;
+    let mut lines = head.split("\r\n");
+
+    // e.g. "HTTP/1.1 200 OK"
+    let status_line = lines.next().unwrap_or("");
+    let mut parts = status_line.splitn(3, ' ');
+    if !parts.next().unwrap_or("").starts_with("HTTP/1.") {
+        bail!(ErrorKind::InvalidHttpResponse);
+    }
+    let status = parts
+        .next()
+        .and_then(|status| status.parse().ok())
+        .ok_or(ErrorKind::InvalidHttpResponse)?;
+    let reason = parts.next().unwrap_or("").to_string();
+
+    let mut content_length = None;
+    let mut chunked = false;
+    for line in lines {
+        let (name, value) = line.split_once(':').ok_or(ErrorKind::InvalidHttpResponse)?;
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("Content-Length") {
+            let len = value
+                .parse::<usize>()
+                .map_err(|_| ErrorKind::InvalidHttpResponse)?;
+            content_length = Some(len);
+        } else if name.eq_ignore_ascii_case("Transfer-Encoding") {
+            chunked = value.eq_ignore_ascii_case("chunked");
+        }
+    }
+
+    let body = &data[header_end + 4..];
+    let body = if chunked {
+        decode_chunked(body)?
+    } else if let Some(len) = content_length {
+        // the connection was closed before the whole body was received
+        if body.len() < len {
+            bail!(ErrorKind::InvalidHttpResponse);
+        }
+        body[..len].to_vec()
+    } else {
+        body.to_vec()
+    };
+
+    Ok(Response {
+        status,
+        reason,
+        body,
+    })
+}
+
+/// Decodes a body of the chunked transfer coding. Chunk extensions and trailers are ignored.
+fn decode_chunked(mut data: &[u8]) -> Result<Vec<u8>> {
+    let mut body = Vec::new();
+    loop {
+        let line_end = find(data, b"\r\n").ok_or(ErrorKind::InvalidHttpResponse)?;
+        let size = str::from_utf8(&data[..line_end])
+            .ok()
+            .and_then(|line| line.split(';').next())
+            .and_then(|size| usize::from_str_radix(size.trim(), 16).ok())
+            .ok_or(ErrorKind::InvalidHttpResponse)?;
+        data = &data[line_end + 2..];
+        if size == 0 {
+            return Ok(body);
+        }
+        if data.len() < size + 2 || &data[size..size + 2] != b"\r\n" {
+            bail!(ErrorKind::InvalidHttpResponse);
+        }
+        body.extend_from_slice(&data[..size]);
+        data = &data[size + 2..];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn parse_url() {
+        #[allow(clippy::unwrap_used)]
+        let url = Url::parse("http://10.0.2.2:8080/index.html").unwrap();
+        assert_eq!(
+            url.socket_addr(),
+            SocketAddrV4::new(Ipv4Addr::new(10, 0, 2, 2), 8080)
+        );
+        assert_eq!(url.path, "/index.html");
+
+        #[allow(clippy::unwrap_used)]
+        let url = Url::parse("http://localhost").unwrap();
+        assert_eq!(
+            url.socket_addr(),
+            SocketAddrV4::new(Ipv4Addr::LOCALHOST, 80)
+        );
+        assert_eq!(url.path, "/");
+
+        assert!(Url::parse("https://10.0.2.2/").is_err());
+        assert!(Url::parse("http://example.com/").is_err());
+    }
+
+    #[test_case]
+    fn parse_chunked_response() {
+        let data = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n7;ext\r\n, world\r\n0\r\n\r\n";
+        #[allow(clippy::unwrap_used)]
+        let response = parse_response(data).unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.reason, "OK");
+        assert_eq!(response.body, b"hello, world");
+    }
+
+    #[test_case]
+    fn parse_content_length() {
+        let data = b"HTTP/1.0 404 Not Found\r\ncontent-length: 3\r\n\r\nabcdef";
+        #[allow(clippy::unwrap_used)]
+        let response = parse_response(data).unwrap();
+        assert_eq!(response.status, 404);
+        assert!(!response.is_success());
+        assert_eq!(response.body, b"abc");
+
+        let data = b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nabc";
+        assert!(parse_response(data).is_err());
+    }
+}
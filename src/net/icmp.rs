@@ -0,0 +1,114 @@
+//! Internet Control Message Protocol.
+//!
+//! Echo requests are answered, and echo replies complete the requests made by [`echo`].
+
+use super::{
+    ipv4::{self, Header},
+    Ipv4Addr,
+};
+use crate::{
+    prelude::*,
+    sync::{oneshot, SpinMutex},
+    time::Instant,
+};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU16, Ordering};
+use x86_64::instructions::interrupts;
+
+const TYPE_ECHO_REPLY: u8 = 0;
+const TYPE_ECHO_REQUEST: u8 = 8;
+
+const HEADER_LEN: usize = 8;
+
+/// A received echo reply.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct EchoReply {
+    pub(crate) src: Ipv4Addr,
+    pub(crate) ttl: u8,
+    /// The length of the ICMP message.
+    pub(crate) len: usize,
+    pub(crate) received: Instant,
+}
+
+type Waiter = (u16, u16, oneshot::Sender<EchoReply>);
+
+static WAITERS: SpinMutex<Vec<Waiter>> = SpinMutex::new(Vec::new());
+static NEXT_ID: AtomicU16 = AtomicU16::new(1);
+
+/// Returns an identifier for a series of echo requests.
+pub(crate) fn new_id() -> u16 {
+    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+fn message(ty: u8, id: u16, seq: u16, data: &[u8]) -> Vec<u8> {
+    let mut message = Vec::with_capacity(HEADER_LEN + data.len());
+    message.extend_from_slice(&[ty, 0, 0, 0]);
+    message.extend_from_slice(&id.to_be_bytes());
+    message.extend_from_slice(&seq.to_be_bytes());
+    message.extend_from_slice(data);
+    let checksum = super::checksum(&message);
+    message[2..4].copy_from_slice(&checksum.to_be_bytes());
+    message
+}
+
+/// Sends an echo request to `dst`. The returned receiver completes when the reply arrives.
+///
+/// Dropping the receiver abandons the request.
+pub(crate) fn echo(
+    dst: Ipv4Addr,
+    id: u16,
+    seq: u16,
+    data: &[u8],
+) -> Result<oneshot::Receiver<EchoReply>> {
+    let (tx, rx) = oneshot::channel();
+    interrupts::without_interrupts(|| {
+        let mut waiters = WAITERS.lock();
+        waiters.retain(|(_, _, tx)| !tx.is_canceled());
+        waiters.push((id, seq, tx));
+    });
+    ipv4::send(
+        dst,
+        ipv4::PROTOCOL_ICMP,
+        &message(TYPE_ECHO_REQUEST, id, seq, data),
+    )?;
+    Ok(rx)
+}
+
+pub(super) fn receive(header: &Header, message: &[u8]) {
+    if message.len() < HEADER_LEN || super::checksum(message) != 0 {
+        return;
+    }
+    let id = u16::from_be_bytes([message[4], message[5]]);
+    let seq = u16::from_be_bytes([message[6], message[7]]);
+    match message[0] {
+        TYPE_ECHO_REQUEST => {
+            // requests to broadcast addresses are not answered
+            if header.dst.is_broadcast() || !super::is_own_addr(header.dst) {
+                return;
+            }
+            let reply = self::message(TYPE_ECHO_REPLY, id, seq, &message[HEADER_LEN..]);
+            if let Err(err) = ipv4::send(header.src, ipv4::PROTOCOL_ICMP, &reply) {
+                debug!("icmp: failed to reply to {}: {}", header.src, err);
+            }
+        }
+        TYPE_ECHO_REPLY => {
+            let reply = EchoReply {
+                src: header.src,
+                ttl: header.ttl,
+                len: message.len(),
+                received: Instant::now(),
+            };
+            let waiter = interrupts::without_interrupts(|| {
+                let mut waiters = WAITERS.lock();
+                let index = waiters
+                    .iter()
+                    .position(|(wid, wseq, _)| *wid == id && *wseq == seq)?;
+                Some(waiters.swap_remove(index))
+            });
+            if let Some((_, _, tx)) = waiter {
+                let _ = tx.send(reply);
+            }
+        }
+        _ => {}
+    }
+}
@@ -0,0 +1,276 @@
+//! Internet Protocol version 4.
+
+use super::{icmp, tcp, udp, Checksum, Interface};
+use crate::prelude::*;
+use alloc::vec::Vec;
+use core::{
+    fmt,
+    str::FromStr,
+    sync::atomic::{AtomicU16, Ordering},
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(crate) struct Ipv4Addr([u8; 4]);
+
+impl Ipv4Addr {
+    pub(crate) const UNSPECIFIED: Self = Self::new(0, 0, 0, 0);
+    pub(crate) const LOCALHOST: Self = Self::new(127, 0, 0, 1);
+    pub(crate) const BROADCAST: Self = Self::new(255, 255, 255, 255);
+
+    pub(crate) const fn new(a: u8, b: u8, c: u8, d: u8) -> Self {
+        Self([a, b, c, d])
+    }
+
+    /// Reads an address from the first four bytes of `bytes`.
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Self {
+        Self([bytes[0], bytes[1], bytes[2], bytes[3]])
+    }
+
+    pub(crate) fn octets(self) -> [u8; 4] {
+        self.0
+    }
+
+    fn to_u32(self) -> u32 {
+        u32::from_be_bytes(self.0)
+    }
+
+    fn netmask(prefix_len: u8) -> u32 {
+        u32::MAX
+            .checked_shl(32 - u32::from(prefix_len))
+            .unwrap_or(0)
+    }
+
+    pub(crate) fn is_loopback(self) -> bool {
+        self.0[0] == 127
+    }
+
+    pub(crate) fn is_broadcast(self) -> bool {
+        self == Self::BROADCAST
+    }
+
+    /// Returns `true` if `self` is in the subnet of `addr` with the prefix of `prefix_len` bits.
+    pub(crate) fn in_subnet(self, addr: Self, prefix_len: u8) -> bool {
+        let mask = Self::netmask(prefix_len);
+        self.to_u32() & mask == addr.to_u32() & mask
+    }
+
+    /// Returns the broadcast address of the subnet of `self`.
+    pub(crate) fn subnet_broadcast(self, prefix_len: u8) -> Self {
+        Self((self.to_u32() | !Self::netmask(prefix_len)).to_be_bytes())
+    }
+}
+
+impl FromStr for Ipv4Addr {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut octets = [0; 4];
+        let mut parts = s.split('.');
+        for octet in &mut octets {
+            let part = parts.next().ok_or(ErrorKind::InvalidIpv4Addr)?;
+            if part.is_empty() || part.len() > 3 || !part.bytes().all(|b| b.is_ascii_digit()) {
+                bail!(ErrorKind::InvalidIpv4Addr);
+            }
+            *octet = part.parse().map_err(|_| ErrorKind::InvalidIpv4Addr)?;
+        }
+        if parts.next().is_some() {
+            bail!(ErrorKind::InvalidIpv4Addr);
+        }
+        Ok(Self(octets))
+    }
+}
+
+impl fmt::Display for Ipv4Addr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [o0, o1, o2, o3] = self.0;
+        write!(f, "{}.{}.{}.{}", o0, o1, o2, o3)
+    }
+}
+
+impl fmt::Debug for Ipv4Addr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+pub(crate) const PROTOCOL_ICMP: u8 = 1;
+pub(crate) const PROTOCOL_TCP: u8 = 6;
+pub(crate) const PROTOCOL_UDP: u8 = 17;
+
+pub(super) const HEADER_LEN: usize = 20;
+const VERSION_IHL: u8 = 0x45;
+const DEFAULT_TTL: u8 = 64;
+/// Don't Fragment
+const FLAG_DF: u16 = 1 << 14;
+/// More Fragments
+const FLAG_MF: u16 = 1 << 13;
+const FRAGMENT_OFFSET_MASK: u16 = 0x1fff;
+
+/// The largest payload sent, which fits in an Ethernet frame since packets are not fragmented.
+pub(crate) const MAX_PAYLOAD_LEN: usize = 1500 - HEADER_LEN;
+
+static NEXT_ID: AtomicU16 = AtomicU16::new(1);
+
+/// The header fields of a received packet passed to the upper layers.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Header {
+    pub(crate) src: Ipv4Addr,
+    pub(crate) dst: Ipv4Addr,
+    pub(crate) protocol: u8,
+    pub(crate) ttl: u8,
+}
+
+/// The way packets to a destination are sent.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Route {
+    iface: &'static Interface,
+    next_hop: Ipv4Addr,
+    /// The source address of the packets, which the transport protocols need for checksums.
+    pub(crate) src: Ipv4Addr,
+    pub(crate) dst: Ipv4Addr,
+}
+
+/// Looks up the route to `dst`. The source address is that of the interface routed through.
+pub(crate) fn route(dst: Ipv4Addr) -> Result<Route> {
+    let (iface, next_hop) = super::route(dst).ok_or(ErrorKind::NoRoute)?;
+    // packets to the own addresses are sent from the same address
+    let src = if super::is_own_addr(dst) {
+        dst
+    } else {
+        iface.config().addr
+    };
+    Ok(Route {
+        iface,
+        next_hop,
+        src,
+        dst,
+    })
+}
+
+/// Sends `payload` to `dst`.
+pub(crate) fn send(dst: Ipv4Addr, protocol: u8, payload: &[u8]) -> Result<()> {
+    send_via(&route(dst)?, protocol, payload)
+}
+
+/// Sends `payload` along `route`.
+pub(crate) fn send_via(route: &Route, protocol: u8, payload: &[u8]) -> Result<()> {
+    let Route {
+        iface,
+        next_hop,
+        src,
+        dst,
+    } = *route;
+    if payload.len() > MAX_PAYLOAD_LEN {
+        bail!(ErrorKind::PacketTooLarge);
+    }
+    let total_len = (HEADER_LEN + payload.len()) as u16;
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+
+    let mut packet = Vec::with_capacity(HEADER_LEN + payload.len());
+    packet.extend_from_slice(&[VERSION_IHL, 0]);
+    packet.extend_from_slice(&total_len.to_be_bytes());
+    packet.extend_from_slice(&id.to_be_bytes());
+    packet.extend_from_slice(&FLAG_DF.to_be_bytes());
+    packet.extend_from_slice(&[DEFAULT_TTL, protocol, 0, 0]);
+    packet.extend_from_slice(&src.octets());
+    packet.extend_from_slice(&dst.octets());
+    let checksum = super::checksum(&packet);
+    packet[10..12].copy_from_slice(&checksum.to_be_bytes());
+    packet.extend_from_slice(payload);
+
+    super::transmit_ipv4(iface, next_hop, packet)
+}
+
+pub(super) fn receive(packet: &[u8]) {
+    if packet.len() < HEADER_LEN || packet[0] >> 4 != 4 {
+        return;
+    }
+    let header_len = usize::from(packet[0] & 0xf) * 4;
+    let total_len = usize::from(u16::from_be_bytes([packet[2], packet[3]]));
+    if header_len < HEADER_LEN || total_len < header_len || total_len > packet.len() {
+        return;
+    }
+    if super::checksum(&packet[..header_len]) != 0 {
+        return;
+    }
+    let flags = u16::from_be_bytes([packet[6], packet[7]]);
+    if flags & FLAG_MF != 0 || flags & FRAGMENT_OFFSET_MASK != 0 {
+        // fragments are not reassembled
+        return;
+    }
+    let header = Header {
+        src: Ipv4Addr::from_bytes(&packet[12..16]),
+        dst: Ipv4Addr::from_bytes(&packet[16..20]),
+        protocol: packet[9],
+        ttl: packet[8],
+    };
+    if !super::is_local(header.dst) {
+        return;
+    }
+    // the padding of short Ethernet frames is removed
+    let payload = &packet[header_len..total_len];
+    match header.protocol {
+        PROTOCOL_ICMP => icmp::receive(&header, payload),
+        PROTOCOL_TCP => tcp::receive(&header, payload),
+        PROTOCOL_UDP => udp::receive(&header, payload),
+        _ => {}
+    }
+}
+
+/// Starts the checksum of a transport protocol with the pseudo-header of the packet.
+pub(super) fn pseudo_header_checksum(
+    src: Ipv4Addr,
+    dst: Ipv4Addr,
+    protocol: u8,
+    len: usize,
+) -> Checksum {
+    let mut checksum = Checksum::new();
+    checksum
+        .add(&src.octets())
+        .add(&dst.octets())
+        .add(&[0, protocol])
+        .add(&(len as u16).to_be_bytes());
+    checksum
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn parse_addr() {
+        assert_eq!(
+            "10.0.2.15".parse::<Ipv4Addr>().ok(),
+            Some(Ipv4Addr::new(10, 0, 2, 15))
+        );
+        for s in [
+            "",
+            "10.0.2",
+            "10.0.2.15.1",
+            "10.0.2.256",
+            "10..2.15",
+            "+1.0.0.1",
+        ] {
+            assert!(s.parse::<Ipv4Addr>().is_err());
+        }
+    }
+
+    #[test_case]
+    fn subnet() {
+        let addr = Ipv4Addr::new(10, 0, 2, 15);
+        assert!(Ipv4Addr::new(10, 0, 2, 2).in_subnet(addr, 24));
+        assert!(!Ipv4Addr::new(10, 0, 3, 2).in_subnet(addr, 24));
+        assert!(Ipv4Addr::new(192, 168, 0, 1).in_subnet(addr, 0));
+        assert_eq!(addr.subnet_broadcast(24), Ipv4Addr::new(10, 0, 2, 255));
+    }
+
+    #[test_case]
+    fn checksum() {
+        // RFC 1071, section 3
+        let data = [0x00, 0x01, 0xf2, 0x03, 0xf4, 0xf5, 0xf6, 0xf7];
+        assert_eq!(super::super::checksum(&data), !0xddf2);
+        let mut odd = super::super::Checksum::new();
+        odd.add(&data[..3]).add(&data[3..]);
+        assert_eq!(odd.finish(), !0xddf2);
+    }
+}
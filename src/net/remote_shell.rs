@@ -0,0 +1,171 @@
+//! Remote shell on TCP port 23, for driving sabios from the host during development.
+//!
+//! Each connection gets a headless [`Terminal`]. Lines received are executed as commands, and
+//! their output is sent back. The protocol is plain text as with a telnet client in line mode;
+//! telnet commands are ignored. With QEMU's user networking, the service is forwarded to port
+//! 10023 of the host.
+
+use super::tcp::{TcpListener, TcpStream};
+use crate::{
+    prelude::*,
+    task::{self, Task},
+    terminal::Terminal,
+};
+use alloc::{string::String, vec::Vec};
+use core::future::Future;
+use x86_64::instructions::interrupts;
+
+const PORT: u16 = 23;
+const BUFFER_SIZE: usize = 1024;
+const MAX_LINE_LEN: usize = 1024;
+
+/// Interpret As Command
+const IAC: u8 = 255;
+/// Subnegotiation Begin
+const SB: u8 = 250;
+/// Subnegotiation End
+const SE: u8 = 240;
+const WILL: u8 = 251;
+const DONT: u8 = 254;
+
+/// Splits received bytes into lines, skipping telnet commands.
+#[derive(Debug, Default)]
+struct LineReader {
+    line: Vec<u8>,
+    state: TelnetState,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TelnetState {
+    Data,
+    Command,
+    Option,
+    Subnegotiation,
+    SubnegotiationCommand,
+}
+
+impl Default for TelnetState {
+    fn default() -> Self {
+        Self::Data
+    }
+}
+
+impl LineReader {
+    /// Feeds a byte, and returns a line when it is terminated.
+    fn push(&mut self, byte: u8) -> Option<String> {
+        self.state = match (self.state, byte) {
+            (TelnetState::Data, IAC) => TelnetState::Command,
+            (TelnetState::Data, b'\n') => {
+                let line = String::from_utf8_lossy(&self.line).into_owned();
+                self.line.clear();
+                return Some(line);
+            }
+            (TelnetState::Data, b'\r' | b'\0') => TelnetState::Data,
+            (TelnetState::Data, 0x08 | 0x7f) => {
+                self.line.pop();
+                TelnetState::Data
+            }
+            (TelnetState::Data, byte) => {
+                if self.line.len() < MAX_LINE_LEN {
+                    self.line.push(byte);
+                }
+                TelnetState::Data
+            }
+            // escaped 255
+            (TelnetState::Command, IAC) => {
+                self.line.push(IAC);
+                TelnetState::Data
+            }
+            (TelnetState::Command, SB) => TelnetState::Subnegotiation,
+            (TelnetState::Command, WILL..=DONT) => TelnetState::Option,
+            (TelnetState::Command, _) | (TelnetState::Option, _) => TelnetState::Data,
+            (TelnetState::Subnegotiation, IAC) => TelnetState::SubnegotiationCommand,
+            (TelnetState::Subnegotiation, _) => TelnetState::Subnegotiation,
+            (TelnetState::SubnegotiationCommand, SE) => TelnetState::Data,
+            (TelnetState::SubnegotiationCommand, _) => TelnetState::Subnegotiation,
+        };
+        None
+    }
+}
+
+/// Sends `s`, converting line endings to CRLF.
+async fn send(stream: &TcpStream, s: &str) -> Result<()> {
+    let mut data = Vec::with_capacity(s.len());
+    for byte in s.bytes() {
+        if byte == b'\n' {
+            data.push(b'\r');
+        }
+        data.push(byte);
+    }
+    stream.write(&data).await?;
+    Ok(())
+}
+
+async fn serve(stream: TcpStream) -> Result<()> {
+    let mut terminal = Terminal::headless();
+    let mut reader = LineReader::default();
+    let mut buf = [0; BUFFER_SIZE];
+
+    send(
+        &stream,
+        "sabios remote shell (type `exit` to disconnect)\n> ",
+    )
+    .await?;
+    loop {
+        let len = stream.read(&mut buf).await?;
+        if len == 0 {
+            return Ok(());
+        }
+        for byte in &buf[..len] {
+            let line = match reader.push(*byte) {
+                Some(line) => line,
+                None => continue,
+            };
+            if line.trim() == "exit" {
+                stream.shutdown();
+                return Ok(());
+            }
+            let output = terminal.execute(&line);
+            send(&stream, &output).await?;
+            send(&stream, "> ").await?;
+        }
+    }
+}
+
+pub(crate) fn handler_task() -> impl Future<Output = Result<()>> {
+    let listener = TcpListener::bind(PORT);
+    async move {
+        let listener = listener?;
+        loop {
+            let stream = listener.accept().await;
+            let peer = stream.peer_addr();
+            info!("remote shell: connected from {}", peer);
+            // commands may block the current task, so each session runs in its own task
+            let task = Task::new(task::DEFAULT_STACK_SIZE, async move {
+                if let Err(err) = serve(stream).await {
+                    debug!("remote shell: connection from {} failed: {}", peer, err);
+                }
+                info!("remote shell: disconnected from {}", peer);
+            });
+            interrupts::without_interrupts(|| task::spawn(task));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn telnet_commands() {
+        let mut reader = LineReader::default();
+        let input = [
+            IAC, WILL, 1, b'l', b's', IAC, SB, 24, 0, b'x', IAC, SE, b'x', 0x7f, b'\r', b'\n',
+        ];
+        let lines = input
+            .iter()
+            .filter_map(|byte| reader.push(*byte))
+            .collect::<Vec<_>>();
+        assert_eq!(lines, ["ls"]);
+    }
+}
@@ -0,0 +1,967 @@
+//! Transmission Control Protocol.
+//!
+//! Connections follow the state machine of RFC 793 with some simplifications:
+//!
+//! - Segments which are not the next in sequence are dropped, and recovered by the
+//!   retransmission of the peer.
+//! - Each connection has a fixed-size receive buffer, and advertises its free space as the
+//!   window.
+//! - Unacknowledged data is retransmitted from the oldest byte (go-back-N), with an exponential
+//!   backoff from a fixed initial timeout.
+//!
+//! The retransmission, TIME-WAIT and FIN-WAIT-2 timers are checked by [`handler_task`] on a
+//! periodic timer.
+
+use super::{
+    ipv4::{self, Header},
+    Ipv4Addr, SocketAddrV4,
+};
+use crate::{
+    prelude::*,
+    random,
+    sync::{SpinMutex, WaitQueue},
+    timer::lapic,
+};
+use alloc::{collections::VecDeque, sync::Arc, vec::Vec};
+use core::{cmp, convert::TryFrom, fmt, mem, sync::atomic::AtomicU16};
+use futures_util::StreamExt as _;
+use x86_64::instructions::interrupts;
+
+const HEADER_LEN: usize = 20;
+
+const FLAG_FIN: u8 = 1 << 0;
+const FLAG_SYN: u8 = 1 << 1;
+const FLAG_RST: u8 = 1 << 2;
+const FLAG_PSH: u8 = 1 << 3;
+const FLAG_ACK: u8 = 1 << 4;
+
+const OPTION_END: u8 = 0;
+const OPTION_NOP: u8 = 1;
+const OPTION_MSS: u8 = 2;
+
+/// The size of the receive buffer, whose free space is advertised as the window.
+const RECV_BUFFER_SIZE: usize = 8192;
+const SEND_BUFFER_SIZE: usize = 16384;
+/// The largest segment, which fits in an Ethernet frame.
+const MAX_MSS: usize = ipv4::MAX_PAYLOAD_LEN - HEADER_LEN;
+/// The MSS assumed if the peer does not advertise one (RFC 1122).
+const DEFAULT_MSS: usize = 536;
+
+const INITIAL_RTO_TICKS: u64 = lapic::TICKS_PER_SECOND;
+const MAX_RTO_TICKS: u64 = 60 * lapic::TICKS_PER_SECOND;
+/// The number of retransmissions before the connection is aborted.
+const MAX_RETRIES: u32 = 8;
+/// Twice the maximum segment lifetime, which is much shorter than the 2 minutes of RFC 793 since
+/// the connections of this kernel are short-lived.
+const TIME_WAIT_TICKS: u64 = 4 * lapic::TICKS_PER_SECOND;
+/// How long a closed connection waits for the FIN of the peer.
+const FIN_WAIT_2_TICKS: u64 = 60 * lapic::TICKS_PER_SECOND;
+const TIMER_INTERVAL_TICKS: u64 = lapic::TICKS_PER_SECOND / 10;
+
+/// The number of established connections waiting to be accepted.
+const BACKLOG: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum State {
+    /// Only listeners are in this state, and connections start from the other states.
+    Listen,
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+    TimeWait,
+    Closed,
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            State::Listen => "LISTEN",
+            State::SynSent => "SYN-SENT",
+            State::SynReceived => "SYN-RECEIVED",
+            State::Established => "ESTABLISHED",
+            State::FinWait1 => "FIN-WAIT-1",
+            State::FinWait2 => "FIN-WAIT-2",
+            State::CloseWait => "CLOSE-WAIT",
+            State::Closing => "CLOSING",
+            State::LastAck => "LAST-ACK",
+            State::TimeWait => "TIME-WAIT",
+            State::Closed => "CLOSED",
+        };
+        f.pad(name)
+    }
+}
+
+/// Why a connection was aborted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Failure {
+    Refused,
+    Reset,
+    TimedOut,
+}
+
+impl Failure {
+    fn to_error(self) -> Error {
+        match self {
+            Failure::Refused => ErrorKind::ConnectionRefused.into(),
+            Failure::Reset => ErrorKind::ConnectionReset.into(),
+            Failure::TimedOut => ErrorKind::ConnectionTimedOut.into(),
+        }
+    }
+}
+
+fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+fn seq_le(a: u32, b: u32) -> bool {
+    !seq_lt(b, a)
+}
+
+/// A segment to be sent.
+#[derive(Debug)]
+struct Segment {
+    seq: u32,
+    ack: u32,
+    flags: u8,
+    window: u16,
+    /// Advertises the MSS, which is done only in SYN segments.
+    mss: bool,
+    data: Vec<u8>,
+}
+
+/// A received segment.
+#[derive(Debug)]
+struct Incoming<'a> {
+    seq: u32,
+    ack: u32,
+    flags: u8,
+    window: u16,
+    mss: Option<u16>,
+    data: &'a [u8],
+}
+
+impl Incoming<'_> {
+    fn has(&self, flag: u8) -> bool {
+        self.flags & flag != 0
+    }
+}
+
+/// Transmission control block, the state of a connection.
+#[derive(Debug)]
+struct Tcb {
+    state: State,
+    failure: Option<Failure>,
+    /// The connection was created by a listener.
+    passive: bool,
+
+    iss: u32,
+    snd_una: u32,
+    snd_nxt: u32,
+    snd_wnd: usize,
+    mss: usize,
+    /// Unacknowledged and unsent data, starting at `snd_una`.
+    send_buf: VecDeque<u8>,
+    /// The user closed the sending side, and FIN is sent after `send_buf`.
+    fin_queued: bool,
+    /// The sequence number of FIN, once it is sent.
+    fin_seq: Option<u32>,
+
+    rcv_nxt: u32,
+    recv_buf: VecDeque<u8>,
+    fin_received: bool,
+
+    /// The tick when the unacknowledged data is retransmitted.
+    retransmit_at: Option<u64>,
+    rto_ticks: u64,
+    retries: u32,
+    /// The tick when the connection is closed in TIME-WAIT or FIN-WAIT-2.
+    expire_at: Option<u64>,
+}
+
+impl Tcb {
+    fn new(state: State, passive: bool) -> Self {
+        let iss = random::u64() as u32;
+        Self {
+            state,
+            failure: None,
+            passive,
+            iss,
+            snd_una: iss,
+            snd_nxt: iss,
+            snd_wnd: 0,
+            mss: DEFAULT_MSS,
+            send_buf: VecDeque::new(),
+            fin_queued: false,
+            fin_seq: None,
+            rcv_nxt: 0,
+            recv_buf: VecDeque::new(),
+            fin_received: false,
+            retransmit_at: None,
+            rto_ticks: INITIAL_RTO_TICKS,
+            retries: 0,
+            expire_at: None,
+        }
+    }
+
+    fn window(&self) -> u16 {
+        (RECV_BUFFER_SIZE - self.recv_buf.len()) as u16
+    }
+
+    fn segment(&self, seq: u32, flags: u8, data: Vec<u8>) -> Segment {
+        Segment {
+            seq,
+            ack: self.rcv_nxt,
+            flags,
+            window: self.window(),
+            mss: flags & FLAG_SYN != 0,
+            data,
+        }
+    }
+
+    fn ack_segment(&self) -> Segment {
+        self.segment(self.snd_nxt, FLAG_ACK, Vec::new())
+    }
+
+    fn syn_acked(&self) -> bool {
+        seq_lt(self.iss, self.snd_una)
+    }
+
+    fn fin_acked(&self) -> bool {
+        self.fin_seq.map_or(false, |fin| seq_lt(fin, self.snd_una))
+    }
+
+    fn close(&mut self, failure: Option<Failure>) {
+        self.state = State::Closed;
+        self.failure = self.failure.or(failure);
+        self.retransmit_at = None;
+        self.expire_at = None;
+    }
+
+    fn enter_time_wait(&mut self, now: u64) {
+        self.state = State::TimeWait;
+        self.retransmit_at = None;
+        self.expire_at = Some(now + TIME_WAIT_TICKS);
+    }
+
+    /// Sends SYN or the data and FIN the window allows.
+    ///
+    /// With `force`, one byte is sent even if the window is zero, to probe the window.
+    fn output(&mut self, now: u64, force: bool, out: &mut Vec<Segment>) {
+        match self.state {
+            State::SynSent | State::SynReceived => {
+                if self.snd_nxt == self.iss {
+                    let flags = if self.state == State::SynSent {
+                        FLAG_SYN
+                    } else {
+                        FLAG_SYN | FLAG_ACK
+                    };
+                    let mut segment = self.segment(self.iss, flags, Vec::new());
+                    if flags & FLAG_ACK == 0 {
+                        segment.ack = 0;
+                    }
+                    out.push(segment);
+                    self.snd_nxt = self.iss.wrapping_add(1);
+                }
+            }
+            State::Established
+            | State::CloseWait
+            | State::FinWait1
+            | State::Closing
+            | State::LastAck => {
+                while self.fin_seq.is_none() {
+                    let sent = self.snd_nxt.wrapping_sub(self.snd_una) as usize;
+                    let unsent = self.send_buf.len() - sent;
+                    let mut window = self.snd_wnd.saturating_sub(sent);
+                    if force && window == 0 && sent == 0 {
+                        window = 1;
+                    }
+                    let len = cmp::min(cmp::min(unsent, window), self.mss);
+                    if len > 0 {
+                        let data = self.send_buf.range(sent..sent + len).copied().collect();
+                        let flags = if len == unsent {
+                            FLAG_ACK | FLAG_PSH
+                        } else {
+                            FLAG_ACK
+                        };
+                        out.push(self.segment(self.snd_nxt, flags, data));
+                        self.snd_nxt = self.snd_nxt.wrapping_add(len as u32);
+                        continue;
+                    }
+                    if unsent == 0 && self.fin_queued {
+                        out.push(self.segment(self.snd_nxt, FLAG_FIN | FLAG_ACK, Vec::new()));
+                        self.fin_seq = Some(self.snd_nxt);
+                        self.snd_nxt = self.snd_nxt.wrapping_add(1);
+                    }
+                    break;
+                }
+            }
+            State::Listen | State::FinWait2 | State::TimeWait | State::Closed => {}
+        }
+
+        let in_flight = self.snd_nxt != self.snd_una;
+        let blocked = self.send_buf.len() > self.snd_nxt.wrapping_sub(self.snd_una) as usize;
+        if (in_flight || blocked) && self.state != State::Closed {
+            if self.retransmit_at.is_none() {
+                self.retransmit_at = Some(now + self.rto_ticks);
+            }
+        } else {
+            self.retransmit_at = None;
+        }
+    }
+
+    /// Retransmits from the oldest unacknowledged byte.
+    fn on_retransmit_timeout(&mut self, now: u64, out: &mut Vec<Segment>) {
+        // window probes are answered even if the window stays closed
+        if self.snd_wnd > 0 || !self.syn_acked() {
+            self.retries += 1;
+            if self.retries > MAX_RETRIES {
+                out.push(self.segment(self.snd_nxt, FLAG_RST, Vec::new()));
+                self.close(Some(Failure::TimedOut));
+                return;
+            }
+        }
+        self.rto_ticks = cmp::min(self.rto_ticks * 2, MAX_RTO_TICKS);
+        self.snd_nxt = self.snd_una;
+        if !self.fin_acked() {
+            self.fin_seq = None;
+        }
+        self.retransmit_at = None;
+        self.output(now, true, out);
+    }
+
+    /// Updates the state with a segment of the connection.
+    ///
+    /// Returns `true` if the connection became established.
+    fn receive(&mut self, seg: &Incoming<'_>, now: u64, out: &mut Vec<Segment>) -> bool {
+        if seg.has(FLAG_RST) {
+            match self.state {
+                State::SynSent => {
+                    if seg.has(FLAG_ACK) && seg.ack == self.snd_nxt {
+                        self.close(Some(Failure::Refused));
+                    }
+                }
+                State::Closed => {}
+                // only an exact match is accepted against blind resets (RFC 5961)
+                _ if seg.seq == self.rcv_nxt => {
+                    let failure = (self.state != State::TimeWait).then(|| Failure::Reset);
+                    self.close(failure);
+                }
+                _ => {}
+            }
+            return false;
+        }
+
+        match self.state {
+            State::SynSent => {
+                if seg.has(FLAG_ACK) && seg.ack != self.snd_nxt {
+                    out.push(self.segment(seg.ack, FLAG_RST, Vec::new()));
+                    return false;
+                }
+                if seg.has(FLAG_SYN) && seg.has(FLAG_ACK) {
+                    self.rcv_nxt = seg.seq.wrapping_add(1);
+                    self.snd_una = seg.ack;
+                    self.snd_wnd = usize::from(seg.window);
+                    self.mss = seg.mss.map_or(DEFAULT_MSS, usize::from).min(MAX_MSS);
+                    self.state = State::Established;
+                    self.retries = 0;
+                    self.rto_ticks = INITIAL_RTO_TICKS;
+                    out.push(self.ack_segment());
+                    self.output(now, false, out);
+                    return true;
+                }
+                return false;
+            }
+            State::Listen | State::Closed => return false,
+            _ => {}
+        }
+
+        if seg.has(FLAG_SYN) {
+            // the peer retransmitted SYN, since our SYN-ACK or ACK was lost
+            if seg.seq.wrapping_add(1) == self.rcv_nxt {
+                if self.state == State::SynReceived {
+                    self.snd_nxt = self.iss;
+                    self.output(now, false, out);
+                } else {
+                    out.push(self.ack_segment());
+                }
+            }
+            return false;
+        }
+        if !seg.has(FLAG_ACK) {
+            return false;
+        }
+
+        let mut established = false;
+        if self.state == State::SynReceived {
+            if seg.ack != self.snd_nxt {
+                out.push(self.segment(seg.ack, FLAG_RST, Vec::new()));
+                return false;
+            }
+            self.state = State::Established;
+            established = true;
+        }
+
+        if seq_lt(self.snd_una, seg.ack) && seq_le(seg.ack, self.snd_nxt) {
+            let mut acked = seg.ack.wrapping_sub(self.snd_una) as usize;
+            if !self.syn_acked() {
+                acked -= 1;
+            }
+            if self.fin_seq.map_or(false, |fin| seq_lt(fin, seg.ack)) {
+                acked -= 1;
+            }
+            self.send_buf.drain(..cmp::min(acked, self.send_buf.len()));
+            self.snd_una = seg.ack;
+            self.retries = 0;
+            self.rto_ticks = INITIAL_RTO_TICKS;
+            self.retransmit_at = None;
+        } else if seq_lt(self.snd_nxt, seg.ack) {
+            // acknowledges data not sent yet
+            out.push(self.ack_segment());
+            return established;
+        }
+        if seq_le(self.snd_una, seg.ack) {
+            self.snd_wnd = usize::from(seg.window);
+        }
+
+        if self.fin_acked() {
+            match self.state {
+                State::FinWait1 => {
+                    self.state = State::FinWait2;
+                    self.expire_at = Some(now + FIN_WAIT_2_TICKS);
+                }
+                State::Closing => self.enter_time_wait(now),
+                State::LastAck => {
+                    self.close(None);
+                    return established;
+                }
+                _ => {}
+            }
+        }
+
+        let mut ack_needed = false;
+        let receiving = matches!(
+            self.state,
+            State::Established | State::FinWait1 | State::FinWait2
+        );
+        if !seg.data.is_empty() {
+            if receiving && seg.seq == self.rcv_nxt {
+                let len = cmp::min(seg.data.len(), RECV_BUFFER_SIZE - self.recv_buf.len());
+                self.recv_buf.extend(&seg.data[..len]);
+                self.rcv_nxt = self.rcv_nxt.wrapping_add(len as u32);
+            }
+            ack_needed = true;
+        }
+
+        if seg.has(FLAG_FIN) {
+            let fin_seq = seg.seq.wrapping_add(seg.data.len() as u32);
+            if !self.fin_received && fin_seq == self.rcv_nxt {
+                self.fin_received = true;
+                self.rcv_nxt = self.rcv_nxt.wrapping_add(1);
+                match self.state {
+                    State::Established => self.state = State::CloseWait,
+                    State::FinWait1 if self.fin_acked() => self.enter_time_wait(now),
+                    State::FinWait1 => self.state = State::Closing,
+                    State::FinWait2 => self.enter_time_wait(now),
+                    _ => {}
+                }
+            } else if self.state == State::TimeWait {
+                // our ACK of FIN was lost
+                self.enter_time_wait(now);
+            }
+            ack_needed = true;
+        }
+
+        let sent = out.len();
+        self.output(now, false, out);
+        if ack_needed && out.len() == sent {
+            out.push(self.ack_segment());
+        }
+        established
+    }
+}
+
+#[derive(Debug)]
+struct Connection {
+    local: SocketAddrV4,
+    remote: SocketAddrV4,
+    tcb: SpinMutex<Tcb>,
+    wait_queue: WaitQueue,
+}
+
+impl Connection {
+    fn with_tcb<T>(&self, f: impl FnOnce(&mut Tcb) -> T) -> T {
+        interrupts::without_interrupts(|| f(&mut *self.tcb.lock()))
+    }
+
+    fn state(&self) -> State {
+        self.with_tcb(|tcb| tcb.state)
+    }
+
+    /// Sends `segments`, wakes up the users and forgets the connection once it is closed.
+    fn flush(self: &Arc<Self>, segments: Vec<Segment>) {
+        for segment in segments {
+            if let Err(err) = transmit(self.local, self.remote, &segment) {
+                debug!("tcp: failed to send a segment to {}: {}", self.remote, err);
+            }
+        }
+        self.wait_queue.notify_all();
+        if self.state() == State::Closed {
+            interrupts::without_interrupts(|| {
+                CONNECTIONS.lock().retain(|conn| !Arc::ptr_eq(conn, self));
+            });
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Listener {
+    queue: SpinMutex<VecDeque<TcpStream>>,
+    wait_queue: WaitQueue,
+}
+
+static CONNECTIONS: SpinMutex<Vec<Arc<Connection>>> = SpinMutex::new(Vec::new());
+static LISTENERS: SpinMutex<Vec<(u16, Arc<Listener>)>> = SpinMutex::new(Vec::new());
+static NEXT_EPHEMERAL_PORT: AtomicU16 = AtomicU16::new(super::FIRST_EPHEMERAL_PORT);
+
+fn find_listener(port: u16) -> Option<Arc<Listener>> {
+    interrupts::without_interrupts(|| {
+        LISTENERS
+            .lock()
+            .iter()
+            .find(|(bound, _)| *bound == port)
+            .map(|(_, listener)| listener.clone())
+    })
+}
+
+/// A TCP connection. Dropping the stream closes the connection gracefully.
+#[derive(Debug)]
+pub(crate) struct TcpStream {
+    conn: Arc<Connection>,
+}
+
+impl TcpStream {
+    /// Opens a connection to `remote`.
+    pub(crate) async fn connect(remote: SocketAddrV4) -> Result<Self> {
+        let route = ipv4::route(remote.addr())?;
+        let conn = interrupts::without_interrupts(|| {
+            let mut conns = CONNECTIONS.lock();
+            let listeners = LISTENERS.lock();
+            let port = super::allocate_ephemeral_port(&NEXT_EPHEMERAL_PORT, |port| {
+                conns.iter().any(|conn| conn.local.port() == port)
+                    || listeners.iter().any(|(bound, _)| *bound == port)
+            })?;
+            let conn = Arc::new(Connection {
+                local: SocketAddrV4::new(route.src, port),
+                remote,
+                tcb: SpinMutex::new(Tcb::new(State::SynSent, false)),
+                wait_queue: WaitQueue::new(),
+            });
+            conns.push(conn.clone());
+            Ok::<_, Error>(conn)
+        })?;
+
+        let mut segments = Vec::new();
+        conn.with_tcb(|tcb| tcb.output(lapic::current_tick(), false, &mut segments));
+        conn.flush(segments);
+
+        let failure = conn
+            .wait_queue
+            .wait_until(|| {
+                conn.with_tcb(|tcb| match tcb.state {
+                    State::SynSent => None,
+                    _ => Some(tcb.failure),
+                })
+            })
+            .await;
+        if let Some(failure) = failure {
+            return Err(failure.to_error());
+        }
+        Ok(Self { conn })
+    }
+
+    pub(crate) fn peer_addr(&self) -> SocketAddrV4 {
+        self.conn.remote
+    }
+
+    /// Reads received data into `buf`, waiting until some arrives. Returns 0 at the end of the
+    /// stream.
+    pub(crate) async fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        let conn = &self.conn;
+        let (result, segment) = conn
+            .wait_queue
+            .wait_until(|| {
+                conn.with_tcb(|tcb| {
+                    if !tcb.recv_buf.is_empty() {
+                        let len = cmp::min(buf.len(), tcb.recv_buf.len());
+                        let was_full = usize::from(tcb.window()) < tcb.mss;
+                        for (dst, src) in buf.iter_mut().zip(tcb.recv_buf.drain(..len)) {
+                            *dst = src;
+                        }
+                        // tell the peer that the window is open again
+                        let update = (was_full && usize::from(tcb.window()) >= tcb.mss)
+                            .then(|| tcb.ack_segment());
+                        return Some((Ok(len), update));
+                    }
+                    if let Some(failure) = tcb.failure {
+                        return Some((Err(failure.to_error()), None));
+                    }
+                    (tcb.fin_received || tcb.state == State::Closed).then(|| (Ok(0), None))
+                })
+            })
+            .await;
+        conn.flush(segment.into_iter().collect());
+        result
+    }
+
+    /// Writes all of `data`, waiting while the send buffer is full.
+    pub(crate) async fn write(&self, data: &[u8]) -> Result<usize> {
+        let conn = &self.conn;
+        let mut written = 0;
+        while written < data.len() {
+            let segments = conn
+                .wait_queue
+                .wait_until(|| {
+                    conn.with_tcb(|tcb| {
+                        if let Some(failure) = tcb.failure {
+                            return Some(Err(failure.to_error()));
+                        }
+                        if tcb.fin_queued || tcb.state == State::Closed {
+                            return Some(Err(ErrorKind::NotConnected.into()));
+                        }
+                        let space = SEND_BUFFER_SIZE - tcb.send_buf.len();
+                        if space == 0 {
+                            return None;
+                        }
+                        let len = cmp::min(space, data.len() - written);
+                        tcb.send_buf.extend(&data[written..written + len]);
+                        written += len;
+                        let mut segments = Vec::new();
+                        tcb.output(lapic::current_tick(), false, &mut segments);
+                        Some(Ok(segments))
+                    })
+                })
+                .await?;
+            conn.flush(segments);
+        }
+        Ok(written)
+    }
+
+    /// Closes the sending side. FIN is sent after the data written so far.
+    pub(crate) fn shutdown(&self) {
+        let mut segments = Vec::new();
+        self.conn.with_tcb(|tcb| {
+            match tcb.state {
+                State::SynSent | State::SynReceived => {
+                    tcb.close(None);
+                    return;
+                }
+                State::Established => tcb.state = State::FinWait1,
+                State::CloseWait => tcb.state = State::LastAck,
+                _ => return,
+            }
+            tcb.fin_queued = true;
+            tcb.output(lapic::current_tick(), false, &mut segments);
+        });
+        self.conn.flush(segments);
+    }
+}
+
+impl Drop for TcpStream {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+/// A socket listening for connections, which stops listening on drop.
+#[derive(Debug)]
+pub(crate) struct TcpListener {
+    port: u16,
+    listener: Arc<Listener>,
+}
+
+impl TcpListener {
+    /// Listens on `port` of all interfaces.
+    pub(crate) fn bind(port: u16) -> Result<Self> {
+        let listener = Arc::new(Listener::default());
+        interrupts::without_interrupts(|| {
+            let mut listeners = LISTENERS.lock();
+            if listeners.iter().any(|(bound, _)| *bound == port) {
+                bail!(ErrorKind::AddrInUse(port));
+            }
+            listeners.push((port, listener.clone()));
+            Ok(())
+        })?;
+        Ok(Self { port, listener })
+    }
+
+    /// Waits for an established connection.
+    pub(crate) async fn accept(&self) -> TcpStream {
+        let listener = &self.listener;
+        listener
+            .wait_queue
+            .wait_until(|| interrupts::without_interrupts(|| listener.queue.lock().pop_front()))
+            .await
+    }
+}
+
+impl Drop for TcpListener {
+    fn drop(&mut self) {
+        let queued = interrupts::without_interrupts(|| {
+            LISTENERS.lock().retain(|(port, _)| *port != self.port);
+            mem::take(&mut *self.listener.queue.lock())
+        });
+        // the connections not accepted yet are closed as their streams are dropped
+        drop(queued);
+    }
+}
+
+fn checksum(src: Ipv4Addr, dst: Ipv4Addr, segment: &[u8]) -> u16 {
+    ipv4::pseudo_header_checksum(src, dst, ipv4::PROTOCOL_TCP, segment.len())
+        .add(segment)
+        .finish()
+}
+
+fn transmit(local: SocketAddrV4, remote: SocketAddrV4, segment: &Segment) -> Result<()> {
+    let route = ipv4::route(remote.addr())?;
+    let options_len = if segment.mss { 4 } else { 0 };
+    let header_len = HEADER_LEN + options_len;
+    let mut bytes = Vec::with_capacity(header_len + segment.data.len());
+    bytes.extend_from_slice(&local.port().to_be_bytes());
+    bytes.extend_from_slice(&remote.port().to_be_bytes());
+    bytes.extend_from_slice(&segment.seq.to_be_bytes());
+    bytes.extend_from_slice(&segment.ack.to_be_bytes());
+    bytes.extend_from_slice(&[(header_len / 4) as u8 * 16, segment.flags]);
+    bytes.extend_from_slice(&segment.window.to_be_bytes());
+    // checksum and urgent pointer
+    bytes.extend_from_slice(&[0; 4]);
+    if segment.mss {
+        bytes.extend_from_slice(&[OPTION_MSS, 4]);
+        bytes.extend_from_slice(&(MAX_MSS as u16).to_be_bytes());
+    }
+    bytes.extend_from_slice(&segment.data);
+    let checksum = checksum(route.src, route.dst, &bytes);
+    bytes[16..18].copy_from_slice(&checksum.to_be_bytes());
+    ipv4::send_via(&route, ipv4::PROTOCOL_TCP, &bytes)
+}
+
+fn parse_mss(options: &[u8]) -> Option<u16> {
+    let mut rest = options;
+    while let Some(&kind) = rest.first() {
+        match kind {
+            OPTION_END => break,
+            OPTION_NOP => rest = &rest[1..],
+            _ => {
+                let len = usize::from(*rest.get(1)?);
+                if len < 2 || len > rest.len() {
+                    return None;
+                }
+                if kind == OPTION_MSS && len == 4 {
+                    return Some(u16::from_be_bytes([rest[2], rest[3]]));
+                }
+                rest = &rest[len..];
+            }
+        }
+    }
+    None
+}
+
+/// A TCP socket, as listed by `netstat`.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct SocketInfo {
+    /// The local address, which is unspecified for listeners.
+    pub(crate) local: SocketAddrV4,
+    pub(crate) remote: Option<SocketAddrV4>,
+    pub(crate) state: State,
+}
+
+/// Returns the listeners and the connections.
+pub(crate) fn sockets() -> Vec<SocketInfo> {
+    let ports = interrupts::without_interrupts(|| {
+        LISTENERS
+            .lock()
+            .iter()
+            .map(|(port, _)| *port)
+            .collect::<Vec<_>>()
+    });
+    let conns = interrupts::without_interrupts(|| CONNECTIONS.lock().clone());
+    let listeners = ports.into_iter().map(|port| SocketInfo {
+        local: SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, port),
+        remote: None,
+        state: State::Listen,
+    });
+    let conns = conns.into_iter().map(|conn| SocketInfo {
+        local: conn.local,
+        remote: Some(conn.remote),
+        state: conn.state(),
+    });
+    listeners.chain(conns).collect()
+}
+
+/// Answers a segment for no connection with RST (RFC 793, section 3.4).
+fn reset(local: SocketAddrV4, remote: SocketAddrV4, seg: &Incoming<'_>) {
+    if seg.has(FLAG_RST) {
+        return;
+    }
+    let segment = if seg.has(FLAG_ACK) {
+        Segment {
+            seq: seg.ack,
+            ack: 0,
+            flags: FLAG_RST,
+            window: 0,
+            mss: false,
+            data: Vec::new(),
+        }
+    } else {
+        let len = seg.data.len() + usize::from(seg.has(FLAG_SYN)) + usize::from(seg.has(FLAG_FIN));
+        Segment {
+            seq: 0,
+            ack: seg.seq.wrapping_add(len as u32),
+            flags: FLAG_RST | FLAG_ACK,
+            window: 0,
+            mss: false,
+            data: Vec::new(),
+        }
+    };
+    if let Err(err) = transmit(local, remote, &segment) {
+        debug!("tcp: failed to reset {}: {}", remote, err);
+    }
+}
+
+pub(super) fn receive(header: &Header, bytes: &[u8]) {
+    if bytes.len() < HEADER_LEN || checksum(header.src, header.dst, bytes) != 0 {
+        return;
+    }
+    let header_len = usize::from(bytes[12] >> 4) * 4;
+    if header_len < HEADER_LEN || header_len > bytes.len() {
+        return;
+    }
+    let read_u32 = |offset: usize| {
+        #[allow(clippy::unwrap_used)]
+        u32::from_be_bytes(<[u8; 4]>::try_from(&bytes[offset..offset + 4]).unwrap())
+    };
+    let local = SocketAddrV4::new(header.dst, u16::from_be_bytes([bytes[2], bytes[3]]));
+    let remote = SocketAddrV4::new(header.src, u16::from_be_bytes([bytes[0], bytes[1]]));
+    let flags = bytes[13];
+    let seg = Incoming {
+        seq: read_u32(4),
+        ack: read_u32(8),
+        flags,
+        window: u16::from_be_bytes([bytes[14], bytes[15]]),
+        mss: (flags & FLAG_SYN != 0)
+            .then(|| parse_mss(&bytes[HEADER_LEN..header_len]))
+            .flatten(),
+        data: &bytes[header_len..],
+    };
+    let now = lapic::current_tick();
+
+    let conn = interrupts::without_interrupts(|| {
+        CONNECTIONS
+            .lock()
+            .iter()
+            .find(|conn| conn.local == local && conn.remote == remote)
+            .cloned()
+    });
+    let conn = match conn {
+        Some(conn) => conn,
+        None => {
+            accept_syn(local, remote, &seg, now);
+            return;
+        }
+    };
+
+    let mut segments = Vec::new();
+    let established = conn.with_tcb(|tcb| tcb.receive(&seg, now, &mut segments));
+    let passive = conn.with_tcb(|tcb| tcb.passive);
+    conn.flush(segments);
+    if established && passive {
+        let stream = TcpStream { conn };
+        match find_listener(local.port()) {
+            Some(listener) => {
+                interrupts::without_interrupts(|| listener.queue.lock().push_back(stream));
+                listener.wait_queue.notify_all();
+            }
+            // the stream is closed on drop
+            None => drop(stream),
+        }
+    }
+}
+
+/// Creates a connection for SYN to a listening port, or resets the peer.
+fn accept_syn(local: SocketAddrV4, remote: SocketAddrV4, seg: &Incoming<'_>, now: u64) {
+    let listener = match find_listener(local.port()) {
+        Some(listener) if seg.has(FLAG_SYN) && !seg.has(FLAG_ACK) && !seg.has(FLAG_RST) => listener,
+        _ => {
+            reset(local, remote, seg);
+            return;
+        }
+    };
+    if interrupts::without_interrupts(|| listener.queue.lock().len()) >= BACKLOG {
+        // the peer retries later
+        return;
+    }
+
+    let mut tcb = Tcb::new(State::SynReceived, true);
+    tcb.rcv_nxt = seg.seq.wrapping_add(1);
+    tcb.snd_wnd = usize::from(seg.window);
+    tcb.mss = seg.mss.map_or(DEFAULT_MSS, usize::from).min(MAX_MSS);
+    let mut segments = Vec::new();
+    tcb.output(now, false, &mut segments);
+    let conn = Arc::new(Connection {
+        local,
+        remote,
+        tcb: SpinMutex::new(tcb),
+        wait_queue: WaitQueue::new(),
+    });
+    interrupts::without_interrupts(|| CONNECTIONS.lock().push(conn.clone()));
+    conn.flush(segments);
+}
+
+/// Runs the timers of the connections.
+pub(crate) async fn handler_task() -> Result<()> {
+    let start = lapic::current_tick() + TIMER_INTERVAL_TICKS;
+    let mut interval = lapic::interval(start, TIMER_INTERVAL_TICKS)?;
+    while let Some(now) = interval.next().await {
+        let now = now?;
+        let conns = interrupts::without_interrupts(|| CONNECTIONS.lock().clone());
+        for conn in conns {
+            let mut segments = Vec::new();
+            conn.with_tcb(|tcb| {
+                if tcb.expire_at.map_or(false, |expire_at| expire_at <= now) {
+                    tcb.close(None);
+                } else if tcb.retransmit_at.map_or(false, |at| at <= now) {
+                    tcb.on_retransmit_timeout(now, &mut segments);
+                }
+            });
+            conn.flush(segments);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn sequence_wraparound() {
+        assert!(seq_lt(0xffff_fff0, 0x10));
+        assert!(!seq_lt(0x10, 0xffff_fff0));
+        assert!(seq_le(5, 5));
+    }
+
+    #[test_case]
+    fn mss_option() {
+        assert_eq!(
+            parse_mss(&[OPTION_NOP, OPTION_MSS, 4, 0x05, 0xb4]),
+            Some(1460)
+        );
+        assert_eq!(parse_mss(&[4, 2, OPTION_MSS, 4, 0x02, 0x18]), Some(536));
+        assert_eq!(parse_mss(&[OPTION_END, OPTION_MSS, 4, 0x05, 0xb4]), None);
+        assert_eq!(parse_mss(&[OPTION_MSS, 4, 0x05]), None);
+    }
+}
@@ -0,0 +1,164 @@
+//! User Datagram Protocol.
+//!
+//! Each bound port has a queue of received datagrams. Datagrams to ports without a socket, and
+//! datagrams arriving while the queue is full, are dropped.
+
+use super::{
+    ipv4::{self, Header},
+    Ipv4Addr, SocketAddrV4,
+};
+use crate::{
+    prelude::*,
+    sync::{SpinMutex, WaitQueue},
+};
+use alloc::{collections::VecDeque, sync::Arc, vec::Vec};
+use core::sync::atomic::AtomicU16;
+use x86_64::instructions::interrupts;
+
+const HEADER_LEN: usize = 8;
+/// The largest payload of a datagram, which is not fragmented.
+pub(crate) const MAX_PAYLOAD_LEN: usize = ipv4::MAX_PAYLOAD_LEN - HEADER_LEN;
+
+const MAX_QUEUED: usize = 64;
+
+#[derive(Debug, Default)]
+struct Binding {
+    queue: SpinMutex<VecDeque<(SocketAddrV4, Vec<u8>)>>,
+    wait_queue: WaitQueue,
+}
+
+static BINDINGS: SpinMutex<Vec<(u16, Arc<Binding>)>> = SpinMutex::new(Vec::new());
+static NEXT_EPHEMERAL_PORT: AtomicU16 = AtomicU16::new(super::FIRST_EPHEMERAL_PORT);
+
+/// A UDP socket bound to a local port, which is released on drop.
+///
+/// The socket receives datagrams to the port on all interfaces.
+#[derive(Debug)]
+pub(crate) struct UdpSocket {
+    port: u16,
+    binding: Arc<Binding>,
+}
+
+impl UdpSocket {
+    /// Binds a socket to `port`, or to a free ephemeral port if `port` is 0.
+    pub(crate) fn bind(port: u16) -> Result<Self> {
+        let binding = Arc::new(Binding::default());
+        let port = interrupts::without_interrupts(|| {
+            let mut bindings = BINDINGS.lock();
+            let port = if port == 0 {
+                super::allocate_ephemeral_port(&NEXT_EPHEMERAL_PORT, |port| {
+                    is_bound(&bindings, port)
+                })?
+            } else {
+                port
+            };
+            if is_bound(&bindings, port) {
+                bail!(ErrorKind::AddrInUse(port));
+            }
+            bindings.push((port, binding.clone()));
+            Ok(port)
+        })?;
+        Ok(Self { port, binding })
+    }
+
+    /// Sends `data` as a datagram to `dst`. This never waits, since datagrams are not queued
+    /// for sending.
+    pub(crate) fn send_to(&self, data: &[u8], dst: SocketAddrV4) -> Result<()> {
+        send(self.port, dst, data)
+    }
+
+    /// Receives a datagram, waiting until one arrives. Returns its length and the sender.
+    ///
+    /// The datagram is truncated if `buf` is too small, like `recvfrom(2)`.
+    pub(crate) async fn recv_from(&self, buf: &mut [u8]) -> (usize, SocketAddrV4) {
+        let binding = &self.binding;
+        let (src, data) = binding
+            .wait_queue
+            .wait_until(|| interrupts::without_interrupts(|| binding.queue.lock().pop_front()))
+            .await;
+        let len = usize::min(data.len(), buf.len());
+        buf[..len].copy_from_slice(&data[..len]);
+        (len, src)
+    }
+}
+
+impl Drop for UdpSocket {
+    fn drop(&mut self) {
+        interrupts::without_interrupts(|| BINDINGS.lock().retain(|(port, _)| *port != self.port));
+    }
+}
+
+/// Returns the bound ports.
+pub(crate) fn ports() -> Vec<u16> {
+    interrupts::without_interrupts(|| BINDINGS.lock().iter().map(|(port, _)| *port).collect())
+}
+
+fn is_bound(bindings: &[(u16, Arc<Binding>)], port: u16) -> bool {
+    bindings.iter().any(|(bound, _)| *bound == port)
+}
+
+fn send(src_port: u16, dst: SocketAddrV4, data: &[u8]) -> Result<()> {
+    if data.len() > MAX_PAYLOAD_LEN {
+        bail!(ErrorKind::PacketTooLarge);
+    }
+    let route = ipv4::route(dst.addr())?;
+    let len = HEADER_LEN + data.len();
+    let mut datagram = Vec::with_capacity(len);
+    datagram.extend_from_slice(&src_port.to_be_bytes());
+    datagram.extend_from_slice(&dst.port().to_be_bytes());
+    datagram.extend_from_slice(&(len as u16).to_be_bytes());
+    datagram.extend_from_slice(&[0, 0]);
+    datagram.extend_from_slice(data);
+    let checksum = checksum(route.src, route.dst, &datagram);
+    // zero means that the checksum is not computed
+    let checksum = if checksum == 0 { 0xffff } else { checksum };
+    datagram[6..8].copy_from_slice(&checksum.to_be_bytes());
+    ipv4::send_via(&route, ipv4::PROTOCOL_UDP, &datagram)
+}
+
+fn checksum(src: Ipv4Addr, dst: Ipv4Addr, datagram: &[u8]) -> u16 {
+    ipv4::pseudo_header_checksum(src, dst, ipv4::PROTOCOL_UDP, datagram.len())
+        .add(datagram)
+        .finish()
+}
+
+pub(super) fn receive(header: &Header, datagram: &[u8]) {
+    if datagram.len() < HEADER_LEN {
+        return;
+    }
+    let src_port = u16::from_be_bytes([datagram[0], datagram[1]]);
+    let dst_port = u16::from_be_bytes([datagram[2], datagram[3]]);
+    let len = usize::from(u16::from_be_bytes([datagram[4], datagram[5]]));
+    if len < HEADER_LEN || len > datagram.len() {
+        return;
+    }
+    let datagram = &datagram[..len];
+    let has_checksum = datagram[6..8] != [0, 0];
+    if has_checksum && checksum(header.src, header.dst, datagram) != 0 {
+        return;
+    }
+
+    let binding = interrupts::without_interrupts(|| {
+        BINDINGS
+            .lock()
+            .iter()
+            .find(|(port, _)| *port == dst_port)
+            .map(|(_, binding)| binding.clone())
+    });
+    let binding = match binding {
+        Some(binding) => binding,
+        None => return,
+    };
+    let src = SocketAddrV4::new(header.src, src_port);
+    let queued = interrupts::without_interrupts(|| {
+        let mut queue = binding.queue.lock();
+        if queue.len() >= MAX_QUEUED {
+            return false;
+        }
+        queue.push_back((src, datagram[HEADER_LEN..].into()));
+        true
+    });
+    if queued {
+        binding.wait_queue.notify_all();
+    }
+}
@@ -1,11 +1,59 @@
-use crate::{memory::BitmapMemoryManager, prelude::*};
+use crate::{memory::BitmapMemoryManager, mmio::VolatileMmio, prelude::*, sync::OnceCell};
+use alloc::vec::Vec;
 use x86_64::{
-    structures::paging::{Mapper, OffsetPageTable, Page, PageTable, PhysFrame},
+    instructions::tlb,
+    registers::{
+        control::{Cr0, Cr0Flags},
+        model_specific::{Efer, EferFlags},
+    },
+    structures::paging::{
+        frame::PhysFrameRange,
+        mapper::{MapToError, MappedFrame, TranslateResult},
+        page_table::PageTableEntry,
+        Mapper, OffsetPageTable, Page, PageSize, PageTable, PageTableFlags, PhysFrame, Size2MiB,
+        Size4KiB, Translate,
+    },
     PhysAddr, VirtAddr,
 };
 
+/// Number of 4 KiB frames in a 2 MiB page.
+const FRAMES_PER_HUGE_PAGE: usize = (Size2MiB::SIZE / Size4KiB::SIZE) as usize;
+
+/// Maximum number of pages invalidated one by one.
+///
+/// Flushing the whole TLB is cheaper than issuing `invlpg` for many pages.
+const MAX_FLUSH_PAGES: usize = 32;
+
+static PHYSICAL_MEMORY_OFFSET: OnceCell<VirtAddr> = OnceCell::uninit();
+
+/// Handler to invalidate TLB entries on the other CPUs.
+static SHOOTDOWN_HANDLER: OnceCell<fn(&TlbFlush)> = OnceCell::uninit();
+
+/// Access permission of mapped pages.
+///
+/// Writable pages are never executable (W^X), so that data written by the kernel cannot be
+/// executed by accident.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Protection {
+    Writable,
+    Executable,
+}
+
+impl Protection {
+    pub(crate) fn flags(self) -> PageTableFlags {
+        use x86_64::structures::paging::PageTableFlags as Flags;
+        match self {
+            Protection::Writable => Flags::PRESENT | Flags::WRITABLE | Flags::NO_EXECUTE,
+            Protection::Executable => Flags::PRESENT,
+        }
+    }
+}
+
 /// Initialize a new OffsetPageTable.
 ///
+/// All writable pages mapped by the bootloader, such as the complete physical memory mapping and
+/// the stack, are made non-executable, so that only the kernel code remains executable.
+///
 /// # Safety
 ///
 /// This function is unsafe because the caller must guarantee that the
@@ -13,10 +61,82 @@ use x86_64::{
 /// `physical_memory_offset`. Also, this function must be only called once
 /// to avoid aliasing `&mut` references (which is undefined behavior).
 pub(crate) unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
+    PHYSICAL_MEMORY_OFFSET.init_once(|| physical_memory_offset);
+
+    // NO_EXECUTE flag is a reserved bit unless NXE is enabled
+    unsafe { Efer::update(|flags| flags.insert(EferFlags::NO_EXECUTE_ENABLE)) };
+    // make read-only pages read-only also for the kernel
+    unsafe { Cr0::update(|flags| flags.insert(Cr0Flags::WRITE_PROTECT)) };
     let level_4_table = unsafe { active_level_4_table(physical_memory_offset) };
+    enforce_w_xor_x(level_4_table);
     unsafe { OffsetPageTable::new(level_4_table, physical_memory_offset) }
 }
 
+/// Calls `f` with the virtual address, the entry and the effective flags of each mapped page
+/// (including huge pages) under `table`.
+///
+/// The effective flags are writable only if all levels are writable, and non-executable if any
+/// level is non-executable.
+fn for_each_page(
+    table: &mut PageTable,
+    level: u32,
+    base: u64,
+    parent_flags: PageTableFlags,
+    f: &mut impl FnMut(VirtAddr, &mut PageTableEntry, PageTableFlags),
+) {
+    for (index, entry) in table.iter_mut().enumerate() {
+        let flags = entry.flags();
+        if !flags.contains(PageTableFlags::PRESENT) {
+            continue;
+        }
+        let addr = base | ((index as u64) << (12 + 9 * (level - 1)));
+        let mut effective = flags;
+        effective.set(
+            PageTableFlags::WRITABLE,
+            flags.contains(PageTableFlags::WRITABLE)
+                && parent_flags.contains(PageTableFlags::WRITABLE),
+        );
+        effective.set(
+            PageTableFlags::NO_EXECUTE,
+            flags.contains(PageTableFlags::NO_EXECUTE)
+                || parent_flags.contains(PageTableFlags::NO_EXECUTE),
+        );
+        if level == 1 || flags.contains(PageTableFlags::HUGE_PAGE) {
+            f(VirtAddr::new_truncate(addr), entry, effective);
+        } else {
+            let table = unsafe { &mut *phys_to_virt(entry.addr()).as_mut_ptr::<PageTable>() };
+            for_each_page(table, level - 1, addr, effective, f);
+        }
+    }
+}
+
+/// Makes all writable pages under the level 4 table non-executable.
+fn enforce_w_xor_x(level_4_table: &mut PageTable) {
+    let mut count = 0;
+    for_each_page(
+        level_4_table,
+        4,
+        0,
+        PageTableFlags::WRITABLE,
+        &mut |_addr, entry, effective| {
+            if effective.contains(PageTableFlags::WRITABLE)
+                && !effective.contains(PageTableFlags::NO_EXECUTE)
+            {
+                entry.set_flags(entry.flags() | PageTableFlags::NO_EXECUTE);
+                count += 1;
+            }
+        },
+    );
+    tlb::flush_all();
+    debug!("W^X: made {} writable pages non-executable", count);
+}
+
+/// Returns the virtual address where the physical address is mapped in the complete
+/// physical memory mapping.
+pub(crate) fn phys_to_virt(addr: PhysAddr) -> VirtAddr {
+    *PHYSICAL_MEMORY_OFFSET.get() + addr.as_u64()
+}
+
 /// Returns a mutable reference to the active level 4 table.
 ///
 /// This function is unsafe because the caller must guarantee that the
@@ -43,14 +163,374 @@ pub(crate) fn make_identity_mapping(
     base_addr: u64,
     num_pages: usize,
 ) -> Result<()> {
-    use x86_64::structures::paging::PageTableFlags as Flags;
-    let base_page = Page::from_start_address(VirtAddr::new(base_addr))?;
-    let base_frame = PhysFrame::from_start_address(PhysAddr::new(base_addr))?;
-    let flags = Flags::PRESENT | Flags::WRITABLE;
+    let flags = Protection::Writable.flags();
+    identity_map(mapper, allocator, base_addr, num_pages, flags)
+}
+
+/// Maps `[base_addr, base_addr + num_pages * 4KiB)` as identity mapping, skipping pages
+/// already mapped.
+pub(crate) fn ensure_identity_mapping(
+    mapper: &mut OffsetPageTable,
+    allocator: &mut BitmapMemoryManager,
+    base_addr: u64,
+    num_pages: usize,
+    protection: Protection,
+) -> Result<()> {
+    let flags = protection.flags();
+    for i in 0..num_pages {
+        let addr = base_addr + i as u64 * Size4KiB::SIZE;
+        if mapper.translate_addr(VirtAddr::new(addr)).is_none() {
+            identity_map(mapper, allocator, addr, 1, flags)?;
+        }
+    }
+    Ok(())
+}
+
+fn identity_map(
+    mapper: &mut OffsetPageTable,
+    allocator: &mut BitmapMemoryManager,
+    base_addr: u64,
+    num_pages: usize,
+    flags: PageTableFlags,
+) -> Result<()> {
+    let base_page = Page::<Size4KiB>::from_start_address(VirtAddr::new(base_addr))?;
+    let base_frame = PhysFrame::<Size4KiB>::from_start_address(PhysAddr::new(base_addr))?;
     for i in 0..num_pages {
         let page = base_page + i as u64;
         let frame = base_frame + i as u64;
+        // registers in the same page (e.g. regions of a BAR) may be mapped more than once
+        if mapper.translate_page(page).ok() == Some(frame) {
+            continue;
+        }
         unsafe { mapper.map_to(page, frame, flags, &mut *allocator) }?.flush();
     }
     Ok(())
 }
+
+/// Maps device registers at `[phys, phys + size)` as identity mapping with caching disabled.
+///
+/// Normal write-back caching may reorder, merge, or drop accesses to device registers,
+/// so MMIO pages must be mapped with the cache-disable and write-through flags.
+pub(crate) fn map_mmio(
+    mapper: &mut OffsetPageTable,
+    allocator: &mut BitmapMemoryManager,
+    phys: PhysAddr,
+    size: u64,
+) -> Result<VolatileMmio> {
+    use x86_64::structures::paging::PageTableFlags as Flags;
+    let start = phys.align_down(Size4KiB::SIZE);
+    let end = (phys + size).align_up(Size4KiB::SIZE);
+    let num_pages = ((end - start) / Size4KiB::SIZE) as usize;
+    let flags = Protection::Writable.flags() | Flags::NO_CACHE | Flags::WRITE_THROUGH;
+    identity_map(mapper, allocator, start.as_u64(), num_pages, flags)?;
+    Ok(unsafe { VolatileMmio::new(VirtAddr::new(phys.as_u64()), size) })
+}
+
+/// Creates the page tables needed to map the virtual range without mapping any pages.
+///
+/// Pages in the range can then be mapped by [`map_preallocated_page`], which needs neither
+/// the mapper nor page table allocations.
+pub(crate) fn allocate_page_tables(
+    mapper: &mut OffsetPageTable,
+    allocator: &mut BitmapMemoryManager,
+    start: VirtAddr,
+    size: u64,
+) -> Result<()> {
+    let start_page = Page::<Size2MiB>::containing_address(start);
+    let end_page = Page::<Size2MiB>::containing_address(start + (size - 1));
+    for page in Page::range_inclusive(start_page, end_page) {
+        let level_4_table = mapper.level_4_table();
+        let level_3_table = next_table_or_create(&mut level_4_table[page.p4_index()], allocator)?;
+        let level_2_table = next_table_or_create(&mut level_3_table[page.p3_index()], allocator)?;
+        next_table_or_create(&mut level_2_table[page.p2_index()], allocator)?;
+    }
+    Ok(())
+}
+
+fn next_table_or_create<'a>(
+    entry: &'a mut PageTableEntry,
+    allocator: &mut BitmapMemoryManager,
+) -> Result<&'a mut PageTable> {
+    if entry.is_unused() {
+        let frame = allocator.allocate(1)?.start;
+        let table = phys_to_virt(frame.start_address()).as_mut_ptr::<PageTable>();
+        unsafe { table.write(PageTable::new()) };
+        entry.set_frame(frame, PageTableFlags::PRESENT | PageTableFlags::WRITABLE);
+    } else if entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+        bail!(ErrorKind::PartialHugePage);
+    }
+    Ok(unsafe { &mut *phys_to_virt(entry.addr()).as_mut_ptr::<PageTable>() })
+}
+
+/// Maps `page` to `frame` using the page tables created by [`allocate_page_tables`].
+///
+/// This can be used in the page fault handler, where the mapper may be in use by the
+/// interrupted code.
+///
+/// # Safety
+///
+/// This function is unsafe because the caller must guarantee that no one else modifies
+/// the level 1 page table of `page` concurrently.
+pub(crate) unsafe fn map_preallocated_page(
+    page: Page,
+    frame: PhysFrame,
+    protection: Protection,
+) -> Result<()> {
+    let entry = unsafe { preallocated_entry(page)? };
+    if !entry.is_unused() {
+        bail!(ErrorKind::MapTo(MapToError::PageAlreadyMapped(
+            entry.frame()?
+        )));
+    }
+    entry.set_frame(frame, protection.flags());
+    tlb::flush(page.start_address());
+    Ok(())
+}
+
+/// Returns whether `page` is mapped in the page tables created by [`allocate_page_tables`].
+///
+/// # Safety
+///
+/// This function is unsafe because the caller must guarantee that no one else modifies
+/// the level 1 page table of `page` concurrently.
+pub(crate) unsafe fn is_preallocated_page_mapped(page: Page) -> Result<bool> {
+    let entry = unsafe { preallocated_entry(page)? };
+    Ok(!entry.is_unused())
+}
+
+/// Returns the level 1 entry of `page` in the page tables created by [`allocate_page_tables`].
+///
+/// # Safety
+///
+/// The caller must guarantee that no one else modifies the level 1 page table of `page`
+/// concurrently.
+unsafe fn preallocated_entry(page: Page) -> Result<&'static mut PageTableEntry> {
+    use x86_64::registers::control::Cr3;
+
+    let (level_4_frame, _) = Cr3::read();
+    let mut table =
+        unsafe { &mut *phys_to_virt(level_4_frame.start_address()).as_mut_ptr::<PageTable>() };
+    for index in [page.p4_index(), page.p3_index(), page.p2_index()] {
+        let entry = &table[index];
+        if entry.is_unused() || entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+            bail!(ErrorKind::PageTableNotAllocated(page.start_address()));
+        }
+        table = unsafe { &mut *phys_to_virt(entry.addr()).as_mut_ptr::<PageTable>() };
+    }
+    Ok(&mut table[page.p1_index()])
+}
+
+/// Pages whose TLB entries must be invalidated after page table updates.
+#[derive(Debug, Clone)]
+#[must_use]
+pub(crate) struct TlbFlush {
+    pages: [VirtAddr; MAX_FLUSH_PAGES],
+    len: usize,
+    all: bool,
+}
+
+impl TlbFlush {
+    pub(crate) fn new() -> Self {
+        Self {
+            pages: [VirtAddr::zero(); MAX_FLUSH_PAGES],
+            len: 0,
+            all: false,
+        }
+    }
+
+    fn add(&mut self, addr: VirtAddr) {
+        if self.len < MAX_FLUSH_PAGES {
+            self.pages[self.len] = addr;
+            self.len += 1;
+        } else {
+            self.all = true;
+        }
+    }
+
+    /// Returns the pages to be invalidated, or `None` if the whole TLB must be flushed.
+    fn pages(&self) -> Option<&[VirtAddr]> {
+        (!self.all).then(|| &self.pages[..self.len])
+    }
+
+    /// Invalidates TLB entries on the current CPU only.
+    ///
+    /// This is called by the shootdown handler on each of the other CPUs.
+    pub(crate) fn flush_local(&self) {
+        match self.pages() {
+            Some(pages) => {
+                for addr in pages {
+                    tlb::flush(*addr);
+                }
+            }
+            None => tlb::flush_all(),
+        }
+    }
+
+    /// Invalidates TLB entries on the current CPU and notifies the other CPUs.
+    pub(crate) fn flush(self) {
+        self.flush_local();
+        if let Ok(handler) = SHOOTDOWN_HANDLER.try_get() {
+            handler(&self);
+        }
+    }
+}
+
+/// Registers the handler that invalidates TLB entries on the other CPUs.
+///
+/// Until the handler is registered, [`TlbFlush::flush`] only affects the current CPU.
+pub(crate) fn set_shootdown_handler(handler: fn(&TlbFlush)) {
+    SHOOTDOWN_HANDLER.init_once(|| handler);
+}
+
+/// Unmaps the virtual range and calls `f` with each unmapped frame range.
+///
+/// Pages not mapped are skipped.
+fn unmap_range_with(
+    mapper: &mut OffsetPageTable,
+    start: VirtAddr,
+    size: u64,
+    mut f: impl FnMut(PhysFrameRange),
+) -> Result<TlbFlush> {
+    let end = start + size;
+    let mut flush = TlbFlush::new();
+    let mut addr = start.align_down(Size4KiB::SIZE);
+    while addr < end {
+        let frame = match mapper.translate(addr) {
+            TranslateResult::Mapped { frame, .. } => frame,
+            TranslateResult::NotMapped => {
+                addr += Size4KiB::SIZE;
+                continue;
+            }
+            TranslateResult::InvalidFrameAddress(phys) => {
+                bail!(ErrorKind::InvalidFrameAddress(phys))
+            }
+        };
+        match frame {
+            MappedFrame::Size4KiB(_) => {
+                let page = Page::<Size4KiB>::containing_address(addr);
+                let (frame, flush_page) = mapper.unmap(page)?;
+                flush_page.ignore();
+                flush.add(page.start_address());
+                f(PhysFrame::range(frame, frame + 1));
+                addr = page.start_address() + Size4KiB::SIZE;
+            }
+            MappedFrame::Size2MiB(_) => {
+                let page = Page::<Size2MiB>::containing_address(addr);
+                if page.start_address() < start || end - page.start_address() < Size2MiB::SIZE {
+                    // splitting huge pages is not supported
+                    bail!(ErrorKind::PartialHugePage);
+                }
+                let (frame, flush_page) = mapper.unmap(page)?;
+                flush_page.ignore();
+                flush.add(page.start_address());
+                let frame = PhysFrame::<Size4KiB>::containing_address(frame.start_address());
+                f(PhysFrame::range(frame, frame + FRAMES_PER_HUGE_PAGE as u64));
+                addr = page.start_address() + Size2MiB::SIZE;
+            }
+            MappedFrame::Size1GiB(_) => bail!(ErrorKind::PartialHugePage),
+        }
+    }
+    Ok(flush)
+}
+
+/// Unmaps the virtual range without freeing the mapped frames.
+///
+/// This is for the ranges whose frames are not owned by the kernel, such as MMIO.
+/// The returned [`TlbFlush`] must be flushed before the range is reused.
+pub(crate) fn unmap_range(
+    mapper: &mut OffsetPageTable,
+    start: VirtAddr,
+    size: u64,
+) -> Result<TlbFlush> {
+    unmap_range_with(mapper, start, size, |_| {})
+}
+
+/// Unmaps the virtual range and returns the mapped frames to `allocator`.
+///
+/// The TLB is flushed before the frames are freed, so that stale entries never point to
+/// reallocated frames.
+pub(crate) fn unmap_and_free_range(
+    mapper: &mut OffsetPageTable,
+    allocator: &mut BitmapMemoryManager,
+    start: VirtAddr,
+    size: u64,
+) -> Result<()> {
+    let mut frames = Vec::new();
+    unmap_range_with(mapper, start, size, |range| frames.push(range))?.flush();
+    for range in frames {
+        allocator.free(range);
+    }
+    Ok(())
+}
+
+/// Changes the flags of all pages mapped in the virtual range.
+///
+/// The returned [`TlbFlush`] must be flushed to make the new flags effective.
+pub(crate) fn remap_with_flags(
+    mapper: &mut OffsetPageTable,
+    start: VirtAddr,
+    size: u64,
+    flags: PageTableFlags,
+) -> Result<TlbFlush> {
+    let end = start + size;
+    let mut flush = TlbFlush::new();
+    let mut addr = start.align_down(Size4KiB::SIZE);
+    while addr < end {
+        match mapper.translate(addr) {
+            TranslateResult::Mapped {
+                frame: MappedFrame::Size4KiB(_),
+                ..
+            } => {
+                let page = Page::<Size4KiB>::containing_address(addr);
+                unsafe { mapper.update_flags(page, flags) }?.ignore();
+                flush.add(page.start_address());
+                addr = page.start_address() + Size4KiB::SIZE;
+            }
+            TranslateResult::Mapped {
+                frame: MappedFrame::Size2MiB(_),
+                ..
+            } => {
+                let page = Page::<Size2MiB>::containing_address(addr);
+                unsafe { mapper.update_flags(page, flags | PageTableFlags::HUGE_PAGE) }?.ignore();
+                flush.add(page.start_address());
+                addr = page.start_address() + Size2MiB::SIZE;
+            }
+            TranslateResult::Mapped { .. } => {
+                bail!(ErrorKind::PartialHugePage)
+            }
+            TranslateResult::NotMapped => addr += Size4KiB::SIZE,
+            TranslateResult::InvalidFrameAddress(phys) => {
+                bail!(ErrorKind::InvalidFrameAddress(phys))
+            }
+        }
+    }
+    Ok(flush)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use x86_64::registers::control::Cr3;
+
+    #[test_case]
+    fn no_writable_executable_page() {
+        let (level_4_frame, _) = Cr3::read();
+        let level_4_table =
+            unsafe { &mut *phys_to_virt(level_4_frame.start_address()).as_mut_ptr::<PageTable>() };
+        let mut found = None;
+        for_each_page(
+            level_4_table,
+            4,
+            0,
+            PageTableFlags::WRITABLE,
+            &mut |addr, _entry, effective| {
+                if effective.contains(PageTableFlags::WRITABLE)
+                    && !effective.contains(PageTableFlags::NO_EXECUTE)
+                {
+                    found.get_or_insert(addr);
+                }
+            },
+        );
+        assert_eq!(found, None);
+    }
+}
@@ -1,13 +1,38 @@
-use crate::{interrupt::InterruptIndex, prelude::*, sync::SpinMutex};
+use crate::{
+    acpi, interrupt, memory, paging,
+    prelude::*,
+    sync::{OnceCell, SpinMutex},
+};
+use alloc::vec::Vec;
 use arrayvec::ArrayVec;
 use bit_field::BitField;
-use core::{fmt, ops::Range};
+use core::{
+    convert::TryFrom,
+    fmt,
+    ops::{Range, RangeInclusive},
+};
 use custom_debug_derive::Debug as CustomDebug;
-use x86_64::instructions::port::Port;
+use enumflags2::{bitflags, BitFlags};
+use x86_64::{instructions::port::Port, structures::paging::OffsetPageTable, PhysAddr};
+
+pub(crate) use self::ids::{class_name, device_name, vendor_name};
+
+mod ids;
 
 const INVALID_VENDOR_ID: u16 = 0xffff;
 
-struct Addr(u32);
+/// The size of the configuration space of a function, including the PCIe extended space.
+const CONFIG_SPACE_SIZE: u16 = 0x1000;
+/// The start of the PCIe extended configuration space, which is reachable only through ECAM.
+const EXTENDED_CONFIG_START: u16 = 0x100;
+
+#[derive(Debug, Clone, Copy)]
+struct Addr {
+    bus: u8,
+    device: u8,
+    function: u8,
+    reg_addr: u16,
+}
 
 impl Addr {
     const BITS_ADDR: Range<usize> = 0..8;
@@ -17,33 +42,30 @@ impl Addr {
     const BITS_RESERVED: Range<usize> = 24..31;
     const BIT_ENABLE: usize = 31;
 
-    fn new(bus: u8, device: u8, function: u8, reg_addr: u8) -> Self {
+    fn new(bus: u8, device: u8, function: u8, reg_addr: u16) -> Self {
         assert_eq!(reg_addr & 0x3, 0);
+        assert!(reg_addr < CONFIG_SPACE_SIZE);
+        Self {
+            bus,
+            device,
+            function,
+            reg_addr,
+        }
+    }
+
+    /// Returns the value of the CONFIG_ADDRESS port, or `None` for the extended configuration
+    /// space.
+    fn port_value(&self) -> Option<u32> {
+        let reg_addr = u8::try_from(self.reg_addr).ok()?;
         let mut value = 0u32;
         value.set_bits(Self::BITS_ADDR, u32::from(reg_addr));
-        value.set_bits(Self::BITS_FUNCTION, u32::from(function));
-        value.set_bits(Self::BITS_DEVICE, u32::from(device));
-        value.set_bits(Self::BITS_BUS, u32::from(bus));
+        value.set_bits(Self::BITS_FUNCTION, u32::from(self.function));
+        value.set_bits(Self::BITS_DEVICE, u32::from(self.device));
+        value.set_bits(Self::BITS_BUS, u32::from(self.bus));
         value.set_bits(Self::BITS_RESERVED, 0);
         value.set_bit(Self::BIT_ENABLE, true);
-        Self(value)
-    }
-
-    // fn reg_addr(&self) -> u8 {
-    //     self.0.get_bits(Self::BITS_ADDR) as u8
-    // }
-    // fn function(&self) -> u8 {
-    //     self.0.get_bits(Self::BITS_FUNCTION) as u8
-    // }
-    // fn device(&self) -> u8 {
-    //     self.0.get_bits(Self::BITS_DEVICE) as u8
-    // }
-    // fn bus(&self) -> u8 {
-    //     self.0.get_bits(Self::BITS_BUS) as u8
-    // }
-    // fn enable(&self) -> bool {
-    //     self.0.get_bit(Self::BIT_ENABLE)
-    // }
+        Some(value)
+    }
 }
 
 #[derive(Debug)]
@@ -52,40 +74,125 @@ struct PortSet {
     data: Port<u32>,
 }
 
+/// The memory-mapped configuration space (ECAM) of PCI segment group 0.
 #[derive(Debug)]
-struct Config(SpinMutex<PortSet>);
+struct Ecam {
+    /// The address of the configuration space of bus 0.
+    base: u64,
+    buses: RangeInclusive<u8>,
+}
 
-static CONFIG: Config = Config(SpinMutex::new(PortSet {
-    addr: Port::new(0x0cf8),
-    data: Port::new(0xcfc),
-}));
+impl Ecam {
+    fn reg_ptr(&self, addr: Addr) -> Option<*mut u32> {
+        if !self.buses.contains(&addr.bus) {
+            return None;
+        }
+        let offset = (u64::from(addr.bus) << 20)
+            | (u64::from(addr.device) << 15)
+            | (u64::from(addr.function) << 12)
+            | u64::from(addr.reg_addr);
+        Some((self.base + offset) as *mut u32)
+    }
+}
+
+/// Configuration space access through ECAM if available, or through the I/O ports otherwise.
+#[derive(Debug)]
+struct Config {
+    ecam: OnceCell<Ecam>,
+    ports: SpinMutex<PortSet>,
+}
+
+static CONFIG: Config = Config {
+    ecam: OnceCell::uninit(),
+    ports: SpinMutex::new(PortSet {
+        addr: Port::new(0x0cf8),
+        data: Port::new(0xcfc),
+    }),
+};
 
 impl Config {
+    fn ecam_reg(&self, addr: Addr) -> Option<*mut u32> {
+        self.ecam.try_get().ok()?.reg_ptr(addr)
+    }
+
+    /// Reads a register. The extended configuration space reads as all ones without ECAM.
     fn read(&self, addr: Addr) -> u32 {
-        let mut ports = self.0.lock();
+        if let Some(reg) = self.ecam_reg(addr) {
+            return unsafe { reg.read_volatile() };
+        }
+        let port_value = match addr.port_value() {
+            Some(value) => value,
+            None => return u32::MAX,
+        };
+        let mut ports = self.ports.lock();
         unsafe {
-            ports.addr.write(addr.0);
+            ports.addr.write(port_value);
             ports.data.read()
         }
     }
 
+    /// Writes a register. Writes to the extended configuration space are ignored without ECAM.
     fn write(&self, addr: Addr, data: u32) {
-        let mut ports = self.0.lock();
+        if let Some(reg) = self.ecam_reg(addr) {
+            unsafe { reg.write_volatile(data) };
+            return;
+        }
+        let port_value = match addr.port_value() {
+            Some(value) => value,
+            None => return,
+        };
+        let mut ports = self.ports.lock();
         unsafe {
-            ports.addr.write(addr.0);
+            ports.addr.write(port_value);
             ports.data.write(data)
         }
     }
 }
 
+/// Switches the configuration space access to ECAM if MCFG describes it.
+///
+/// This must be called after ACPI is initialized, and before any device is scanned.
+pub(crate) fn init(mapper: &mut OffsetPageTable) -> Result<()> {
+    let region = match acpi::pci_config_regions()
+        .iter()
+        .find(|region| region.segment == 0)
+    {
+        Some(region) => region,
+        None => {
+            info!("PCI: configuration space is accessed through I/O ports");
+            return Ok(());
+        }
+    };
+    let buses = region.start_bus..=region.end_bus;
+    let start = region.base + (u64::from(region.start_bus) << 20);
+    let size = (u64::from(region.end_bus - region.start_bus) + 1) << 20;
+    memory::with_memory_manager(|allocator| paging::map_mmio(mapper, allocator, start, size))?;
+    info!(
+        "PCI: ECAM at {:x} for bus {:02x}-{:02x}",
+        region.base.as_u64(),
+        region.start_bus,
+        region.end_bus
+    );
+    CONFIG.ecam.init_once(|| Ecam {
+        base: region.base.as_u64(),
+        buses,
+    });
+    Ok(())
+}
+
+/// Returns whether the PCIe extended configuration space (offsets from 0x100) is accessible.
+pub(crate) fn has_extended_config() -> bool {
+    CONFIG.ecam.try_get().is_ok()
+}
+
 fn read_vendor_id(bus: u8, device: u8, function: u8) -> u16 {
     let addr = Addr::new(bus, device, function, 0x00);
     (CONFIG.read(addr) & 0xffff) as u16
 }
-// fn read_device_id(bus: u8, device: u8, function: u8) -> u16 {
-//     let addr = Addr::new(bus, device, function, 0x00);
-//     (CONFIG.read(addr) >> 16) as u16
-// }
+fn read_device_id(bus: u8, device: u8, function: u8) -> u16 {
+    let addr = Addr::new(bus, device, function, 0x00);
+    (CONFIG.read(addr) >> 16) as u16
+}
 fn read_header_type(bus: u8, device: u8, function: u8) -> u8 {
     let addr = Addr::new(bus, device, function, 0x0c);
     ((CONFIG.read(addr) >> 16) & 0xff) as u8
@@ -113,24 +220,166 @@ pub(crate) struct Device {
     pub(crate) device: u8,
     pub(crate) function: u8,
     pub(crate) vendor_id: u16,
+    pub(crate) device_id: u16,
     pub(crate) class_code: ClassCode,
     pub(crate) header_type: u8,
 }
 
+/// Bits of the command register.
+#[bitflags]
+#[repr(u16)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Command {
+    IoSpace = 1 << 0,
+    MemorySpace = 1 << 1,
+    BusMaster = 1 << 2,
+    InterruptDisable = 1 << 10,
+}
+
 impl fmt::Display for Device {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "{:02x}.{:02x}.{:02x} vend={:04x}, class={}, head={:02x}",
-            self.bus, self.device, self.function, self.vendor_id, self.class_code, self.header_type
+            "{:02x}.{:02x}.{:02x} vend={:04x}, dev={:04x}, class={}, head={:02x}",
+            self.bus,
+            self.device,
+            self.function,
+            self.vendor_id,
+            self.device_id,
+            self.class_code,
+            self.header_type
         )
     }
 }
 
 impl Device {
-    fn addr(&self, reg_addr: u8) -> Addr {
+    fn addr(&self, reg_addr: u16) -> Addr {
         Addr::new(self.bus, self.device, self.function, reg_addr)
     }
+
+    /// Returns the bits of the command register which have [`Command`] variants.
+    pub(crate) fn command(&self) -> BitFlags<Command> {
+        BitFlags::from_bits_truncate((read_conf_reg(self, 0x04) & 0xffff) as u16)
+    }
+
+    /// Sets `set` and clears `clear` in the command register, keeping the other bits.
+    pub(crate) fn update_command(
+        &self,
+        set: impl Into<BitFlags<Command>>,
+        clear: impl Into<BitFlags<Command>>,
+    ) {
+        let command = read_conf_reg(self, 0x04) & 0xffff;
+        let command = (command | u32::from(set.into().bits())) & !u32::from(clear.into().bits());
+        // the status register in the upper half is written with zeros, since writing ones
+        // clears its bits
+        write_conf_reg(self, 0x04, command);
+    }
+
+    /// Enables the memory space decoding and the bus mastering, which DMA-capable devices need.
+    pub(crate) fn enable_bus_master(&self) {
+        self.update_command(Command::MemorySpace | Command::BusMaster, BitFlags::empty());
+    }
+
+    /// Returns the entries of the capability list.
+    pub(crate) fn capabilities(&self) -> Capabilities<'_> {
+        let has_list = read_conf_reg(self, 0x04) & STATUS_CAPABILITIES_LIST != 0;
+        let next = if has_list {
+            (read_conf_reg(self, 0x34) & 0xfc) as u8
+        } else {
+            0
+        };
+        Capabilities {
+            dev: self,
+            next,
+            remaining: MAX_CAPABILITIES,
+        }
+    }
+
+    /// Returns the IDs and offsets of the PCIe extended capabilities.
+    ///
+    /// This is empty for conventional PCI devices, and if the extended space is not accessible.
+    pub(crate) fn extended_capabilities(&self) -> impl Iterator<Item = (u16, u16)> + '_ {
+        let mut next = Some(EXTENDED_CONFIG_START);
+        // bounds a malformed list which loops
+        let mut remaining = (CONFIG_SPACE_SIZE - EXTENDED_CONFIG_START) / 4;
+        core::iter::from_fn(move || loop {
+            remaining = remaining.checked_sub(1)?;
+            let offset = next.take()?;
+            let header = read_ext_conf_reg(self, offset)?;
+            if header == 0 || header == u32::MAX {
+                return None;
+            }
+            let next_offset = (header >> 20) as u16 & !0x3;
+            if next_offset >= EXTENDED_CONFIG_START {
+                next = Some(next_offset);
+            }
+            let id = (header & 0xffff) as u16;
+            // an ID of zero in the first header means the list is empty
+            if id != 0 {
+                return Some((id, offset));
+            }
+        })
+    }
+
+    /// Returns the subsystem vendor ID and subsystem ID, which only the normal (type 0) header
+    /// has.
+    pub(crate) fn subsystem(&self) -> Option<(u16, u16)> {
+        if self.header_type & 0x7f != 0 {
+            return None;
+        }
+        let value = read_conf_reg(self, 0x2c);
+        Some(((value & 0xffff) as u16, (value >> 16) as u16))
+    }
+
+    /// Returns how the device currently signals interrupts.
+    pub(crate) fn interrupt_mode(&self) -> InterruptMode {
+        for cap in self.capabilities() {
+            let header = read_capability_header(self, cap.offset);
+            match cap.id {
+                CapabilityId::Msi if header.msi_enable() => {
+                    return InterruptMode::Msi {
+                        vectors: 1 << header.multi_msg_enable(),
+                    }
+                }
+                CapabilityId::MsiX if header.msix_enable() => return InterruptMode::MsiX,
+                _ => {}
+            }
+        }
+        let value = read_conf_reg(self, 0x3c);
+        let line = (value & 0xff) as u8;
+        match (value >> 8) as u8 {
+            pin @ 1..=4 => InterruptMode::Pin { pin, line },
+            _ => InterruptMode::None,
+        }
+    }
+}
+
+/// How a device signals interrupts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum InterruptMode {
+    None,
+    /// The legacy INTx pin (1 = INTA) and the line assigned by the firmware.
+    Pin {
+        pin: u8,
+        line: u8,
+    },
+    Msi {
+        vectors: u8,
+    },
+    MsiX,
+}
+
+impl fmt::Display for InterruptMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::None => write!(f, "none"),
+            Self::Pin { pin, line } => {
+                write!(f, "pin {}, line {}", char::from(b'A' + pin - 1), line)
+            }
+            Self::Msi { vectors } => write!(f, "MSI ({} vectors)", vectors),
+            Self::MsiX => write!(f, "MSI-X"),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -162,8 +411,13 @@ impl ClassCode {
     }
 }
 
-pub(crate) type Devices = ArrayVec<Device, 32>;
+/// Devices found by scanning, which are allocated on the heap since large hosts have many
+/// functions.
+pub(crate) type Devices = Vec<Device>;
 
+/// Scans all buses reachable from the host bridges.
+///
+/// This must be called after the heap is initialized.
 pub(crate) fn scan_all_bus() -> Result<Devices> {
     let mut devices = Devices::new();
 
@@ -208,6 +462,7 @@ fn scan_device(devices: &mut Devices, bus: u8, device: u8) -> Result<()> {
 
 fn scan_function(devices: &mut Devices, bus: u8, device: u8, function: u8) -> Result<()> {
     let vendor_id = read_vendor_id(bus, device, function);
+    let device_id = read_device_id(bus, device, function);
     let class_code = read_class_code(bus, device, function);
     let header_type = read_header_type(bus, device, function);
     let dev = Device {
@@ -215,11 +470,12 @@ fn scan_function(devices: &mut Devices, bus: u8, device: u8, function: u8) -> Re
         device,
         function,
         vendor_id,
+        device_id,
         class_code,
         header_type,
     };
     debug!("{}", dev);
-    devices.try_push(dev).map_err(|_| ErrorKind::Full)?;
+    devices.push(dev);
 
     if class_code.base == 0x06 && class_code.sub == 0x04 {
         // standard PCI-PCI bridge
@@ -236,32 +492,234 @@ fn calc_bar_addr(bar_index: u8) -> u8 {
 }
 
 pub(crate) fn read_conf_reg(dev: &Device, reg_addr: u8) -> u32 {
-    CONFIG.read(dev.addr(reg_addr))
+    CONFIG.read(dev.addr(u16::from(reg_addr)))
 }
 
 pub(crate) fn write_conf_reg(dev: &Device, reg_addr: u8, value: u32) {
-    CONFIG.write(dev.addr(reg_addr), value)
+    CONFIG.write(dev.addr(u16::from(reg_addr)), value)
+}
+
+/// Reads a register at any offset of the configuration space, including the PCIe extended
+/// space, or `None` if the extended space is not accessible.
+pub(crate) fn read_ext_conf_reg(dev: &Device, reg_addr: u16) -> Option<u32> {
+    if reg_addr >= EXTENDED_CONFIG_START && !has_extended_config() {
+        return None;
+    }
+    Some(CONFIG.read(dev.addr(reg_addr)))
+}
+
+/// Configuration space registers saved while the device is powered off.
+#[cfg(feature = "acpi-s3")]
+#[derive(Debug, Clone)]
+pub(crate) struct SavedConfig {
+    device: Device,
+    regs: [u32; 64],
+}
+
+#[cfg(feature = "acpi-s3")]
+impl SavedConfig {
+    pub(crate) fn save(device: &Device) -> Self {
+        let mut regs = [0; 64];
+        for (i, reg) in regs.iter_mut().enumerate() {
+            *reg = read_conf_reg(device, (i * 4) as u8);
+        }
+        Self {
+            device: *device,
+            regs,
+        }
+    }
+
+    /// Stops DMA of the device by clearing Bus Master Enable bit of the command register.
+    pub(crate) fn quiesce(&self) {
+        self.device
+            .update_command(BitFlags::empty(), Command::BusMaster);
+    }
+
+    pub(crate) fn restore(&self) {
+        // restore command register (offset 0x04) last, so that the device is enabled
+        // after all other registers are configured
+        for (i, reg) in self.regs.iter().enumerate().skip(1).rev() {
+            write_conf_reg(&self.device, (i * 4) as u8, *reg);
+        }
+    }
+}
+
+const BAR_IO_SPACE: u32 = 1 << 0;
+const BAR_TYPE_64BIT: u32 = 0b10 << 1;
+const BAR_PREFETCHABLE: u32 = 1 << 3;
+const MAX_BARS: usize = 6;
+
+/// The resource decoded by a Base Address Register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BarResource {
+    Io {
+        port: u32,
+        size: u32,
+    },
+    Memory {
+        addr: PhysAddr,
+        size: u64,
+        is_64bit: bool,
+        prefetchable: bool,
+    },
+}
+
+impl fmt::Display for BarResource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BarResource::Io { port, size } => write!(f, "I/O {:04x} ({} bytes)", port, size),
+            BarResource::Memory {
+                addr,
+                size,
+                is_64bit,
+                prefetchable,
+            } => {
+                write!(f, "mem {:x} ({} KiB", addr.as_u64(), size / 1024)?;
+                if *is_64bit {
+                    write!(f, ", 64-bit")?;
+                }
+                if *prefetchable {
+                    write!(f, ", prefetchable")?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
 }
 
-pub(crate) fn read_bar(dev: &Device, bar_index: u8) -> Result<u64> {
-    if bar_index >= 6 {
+impl Device {
+    /// Returns the number of BARs, which depends on the header type.
+    fn num_bars(&self) -> u8 {
+        match self.header_type & 0x7f {
+            0x00 => 6,
+            0x01 => 2,
+            _ => 0,
+        }
+    }
+}
+
+/// Writes all ones to the BAR at `addr` and returns the value read back, restoring the BAR.
+fn probe_bar(dev: &Device, addr: u8) -> u32 {
+    let original = read_conf_reg(dev, addr);
+    write_conf_reg(dev, addr, u32::MAX);
+    let probed = read_conf_reg(dev, addr);
+    write_conf_reg(dev, addr, original);
+    probed
+}
+
+/// Decodes and sizes a BAR, or returns `None` if the BAR is not implemented.
+///
+/// Sizing temporarily disables the decoding of the device, so this must not be called while a
+/// driver is accessing the device.
+pub(crate) fn read_bar(dev: &Device, bar_index: u8) -> Result<Option<BarResource>> {
+    if bar_index >= dev.num_bars() {
         bail!(ErrorKind::IndexOutOfRange);
     }
 
     let addr = calc_bar_addr(bar_index);
     let bar = read_conf_reg(dev, addr);
+    let is_64bit = bar & BAR_IO_SPACE == 0 && bar & (0b11 << 1) == BAR_TYPE_64BIT;
+    if is_64bit && bar_index + 1 >= dev.num_bars() {
+        bail!(ErrorKind::IndexOutOfRange);
+    }
 
-    // 32 bit address
-    if (bar & 4) == 0 {
-        return Ok(u64::from(bar));
+    let command = dev.command();
+    dev.update_command(BitFlags::empty(), Command::IoSpace | Command::MemorySpace);
+    let probed = probe_bar(dev, addr);
+    let probed_upper = if is_64bit {
+        probe_bar(dev, addr + 4)
+    } else {
+        0
+    };
+    dev.update_command(
+        command & (Command::IoSpace | Command::MemorySpace),
+        BitFlags::empty(),
+    );
+
+    if bar & BAR_IO_SPACE != 0 {
+        let mask = probed & !0x3;
+        if mask == 0 {
+            return Ok(None);
+        }
+        return Ok(Some(BarResource::Io {
+            port: bar & !0x3,
+            size: (!mask).wrapping_add(1) & 0xffff,
+        }));
     }
-    // 64 bit address
-    if bar_index >= 5 {
-        bail!(ErrorKind::IndexOutOfRange);
+
+    let (base, mask) = if is_64bit {
+        let bar_upper = read_conf_reg(dev, addr + 4);
+        (
+            u64::from(bar & !0xf) | u64::from(bar_upper) << 32,
+            u64::from(probed & !0xf) | u64::from(probed_upper) << 32,
+        )
+    } else {
+        (
+            u64::from(bar & !0xf),
+            u64::from(probed & !0xf) | 0xffff_ffff_0000_0000,
+        )
+    };
+    if mask == 0xffff_ffff_0000_0000 || mask == 0 {
+        return Ok(None);
     }
+    Ok(Some(BarResource::Memory {
+        addr: PhysAddr::new(base),
+        size: (!mask).wrapping_add(1),
+        is_64bit,
+        prefetchable: bar & BAR_PREFETCHABLE != 0,
+    }))
+}
+
+/// The BARs of a device, with their indexes.
+#[derive(Debug, Clone)]
+pub(crate) struct DeviceResources {
+    pub(crate) device: Device,
+    pub(crate) bars: ArrayVec<(u8, BarResource), MAX_BARS>,
+}
+
+static RESOURCE_MAP: OnceCell<Vec<DeviceResources>> = OnceCell::uninit();
+
+/// Sizes the BARs of all devices.
+///
+/// This must be called before the drivers start, since sizing disables the decoding of each
+/// device for a while.
+pub(crate) fn init_resource_map(devices: &[Device]) {
+    let map = devices
+        .iter()
+        .map(|dev| {
+            let mut bars = ArrayVec::new();
+            let mut index = 0;
+            while index < dev.num_bars() {
+                match read_bar(dev, index) {
+                    Ok(Some(bar)) => {
+                        bars.push((index, bar));
+                        if let BarResource::Memory { is_64bit: true, .. } = bar {
+                            index += 1;
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(err) => warn!("{}: failed to read BAR{}: {}", dev, index, err),
+                }
+                index += 1;
+            }
+            DeviceResources { device: *dev, bars }
+        })
+        .collect();
+    RESOURCE_MAP.init_once(|| map);
+}
 
-    let bar_upper = read_conf_reg(dev, addr + 4);
-    Ok(u64::from(bar) | u64::from(bar_upper) << 32)
+/// Returns the BARs sized at boot, or an empty slice for devices which were not present then.
+pub(crate) fn resources(dev: &Device) -> &'static [(u8, BarResource)] {
+    RESOURCE_MAP
+        .try_get()
+        .ok()
+        .and_then(|map| {
+            map.iter().find(|res| {
+                (res.device.bus, res.device.device, res.device.function)
+                    == (dev.bus, dev.device, dev.function)
+            })
+        })
+        .map_or(&[], |res| res.bars.as_slice())
 }
 
 #[allow(dead_code)]
@@ -293,8 +751,83 @@ impl MsiDeliveryMode {
     }
 }
 
-const CAPABILITY_MSI: u8 = 0x05;
-const CAPABILITY_MSIX: u8 = 0x11;
+/// Capabilities List bit of the status register, in the dword at offset 0x04.
+const STATUS_CAPABILITIES_LIST: u32 = 1 << 20;
+/// Bounds a malformed capability list which loops (each capability takes at least 4 bytes
+/// after the standard header).
+const MAX_CAPABILITIES: usize = (256 - 0x40) / 4;
+
+pub(crate) const EXT_CAPABILITY_AER: u16 = 0x0001;
+pub(crate) const EXT_CAPABILITY_SRIOV: u16 = 0x0010;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CapabilityId {
+    PowerManagement,
+    Msi,
+    VendorSpecific,
+    PciExpress,
+    MsiX,
+    Other(u8),
+}
+
+impl From<u8> for CapabilityId {
+    fn from(value: u8) -> Self {
+        match value {
+            0x01 => Self::PowerManagement,
+            0x05 => Self::Msi,
+            0x09 => Self::VendorSpecific,
+            0x10 => Self::PciExpress,
+            0x11 => Self::MsiX,
+            _ => Self::Other(value),
+        }
+    }
+}
+
+impl fmt::Display for CapabilityId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::PowerManagement => write!(f, "PM"),
+            Self::Msi => write!(f, "MSI"),
+            Self::VendorSpecific => write!(f, "vendor"),
+            Self::PciExpress => write!(f, "PCIe"),
+            Self::MsiX => write!(f, "MSI-X"),
+            Self::Other(id) => write!(f, "{:02x}", id),
+        }
+    }
+}
+
+/// An entry of the capability list of a device.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Capability {
+    pub(crate) id: CapabilityId,
+    /// The offset in the configuration space.
+    pub(crate) offset: u8,
+}
+
+#[derive(Debug)]
+pub(crate) struct Capabilities<'a> {
+    dev: &'a Device,
+    next: u8,
+    remaining: usize,
+}
+
+impl Iterator for Capabilities<'_> {
+    type Item = Capability;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next == 0 {
+            return None;
+        }
+        self.remaining = self.remaining.checked_sub(1)?;
+        let offset = self.next;
+        let header = read_capability_header(self.dev, offset);
+        self.next = header.next_ptr & 0xfc;
+        Some(Capability {
+            id: CapabilityId::from(header.cap_id),
+            offset,
+        })
+    }
+}
 
 #[derive(CustomDebug, Clone, Copy, Default)]
 #[repr(C)]
@@ -325,9 +858,12 @@ impl CapabilityHeader {
     const BIT_ADDR_64_CAPABLE: usize = 7;
     const BIT_PER_VECTOR_MASK_CAPABLE: usize = 8;
     // const BITS_RESERVED: Range<usize> = 9..16;
-    // fn msi_enable(self) -> bool {
-    //     self.cap.get_bit(Self::BIT_MSI_ENABLE)
-    // }
+    const BITS_MSIX_TABLE_SIZE: Range<usize> = 0..11;
+    const BIT_MSIX_FUNCTION_MASK: usize = 14;
+    const BIT_MSIX_ENABLE: usize = 15;
+    fn msi_enable(self) -> bool {
+        self.cap.get_bit(Self::BIT_MSI_ENABLE)
+    }
     fn set_msi_enable(&mut self, value: bool) -> &mut Self {
         let _ = self.cap.set_bit(Self::BIT_MSI_ENABLE, value);
         self
@@ -335,9 +871,9 @@ impl CapabilityHeader {
     fn multi_msg_capable(self) -> u8 {
         self.cap.get_bits(Self::BITS_MULTI_MSG_CAPABLE) as u8
     }
-    // fn multi_msg_enable(self) -> u8 {
-    //     self.cap.get_bits(Self::BITS_MULTI_MSG_ENABLE) as u8
-    // }
+    fn multi_msg_enable(self) -> u8 {
+        self.cap.get_bits(Self::BITS_MULTI_MSG_ENABLE) as u8
+    }
     fn set_multi_msg_enable(&mut self, value: u8) -> &mut Self {
         let _ = self
             .cap
@@ -350,6 +886,20 @@ impl CapabilityHeader {
     fn per_vector_mask_capable(self) -> bool {
         self.cap.get_bit(Self::BIT_PER_VECTOR_MASK_CAPABLE)
     }
+    fn msix_enable(self) -> bool {
+        self.cap.get_bit(Self::BIT_MSIX_ENABLE)
+    }
+    fn set_msix_enable(&mut self, value: bool) -> &mut Self {
+        let _ = self.cap.set_bit(Self::BIT_MSIX_ENABLE, value);
+        self
+    }
+    fn set_msix_function_mask(&mut self, value: bool) -> &mut Self {
+        let _ = self.cap.set_bit(Self::BIT_MSIX_FUNCTION_MASK, value);
+        self
+    }
+    fn msix_table_size(self) -> u16 {
+        self.cap.get_bits(Self::BITS_MSIX_TABLE_SIZE) + 1
+    }
 }
 
 #[derive(CustomDebug, Clone, Copy)]
@@ -368,46 +918,57 @@ struct MsiCapability {
     pending_bits: u32,
 }
 
-pub(crate) fn configure_msi_fixed_destination(
+/// Enables MSI or MSI-X of `dev` with a vector for each handler, and routes the messages to the
+/// CPU of `apic_id`.
+///
+/// Message `i` is delivered to `handlers[i]`. If the device supports fewer messages than
+/// handlers, only the leading handlers are used. Returns the first vector and the number of
+/// enabled messages. MSI-X needs its table in a memory BAR, which is mapped with `mapper`.
+pub(crate) fn enable_msi(
     dev: &Device,
+    mapper: &mut OffsetPageTable,
+    name: &'static str,
     apic_id: u32,
     trigger_mode: MsiTriggerMode,
     delivery_mode: MsiDeliveryMode,
-    vector: InterruptIndex,
-    num_vector_exponent: u8,
-) -> Result<()> {
+    handlers: &[interrupt::Handler],
+) -> Result<(u8, usize)> {
+    // MSI messages are addressed to this fixed range, whatever the local APIC base is
     let msg_addr = 0xfee00000 | (apic_id << 12);
-    let mut msg_data = (delivery_mode.as_u32() << 8) | vector.as_u32();
-    if trigger_mode == MsiTriggerMode::Level {
-        msg_data |= 0xc000;
-    }
-    configure_msi(dev, msg_addr, msg_data, num_vector_exponent)?;
-    Ok(())
-}
-
-fn configure_msi(
-    dev: &Device,
-    msg_addr: u32,
-    msg_data: u32,
-    num_vector_exponent: u8,
-) -> Result<()> {
-    let mut cap_addr = (read_conf_reg(dev, 0x34) & 0xff) as u8;
-    let mut msi_cap_addr = None;
-    let mut msix_cap_addr = None;
-    while cap_addr != 0 {
-        let header = read_capability_header(dev, cap_addr);
-        match header.cap_id {
-            CAPABILITY_MSI => msi_cap_addr = Some(cap_addr),
-            CAPABILITY_MSIX => msix_cap_addr = Some(cap_addr),
-            _ => {}
+    let msg_data = |vector: u8| {
+        let mut msg_data = (delivery_mode.as_u32() << 8) | u32::from(vector);
+        if trigger_mode == MsiTriggerMode::Level {
+            msg_data |= 0xc000;
         }
-        cap_addr = header.next_ptr;
-    }
-    if let Some(cap_addr) = msi_cap_addr {
-        return configure_msi_register(dev, cap_addr, msg_addr, msg_data, num_vector_exponent);
+        msg_data
+    };
+    let register = |base: u8, count: usize| {
+        for (vector, handler) in (base..).zip(&handlers[..count]) {
+            interrupt::register_handler(vector, *handler)?;
+        }
+        Ok::<_, Error>(())
+    };
+    let requested = handlers.len().max(1).next_power_of_two().trailing_zeros() as u8;
+
+    let find = |id| dev.capabilities().find(|cap| cap.id == id);
+    if let Some(cap) = find(CapabilityId::Msi) {
+        let capable = read_capability_header(dev, cap.offset).multi_msg_capable();
+        let exponent = u8::min(capable, requested);
+        let base = interrupt::allocate_vectors(name, exponent)?;
+        let count = usize::min(1 << exponent, handlers.len());
+        register(base, count)?;
+        configure_msi_register(dev, cap.offset, msg_addr, msg_data(base), exponent)?;
+        return Ok((base, count));
     }
-    if let Some(cap_addr) = msix_cap_addr {
-        return configure_msix_register(dev, cap_addr, msg_addr, msg_data, num_vector_exponent);
+    if let Some(cap) = find(CapabilityId::MsiX) {
+        let table_size = usize::from(read_capability_header(dev, cap.offset).msix_table_size());
+        let count = usize::min(table_size, handlers.len());
+        let exponent = count.max(1).next_power_of_two().trailing_zeros() as u8;
+        let base = interrupt::allocate_vectors(name, exponent)?;
+        register(base, count)?;
+        let msg_data = (base..).take(count).map(msg_data).collect::<Vec<_>>();
+        configure_msix_register(dev, mapper, cap.offset, msg_addr, &msg_data)?;
+        return Ok((base, count));
     }
     bail!(ErrorKind::NoPciMsi)
 }
@@ -432,14 +993,47 @@ fn configure_msi_register(
     Ok(())
 }
 
+/// The size of an MSI-X table entry: message address, upper address, data and vector control.
+const MSIX_ENTRY_SIZE: u64 = 16;
+const MSIX_ENTRY_MASKED: u32 = 1 << 0;
+
+/// Programs the MSI-X table with `msg_data` for the leading entries, and masks the rest.
 fn configure_msix_register(
-    _dev: &Device,
-    _cap_addr: u8,
-    _msg_addr: u32,
-    _msg_data: u32,
-    _num_vector_exponent: u8,
+    dev: &Device,
+    mapper: &mut OffsetPageTable,
+    cap_addr: u8,
+    msg_addr: u32,
+    msg_data: &[u32],
 ) -> Result<()> {
-    bail!(ErrorKind::NotImplemented)
+    let mut header = read_capability_header(dev, cap_addr);
+    let table = read_conf_reg(dev, cap_addr + 4);
+    let bar_index = (table & 0x7) as u8;
+    let table_offset = u64::from(table & !0x7);
+    let table_addr = match resources(dev).iter().find(|(index, _)| *index == bar_index) {
+        Some((_, BarResource::Memory { addr, .. })) => *addr + table_offset,
+        _ => bail!(ErrorKind::InvalidBar),
+    };
+    let table_size = u64::from(header.msix_table_size());
+    let table = memory::with_memory_manager(|allocator| {
+        paging::map_mmio(mapper, allocator, table_addr, table_size * MSIX_ENTRY_SIZE)
+    })?;
+
+    for entry in 0..table_size {
+        let offset = entry * MSIX_ENTRY_SIZE;
+        match msg_data.get(entry as usize) {
+            Some(data) => {
+                table.write(offset, msg_addr);
+                table.write(offset + 4, 0_u32);
+                table.write(offset + 8, *data);
+                table.write(offset + 12, 0_u32);
+            }
+            None => table.write(offset + 12, MSIX_ENTRY_MASKED),
+        }
+    }
+
+    header.set_msix_function_mask(false).set_msix_enable(true);
+    write_conf_reg(dev, cap_addr, header.as_u32());
+    Ok(())
 }
 
 fn read_capability_header(dev: &Device, cap_addr: u8) -> CapabilityHeader {
@@ -0,0 +1,103 @@
+//! Names of common PCI vendors, devices and classes, mainly those emulated by QEMU.
+
+use super::ClassCode;
+
+const VENDORS: &[(u16, &str)] = &[
+    (0x1002, "AMD"),
+    (0x1033, "NEC"),
+    (0x10de, "NVIDIA"),
+    (0x10ec, "Realtek"),
+    (0x1234, "QEMU"),
+    (0x15ad, "VMware"),
+    (0x1af4, "Red Hat (virtio)"),
+    (0x1b36, "Red Hat (QEMU)"),
+    (0x8086, "Intel"),
+];
+
+const DEVICES: &[(u16, u16, &str)] = &[
+    (0x1033, 0x0194, "uPD720200 USB 3.0 Host Controller"),
+    (0x1234, 0x1111, "Standard VGA"),
+    (0x1af4, 0x1000, "Virtio network device"),
+    (0x1af4, 0x1001, "Virtio block device"),
+    (0x1af4, 0x1041, "Virtio 1.0 network device"),
+    (0x1af4, 0x1042, "Virtio 1.0 block device"),
+    (0x1b36, 0x0008, "PCIe Host bridge"),
+    (0x1b36, 0x000d, "XHCI Host Controller"),
+    (0x8086, 0x100e, "82540EM Gigabit Ethernet Controller"),
+    (0x8086, 0x10d3, "82574L Gigabit Network Connection"),
+    (0x8086, 0x1237, "440FX - 82441FX PMC [Natoma]"),
+    (0x8086, 0x2918, "82801IB (ICH9) LPC Interface Controller"),
+    (
+        0x8086,
+        0x2922,
+        "82801IR/IO/IH (ICH9R/DO/DH) 6 port SATA Controller [AHCI mode]",
+    ),
+    (0x8086, 0x2930, "82801I (ICH9 Family) SMBus Controller"),
+    (
+        0x8086,
+        0x2934,
+        "82801I (ICH9 Family) USB UHCI Controller #1",
+    ),
+    (
+        0x8086,
+        0x293a,
+        "82801I (ICH9 Family) USB2 EHCI Controller #1",
+    ),
+    (0x8086, 0x29c0, "82G33/G31/P35/P31 Express DRAM Controller"),
+    (0x8086, 0x5845, "QEMU NVM Express Controller"),
+    (0x8086, 0x7000, "82371SB PIIX3 ISA [Natoma/Triton II]"),
+    (0x8086, 0x7010, "82371SB PIIX3 IDE [Natoma/Triton II]"),
+    (0x8086, 0x7113, "82371AB/EB/MB PIIX4 ACPI"),
+];
+
+/// Class names, where `None` matches any subclass or programming interface.
+const CLASSES: &[(u8, Option<u8>, Option<u8>, &str)] = &[
+    (0x01, Some(0x01), None, "IDE interface"),
+    (0x01, Some(0x06), Some(0x01), "SATA controller (AHCI)"),
+    (0x01, Some(0x08), Some(0x02), "NVMe controller"),
+    (0x01, None, None, "Mass storage controller"),
+    (0x02, Some(0x00), None, "Ethernet controller"),
+    (0x02, None, None, "Network controller"),
+    (0x03, Some(0x00), None, "VGA compatible controller"),
+    (0x03, None, None, "Display controller"),
+    (0x04, None, None, "Multimedia controller"),
+    (0x05, None, None, "Memory controller"),
+    (0x06, Some(0x00), None, "Host bridge"),
+    (0x06, Some(0x01), None, "ISA bridge"),
+    (0x06, Some(0x04), None, "PCI bridge"),
+    (0x06, None, None, "Bridge"),
+    (0x07, None, None, "Communication controller"),
+    (0x08, None, None, "System peripheral"),
+    (0x09, None, None, "Input device controller"),
+    (0x0c, Some(0x03), Some(0x00), "USB controller (UHCI)"),
+    (0x0c, Some(0x03), Some(0x10), "USB controller (OHCI)"),
+    (0x0c, Some(0x03), Some(0x20), "USB controller (EHCI)"),
+    (0x0c, Some(0x03), Some(0x30), "USB controller (xHCI)"),
+    (0x0c, Some(0x05), None, "SMBus"),
+    (0x0c, None, None, "Serial bus controller"),
+];
+
+pub(crate) fn vendor_name(vendor_id: u16) -> Option<&'static str> {
+    VENDORS
+        .iter()
+        .find(|(id, _)| *id == vendor_id)
+        .map(|(_, name)| *name)
+}
+
+pub(crate) fn device_name(vendor_id: u16, device_id: u16) -> Option<&'static str> {
+    DEVICES
+        .iter()
+        .find(|(vendor, device, _)| (*vendor, *device) == (vendor_id, device_id))
+        .map(|(_, _, name)| *name)
+}
+
+pub(crate) fn class_name(class_code: &ClassCode) -> &'static str {
+    CLASSES
+        .iter()
+        .find(|(base, sub, interface, _)| {
+            *base == class_code.base
+                && sub.map_or(true, |sub| sub == class_code.sub)
+                && interface.map_or(true, |interface| interface == class_code.interface)
+        })
+        .map_or("Unclassified device", |(_, _, _, name)| *name)
+}
@@ -0,0 +1,104 @@
+//! ACPI power management timer.
+//!
+//! The PM timer is a free-running 3.579545 MHz counter of 24 or 32 bits, which keeps counting
+//! in all power states of the processor. Reads are extended to 64 bits, which requires the counter
+//! to be read at least once per wraparound (about 4.7 seconds with 24 bits). Once the clock is
+//! backed by the PM timer, every interrupt handler reads it (see
+//! [`interrupt::record`](crate::interrupt::record)), so the periodic timer interrupt keeps the
+//! extension correct.
+
+use crate::{acpi, device, prelude::*, sync::OnceCell, time::TimerSource};
+use alloc::format;
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::instructions::port::PortReadOnly;
+
+pub(crate) const FREQUENCY: u64 = 3_579_545;
+
+#[derive(Debug)]
+pub(crate) struct PmTimer {
+    port: u16,
+    mask: u64,
+    /// The last value returned by `read`.
+    last: AtomicU64,
+}
+
+impl PmTimer {
+    fn read_raw(&self) -> u64 {
+        let mut port = PortReadOnly::<u32>::new(self.port);
+        u64::from(unsafe { port.read() }) & self.mask
+    }
+}
+
+impl TimerSource for PmTimer {
+    fn name(&self) -> &'static str {
+        "ACPI PM timer"
+    }
+
+    fn frequency(&self) -> u64 {
+        FREQUENCY
+    }
+
+    fn read(&self) -> u64 {
+        let raw = self.read_raw();
+        let mut last = self.last.load(Ordering::Relaxed);
+        loop {
+            let delta = raw.wrapping_sub(last) & self.mask;
+            if delta > self.mask / 2 {
+                // another reader stored a value read after `raw`
+                return last;
+            }
+            match self.last.compare_exchange_weak(
+                last,
+                last + delta,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return last + delta,
+                Err(current) => last = current,
+            }
+        }
+    }
+}
+
+static PM_TIMER: OnceCell<PmTimer> = OnceCell::uninit();
+
+/// Locates the PM timer through FADT.
+///
+/// This must be called after ACPI is initialized.
+pub(crate) fn init() {
+    let (port, is_32bit) = match acpi::pm_timer_block() {
+        Some(block) => block,
+        None => {
+            info!("ACPI PM timer is not available");
+            return;
+        }
+    };
+    let mask = if is_32bit { 0xffff_ffff } else { 0x00ff_ffff };
+    let timer = PmTimer {
+        port,
+        mask,
+        last: AtomicU64::new(0),
+    };
+    // start the extended counter from the current raw value
+    timer.last.store(timer.read_raw(), Ordering::Relaxed);
+    PM_TIMER.init_once(|| timer);
+    device::register_platform(format!("ACPI PM timer (port {:04x})", port), "pm_timer");
+}
+
+/// Returns the PM timer, or `None` if it is not available or not initialized yet.
+pub(crate) fn get() -> Option<&'static PmTimer> {
+    PM_TIMER.try_get().ok()
+}
+
+/// Busy-waits for `msec` milliseconds.
+///
+/// This is the reference for calibrating other timers, and is usable before interrupts are
+/// enabled.
+pub(crate) fn wait_milliseconds(msec: u32) {
+    #[allow(clippy::expect_used)]
+    let timer = get().expect("ACPI PM timer is not available");
+    let end = timer.read() + FREQUENCY * u64::from(msec) / 1000;
+    while timer.read() < end {
+        core::hint::spin_loop();
+    }
+}
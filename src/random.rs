@@ -0,0 +1,74 @@
+//! Boot-time entropy source used to randomize the memory layout.
+//!
+//! RDRAND is used if the CPU supports it. Otherwise, numbers are generated from a TSC seed,
+//! which is predictable but still differs between boots.
+
+use crate::{cpu, prelude::*};
+use core::{
+    arch::x86_64::_rdtsc,
+    ops::Range,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+static STATE: AtomicU64 = AtomicU64::new(0);
+
+pub(crate) fn init() {
+    let seed = unsafe { _rdtsc() };
+    STATE.store(seed, Ordering::Relaxed);
+    if cpu::features().rdrand {
+        info!("entropy source: RDRAND");
+    } else {
+        info!("entropy source: TSC");
+    }
+}
+
+fn rdrand() -> Option<u64> {
+    // RDRAND may fail temporarily, so retry several times
+    for _ in 0..10 {
+        let value: u64;
+        let ok: u8;
+        unsafe {
+            asm!(
+                "rdrand {value}",
+                "setc {ok}",
+                value = out(reg) value,
+                ok = out(reg_byte) ok,
+                options(nomem, nostack),
+            )
+        };
+        if ok != 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// SplitMix64
+fn next_pseudo_random() -> u64 {
+    let mut z = STATE
+        .fetch_add(0x9e37_79b9_7f4a_7c15, Ordering::Relaxed)
+        .wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Mixes `value` from an entropy source into the pseudo-random state.
+pub(crate) fn add_entropy(value: u64) {
+    STATE.fetch_xor(value, Ordering::Relaxed);
+}
+
+pub(crate) fn u64() -> u64 {
+    if cpu::features().rdrand {
+        if let Some(value) = rdrand() {
+            return value;
+        }
+    }
+    next_pseudo_random()
+}
+
+/// Returns a random number in `range`.
+pub(crate) fn range(range: Range<u64>) -> u64 {
+    assert!(range.start < range.end);
+    range.start + u64() % (range.end - range.start)
+}
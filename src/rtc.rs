@@ -0,0 +1,251 @@
+//! CMOS real-time clock.
+//!
+//! The RTC is read only once at boot to seed the wall clock kept by [`time`], since reading it
+//! takes up to a second while an update is in progress. The RTC is assumed to hold UTC.
+
+use crate::{acpi, device, prelude::*, time};
+use core::{fmt, time::Duration};
+use x86_64::instructions::{interrupts, port::Port};
+
+const ADDRESS_PORT: u16 = 0x70;
+const DATA_PORT: u16 = 0x71;
+/// Keeps NMI disabled while the address register is written.
+const NMI_DISABLE: u8 = 0x80;
+
+const REG_SECOND: u8 = 0x00;
+const REG_MINUTE: u8 = 0x02;
+const REG_HOUR: u8 = 0x04;
+const REG_DAY: u8 = 0x07;
+const REG_MONTH: u8 = 0x08;
+const REG_YEAR: u8 = 0x09;
+const REG_STATUS_A: u8 = 0x0a;
+const REG_STATUS_B: u8 = 0x0b;
+
+/// Update In Progress
+const STATUS_A_UIP: u8 = 1 << 7;
+const STATUS_B_24_HOUR: u8 = 1 << 1;
+const STATUS_B_BINARY: u8 = 1 << 2;
+const HOUR_PM: u8 = 1 << 7;
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// A calendar date and time in UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct DateTime {
+    pub(crate) year: u16,
+    pub(crate) month: u8,
+    pub(crate) day: u8,
+    pub(crate) hour: u8,
+    pub(crate) minute: u8,
+    pub(crate) second: u8,
+}
+
+impl DateTime {
+    /// Converts the time elapsed from the Unix epoch.
+    pub(crate) fn from_unix(since_epoch: Duration) -> Self {
+        let secs = since_epoch.as_secs();
+        let days = (secs / SECONDS_PER_DAY) as i64;
+        let secs_of_day = secs % SECONDS_PER_DAY;
+
+        // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z.rem_euclid(146_097);
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + i64::from(month <= 2);
+
+        Self {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (secs_of_day / 3600) as u8,
+            minute: (secs_of_day / 60 % 60) as u8,
+            second: (secs_of_day % 60) as u8,
+        }
+    }
+
+    /// Returns the time elapsed from the Unix epoch, or zero for earlier dates.
+    pub(crate) fn to_unix(self) -> Duration {
+        // http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+        let month = i64::from(self.month);
+        let year = i64::from(self.year) - i64::from(month <= 2);
+        let era = year.div_euclid(400);
+        let yoe = year.rem_euclid(400);
+        let mp = (month + 9) % 12;
+        let doy = (153 * mp + 2) / 5 + i64::from(self.day) - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        let days = era * 146_097 + doe - 719_468;
+
+        let secs = days * SECONDS_PER_DAY as i64
+            + i64::from(self.hour) * 3600
+            + i64::from(self.minute) * 60
+            + i64::from(self.second);
+        Duration::from_secs(secs.max(0) as u64)
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+fn read_register(index: u8) -> u8 {
+    let mut address = Port::<u8>::new(ADDRESS_PORT);
+    let mut data = Port::<u8>::new(DATA_PORT);
+    unsafe {
+        address.write(NMI_DISABLE | index);
+        data.read()
+    }
+}
+
+/// Raw register values, which may be BCD and 12-hour.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Registers {
+    second: u8,
+    minute: u8,
+    hour: u8,
+    day: u8,
+    month: u8,
+    year: u8,
+    century: Option<u8>,
+}
+
+impl Registers {
+    fn read(century_register: Option<u8>) -> Self {
+        while read_register(REG_STATUS_A) & STATUS_A_UIP != 0 {
+            core::hint::spin_loop();
+        }
+        Self {
+            second: read_register(REG_SECOND),
+            minute: read_register(REG_MINUTE),
+            hour: read_register(REG_HOUR),
+            day: read_register(REG_DAY),
+            month: read_register(REG_MONTH),
+            year: read_register(REG_YEAR),
+            century: century_register.map(read_register),
+        }
+    }
+
+    fn to_date_time(self, status_b: u8) -> DateTime {
+        let binary = status_b & STATUS_B_BINARY != 0;
+        let decode = |value: u8| {
+            if binary {
+                value
+            } else {
+                (value >> 4) * 10 + (value & 0xf)
+            }
+        };
+
+        let pm = self.hour & HOUR_PM != 0;
+        let mut hour = decode(self.hour & !HOUR_PM);
+        if status_b & STATUS_B_24_HOUR == 0 {
+            // 12 AM is 0 o'clock, and 12 PM is 12 o'clock
+            hour %= 12;
+            if pm {
+                hour += 12;
+            }
+        }
+        let century = self.century.map(decode).unwrap_or(20);
+
+        DateTime {
+            year: u16::from(century) * 100 + u16::from(decode(self.year)),
+            month: decode(self.month),
+            day: decode(self.day),
+            hour,
+            minute: decode(self.minute),
+            second: decode(self.second),
+        }
+    }
+}
+
+/// Reads the current date and time from the RTC.
+///
+/// The registers are read until two consecutive reads agree, so a read racing with an update is
+/// never returned.
+pub(crate) fn read() -> DateTime {
+    let century_register = acpi::rtc_century_register();
+    interrupts::without_interrupts(|| {
+        let mut last = Registers::read(century_register);
+        loop {
+            let current = Registers::read(century_register);
+            if current == last {
+                break;
+            }
+            last = current;
+        }
+        last.to_date_time(read_register(REG_STATUS_B))
+    })
+}
+
+/// Seeds the wall clock with the RTC.
+///
+/// This must be called after the clock is initialized.
+pub(crate) fn init() {
+    device::register_platform("CMOS RTC", "rtc");
+    let now = read();
+    time::set_wall_clock(now.to_unix());
+    info!("RTC: {} UTC", now);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date_time(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> DateTime {
+        DateTime {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+        }
+    }
+
+    #[test_case]
+    fn unix_epoch() {
+        let epoch = date_time(1970, 1, 1, 0, 0, 0);
+        assert_eq!(epoch.to_unix(), Duration::ZERO);
+        assert_eq!(DateTime::from_unix(Duration::ZERO), epoch);
+    }
+
+    #[test_case]
+    fn leap_day() {
+        let leap_day = date_time(2024, 2, 29, 12, 34, 56);
+        assert_eq!(leap_day.to_unix(), Duration::from_secs(1_709_210_096));
+        assert_eq!(DateTime::from_unix(leap_day.to_unix()), leap_day);
+        let next_day = DateTime::from_unix(leap_day.to_unix() + Duration::from_secs(86400));
+        assert_eq!(next_day, date_time(2024, 3, 1, 12, 34, 56));
+    }
+
+    #[test_case]
+    fn bcd_12_hour() {
+        let registers = Registers {
+            second: 0x59,
+            minute: 0x30,
+            hour: HOUR_PM | 0x12,
+            day: 0x31,
+            month: 0x12,
+            year: 0x21,
+            century: None,
+        };
+        assert_eq!(
+            registers.to_date_time(0),
+            date_time(2021, 12, 31, 12, 30, 59)
+        );
+        let registers = Registers {
+            hour: 0x12,
+            ..registers
+        };
+        assert_eq!(registers.to_date_time(0).hour, 0);
+    }
+}
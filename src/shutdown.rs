@@ -0,0 +1,31 @@
+//! Power-off through the ACPI soft-off state (S5).
+
+use crate::{acpi, fat, pm_timer, prelude::*};
+use x86_64::instructions::interrupts;
+
+/// `SLP_TYPa` and `SLP_TYPb` of `_S5_` defined by QEMU, used if the DSDT does not define it.
+const DEFAULT_S5_SLEEP_TYPE: (u16, u16) = (0, 0);
+
+/// Powers off the system.
+///
+/// The file system is locked before writing `SLP_EN`, so that no task is in the middle of an
+/// access. The file system image lives in memory and is never modified, so there is nothing to
+/// write back.
+///
+/// This function returns only if the system failed to enter the soft-off state.
+pub(crate) fn shutdown() -> Result<()> {
+    let sleep_type = acpi::sleep_type(b"_S5_").unwrap_or_else(|| {
+        warn!("_S5_ not found in DSDT, using the default sleep type");
+        DEFAULT_S5_SLEEP_TYPE
+    });
+
+    let _fs = fat::lock();
+    info!("powering off");
+    interrupts::without_interrupts(|| {
+        acpi::enter_sleep_state(sleep_type);
+        // the system may take a while to enter the sleep state
+        pm_timer::wait_milliseconds(100);
+    });
+    warn!("failed to power off");
+    bail!(ErrorKind::SleepFailed)
+}
@@ -0,0 +1,174 @@
+//! Application processor (AP) bring-up.
+//!
+//! Each AP listed in MADT is started with the INIT-SIPI-SIPI sequence. It begins executing the
+//! trampoline in real mode, switches to long mode with the kernel page table, loads its own
+//! GDT/TSS (prepared by the bootstrap processor) and the shared IDT, and reports itself online.
+//!
+//! The scheduler still assumes a single CPU, so started APs only idle in `hlt` and invalidate
+//! their TLB entries when the bootstrap processor changes the page table.
+
+use crate::{
+    acpi, cpu, gdt,
+    interrupt::{self, InterruptContextGuard, InterruptIndex},
+    local_apic::{self, IpiDelivery},
+    memory,
+    paging::{self, TlbFlush},
+    pm_timer,
+    prelude::*,
+    trampoline::{Trampoline, TrampolineParams},
+};
+use alloc::vec;
+use core::{
+    convert::TryFrom,
+    hint, mem, ptr,
+    sync::atomic::{AtomicPtr, AtomicUsize, Ordering},
+};
+use x86_64::{
+    instructions::interrupts,
+    structures::{idt::InterruptStackFrame, paging::OffsetPageTable},
+    VirtAddr,
+};
+
+const AP_STACK_SIZE: usize = 4096 * 4;
+/// How long the bootstrap processor waits for each AP to come online.
+const STARTUP_TIMEOUT_MILLISECONDS: u32 = 100;
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C, align(16))]
+struct ApStackElement {
+    _dummy: [u8; 16],
+}
+static_assertions::const_assert_eq!(mem::size_of::<ApStackElement>(), 16);
+
+/// The flush requested by [`shootdown`], read by the handler on the other CPUs.
+static SHOOTDOWN_REQUEST: AtomicPtr<TlbFlush> = AtomicPtr::new(ptr::null_mut());
+/// Number of CPUs which have not finished the requested flush yet.
+static SHOOTDOWN_PENDING: AtomicUsize = AtomicUsize::new(0);
+
+/// Registers the CPUs listed in MADT and starts all APs.
+///
+/// This must be called after the ACPI PM timer becomes available, with interrupts disabled.
+pub(crate) fn init(mapper: &mut OffsetPageTable) -> Result<()> {
+    let bsp_apic_id = cpu::lapic_id();
+    let ap_apic_ids = acpi::platform_info()
+        .map(|info| &info.local_apic_ids[..])
+        .unwrap_or(&[])
+        .iter()
+        .copied()
+        .filter(|&id| id != bsp_apic_id);
+    cpu::init_cpus(ap_apic_ids);
+
+    let apic_ids = cpu::apic_ids();
+    if apic_ids.len() <= 1 {
+        info!("no application processors found");
+        return Ok(());
+    }
+
+    let trampoline =
+        memory::with_memory_manager(|allocator| Trampoline::install(mapper, allocator))?;
+    // the startup vector is the page number of the trampoline
+    let vector = u8::try_from(trampoline.start_address().as_u64() >> 12)?;
+
+    for (index, &apic_id) in apic_ids.iter().enumerate().skip(1) {
+        gdt::init_cpu(index);
+
+        // zero-initialization touches the lazily mapped heap pages, so the AP never faults on
+        // its stack
+        let stack = vec![
+            ApStackElement { _dummy: [0; 16] };
+            AP_STACK_SIZE / mem::size_of::<ApStackElement>()
+        ]
+        .leak();
+        let stack_end = VirtAddr::from_ptr(stack.as_ptr()) + AP_STACK_SIZE;
+        trampoline.set_params(&TrampolineParams::from_current_cpu(
+            stack_end,
+            ap_entry,
+            index as u64,
+        ));
+
+        local_apic::registers().send_ipi(apic_id, IpiDelivery::Init);
+        pm_timer::wait_milliseconds(10);
+        for _ in 0..2 {
+            local_apic::registers().send_ipi(apic_id, IpiDelivery::Startup(vector));
+            pm_timer::wait_milliseconds(1);
+            if cpu::is_online(index) {
+                break;
+            }
+        }
+
+        let mut waited = 0;
+        while !cpu::is_online(index) && waited < STARTUP_TIMEOUT_MILLISECONDS {
+            pm_timer::wait_milliseconds(1);
+            waited += 1;
+        }
+        if cpu::is_online(index) {
+            debug!("CPU {} (APIC ID {}) is online", index, apic_id);
+        } else {
+            warn!("CPU {} (APIC ID {}) did not respond", index, apic_id);
+        }
+    }
+
+    info!("{} of {} CPUs online", cpu::online_count(), apic_ids.len());
+
+    if cpu::online_count() > 1 {
+        paging::set_shootdown_handler(shootdown);
+    }
+    // an AP which did not respond may still start the trampoline later
+    if cpu::online_count() == apic_ids.len() {
+        memory::with_memory_manager(|allocator| trampoline.uninstall(mapper, allocator))?;
+    }
+
+    Ok(())
+}
+
+/// Makes the other online CPUs invalidate the TLB entries of `flush`, and waits for them.
+///
+/// Only the bootstrap processor changes the page table, so no two shootdowns run at a time.
+fn shootdown(flush: &TlbFlush) {
+    interrupts::without_interrupts(|| {
+        let current = cpu::current_index();
+        let targets = || {
+            cpu::apic_ids()
+                .iter()
+                .enumerate()
+                .filter(move |(index, _)| *index != current && cpu::is_online(*index))
+        };
+        SHOOTDOWN_PENDING.store(targets().count(), Ordering::Relaxed);
+        SHOOTDOWN_REQUEST.store(flush as *const TlbFlush as *mut TlbFlush, Ordering::Release);
+        let vector = InterruptIndex::TlbShootdown.as_u8();
+        for (_, &apic_id) in targets() {
+            local_apic::registers().send_ipi(apic_id, IpiDelivery::Fixed(vector));
+        }
+        while SHOOTDOWN_PENDING.load(Ordering::Acquire) > 0 {
+            hint::spin_loop();
+        }
+        SHOOTDOWN_REQUEST.store(ptr::null_mut(), Ordering::Relaxed);
+    });
+}
+
+/// Invalidates the TLB entries requested by [`shootdown`] on the current CPU.
+pub(crate) extern "x86-interrupt" fn tlb_shootdown_handler(_stack_frame: InterruptStackFrame) {
+    interrupt::record(InterruptIndex::TlbShootdown.as_u8());
+    let _guard = InterruptContextGuard::new();
+    let request = SHOOTDOWN_REQUEST.load(Ordering::Acquire);
+    if let Some(flush) = unsafe { request.as_ref() } {
+        flush.flush_local();
+    }
+    SHOOTDOWN_PENDING.fetch_sub(1, Ordering::Release);
+    interrupt::notify_end_of_interrupt();
+}
+
+extern "C" fn ap_entry(index: u64) -> ! {
+    let index = index as usize;
+    cpu::init_local(index);
+    gdt::load(index);
+    interrupt::reload();
+    local_apic::init_local();
+    cpu::set_online(index);
+
+    // Nothing is scheduled on APs yet, so they only wait for TLB shootdown requests. Logging is
+    // avoided here, since the locks of the loggers are not meant to be contended.
+    loop {
+        interrupts::enable_and_hlt();
+    }
+}
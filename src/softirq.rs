@@ -0,0 +1,74 @@
+//! Deferred work (bottom halves) of interrupt handlers.
+//!
+//! Interrupt handlers only [`raise`] a softirq to record that work is pending. The registered
+//! handler runs later in the softirq co-task with interrupts enabled, so that the time spent
+//! with interrupts disabled stays short and the locks taken by handlers are never contended by
+//! interrupt handlers.
+
+use crate::{
+    sync::{OnceCell, WaitQueue},
+    watchdog::{self, Heartbeat},
+};
+use core::sync::atomic::{AtomicU32, Ordering};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SoftIrq {
+    Xhci,
+    Net,
+}
+
+const NUM_SOFTIRQS: usize = 2;
+
+impl SoftIrq {
+    const ALL: [SoftIrq; NUM_SOFTIRQS] = [SoftIrq::Xhci, SoftIrq::Net];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    fn bit(self) -> u32 {
+        1 << self.index()
+    }
+}
+
+static PENDING: AtomicU32 = AtomicU32::new(0);
+static WAIT_QUEUE: WaitQueue = WaitQueue::new();
+static HANDLERS: [OnceCell<fn()>; NUM_SOFTIRQS] = [OnceCell::uninit(), OnceCell::uninit()];
+static HEARTBEATS: [Heartbeat; NUM_SOFTIRQS] = [Heartbeat::new("xhc"), Heartbeat::new("net")];
+
+/// Registers the handler of `irq`, which runs in the softirq co-task each time `irq` is raised.
+pub(crate) fn register(irq: SoftIrq, handler: fn()) {
+    HANDLERS[irq.index()].init_once(|| handler);
+}
+
+/// Schedules the handler of `irq`. Raising a pending softirq again runs the handler only once.
+///
+/// This can be called from interrupt handlers.
+pub(crate) fn raise(irq: SoftIrq) {
+    HEARTBEATS[irq.index()].expect();
+    PENDING.fetch_or(irq.bit(), Ordering::Relaxed);
+    WAIT_QUEUE.notify_all();
+}
+
+fn take_pending() -> Option<u32> {
+    let pending = PENDING.swap(0, Ordering::Relaxed);
+    (pending != 0).then(|| pending)
+}
+
+pub(crate) async fn handler_task() {
+    for heartbeat in &HEARTBEATS {
+        watchdog::register(heartbeat);
+    }
+    loop {
+        let bits = WAIT_QUEUE.wait_until(take_pending).await;
+        for irq in SoftIrq::ALL {
+            if bits & irq.bit() == 0 {
+                continue;
+            }
+            HEARTBEATS[irq.index()].beat();
+            if let Ok(handler) = HANDLERS[irq.index()].try_get() {
+                handler();
+            }
+        }
+    }
+}
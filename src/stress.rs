@@ -0,0 +1,150 @@
+//! Helpers for deliberately loading the system from the terminal.
+
+use crate::{
+    allocator,
+    co_task::JoinHandle,
+    sync::{Mutex, Semaphore},
+    task::{self, Task, TaskId},
+    time::Instant,
+};
+use alloc::{
+    alloc::{self as heap, Layout},
+    sync::Arc,
+    vec::Vec,
+};
+use core::{
+    hint, ptr,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::Duration,
+};
+use x86_64::instructions::interrupts;
+
+const MEMHOG_CHUNK_SIZE: usize = 1024 * 1024;
+/// Heap memory memhog always leaves free, so that the rest of the kernel keeps working.
+const MEMHOG_HEADROOM: usize = 16 * 1024 * 1024;
+
+/// Lets only one memhog run at a time, so that concurrent runs don't starve each other.
+static MEMHOG: Semaphore = Semaphore::new(1);
+
+#[derive(Debug, Default)]
+struct WorkerState {
+    iterations: AtomicU64,
+    stop: AtomicBool,
+}
+
+#[derive(Debug)]
+struct Worker {
+    task_id: TaskId,
+    state: Arc<WorkerState>,
+    done: JoinHandle<()>,
+}
+
+static WORKERS: Mutex<Vec<Worker>> = Mutex::new(Vec::new());
+
+/// Spawns `count` tasks that keep the CPU busy until [`stop_workers`] is called.
+pub(crate) fn spawn_workers(count: usize) {
+    let mut workers = WORKERS.lock();
+    for _ in 0..count {
+        let state = Arc::new(WorkerState::default());
+        let task_state = state.clone();
+        let (task, done) = Task::with_handle(task::DEFAULT_STACK_SIZE, async move {
+            while !task_state.stop.load(Ordering::Relaxed) {
+                task_state.iterations.fetch_add(1, Ordering::Relaxed);
+                hint::spin_loop();
+            }
+        });
+        let task_id = interrupts::without_interrupts(|| task::spawn(task));
+        workers.push(Worker {
+            task_id,
+            state,
+            done,
+        });
+    }
+}
+
+/// Returns the task ID and the loop count of each running busy task.
+pub(crate) fn workers() -> Vec<(TaskId, u64)> {
+    WORKERS
+        .lock()
+        .iter()
+        .map(|worker| {
+            let iterations = worker.state.iterations.load(Ordering::Relaxed);
+            (worker.task_id, iterations)
+        })
+        .collect()
+}
+
+/// Stops all busy tasks, waits until their loops end and returns the number of stopped tasks.
+///
+/// Tasks cannot exit yet, so stopped tasks finish their future and stay asleep.
+pub(crate) fn stop_workers() -> usize {
+    let workers = core::mem::take(&mut *WORKERS.lock());
+    for worker in &workers {
+        worker.state.stop.store(true, Ordering::Relaxed);
+    }
+    let count = workers.len();
+    for worker in workers {
+        // the handle is canceled only if the task is dropped, which never happens
+        let _ = task::block_on(worker.done);
+    }
+    count
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct MemhogReport {
+    pub(crate) allocated: usize,
+    pub(crate) exhausted: bool,
+}
+
+/// Allocates heap memory in 1 MiB chunks until `limit` bytes are allocated or
+/// the heap is exhausted, then frees everything.
+///
+/// `limit` is clamped so that at least 16 MiB of the heap is left for others.
+///
+/// The current task sleeps while another memhog is running.
+pub(crate) fn memhog(limit: usize) -> MemhogReport {
+    let _permit = MEMHOG.acquire();
+    #[allow(clippy::unwrap_used)]
+    let layout = Layout::from_size_align(MEMHOG_CHUNK_SIZE, 4096).unwrap();
+    let limit = usize::min(limit, allocator::HEAP_SIZE - MEMHOG_HEADROOM);
+    // reserve the chunk list first so that pushing never allocates while the heap is exhausted
+    let mut chunks = Vec::new();
+    if chunks
+        .try_reserve_exact((limit + MEMHOG_CHUNK_SIZE - 1) / MEMHOG_CHUNK_SIZE)
+        .is_err()
+    {
+        return MemhogReport {
+            allocated: 0,
+            exhausted: true,
+        };
+    }
+    let mut exhausted = false;
+    while chunks.len() * MEMHOG_CHUNK_SIZE < limit {
+        let chunk = unsafe { heap::alloc(layout) };
+        if chunk.is_null() {
+            exhausted = true;
+            break;
+        }
+        // touch the memory to make sure it is really usable
+        unsafe { ptr::write_bytes(chunk, 0xa5, MEMHOG_CHUNK_SIZE) };
+        chunks.push(chunk);
+    }
+
+    let allocated = chunks.len() * MEMHOG_CHUNK_SIZE;
+    for chunk in chunks {
+        unsafe { heap::dealloc(chunk, layout) };
+    }
+
+    MemhogReport {
+        allocated,
+        exhausted,
+    }
+}
+
+/// Busy-waits for `duration` without yielding.
+pub(crate) fn spin(duration: Duration) {
+    let start = Instant::now();
+    while start.elapsed() < duration {
+        hint::spin_loop();
+    }
+}
@@ -0,0 +1,178 @@
+//! Experimental suspend-to-RAM (ACPI S3) support.
+//!
+//! The CPU and devices lose their state in S3, and the firmware jumps to the waking vector in
+//! real mode on resume. The waking vector points to the trampoline, which switches back to
+//! long mode and restores the kernel context saved by [`suspend`].
+//!
+//! Only the state owned by the kernel itself (CPU registers, LAPIC timer, PCI configuration
+//! space) is restored. Device drivers are not notified, so devices which need
+//! re-initialization (e.g. USB devices behind xHC) may stop working after resume.
+
+use crate::{
+    acpi, cpu, gdt, interrupt, local_apic, memory, pci, pm_timer,
+    prelude::*,
+    sync::OnceCell,
+    timer,
+    trampoline::{Trampoline, TrampolineParams},
+};
+use alloc::vec::Vec;
+use x86_64::{instructions::interrupts, structures::paging::OffsetPageTable, VirtAddr};
+
+const RESUME_STACK_SIZE: usize = 4096 * 4;
+
+#[derive(Debug)]
+#[repr(C, align(16))]
+struct ResumeStack([u8; RESUME_STACK_SIZE]);
+
+#[derive(Debug)]
+#[repr(C, align(16))]
+struct FxSaveArea([u8; 512]);
+
+/// Callee-saved registers restored on resume.
+#[derive(Debug, Default)]
+#[repr(C)]
+struct SavedContext {
+    rbx: u64,
+    rbp: u64,
+    r12: u64,
+    r13: u64,
+    r14: u64,
+    r15: u64,
+    rsp: u64,
+    rip: u64,
+}
+
+static TRAMPOLINE: OnceCell<Trampoline> = OnceCell::uninit();
+static mut RESUME_STACK: ResumeStack = ResumeStack([0; RESUME_STACK_SIZE]);
+static mut FX_SAVE_AREA: FxSaveArea = FxSaveArea([0; 512]);
+static mut SAVED_CONTEXT: SavedContext = SavedContext {
+    rbx: 0,
+    rbp: 0,
+    r12: 0,
+    r13: 0,
+    r14: 0,
+    r15: 0,
+    rsp: 0,
+    rip: 0,
+};
+
+pub(crate) fn init(mapper: &mut OffsetPageTable) -> Result<()> {
+    if acpi::sleep_type(b"_S3_").is_none() {
+        info!("S3 sleep state is not supported");
+        return Ok(());
+    }
+
+    let trampoline =
+        memory::with_memory_manager(|allocator| Trampoline::install(mapper, allocator))?;
+    acpi::set_waking_vector(trampoline.start_address())?;
+    debug!("S3 waking vector: {:?}", trampoline.start_address());
+    TRAMPOLINE.init_once(|| trampoline);
+
+    Ok(())
+}
+
+/// Suspends the system to RAM, and returns after the system resumed.
+pub(crate) fn suspend() -> Result<()> {
+    let trampoline = TRAMPOLINE
+        .try_get()
+        .map_err(|_| ErrorKind::SleepStateNotSupported)?;
+    let sleep_type = acpi::sleep_type(b"_S3_").ok_or(ErrorKind::SleepStateNotSupported)?;
+    let configs = pci::scan_all_bus()?
+        .iter()
+        .map(pci::SavedConfig::save)
+        .collect::<Vec<_>>();
+
+    interrupts::without_interrupts(|| {
+        let lapic = timer::lapic::save_state();
+        let stack_end = unsafe { VirtAddr::from_ptr(&RESUME_STACK) } + RESUME_STACK_SIZE;
+        trampoline.set_params(&TrampolineParams::from_current_cpu(
+            stack_end,
+            resume_entry,
+            0,
+        ));
+
+        for config in &configs {
+            config.quiesce();
+        }
+
+        unsafe { asm!("fxsave64 [{}]", in(reg) &mut FX_SAVE_AREA) };
+        let resumed = unsafe { save_context(&mut SAVED_CONTEXT) } != 0;
+        if !resumed {
+            info!("entering S3 sleep state");
+            unsafe { asm!("wbinvd") };
+            acpi::enter_sleep_state(sleep_type);
+            // the system may take a while to enter the sleep state
+            pm_timer::wait_milliseconds(100);
+            warn!("failed to enter S3 sleep state");
+        }
+        unsafe { asm!("fxrstor64 [{}]", in(reg) &FX_SAVE_AREA) };
+
+        local_apic::init_local();
+        timer::lapic::restore_state(&lapic);
+        for config in &configs {
+            config.restore();
+        }
+
+        if resumed {
+            // APs are powered off in S3 and not started again
+            for index in 1..cpu::apic_ids().len() {
+                cpu::set_offline(index);
+            }
+            info!("resumed from S3 sleep state");
+            Ok(())
+        } else {
+            Err(ErrorKind::SleepFailed.into())
+        }
+    })
+}
+
+extern "C" fn resume_entry(_arg: u64) -> ! {
+    // the GS base is lost while the CPU is powered off
+    cpu::init_local(0);
+    gdt::reload();
+    interrupt::reload();
+    unsafe { restore_context(&SAVED_CONTEXT) }
+}
+
+/// Saves the callee-saved registers and returns 0.
+///
+/// When [`restore_context`] is called with the saved context, this function returns again
+/// with 1.
+#[naked]
+unsafe extern "C" fn save_context(_ctx: *mut SavedContext) -> u64 {
+    unsafe {
+        asm!(
+            "mov [rdi + 0x00], rbx",
+            "mov [rdi + 0x08], rbp",
+            "mov [rdi + 0x10], r12",
+            "mov [rdi + 0x18], r13",
+            "mov [rdi + 0x20], r14",
+            "mov [rdi + 0x28], r15",
+            "lea rax, [rsp + 8]",
+            "mov [rdi + 0x30], rax", // RSP after return
+            "mov rax, [rsp]",
+            "mov [rdi + 0x38], rax", // return address
+            "xor eax, eax",
+            "ret",
+            options(noreturn)
+        )
+    }
+}
+
+#[naked]
+unsafe extern "C" fn restore_context(_ctx: *const SavedContext) -> ! {
+    unsafe {
+        asm!(
+            "mov rbx, [rdi + 0x00]",
+            "mov rbp, [rdi + 0x08]",
+            "mov r12, [rdi + 0x10]",
+            "mov r13, [rdi + 0x18]",
+            "mov r14, [rdi + 0x20]",
+            "mov r15, [rdi + 0x28]",
+            "mov rsp, [rdi + 0x30]",
+            "mov eax, 1",
+            "jmp [rdi + 0x38]",
+            options(noreturn)
+        )
+    }
+}
@@ -1,7 +1,14 @@
-pub(crate) use self::{mutex::*, once_cell::*, spin_mutex::*};
+pub(crate) use self::{
+    async_mutex::*, mutex::*, once_cell::*, semaphore::*, spin_mutex::*, wait_queue::*,
+};
 
+mod async_mutex;
+pub(crate) mod broadcast;
 pub(crate) mod mpsc;
 mod mutex;
 mod once_cell;
 pub(crate) mod oneshot;
+mod semaphore;
 mod spin_mutex;
+mod wait_queue;
+pub(crate) mod watch;
@@ -0,0 +1,183 @@
+use crate::prelude::*;
+use core::{
+    cell::UnsafeCell,
+    fmt,
+    future::Future,
+    ops::{Deref, DerefMut},
+    pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll, Waker},
+};
+use crossbeam_queue::SegQueue;
+
+/// A lock which parks only the waiting co-task.
+///
+/// Unlike [`Mutex`](super::Mutex), waiting for the lock does not put the whole task to sleep, so
+/// other co-tasks on the same executor keep running. The guard may be held across `.await`.
+pub(crate) struct AsyncMutex<T: ?Sized> {
+    lock: AtomicBool,
+    wakers: SegQueue<Waker>,
+    data: UnsafeCell<T>,
+}
+
+pub(crate) struct AsyncMutexGuard<'a, T: ?Sized + 'a> {
+    lock: &'a AtomicBool,
+    wakers: &'a SegQueue<Waker>,
+    data: &'a mut T,
+}
+
+unsafe impl<T: ?Sized + Send> Sync for AsyncMutex<T> {}
+unsafe impl<T: ?Sized + Send> Send for AsyncMutex<T> {}
+
+impl<T> AsyncMutex<T> {
+    #[inline(always)]
+    pub(crate) const fn new(data: T) -> Self {
+        Self {
+            lock: AtomicBool::new(false),
+            wakers: SegQueue::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+}
+
+impl<T> AsyncMutex<T>
+where
+    T: ?Sized,
+{
+    fn acquire(&self) -> Option<AsyncMutexGuard<T>> {
+        self.lock
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| AsyncMutexGuard {
+                lock: &self.lock,
+                wakers: &self.wakers,
+                data: unsafe { &mut *self.data.get() },
+            })
+    }
+
+    #[inline(always)]
+    #[track_caller]
+    pub(crate) fn try_lock(&self) -> Result<AsyncMutexGuard<T>> {
+        match self.acquire() {
+            Some(guard) => Ok(guard),
+            None => bail!(ErrorKind::Deadlock),
+        }
+    }
+
+    /// Returns a future which resolves to the guard once the lock is acquired.
+    pub(crate) fn lock(&self) -> Lock<'_, T> {
+        Lock { mutex: self }
+    }
+}
+
+impl<T> fmt::Debug for AsyncMutex<T>
+where
+    T: ?Sized + fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.try_lock() {
+            Ok(guard) => write!(f, "AsyncMutex {{ data: {:?} }}", guard),
+            Err(_) => write!(f, "AsyncMutex {{ <locked> }}"),
+        }
+    }
+}
+
+impl<T> Default for AsyncMutex<T>
+where
+    T: ?Sized + Default,
+{
+    fn default() -> Self {
+        Self::new(Default::default())
+    }
+}
+
+impl<T> From<T> for AsyncMutex<T> {
+    fn from(data: T) -> Self {
+        Self::new(data)
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Lock<'a, T: ?Sized> {
+    mutex: &'a AsyncMutex<T>,
+}
+
+impl<'a, T> Future for Lock<'a, T>
+where
+    T: ?Sized,
+{
+    type Output = AsyncMutexGuard<'a, T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // fast path
+        if let Some(guard) = self.mutex.acquire() {
+            return Poll::Ready(guard);
+        }
+
+        self.mutex.wakers.push(cx.waker().clone());
+        // the lock may have been released before the waker was pushed
+        match self.mutex.acquire() {
+            Some(guard) => Poll::Ready(guard),
+            None => Poll::Pending,
+        }
+    }
+}
+
+impl<T> fmt::Debug for AsyncMutexGuard<'_, T>
+where
+    T: ?Sized + fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T> fmt::Display for AsyncMutexGuard<'_, T>
+where
+    T: ?Sized + fmt::Display,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
+    }
+}
+
+impl<T> Deref for AsyncMutexGuard<'_, T>
+where
+    T: ?Sized,
+{
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.data
+    }
+}
+
+impl<T> DerefMut for AsyncMutexGuard<'_, T>
+where
+    T: ?Sized,
+{
+    fn deref_mut(&mut self) -> &mut T {
+        self.data
+    }
+}
+
+impl<T> Drop for AsyncMutexGuard<'_, T>
+where
+    T: ?Sized,
+{
+    fn drop(&mut self) {
+        self.lock.store(false, Ordering::Release);
+
+        // Wakes all current waiters, since some of them may have been dropped before acquiring
+        // the lock. The losers push their wakers again.
+        let len = self.wakers.len();
+        let mut count = 0;
+        while let Some(waker) = self.wakers.pop() {
+            waker.wake();
+            count += 1;
+            if count >= len {
+                break;
+            }
+        }
+    }
+}
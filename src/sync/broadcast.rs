@@ -0,0 +1,139 @@
+//! A single-producer, multi-consumer channel which delivers each value to all receivers.
+//!
+//! The channel retains the latest `capacity` values. Sending never fails or blocks, and slow
+//! receivers skip the values dropped from the channel instead of blocking the sender.
+
+use super::{SpinMutex, WaitQueue};
+use alloc::{collections::VecDeque, sync::Arc};
+use core::{
+    pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll},
+};
+use futures_util::Stream;
+use x86_64::instructions::interrupts;
+
+pub(crate) fn channel<T>(capacity: usize) -> Sender<T> {
+    assert!(capacity > 0);
+    let inner = Arc::new(Inner {
+        buffer: SpinMutex::new(Buffer {
+            values: VecDeque::with_capacity(capacity),
+            next_seq: 0,
+        }),
+        capacity,
+        closed: AtomicBool::new(false),
+        wait_queue: WaitQueue::new(),
+    });
+    Sender { inner }
+}
+
+#[derive(Debug)]
+pub(crate) struct Sender<T> {
+    inner: Arc<Inner<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Sends `value` to all receivers, dropping the oldest value if the channel is full.
+    ///
+    /// This never allocates memory, so this can be called from interrupt handlers.
+    pub(crate) fn send(&self, value: T) {
+        interrupts::without_interrupts(|| {
+            let mut buffer = self.inner.buffer.lock();
+            if buffer.values.len() == self.inner.capacity {
+                buffer.values.pop_front();
+            }
+            buffer.values.push_back(value);
+            buffer.next_seq += 1;
+        });
+        self.inner.wait_queue.notify_all();
+    }
+
+    /// Creates a receiver, which receives the values retained in the channel first.
+    pub(crate) fn subscribe(&self) -> Receiver<T> {
+        let next_seq = interrupts::without_interrupts(|| self.inner.buffer.lock().first_seq());
+        Receiver {
+            inner: self.inner.clone(),
+            next_seq,
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.inner.closed.store(true, Ordering::Release);
+        self.inner.wait_queue.notify_all();
+    }
+}
+
+/// A receiver of the broadcast channel.
+///
+/// As a `Stream`, it yields the values in the order they are sent, and ends when the sender is
+/// dropped and all the values retained are received.
+#[derive(Debug)]
+pub(crate) struct Receiver<T> {
+    inner: Arc<Inner<T>>,
+    /// The sequence number of the value to be received next.
+    next_seq: u64,
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            next_seq: self.next_seq,
+        }
+    }
+}
+
+impl<T> Stream for Receiver<T>
+where
+    T: Clone,
+{
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        let inner = &this.inner;
+        let next_seq = &mut this.next_seq;
+        inner.wait_queue.poll_until(cx, || {
+            // loaded first, so that the values sent before closing are not missed
+            let closed = inner.closed.load(Ordering::Acquire);
+            let value = interrupts::without_interrupts(|| {
+                let buffer = inner.buffer.lock();
+                // skip the values dropped before received
+                let first_seq = buffer.first_seq();
+                *next_seq = u64::max(*next_seq, first_seq);
+                let value = buffer.values.get((*next_seq - first_seq) as usize)?;
+                *next_seq += 1;
+                Some(value.clone())
+            });
+            match value {
+                Some(value) => Some(Some(value)),
+                None if closed => Some(None),
+                None => None,
+            }
+        })
+    }
+}
+
+#[derive(Debug)]
+struct Buffer<T> {
+    values: VecDeque<T>,
+    /// The sequence number of the value to be sent next.
+    next_seq: u64,
+}
+
+impl<T> Buffer<T> {
+    /// Returns the sequence number of the oldest value retained.
+    fn first_seq(&self) -> u64 {
+        self.next_seq - self.values.len() as u64
+    }
+}
+
+#[derive(Debug)]
+struct Inner<T> {
+    buffer: SpinMutex<Buffer<T>>,
+    capacity: usize,
+    closed: AtomicBool,
+    wait_queue: WaitQueue,
+}
@@ -1,14 +1,40 @@
+use super::{SpinMutex, WaitQueue};
 use crate::prelude::*;
-use alloc::sync::Arc;
+use alloc::{
+    sync::{Arc, Weak},
+    vec::Vec,
+};
 use core::{
     pin::Pin,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
     task::{Context, Poll},
 };
-use crossbeam_queue::ArrayQueue;
-use futures_util::{task::AtomicWaker, Stream};
+use crossbeam_queue::{ArrayQueue, SegQueue};
+use futures_util::{future, Stream};
+use x86_64::instructions::interrupts;
+
+/// Channels registered with [`Sender::register`], listed by [`stats`].
+static REGISTRY: SpinMutex<Vec<(&'static str, Weak<Metrics>)>> = SpinMutex::new(Vec::new());
 
+/// Creates a channel which holds at most `buffer` values. Sending to a full channel fails
+/// ([`Sender::try_send`]) or waits for the receiver ([`Sender::send_async`]).
 pub(crate) fn channel<T>(buffer: usize) -> (Sender<T>, Receiver<T>) {
-    let inner = Arc::new(Inner::new(buffer));
+    new_channel(Queue::Bounded(ArrayQueue::new(buffer)))
+}
+
+/// Creates a channel which grows as needed. Sending never fails, but allocates memory, so this
+/// must not be sent to from interrupt handlers.
+pub(crate) fn unbounded_channel<T>() -> (Sender<T>, Receiver<T>) {
+    new_channel(Queue::Unbounded(SegQueue::new()))
+}
+
+fn new_channel<T>(queue: Queue<T>) -> (Sender<T>, Receiver<T>) {
+    let inner = Arc::new(Inner {
+        metrics: Arc::new(Metrics::new(queue.capacity())),
+        queue,
+        wait_queue: WaitQueue::new(),
+        space_queue: WaitQueue::new(),
+    });
     let tx = Sender {
         inner: inner.clone(),
     };
@@ -16,17 +42,91 @@ pub(crate) fn channel<T>(buffer: usize) -> (Sender<T>, Receiver<T>) {
     (tx, rx)
 }
 
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ChannelStats {
+    pub(crate) name: &'static str,
+    pub(crate) len: usize,
+    /// `None` for unbounded channels.
+    pub(crate) capacity: Option<usize>,
+    pub(crate) high_water_mark: usize,
+    /// Number of sends which failed because the channel was full.
+    pub(crate) full_count: u64,
+}
+
+/// Returns the statistics of the registered channels which are still alive.
+pub(crate) fn stats() -> Vec<ChannelStats> {
+    interrupts::without_interrupts(|| {
+        REGISTRY.with_lock(|registry| {
+            registry
+                .iter()
+                .filter_map(|(name, metrics)| Some((*name, metrics.upgrade()?)))
+                .map(|(name, metrics)| metrics.stats(name))
+                .collect()
+        })
+    })
+}
+
 #[derive(Debug)]
 pub(crate) struct Sender<T> {
     inner: Arc<Inner<T>>,
 }
 
 impl<T> Sender<T> {
-    pub(crate) fn send(&self, value: T) -> Result<()> {
-        self.inner.queue.push(value).map_err(|_| ErrorKind::Full)?;
-        self.inner.waker.wake();
+    fn push(&self, value: T) -> core::result::Result<(), T> {
+        let metrics = &self.inner.metrics;
+        // counted before pushing, so that the receiver never sees a negative length
+        let len = metrics.len.fetch_add(1, Ordering::Relaxed) + 1;
+        if let Err(value) = self.inner.queue.push(value) {
+            metrics.len.fetch_sub(1, Ordering::Relaxed);
+            return Err(value);
+        }
+        metrics.high_water_mark.fetch_max(len, Ordering::Relaxed);
+        self.inner.wait_queue.notify_all();
         Ok(())
     }
+
+    /// Sends `value` without waiting. Fails with [`ErrorKind::Full`] if the channel is full.
+    ///
+    /// This can be called from interrupt handlers if the channel is bounded.
+    pub(crate) fn try_send(&self, value: T) -> Result<()> {
+        if self.push(value).is_err() {
+            self.inner
+                .metrics
+                .full_count
+                .fetch_add(1, Ordering::Relaxed);
+            bail!(ErrorKind::Full);
+        }
+        Ok(())
+    }
+
+    /// Sends `value`, waiting until the receiver makes room if the channel is full.
+    pub(crate) async fn send_async(&self, value: T) {
+        let mut value = Some(value);
+        future::poll_fn(|cx| {
+            self.inner.space_queue.poll_until(cx, || {
+                let v = value.take()?;
+                match self.push(v) {
+                    Ok(()) => Some(()),
+                    Err(v) => {
+                        value = Some(v);
+                        None
+                    }
+                }
+            })
+        })
+        .await
+    }
+
+    /// Registers the channel under `name` to make it listed by [`stats`].
+    pub(crate) fn register(&self, name: &'static str) {
+        let metrics = Arc::downgrade(&self.inner.metrics);
+        interrupts::without_interrupts(|| {
+            REGISTRY.with_lock(|registry| {
+                registry.retain(|(_, metrics)| metrics.strong_count() > 0);
+                registry.push((name, metrics));
+            })
+        });
+    }
 }
 
 impl<T> Clone for Sender<T> {
@@ -42,36 +142,110 @@ pub(crate) struct Receiver<T> {
     inner: Arc<Inner<T>>,
 }
 
+impl<T> Receiver<T> {
+    /// Returns `true` if no value is waiting to be received.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.inner.queue.is_empty()
+    }
+}
+
 impl<T> Stream for Receiver<T> {
     type Item = T;
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        // fast path
-        if let Some(value) = self.inner.queue.pop() {
-            return Poll::Ready(Some(value));
+        let inner = &self.inner;
+        inner
+            .wait_queue
+            .poll_until(cx, || {
+                let value = inner.queue.pop()?;
+                inner.metrics.on_pop();
+                if inner.metrics.capacity.is_some() {
+                    inner.space_queue.notify_all();
+                }
+                Some(value)
+            })
+            .map(Some)
+    }
+}
+
+#[derive(Debug)]
+enum Queue<T> {
+    Bounded(ArrayQueue<T>),
+    Unbounded(SegQueue<T>),
+}
+
+impl<T> Queue<T> {
+    fn capacity(&self) -> Option<usize> {
+        match self {
+            Self::Bounded(queue) => Some(queue.capacity()),
+            Self::Unbounded(_) => None,
+        }
+    }
+
+    fn push(&self, value: T) -> core::result::Result<(), T> {
+        match self {
+            Self::Bounded(queue) => queue.push(value),
+            Self::Unbounded(queue) => {
+                queue.push(value);
+                Ok(())
+            }
+        }
+    }
+
+    fn pop(&self) -> Option<T> {
+        match self {
+            Self::Bounded(queue) => queue.pop(),
+            Self::Unbounded(queue) => queue.pop(),
         }
+    }
 
-        self.inner.waker.register(cx.waker());
-        if let Some(value) = self.inner.queue.pop() {
-            self.inner.waker.take();
-            Poll::Ready(Some(value))
-        } else {
-            Poll::Pending
+    fn is_empty(&self) -> bool {
+        match self {
+            Self::Bounded(queue) => queue.is_empty(),
+            Self::Unbounded(queue) => queue.is_empty(),
         }
     }
 }
 
 #[derive(Debug)]
-struct Inner<T> {
-    queue: ArrayQueue<T>,
-    waker: AtomicWaker,
+struct Metrics {
+    capacity: Option<usize>,
+    len: AtomicUsize,
+    high_water_mark: AtomicUsize,
+    full_count: AtomicU64,
 }
 
-impl<T> Inner<T> {
-    fn new(buffer: usize) -> Self {
+impl Metrics {
+    fn new(capacity: Option<usize>) -> Self {
         Self {
-            queue: ArrayQueue::new(buffer),
-            waker: AtomicWaker::new(),
+            capacity,
+            len: AtomicUsize::new(0),
+            high_water_mark: AtomicUsize::new(0),
+            full_count: AtomicU64::new(0),
         }
     }
+
+    fn on_pop(&self) {
+        self.len.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    fn stats(&self, name: &'static str) -> ChannelStats {
+        ChannelStats {
+            name,
+            len: self.len.load(Ordering::Relaxed),
+            capacity: self.capacity,
+            high_water_mark: self.high_water_mark.load(Ordering::Relaxed),
+            full_count: self.full_count.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Inner<T> {
+    queue: Queue<T>,
+    metrics: Arc<Metrics>,
+    /// Receivers waiting for values.
+    wait_queue: WaitQueue,
+    /// Senders waiting for room in bounded channels.
+    space_queue: WaitQueue,
 }
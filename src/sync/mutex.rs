@@ -1,4 +1,6 @@
+use super::SpinMutex;
 use crate::{
+    interrupt,
     prelude::*,
     task::{self, TaskId},
 };
@@ -11,14 +13,27 @@ use core::{
 use crossbeam_queue::SegQueue;
 use x86_64::instructions::interrupts;
 
+/// A lock which puts the waiting tasks to sleep.
+///
+/// Waiters acquire the lock in FIFO order. On unlock, the lock is handed off directly to the
+/// first waiter without being released, so a task arriving later cannot barge ahead of it.
+///
+/// While a task waits for the lock, the owner inherits the level of the waiter if it is higher,
+/// so that tasks of levels in between cannot starve the owner (priority inversion). The owner
+/// returns to its own level when it releases the lock, even if it holds other locks.
 pub(crate) struct Mutex<T: ?Sized> {
     lock: AtomicBool,
+    /// The task holding the lock, `None` if the lock is free or held in interrupt context.
+    owner: SpinMutex<Option<TaskId>>,
+    /// Waiting tasks. This is always empty while `lock` is false, since a locked mutex with
+    /// waiters is handed off to the first of them instead of being released.
     queue: SegQueue<TaskId>,
     data: UnsafeCell<T>,
 }
 
 pub(crate) struct MutexGuard<'a, T: ?Sized + 'a> {
     lock: &'a AtomicBool,
+    owner: &'a SpinMutex<Option<TaskId>>,
     queue: &'a SegQueue<TaskId>,
     data: &'a mut T,
 }
@@ -31,6 +46,7 @@ impl<T> Mutex<T> {
     pub(crate) const fn new(data: T) -> Self {
         Self {
             lock: AtomicBool::new(false),
+            owner: SpinMutex::new(None),
             queue: SegQueue::new(),
             data: UnsafeCell::new(data),
         }
@@ -41,11 +57,6 @@ impl<T> Mutex<T>
 where
     T: ?Sized,
 {
-    #[inline(always)]
-    pub(crate) fn is_locked(&self) -> bool {
-        self.lock.load(Ordering::Relaxed)
-    }
-
     #[inline(always)]
     #[track_caller]
     pub(crate) fn try_lock(&self) -> Result<MutexGuard<T>> {
@@ -56,11 +67,13 @@ where
             .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
             .is_ok()
         {
-            Ok(MutexGuard {
-                lock: &self.lock,
-                queue: &self.queue,
-                data: unsafe { &mut *self.data.get() },
-            })
+            interrupts::without_interrupts(|| {
+                let owner = (!interrupt::is_interrupt_context())
+                    .then(task::try_current_id)
+                    .flatten();
+                *self.owner.lock() = owner;
+            });
+            Ok(self.guard())
         } else {
             bail!(ErrorKind::Deadlock)
         }
@@ -71,26 +84,55 @@ where
     pub(crate) fn lock(&self) -> MutexGuard<T> {
         let task_id = interrupts::without_interrupts(|| task::current().id());
 
-        // Can fail to lock even if the lock is not locked. May be more efficient than `try_lock`
-        // when called in a loop.
-        while self
+        // fast path
+        if self
             .lock
-            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
-            .is_err()
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
         {
-            while self.is_locked() {
-                assert!(interrupts::are_enabled());
-                interrupts::without_interrupts(|| {
-                    if self.is_locked() {
-                        self.queue.push(task_id);
-                        task::sleep(task_id);
+            interrupts::without_interrupts(|| *self.owner.lock() = Some(task_id));
+            return self.guard();
+        }
+
+        assert!(interrupts::are_enabled());
+        let mut queued = false;
+        loop {
+            let acquired = interrupts::without_interrupts(|| {
+                if !queued {
+                    // the lock may have been released since the fast path
+                    if self
+                        .lock
+                        .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+                        .is_ok()
+                    {
+                        *self.owner.lock() = Some(task_id);
+                        return true;
                     }
-                });
+                    self.queue.push(task_id);
+                    queued = true;
+                }
+
+                let owner = *self.owner.lock();
+                if owner == Some(task_id) {
+                    // handed off by the previous owner
+                    return true;
+                }
+                if let Some(owner) = owner {
+                    task::inherit_level(owner, task_id);
+                }
+                task::sleep(task_id);
+                false
+            });
+            if acquired {
+                return self.guard();
             }
         }
+    }
 
+    fn guard(&self) -> MutexGuard<T> {
         MutexGuard {
             lock: &self.lock,
+            owner: &self.owner,
             queue: &self.queue,
             data: unsafe { &mut *self.data.get() },
         }
@@ -167,16 +209,22 @@ where
     T: ?Sized,
 {
     fn drop(&mut self) {
-        self.lock.store(false, Ordering::Release);
-
-        let len = self.queue.len();
-        let mut count = 0;
-        while let Some(task_id) = self.queue.pop() {
-            interrupts::without_interrupts(|| task::wake(task_id));
-            count += 1;
-            if count >= len {
-                break;
+        let owner = interrupts::without_interrupts(|| {
+            let owner = self.owner.lock().take();
+            match self.queue.pop() {
+                Some(next) => {
+                    // hand off the lock without releasing it
+                    *self.owner.lock() = Some(next);
+                    task::wake(next);
+                }
+                None => self.lock.store(false, Ordering::Release),
             }
+            owner
+        });
+
+        if let Some(owner) = owner {
+            // switches to the next owner at once if the owner was running at its level
+            interrupts::without_interrupts(|| task::restore_level(owner));
         }
     }
 }
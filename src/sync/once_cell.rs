@@ -1,13 +1,23 @@
+use super::WaitQueue;
 use crate::prelude::*;
-use conquer_once::noblock;
+use conquer_once::{noblock, TryInitError};
+use core::future::Future;
 
 /// A wrapper around `noblock::OnceCell` which panics immediately when error detected.
+///
+/// Tasks and co-tasks can [`wait`](Self::wait) for the cell to be initialized by someone else.
 #[derive(Debug)]
-pub(crate) struct OnceCell<T>(noblock::OnceCell<T>);
+pub(crate) struct OnceCell<T> {
+    inner: noblock::OnceCell<T>,
+    wait_queue: WaitQueue,
+}
 
 impl<T> OnceCell<T> {
     pub(crate) const fn uninit() -> Self {
-        Self(noblock::OnceCell::uninit())
+        Self {
+            inner: noblock::OnceCell::uninit(),
+            wait_queue: WaitQueue::new(),
+        }
     }
 
     #[track_caller]
@@ -18,7 +28,34 @@ impl<T> OnceCell<T> {
 
     #[track_caller]
     pub(crate) fn try_init_once(&self, f: impl FnOnce() -> T) -> Result<()> {
-        Ok(self.0.try_init_once(f)?)
+        self.inner.try_init_once(f)?;
+        self.wait_queue.notify_all();
+        Ok(())
+    }
+
+    /// Returns the value, initializing it with `f` if the cell is uninitialized.
+    #[track_caller]
+    pub(crate) fn get_or_init(&self, f: impl FnOnce() -> T) -> &T {
+        #[allow(clippy::unwrap_used)]
+        self.get_or_try_init(|| Ok(f())).unwrap()
+    }
+
+    /// Returns the value, initializing it with `f` if the cell is uninitialized.
+    ///
+    /// If `f` fails, the cell is left uninitialized.
+    #[track_caller]
+    pub(crate) fn get_or_try_init(&self, f: impl FnOnce() -> Result<T>) -> Result<&T> {
+        if let Ok(value) = self.inner.try_get() {
+            return Ok(value);
+        }
+        let value = f()?;
+        match self.inner.try_init_once(|| value) {
+            // initialized by someone else while `f` was running
+            Ok(()) | Err(TryInitError::AlreadyInit) => {}
+            Err(err) => bail!(err),
+        }
+        self.wait_queue.notify_all();
+        self.try_get()
     }
 
     #[track_caller]
@@ -29,6 +66,12 @@ impl<T> OnceCell<T> {
 
     #[track_caller]
     pub(crate) fn try_get(&self) -> Result<&T> {
-        Ok(self.0.try_get()?)
+        Ok(self.inner.try_get()?)
+    }
+
+    /// Returns a future which resolves to the value once the cell is initialized.
+    pub(crate) fn wait(&self) -> impl Future<Output = &T> {
+        self.wait_queue
+            .wait_until(move || self.inner.try_get().ok())
     }
 }
@@ -1,11 +1,12 @@
-use super::Mutex;
+use super::{Mutex, WaitQueue};
 use alloc::sync::Arc;
 use core::{
+    fmt,
     future::Future,
     pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
     task::{Context, Poll},
 };
-use futures_util::task::AtomicWaker;
 
 pub(crate) fn channel<T>() -> (Sender<T>, Receiver<T>) {
     let inner = Arc::new(Inner::new());
@@ -16,53 +17,88 @@ pub(crate) fn channel<T>() -> (Sender<T>, Receiver<T>) {
     (tx, rx)
 }
 
+/// The error returned by [`Receiver`] when the sender is dropped without sending a value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Canceled;
+
+impl fmt::Display for Canceled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "oneshot canceled")
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct Sender<T> {
     inner: Arc<Inner<T>>,
 }
 
 impl<T> Sender<T> {
-    pub(crate) fn send(self, value: T) {
+    /// Sends `value` to the receiver, or returns it back if the receiver has been dropped.
+    pub(crate) fn send(self, value: T) -> Result<(), T> {
+        if self.is_canceled() {
+            return Err(value);
+        }
         *self.inner.value.lock() = Some(value);
-        self.inner.waker.wake();
+        // the receiver is notified when `self` is dropped
+        Ok(())
+    }
+
+    /// Returns `true` if the receiver has been dropped.
+    pub(crate) fn is_canceled(&self) -> bool {
+        self.inner.receiver_dropped.load(Ordering::Acquire)
     }
 }
 
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.inner.sender_dropped.store(true, Ordering::Release);
+        self.inner.wait_queue.notify_all();
+    }
+}
+
+/// A future which resolves to the sent value, or [`Canceled`] if the sender is dropped without
+/// sending.
 #[derive(Debug)]
 pub(crate) struct Receiver<T> {
     inner: Arc<Inner<T>>,
 }
 
 impl<T> Future for Receiver<T> {
-    type Output = T;
+    type Output = Result<T, Canceled>;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        // fast path
-        if let Some(value) = self.inner.value.lock().take() {
-            return Poll::Ready(value);
-        }
+        let inner = &self.inner;
+        inner.wait_queue.poll_until(cx, || {
+            // the value is stored before the sender is dropped
+            if !inner.sender_dropped.load(Ordering::Acquire) {
+                return None;
+            }
+            Some(inner.value.lock().take().ok_or(Canceled))
+        })
+    }
+}
 
-        self.inner.waker.register(cx.waker());
-        if let Some(value) = self.inner.value.lock().take() {
-            self.inner.waker.take();
-            Poll::Ready(value)
-        } else {
-            Poll::Pending
-        }
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.inner.receiver_dropped.store(true, Ordering::Release);
     }
 }
 
 #[derive(Debug)]
 struct Inner<T> {
     value: Mutex<Option<T>>,
-    waker: AtomicWaker,
+    sender_dropped: AtomicBool,
+    receiver_dropped: AtomicBool,
+    wait_queue: WaitQueue,
 }
 
 impl<T> Inner<T> {
     fn new() -> Self {
         Self {
             value: Mutex::new(None),
-            waker: AtomicWaker::new(),
+            sender_dropped: AtomicBool::new(false),
+            receiver_dropped: AtomicBool::new(false),
+            wait_queue: WaitQueue::new(),
         }
     }
 }
@@ -0,0 +1,169 @@
+use super::WaitQueue;
+use crate::prelude::*;
+use core::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicUsize, Ordering},
+    task::{Context, Poll},
+};
+
+/// A counting semaphore.
+///
+/// Permits can be acquired either by blocking the task ([`acquire`](Self::acquire)) or by parking
+/// only the calling co-task ([`acquire_async`](Self::acquire_async)). A permit is returned when
+/// [`SemaphorePermit`] is dropped, or can be discarded with [`SemaphorePermit::forget`] and
+/// added back by another party with [`add_permits`](Self::add_permits).
+pub(crate) struct Semaphore {
+    permits: AtomicUsize,
+    wait_queue: WaitQueue,
+}
+
+#[must_use = "the permit is returned immediately if not held"]
+pub(crate) struct SemaphorePermit<'a> {
+    semaphore: &'a Semaphore,
+}
+
+impl Semaphore {
+    pub(crate) const fn new(permits: usize) -> Self {
+        Self {
+            permits: AtomicUsize::new(permits),
+            wait_queue: WaitQueue::new(),
+        }
+    }
+
+    pub(crate) fn available_permits(&self) -> usize {
+        self.permits.load(Ordering::Relaxed)
+    }
+
+    fn try_take(&self) -> Option<SemaphorePermit<'_>> {
+        let mut permits = self.permits.load(Ordering::Relaxed);
+        while permits > 0 {
+            match self.permits.compare_exchange_weak(
+                permits,
+                permits - 1,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return Some(SemaphorePermit { semaphore: self }),
+                Err(current) => permits = current,
+            }
+        }
+        None
+    }
+
+    #[track_caller]
+    pub(crate) fn try_acquire(&self) -> Result<SemaphorePermit<'_>> {
+        match self.try_take() {
+            Some(permit) => Ok(permit),
+            None => bail!(ErrorKind::NoPermit),
+        }
+    }
+
+    /// Acquires a permit, putting the current task to sleep until one becomes available.
+    pub(crate) fn acquire(&self) -> SemaphorePermit<'_> {
+        self.wait_queue.block_until(|| self.try_take())
+    }
+
+    /// Returns a future which resolves to a permit, parking only the calling co-task.
+    pub(crate) fn acquire_async(&self) -> Acquire<'_> {
+        Acquire { semaphore: self }
+    }
+
+    /// Adds `n` permits and wakes the waiters.
+    pub(crate) fn add_permits(&self, n: usize) {
+        self.permits.fetch_add(n, Ordering::Release);
+        // Wakes all waiters, since they may give up before taking a permit. The losers wait
+        // again.
+        self.wait_queue.notify_all();
+    }
+}
+
+impl fmt::Debug for Semaphore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Semaphore")
+            .field("permits", &self.available_permits())
+            .finish()
+    }
+}
+
+impl SemaphorePermit<'_> {
+    /// Discards the permit without returning it to the semaphore.
+    pub(crate) fn forget(self) {
+        core::mem::forget(self);
+    }
+}
+
+impl fmt::Debug for SemaphorePermit<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SemaphorePermit").finish()
+    }
+}
+
+impl Drop for SemaphorePermit<'_> {
+    fn drop(&mut self) {
+        self.semaphore.add_permits(1);
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Acquire<'a> {
+    semaphore: &'a Semaphore,
+}
+
+impl<'a> Future for Acquire<'a> {
+    type Output = SemaphorePermit<'a>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let semaphore = self.semaphore;
+        semaphore.wait_queue.poll_until(cx, || semaphore.try_take())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::{sync::Arc, task::Wake};
+    use core::task::Waker;
+
+    struct WakeCount(AtomicUsize);
+
+    impl Wake for WakeCount {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test_case]
+    fn acquire_async_waits_for_release() {
+        let semaphore = Semaphore::new(1);
+        let count = Arc::new(WakeCount(AtomicUsize::new(0)));
+        let waker = Waker::from(Arc::clone(&count));
+        let mut cx = Context::from_waker(&waker);
+
+        let permit = match Pin::new(&mut semaphore.acquire_async()).poll(&mut cx) {
+            Poll::Ready(permit) => permit,
+            Poll::Pending => panic!("a permit is available"),
+        };
+        assert_eq!(semaphore.available_permits(), 0);
+
+        let mut acquire = semaphore.acquire_async();
+        assert!(Pin::new(&mut acquire).poll(&mut cx).is_pending());
+        drop(permit);
+        assert_eq!(count.0.load(Ordering::Relaxed), 1);
+        assert!(Pin::new(&mut acquire).poll(&mut cx).is_ready());
+        assert_eq!(semaphore.available_permits(), 1);
+    }
+    #[test_case]
+    fn forget_and_add_permits() {
+        let semaphore = Semaphore::new(1);
+        #[allow(clippy::unwrap_used)]
+        semaphore.try_acquire().unwrap().forget();
+        assert_eq!(semaphore.available_permits(), 0);
+        assert!(semaphore.try_acquire().is_err());
+
+        semaphore.add_permits(1);
+        assert!(semaphore.try_acquire().is_ok());
+        assert_eq!(semaphore.available_permits(), 1);
+    }
+}
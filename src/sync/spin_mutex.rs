@@ -1,14 +1,34 @@
 use crate::prelude::*;
+use core::{
+    fmt,
+    ops::{Deref, DerefMut},
+};
 
 /// A wrapper around `spin::Mutex` which panics immediately when deadlock detected.
+///
+/// With the `lock-debug` feature, the task, CPU and location of the owner are recorded. Contention
+/// on the same CPU (which never resolves) dumps the owner to the emergency console, and a lock
+/// held by another CPU is waited for up to a second before being reported.
 #[derive(Debug, Default)]
-pub(crate) struct SpinMutex<T: ?Sized>(spin::Mutex<T>);
+pub(crate) struct SpinMutex<T: ?Sized> {
+    #[cfg(feature = "lock-debug")]
+    owner: debug::Owner,
+    inner: spin::Mutex<T>,
+}
 
-pub(crate) type SpinMutexGuard<'a, T> = spin::MutexGuard<'a, T>;
+pub(crate) struct SpinMutexGuard<'a, T: ?Sized + 'a> {
+    #[cfg(feature = "lock-debug")]
+    owner: &'a debug::Owner,
+    inner: spin::MutexGuard<'a, T>,
+}
 
 impl<T> SpinMutex<T> {
     pub(crate) const fn new(value: T) -> Self {
-        Self(spin::Mutex::new(value))
+        Self {
+            #[cfg(feature = "lock-debug")]
+            owner: debug::Owner::new(),
+            inner: spin::Mutex::new(value),
+        }
     }
 }
 
@@ -18,17 +38,38 @@ where
 {
     #[track_caller]
     pub(crate) fn lock(&self) -> SpinMutexGuard<'_, T> {
+        #[cfg(feature = "lock-debug")]
+        {
+            let guard = self.owner.wait(&self.inner);
+            self.guard(guard)
+        }
+
+        #[cfg(not(feature = "lock-debug"))]
         #[allow(clippy::unwrap_used)]
         self.try_lock().unwrap()
     }
 
     #[track_caller]
     pub(crate) fn try_lock(&self) -> Result<SpinMutexGuard<'_, T>> {
-        Ok(self.0.try_lock().ok_or(ErrorKind::Deadlock)?)
+        let guard = self.inner.try_lock().ok_or(ErrorKind::Deadlock)?;
+        Ok(self.guard(guard))
+    }
+
+    #[track_caller]
+    fn guard<'a>(&'a self, inner: spin::MutexGuard<'a, T>) -> SpinMutexGuard<'a, T> {
+        #[cfg(feature = "lock-debug")]
+        self.owner.set();
+        SpinMutexGuard {
+            #[cfg(feature = "lock-debug")]
+            owner: &self.owner,
+            inner,
+        }
     }
 
     pub(crate) unsafe fn force_unlock(&self) {
-        unsafe { self.0.force_unlock() }
+        #[cfg(feature = "lock-debug")]
+        self.owner.clear();
+        unsafe { self.inner.force_unlock() }
     }
 
     #[track_caller]
@@ -36,3 +77,149 @@ where
         f(&mut *self.lock())
     }
 }
+
+impl<T> fmt::Debug for SpinMutexGuard<'_, T>
+where
+    T: ?Sized + fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T> Deref for SpinMutexGuard<'_, T>
+where
+    T: ?Sized,
+{
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &*self.inner
+    }
+}
+
+impl<T> DerefMut for SpinMutexGuard<'_, T>
+where
+    T: ?Sized,
+{
+    fn deref_mut(&mut self) -> &mut T {
+        &mut *self.inner
+    }
+}
+
+#[cfg(feature = "lock-debug")]
+impl<T> Drop for SpinMutexGuard<'_, T>
+where
+    T: ?Sized,
+{
+    fn drop(&mut self) {
+        self.owner.clear();
+    }
+}
+
+#[cfg(feature = "lock-debug")]
+mod debug {
+    use crate::{cpu, emergency_console, task, time::Instant};
+    use core::{
+        fmt::Write as _,
+        panic::Location,
+        ptr,
+        sync::atomic::{AtomicPtr, AtomicU64, AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    /// How long a lock held by another CPU is waited for before it is reported as a deadlock.
+    const SPIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+    const NO_CPU: usize = usize::MAX;
+
+    #[derive(Debug, Default)]
+    pub(super) struct Owner {
+        /// Task ID plus one, or zero if unknown.
+        task_id: AtomicU64,
+        cpu: AtomicUsize,
+        location: AtomicPtr<Location<'static>>,
+    }
+
+    impl Owner {
+        pub(super) const fn new() -> Self {
+            Self {
+                task_id: AtomicU64::new(0),
+                cpu: AtomicUsize::new(NO_CPU),
+                location: AtomicPtr::new(ptr::null_mut()),
+            }
+        }
+
+        #[track_caller]
+        pub(super) fn set(&self) {
+            let location = Location::caller() as *const Location<'static>;
+            let task_id = task::current_id_unlocked().map_or(0, |id| id.as_u64() + 1);
+            self.task_id.store(task_id, Ordering::Relaxed);
+            self.cpu.store(cpu::current_index(), Ordering::Relaxed);
+            self.location.store(location as *mut _, Ordering::Relaxed);
+        }
+
+        pub(super) fn clear(&self) {
+            self.cpu.store(NO_CPU, Ordering::Relaxed);
+            self.location.store(ptr::null_mut(), Ordering::Relaxed);
+            self.task_id.store(0, Ordering::Relaxed);
+        }
+
+        /// Waits for the lock while it is held by another CPU.
+        #[track_caller]
+        pub(super) fn wait<'a, T: ?Sized>(
+            &self,
+            lock: &'a spin::Mutex<T>,
+        ) -> spin::MutexGuard<'a, T> {
+            let start = Instant::now();
+            loop {
+                if let Some(guard) = lock.try_lock() {
+                    return guard;
+                }
+                let cpu = self.cpu.load(Ordering::Relaxed);
+                if cpu == cpu::current_index() {
+                    self.report("deadlock");
+                }
+                if start.elapsed() > SPIN_TIMEOUT {
+                    self.report("lock held too long");
+                }
+                core::hint::spin_loop();
+            }
+        }
+
+        #[track_caller]
+        fn report(&self, reason: &str) -> ! {
+            let caller = Location::caller();
+            let task_id = self.task_id.load(Ordering::Relaxed);
+            let cpu = self.cpu.load(Ordering::Relaxed);
+            let location = unsafe { self.location.load(Ordering::Relaxed).as_ref() };
+            let current = task::current_id_unlocked();
+            emergency_console::with_console(|console| {
+                let _ = writeln!(console, "SPIN LOCK: {}", reason);
+                let _ = write!(console, "Acquiring at {}", caller);
+                if let Some(current) = current {
+                    let _ = write!(console, " by task {}", current);
+                }
+                let _ = writeln!(console, " on CPU {}", cpu::current_index());
+                match location {
+                    Some(location) => {
+                        let _ = write!(console, "Held at {}", location);
+                    }
+                    None => {
+                        let _ = write!(console, "Held at unknown location");
+                    }
+                }
+                if task_id != 0 {
+                    let _ = write!(console, " by task {}", task_id - 1);
+                }
+                if cpu != NO_CPU {
+                    let _ = write!(console, " on CPU {}", cpu);
+                }
+                let _ = writeln!(console);
+                if current.map(|id| id.as_u64() + 1) == Some(task_id) && task_id != 0 {
+                    let _ = writeln!(console, "(re-entrant acquisition)");
+                }
+            });
+        }
+    }
+}
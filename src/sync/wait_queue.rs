@@ -0,0 +1,140 @@
+use super::SpinMutex;
+use crate::task;
+use alloc::vec::Vec;
+use core::{
+    fmt,
+    future::Future,
+    mem,
+    task::{Context, Poll, Waker},
+};
+use futures_util::future;
+use x86_64::instructions::interrupts;
+
+/// A queue of tasks and co-tasks waiting for a condition to hold (a condition variable).
+///
+/// Waiters check the condition, register themselves and check it again, so a notification
+/// between the two checks is never lost. Notifying does not allocate and can be done from
+/// interrupt handlers.
+pub(crate) struct WaitQueue {
+    wakers: SpinMutex<Vec<Waker>>,
+}
+
+impl WaitQueue {
+    pub(crate) const fn new() -> Self {
+        Self {
+            wakers: SpinMutex::new(Vec::new()),
+        }
+    }
+
+    fn register(&self, waker: &Waker) {
+        interrupts::without_interrupts(|| {
+            let mut wakers = self.wakers.lock();
+            if !wakers.iter().any(|w| w.will_wake(waker)) {
+                wakers.push(waker.clone());
+            }
+        });
+    }
+
+    /// Polls `condition`, registering the waker of `cx` if it does not hold yet.
+    ///
+    /// This is the building block of [`Future`] and `Stream` implementations.
+    pub(crate) fn poll_until<T>(
+        &self,
+        cx: &mut Context<'_>,
+        mut condition: impl FnMut() -> Option<T>,
+    ) -> Poll<T> {
+        // fast path
+        if let Some(value) = condition() {
+            return Poll::Ready(value);
+        }
+
+        self.register(cx.waker());
+        match condition() {
+            Some(value) => Poll::Ready(value),
+            None => Poll::Pending,
+        }
+    }
+
+    /// Returns a future which resolves once `condition` returns `Some`.
+    pub(crate) fn wait_until<'a, T>(
+        &'a self,
+        mut condition: impl FnMut() -> Option<T> + 'a,
+    ) -> impl Future<Output = T> + 'a {
+        future::poll_fn(move |cx| self.poll_until(cx, &mut condition))
+    }
+
+    /// Puts the current task to sleep until `condition` returns `Some`.
+    pub(crate) fn block_until<T>(&self, condition: impl FnMut() -> Option<T>) -> T {
+        task::block_on(self.wait_until(condition))
+    }
+
+    /// Wakes all waiters, which check their conditions again.
+    pub(crate) fn notify_all(&self) {
+        // A woken task may be switched to immediately and register itself again, so the wakers
+        // are taken out of the lock before they are woken.
+        let mut wakers = interrupts::without_interrupts(|| mem::take(&mut *self.wakers.lock()));
+        for waker in wakers.drain(..) {
+            waker.wake();
+        }
+        // gives the buffer back, so that registering does not allocate again
+        interrupts::without_interrupts(|| {
+            let mut current = self.wakers.lock();
+            if current.is_empty() {
+                mem::swap(&mut *current, &mut wakers);
+            }
+        });
+    }
+}
+
+impl Default for WaitQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for WaitQueue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WaitQueue").finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::{sync::Arc, task::Wake};
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    /// A waiter which registers itself again while it is woken, like a task switched to
+    /// immediately by the wake.
+    struct Reregister {
+        queue: Arc<WaitQueue>,
+        woken: AtomicUsize,
+    }
+
+    impl Wake for Reregister {
+        fn wake(self: Arc<Self>) {
+            // registers only once more, so that the queue does not keep the waiter alive
+            if self.woken.fetch_add(1, Ordering::Relaxed) == 0 {
+                let queue = Arc::clone(&self.queue);
+                queue.register(&Waker::from(self));
+            }
+        }
+    }
+
+    #[test_case]
+    fn notify_all_reregister() {
+        let queue = Arc::new(WaitQueue::new());
+        let waiter = Arc::new(Reregister {
+            queue: Arc::clone(&queue),
+            woken: AtomicUsize::new(0),
+        });
+        queue.register(&Waker::from(Arc::clone(&waiter)));
+
+        queue.notify_all();
+        assert_eq!(waiter.woken.load(Ordering::Relaxed), 1);
+        queue.notify_all();
+        assert_eq!(waiter.woken.load(Ordering::Relaxed), 2);
+        queue.notify_all();
+        assert_eq!(waiter.woken.load(Ordering::Relaxed), 2);
+    }
+}
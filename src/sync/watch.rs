@@ -0,0 +1,147 @@
+//! A single-producer, multi-consumer channel which retains only the latest value.
+//!
+//! Sending never fails or blocks, and slow receivers skip intermediate values instead of
+//! overflowing a queue.
+
+use super::{SpinMutex, WaitQueue};
+use alloc::sync::Arc;
+use core::{
+    mem,
+    pin::Pin,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    task::{Context, Poll},
+};
+use futures_util::Stream;
+use x86_64::instructions::interrupts;
+
+pub(crate) fn channel<T>(init: T) -> (Sender<T>, Receiver<T>) {
+    let inner = Arc::new(Inner {
+        value: SpinMutex::new(init),
+        version: AtomicU64::new(0),
+        closed: AtomicBool::new(false),
+        wait_queue: WaitQueue::new(),
+    });
+    let tx = Sender {
+        inner: inner.clone(),
+    };
+    let rx = Receiver { inner, seen: 0 };
+    (tx, rx)
+}
+
+#[derive(Debug)]
+pub(crate) struct Sender<T> {
+    inner: Arc<Inner<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Replaces the value and notifies all receivers. Returns the previous value.
+    pub(crate) fn send(&self, value: T) -> T {
+        let old = interrupts::without_interrupts(|| {
+            let old = mem::replace(&mut *self.inner.value.lock(), value);
+            self.inner.version.fetch_add(1, Ordering::Release);
+            old
+        });
+        self.inner.wait_queue.notify_all();
+        old
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.inner.closed.store(true, Ordering::Release);
+        self.inner.wait_queue.notify_all();
+    }
+}
+
+/// A receiver of the watch channel.
+///
+/// As a `Stream`, it yields the latest value each time the value changes, and ends when the
+/// sender is dropped.
+#[derive(Debug)]
+pub(crate) struct Receiver<T> {
+    inner: Arc<Inner<T>>,
+    seen: u64,
+}
+
+impl<T> Receiver<T> {
+    /// Returns the latest value and marks it as seen.
+    pub(crate) fn get(&mut self) -> T
+    where
+        T: Clone,
+    {
+        let inner = &self.inner;
+        let (value, version) = interrupts::without_interrupts(|| {
+            let value = inner.value.lock();
+            (value.clone(), inner.version.load(Ordering::Acquire))
+        });
+        self.seen = version;
+        value
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            seen: self.seen,
+        }
+    }
+}
+
+impl<T> Stream for Receiver<T>
+where
+    T: Clone,
+{
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        let inner = &this.inner;
+        let seen = this.seen;
+        let changed = inner.wait_queue.poll_until(cx, || {
+            if inner.version.load(Ordering::Acquire) != seen {
+                Some(true)
+            } else if inner.closed.load(Ordering::Acquire) {
+                Some(false)
+            } else {
+                None
+            }
+        });
+        match changed {
+            Poll::Ready(true) => Poll::Ready(Some(this.get())),
+            Poll::Ready(false) => Poll::Ready(None),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Inner<T> {
+    value: SpinMutex<T>,
+    /// Incremented each time the value is replaced.
+    version: AtomicU64,
+    closed: AtomicBool,
+    wait_queue: WaitQueue,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::{task::noop_waker, StreamExt as _};
+
+    #[test_case]
+    fn receive_latest_value() {
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let (tx, mut rx) = channel(0);
+        assert_eq!(rx.poll_next_unpin(&mut cx), Poll::Pending);
+
+        tx.send(1);
+        tx.send(2);
+        assert_eq!(rx.poll_next_unpin(&mut cx), Poll::Ready(Some(2)));
+        assert_eq!(rx.poll_next_unpin(&mut cx), Poll::Pending);
+
+        drop(tx);
+        assert_eq!(rx.poll_next_unpin(&mut cx), Poll::Ready(None));
+    }
+}
@@ -1,33 +1,46 @@
 use crate::{
-    co_task::{CoTask, Executor},
-    gdt,
+    co_task::{CoTask, Executor, JoinHandle},
+    gdt, idle,
     interrupt::{self, InterruptContextGuard},
     prelude::*,
+    random,
     sync::{OnceCell, SpinMutex},
+    time::Instant,
+    timer,
 };
 use alloc::{
     boxed::Box,
     collections::{BTreeMap, VecDeque},
     sync::Arc,
+    task::Wake,
     vec,
+    vec::Vec,
 };
 use core::{
+    convert::TryFrom,
     fmt,
     future::Future,
-    mem,
-    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    mem, slice,
+    sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    task::{Context, Poll, Waker},
+    time::Duration,
 };
 use custom_debug_derive::Debug as CustomDebug;
+use futures_util::pin_mut;
 use x86_64::{instructions::interrupts, registers::control::Cr3};
 
 static TASK_MANAGER: OnceCell<SpinMutex<TaskManager>> = OnceCell::uninit();
+/// ID of the current task plus one, or zero before the task manager is initialized. Kept in sync
+/// with `TaskManager::current_task_id` so that it can be read without the lock.
+static CURRENT_TASK_ID: AtomicU64 = AtomicU64::new(0);
+static CONTEXT_SWITCHES: AtomicU64 = AtomicU64::new(0);
 
 pub(crate) fn init() {
     let main_task = Task::new_main();
     main_task.set_level(MAX_LEVEL);
     TASK_MANAGER.init_once(|| SpinMutex::new(TaskManager::new(main_task)));
 
-    let idle_task = Task::new(async { crate::hlt_loop() });
+    let idle_task = Task::new(IDLE_STACK_SIZE, async { idle::run() });
     idle_task.set_level(MIN_LEVEL);
     spawn(idle_task);
 }
@@ -75,12 +88,196 @@ pub(crate) fn sleep(task_id: TaskId) {
     }
 }
 
+/// Raises the level of `owner` to the level of `waiter` if it is lower, until
+/// [`restore_level`] is called.
+///
+/// Used by locks to avoid priority inversion while `waiter` waits for a lock held by `owner`.
+pub(crate) fn inherit_level(owner: TaskId, waiter: TaskId) {
+    assert!(!interrupts::are_enabled());
+    TASK_MANAGER
+        .get()
+        .with_lock(|tm| tm.inherit_level(owner, waiter));
+}
+
+/// Returns the task to its own level after [`inherit_level`].
+pub(crate) fn restore_level(task_id: TaskId) {
+    assert!(!interrupts::are_enabled());
+    if let Some(switch_task) = TASK_MANAGER.get().with_lock(|tm| tm.restore_level(task_id)) {
+        switch_task.switch();
+    }
+}
+
+/// Spawns `task` in the real-time class, which runs above all normal levels.
+///
+/// Real-time tasks are never preempted by normal tasks and are not round-robined. Each
+/// activation (from a wake-up to the next sleep) is expected to finish within `deadline`, and
+/// activations exceeding it are counted as deadline misses.
+pub(crate) fn spawn_realtime(task: Task, deadline: Duration) -> TaskId {
+    task.set_level(REALTIME_LEVEL);
+    TASK_MANAGER.get().with_lock(|tm| {
+        tm.realtime.insert(task.id, RealtimeStats::new(deadline));
+    });
+    spawn(task)
+}
+
+/// Returns whether any task is waiting to run, which needs timer ticks to preempt the current
+/// task.
+pub(crate) fn has_waiting_tasks() -> bool {
+    assert!(!interrupts::are_enabled());
+    match TASK_MANAGER.try_get() {
+        Ok(task_manager) => {
+            task_manager.with_lock(|tm| tm.wake_queue.iter().any(|q| !q.is_empty()))
+        }
+        Err(_) => false,
+    }
+}
+
+/// Returns the number of context switches since boot.
+pub(crate) fn context_switches() -> u64 {
+    CONTEXT_SWITCHES.load(Ordering::Relaxed)
+}
+
+/// Switches to another task of the same or higher level, if any.
+pub(crate) fn yield_now() {
+    assert!(!interrupt::is_interrupt_context());
+    interrupts::without_interrupts(|| {
+        if let Some(switch_task) = TASK_MANAGER.get().with_lock(|tm| tm.switch_context(false)) {
+            switch_task.switch();
+        }
+    });
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct TaskInfo {
+    pub(crate) id: TaskId,
+    pub(crate) level: usize,
+    pub(crate) running: bool,
+    pub(crate) cpu_time: Duration,
+    /// Deadline statistics if the task is in the real-time class.
+    pub(crate) realtime: Option<RealtimeStats>,
+    /// Usable stack size, `None` for the main task.
+    pub(crate) stack_size: Option<usize>,
+    /// The maximum stack usage in bytes, `None` for the main task.
+    pub(crate) stack_used: Option<usize>,
+}
+
+/// Returns the information of all tasks.
+pub(crate) fn list() -> Vec<TaskInfo> {
+    interrupts::without_interrupts(|| {
+        TASK_MANAGER.get().with_lock(|tm| {
+            tm.tasks
+                .values()
+                .map(|task| TaskInfo {
+                    id: task.id,
+                    level: task.level(),
+                    running: task.id == tm.current_task_id,
+                    cpu_time: task.cpu_time(),
+                    realtime: tm.realtime.get(&task.id).copied(),
+                    stack_size: task.stack_size(),
+                    stack_used: task.stack_high_water_mark(),
+                })
+                .collect()
+        })
+    })
+}
+
+/// Returns the CPU time consumed by the current task, including its running time slice.
+pub(crate) fn current_cpu_time() -> Duration {
+    assert!(!interrupts::are_enabled());
+    TASK_MANAGER
+        .get()
+        .with_lock(|tm| tm.current_task().cpu_time() + tm.switched_at.elapsed())
+}
+
+/// Returns the frame pointer saved when the task was switched out.
+///
+/// Returns `None` if the task is running or does not exist.
+pub(crate) fn saved_frame_pointer(task_id: TaskId) -> Option<u64> {
+    assert!(!interrupts::are_enabled());
+    TASK_MANAGER.get().with_lock(|tm| {
+        if tm.current_task_id == task_id {
+            return None;
+        }
+        tm.tasks.get(&task_id).map(|task| task.ctx.rbp)
+    })
+}
+
 pub(crate) fn current() -> Arc<Task> {
     assert!(!interrupt::is_interrupt_context());
     assert!(!interrupts::are_enabled());
     TASK_MANAGER.get().lock().current_task()
 }
 
+/// Returns the ID of the current task, or `None` if the task manager is not initialized yet.
+pub(crate) fn try_current_id() -> Option<TaskId> {
+    assert!(!interrupts::are_enabled());
+    let task_manager = TASK_MANAGER.try_get().ok()?;
+    Some(task_manager.lock().current_task_id)
+}
+
+/// Returns the ID of the current task without taking the task manager lock.
+///
+/// This is for diagnostics of the locks themselves.
+#[cfg(feature = "lock-debug")]
+pub(crate) fn current_id_unlocked() -> Option<TaskId> {
+    match CURRENT_TASK_ID.load(Ordering::Relaxed) {
+        0 => None,
+        id => Some(TaskId(id - 1)),
+    }
+}
+
+/// Blocks the current task until `duration` elapses.
+///
+/// Other tasks run in the meantime, but co-tasks sharing the executor with the caller don't.
+/// Co-tasks should await [`timer::lapic::sleep`] instead.
+pub(crate) fn sleep_for(duration: Duration) -> Result<()> {
+    block_on(timer::lapic::sleep(duration))
+}
+
+/// Runs `future` to completion, putting the current task to sleep while it is pending.
+pub(crate) fn block_on<F>(future: F) -> F::Output
+where
+    F: Future,
+{
+    let task_id = interrupts::without_interrupts(|| current().id());
+    let task_waker = Arc::new(TaskWaker {
+        task_id,
+        woken: AtomicBool::new(false),
+    });
+    let waker = Waker::from(Arc::clone(&task_waker));
+    let mut context = Context::from_waker(&waker);
+
+    pin_mut!(future);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+            return output;
+        }
+        interrupts::disable();
+        if !task_waker.woken.swap(false, Ordering::Relaxed) {
+            sleep(task_id);
+        }
+        interrupts::enable();
+    }
+}
+
+struct TaskWaker {
+    task_id: TaskId,
+    woken: AtomicBool,
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref()
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        interrupts::without_interrupts(|| {
+            self.woken.store(true, Ordering::Relaxed);
+            wake(self.task_id);
+        })
+    }
+}
+
 #[derive(Debug)]
 #[must_use]
 struct SwitchTask {
@@ -92,6 +289,7 @@ impl SwitchTask {
     fn switch(self) {
         assert!(Arc::strong_count(&self.next_task) > 1);
         assert!(Arc::strong_count(&self.current_task) > 1);
+        CONTEXT_SWITCHES.fetch_add(1, Ordering::Relaxed);
         unsafe {
             let next_task_ptr = Arc::as_ptr(&self.next_task);
             let current_task_ptr = Arc::as_ptr(&self.current_task);
@@ -110,17 +308,90 @@ impl SwitchTask {
 const MAX_LEVEL: usize = 3;
 const MIN_LEVEL: usize = 0;
 const DEFAULT_LEVEL: usize = 1;
+/// The level of real-time tasks, which is not a normal level and has no time slice.
+pub(crate) const REALTIME_LEVEL: usize = MAX_LEVEL + 1;
+
+/// Timer ticks a task can run before other tasks of the same level, indexed by level.
+///
+/// `None` disables round robin for the level, so that the idle task never preempts others.
+const DEFAULT_TIME_SLICES: [Option<u64>; MAX_LEVEL + 1] = [None, Some(4), Some(2), Some(1)];
+
+/// Returns the time slice of each level.
+pub(crate) fn time_slices() -> [Option<u64>; MAX_LEVEL + 1] {
+    interrupts::without_interrupts(|| TASK_MANAGER.get().lock().time_slices)
+}
+
+/// Sets the time slice of tasks in `level` in timer ticks.
+pub(crate) fn set_time_slice(level: usize, ticks: Option<u64>) -> Result<()> {
+    if level > MAX_LEVEL || ticks == Some(0) {
+        bail!(ErrorKind::IndexOutOfRange);
+    }
+    interrupts::without_interrupts(|| {
+        TASK_MANAGER.get().lock().time_slices[level] = ticks;
+    });
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RealtimeStats {
+    pub(crate) deadline: Duration,
+    /// When the current activation started, `None` while the task sleeps.
+    activated_at: Option<Instant>,
+    /// The number of finished activations.
+    pub(crate) activations: u64,
+    pub(crate) deadline_misses: u64,
+    /// The longest activation.
+    pub(crate) worst_time: Duration,
+}
+
+impl RealtimeStats {
+    fn new(deadline: Duration) -> Self {
+        Self {
+            deadline,
+            activated_at: None,
+            activations: 0,
+            deadline_misses: 0,
+            worst_time: Duration::ZERO,
+        }
+    }
+
+    fn activate(&mut self, now: Instant) {
+        if self.activated_at.is_none() {
+            self.activated_at = Some(now);
+        }
+    }
+
+    fn finish(&mut self, now: Instant) {
+        let activated_at = match self.activated_at.take() {
+            Some(activated_at) => activated_at,
+            None => return,
+        };
+        let time = now - activated_at;
+        self.activations += 1;
+        if time > self.deadline {
+            self.deadline_misses += 1;
+        }
+        self.worst_time = self.worst_time.max(time);
+    }
+}
 
 #[derive(Debug)]
 struct TaskManager {
     tasks: BTreeMap<TaskId, Arc<Task>>,
     current_task_id: TaskId,
-    wake_queue: [VecDeque<TaskId>; MAX_LEVEL + 1],
+    wake_queue: [VecDeque<TaskId>; REALTIME_LEVEL + 1],
+    time_slices: [Option<u64>; MAX_LEVEL + 1],
+    realtime: BTreeMap<TaskId, RealtimeStats>,
+    /// Timer ticks left in the time slice of the current task.
+    remaining_ticks: u64,
+    /// When the current task started running.
+    switched_at: Instant,
 }
 
 impl TaskManager {
     fn new(current_task: Task) -> Self {
         let current_task_id = current_task.id;
+        CURRENT_TASK_ID.store(current_task_id.0 + 1, Ordering::Relaxed);
         let mut tasks = BTreeMap::new();
         tasks.insert(current_task_id, Arc::new(current_task));
         Self {
@@ -131,7 +402,12 @@ impl TaskManager {
                 VecDeque::with_capacity(1),
                 VecDeque::with_capacity(1),
                 VecDeque::with_capacity(1),
+                VecDeque::with_capacity(1),
             ],
+            time_slices: DEFAULT_TIME_SLICES,
+            realtime: BTreeMap::new(),
+            remaining_ticks: 0,
+            switched_at: Instant::now(),
         }
     }
 
@@ -165,6 +441,11 @@ impl TaskManager {
             self.wake_queue[level].push_back(current_task.id);
         }
         self.current_task_id = next_task.id;
+        CURRENT_TASK_ID.store(next_task.id.0 + 1, Ordering::Relaxed);
+        self.remaining_ticks = self.time_slice(next_task.level()).unwrap_or(0);
+        let now = Instant::now();
+        current_task.add_cpu_time(now - self.switched_at);
+        self.switched_at = now;
 
         Some(SwitchTask {
             next_task,
@@ -183,6 +464,10 @@ impl TaskManager {
 
         // request to wake
         self.wake_queue[level].push_back(task_id);
+        timer::lapic::request_tick();
+        if let Some(stats) = self.realtime.get_mut(&task_id) {
+            stats.activate(Instant::now());
+        }
 
         // if the task has higher level than current task, switch to the task immediately
         if !interrupt::is_interrupt_context() && level > self.current_task().level() {
@@ -196,6 +481,9 @@ impl TaskManager {
     fn sleep(&mut self, task_id: TaskId) -> Option<SwitchTask> {
         if self.current_task_id == task_id {
             // sleep running task
+            if let Some(stats) = self.realtime.get_mut(&task_id) {
+                stats.finish(Instant::now());
+            }
             return self.switch_context(true);
         }
 
@@ -209,6 +497,85 @@ impl TaskManager {
         None
     }
 
+    /// Accounts a timer tick to the current task, and switches tasks if a higher level task is
+    /// waiting or the time slice of the current task is used up.
+    #[must_use]
+    fn tick(&mut self) -> Option<SwitchTask> {
+        let level = self.current_task().level();
+        if self.wake_queue[level + 1..]
+            .iter()
+            .any(|queue| !queue.is_empty())
+        {
+            return self.switch_context(false);
+        }
+
+        let time_slice = self.time_slice(level)?;
+        if self.remaining_ticks > 1 {
+            self.remaining_ticks -= 1;
+            return None;
+        }
+        // the current task keeps running with a new time slice if no other task is waiting
+        self.remaining_ticks = time_slice;
+        self.switch_context(false)
+    }
+
+    /// Changes the level of `task`, moving it to the wake queue of the new level if it is waiting.
+    fn move_to_level(&mut self, task: &Task, level: usize) {
+        let old_level = task.level();
+        if old_level == level {
+            return;
+        }
+        if let Some(idx) = self.wake_queue[old_level]
+            .iter()
+            .position(|task_id| *task_id == task.id)
+        {
+            let _ = self.wake_queue[old_level].remove(idx);
+            self.wake_queue[level].push_back(task.id);
+        }
+        task.level.store(level, Ordering::Relaxed);
+    }
+
+    fn inherit_level(&mut self, owner: TaskId, waiter: TaskId) {
+        let waiter_level = match self.tasks.get(&waiter) {
+            Some(waiter) => waiter.level(),
+            None => return,
+        };
+        let owner = match self.tasks.get(&owner) {
+            Some(owner) => Arc::clone(owner),
+            None => return,
+        };
+        if waiter_level > owner.level() {
+            self.move_to_level(&owner, waiter_level);
+        }
+    }
+
+    #[must_use]
+    fn restore_level(&mut self, task_id: TaskId) -> Option<SwitchTask> {
+        let task = Arc::clone(self.tasks.get(&task_id)?);
+        let base_level = task.base_level.load(Ordering::Relaxed);
+        if task.level() == base_level {
+            return None;
+        }
+        self.move_to_level(&task, base_level);
+
+        let higher_task_waiting = self.wake_queue[base_level + 1..]
+            .iter()
+            .any(|queue| !queue.is_empty());
+        if task_id == self.current_task_id
+            && higher_task_waiting
+            && !interrupt::is_interrupt_context()
+        {
+            self.switch_context(false)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the time slice of `level`. Real-time tasks have no time slice.
+    fn time_slice(&self, level: usize) -> Option<u64> {
+        self.time_slices.get(level).copied().flatten()
+    }
+
     fn current_task(&self) -> Arc<Task> {
         #[allow(clippy::unwrap_used)] // current task must be exist
         Arc::clone(self.tasks.get(&self.current_task_id).unwrap())
@@ -227,7 +594,7 @@ impl TaskManager {
 }
 
 pub(crate) fn on_interrupt(guard: InterruptContextGuard) {
-    if let Some(task_switch) = TASK_MANAGER.get().with_lock(|tm| tm.switch_context(false)) {
+    if let Some(task_switch) = TASK_MANAGER.get().with_lock(|tm| tm.tick()) {
         drop(guard);
         task_switch.switch();
     }
@@ -242,6 +609,11 @@ impl TaskId {
         static NEXT_ID: AtomicU64 = AtomicU64::new(0);
         Self(NEXT_ID.fetch_add(1, Ordering::Relaxed))
     }
+
+    #[cfg_attr(not(feature = "lock-debug"), allow(dead_code))]
+    pub(crate) fn as_u64(self) -> u64 {
+        self.0
+    }
 }
 
 impl fmt::Display for TaskId {
@@ -291,21 +663,44 @@ impl Default for TaskContext {
     }
 }
 
-#[derive(Debug, Clone, Copy, Default)]
+/// Maximum bytes added below the initial stack pointer of tasks for randomization.
+const MAX_STACK_SLACK: usize = 1024;
+
+/// Stack size for tasks running typical co-tasks.
+pub(crate) const DEFAULT_STACK_SIZE: usize = 1024 * 16;
+/// Stack size for the idle task, which never runs deep call chains.
+const IDLE_STACK_SIZE: usize = 1024 * 8;
+
+/// Byte pattern filled in new stacks to find how deep the stack has been used.
+const STACK_POISON: u8 = 0xa5;
+
+#[derive(Debug, Clone, Copy)]
 #[repr(C, align(16))]
 struct TaskStackElement {
-    _dummy: [u8; 16],
+    bytes: [u8; 16],
 }
 static_assertions::const_assert_eq!(mem::size_of::<TaskStackElement>(), 16);
 
+impl TaskStackElement {
+    const POISONED: Self = Self {
+        bytes: [STACK_POISON; 16],
+    };
+}
+
 #[derive(CustomDebug)]
 pub(crate) struct Task {
     id: TaskId,
+    /// The effective level, which may be raised by [`inherit_level`].
     level: AtomicUsize,
+    /// The level the task is spawned with.
+    base_level: AtomicUsize,
+    cpu_nanos: AtomicU64,
     #[debug(skip)]
     ctx: Box<TaskContext>,
     #[debug(skip)]
-    _stack: Box<[TaskStackElement]>,
+    stack: Box<[TaskStackElement]>,
+    /// Offset of the initial stack pointer from the stack bottom.
+    stack_top: usize,
 }
 
 impl Task {
@@ -318,22 +713,49 @@ impl Task {
         Self {
             id,
             level,
+            base_level: AtomicUsize::new(DEFAULT_LEVEL),
+            cpu_nanos: AtomicU64::new(0),
             ctx,
-            _stack: stack,
+            stack,
+            stack_top: 0,
         }
     }
 
-    pub(crate) fn new(future: impl Future<Output = ()> + Send + 'static) -> Self {
+    /// Creates a task running `future`, whose stack has at least `stack_size` usable bytes.
+    pub(crate) fn new(
+        stack_size: usize,
+        future: impl Future<Output = ()> + Send + 'static,
+    ) -> Self {
+        Self::with_executor(stack_size, |executor| executor.spawn(CoTask::new(future))).0
+    }
+
+    /// Creates a task running `future`, and a handle to await its output.
+    pub(crate) fn with_handle<T>(
+        stack_size: usize,
+        future: impl Future<Output = T> + Send + 'static,
+    ) -> (Self, JoinHandle<T>)
+    where
+        T: Send + 'static,
+    {
+        Self::with_executor(stack_size, |executor| executor.spawn_with_handle(future))
+    }
+
+    /// Creates a task running the executor, where `spawn` spawns the first co-task.
+    fn with_executor<R>(stack_size: usize, spawn: impl FnOnce(&mut Executor) -> R) -> (Self, R) {
         let id = TaskId::new();
         let level = AtomicUsize::new(DEFAULT_LEVEL);
-        let stack_size = 1024 * 8;
         let stack_elem_size = mem::size_of::<TaskStackElement>();
-        let stack =
-            vec![TaskStackElement::default(); (stack_size + stack_elem_size - 1) / stack_elem_size]
-                .into_boxed_slice();
+        // randomize the initial stack pointer, keeping `stack_size` bytes usable
+        let stack_slack =
+            random::range(0..(MAX_STACK_SLACK / stack_elem_size) as u64) as usize * stack_elem_size;
+        let stack = vec![
+            TaskStackElement::POISONED;
+            (stack_size + MAX_STACK_SLACK + stack_elem_size - 1) / stack_elem_size
+        ]
+        .into_boxed_slice();
 
         let mut executor = Executor::new(id);
-        executor.spawn(CoTask::new(future));
+        let spawned = spawn(&mut executor);
         let arg = Box::new(EntryPointArg { executor });
 
         let mut ctx = Box::new(TaskContext::default());
@@ -348,17 +770,23 @@ impl Task {
         ctx.rflags = 0x202;
         ctx.cs = u64::from(selectors.kernel_code_selector.0);
         ctx.ss = u64::from(selectors.kernel_stack_selector.0);
-        ctx.rsp = unsafe { (stack.as_ptr() as *const u8).add(stack_size - 8) as u64 };
+        let stack_top = stack_size + stack_slack;
+        ctx.rsp = unsafe { (stack.as_ptr() as *const u8).add(stack_top - 8) as u64 };
         assert!(ctx.rsp & 0xf == 8);
+        trace!("task {}: stack top = {:#x}", id, ctx.rsp);
 
         ctx.fxsave_area[24..][..4].copy_from_slice(&0x1f80u32.to_le_bytes());
 
-        Self {
+        let task = Self {
             id,
             level,
+            base_level: AtomicUsize::new(DEFAULT_LEVEL),
+            cpu_nanos: AtomicU64::new(0),
             ctx,
-            _stack: stack,
-        }
+            stack,
+            stack_top,
+        };
+        (task, spawned)
     }
 
     pub(crate) fn id(&self) -> TaskId {
@@ -369,8 +797,38 @@ impl Task {
         self.level.load(Ordering::Relaxed)
     }
 
+    /// Sets the level of a task not spawned yet.
     fn set_level(&self, level: usize) {
         self.level.store(level, Ordering::Relaxed);
+        self.base_level.store(level, Ordering::Relaxed);
+    }
+
+    /// Returns the usable stack size, or `None` for the main task running on the boot stack.
+    fn stack_size(&self) -> Option<usize> {
+        (self.stack_top > 0).then(|| self.stack_top)
+    }
+
+    /// Returns the maximum number of stack bytes the task has ever used.
+    ///
+    /// The usage is found by looking for the lowest byte whose poison pattern was overwritten,
+    /// so a stack overflow beyond the stack bottom is reported as the full stack size.
+    fn stack_high_water_mark(&self) -> Option<usize> {
+        let size = self.stack_size()?;
+        let bytes = unsafe { slice::from_raw_parts(self.stack.as_ptr() as *const u8, size) };
+        let untouched = bytes
+            .iter()
+            .position(|&byte| byte != STACK_POISON)
+            .unwrap_or(size);
+        Some(size - untouched)
+    }
+
+    fn cpu_time(&self) -> Duration {
+        Duration::from_nanos(self.cpu_nanos.load(Ordering::Relaxed))
+    }
+
+    fn add_cpu_time(&self, time: Duration) {
+        let nanos = u64::try_from(time.as_nanos()).unwrap_or(u64::MAX);
+        self.cpu_nanos.fetch_add(nanos, Ordering::Relaxed);
     }
 
     fn switch(next: &Task, current: &Task) {
@@ -434,8 +892,8 @@ extern "C" fn switch_context(_next: &TaskContext, _current: &TaskContext) {
             "mov cr3, rax",
             "mov rax, [rdi + 0x30]",
             "mov fs, ax",
-            "mov rax, [rdi + 0x38]",
-            "mov gs, ax",
+            // GS is not restored, since loading a selector clears the GS base, which points to
+            // the per-CPU data, on some CPUs
             //
             "mov rax, [rdi + 0x40]",
             "mov rbx, [rdi + 0x48]",
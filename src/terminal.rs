@@ -1,23 +1,38 @@
 use crate::{
-    fat,
-    fmt::ByteString,
+    bench, clipboard, co_task,
+    device::{self, DeviceId, DeviceState},
+    dialog, driver, fat,
+    fmt::Jisx0201String,
     framed_window::{FramedWindow, FramedWindowEvent},
     graphics::{font, Color, Draw, Offset, Point, Rectangle, Size},
+    idle, interrupt, memory, memtest,
+    mouse::{MouseButton, MouseEvent},
+    net::{
+        self,
+        capture::{self, Capture},
+        http, icmp, tcp, udp, Ipv4Addr, Link, SocketAddrV4,
+    },
     pci,
     prelude::*,
+    shutdown, stress,
+    sync::mpsc,
+    task,
+    theme::{self, Theme},
+    time::{self, Instant},
     timer,
+    usb::audio,
+    xhc,
 };
-use alloc::{collections::VecDeque, string::String, vec::Vec};
+use alloc::{collections::VecDeque, format, string::String, vec, vec::Vec};
+use bootloader::boot_info::MemoryRegionKind;
 use core::{
     fmt::{self, Write as _},
-    mem,
+    future::{self, Future},
+    iter, mem,
+    time::Duration,
 };
 use futures_util::select_biased;
-
-const FOREGROUND: Color = Color::WHITE;
-const BACKGROUND: Color = Color::BLACK;
-const BORDER_DARK: Color = Color::from_code(0x848484);
-const BORDER_LIGHT: Color = Color::from_code(0xc6c6c6);
+use x86_64::instructions::interrupts;
 
 const PADDING_TOP: i32 = 4;
 const PADDING_BOTTOM: i32 = 4;
@@ -27,6 +42,16 @@ const PADDING_POS: Point<i32> = Point::new(PADDING_LEFT, PADDING_TOP);
 const PADDING_SIZE: Size<i32> =
     Size::new(PADDING_LEFT + PADDING_RIGHT, PADDING_TOP + PADDING_BOTTOM);
 const HISTORY_LEN: usize = 8;
+/// The number of lines kept after they are scrolled out of the window.
+const SCROLLBACK_LEN: usize = 256;
+/// The number of lines scrolled by a notch of the mouse wheel.
+const WHEEL_SCROLL_LINES: usize = 3;
+/// The number of packets `tcpdump` captures by default on a headless terminal, which cannot be
+/// stopped by a key press.
+const HEADLESS_CAPTURE_COUNT: usize = 16;
+/// The number of frames `beep` generates at a time.
+const BEEP_CHUNK_FRAMES: u64 = 480;
+const BEEP_AMPLITUDE: i16 = 0x1000;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum Direction {
@@ -34,70 +59,422 @@ enum Direction {
     Newer,
 }
 
+/// A packet capture started by `tcpdump`, which runs after the command line is executed.
+#[derive(Debug)]
+struct CaptureJob {
+    capture: Capture,
+    iface: Option<&'static str>,
+    count: Option<usize>,
+    file: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Selection {
+    start: Point<i32>,
+    end: Point<i32>,
+}
+
+impl Selection {
+    /// Returns the range of the selected cell indices.
+    fn range(&self, width: i32) -> (i32, i32) {
+        let start = self.start.y * width + self.start.x;
+        let end = self.end.y * width + self.end.x;
+        (i32::min(start, end), i32::max(start, end) + 1)
+    }
+}
+
+/// Truncates or pads `line` to `width` cells, dropping a wide character cut off at the end.
+fn fit_line(line: &[char], width: usize) -> Vec<char> {
+    let mut line = line.iter().copied().take(width).collect::<Vec<_>>();
+    line.resize(width, ' ');
+    if let Some(last) = line.last_mut() {
+        if font::char_width(*last) == 2 {
+            *last = ' ';
+        }
+    }
+    line
+}
+
+/// Runs `future` as a helper co-task, and blocks the terminal task until it completes.
+///
+/// The executor of the terminal task does not run while a command blocks it, so the helper is
+/// spawned on the least loaded executor of another task.
+fn run_helper<T>(future: impl Future<Output = T> + Send + 'static) -> Result<T>
+where
+    T: Send + 'static,
+{
+    let current = interrupts::without_interrupts(|| task::current().id());
+    #[allow(clippy::expect_used)]
+    let handle = co_task::executors()
+        .into_iter()
+        .filter(|handle| handle.task_id() != current)
+        .min_by_key(co_task::Handle::load)
+        .expect("no other executor is running");
+    Ok(task::block_on(handle.spawn_with_handle(future))?)
+}
+
 #[derive(Debug)]
 pub(crate) struct Terminal {
     text_size: Size<i32>,
+    cells: Vec<char>,
+    /// Lines scrolled out of the window, the oldest first.
+    scrollback: VecDeque<Vec<char>>,
+    /// The number of lines the view is scrolled back by the mouse wheel.
+    scroll_offset: usize,
+    selection: Option<Selection>,
+    selecting: bool,
     cursor: Point<i32>,
     cursor_visible: bool,
     line_buf: String,
     history: VecDeque<String>,
     history_index: Option<usize>,
-    window: FramedWindow,
+    redraw_frames: usize,
+    capture_job: Option<CaptureJob>,
+    /// `None` for a headless terminal, whose output is buffered in `output` instead.
+    window: Option<FramedWindow>,
+    output: String,
+    fg: Color,
+    bg: Color,
 }
 
 impl Terminal {
     pub(crate) fn new(title: String, pos: Point<i32>, text_size: Size<i32>) -> Result<Self> {
-        let font_size = font::FONT_PIXEL_SIZE;
+        let font_size = font::pixel_size();
         let window = FramedWindow::builder(title)
             .pos(pos)
             .size(text_size * font_size + PADDING_SIZE)
+            .resizable(true)
             .build()?;
+        let theme = theme::current();
         Ok(Self {
             text_size,
+            cells: vec![' '; (text_size.x * text_size.y) as usize],
+            scrollback: VecDeque::with_capacity(SCROLLBACK_LEN),
+            scroll_offset: 0,
+            selection: None,
+            selecting: false,
             cursor: Point::new(0, 0),
             cursor_visible: false,
             line_buf: String::new(),
             history: VecDeque::with_capacity(HISTORY_LEN),
             history_index: None,
-            window,
+            redraw_frames: 0,
+            capture_job: None,
+            window: Some(window),
+            output: String::new(),
+            fg: theme.terminal_fg,
+            bg: theme.terminal_bg,
         })
     }
 
+    /// Creates a terminal without a window, which is driven by [`execute`](Self::execute).
+    pub(crate) fn headless() -> Self {
+        let theme = theme::current();
+        Self {
+            text_size: Size::new(0, 0),
+            cells: Vec::new(),
+            scrollback: VecDeque::new(),
+            scroll_offset: 0,
+            selection: None,
+            selecting: false,
+            cursor: Point::new(0, 0),
+            cursor_visible: false,
+            line_buf: String::new(),
+            history: VecDeque::with_capacity(HISTORY_LEN),
+            history_index: None,
+            redraw_frames: 0,
+            capture_job: None,
+            window: None,
+            output: String::new(),
+            fg: theme.terminal_fg,
+            bg: theme.terminal_bg,
+        }
+    }
+
+    /// Executes a command line, and returns the output.
+    pub(crate) fn execute(&mut self, line: &str) -> String {
+        self.line_buf = line.into();
+        self.execute_line();
+        if !self.line_buf.is_empty() && !self.line_buf.starts_with(char::is_whitespace) {
+            self.push_history();
+        }
+        self.line_buf.clear();
+        if let Some(job) = self.capture_job.take() {
+            if let Err(err) = task::block_on(self.run_capture(job)) {
+                let _ = writeln!(self, "tcpdump: {}", err);
+            }
+        }
+        mem::take(&mut self.output)
+    }
+
     fn draw_terminal(&mut self) {
-        let area = self.window.area();
-        self.window
-            .draw_box(area, BACKGROUND, BORDER_DARK, BORDER_LIGHT)
+        let theme = theme::current();
+        if let Some(window) = &mut self.window {
+            let area = window.area();
+            window.draw_box(area, self.bg, theme.shadow, theme.face)
+        }
+    }
+
+    /// Redraws the whole terminal in the colors of the current theme.
+    fn redraw(&mut self) {
+        let theme = theme::current();
+        self.fg = theme.terminal_fg;
+        self.bg = theme.terminal_bg;
+        self.draw_terminal();
+        self.draw_view();
+        self.draw_cursor(self.cursor_visible);
+    }
+
+    /// Fits the cells to the resized window, and redraws the whole terminal.
+    ///
+    /// The lines above the cursor are scrolled out if the window gets shorter, and the lines are
+    /// truncated or padded to the new width.
+    fn resize(&mut self) {
+        let window_size = match &self.window {
+            Some(window) => window.size(),
+            None => return,
+        };
+        let font_size = font::pixel_size();
+        let content_size = window_size - PADDING_SIZE;
+        let text_size = Size::new(
+            i32::max(content_size.x / font_size.x, 1),
+            i32::max(content_size.y / font_size.y, 1),
+        );
+        self.selection = None;
+        self.selecting = false;
+        self.scroll_offset = 0;
+
+        let old_width = self.text_size.x as usize;
+        let mut lines = self.cells.chunks(old_width).collect::<Vec<_>>();
+        let scrolled_out = i32::max(self.cursor.y + 1 - text_size.y, 0) as usize;
+        for line in lines.drain(..scrolled_out) {
+            if self.scrollback.len() == SCROLLBACK_LEN {
+                self.scrollback.pop_front();
+            }
+            self.scrollback.push_back(line.to_vec());
+        }
+        let width = text_size.x as usize;
+        let mut cells = vec![' '; (text_size.x * text_size.y) as usize];
+        for (dst, line) in cells.chunks_mut(width).zip(lines) {
+            dst.copy_from_slice(&fit_line(line, width));
+        }
+        for line in &mut self.scrollback {
+            *line = fit_line(line, width);
+        }
+
+        self.text_size = text_size;
+        self.cells = cells;
+        self.cursor = Point::new(
+            i32::min(self.cursor.x, text_size.x - 1),
+            self.cursor.y - scrolled_out as i32,
+        );
+        self.redraw();
     }
 
     fn insert_pos(&self) -> Point<i32> {
-        let font_size = font::FONT_PIXEL_SIZE;
+        let font_size = font::pixel_size();
         font_size * self.cursor + PADDING_POS
     }
 
+    fn cell_index(&self, pos: Point<i32>) -> usize {
+        (pos.y * self.text_size.x + pos.x) as usize
+    }
+
+    fn cell_at(&self, pos: Point<i32>) -> Point<i32> {
+        let font_size = font::pixel_size();
+        let pos = pos - PADDING_POS;
+        Point::new(
+            (pos.x / font_size.x).clamp(0, self.text_size.x - 1),
+            (pos.y / font_size.y).clamp(0, self.text_size.y - 1),
+        )
+    }
+
+    fn is_selected(&self, pos: Point<i32>) -> bool {
+        self.selection
+            .map(|selection| {
+                let (start, end) = selection.range(self.text_size.x);
+                let index = self.cell_index(pos) as i32;
+                start <= index && index < end
+            })
+            .unwrap_or(false)
+    }
+
+    fn draw_cell(&mut self, pos: Point<i32>) {
+        // the tail of a wide character is drawn with the character
+        if pos.x > 0 && self.cells[self.cell_index(pos)] == font::WIDE_CHAR_TAIL {
+            let head = pos - Point::new(1, 0);
+            if font::char_width(self.cells[self.cell_index(head)]) == 2 {
+                self.draw_cell(head);
+                return;
+            }
+        }
+        let font_size = font::pixel_size();
+        let (fg, bg) = if self.is_selected(pos) {
+            (self.bg, self.fg)
+        } else {
+            (self.fg, self.bg)
+        };
+        let draw_pos = font_size * pos + PADDING_POS;
+        let ch = self.cells[self.cell_index(pos)];
+        if let Some(window) = &mut self.window {
+            window.draw_char_bg(draw_pos, ch, fg, bg);
+        }
+    }
+
+    fn set_selection(&mut self, selection: Option<Selection>) {
+        if self.selection == selection {
+            return;
+        }
+
+        let width = self.text_size.x;
+        let old_range = self.selection.map(|selection| selection.range(width));
+        let new_range = selection.map(|selection| selection.range(width));
+        self.selection = selection;
+
+        // redraw cells whose highlight state may be changed
+        for (start, end) in old_range.into_iter().chain(new_range) {
+            for index in start..end {
+                self.draw_cell(Point::new(index % width, index / width));
+            }
+        }
+    }
+
+    fn selected_text(&self) -> Option<String> {
+        let width = self.text_size.x;
+        let (start, end) = self.selection?.range(width);
+        let mut text = String::new();
+        for y in (start / width)..=((end - 1) / width) {
+            let line_start = i32::max(start, y * width) as usize;
+            let line_end = i32::min(end, (y + 1) * width) as usize;
+            if y > start / width {
+                text.push('\n');
+            }
+            let line = self.cells[line_start..line_end]
+                .iter()
+                .filter(|ch| **ch != font::WIDE_CHAR_TAIL)
+                .collect::<String>();
+            text.push_str(line.trim_end());
+        }
+        Some(text)
+    }
+
+    /// Scrolls the view back by `lines` lines, or forward if negative.
+    fn scroll_view(&mut self, lines: isize) {
+        let offset = (self.scroll_offset as isize + lines).clamp(0, self.scrollback.len() as isize);
+        if offset as usize == self.scroll_offset {
+            return;
+        }
+        self.set_selection(None);
+        self.scroll_offset = offset as usize;
+        self.draw_view();
+    }
+
+    /// Redraws all the cells, with the lines scrolled back by `scroll_offset`.
+    fn draw_view(&mut self) {
+        let font_size = font::pixel_size();
+        let width = self.text_size.x as usize;
+        let window = match &mut self.window {
+            Some(window) => window,
+            None => return,
+        };
+        let scrollback_start = self.scrollback.len() - self.scroll_offset;
+        for y in 0..self.text_size.y {
+            let index = scrollback_start + y as usize;
+            let line = match self.scrollback.get(index) {
+                Some(line) => &line[..],
+                None => {
+                    let row = index - self.scrollback.len();
+                    &self.cells[row * width..(row + 1) * width]
+                }
+            };
+            let draw_pos = Point::new(0, y) * font_size + PADDING_POS;
+            for (x, ch) in font::visible_cells(line) {
+                let pos = draw_pos + Offset::new(x as i32, 0) * font_size;
+                window.draw_char_bg(pos, ch, self.fg, self.bg);
+            }
+        }
+    }
+
+    fn handle_mouse_event(&mut self, event: MouseEvent) {
+        if event.wheel != 0 {
+            self.scroll_view(event.wheel as isize * WHEEL_SCROLL_LINES as isize);
+        }
+        if !event.down.is_empty() {
+            // the selection is in the cells, which are not visible while scrolled back
+            self.scroll_view(-(self.scroll_offset as isize));
+        }
+        if self.scroll_offset > 0 {
+            return;
+        }
+
+        let cell = self.cell_at(event.pos);
+        if event.down.contains(MouseButton::Left) {
+            self.selecting = true;
+            self.set_selection(Some(Selection {
+                start: cell,
+                end: cell,
+            }));
+        } else if self.selecting {
+            if let Some(selection) = self.selection {
+                self.set_selection(Some(Selection {
+                    end: cell,
+                    ..selection
+                }));
+            }
+        }
+
+        if event.up.contains(MouseButton::Left) && self.selecting {
+            self.selecting = false;
+            match self.selection {
+                Some(selection) if selection.start != selection.end => {
+                    if let Some(text) = self.selected_text() {
+                        clipboard::set_text(text);
+                    }
+                }
+                _ => self.set_selection(None),
+            }
+        }
+    }
+
     fn draw_cursor(&mut self, visible: bool) {
-        let font_size = font::FONT_PIXEL_SIZE;
-        let color = if visible { FOREGROUND } else { BACKGROUND };
+        if self.scroll_offset > 0 {
+            return;
+        }
+        let font_size = font::pixel_size();
+        let color = if visible { self.fg } else { self.bg };
         let pos = self.insert_pos();
-        self.window
-            .fill_rect(Rectangle::new(pos, font_size - Size::new(1, 1)), color);
+        if let Some(window) = &mut self.window {
+            window.fill_rect(Rectangle::new(pos, font_size - Size::new(1, 1)), color);
+        }
     }
 
     fn scroll1(&mut self) {
-        let font_size = font::FONT_PIXEL_SIZE;
-        self.window.move_area(
-            Offset::new(0, -1) * font_size,
-            Rectangle::new(
-                Point::new(0, 1) * font_size + PADDING_POS,
-                (self.text_size - Size::new(0, 1)) * font_size,
-            ),
-        );
-        self.window.fill_rect(
-            Rectangle::new(
-                Offset::new(0, self.text_size.y - 1) * font_size + PADDING_POS,
-                Size::new(self.text_size.x, 1) * font_size,
-            ),
-            BACKGROUND,
-        );
+        let font_size = font::pixel_size();
+        let width = self.text_size.x as usize;
+        if self.scrollback.len() == SCROLLBACK_LEN {
+            self.scrollback.pop_front();
+        }
+        self.scrollback.push_back(self.cells[..width].to_vec());
+        self.cells.copy_within(width.., 0);
+        let len = self.cells.len();
+        self.cells[len - width..].fill(' ');
+
+        if let Some(window) = &mut self.window {
+            window.move_area(
+                Offset::new(0, -1) * font_size,
+                Rectangle::new(
+                    Point::new(0, 1) * font_size + PADDING_POS,
+                    (self.text_size - Size::new(0, 1)) * font_size,
+                ),
+            );
+            window.fill_rect(
+                Rectangle::new(
+                    Offset::new(0, self.text_size.y - 1) * font_size + PADDING_POS,
+                    Size::new(self.text_size.x, 1) * font_size,
+                ),
+                self.bg,
+            );
+        }
     }
 
     fn newline(&mut self) {
@@ -110,16 +487,36 @@ impl Terminal {
     }
 
     fn print_char(&mut self, ch: char) {
+        if self.window.is_none() {
+            self.output.push(ch);
+            return;
+        }
+        self.scroll_view(-(self.scroll_offset as isize));
+        self.set_selection(None);
         self.draw_cursor(false);
         match ch {
             '\0' => {}
             '\n' => self.newline(),
             ch => {
-                self.window.draw_char(self.insert_pos(), ch, FOREGROUND);
-                if self.cursor.x + 1 >= self.text_size.x {
+                let width = font::char_width(ch);
+                if self.cursor.x + width > self.text_size.x {
+                    // pad the line, whose last cell is deleted together with the character
+                    // before it
+                    let index = self.cell_index(self.cursor);
+                    self.cells[index] = font::WIDE_CHAR_TAIL;
+                    self.newline();
+                }
+                let index = self.cell_index(self.cursor);
+                self.cells[index] = ch;
+                self.cells[index + 1..][..width as usize - 1].fill(font::WIDE_CHAR_TAIL);
+                let pos = self.insert_pos();
+                if let Some(window) = &mut self.window {
+                    window.draw_char_bg(pos, ch, self.fg, self.bg);
+                }
+                if self.cursor.x + width >= self.text_size.x {
                     self.newline();
                 } else {
-                    self.cursor.x += 1;
+                    self.cursor.x += width;
                 }
             }
         }
@@ -136,17 +533,85 @@ impl Terminal {
     }
 
     fn delete_backward(&mut self) {
-        let font_size = font::FONT_PIXEL_SIZE;
-        if self.cursor.y > 0 && self.cursor.x == 0 {
-            self.cursor.x = self.text_size.x - 1;
-            self.cursor.y -= 1;
-        } else if self.cursor.x > 0 {
-            self.cursor.x -= 1;
-        } else {
-            assert_eq!(self.cursor, Point::new(0, 0));
+        let font_size = font::pixel_size();
+        let end = self.cell_index(self.cursor);
+        loop {
+            if self.cursor.y > 0 && self.cursor.x == 0 {
+                self.cursor.x = self.text_size.x - 1;
+                self.cursor.y -= 1;
+            } else if self.cursor.x > 0 {
+                self.cursor.x -= 1;
+            } else {
+                assert_eq!(self.cursor, Point::new(0, 0));
+                break;
+            }
+            // skip the tail of a wide character and the padding before it
+            if self.cells[self.cell_index(self.cursor)] != font::WIDE_CHAR_TAIL {
+                break;
+            }
+        }
+        let start = self.cell_index(self.cursor);
+        let width = self.text_size.x;
+        for index in start..end {
+            self.cells[index] = ' ';
+            let cell = Point::new(index as i32 % width, index as i32 / width);
+            if let Some(window) = &mut self.window {
+                window.fill_rect(
+                    Rectangle::new(font_size * cell + PADDING_POS, font_size),
+                    self.bg,
+                );
+            }
+        }
+    }
+
+    fn print_pci_details(&mut self, dev: &pci::Device) {
+        let vendor = pci::vendor_name(dev.vendor_id);
+        let device = pci::device_name(dev.vendor_id, dev.device_id);
+        let _ = writeln!(
+            self,
+            "  {}: {} {}",
+            pci::class_name(&dev.class_code),
+            vendor.unwrap_or("unknown vendor"),
+            device.unwrap_or("unknown device")
+        );
+        if let Some((vendor_id, id)) = dev.subsystem() {
+            let _ = writeln!(self, "  subsystem: {:04x}:{:04x}", vendor_id, id);
+        }
+        let _ = writeln!(self, "  interrupt: {}", dev.interrupt_mode());
+        let _ = write!(self, "  caps:");
+        for cap in dev.capabilities() {
+            let _ = write!(self, " {}@{:02x}", cap.id, cap.offset);
+        }
+        for (id, offset) in dev.extended_capabilities() {
+            let _ = match id {
+                pci::EXT_CAPABILITY_AER => write!(self, " AER@{:03x}", offset),
+                pci::EXT_CAPABILITY_SRIOV => write!(self, " SR-IOV@{:03x}", offset),
+                _ => write!(self, " {:04x}@{:03x}", id, offset),
+            };
+        }
+        let _ = writeln!(self);
+        for (index, bar) in pci::resources(dev) {
+            let _ = writeln!(self, "  BAR{}: {}", index, bar);
+        }
+    }
+
+    fn print_device_tree(
+        &mut self,
+        entries: &[device::DeviceEntry],
+        parent: Option<DeviceId>,
+        depth: usize,
+    ) {
+        for entry in entries.iter().filter(|entry| entry.parent == parent) {
+            let _ = write!(self, "{:indent$}{}", "", entry.name, indent = depth * 2);
+            if let Some(driver) = entry.driver {
+                let _ = write!(self, " [{}]", driver);
+            }
+            if entry.state != DeviceState::Bound || entry.driver.is_some() {
+                let _ = write!(self, " {}", entry.state);
+            }
+            let _ = writeln!(self);
+            self.print_device_tree(entries, Some(entry.id), depth + 1);
         }
-        self.window
-            .fill_rect(Rectangle::new(self.insert_pos(), font_size), BACKGROUND);
     }
 
     fn execute_line(&mut self) {
@@ -167,24 +632,55 @@ impl Terminal {
                 self.print_char('\n');
             }
             "clear" => {
-                let font_size = font::FONT_PIXEL_SIZE;
-                self.window.fill_rect(
-                    Rectangle::new(PADDING_POS, font_size * self.text_size),
-                    BACKGROUND,
-                );
+                let font_size = font::pixel_size();
+                self.set_selection(None);
+                self.cells.fill(' ');
+                match &mut self.window {
+                    Some(window) => window.fill_rect(
+                        Rectangle::new(PADDING_POS, font_size * self.text_size),
+                        self.bg,
+                    ),
+                    // clear the screen of the remote terminal
+                    None => self.output.push_str("\x1b[2J\x1b[H"),
+                }
                 self.cursor = Point::new(0, 0);
             }
+            "date" => match time::local_now() {
+                Some(now) => {
+                    let _ = writeln!(self, "{}", now);
+                }
+                None => {
+                    let _ = writeln!(self, "date: wall clock not set");
+                }
+            },
+            "ifconfig" => self.execute_ifconfig(),
+            "netstat" => self.execute_netstat(),
+            "lsdev" => {
+                let entries = device::entries();
+                self.print_device_tree(&entries, None, 0);
+            }
             "lspci" => match pci::scan_all_bus() {
                 Ok(devices) => {
+                    let verbose = command_line.get(1) == Some(&"-v");
                     for dev in devices {
-                        let _ = writeln!(self, "{}", dev);
+                        let _ = write!(self, "{}", dev);
+                        if let Some(driver) = driver::bound_driver(&dev) {
+                            let _ = write!(self, " [{}]", driver);
+                        }
+                        let _ = writeln!(self);
+                        if verbose {
+                            self.print_pci_details(&dev);
+                        }
                     }
                 }
                 Err(err) => {
                     let _ = writeln!(self, "lspci: failed to scan PCI devices: {}", err);
                 }
             },
+            "lsusb" => self.execute_lsusb(),
             "ls" => {
+                let long = command_line.get(1) == Some(&"-l");
+                let offset = time::utc_offset();
                 let fs = fat::lock();
                 for entry in fs.root_dir().entries() {
                     let entry = match entry {
@@ -196,14 +692,251 @@ impl Terminal {
                     };
                     let basename = entry.basename();
                     let extension = entry.extension();
-                    if extension.is_empty() {
-                        let _ = writeln!(self, "{}", ByteString(basename));
+                    if long {
+                        let modified = time::Rfc3339 {
+                            date_time: entry.modified(),
+                            micros: 0,
+                            offset,
+                        };
+                        let _ = write!(self, "{:>8} {} ", entry.file_size(), modified);
+                    }
+                    let (basename, extension) =
+                        (Jisx0201String(basename), Jisx0201String(extension));
+                    if extension.0.is_empty() {
+                        let _ = writeln!(self, "{}", basename);
                     } else {
-                        let _ =
-                            writeln!(self, "{}.{}", ByteString(basename), ByteString(extension));
+                        let _ = writeln!(self, "{}.{}", basename, extension);
                     }
                 }
             }
+            "shutdown" => match self.confirm_shutdown() {
+                Ok(true) => {
+                    if let Err(err) = shutdown::shutdown() {
+                        let _ = writeln!(self, "shutdown: {}", err);
+                    }
+                }
+                Ok(false) => {}
+                Err(err) => {
+                    let _ = writeln!(self, "shutdown: {}", err);
+                }
+            },
+            "uptime" => {
+                let uptime = time::uptime();
+                let secs = uptime.as_secs();
+                let _ = writeln!(
+                    self,
+                    "up {} days, {:02}:{:02}:{:02}.{:02}",
+                    secs / 86400,
+                    secs / 3600 % 24,
+                    secs / 60 % 60,
+                    secs % 60,
+                    uptime.subsec_millis() / 10
+                );
+                let _ = writeln!(self, "{} context switches", task::context_switches());
+                let _ = writeln!(self, "{} interrupts", interrupt::total_count());
+            }
+            #[cfg(feature = "acpi-s3")]
+            "suspend" => {
+                if let Err(err) = crate::suspend::suspend() {
+                    let _ = writeln!(self, "suspend: {}", err);
+                }
+            }
+            #[cfg(feature = "alloc-tracking")]
+            "leaks" => {
+                let sites = crate::allocator::tracking::live_sites();
+                for site in sites.iter().take(10) {
+                    let _ = write!(
+                        self,
+                        "{} bytes in {} allocs:",
+                        site.live_bytes, site.live_count
+                    );
+                    for addr in site.backtrace.iter().take_while(|addr| **addr != 0) {
+                        let _ = write!(self, " {:x}", addr);
+                    }
+                    let _ = writeln!(self);
+                }
+            }
+            "stress" => self.execute_stress(&command_line[1..]),
+            "memhog" => match command_line.get(1).map(|arg| arg.parse::<usize>()) {
+                Some(Ok(mib)) => {
+                    let report = stress::memhog(mib.saturating_mul(1024 * 1024));
+                    let _ = writeln!(
+                        self,
+                        "memhog: allocated {} MiB{}, freed",
+                        report.allocated / (1024 * 1024),
+                        if report.exhausted {
+                            " (heap exhausted)"
+                        } else {
+                            ""
+                        }
+                    );
+                }
+                _ => {
+                    let _ = writeln!(self, "usage: memhog <MiB>");
+                }
+            },
+            "mmap" => self.execute_mmap(),
+            "bench" => self.execute_bench(),
+            "memtest" => match command_line.get(1).map(|arg| arg.parse::<u64>()) {
+                Some(Ok(mib)) => self.execute_memtest(mib),
+                _ => {
+                    let _ = writeln!(self, "usage: memtest <MiB>");
+                }
+            },
+            "tcpdump" => self.execute_tcpdump(&command_line[1..]),
+            "ping" => match command_line.get(1).map(|arg| arg.parse::<Ipv4Addr>()) {
+                Some(Ok(addr)) => self.execute_ping(addr),
+                Some(Err(err)) => {
+                    let _ = writeln!(self, "ping: {}", err);
+                }
+                None => {
+                    let _ = writeln!(self, "usage: ping <addr>");
+                }
+            },
+            "wget" => match command_line.get(1) {
+                Some(url) => self.execute_wget(url, command_line.get(2).copied()),
+                None => {
+                    let _ = writeln!(self, "usage: wget <url> [file]");
+                }
+            },
+            "sleep" => match command_line.get(1).map(|arg| arg.parse::<u64>()) {
+                Some(Ok(ms)) => {
+                    if let Err(err) = task::sleep_for(Duration::from_millis(ms)) {
+                        let _ = writeln!(self, "sleep: {}", err);
+                    }
+                }
+                _ => {
+                    let _ = writeln!(self, "usage: sleep <ms>");
+                }
+            },
+            "beep" => {
+                let freq = command_line
+                    .get(1)
+                    .map_or(Ok(440), |arg| arg.parse::<u64>());
+                let ms = command_line
+                    .get(2)
+                    .map_or(Ok(200), |arg| arg.parse::<u64>());
+                match (freq, ms) {
+                    (Ok(freq), Ok(ms)) if freq > 0 => self.execute_beep(freq, ms),
+                    _ => {
+                        let _ = writeln!(self, "usage: beep [<Hz> [<ms>]]");
+                    }
+                }
+            }
+            "ps" => {
+                for info in task::list() {
+                    let _ = write!(self, "task {}: ", info.id);
+                    if info.level == task::REALTIME_LEVEL {
+                        let _ = write!(self, "realtime");
+                    } else {
+                        let _ = write!(self, "level {}", info.level);
+                    }
+                    let _ = write!(self, ", {} ms", info.cpu_time.as_millis());
+                    if let Some(rt) = info.realtime {
+                        let _ = write!(
+                            self,
+                            ", {}/{} deadline misses (worst {} us)",
+                            rt.deadline_misses,
+                            rt.activations,
+                            rt.worst_time.as_micros()
+                        );
+                    }
+                    if let (Some(size), Some(used)) = (info.stack_size, info.stack_used) {
+                        let _ = write!(self, ", stack {}/{}", used, size);
+                    }
+                    let _ = writeln!(self, "{}", if info.running { " (running)" } else { "" });
+                }
+            }
+            "lscotask" => {
+                for stats in co_task::stats() {
+                    let _ = writeln!(
+                        self,
+                        "task {} cotask {}: {} polls, {} us total, {} us max",
+                        stats.task_id,
+                        stats.co_task_id,
+                        stats.polls,
+                        stats.total_time.as_micros(),
+                        stats.max_time.as_micros()
+                    );
+                }
+            }
+            "lsirq" => {
+                let now = Instant::now().since_start();
+                for stats in interrupt::stats() {
+                    let _ = write!(
+                        self,
+                        "{:#04x} {:<19} {:>8}",
+                        stats.vector, stats.name, stats.count
+                    );
+                    match stats.last_seen {
+                        Some(last_seen) => {
+                            let ago = now.saturating_sub(last_seen);
+                            let _ = writeln!(self, ", {} ms ago", ago.as_millis());
+                        }
+                        None => {
+                            let _ = writeln!(self);
+                        }
+                    }
+                }
+                let lapic_errors = interrupt::lapic_errors();
+                if lapic_errors != 0 {
+                    let _ = writeln!(self, "LAPIC error status: {:#04x}", lapic_errors);
+                }
+            }
+            "lschan" => {
+                for stats in mpsc::stats() {
+                    let _ = write!(self, "{:<8} {:>5}", stats.name, stats.len);
+                    match stats.capacity {
+                        Some(capacity) => {
+                            let _ = write!(self, "/{:<5}", capacity);
+                        }
+                        None => {
+                            let _ = write!(self, "/-    ");
+                        }
+                    }
+                    let _ = writeln!(
+                        self,
+                        " max {}, {} full",
+                        stats.high_water_mark, stats.full_count
+                    );
+                }
+            }
+            "lsexec" => {
+                for handle in co_task::executors() {
+                    let _ = writeln!(self, "task {}: load {}", handle.task_id(), handle.load());
+                }
+            }
+            "idle" => match idle::stats() {
+                Some(stats) => {
+                    let uptime = Instant::now().since_start();
+                    let residency = if uptime.is_zero() {
+                        0.0
+                    } else {
+                        stats.idle_time.as_secs_f64() / uptime.as_secs_f64() * 100.0
+                    };
+                    let _ = writeln!(
+                        self,
+                        "{}: {} ms idle ({:.1}%), {} wakeups",
+                        stats.method,
+                        stats.idle_time.as_millis(),
+                        residency,
+                        stats.wakeups
+                    );
+                }
+                None => {
+                    let _ = writeln!(self, "idle: not started");
+                }
+            },
+            "timeslice" => self.execute_timeslice(&command_line[1..]),
+            "window" => self.execute_window(&command_line[1..]),
+            "dialog" => self.execute_dialog(&command_line[1..]),
+            "theme" => self.execute_theme(&command_line[1..]),
+            "spin" => match command_line.get(1).map(|arg| arg.parse::<u64>()) {
+                Some(Ok(ms)) => stress::spin(Duration::from_millis(ms)),
+                _ => {
+                    let _ = writeln!(self, "usage: spin <ms>");
+                }
+            },
             command => {
                 let _ = writeln!(self, "no such command: {}", command);
             }
@@ -211,6 +944,739 @@ impl Terminal {
         self.line_buf = line_buf;
     }
 
+    fn execute_mmap(&mut self) {
+        const MIB: u64 = 1024 * 1024;
+
+        let mut usable = 0;
+        let mut bootloader = 0;
+        let mut other = 0;
+        let mut holes = 0;
+        let mut prev_end = 0;
+        for region in memory::regions() {
+            if prev_end < region.start {
+                let _ = writeln!(self, "{:#012x}-{:#012x} hole", prev_end, region.start);
+                holes += region.start - prev_end;
+            }
+            prev_end = region.end;
+
+            let size = region.end - region.start;
+            let kind = match region.kind {
+                MemoryRegionKind::Usable => {
+                    usable += size;
+                    "usable"
+                }
+                MemoryRegionKind::Bootloader => {
+                    bootloader += size;
+                    "bootloader"
+                }
+                _ => {
+                    other += size;
+                    "reserved"
+                }
+            };
+            let _ = writeln!(
+                self,
+                "{:#012x}-{:#012x} {} ({:?})",
+                region.start, region.end, kind, region.kind
+            );
+        }
+
+        let reserved_frames: u64 = memory::reserved_ranges()
+            .iter()
+            .map(|range| range.end - range.start)
+            .sum();
+        let free_frames = memory::with_memory_manager(|allocator| allocator.count_free_frames());
+        let _ = writeln!(
+            self,
+            "usable {} MiB, bootloader {} MiB, reserved {} MiB, holes {} MiB",
+            usable / MIB,
+            bootloader / MIB,
+            other / MIB,
+            holes / MIB
+        );
+        let _ = writeln!(
+            self,
+            "reserved by allocator {} MiB, free {} MiB",
+            reserved_frames * memory::BYTES_PER_FRAME / MIB,
+            free_frames * memory::BYTES_PER_FRAME / MIB
+        );
+    }
+
+    fn execute_bench(&mut self) {
+        let (results, previous) = match bench::run() {
+            Ok(results) => results,
+            Err(err) => {
+                let _ = writeln!(self, "bench: {}", err);
+                return;
+            }
+        };
+        let _ = writeln!(
+            self,
+            "{:<16} {:>13} {:>13} {:>7}",
+            "", "now", "prev", "speed"
+        );
+        for (i, result) in results.iter().enumerate() {
+            let unit = match result.unit {
+                bench::Unit::Nanos => "ns",
+                bench::Unit::PerSecond => "/s",
+                bench::Unit::MiBPerSecond => "MiB/s",
+            };
+            let now = format!("{:.0} {}", result.value, unit);
+            let _ = write!(self, "{:<16} {:>13}", result.name, now);
+            match previous.as_ref().map(|previous| &previous[i]) {
+                Some(prev) => {
+                    let prev_value = format!("{:.0} {}", prev.value, unit);
+                    let _ = writeln!(self, " {:>13} {:>6.2}x", prev_value, result.speedup(prev));
+                }
+                None => {
+                    let _ = writeln!(self, " {:>13} {:>7}", "-", "-");
+                }
+            }
+        }
+    }
+
+    /// Plays a square wave of `freq` Hz for `ms` milliseconds.
+    fn execute_beep(&mut self, freq: u64, ms: u64) {
+        if !audio::is_streaming() {
+            let _ = writeln!(self, "beep: no audio device");
+            return;
+        }
+        let sample_rate = audio::SAMPLE_RATE as u64;
+        let frames = sample_rate * ms / 1000;
+        let half_period = u64::max(sample_rate / (freq * 2), 1);
+        let mut chunk = Vec::new();
+        for start in (0..frames).step_by(BEEP_CHUNK_FRAMES as usize) {
+            chunk.clear();
+            for frame in start..u64::min(start + BEEP_CHUNK_FRAMES, frames) {
+                let sample = if (frame / half_period) % 2 == 0 {
+                    BEEP_AMPLITUDE
+                } else {
+                    -BEEP_AMPLITUDE
+                };
+                chunk.extend(iter::repeat(sample).take(audio::CHANNELS));
+            }
+            let mut written = 0;
+            while written < chunk.len() {
+                written += audio::write(&chunk[written..]);
+                if written < chunk.len() {
+                    // wait for the device to play the buffered samples
+                    if let Err(err) = task::sleep_for(Duration::from_millis(10)) {
+                        let _ = writeln!(self, "beep: {}", err);
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    fn execute_memtest(&mut self, mib: u64) {
+        let report = memtest::run(mib.saturating_mul(1024 * 1024));
+        for mismatch in &report.mismatches {
+            let _ = writeln!(
+                self,
+                "memtest: mismatch at {:#x}: expected {:#018x}, got {:#018x}",
+                mismatch.addr.as_u64(),
+                mismatch.expected,
+                mismatch.actual
+            );
+        }
+        let _ = write!(
+            self,
+            "memtest: tested {} MiB, {} errors",
+            report.tested_bytes / (1024 * 1024),
+            report.mismatch_count
+        );
+        let elapsed_micros = report.elapsed.as_micros();
+        if elapsed_micros > 0 {
+            let bandwidth =
+                u128::from(report.transferred_bytes) * 1_000_000 / elapsed_micros / (1024 * 1024);
+            let _ = write!(self, ", {} MiB/s", bandwidth);
+        }
+        let _ = writeln!(self);
+    }
+
+    fn execute_lsusb(&mut self) {
+        let (max_ports, devices) = match xhc::devices() {
+            Some(devices) => devices,
+            None => {
+                let _ = writeln!(self, "lsusb: no USB host controller");
+                return;
+            }
+        };
+        let _ = writeln!(self, "xHC root hub: {} ports", max_ports);
+        for dev in devices {
+            let _ = write!(
+                self,
+                "  port {}: slot {}, {}",
+                dev.port, dev.slot_id, dev.speed
+            );
+            if let Some(desc) = dev.descriptor {
+                let _ = write!(
+                    self,
+                    ", ID {:04x}:{:04x}, class {:02x}:{:02x}:{:02x}, USB {:x}.{:02x}",
+                    desc.vendor_id,
+                    desc.product_id,
+                    desc.device_class,
+                    desc.device_sub_class,
+                    desc.device_protocol,
+                    desc.usb_release >> 8,
+                    desc.usb_release & 0xff,
+                );
+            }
+            if !dev.configured {
+                let _ = write!(self, " (not configured)");
+            }
+            let _ = writeln!(self);
+            for interface in dev.interfaces {
+                let desc = interface.descriptor;
+                let _ = write!(
+                    self,
+                    "    interface {}.{}: class {:02x}:{:02x}:{:02x}",
+                    desc.interface_number,
+                    desc.alternate_setting,
+                    desc.interface_class,
+                    desc.interface_sub_class,
+                    desc.interface_protocol,
+                );
+                if let Some(driver) = interface.driver {
+                    let _ = write!(self, " [{}]", driver);
+                }
+                let _ = writeln!(self);
+            }
+        }
+    }
+
+    fn execute_ifconfig(&mut self) {
+        for iface in net::interfaces() {
+            let state = if iface.link_up() { "UP" } else { "DOWN" };
+            let _ = write!(self, "{}: {}", iface.name, state);
+            match iface.link {
+                Link::Loopback => {
+                    let _ = writeln!(self, " loopback");
+                }
+                Link::Ethernet(mac) => {
+                    let _ = writeln!(self, " ether {}", mac);
+                }
+            }
+            let config = iface.config();
+            let _ = write!(self, "    inet {}/{}", config.addr, config.prefix_len);
+            if let Some(gateway) = config.gateway {
+                let _ = write!(self, " gateway {}", gateway);
+            }
+            let _ = writeln!(self);
+            let (packets, bytes) = iface.stats.rx();
+            let _ = writeln!(self, "    RX packets {} bytes {}", packets, bytes);
+            let (packets, bytes) = iface.stats.tx();
+            let _ = writeln!(self, "    TX packets {} bytes {}", packets, bytes);
+        }
+    }
+
+    fn execute_netstat(&mut self) {
+        let _ = writeln!(
+            self,
+            "{:<5} {:<21} {:<21} State",
+            "Proto", "Local Address", "Foreign Address"
+        );
+        for socket in tcp::sockets() {
+            let remote = socket
+                .remote
+                .map_or_else(|| String::from("*"), |remote| format!("{}", remote));
+            let _ = writeln!(
+                self,
+                "{:<5} {:<21} {:<21} {}",
+                "tcp",
+                format!("{}", socket.local),
+                remote,
+                socket.state
+            );
+        }
+        for port in udp::ports() {
+            let local = SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, port);
+            let _ = writeln!(self, "{:<5} {:<21} *", "udp", format!("{}", local));
+        }
+    }
+
+    fn execute_wget(&mut self, url: &str, file: Option<&str>) {
+        const TIMEOUT: Duration = Duration::from_secs(30);
+
+        let url = match http::Url::parse(url) {
+            Ok(url) => url,
+            Err(err) => {
+                let _ = writeln!(self, "wget: {}", err);
+                return;
+            }
+        };
+        let _ = writeln!(self, "Connecting to {}...", url.socket_addr());
+        let response =
+            match run_helper(async move { timer::timeout(TIMEOUT, http::get(&url)).await }) {
+                Ok(Ok(Ok(response))) => response,
+                Ok(Ok(Err(err))) | Err(err) => {
+                    let _ = writeln!(self, "wget: {}", err);
+                    return;
+                }
+                Ok(Err(_)) => {
+                    let _ = writeln!(self, "wget: timed out");
+                    return;
+                }
+            };
+        if !response.is_success() {
+            let _ = writeln!(
+                self,
+                "wget: server returned {} {}",
+                response.status, response.reason
+            );
+            return;
+        }
+
+        let file = match file {
+            Some(file) => file,
+            None => {
+                let _ = write!(self, "{}", String::from_utf8_lossy(&response.body));
+                return;
+            }
+        };
+        let mut fs = fat::lock();
+        match fat::write_file(&mut **fs, file, &response.body) {
+            Ok(()) => {
+                let _ = writeln!(self, "saved {} bytes to {}", response.body.len(), file);
+            }
+            Err(err) => {
+                let _ = writeln!(self, "wget: failed to save {}: {}", file, err);
+            }
+        }
+    }
+
+    fn execute_tcpdump(&mut self, args: &[&str]) {
+        const USAGE: &str = "usage: tcpdump [-i <interface>] [-c <count>] [-w <file>]";
+
+        let mut iface = None;
+        let mut count = None;
+        let mut file = None;
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match (*arg, args.next()) {
+                ("-i", Some(name)) => match net::interfaces().iter().find(|i| i.name == *name) {
+                    Some(found) => iface = Some(found.name),
+                    None => {
+                        let _ = writeln!(self, "tcpdump: no such interface: {}", name);
+                        return;
+                    }
+                },
+                ("-c", Some(value)) => match value.parse() {
+                    Ok(value) => count = Some(value),
+                    Err(_) => {
+                        let _ = writeln!(self, "{}", USAGE);
+                        return;
+                    }
+                },
+                ("-w", Some(name)) => file = Some(String::from(*name)),
+                _ => {
+                    let _ = writeln!(self, "{}", USAGE);
+                    return;
+                }
+            }
+        }
+        if self.window.is_none() {
+            count = count.or(Some(HEADLESS_CAPTURE_COUNT));
+        }
+        self.capture_job = Some(CaptureJob {
+            capture: Capture::open(iface),
+            iface,
+            count,
+            file,
+        });
+    }
+
+    /// Prints the summaries of captured packets, or writes them to a pcap file, until `count`
+    /// packets are captured or a key is pressed.
+    async fn run_capture(&mut self, job: CaptureJob) -> Result<()> {
+        let CaptureJob {
+            capture,
+            iface,
+            count,
+            file,
+        } = job;
+        let mut pcap = file.as_ref().map(|_| capture::pcap_header());
+        let _ = write!(self, "listening on {}", iface.unwrap_or("all interfaces"));
+        if self.window.is_some() {
+            let _ = write!(self, ", press any key to stop");
+        }
+        let _ = writeln!(self);
+        self.flush().await?;
+
+        let offset = time::utc_offset();
+        let wall_clock = time::wall_clock().is_some();
+        let mut captured = 0;
+        while count.map_or(true, |count| captured < count) {
+            let packet = select_biased! {
+                event = self.recv_event().fuse() => match event {
+                    Some(Ok(FramedWindowEvent::Keyboard(event))) if event.press => break,
+                    // the window stays open, and is closed by the next request
+                    Some(Ok(FramedWindowEvent::CloseRequested)) => break,
+                    Some(Ok(FramedWindowEvent::Redraw)) => {
+                        self.redraw();
+                        self.flush().await?;
+                        continue;
+                    }
+                    Some(Ok(FramedWindowEvent::Resized)) => {
+                        self.resize();
+                        self.flush().await?;
+                        continue;
+                    }
+                    Some(Ok(
+                        FramedWindowEvent::Mouse(_)
+                        | FramedWindowEvent::Keyboard(_)
+                        | FramedWindowEvent::Copy
+                        | FramedWindowEvent::Paste,
+                    )) => continue,
+                    None => break,
+                    Some(Err(err)) => return Err(err),
+                },
+                packet = capture.next().fuse() => packet,
+            };
+            captured += 1;
+            if let Some(pcap) = &mut pcap {
+                capture::write_pcap_record(pcap, &packet);
+                continue;
+            }
+
+            if wall_clock {
+                let time = time::Rfc3339::from_unix(packet.timestamp, offset);
+                let dt = time.date_time;
+                let _ = write!(
+                    self,
+                    "{:02}:{:02}:{:02}.{:06}",
+                    dt.hour, dt.minute, dt.second, time.micros
+                );
+            } else {
+                let _ = write!(
+                    self,
+                    "{}.{:06}",
+                    packet.timestamp.as_secs(),
+                    packet.timestamp.subsec_micros()
+                );
+            }
+            let direction = match packet.direction {
+                capture::Direction::In => "In",
+                capture::Direction::Out => "Out",
+            };
+            let _ = writeln!(
+                self,
+                " {} {:<3} {}",
+                packet.iface,
+                direction,
+                capture::summary(&packet)
+            );
+            self.flush().await?;
+        }
+
+        let _ = writeln!(
+            self,
+            "{} packets captured, {} dropped",
+            captured,
+            capture.dropped()
+        );
+        drop(capture);
+        if let (Some(file), Some(pcap)) = (file, pcap) {
+            let mut fs = fat::lock();
+            fat::write_file(&mut **fs, &file, &pcap)?;
+            let _ = writeln!(self, "wrote {} bytes to {}", pcap.len(), file);
+        }
+        Ok(())
+    }
+
+    fn execute_ping(&mut self, addr: Ipv4Addr) {
+        const COUNT: u16 = 4;
+        const DATA_LEN: usize = 56;
+        const TIMEOUT: Duration = Duration::from_secs(1);
+        const INTERVAL: Duration = Duration::from_secs(1);
+
+        let id = icmp::new_id();
+        let data = (0..DATA_LEN).map(|i| i as u8).collect::<Vec<_>>();
+        let _ = writeln!(self, "PING {}: {} data bytes", addr, DATA_LEN);
+        let mut transmitted = 0;
+        let mut rtts = Vec::new();
+        for seq in 0..COUNT {
+            if seq > 0 {
+                let _ = task::sleep_for(INTERVAL);
+            }
+            let sent = Instant::now();
+            let rx = match icmp::echo(addr, id, seq, &data) {
+                Ok(rx) => rx,
+                Err(err) => {
+                    let _ = writeln!(self, "ping: {}", err);
+                    continue;
+                }
+            };
+            transmitted += 1;
+            match task::block_on(timer::timeout(TIMEOUT, rx)) {
+                Ok(Ok(reply)) => {
+                    let rtt = reply.received.duration_since(sent);
+                    let _ = writeln!(
+                        self,
+                        "{} bytes from {}: icmp_seq={} ttl={} time={}.{:03} ms",
+                        reply.len,
+                        reply.src,
+                        seq,
+                        reply.ttl,
+                        rtt.as_micros() / 1000,
+                        rtt.as_micros() % 1000
+                    );
+                    rtts.push(rtt);
+                }
+                _ => {
+                    let _ = writeln!(self, "Request timeout for icmp_seq {}", seq);
+                }
+            }
+        }
+
+        let _ = writeln!(self, "--- {} ping statistics ---", addr);
+        let loss = if transmitted > 0 {
+            (transmitted - rtts.len()) * 100 / transmitted
+        } else {
+            100
+        };
+        let _ = writeln!(
+            self,
+            "{} packets transmitted, {} packets received, {}% packet loss",
+            transmitted,
+            rtts.len(),
+            loss
+        );
+        if let (Some(min), Some(max)) = (rtts.iter().min(), rtts.iter().max()) {
+            let avg = rtts.iter().sum::<Duration>() / rtts.len() as u32;
+            let ms = |d: &Duration| (d.as_micros() / 1000, d.as_micros() % 1000);
+            let (min, avg, max) = (ms(min), ms(&avg), ms(max));
+            let _ = writeln!(
+                self,
+                "round-trip min/avg/max = {}.{:03}/{}.{:03}/{}.{:03} ms",
+                min.0, min.1, avg.0, avg.1, max.0, max.1
+            );
+        }
+    }
+
+    fn execute_timeslice(&mut self, args: &[&str]) {
+        match args {
+            [] => {
+                for (level, ticks) in task::time_slices().iter().enumerate() {
+                    match ticks {
+                        Some(ticks) => {
+                            let _ = writeln!(self, "level {}: {} ticks", level, ticks);
+                        }
+                        None => {
+                            let _ = writeln!(self, "level {}: off", level);
+                        }
+                    }
+                }
+            }
+            [level, ticks] => {
+                let ticks = match *ticks {
+                    "off" => Ok(None),
+                    ticks => ticks.parse().map(Some),
+                };
+                match (level.parse(), ticks) {
+                    (Ok(level), Ok(ticks)) => {
+                        if let Err(err) = task::set_time_slice(level, ticks) {
+                            let _ = writeln!(self, "timeslice: {}", err);
+                        }
+                    }
+                    _ => {
+                        let _ = writeln!(self, "usage: timeslice [<level> <ticks|off>]");
+                    }
+                }
+            }
+            _ => {
+                let _ = writeln!(self, "usage: timeslice [<level> <ticks|off>]");
+            }
+        }
+    }
+
+    /// Changes the z-order or the visibility of the window of the terminal.
+    fn execute_window(&mut self, args: &[&str]) {
+        let window = match &self.window {
+            Some(window) => window,
+            None => {
+                let _ = writeln!(self, "window: no window");
+                return;
+            }
+        };
+        let res = match args {
+            ["raise"] => window.raise(),
+            ["lower"] => window.lower(),
+            ["top", "on"] => window.set_always_on_top(true),
+            ["top", "off"] => window.set_always_on_top(false),
+            ["hide", ms] => match ms.parse::<u64>() {
+                // the window is shown again after `ms` milliseconds
+                Ok(ms) => window.hide().and_then(|()| {
+                    task::sleep_for(Duration::from_millis(ms))?;
+                    window.show()
+                }),
+                Err(_) => {
+                    let _ = writeln!(self, "usage: window hide <ms>");
+                    return;
+                }
+            },
+            _ => {
+                let _ = writeln!(self, "usage: window <raise|lower|top <on|off>|hide <ms>>");
+                return;
+            }
+        };
+        if let Err(err) = res {
+            let _ = writeln!(self, "window: {}", err);
+        }
+    }
+
+    /// Asks the user to confirm shutting down in a dialog, which a headless terminal skips.
+    fn confirm_shutdown(&self) -> Result<bool> {
+        let owner = match &self.window {
+            Some(window) => window.layer_id(),
+            None => return Ok(true),
+        };
+        let rx = dialog::confirm(owner, "Shut down".into(), "Shut down the computer?")?;
+        Ok(task::block_on(rx).unwrap_or(false))
+    }
+
+    fn execute_dialog(&mut self, args: &[&str]) {
+        let owner = match &self.window {
+            Some(window) => window.layer_id(),
+            None => {
+                let _ = writeln!(self, "dialog: no window");
+                return;
+            }
+        };
+        let (kind, message) = match args {
+            [kind, message @ ..] if !message.is_empty() => (*kind, message.join(" ")),
+            _ => {
+                let _ = writeln!(self, "usage: dialog <message|confirm|prompt> <text>");
+                return;
+            }
+        };
+        // the terminal waits for the dialog, whose owner receives no input meanwhile
+        let title = String::from("Dialog");
+        let res = match kind {
+            "message" => dialog::message_box(owner, title, &message)
+                .map(|rx| task::block_on(rx).map(|()| String::from("ok"))),
+            "confirm" => dialog::confirm(owner, title, &message)
+                .map(|rx| task::block_on(rx).map(|ok| format!("{}", ok))),
+            "prompt" => dialog::prompt(owner, title, &message)
+                .map(|rx| task::block_on(rx).map(|text| format!("{:?}", text))),
+            _ => {
+                let _ = writeln!(self, "usage: dialog <message|confirm|prompt> <text>");
+                return;
+            }
+        };
+        match res {
+            Ok(Ok(result)) => {
+                let _ = writeln!(self, "{}", result);
+            }
+            Ok(Err(err)) => {
+                let _ = writeln!(self, "dialog: {}", err);
+            }
+            Err(err) => {
+                let _ = writeln!(self, "dialog: {}", err);
+            }
+        }
+    }
+
+    fn execute_theme(&mut self, args: &[&str]) {
+        match args {
+            [] => {
+                let current = theme::current();
+                for (name, theme) in Theme::PRESETS {
+                    let mark = if *theme == current { '*' } else { ' ' };
+                    let _ = writeln!(self, "{} {}", mark, name);
+                }
+            }
+            [name] => match Theme::preset(name) {
+                Some(theme) => {
+                    if let Err(err) = theme::set(theme) {
+                        let _ = writeln!(self, "theme: {}", err);
+                    }
+                }
+                None => {
+                    let _ = writeln!(self, "theme: unknown theme {}", name);
+                }
+            },
+            _ => {
+                let _ = writeln!(self, "usage: theme [<name>]");
+            }
+        }
+    }
+
+    fn execute_stress(&mut self, args: &[&str]) {
+        match args {
+            [] => {
+                let workers = stress::workers();
+                if workers.is_empty() {
+                    let _ = writeln!(self, "stress: no busy tasks");
+                }
+                for (task_id, iterations) in workers {
+                    let _ = writeln!(self, "task {}: {} iterations", task_id, iterations);
+                }
+            }
+            ["stop"] => {
+                let count = stress::stop_workers();
+                let _ = writeln!(self, "stress: stopped {} busy tasks", count);
+            }
+            ["redraw", _] if self.window.is_none() => {
+                let _ = writeln!(self, "stress: redraw needs a window");
+            }
+            ["redraw", frames] => match frames.parse() {
+                Ok(frames) => self.redraw_frames = frames,
+                Err(_) => {
+                    let _ = writeln!(self, "usage: stress redraw <frames>");
+                }
+            },
+            [count] => match count.parse() {
+                Ok(count) => {
+                    stress::spawn_workers(count);
+                    let _ = writeln!(self, "stress: spawned {} busy tasks", count);
+                }
+                Err(_) => {
+                    let _ = writeln!(self, "usage: stress [<tasks> | stop | redraw <frames>]");
+                }
+            },
+            _ => {
+                let _ = writeln!(self, "usage: stress [<tasks> | stop | redraw <frames>]");
+            }
+        }
+    }
+
+    /// Repaints the whole window `frames` times, waiting for the compositor each time.
+    async fn flood_redraw(&mut self, frames: usize) -> Result<()> {
+        let start = Instant::now();
+        for frame in 0..frames {
+            let color = if frame % 2 == 0 { self.fg } else { self.bg };
+            if let Some(window) = &mut self.window {
+                let area = window.area();
+                window.fill_rect(area, color);
+            }
+            self.flush().await?;
+        }
+        let elapsed = start.elapsed();
+
+        // restore the screen contents
+        self.draw_terminal();
+        for y in 0..self.text_size.y {
+            for x in 0..self.text_size.x {
+                self.draw_cell(Point::new(x, y));
+            }
+        }
+        let fps = (frames as u128 * 1_000_000)
+            .checked_div(elapsed.as_micros())
+            .unwrap_or(0);
+        let _ = writeln!(
+            self,
+            "stress: {} frames in {} ms ({} fps)",
+            frames,
+            elapsed.as_millis(),
+            fps
+        );
+        self.print_prompt();
+        self.draw_cursor(true);
+        Ok(())
+    }
+
     fn push_history(&mut self) {
         while self.history.len() > HISTORY_LEN - 1 {
             self.history.pop_back();
@@ -249,7 +1715,9 @@ impl Terminal {
 
     fn handle_event(&mut self, event: FramedWindowEvent) {
         match event {
+            FramedWindowEvent::Keyboard(event) if !event.press => {}
             FramedWindowEvent::Keyboard(event) => {
+                self.scroll_view(-(self.scroll_offset as isize));
                 self.draw_cursor(false);
                 match event.ascii {
                     '\0' if event.keycode == 0x51 => {
@@ -269,7 +1737,10 @@ impl Terminal {
                         {
                             self.push_history();
                         }
-                        self.print_prompt();
+                        // the prompt is printed after the redraw flood or the capture finishes
+                        if self.redraw_frames == 0 && self.capture_job.is_none() {
+                            self.print_prompt();
+                        }
                     }
                     '\x08' => {
                         if self.line_buf.pop().is_some() {
@@ -283,6 +1754,28 @@ impl Terminal {
                 }
                 self.draw_cursor(true);
             }
+            FramedWindowEvent::Mouse(event) => self.handle_mouse_event(event),
+            FramedWindowEvent::Copy => {
+                if let Some(text) = self.selected_text() {
+                    clipboard::set_text(text);
+                }
+            }
+            FramedWindowEvent::Paste => {
+                self.scroll_view(-(self.scroll_offset as isize));
+                self.draw_cursor(false);
+                // only the first line is pasted, so that no command is executed by pasting
+                let text = clipboard::text().unwrap_or_default();
+                for ch in text.chars().take_while(|ch| *ch != '\n') {
+                    if !ch.is_control() {
+                        self.line_buf.push(ch);
+                        self.print_char(ch);
+                    }
+                }
+                self.draw_cursor(true);
+            }
+            FramedWindowEvent::Redraw => self.redraw(),
+            FramedWindowEvent::Resized => self.resize(),
+            FramedWindowEvent::CloseRequested => {}
         }
     }
 
@@ -291,20 +1784,53 @@ impl Terminal {
         self.draw_cursor(self.cursor_visible);
     }
 
+    async fn flush(&mut self) -> Result<()> {
+        if let Some(window) = &mut self.window {
+            window.flush().await?;
+        }
+        Ok(())
+    }
+
+    /// Receives an event of the window. This never resolves for a headless terminal.
+    async fn recv_event(&mut self) -> Option<Result<FramedWindowEvent>> {
+        match &mut self.window {
+            Some(window) => window.recv_event().await,
+            None => future::pending().await,
+        }
+    }
+
+    /// Runs the terminal on its window. This returns immediately for a headless terminal.
     pub(crate) async fn run(mut self) -> Result<()> {
+        if self.window.is_none() {
+            return Ok(());
+        }
         self.draw_terminal();
         self.print_prompt();
-        self.window.flush().await?;
+        self.flush().await?;
 
         let mut interval = timer::lapic::interval(0, 50)?;
         loop {
             select_biased! {
-                event = self.window.recv_event().fuse() => {
+                event = self.recv_event().fuse() => {
                     let event = match event {
                         Some(event) => event?,
                         None => return Ok(()),
                     };
+                    if let FramedWindowEvent::CloseRequested = event {
+                        return Ok(());
+                    }
                     self.handle_event(event);
+                    let frames = mem::take(&mut self.redraw_frames);
+                    if frames > 0 {
+                        self.flood_redraw(frames).await?;
+                    }
+                    if let Some(job) = self.capture_job.take() {
+                        if let Err(err) = self.run_capture(job).await {
+                            let _ = writeln!(self, "tcpdump: {}", err);
+                        }
+                        self.print_prompt();
+                        self.draw_cursor(true);
+                    }
                 }
                 timeout = interval.next().fuse() => {
                     let _timeout = match timeout {
@@ -314,7 +1840,7 @@ impl Terminal {
                     self.handle_timeout();
                 }
             }
-            self.window.flush().await?;
+            self.flush().await?;
         }
     }
 }
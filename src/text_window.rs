@@ -1,102 +1,83 @@
 use crate::{
-    framed_window::{FramedWindow, FramedWindowEvent},
-    graphics::{font, Color, Draw, Point, Rectangle, Size},
+    graphics::Point,
     prelude::*,
     timer,
+    ui::{Button, Checkbox, Label, Layout, TextBox, Ui, UiEvent, Widget, WidgetId},
 };
-use alloc::string::String;
+use alloc::{string::String, vec};
 use futures_util::select_biased;
 
-const BACKGROUND: Color = Color::WHITE;
-const BORDER_DARK: Color = Color::from_code(0x848484);
-const BORDER_LIGHT: Color = Color::from_code(0xc6c6c6);
-
 #[derive(Debug)]
 pub(crate) struct TextWindow {
-    window: FramedWindow,
-    index: i32,
-    max_chars: i32,
-    cursor_visible: bool,
+    ui: Ui,
+    text_box: WidgetId,
+    log: WidgetId,
+    clear: WidgetId,
 }
 
 impl TextWindow {
     pub(crate) fn new(title: String, pos: Point<i32>) -> Result<Self> {
-        let font_size = font::FONT_PIXEL_SIZE;
-        let window_size = Size::new(160, font_size.y + 8);
-        let window = FramedWindow::builder(title)
-            .size(window_size)
-            .pos(pos)
-            .build()?;
+        let mut builder = Ui::builder(title).pos(pos);
+        let label = builder.add(Label::new("Type and press Enter".into()));
+        let text_box = builder.add(TextBox::new(18));
+        let log = builder.add(Checkbox::new("Log".into(), true));
+        let clear = builder.add(Button::new("Clear".into()));
+        let buttons = builder.add(Layout::horizontal(vec![log, clear]));
+        let root = builder.add(Layout::vertical(vec![label, text_box, buttons]));
+        let ui = builder.build(root)?;
         Ok(Self {
-            window,
-            index: 0,
-            max_chars: (window_size.x - 8) / font_size.x - 1,
-            cursor_visible: true,
+            ui,
+            text_box,
+            log,
+            clear,
         })
     }
 
-    fn insert_pos(&self) -> Point<i32> {
-        let font_size = font::FONT_PIXEL_SIZE;
-        Point::new(4 + font_size.x * self.index, 6)
-    }
-
-    fn draw_text_box(&mut self) {
-        let area = self.window.area();
-        self.window
-            .draw_box(area, BACKGROUND, BORDER_DARK, BORDER_LIGHT);
-    }
-
-    fn draw_cursor(&mut self, visible: bool) {
-        let font_size = font::FONT_PIXEL_SIZE;
-        let color = if visible { Color::BLACK } else { Color::WHITE };
-        let pos = self.insert_pos();
-        self.window
-            .fill_rect(Rectangle::new(pos, font_size - Size::new(1, 1)), color);
+    fn text_box(&mut self) -> Option<&mut TextBox> {
+        match self.ui.widget_mut(self.text_box)? {
+            Widget::TextBox(text_box) => Some(text_box),
+            _ => None,
+        }
     }
 
-    fn handle_event(&mut self, event: FramedWindowEvent) {
+    fn handle_event(&mut self, event: UiEvent) {
         match event {
-            FramedWindowEvent::Keyboard(event) => {
-                if event.ascii == '\0' {
-                    return;
+            // Enter logs the text if the checkbox is checked, and clears the text box
+            UiEvent::Submitted(id) if id == self.text_box => {
+                let log = matches!(
+                    self.ui.widget(self.log),
+                    Some(Widget::Checkbox(checkbox)) if checkbox.checked()
+                );
+                if let Some(text_box) = self.text_box() {
+                    if log {
+                        info!("text box: {}", text_box.text());
+                    }
+                    text_box.clear();
                 }
-
-                if event.ascii == '\x08' && self.index > 0 {
-                    self.draw_cursor(false);
-                    self.index -= 1;
-                    self.window.fill_rect(
-                        Rectangle::new(self.insert_pos(), Size::new(8, 16)),
-                        Color::WHITE,
-                    );
-                    self.draw_cursor(self.cursor_visible);
-                } else if event.ascii >= ' ' && self.index < self.max_chars {
-                    self.draw_cursor(false);
-                    let pos = self.insert_pos();
-                    self.window.draw_char(pos, event.ascii, Color::BLACK);
-                    self.index += 1;
-                    self.draw_cursor(self.cursor_visible);
+            }
+            UiEvent::Clicked(id) if id == self.clear => {
+                if let Some(text_box) = self.text_box() {
+                    text_box.clear();
                 }
             }
+            _ => {}
         }
     }
 
-    fn handle_timeout(&mut self) {
-        self.cursor_visible = !self.cursor_visible;
-        self.draw_cursor(self.cursor_visible);
-    }
-
     pub(crate) async fn run(mut self) -> Result<()> {
-        self.draw_text_box();
-        self.window.flush().await?;
+        self.ui.flush().await?;
 
         let mut interval = timer::lapic::interval(0, 50)?;
         loop {
             select_biased! {
-                event = self.window.recv_event().fuse() => {
+                event = self.ui.recv_event().fuse() => {
                     let event = match event {
                         Some(event) => event?,
                         None => return Ok(()),
                     };
+                    if let UiEvent::CloseRequested = event {
+                        return Ok(());
+                    }
                     self.handle_event(event);
                 }
                 timeout = interval.next().fuse() => {
@@ -104,10 +85,10 @@ impl TextWindow {
                         Some(event) => event?,
                         _ => return Ok(()),
                     };
-                    self.handle_timeout();
+                    self.ui.blink_cursor();
                 }
             }
-            self.window.flush().await?;
+            self.ui.flush().await?;
         }
     }
 }
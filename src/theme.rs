@@ -0,0 +1,199 @@
+//! Colors of the desktop, the taskbar and the windows.
+//!
+//! The theme is loaded from the configuration file, which selects a preset by `preset = <name>`
+//! and overrides its colors by lines of `<name> = #rrggbb`. The `theme` command of the terminal
+//! switches to another preset at runtime, and all windows are requested to redraw themselves.
+
+use crate::{console, fat, graphics::Color, layer, prelude::*, sync::SpinMutex};
+use core::str;
+use x86_64::instructions::interrupts;
+
+/// The file in the root directory of the file system which selects the theme.
+const THEME_CONFIG_FILE: &str = "theme.cfg";
+
+static THEME: SpinMutex<Theme> = SpinMutex::new(Theme::LIGHT);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Theme {
+    pub(crate) desktop_bg: Color,
+    pub(crate) desktop_fg: Color,
+    pub(crate) taskbar_bg: Color,
+    pub(crate) start_button: Color,
+    pub(crate) taskbar_item: Color,
+    pub(crate) taskbar_item_active: Color,
+    /// The color of the faces of window frames and widgets.
+    pub(crate) face: Color,
+    /// The color of the lit edges of 3D borders.
+    pub(crate) highlight: Color,
+    /// The color of the shaded edges of 3D borders.
+    pub(crate) shadow: Color,
+    /// The color of the outer shaded edges of window frames.
+    pub(crate) dark_shadow: Color,
+    pub(crate) title_active: Color,
+    pub(crate) title_inactive: Color,
+    pub(crate) title_text: Color,
+    /// The color of the text of widgets.
+    pub(crate) text: Color,
+    /// The background color of text boxes.
+    pub(crate) input_bg: Color,
+    pub(crate) terminal_fg: Color,
+    pub(crate) terminal_bg: Color,
+}
+
+impl Theme {
+    pub(crate) const LIGHT: Self = Self {
+        desktop_bg: Color::new(45, 118, 237),
+        desktop_fg: Color::WHITE,
+        taskbar_bg: Color::new(1, 8, 17),
+        start_button: Color::new(80, 80, 80),
+        taskbar_item: Color::new(40, 40, 48),
+        taskbar_item_active: Color::new(70, 70, 84),
+        face: Color::from_code(0xc6c6c6),
+        highlight: Color::WHITE,
+        shadow: Color::from_code(0x848484),
+        dark_shadow: Color::BLACK,
+        title_active: Color::from_code(0x000084),
+        title_inactive: Color::from_code(0x848484),
+        title_text: Color::WHITE,
+        text: Color::BLACK,
+        input_bg: Color::WHITE,
+        terminal_fg: Color::WHITE,
+        terminal_bg: Color::BLACK,
+    };
+
+    pub(crate) const DARK: Self = Self {
+        desktop_bg: Color::from_code(0x1d2733),
+        desktop_fg: Color::from_code(0xd8dee9),
+        taskbar_bg: Color::from_code(0x0b0f14),
+        start_button: Color::from_code(0x3b4252),
+        taskbar_item: Color::from_code(0x1f242d),
+        taskbar_item_active: Color::from_code(0x3b4252),
+        face: Color::from_code(0x3b4252),
+        highlight: Color::from_code(0x5c677d),
+        shadow: Color::from_code(0x232831),
+        dark_shadow: Color::from_code(0x0b0f14),
+        title_active: Color::from_code(0x4c6a92),
+        title_inactive: Color::from_code(0x2e3440),
+        title_text: Color::from_code(0xeceff4),
+        text: Color::from_code(0xeceff4),
+        input_bg: Color::from_code(0x2e3440),
+        terminal_fg: Color::from_code(0xd8dee9),
+        terminal_bg: Color::from_code(0x11151c),
+    };
+
+    pub(crate) const PRESETS: &'static [(&'static str, Self)] =
+        &[("light", Self::LIGHT), ("dark", Self::DARK)];
+
+    pub(crate) fn preset(name: &str) -> Option<Self> {
+        Self::PRESETS
+            .iter()
+            .find(|(preset, _)| *preset == name)
+            .map(|(_, theme)| *theme)
+    }
+
+    fn color_mut(&mut self, name: &str) -> Option<&mut Color> {
+        let color = match name {
+            "desktop_bg" => &mut self.desktop_bg,
+            "desktop_fg" => &mut self.desktop_fg,
+            "taskbar_bg" => &mut self.taskbar_bg,
+            "start_button" => &mut self.start_button,
+            "taskbar_item" => &mut self.taskbar_item,
+            "taskbar_item_active" => &mut self.taskbar_item_active,
+            "face" => &mut self.face,
+            "highlight" => &mut self.highlight,
+            "shadow" => &mut self.shadow,
+            "dark_shadow" => &mut self.dark_shadow,
+            "title_active" => &mut self.title_active,
+            "title_inactive" => &mut self.title_inactive,
+            "title_text" => &mut self.title_text,
+            "text" => &mut self.text,
+            "input_bg" => &mut self.input_bg,
+            "terminal_fg" => &mut self.terminal_fg,
+            "terminal_bg" => &mut self.terminal_bg,
+            _ => return None,
+        };
+        Some(color)
+    }
+
+    /// Parses the configuration, skipping `#` comment lines. The colors are overridden in the
+    /// order of the lines, so they follow the preset.
+    fn parse(config: &str) -> Option<Self> {
+        let mut theme = Self::LIGHT;
+        for line in config.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (name, value) = line.split_once('=')?;
+            let (name, value) = (name.trim(), value.trim());
+            if name == "preset" {
+                theme = Self::preset(value)?;
+                continue;
+            }
+            let code = value.strip_prefix('#')?;
+            if code.len() != 6 {
+                return None;
+            }
+            *theme.color_mut(name)? = Color::from_code(u32::from_str_radix(code, 16).ok()?);
+        }
+        Some(theme)
+    }
+}
+
+pub(crate) fn current() -> Theme {
+    interrupts::without_interrupts(|| *THEME.lock())
+}
+
+/// Loads the theme selected by the configuration file.
+///
+/// This must be called after the file system is initialized, and before any window is created.
+/// The light theme is used if the file is missing or invalid.
+pub(crate) fn init() {
+    let fs = fat::lock();
+    let data = match fat::find_file(&**fs, THEME_CONFIG_FILE).and_then(|e| fat::read_file(&**fs, e))
+    {
+        Ok(data) => data,
+        Err(err) => {
+            info!("theme: {} is not loaded: {}", THEME_CONFIG_FILE, err);
+            return;
+        }
+    };
+    match str::from_utf8(&data).ok().and_then(Theme::parse) {
+        Some(theme) => {
+            interrupts::without_interrupts(|| *THEME.lock() = theme);
+            if let Err(err) = console::set_colors(theme.desktop_fg, theme.desktop_bg) {
+                warn!("theme: failed to redraw the console: {}", err);
+            }
+        }
+        None => warn!("theme: invalid {}", THEME_CONFIG_FILE),
+    }
+}
+
+/// Changes the theme, and requests all windows to redraw themselves.
+pub(crate) fn set(theme: Theme) -> Result<()> {
+    interrupts::without_interrupts(|| *THEME.lock() = theme);
+    console::set_colors(theme.desktop_fg, theme.desktop_bg)?;
+    layer::event_tx().redraw_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn parse() {
+        assert_eq!(Theme::parse("# comment\n\n"), Some(Theme::LIGHT));
+        assert_eq!(Theme::parse("preset = dark"), Some(Theme::DARK));
+
+        let theme = Theme::parse("preset = dark\ndesktop_bg = #102030\n");
+        let expected = Theme {
+            desktop_bg: Color::new(0x10, 0x20, 0x30),
+            ..Theme::DARK
+        };
+        assert_eq!(theme, Some(expected));
+
+        assert_eq!(Theme::parse("preset = blue"), None);
+        assert_eq!(Theme::parse("text = #12345"), None);
+        assert_eq!(Theme::parse("unknown = #123456"), None);
+        assert_eq!(Theme::parse("text #123456"), None);
+    }
+}
@@ -0,0 +1,397 @@
+//! Monotonic clock.
+//!
+//! The clock counts the invariant TSC (detected with CPUID), which runs at a constant rate
+//! regardless of the power state. Its frequency is calibrated against the ACPI PM timer at boot.
+//! If the TSC is missing or not invariant, the PM timer itself backs the clock at a coarser
+//! resolution, and without the PM timer the LAPIC timer ticks are counted instead, at the tick
+//! resolution.
+//!
+//! The wall clock is kept as the offset of the Unix time from the monotonic clock, which is
+//! seeded by [`rtc`](crate::rtc). The local time is the wall clock shifted by the UTC offset
+//! configured in the file system.
+
+use crate::{
+    cpu, fat,
+    pm_timer::{self, PmTimer},
+    prelude::*,
+    rtc::DateTime,
+    sync::OnceCell,
+    timer,
+};
+use core::{
+    arch::x86_64::_rdtsc,
+    convert::TryFrom,
+    fmt,
+    ops::{Add, Sub},
+    str,
+    sync::atomic::{AtomicI16, AtomicU64, Ordering},
+    time::Duration,
+};
+
+const CALIBRATION_MILLISECONDS: u32 = 50;
+const NANOS_PER_SECOND: u128 = 1_000_000_000;
+
+/// A free-running counter which can back the clock.
+pub(crate) trait TimerSource: Sync {
+    fn name(&self) -> &'static str;
+
+    /// Returns the frequency of the counter in Hz.
+    fn frequency(&self) -> u64;
+
+    /// Reads the counter, which never wraps around.
+    fn read(&self) -> u64;
+}
+
+#[derive(Debug)]
+struct Tsc {
+    frequency: u64,
+}
+
+impl TimerSource for Tsc {
+    fn name(&self) -> &'static str {
+        "invariant TSC"
+    }
+
+    fn frequency(&self) -> u64 {
+        self.frequency
+    }
+
+    fn read(&self) -> u64 {
+        unsafe { _rdtsc() }
+    }
+}
+
+/// The timer interrupts counted by the LAPIC timer handler.
+#[derive(Debug)]
+struct LapicTick;
+
+impl TimerSource for LapicTick {
+    fn name(&self) -> &'static str {
+        "LAPIC timer"
+    }
+
+    fn frequency(&self) -> u64 {
+        timer::lapic::TICKS_PER_SECOND
+    }
+
+    fn read(&self) -> u64 {
+        timer::lapic::current_tick()
+    }
+}
+
+struct Clock {
+    source: &'static dyn TimerSource,
+    start: u64,
+}
+
+static TSC: OnceCell<Tsc> = OnceCell::uninit();
+static LAPIC_TICK: LapicTick = LapicTick;
+static CLOCK: OnceCell<Clock> = OnceCell::uninit();
+/// Unix time in nanoseconds at the clock initialization, or zero if the wall clock is not set.
+static WALL_CLOCK_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+/// Measures the TSC frequency against the PM timer.
+fn calibrate_tsc(pm_timer: &PmTimer) -> u64 {
+    let pm_start = pm_timer.read();
+    let tsc_start = unsafe { _rdtsc() };
+    pm_timer::wait_milliseconds(CALIBRATION_MILLISECONDS);
+    let pm_end = pm_timer.read();
+    let tsc_end = unsafe { _rdtsc() };
+    let frequency = u128::from(tsc_end - tsc_start) * u128::from(pm_timer.frequency())
+        / u128::from(pm_end - pm_start);
+    u64::try_from(frequency).unwrap_or(u64::MAX)
+}
+
+/// Selects and calibrates the clock source.
+///
+/// This must be called after the PM timer is initialized. [`read_tsc_ns`] and [`Instant::now`]
+/// return zero until then.
+pub(crate) fn init() {
+    let features = cpu::features();
+    let pm_timer = pm_timer::get();
+    if features.tsc && !features.invariant_tsc {
+        warn!("TSC is not invariant and is not used as the clock source");
+    }
+
+    let source: &'static dyn TimerSource = match pm_timer {
+        Some(pm_timer) if features.tsc && features.invariant_tsc => {
+            let frequency = calibrate_tsc(pm_timer);
+            TSC.init_once(|| Tsc { frequency });
+            TSC.get()
+        }
+        Some(pm_timer) => pm_timer,
+        None => &LAPIC_TICK,
+    };
+    info!(
+        "clock source: {} ({} kHz)",
+        source.name(),
+        source.frequency() / 1000
+    );
+    let start = source.read();
+    CLOCK.init_once(|| Clock { source, start });
+}
+
+/// Returns the calibrated TSC frequency in Hz, or `None` if the TSC is not the clock source.
+pub(crate) fn tsc_frequency() -> Option<u64> {
+    TSC.try_get().ok().map(|tsc| tsc.frequency)
+}
+
+/// Returns the nanoseconds elapsed from the clock initialization.
+///
+/// This is the raw reading behind [`Instant::now`], for code which stores timestamps in atomics.
+/// The resolution is a nanosecond with TSC, and coarser with the other clock sources.
+pub(crate) fn read_tsc_ns() -> u64 {
+    let clock = match CLOCK.try_get() {
+        Ok(clock) => clock,
+        Err(_) => return 0,
+    };
+    let count = clock.source.read().saturating_sub(clock.start);
+    let nanos = u128::from(count) * NANOS_PER_SECOND / u128::from(clock.source.frequency());
+    u64::try_from(nanos).unwrap_or(u64::MAX)
+}
+
+/// Sets the wall clock to `since_epoch`, the time elapsed from the Unix epoch.
+pub(crate) fn set_wall_clock(since_epoch: Duration) {
+    let now = u64::try_from(since_epoch.as_nanos()).unwrap_or(u64::MAX);
+    let offset = now.saturating_sub(read_tsc_ns()).max(1);
+    WALL_CLOCK_OFFSET.store(offset, Ordering::Relaxed);
+}
+
+/// Returns the time elapsed from the Unix epoch, or `None` if the wall clock is not set.
+pub(crate) fn wall_clock() -> Option<Duration> {
+    match WALL_CLOCK_OFFSET.load(Ordering::Relaxed) {
+        0 => None,
+        offset => Some(Duration::from_nanos(offset.saturating_add(read_tsc_ns()))),
+    }
+}
+
+/// An offset of the local time from UTC.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct UtcOffset {
+    minutes: i16,
+}
+
+impl UtcOffset {
+    pub(crate) const UTC: Self = Self { minutes: 0 };
+
+    /// Returns `None` if the offset is not within a day.
+    pub(crate) fn from_minutes(minutes: i16) -> Option<Self> {
+        (minutes.abs() < 24 * 60).then(|| Self { minutes })
+    }
+
+    /// Parses `Z`, `UTC`, or a signed `hh:mm` optionally prefixed with `UTC`.
+    pub(crate) fn parse(s: &str) -> Option<Self> {
+        let s = s.trim();
+        if s == "Z" || s == "UTC" {
+            return Some(Self::UTC);
+        }
+        let s = s.strip_prefix("UTC").unwrap_or(s);
+        let (sign, s) = match s.as_bytes().first()? {
+            b'+' => (1, &s[1..]),
+            b'-' => (-1, &s[1..]),
+            _ => return None,
+        };
+        let (hours, minutes) = s.split_once(':')?;
+        let is_field = |s: &str| s.len() == 2 && s.bytes().all(|b| b.is_ascii_digit());
+        if !is_field(hours) || !is_field(minutes) {
+            return None;
+        }
+        let hours = hours.parse::<i16>().ok()?;
+        let minutes = minutes.parse::<i16>().ok()?;
+        if minutes >= 60 {
+            return None;
+        }
+        Self::from_minutes(sign * (hours * 60 + minutes))
+    }
+
+    fn as_secs(self) -> i64 {
+        i64::from(self.minutes) * 60
+    }
+}
+
+impl fmt::Display for UtcOffset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.minutes < 0 { '-' } else { '+' };
+        let minutes = self.minutes.abs();
+        write!(f, "{}{:02}:{:02}", sign, minutes / 60, minutes % 60)
+    }
+}
+
+static UTC_OFFSET_MINUTES: AtomicI16 = AtomicI16::new(0);
+
+/// The file in the root directory of the file system which holds the UTC offset.
+const TIMEZONE_FILE: &str = "tz.cfg";
+
+/// Loads the UTC offset from the configuration file, skipping `#` comment lines.
+///
+/// This must be called after the file system is initialized. The local time is UTC if the file
+/// is missing or invalid.
+pub(crate) fn init_timezone() {
+    let fs = fat::lock();
+    let data = match fat::find_file(&**fs, TIMEZONE_FILE).and_then(|e| fat::read_file(&**fs, e)) {
+        Ok(data) => data,
+        Err(err) => {
+            info!("timezone: {} is not loaded: {}", TIMEZONE_FILE, err);
+            return;
+        }
+    };
+    let line = str::from_utf8(&data).ok().and_then(|s| {
+        s.lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty() && !line.starts_with('#'))
+    });
+    match line.and_then(UtcOffset::parse) {
+        Some(offset) => {
+            set_utc_offset(offset);
+            info!("timezone: UTC{}", offset);
+        }
+        None => warn!("timezone: invalid {}", TIMEZONE_FILE),
+    }
+}
+
+pub(crate) fn set_utc_offset(offset: UtcOffset) {
+    UTC_OFFSET_MINUTES.store(offset.minutes, Ordering::Relaxed);
+}
+
+pub(crate) fn utc_offset() -> UtcOffset {
+    UtcOffset {
+        minutes: UTC_OFFSET_MINUTES.load(Ordering::Relaxed),
+    }
+}
+
+/// A local date and time with its UTC offset, formatted as in RFC 3339.
+///
+/// The alternate format (`{:#}`) includes microseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Rfc3339 {
+    pub(crate) date_time: DateTime,
+    pub(crate) micros: u32,
+    pub(crate) offset: UtcOffset,
+}
+
+impl Rfc3339 {
+    /// Converts the time elapsed from the Unix epoch to the local time of `offset`.
+    pub(crate) fn from_unix(since_epoch: Duration, offset: UtcOffset) -> Self {
+        let secs = i64::try_from(since_epoch.as_secs()).unwrap_or(i64::MAX);
+        let local = u64::try_from(secs.saturating_add(offset.as_secs())).unwrap_or(0);
+        Self {
+            date_time: DateTime::from_unix(Duration::from_secs(local)),
+            micros: since_epoch.subsec_micros(),
+            offset,
+        }
+    }
+}
+
+impl fmt::Display for Rfc3339 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let dt = &self.date_time;
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+            dt.year, dt.month, dt.day, dt.hour, dt.minute, dt.second
+        )?;
+        if f.alternate() {
+            write!(f, ".{:06}", self.micros)?;
+        }
+        write!(f, "{}", self.offset)
+    }
+}
+
+/// Returns the current local time, or `None` if the wall clock is not set.
+pub(crate) fn local_now() -> Option<Rfc3339> {
+    wall_clock().map(|now| Rfc3339::from_unix(now, utc_offset()))
+}
+
+/// Returns the time since the timer was started at boot.
+///
+/// Unlike [`Instant`], this is kept by the timer ticks, and matches the tick-based timeouts.
+pub(crate) fn uptime() -> Duration {
+    timer::lapic::uptime()
+}
+
+/// A point of the monotonic clock, measured from the clock initialization.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(crate) struct Instant {
+    nanos: u64,
+}
+
+impl Instant {
+    pub(crate) fn now() -> Self {
+        Self {
+            nanos: read_tsc_ns(),
+        }
+    }
+
+    /// Returns the time elapsed from `earlier`, or zero if `earlier` is later than `self`.
+    pub(crate) fn duration_since(&self, earlier: Instant) -> Duration {
+        Duration::from_nanos(self.nanos.saturating_sub(earlier.nanos))
+    }
+
+    pub(crate) fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+
+    /// Returns the time from the clock initialization.
+    pub(crate) fn since_start(&self) -> Duration {
+        Duration::from_nanos(self.nanos)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, rhs: Duration) -> Self::Output {
+        let nanos = u64::try_from(rhs.as_nanos()).unwrap_or(u64::MAX);
+        Self {
+            nanos: self.nanos.saturating_add(nanos),
+        }
+    }
+}
+
+impl Sub for Instant {
+    type Output = Duration;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        self.duration_since(rhs)
+    }
+}
+
+impl fmt::Display for Instant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let since_start = self.since_start();
+        write!(
+            f,
+            "{:4}.{:06}",
+            since_start.as_secs(),
+            since_start.subsec_micros()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::format;
+
+    #[test_case]
+    fn parse_utc_offset() {
+        assert_eq!(UtcOffset::parse("Z"), Some(UtcOffset::UTC));
+        assert_eq!(UtcOffset::parse("+09:00"), UtcOffset::from_minutes(540));
+        assert_eq!(UtcOffset::parse("UTC-03:30"), UtcOffset::from_minutes(-210));
+        assert_eq!(UtcOffset::parse("09:00"), None);
+        assert_eq!(UtcOffset::parse("+9:00"), None);
+        assert_eq!(UtcOffset::parse("+-1:30"), None);
+        assert_eq!(UtcOffset::parse("+09:+1"), None);
+        assert_eq!(UtcOffset::parse("+:"), None);
+        assert_eq!(UtcOffset::parse("+24:00"), None);
+    }
+
+    #[test_case]
+    fn format_rfc3339() {
+        #[allow(clippy::unwrap_used)]
+        let offset = UtcOffset::from_minutes(-210).unwrap();
+        let time = Rfc3339::from_unix(Duration::from_secs(1_709_210_096), offset);
+        assert_eq!(format!("{}", time), "2024-02-29T09:04:56-03:30");
+        assert_eq!(format!("{:#}", time), "2024-02-29T09:04:56.000000-03:30");
+    }
+}
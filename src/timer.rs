@@ -1,61 +1,172 @@
+pub(crate) use self::timeout::*;
+
+mod timeout;
+mod wheel;
+
 pub(crate) mod lapic {
+    use super::wheel::TimerWheel;
     use crate::{
-        acpi,
+        cpu, device,
         interrupt::{self, InterruptContextGuard, InterruptIndex},
+        local_apic::{self, TimerMode},
+        pm_timer,
         prelude::*,
-        sync::{mpsc, oneshot, OnceCell},
-        task,
+        sync::{mpsc, oneshot, OnceCell, SpinMutex, WaitQueue},
+        task, time,
+        watchdog::{self, Heartbeat},
     };
-    use alloc::collections::BinaryHeap;
     use core::{
-        cmp,
+        arch::x86_64::_rdtsc,
+        convert::TryFrom,
         pin::Pin,
-        sync::atomic::{AtomicU64, Ordering},
+        sync::atomic::{self, AtomicU64, Ordering},
         task::{Context, Poll},
+        time::Duration,
     };
-    use futures_util::{select_biased, task::AtomicWaker, Future, Stream};
+    use futures_util::{select_biased, Future, Stream};
     use volatile::Volatile;
-    use x86_64::structures::idt::InterruptStackFrame;
+    use x86_64::{
+        instructions::interrupts, registers::model_specific::Msr,
+        structures::idt::InterruptStackFrame,
+    };
 
     const COUNT_MAX: u32 = u32::MAX;
+    const IA32_TSC_DEADLINE: u32 = 0x6e0;
+
+    /// The number of timer ticks per second (the interval set in `init` is 10 ms).
+    pub(crate) const TICKS_PER_SECOND: u64 = 100;
+
+    /// How the timer interrupts are generated.
+    #[derive(Debug, Clone, Copy)]
+    enum Mode {
+        /// An interrupt on every tick.
+        Periodic,
+        /// A one-shot interrupt at the next tick where a timer expires, the scheduler needs to
+        /// preempt the current task, or the watchdog checks heartbeats.
+        ///
+        /// Ticks are counted in TSC cycles from `start`, so skipped ticks are caught up at the
+        /// next interrupt.
+        TscDeadline { start: u64, cycles_per_tick: u64 },
+    }
+
+    static MODE: OnceCell<Mode> = OnceCell::uninit();
 
+    #[cfg(feature = "acpi-s3")]
     fn lvt_timer() -> Volatile<&'static mut u32> {
-        #[allow(clippy::unwrap_used)]
-        unsafe {
-            Volatile::new((0xfee00320u64 as *mut u32).as_mut().unwrap())
-        }
+        local_apic::registers().lvt_timer()
     }
     fn initial_count() -> Volatile<&'static mut u32> {
-        #[allow(clippy::unwrap_used)]
-        unsafe {
-            Volatile::new((0xfee00380u64 as *mut u32).as_mut().unwrap())
-        }
+        local_apic::registers().initial_count()
     }
     fn current_count() -> Volatile<&'static mut u32> {
-        #[allow(clippy::unwrap_used)]
-        unsafe {
-            Volatile::new((0xfee00390u64 as *mut u32).as_mut().unwrap())
-        }
+        local_apic::registers().current_count()
     }
     fn divide_config() -> Volatile<&'static mut u32> {
-        #[allow(clippy::unwrap_used)]
-        unsafe {
-            Volatile::new((0xfee003e0u64 as *mut u32).as_mut().unwrap())
+        local_apic::registers().divide_config()
+    }
+
+    #[cfg(feature = "acpi-s3")]
+    fn spurious_interrupt_vector() -> Volatile<&'static mut u32> {
+        local_apic::registers().spurious_interrupt_vector()
+    }
+
+    /// LAPIC registers lost while the CPU is powered off.
+    #[cfg(feature = "acpi-s3")]
+    #[derive(Debug, Clone, Copy)]
+    pub(crate) struct SavedState {
+        spurious_interrupt_vector: u32,
+        lvt_timer: u32,
+        divide_config: u32,
+        initial_count: u32,
+    }
+
+    #[cfg(feature = "acpi-s3")]
+    pub(crate) fn save_state() -> SavedState {
+        SavedState {
+            spurious_interrupt_vector: spurious_interrupt_vector().read(),
+            lvt_timer: lvt_timer().read(),
+            divide_config: divide_config().read(),
+            initial_count: initial_count().read(),
         }
     }
 
+    #[cfg(feature = "acpi-s3")]
+    pub(crate) fn restore_state(state: &SavedState) {
+        spurious_interrupt_vector().write(state.spurious_interrupt_vector);
+        divide_config().write(state.divide_config);
+        lvt_timer().write(state.lvt_timer);
+        match MODE.try_get() {
+            Ok(Mode::TscDeadline { .. }) => {
+                atomic::fence(Ordering::SeqCst);
+                arm(current_tick() + 1);
+            }
+            // writing initial count starts the timer
+            _ => initial_count().write(state.initial_count),
+        }
+    }
+
+    /// Starts the timer, in TSC-deadline mode if supported.
+    ///
+    /// This must be called after the clock is initialized, so that the TSC frequency is known.
     pub(crate) fn init() {
+        let tsc_frequency = time::tsc_frequency().filter(|_| cpu::features().tsc_deadline);
+        if let Some(tsc_frequency) = tsc_frequency {
+            let start = unsafe { _rdtsc() };
+            MODE.init_once(|| Mode::TscDeadline {
+                start,
+                cycles_per_tick: tsc_frequency / TICKS_PER_SECOND,
+            });
+            divide_config().write(0b1011); // divide 1:1
+            local_apic::registers().set_timer(
+                TimerMode::TscDeadline,
+                InterruptIndex::Timer.as_u8(),
+                false,
+            );
+            // the LVT write must be ordered before the deadline MSR write
+            atomic::fence(Ordering::SeqCst);
+            arm(1);
+            check_timer(TimerMode::TscDeadline);
+            device::register_platform("LAPIC timer (TSC-deadline)", "timer");
+            info!("LAPIC timer: TSC-deadline mode");
+            return;
+        }
+
         divide_config().write(0b1011); // divide 1:1
-        lvt_timer().write(0b001 << 16); // masked, one-shot
+        local_apic::registers().set_timer(TimerMode::OneShot, 0, true);
 
         start();
-        acpi::wait_milliseconds(100);
+        pm_timer::wait_milliseconds(100);
         let elapsed = elapsed();
         stop();
 
+        MODE.init_once(|| Mode::Periodic);
         divide_config().write(0b1011); // divide 1:1
-        lvt_timer().write((0b010 << 16) | (InterruptIndex::Timer as u32)); // not-masked, periodic
+        local_apic::registers().set_timer(
+            TimerMode::Periodic,
+            InterruptIndex::Timer.as_u8(),
+            false,
+        );
         initial_count().write(((elapsed as f64) / 10.0) as u32); // interval : 10 ms
+        check_timer(TimerMode::Periodic);
+        device::register_platform("LAPIC timer (periodic)", "timer");
+        info!("LAPIC timer: periodic mode");
+    }
+
+    /// Checks that the timer is programmed as `init` intended, since a masked or misrouted timer
+    /// silently stops the scheduler.
+    fn check_timer(mode: TimerMode) {
+        let vector = InterruptIndex::Timer.as_u8();
+        match local_apic::registers().timer() {
+            Some(entry) if entry == (mode, vector, false) => {}
+            entry => warn!("LAPIC timer: unexpected LVT entry {:?}", entry),
+        }
+        if mode == TimerMode::Periodic {
+            let count = current_count().read();
+            pm_timer::wait_milliseconds(1);
+            if current_count().read() == count {
+                warn!("LAPIC timer: counter is not running");
+            }
+        }
     }
 
     fn start() {
@@ -70,13 +181,102 @@ pub(crate) mod lapic {
         initial_count().write(0);
     }
 
+    /// Returns the number of timer ticks since the timer was started.
+    pub(crate) fn current_tick() -> u64 {
+        match MODE.try_get() {
+            Ok(Mode::TscDeadline {
+                start,
+                cycles_per_tick,
+            }) => unsafe { _rdtsc() }.saturating_sub(*start) / cycles_per_tick,
+            _ => TICK.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Programs the timer interrupt at `tick` in TSC-deadline mode.
+    fn arm(tick: u64) {
+        if let Ok(Mode::TscDeadline {
+            start,
+            cycles_per_tick,
+        }) = MODE.try_get()
+        {
+            ARMED_TICK.store(tick, Ordering::Relaxed);
+            let deadline = start.saturating_add(tick.saturating_mul(*cycles_per_tick));
+            unsafe { Msr::new(IA32_TSC_DEADLINE).write(deadline) };
+        }
+    }
+
+    /// Makes sure that the timer interrupt occurs at `tick` or earlier.
+    ///
+    /// This does nothing in periodic mode, where every tick is interrupted.
+    fn request_tick_at(tick: u64) {
+        if let Ok(Mode::TscDeadline { .. }) = MODE.try_get() {
+            interrupts::without_interrupts(|| {
+                let tick = tick.max(current_tick() + 1);
+                if tick < ARMED_TICK.load(Ordering::Relaxed) {
+                    arm(tick);
+                }
+            });
+        }
+    }
+
+    /// Makes sure that the next tick is interrupted, for the scheduler to preempt the current
+    /// task.
+    pub(crate) fn request_tick() {
+        request_tick_at(0);
+    }
+
     pub(crate) fn oneshot(timeout: u64) -> Result<oneshot::Receiver<u64>> {
         let (tx, rx) = oneshot::channel();
         let timer = Timer { timeout, tx };
-        TIMER_TX.get().send(timer)?;
+        timer_channel().0.try_send(timer)?;
         Ok(rx)
     }
 
+    /// Returns the time since the timer was started.
+    ///
+    /// Whole ticks are counted by the timer, and the time into the current tick is interpolated
+    /// with the clock.
+    pub(crate) fn uptime() -> Duration {
+        let nanos_per_tick = 1_000_000_000 / TICKS_PER_SECOND;
+        let (tick, into_tick) = match MODE.try_get() {
+            Ok(Mode::TscDeadline {
+                start,
+                cycles_per_tick,
+            }) => {
+                let cycles = unsafe { _rdtsc() }.saturating_sub(*start);
+                let into_tick = u128::from(cycles % cycles_per_tick) * u128::from(nanos_per_tick)
+                    / u128::from(*cycles_per_tick);
+                (cycles / cycles_per_tick, into_tick as u64)
+            }
+            _ => {
+                let tick = TICK.load(Ordering::Relaxed);
+                let tick_time = TICK_TIME.load(Ordering::Relaxed);
+                (tick, time::read_tsc_ns().saturating_sub(tick_time))
+            }
+        };
+        let nanos = tick
+            .saturating_mul(nanos_per_tick)
+            .saturating_add(into_tick.min(nanos_per_tick - 1));
+        // a tick handled between the two loads above may make the reading go backwards
+        let nanos = LAST_UPTIME.fetch_max(nanos, Ordering::Relaxed).max(nanos);
+        Duration::from_nanos(nanos)
+    }
+
+    /// Converts `duration` to the number of ticks, rounding up.
+    pub(crate) fn duration_to_ticks(duration: Duration) -> u64 {
+        let nanos_per_tick = 1_000_000_000 / TICKS_PER_SECOND;
+        let nanos = duration.as_nanos();
+        let ticks = (nanos + u128::from(nanos_per_tick) - 1) / u128::from(nanos_per_tick);
+        u64::try_from(ticks).unwrap_or(u64::MAX)
+    }
+
+    /// Waits until `duration` elapses.
+    pub(crate) async fn sleep(duration: Duration) -> Result<()> {
+        let deadline = current_tick().saturating_add(duration_to_ticks(duration));
+        oneshot(deadline)?.await?;
+        Ok(())
+    }
+
     #[derive(Debug)]
     pub(crate) struct Interval {
         interval: u64,
@@ -96,13 +296,14 @@ pub(crate) mod lapic {
                     self.next = Some(next);
                     Poll::Pending
                 }
-                Poll::Ready(timeout) => match oneshot(timeout + self.interval) {
+                Poll::Ready(Ok(timeout)) => match oneshot(timeout + self.interval) {
                     Ok(next) => {
                         self.next = Some(next);
                         Poll::Ready(Some(Ok(timeout)))
                     }
                     Err(err) => Poll::Ready(Some(Err(err))),
                 },
+                Poll::Ready(Err(err)) => Poll::Ready(Some(Err(err.into()))),
             }
         }
     }
@@ -121,67 +322,66 @@ pub(crate) mod lapic {
         tx: oneshot::Sender<u64>,
     }
 
-    impl PartialEq for Timer {
-        fn eq(&self, other: &Self) -> bool {
-            self.timeout == other.timeout
-        }
-    }
-
-    impl Eq for Timer {}
-
-    impl PartialOrd for Timer {
-        fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
-            Some(self.cmp(other))
-        }
-    }
-
-    impl Ord for Timer {
-        fn cmp(&self, other: &Self) -> cmp::Ordering {
-            self.timeout.cmp(&other.timeout).reverse()
-        }
-    }
-
     #[derive(Debug)]
     struct TimerManager {
         tick: u64,
-        timers: BinaryHeap<Timer>,
+        timers: TimerWheel<oneshot::Sender<u64>>,
     }
 
     impl TimerManager {
         fn new() -> Self {
             Self {
                 tick: 0,
-                timers: BinaryHeap::new(),
+                timers: TimerWheel::new(0),
             }
         }
 
         fn register(&mut self, timer: Timer) {
-            self.timers.push(timer);
-            self.fire_timers();
+            if timer.timeout <= self.tick {
+                // the receiver may have given up waiting
+                let _ = timer.tx.send(timer.timeout);
+                return;
+            }
+            self.timers.insert(timer.timeout, timer.tx);
         }
 
         fn tick(&mut self, count: u64) {
             self.tick += count;
-            self.fire_timers();
-        }
-
-        fn fire_timers(&mut self) {
-            while let Some(timer) = self.timers.peek() {
-                if timer.timeout > self.tick {
-                    break;
-                }
-                #[allow(clippy::unwrap_used)]
-                let timer = self.timers.pop().unwrap();
-                timer.tx.send(timer.timeout);
-            }
+            self.timers.advance(self.tick, |timeout, tx| {
+                let _ = tx.send(timeout);
+            });
         }
     }
 
-    static INTERRUPTED_COUNT: AtomicU64 = AtomicU64::new(0);
-    static TOTAL_INTERRUPTED_COUNT: AtomicU64 = AtomicU64::new(0);
-    static WAKER: AtomicWaker = AtomicWaker::new();
-    static TIMER_TX: OnceCell<mpsc::Sender<Timer>> = OnceCell::uninit();
+    /// Ticks passed to the timer handler task.
+    static PENDING_TICKS: AtomicU64 = AtomicU64::new(0);
+    /// The last tick handled by the interrupt handler.
+    static TICK: AtomicU64 = AtomicU64::new(0);
+    /// The clock reading when `TICK` was last advanced in periodic mode.
+    static TICK_TIME: AtomicU64 = AtomicU64::new(0);
+    /// The last value returned by [`uptime`].
+    static LAST_UPTIME: AtomicU64 = AtomicU64::new(0);
+    /// The tick where the next interrupt is programmed in TSC-deadline mode.
+    static ARMED_TICK: AtomicU64 = AtomicU64::new(u64::MAX);
+    /// The earliest deadline of the registered timers.
+    static NEXT_TIMER_TICK: AtomicU64 = AtomicU64::new(u64::MAX);
+    static WAIT_QUEUE: WaitQueue = WaitQueue::new();
+    type TimerChannel = (
+        mpsc::Sender<Timer>,
+        SpinMutex<Option<mpsc::Receiver<Timer>>>,
+    );
+
+    /// Created on first use. The receiver is taken by [`handler_task`].
+    static TIMER_CHANNEL: OnceCell<TimerChannel> = OnceCell::uninit();
+    static HEARTBEAT: Heartbeat = Heartbeat::new("timer");
+
+    fn take_pending_ticks() -> Option<u64> {
+        let count = PENDING_TICKS.swap(0, Ordering::Relaxed);
+        (count > 0).then(|| count)
+    }
 
+    /// Yields the number of ticks passed since the last item, which the interrupt handler
+    /// signals through [`WAIT_QUEUE`].
     #[derive(Debug)]
     struct InterruptStream {
         _private: (),
@@ -197,55 +397,84 @@ pub(crate) mod lapic {
         type Item = u64;
 
         fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-            // fast path
-            let count = INTERRUPTED_COUNT.swap(0, Ordering::Relaxed);
-            if count > 0 {
-                return Poll::Ready(Some(count));
-            }
+            WAIT_QUEUE.poll_until(cx, take_pending_ticks).map(Some)
+        }
+    }
 
-            WAKER.register(cx.waker());
-            let count = INTERRUPTED_COUNT.swap(0, Ordering::Relaxed);
-            if count > 0 {
-                WAKER.take();
-                Poll::Ready(Some(count))
-            } else {
-                Poll::Pending
-            }
+    /// Returns the tick where the next interrupt is needed in TSC-deadline mode.
+    fn next_interrupt_tick(now: u64) -> u64 {
+        if task::has_waiting_tasks() {
+            return now + 1;
         }
+        let next_watchdog_check = (now / TICKS_PER_SECOND + 1) * TICKS_PER_SECOND;
+        NEXT_TIMER_TICK
+            .load(Ordering::Relaxed)
+            .min(next_watchdog_check)
+            .max(now + 1)
     }
 
     pub(crate) extern "x86-interrupt" fn interrupt_handler(_stack_frame: InterruptStackFrame) {
+        interrupt::record(InterruptIndex::Timer.as_u8());
         let guard = InterruptContextGuard::new();
-        INTERRUPTED_COUNT.fetch_add(1, Ordering::Relaxed);
-        let current_count = TOTAL_INTERRUPTED_COUNT.fetch_add(1, Ordering::Relaxed);
-        WAKER.wake();
+        let mode = MODE.try_get();
+        let (previous, now) = match mode {
+            Ok(Mode::TscDeadline { .. }) => {
+                let now = current_tick();
+                (TICK.swap(now, Ordering::Relaxed), now)
+            }
+            _ => {
+                TICK_TIME.store(time::read_tsc_ns(), Ordering::Relaxed);
+                let previous = TICK.fetch_add(1, Ordering::Relaxed);
+                (previous, previous + 1)
+            }
+        };
+        if now > previous {
+            PENDING_TICKS.fetch_add(now - previous, Ordering::Relaxed);
+            HEARTBEAT.expect();
+            WAIT_QUEUE.notify_all();
+        }
         interrupt::notify_end_of_interrupt();
 
-        if current_count % 2 == 0 {
-            task::on_interrupt(guard);
+        if now / TICKS_PER_SECOND != previous / TICKS_PER_SECOND {
+            watchdog::check();
         }
+        if let Ok(Mode::TscDeadline { .. }) = mode {
+            arm(next_interrupt_tick(now));
+        }
+
+        task::on_interrupt(guard);
     }
 
-    pub(crate) fn handler_task() -> impl Future<Output = ()> {
-        // Initialize TIMER_TX before co-task starts
-        let (tx, mut rx) = mpsc::channel(100);
-        TIMER_TX.init_once(|| tx);
+    fn timer_channel() -> &'static TimerChannel {
+        TIMER_CHANNEL.get_or_init(|| {
+            let (tx, rx) = mpsc::unbounded_channel();
+            tx.register("timer");
+            (tx, SpinMutex::new(Some(rx)))
+        })
+    }
 
-        async move {
-            let mut timer_manager = TimerManager::new();
-            let mut interrupts = InterruptStream::new();
-            loop {
-                select_biased! {
-                    count = interrupts.next().fuse() => {
-                        #[allow(clippy::unwrap_used)]
-                        timer_manager.tick(count.unwrap());
-                    },
-                    timer = rx.next().fuse() => {
-                        #[allow(clippy::unwrap_used)]
-                        timer_manager.register(timer.unwrap());
-                    }
+    pub(crate) async fn handler_task() {
+        #[allow(clippy::expect_used)]
+        let mut rx = interrupts::without_interrupts(|| timer_channel().1.lock().take())
+            .expect("timer handler task started twice");
+        watchdog::register(&HEARTBEAT);
+        let mut timer_manager = TimerManager::new();
+        let mut interrupts = InterruptStream::new();
+        loop {
+            select_biased! {
+                count = interrupts.next().fuse() => {
+                    HEARTBEAT.beat();
+                    #[allow(clippy::unwrap_used)]
+                    timer_manager.tick(count.unwrap());
+                },
+                timer = rx.next().fuse() => {
+                    #[allow(clippy::unwrap_used)]
+                    timer_manager.register(timer.unwrap());
                 }
             }
+            let next = timer_manager.timers.next_deadline().unwrap_or(u64::MAX);
+            NEXT_TIMER_TICK.store(next, Ordering::Relaxed);
+            request_tick_at(next);
         }
     }
 }
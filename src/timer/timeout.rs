@@ -0,0 +1,95 @@
+use super::lapic;
+use crate::{prelude::*, sync::oneshot};
+use core::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+use pin_project::pin_project;
+
+/// Error returned by [`Timeout`] when the deadline passed before the future completed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Elapsed;
+
+impl fmt::Display for Elapsed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "deadline has elapsed")
+    }
+}
+
+/// A point in time measured in LAPIC timer ticks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct Deadline {
+    tick: u64,
+}
+
+impl Deadline {
+    /// Returns the deadline `duration` after now.
+    pub(crate) fn after(duration: Duration) -> Self {
+        let tick = lapic::current_tick().saturating_add(lapic::duration_to_ticks(duration));
+        Self { tick }
+    }
+
+    /// Wraps `future` so that it resolves to `Err(Elapsed)` when this deadline passes.
+    pub(crate) fn timeout<F>(self, future: F) -> Timeout<F>
+    where
+        F: Future,
+    {
+        Timeout {
+            future,
+            deadline: self,
+            timer: None,
+        }
+    }
+}
+
+/// Wraps `future` so that it resolves to `Err(Elapsed)` if it does not complete within
+/// `duration`.
+pub(crate) fn timeout<F>(duration: Duration, future: F) -> Timeout<F>
+where
+    F: Future,
+{
+    Deadline::after(duration).timeout(future)
+}
+
+#[pin_project]
+#[derive(Debug)]
+pub(crate) struct Timeout<F> {
+    #[pin]
+    future: F,
+    deadline: Deadline,
+    // registered on the first poll
+    timer: Option<oneshot::Receiver<u64>>,
+}
+
+impl<F> Future for Timeout<F>
+where
+    F: Future,
+{
+    type Output = core::result::Result<F::Output, Elapsed>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        if let Poll::Ready(output) = this.future.poll(cx) {
+            return Poll::Ready(Ok(output));
+        }
+
+        let timer = match this.timer {
+            Some(timer) => timer,
+            None => match lapic::oneshot(this.deadline.tick) {
+                Ok(timer) => this.timer.insert(timer),
+                Err(err) => {
+                    // the future would never be woken up on expiry
+                    warn!("failed to register timeout: {}", err);
+                    return Poll::Ready(Err(Elapsed));
+                }
+            },
+        };
+        match Pin::new(timer).poll(cx) {
+            Poll::Ready(_) => Poll::Ready(Err(Elapsed)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
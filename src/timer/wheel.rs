@@ -0,0 +1,202 @@
+//! Hierarchical timer wheel.
+//!
+//! Level `n` has [`SLOTS`] slots each covering `SLOTS^n` ticks. A timer is put in the lowest
+//! level whose range covers its deadline, and moved down to lower levels as time advances,
+//! so that both insertion and expiration take constant time per timer.
+
+use alloc::vec::Vec;
+use core::mem;
+
+const LEVELS: usize = 4;
+const SLOT_BITS: u32 = 6;
+const SLOTS: usize = 1 << SLOT_BITS;
+const SLOT_MASK: u64 = (SLOTS - 1) as u64;
+/// Ticks covered by all levels. Timers beyond this are moved around the top level until
+/// their deadlines come into range.
+const MAX_DELTA: u64 = 1 << (SLOT_BITS * LEVELS as u32);
+
+#[derive(Debug)]
+struct Entry<T> {
+    deadline: u64,
+    value: T,
+}
+
+#[derive(Debug)]
+pub(crate) struct TimerWheel<T> {
+    now: u64,
+    len: usize,
+    // `slots[level * SLOTS + index]`
+    slots: Vec<Vec<Entry<T>>>,
+}
+
+impl<T> TimerWheel<T> {
+    pub(crate) fn new(now: u64) -> Self {
+        let mut slots = Vec::with_capacity(LEVELS * SLOTS);
+        slots.resize_with(LEVELS * SLOTS, Vec::new);
+        Self { now, len: 0, slots }
+    }
+
+    /// Returns the current tick, which is the last tick passed to [`Self::advance`].
+    #[cfg(test)]
+    pub(crate) fn now(&self) -> u64 {
+        self.now
+    }
+
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns the earliest deadline of the timers, if any.
+    pub(crate) fn next_deadline(&self) -> Option<u64> {
+        if self.len == 0 {
+            return None;
+        }
+        self.slots
+            .iter()
+            .flatten()
+            .map(|entry| entry.deadline)
+            .min()
+    }
+
+    /// Adds a timer expiring at `deadline`.
+    ///
+    /// Timers whose deadline already passed expire on the next tick.
+    pub(crate) fn insert(&mut self, deadline: u64, value: T) {
+        self.len += 1;
+        self.insert_entry(Entry { deadline, value });
+    }
+
+    fn insert_entry(&mut self, entry: Entry<T>) {
+        let deadline = u64::max(entry.deadline, self.now + 1);
+        let delta = u64::min(deadline - self.now, MAX_DELTA - 1);
+        let level = (0..LEVELS)
+            .find(|level| delta < 1 << (SLOT_BITS * (*level as u32 + 1)))
+            .unwrap_or(LEVELS - 1);
+        let index = ((self.now + delta) >> (SLOT_BITS * level as u32)) & SLOT_MASK;
+        self.slots[level * SLOTS + index as usize].push(entry);
+    }
+
+    /// Advances the wheel to `now`, calling `f` for each expired timer with its deadline.
+    pub(crate) fn advance(&mut self, now: u64, mut f: impl FnMut(u64, T)) {
+        while self.now < now {
+            if self.len == 0 {
+                self.now = now;
+                break;
+            }
+            self.now += 1;
+
+            // move timers in the slots reached at this tick down to the lower levels
+            for level in (1..LEVELS).rev() {
+                let shift = SLOT_BITS * level as u32;
+                if self.now & ((1 << shift) - 1) != 0 {
+                    continue;
+                }
+                let index = (self.now >> shift) & SLOT_MASK;
+                let entries = mem::take(&mut self.slots[level * SLOTS + index as usize]);
+                for entry in entries {
+                    // timers due at this tick expire now, as `insert_entry` would delay them
+                    if entry.deadline <= self.now {
+                        self.len -= 1;
+                        f(entry.deadline, entry.value);
+                    } else {
+                        self.insert_entry(entry);
+                    }
+                }
+            }
+
+            let index = self.now & SLOT_MASK;
+            let entries = mem::take(&mut self.slots[index as usize]);
+            for entry in entries {
+                self.len -= 1;
+                f(entry.deadline, entry.value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    fn advance(wheel: &mut TimerWheel<u32>, now: u64) -> Vec<(u64, u32)> {
+        let mut expired = vec![];
+        wheel.advance(now, |deadline, value| expired.push((deadline, value)));
+        expired
+    }
+
+    #[test_case]
+    fn expire_in_order() {
+        let mut wheel = TimerWheel::new(0);
+        wheel.insert(3, 3);
+        wheel.insert(1, 1);
+        wheel.insert(2, 2);
+        assert_eq!(wheel.len(), 3);
+
+        assert_eq!(advance(&mut wheel, 1), vec![(1, 1)]);
+        assert_eq!(advance(&mut wheel, 3), vec![(2, 2), (3, 3)]);
+        assert_eq!(wheel.len(), 0);
+    }
+
+    #[test_case]
+    fn expire_at_level_boundaries() {
+        let mut wheel = TimerWheel::new(0);
+        wheel.insert(128, 128);
+        wheel.insert(64, 64);
+        wheel.insert(4096, 4096);
+
+        assert_eq!(advance(&mut wheel, 63), vec![]);
+        assert_eq!(advance(&mut wheel, 64), vec![(64, 64)]);
+        assert_eq!(advance(&mut wheel, 127), vec![]);
+        assert_eq!(advance(&mut wheel, 128), vec![(128, 128)]);
+        assert_eq!(advance(&mut wheel, 4095), vec![]);
+        assert_eq!(advance(&mut wheel, 4096), vec![(4096, 4096)]);
+        assert_eq!(wheel.len(), 0);
+    }
+
+    #[test_case]
+    fn expired_deadline() {
+        let mut wheel = TimerWheel::new(10);
+        wheel.insert(5, 5);
+        wheel.insert(10, 10);
+        assert_eq!(advance(&mut wheel, 11), vec![(5, 5), (10, 10)]);
+    }
+
+    #[test_case]
+    fn cascade() {
+        let mut wheel = TimerWheel::new(100);
+        let deadlines = [164, 200, 4196, 300_000, 100 + MAX_DELTA + 5];
+        for (i, deadline) in deadlines.iter().enumerate() {
+            wheel.insert(*deadline, i as u32);
+        }
+
+        for (i, deadline) in deadlines.iter().enumerate() {
+            assert_eq!(advance(&mut wheel, deadline - 1), vec![]);
+            assert_eq!(advance(&mut wheel, *deadline), vec![(*deadline, i as u32)]);
+        }
+        assert_eq!(wheel.len(), 0);
+    }
+
+    #[test_case]
+    fn next_deadline() {
+        let mut wheel = TimerWheel::new(0);
+        assert_eq!(wheel.next_deadline(), None);
+        wheel.insert(5000, 1);
+        wheel.insert(70, 2);
+        assert_eq!(wheel.next_deadline(), Some(70));
+        assert_eq!(advance(&mut wheel, 70), vec![(70, 2)]);
+        assert_eq!(wheel.next_deadline(), Some(5000));
+    }
+
+    #[test_case]
+    fn idle_jump() {
+        let mut wheel = TimerWheel::new(0);
+        assert_eq!(advance(&mut wheel, 1_000_000), vec![]);
+        assert_eq!(wheel.now(), 1_000_000);
+
+        wheel.insert(1_000_070, 1);
+        assert_eq!(advance(&mut wheel, 1_000_069), vec![]);
+        assert_eq!(advance(&mut wheel, 1_000_070), vec![(1_000_070, 1)]);
+    }
+}
@@ -0,0 +1,218 @@
+//! Real mode entry code that switches the CPU to long mode and jumps into the kernel.
+//!
+//! The code is copied to a page below 1 MiB, since the CPU starts executing it in real mode
+//! (e.g. on resume from ACPI S3).
+
+use crate::{
+    memory::BitmapMemoryManager,
+    paging::{self, Protection},
+    prelude::*,
+};
+use core::{mem, ptr};
+use x86_64::{
+    registers::{
+        control::{Cr0, Cr3, Cr4, Cr4Flags},
+        model_specific::{Efer, EferFlags},
+    },
+    structures::paging::{OffsetPageTable, PageSize, Size4KiB},
+    PhysAddr, VirtAddr,
+};
+
+global_asm!(
+    ".pushsection .rodata.trampoline, \"a\"",
+    ".global trampoline_start",
+    ".global trampoline_params",
+    ".global trampoline_end",
+    ".code16",
+    "trampoline_start:",
+    "    cli",
+    "    cld",
+    "    movw %cs, %ax",
+    "    movw %ax, %ds",
+    // %ebx = physical address of the trampoline
+    "    xorl %ebx, %ebx",
+    "    movw %ax, %bx",
+    "    shll $4, %ebx",
+    "    leal (trampoline_gdt - trampoline_start)(%ebx), %eax",
+    "    movl %eax, (trampoline_gdtr - trampoline_start + 2)",
+    "    leal (trampoline_protected - trampoline_start)(%ebx), %eax",
+    "    movl %eax, (trampoline_protected_jump - trampoline_start)",
+    "    lgdtl (trampoline_gdtr - trampoline_start)",
+    "    movl %cr0, %eax",
+    "    orl $1, %eax",
+    "    movl %eax, %cr0",
+    "    ljmpl *(trampoline_protected_jump - trampoline_start)",
+    ".code32",
+    "trampoline_protected:",
+    "    movw $0x10, %ax",
+    "    movw %ax, %ds",
+    "    movw %ax, %es",
+    "    movw %ax, %ss",
+    "    movl (trampoline_params - trampoline_start + 16)(%ebx), %eax", // CR4
+    "    movl %eax, %cr4",
+    "    movl (trampoline_params - trampoline_start + 8)(%ebx), %eax", // CR3
+    "    movl %eax, %cr3",
+    "    movl $0xc0000080, %ecx", // EFER
+    "    movl (trampoline_params - trampoline_start + 24)(%ebx), %eax",
+    "    xorl %edx, %edx",
+    "    wrmsr",
+    // the trampoline page is not writable after paging is enabled
+    "    leal (trampoline_long - trampoline_start)(%ebx), %eax",
+    "    movl %eax, (trampoline_long_jump - trampoline_start)(%ebx)",
+    "    movl (trampoline_params - trampoline_start)(%ebx), %eax", // CR0
+    "    movl %eax, %cr0",
+    "    ljmpl *(trampoline_long_jump - trampoline_start)(%ebx)",
+    ".code64",
+    "trampoline_long:",
+    "    movw $0x10, %ax",
+    "    movw %ax, %ds",
+    "    movw %ax, %es",
+    "    movw %ax, %ss",
+    // upper halves of registers are undefined after mode switch
+    "    movl %ebx, %ebx",
+    "    movq (trampoline_params - trampoline_start + 32)(%rbx), %rsp", // stack
+    "    movq (trampoline_params - trampoline_start + 48)(%rbx), %rdi", // arg
+    "    callq *(trampoline_params - trampoline_start + 40)(%rbx)",     // entry
+    "1:",
+    "    hlt",
+    "    jmp 1b",
+    ".align 8",
+    "trampoline_gdt:",
+    "    .quad 0",
+    "    .quad 0x00cf9a000000ffff", // 32-bit code
+    "    .quad 0x00cf92000000ffff", // data
+    "    .quad 0x00af9a000000ffff", // 64-bit code
+    "trampoline_gdtr:",
+    "    .word trampoline_gdtr - trampoline_gdt - 1",
+    "    .long 0",
+    "trampoline_protected_jump:",
+    "    .long 0",
+    "    .word 0x08",
+    "trampoline_long_jump:",
+    "    .long 0",
+    "    .word 0x18",
+    ".align 8",
+    "trampoline_params:",
+    "    .fill 7, 8, 0",
+    "trampoline_end:",
+    ".popsection",
+    options(att_syntax)
+);
+
+extern "C" {
+    static trampoline_start: u8;
+    static trampoline_params: u8;
+    static trampoline_end: u8;
+}
+
+/// Entry point called in long mode with the kernel page table.
+pub(crate) type EntryPoint = extern "C" fn(arg: u64) -> !;
+
+/// Parameters read by the trampoline code, whose layout is shared with the assembly.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub(crate) struct TrampolineParams {
+    cr0: u64,
+    cr3: u64,
+    cr4: u64,
+    efer: u64,
+    stack: u64,
+    entry: u64,
+    arg: u64,
+}
+
+impl TrampolineParams {
+    /// Creates parameters that restore the control registers of the current CPU.
+    pub(crate) fn from_current_cpu(stack_end: VirtAddr, entry: EntryPoint, arg: u64) -> Self {
+        // PCIDE cannot be set while the CPU is not in long mode
+        let cr4 = Cr4::read() - Cr4Flags::PCID;
+        let efer = Efer::read() - EferFlags::LONG_MODE_ACTIVE;
+        Self {
+            cr0: Cr0::read_raw(),
+            cr3: Cr3::read().0.start_address().as_u64(),
+            cr4: cr4.bits(),
+            efer: efer.bits(),
+            stack: stack_end.as_u64(),
+            entry: entry as usize as u64,
+            arg,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Trampoline {
+    base: PhysAddr,
+}
+
+impl Trampoline {
+    /// Copies the trampoline code to a page below 1 MiB.
+    pub(crate) fn install(
+        mapper: &mut OffsetPageTable,
+        allocator: &mut BitmapMemoryManager,
+    ) -> Result<Self> {
+        let (start, end) =
+            unsafe { (&trampoline_start as *const u8, &trampoline_end as *const u8) };
+        let len = end as usize - start as usize;
+        assert!(len <= 4096);
+
+        // the trampoline sets only the lower 32 bits of CR3
+        if Cr3::read().0.start_address().as_u64() > u64::from(u32::MAX) {
+            bail!(ErrorKind::UnsupportedPageTableAddress);
+        }
+
+        let frame = allocator
+            .allocate_below(1, 1, PhysAddr::new(0x10_0000))?
+            .start;
+        let base = frame.start_address();
+        paging::ensure_identity_mapping(
+            mapper,
+            allocator,
+            base.as_u64(),
+            1,
+            Protection::Executable,
+        )?;
+        // a page mapped before keeps its flags (e.g. non-executable), but the CPU executes the
+        // trampoline through this mapping
+        paging::remap_with_flags(
+            mapper,
+            VirtAddr::new(base.as_u64()),
+            Size4KiB::SIZE,
+            Protection::Executable.flags(),
+        )?
+        .flush();
+        // the identity mapping is not writable, so write through the physical memory mapping
+        let dest = paging::phys_to_virt(base);
+        unsafe { ptr::copy_nonoverlapping(start, dest.as_mut_ptr(), len) };
+
+        Ok(Self { base })
+    }
+
+    /// Unmaps the trampoline and frees its page.
+    ///
+    /// The caller must guarantee that no CPU executes the trampoline anymore.
+    pub(crate) fn uninstall(
+        self,
+        mapper: &mut OffsetPageTable,
+        allocator: &mut BitmapMemoryManager,
+    ) -> Result<()> {
+        paging::unmap_and_free_range(
+            mapper,
+            allocator,
+            VirtAddr::new(self.base.as_u64()),
+            Size4KiB::SIZE,
+        )
+    }
+
+    pub(crate) fn start_address(&self) -> PhysAddr {
+        self.base
+    }
+
+    pub(crate) fn set_params(&self, params: &TrampolineParams) {
+        let offset = unsafe {
+            &trampoline_params as *const u8 as usize - &trampoline_start as *const u8 as usize
+        };
+        assert_eq!(offset % mem::align_of::<TrampolineParams>(), 0);
+        let dest = paging::phys_to_virt(self.base) + offset;
+        unsafe { ptr::write_volatile(dest.as_mut_ptr(), *params) };
+    }
+}
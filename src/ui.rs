@@ -0,0 +1,452 @@
+//! Retained widgets drawn into a framed window.
+//!
+//! A [`Ui`] owns a framed window and the widgets in it, which are placed by [`Layout`]s from the
+//! root widget, and the window is sized to fit them. The mouse and keyboard events of the window
+//! are handled by the widgets, and the application receives [`UiEvent`]s such as the clicks of
+//! buttons. The focused widget receives the keyboard events, and Tab moves the focus.
+
+use crate::{
+    clipboard,
+    framed_window::{FramedWindow, FramedWindowEvent},
+    graphics::{font, Color, Draw, Offset, Point, Rectangle, Size},
+    keyboard::KeyboardEvent,
+    layer::LayerId,
+    mouse::{MouseButton, MouseEvent},
+    prelude::*,
+    theme::{self, Theme},
+};
+use alloc::{string::String, vec::Vec};
+
+pub(crate) use self::{
+    button::Button, checkbox::Checkbox, label::Label, layout::Layout, text_box::TextBox,
+};
+
+mod button;
+mod checkbox;
+mod label;
+mod layout;
+mod text_box;
+
+/// The margin between the frame and the root widget.
+const MARGIN: i32 = 4;
+
+/// Returns the size of `text` drawn in a line.
+fn text_size(text: &str) -> Size<i32> {
+    let cells = text.chars().map(font::char_width).sum::<i32>();
+    let pixel_size = font::pixel_size();
+    Size::new(pixel_size.x * cells, pixel_size.y)
+}
+
+/// Draws a box with a 3D border within `area`, which looks raised or sunken.
+fn draw_bevel(
+    drawer: &mut impl Draw,
+    area: Rectangle<i32>,
+    background: Color,
+    raised: bool,
+    theme: &Theme,
+) {
+    let (top_left, bottom_right) = if raised {
+        (theme.highlight, theme.shadow)
+    } else {
+        (theme.shadow, theme.highlight)
+    };
+    // `draw_box` draws the bottom and right borders just outside of the area
+    let area = Rectangle::new(area.pos, area.size - Offset::new(1, 1));
+    drawer.draw_box(area, background, top_left, bottom_right);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct WidgetId(usize);
+
+#[derive(Debug)]
+pub(crate) enum Widget {
+    Label(Label),
+    Button(Button),
+    Checkbox(Checkbox),
+    TextBox(TextBox),
+    Layout(Layout),
+}
+
+impl From<Label> for Widget {
+    fn from(label: Label) -> Self {
+        Self::Label(label)
+    }
+}
+
+impl From<Button> for Widget {
+    fn from(button: Button) -> Self {
+        Self::Button(button)
+    }
+}
+
+impl From<Checkbox> for Widget {
+    fn from(checkbox: Checkbox) -> Self {
+        Self::Checkbox(checkbox)
+    }
+}
+
+impl From<TextBox> for Widget {
+    fn from(text_box: TextBox) -> Self {
+        Self::TextBox(text_box)
+    }
+}
+
+impl From<Layout> for Widget {
+    fn from(layout: Layout) -> Self {
+        Self::Layout(layout)
+    }
+}
+
+impl Widget {
+    /// Returns `true` if the widget can be focused and clicked.
+    fn is_focusable(&self) -> bool {
+        matches!(self, Self::Button(_) | Self::Checkbox(_) | Self::TextBox(_))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum UiEvent {
+    /// The button is clicked, or pressed by Space or Enter while it is focused.
+    Clicked(WidgetId),
+    /// The checkbox is checked or unchecked.
+    Toggled(WidgetId, bool),
+    /// The text of the text box is changed by the user.
+    Changed(WidgetId),
+    /// Enter is pressed on the text box.
+    Submitted(WidgetId),
+    /// The window is requested to be closed.
+    CloseRequested,
+}
+
+#[derive(Debug)]
+struct Node {
+    widget: Widget,
+    /// The area of the widget relative to the content area of the window.
+    area: Rectangle<i32>,
+}
+
+fn preferred_size(nodes: &[Node], id: WidgetId) -> Size<i32> {
+    match &nodes[id.0].widget {
+        Widget::Label(label) => label.preferred_size(),
+        Widget::Button(button) => button.preferred_size(),
+        Widget::Checkbox(checkbox) => checkbox.preferred_size(),
+        Widget::TextBox(text_box) => text_box.preferred_size(),
+        Widget::Layout(layout) => {
+            let sizes = layout
+                .children()
+                .iter()
+                .map(|child| preferred_size(nodes, *child))
+                .collect::<Vec<_>>();
+            layout.preferred_size(&sizes)
+        }
+    }
+}
+
+/// Places the widget of `id` and its children in `area`.
+fn place(nodes: &mut [Node], id: WidgetId, area: Rectangle<i32>) {
+    nodes[id.0].area = area;
+    if let Widget::Layout(layout) = &nodes[id.0].widget {
+        let children = layout.children().to_vec();
+        let sizes = children
+            .iter()
+            .map(|child| preferred_size(nodes, *child))
+            .collect::<Vec<_>>();
+        let areas = layout.place(area, &sizes);
+        for (child, area) in children.into_iter().zip(areas) {
+            place(nodes, child, area);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Builder {
+    title: String,
+    pos: Point<i32>,
+    owner: Option<LayerId>,
+    nodes: Vec<Node>,
+}
+
+impl Builder {
+    pub(crate) fn new(title: String) -> Self {
+        Self {
+            title,
+            pos: Point::new(0, 0),
+            owner: None,
+            nodes: Vec::new(),
+        }
+    }
+
+    pub(crate) fn pos(mut self, pos: Point<i32>) -> Self {
+        self.pos = pos;
+        self
+    }
+
+    /// Makes the window a modal dialog of `owner`, which is centered over the owner.
+    pub(crate) fn modal_for(mut self, owner: LayerId) -> Self {
+        self.owner = Some(owner);
+        self
+    }
+
+    /// Adds `widget`, which is shown if it is the root or a descendant of the root.
+    pub(crate) fn add(&mut self, widget: impl Into<Widget>) -> WidgetId {
+        self.nodes.push(Node {
+            widget: widget.into(),
+            area: Rectangle::new(Point::new(0, 0), Size::new(0, 0)),
+        });
+        WidgetId(self.nodes.len() - 1)
+    }
+
+    /// Creates the window fitting `root`, and draws the widgets.
+    pub(crate) fn build(self, root: WidgetId) -> Result<Ui> {
+        let Self {
+            title,
+            pos,
+            owner,
+            mut nodes,
+        } = self;
+        if root.0 >= nodes.len() {
+            bail!(ErrorKind::IndexOutOfRange);
+        }
+        let size = preferred_size(&nodes, root);
+        let mut builder = FramedWindow::builder(title)
+            .size(size + Size::new(MARGIN * 2, MARGIN * 2))
+            .pos(pos);
+        if let Some(owner) = owner {
+            builder = builder.modal_for(owner);
+        }
+        let window = builder.build()?;
+        place(
+            &mut nodes,
+            root,
+            Rectangle::new(Point::new(MARGIN, MARGIN), size),
+        );
+
+        let focus = (0..nodes.len())
+            .map(WidgetId)
+            .find(|id| nodes[id.0].widget.is_focusable());
+        let dirty = (0..nodes.len()).map(WidgetId).collect();
+        let mut ui = Ui {
+            window,
+            nodes,
+            focus,
+            pressed: None,
+            dirty,
+            cursor_visible: true,
+        };
+        ui.draw_dirty();
+        Ok(ui)
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Ui {
+    window: FramedWindow,
+    nodes: Vec<Node>,
+    focus: Option<WidgetId>,
+    /// The widget on which the left button is pressed.
+    pressed: Option<WidgetId>,
+    /// The widgets to be drawn on the next flush.
+    dirty: Vec<WidgetId>,
+    cursor_visible: bool,
+}
+
+impl Ui {
+    pub(crate) fn builder(title: String) -> Builder {
+        Builder::new(title)
+    }
+
+    pub(crate) fn widget(&self, id: WidgetId) -> Option<&Widget> {
+        self.nodes.get(id.0).map(|node| &node.widget)
+    }
+
+    /// Returns the widget of `id` to be changed, which is drawn again in its area on the next
+    /// flush.
+    pub(crate) fn widget_mut(&mut self, id: WidgetId) -> Option<&mut Widget> {
+        let node = self.nodes.get_mut(id.0)?;
+        self.dirty.push(id);
+        Some(&mut node.widget)
+    }
+
+    /// Blinks the cursor of the focused text box.
+    pub(crate) fn blink_cursor(&mut self) {
+        self.cursor_visible = !self.cursor_visible;
+        if let Some(id) = self.focus {
+            self.dirty.push(id);
+        }
+    }
+
+    pub(crate) async fn flush(&mut self) -> Result<()> {
+        self.draw_dirty();
+        self.window.flush().await
+    }
+
+    /// Receives the next event, drawing the widgets changed by the events handled meanwhile.
+    pub(crate) async fn recv_event(&mut self) -> Option<Result<UiEvent>> {
+        loop {
+            if !self.dirty.is_empty() {
+                if let Err(err) = self.flush().await {
+                    return Some(Err(err));
+                }
+            }
+            let event = self.window.recv_event().await?;
+            let event = match event {
+                Ok(event) => event,
+                Err(err) => return Some(Err(err)),
+            };
+            let event = match event {
+                FramedWindowEvent::Redraw | FramedWindowEvent::Resized => {
+                    // the frame has filled the content area
+                    self.dirty.extend((0..self.nodes.len()).map(WidgetId));
+                    None
+                }
+                FramedWindowEvent::Mouse(event) => self.handle_mouse(&event),
+                FramedWindowEvent::Keyboard(event) => self.handle_keyboard(&event),
+                FramedWindowEvent::Copy => {
+                    if let Some(Widget::TextBox(text_box)) = self.focused_widget() {
+                        clipboard::set_text(text_box.text());
+                    }
+                    None
+                }
+                FramedWindowEvent::Paste => self.paste(),
+                FramedWindowEvent::CloseRequested => Some(UiEvent::CloseRequested),
+            };
+            if let Some(event) = event {
+                return Some(Ok(event));
+            }
+        }
+    }
+
+    fn focused_widget(&mut self) -> Option<&mut Widget> {
+        let id = self.focus?;
+        self.widget_mut(id)
+    }
+
+    fn set_focus(&mut self, id: WidgetId) {
+        if self.focus != Some(id) {
+            self.dirty.extend(self.focus);
+            self.dirty.push(id);
+            self.focus = Some(id);
+            self.cursor_visible = true;
+        }
+    }
+
+    /// Moves the focus to the next focusable widget in the order they are added.
+    fn focus_next(&mut self) {
+        let start = self.focus.map(|id| id.0 + 1).unwrap_or(0);
+        let len = self.nodes.len();
+        let next = (start..len)
+            .chain(0..start)
+            .map(WidgetId)
+            .find(|id| self.nodes[id.0].widget.is_focusable());
+        if let Some(next) = next {
+            self.set_focus(next);
+        }
+    }
+
+    fn widget_at(&self, pos: Point<i32>) -> Option<WidgetId> {
+        (0..self.nodes.len()).map(WidgetId).find(|id| {
+            let node = &self.nodes[id.0];
+            node.widget.is_focusable() && node.area.contains(&pos)
+        })
+    }
+
+    fn set_button_pressed(&mut self, id: WidgetId, pressed: bool) {
+        if let Some(Widget::Button(button)) = self.widget_mut(id) {
+            button.set_pressed(pressed);
+        }
+    }
+
+    /// Clicks the widget of `id`.
+    fn activate(&mut self, id: WidgetId) -> Option<UiEvent> {
+        match self.widget_mut(id)? {
+            Widget::Button(_) => Some(UiEvent::Clicked(id)),
+            Widget::Checkbox(checkbox) => {
+                let checked = !checkbox.checked();
+                checkbox.set_checked(checked);
+                Some(UiEvent::Toggled(id, checked))
+            }
+            Widget::Label(_) | Widget::TextBox(_) | Widget::Layout(_) => None,
+        }
+    }
+
+    fn handle_mouse(&mut self, event: &MouseEvent) -> Option<UiEvent> {
+        let target = self.widget_at(event.pos);
+        if event.down.contains(MouseButton::Left) {
+            self.pressed = target;
+            if let Some(id) = target {
+                self.set_focus(id);
+                self.set_button_pressed(id, true);
+            }
+        }
+        if event.up.contains(MouseButton::Left) {
+            if let Some(pressed) = self.pressed.take() {
+                self.set_button_pressed(pressed, false);
+                // a click is cancelled by releasing the button out of the widget
+                if Some(pressed) == target {
+                    return self.activate(pressed);
+                }
+            }
+        }
+        None
+    }
+
+    fn handle_keyboard(&mut self, event: &KeyboardEvent) -> Option<UiEvent> {
+        if !event.press || event.ascii == '\0' {
+            return None;
+        }
+        if event.ascii == '\t' {
+            self.focus_next();
+            return None;
+        }
+
+        let id = self.focus?;
+        match (self.widget_mut(id)?, event.ascii) {
+            (Widget::Button(_), ' ' | '\n') | (Widget::Checkbox(_), ' ') => self.activate(id),
+            (Widget::TextBox(_), '\n') => Some(UiEvent::Submitted(id)),
+            (Widget::TextBox(text_box), '\x08') => text_box.pop().then(|| UiEvent::Changed(id)),
+            (Widget::TextBox(text_box), ch) if ch >= ' ' => {
+                text_box.push(ch).then(|| UiEvent::Changed(id))
+            }
+            _ => None,
+        }
+    }
+
+    /// Pastes the text of the clipboard into the focused text box, until the box is full.
+    fn paste(&mut self) -> Option<UiEvent> {
+        let id = self.focus?;
+        let text = clipboard::text()?;
+        if let Widget::TextBox(text_box) = self.widget_mut(id)? {
+            let pasted = text
+                .chars()
+                .filter(|ch| *ch >= ' ')
+                .take_while(|ch| text_box.push(*ch))
+                .count();
+            return (pasted > 0).then(|| UiEvent::Changed(id));
+        }
+        None
+    }
+
+    fn draw_dirty(&mut self) {
+        let mut dirty = core::mem::take(&mut self.dirty);
+        dirty.sort_unstable_by_key(|id| id.0);
+        dirty.dedup();
+        let theme = theme::current();
+        for id in dirty {
+            let node = &self.nodes[id.0];
+            let focused = self.focus == Some(id);
+            let window = &mut self.window;
+            window.push_clip(node.area);
+            match &node.widget {
+                Widget::Label(label) => label.draw(window, node.area, &theme),
+                Widget::Button(button) => button.draw(window, node.area, focused, &theme),
+                Widget::Checkbox(checkbox) => checkbox.draw(window, node.area, focused, &theme),
+                Widget::TextBox(text_box) => {
+                    let cursor = focused && self.cursor_visible;
+                    text_box.draw(window, node.area, cursor, &theme)
+                }
+                Widget::Layout(_) => {}
+            }
+            window.pop_clip();
+        }
+    }
+}
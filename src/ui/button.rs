@@ -0,0 +1,60 @@
+use super::{draw_bevel, text_size};
+use crate::{
+    graphics::{Draw, Offset, Point, Rectangle, Size},
+    theme::Theme,
+};
+use alloc::string::String;
+
+const PADDING: Size<i32> = Size::new(8, 4);
+
+#[derive(Debug)]
+pub(crate) struct Button {
+    label: String,
+    /// `true` while the left button of the mouse is pressed on the button.
+    pressed: bool,
+}
+
+impl Button {
+    pub(crate) fn new(label: String) -> Self {
+        Self {
+            label,
+            pressed: false,
+        }
+    }
+
+    pub(super) fn set_pressed(&mut self, pressed: bool) {
+        self.pressed = pressed;
+    }
+
+    pub(super) fn preferred_size(&self) -> Size<i32> {
+        text_size(&self.label) + PADDING * Size::new(2, 2)
+    }
+
+    pub(super) fn draw(
+        &self,
+        drawer: &mut impl Draw,
+        area: Rectangle<i32>,
+        focused: bool,
+        theme: &Theme,
+    ) {
+        draw_bevel(drawer, area, theme.face, !self.pressed, theme);
+
+        // the label is centered, and moves down right while the button is pressed
+        let text_size = text_size(&self.label);
+        let mut pos = area.pos
+            + Point::new(
+                (area.size.x - text_size.x) / 2,
+                (area.size.y - text_size.y) / 2,
+            );
+        if self.pressed {
+            pos += Offset::new(1, 1);
+        }
+        drawer.draw_str(pos, &self.label, theme.text);
+
+        let focus_color = if focused { theme.text } else { theme.face };
+        drawer.draw_rect(
+            Rectangle::new(area.pos + Offset::new(3, 3), area.size - Size::new(7, 7)),
+            focus_color,
+        );
+    }
+}
@@ -0,0 +1,77 @@
+use super::{draw_bevel, text_size};
+use crate::{
+    graphics::{font, Draw, Offset, Point, Rectangle, Size},
+    theme::Theme,
+};
+use alloc::string::String;
+
+/// The gap between the box and the label.
+const GAP: i32 = 4;
+
+#[derive(Debug)]
+pub(crate) struct Checkbox {
+    label: String,
+    checked: bool,
+}
+
+impl Checkbox {
+    pub(crate) fn new(label: String, checked: bool) -> Self {
+        Self { label, checked }
+    }
+
+    pub(crate) fn checked(&self) -> bool {
+        self.checked
+    }
+
+    pub(crate) fn set_checked(&mut self, checked: bool) {
+        self.checked = checked;
+    }
+
+    /// Returns the length of the sides of the box, which is the height of a line.
+    fn box_len() -> i32 {
+        font::pixel_size().y
+    }
+
+    pub(super) fn preferred_size(&self) -> Size<i32> {
+        let text_size = text_size(&self.label);
+        Size::new(Self::box_len() + GAP + text_size.x + 2, text_size.y + 2)
+    }
+
+    pub(super) fn draw(
+        &self,
+        drawer: &mut impl Draw,
+        area: Rectangle<i32>,
+        focused: bool,
+        theme: &Theme,
+    ) {
+        drawer.fill_rect(area, theme.face);
+
+        let box_len = Self::box_len();
+        let box_pos = area.pos + Point::new(0, (area.size.y - box_len) / 2);
+        let box_area = Rectangle::new(box_pos, Size::new(box_len, box_len));
+        draw_bevel(drawer, box_area, theme.input_bg, false, theme);
+        if self.checked {
+            let mark = box_len / 4;
+            drawer.fill_rect(
+                Rectangle::new(
+                    box_pos + Offset::new(mark, mark),
+                    Size::new(box_len - mark * 2, box_len - mark * 2),
+                ),
+                theme.text,
+            );
+        }
+
+        let text_size = text_size(&self.label);
+        let text_pos = Point::new(
+            box_area.x_end() + GAP,
+            area.y_start() + (area.size.y - text_size.y) / 2,
+        );
+        drawer.draw_str(text_pos, &self.label, theme.text);
+        if focused {
+            drawer.draw_rect(
+                Rectangle::new(text_pos - Offset::new(1, 1), text_size + Size::new(2, 2)),
+                theme.text,
+            );
+        }
+    }
+}
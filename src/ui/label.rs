@@ -0,0 +1,27 @@
+use super::text_size;
+use crate::{
+    graphics::{font, Draw, Point, Rectangle, Size},
+    theme::Theme,
+};
+use alloc::string::String;
+
+#[derive(Debug)]
+pub(crate) struct Label {
+    text: String,
+}
+
+impl Label {
+    pub(crate) fn new(text: String) -> Self {
+        Self { text }
+    }
+
+    pub(super) fn preferred_size(&self) -> Size<i32> {
+        text_size(&self.text)
+    }
+
+    pub(super) fn draw(&self, drawer: &mut impl Draw, area: Rectangle<i32>, theme: &Theme) {
+        drawer.fill_rect(area, theme.face);
+        let y = (area.size.y - font::pixel_size().y) / 2;
+        drawer.draw_str(area.pos + Point::new(0, y), &self.text, theme.text);
+    }
+}
@@ -0,0 +1,112 @@
+use super::WidgetId;
+use crate::graphics::{Point, Rectangle, Size};
+use alloc::vec::Vec;
+
+/// The gap between the children.
+const SPACING: i32 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    Horizontal,
+    Vertical,
+}
+
+/// Places its children in a row or a column.
+///
+/// Each child has its preferred length along the direction, and is stretched to the breadth of
+/// the layout.
+#[derive(Debug)]
+pub(crate) struct Layout {
+    direction: Direction,
+    children: Vec<WidgetId>,
+}
+
+impl Layout {
+    pub(crate) fn horizontal(children: Vec<WidgetId>) -> Self {
+        Self {
+            direction: Direction::Horizontal,
+            children,
+        }
+    }
+
+    pub(crate) fn vertical(children: Vec<WidgetId>) -> Self {
+        Self {
+            direction: Direction::Vertical,
+            children,
+        }
+    }
+
+    pub(super) fn children(&self) -> &[WidgetId] {
+        &self.children
+    }
+
+    /// Swaps the coordinates if the direction is vertical, so that `x` is along the direction.
+    fn along(&self, size: Size<i32>) -> Size<i32> {
+        match self.direction {
+            Direction::Horizontal => size,
+            Direction::Vertical => Size::new(size.y, size.x),
+        }
+    }
+
+    /// Returns the size fitting the children of `sizes`.
+    pub(super) fn preferred_size(&self, sizes: &[Size<i32>]) -> Size<i32> {
+        let gaps = SPACING * (sizes.len().max(1) as i32 - 1);
+        let (length, breadth) = sizes
+            .iter()
+            .map(|size| self.along(*size))
+            .fold((gaps, 0), |(length, breadth), size| {
+                (length + size.x, i32::max(breadth, size.y))
+            });
+        self.along(Size::new(length, breadth))
+    }
+
+    /// Returns the areas of the children of `sizes` placed in `area`.
+    pub(super) fn place(&self, area: Rectangle<i32>, sizes: &[Size<i32>]) -> Vec<Rectangle<i32>> {
+        let breadth = self.along(area.size).y;
+        let mut offset = 0;
+        sizes
+            .iter()
+            .map(|size| {
+                let length = self.along(*size).x;
+                let pos = self.along(Size::new(offset, 0));
+                offset += length + SPACING;
+                Rectangle::new(
+                    area.pos + Point::new(pos.x, pos.y),
+                    self.along(Size::new(length, breadth)),
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test_case]
+    fn place() {
+        let sizes = [Size::new(40, 20), Size::new(30, 10)];
+
+        let row = Layout::horizontal(vec![]);
+        assert_eq!(row.preferred_size(&sizes), Size::new(74, 20));
+        assert_eq!(
+            row.place(Rectangle::from_xywh(4, 4, 74, 20), &sizes),
+            [
+                Rectangle::from_xywh(4, 4, 40, 20),
+                Rectangle::from_xywh(48, 4, 30, 20)
+            ]
+        );
+
+        let column = Layout::vertical(vec![]);
+        assert_eq!(column.preferred_size(&sizes), Size::new(40, 34));
+        assert_eq!(
+            column.place(Rectangle::from_xywh(4, 4, 50, 34), &sizes),
+            [
+                Rectangle::from_xywh(4, 4, 50, 20),
+                Rectangle::from_xywh(4, 28, 50, 10)
+            ]
+        );
+        assert_eq!(column.preferred_size(&[]), Size::new(0, 0));
+    }
+}
@@ -0,0 +1,74 @@
+use super::draw_bevel;
+use crate::{
+    graphics::{font, Draw, Offset, Rectangle, Size},
+    theme::Theme,
+};
+use alloc::{string::String, vec::Vec};
+
+const PADDING: i32 = 4;
+
+#[derive(Debug)]
+pub(crate) struct TextBox {
+    chars: Vec<char>,
+    /// The number of cells the text box shows, which includes the cell of the cursor.
+    width: i32,
+}
+
+impl TextBox {
+    pub(crate) fn new(width: i32) -> Self {
+        Self {
+            chars: Vec::new(),
+            width,
+        }
+    }
+
+    pub(crate) fn text(&self) -> String {
+        self.chars.iter().collect()
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.chars.clear();
+    }
+
+    /// Returns the number of cells the text occupies.
+    fn cells(&self) -> i32 {
+        self.chars.iter().copied().map(font::char_width).sum()
+    }
+
+    /// Appends `ch`, and returns `false` if the text box is full.
+    pub(crate) fn push(&mut self, ch: char) -> bool {
+        if self.cells() + font::char_width(ch) >= self.width {
+            return false;
+        }
+        self.chars.push(ch);
+        true
+    }
+
+    /// Removes the last character, and returns `false` if the text is empty.
+    pub(crate) fn pop(&mut self) -> bool {
+        self.chars.pop().is_some()
+    }
+
+    pub(super) fn preferred_size(&self) -> Size<i32> {
+        let pixel_size = font::pixel_size();
+        Size::new(pixel_size.x * self.width, pixel_size.y) + Size::new(PADDING * 2, PADDING * 2)
+    }
+
+    pub(super) fn draw(
+        &self,
+        drawer: &mut impl Draw,
+        area: Rectangle<i32>,
+        cursor: bool,
+        theme: &Theme,
+    ) {
+        draw_bevel(drawer, area, theme.input_bg, false, theme);
+        let mut pos = area.pos + Offset::new(PADDING, (area.size.y - font::pixel_size().y) / 2);
+        for ch in &self.chars {
+            pos.x = drawer.draw_char(pos, *ch, theme.text).x_end();
+        }
+        if cursor {
+            let font_size = font::pixel_size();
+            drawer.fill_rect(Rectangle::new(pos, font_size - Size::new(1, 1)), theme.text);
+        }
+    }
+}
@@ -0,0 +1,219 @@
+//! USB host stack.
+//!
+//! [`xhci`] drives the host controller and enumerates the devices attached to its root hub ports.
+//! When a device has an interface supported by a class driver, the device is configured and the
+//! driver takes over the endpoints of the interface.
+
+use self::{
+    descriptor::{DeviceDescriptor, InterfaceDescriptor},
+    xhci::DeviceHandle,
+};
+use crate::prelude::*;
+use alloc::{boxed::Box, vec::Vec};
+
+pub(crate) mod audio;
+pub(crate) mod descriptor;
+pub(crate) mod hid;
+pub(crate) mod xhci;
+
+// bmRequestType
+pub(crate) mod request_type {
+    pub(crate) const DIR_OUT: u8 = 0 << 7;
+    pub(crate) const DIR_IN: u8 = 1 << 7;
+    pub(crate) const TYPE_STANDARD: u8 = 0 << 5;
+    pub(crate) const TYPE_CLASS: u8 = 1 << 5;
+    pub(crate) const RECIPIENT_DEVICE: u8 = 0;
+    pub(crate) const RECIPIENT_INTERFACE: u8 = 1;
+}
+
+// bRequest
+pub(crate) mod request {
+    pub(crate) const GET_DESCRIPTOR: u8 = 6;
+    pub(crate) const SET_CONFIGURATION: u8 = 9;
+    pub(crate) const SET_INTERFACE: u8 = 11;
+    // HID class specific requests
+    pub(crate) const SET_REPORT: u8 = 9;
+    pub(crate) const SET_PROTOCOL: u8 = 11;
+}
+
+/// A change of the device attached to a root hub port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PortEvent {
+    /// A device is attached to the port, and addressed in the slot.
+    Attached { port: u8, slot_id: u8 },
+    /// The device is detached from the port, and its class drivers are dropped.
+    Detached { port: u8 },
+}
+
+/// A device attached to a root hub port, as reported by [`xhci::Controller::devices`].
+///
+/// Hubs are not supported, so every device is attached directly to a root hub port.
+#[derive(Debug, Clone)]
+pub(crate) struct DeviceInfo {
+    pub(crate) port: u8,
+    pub(crate) slot_id: u8,
+    pub(crate) speed: &'static str,
+    /// The device descriptor, which is `None` until it is read.
+    pub(crate) descriptor: Option<DeviceDescriptor>,
+    /// The interfaces of the configuration, which are read while the device is initialized.
+    pub(crate) interfaces: Vec<InterfaceInfo>,
+    /// Whether the endpoints of the class drivers are configured.
+    pub(crate) configured: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct InterfaceInfo {
+    pub(crate) descriptor: InterfaceDescriptor,
+    /// The name of the class driver bound to the interface.
+    pub(crate) driver: Option<&'static str>,
+}
+
+/// The setup packet of a control transfer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct SetupData {
+    pub(crate) request_type: u8,
+    pub(crate) request: u8,
+    pub(crate) value: u16,
+    pub(crate) index: u16,
+    pub(crate) length: u16,
+}
+
+impl SetupData {
+    pub(crate) fn is_in(&self) -> bool {
+        self.request_type & request_type::DIR_IN != 0
+    }
+
+    /// `GET_DESCRIPTOR` of the descriptor of `desc_type` and `desc_index`.
+    pub(crate) fn get_descriptor(desc_type: u8, desc_index: u8, length: u16) -> Self {
+        Self {
+            request_type: request_type::DIR_IN
+                | request_type::TYPE_STANDARD
+                | request_type::RECIPIENT_DEVICE,
+            request: request::GET_DESCRIPTOR,
+            value: u16::from(desc_type) << 8 | u16::from(desc_index),
+            index: 0,
+            length,
+        }
+    }
+
+    pub(crate) fn set_configuration(config_value: u8) -> Self {
+        Self {
+            request_type: request_type::DIR_OUT
+                | request_type::TYPE_STANDARD
+                | request_type::RECIPIENT_DEVICE,
+            request: request::SET_CONFIGURATION,
+            value: u16::from(config_value),
+            index: 0,
+            length: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum EndpointType {
+    Control,
+    Isochronous,
+    Bulk,
+    Interrupt,
+}
+
+impl EndpointType {
+    /// Converts the transfer type field of `bmAttributes` of an endpoint descriptor.
+    pub(crate) fn from_attributes(attributes: u8) -> Self {
+        match attributes & 0b11 {
+            0 => Self::Control,
+            1 => Self::Isochronous,
+            2 => Self::Bulk,
+            _ => Self::Interrupt,
+        }
+    }
+}
+
+/// The endpoint address, which consists of the endpoint number and the direction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct EndpointId {
+    address: u8,
+}
+
+impl EndpointId {
+    /// The default control pipe. Control endpoints are always treated as IN.
+    pub(crate) const DEFAULT_CONTROL_PIPE: Self = Self::new(0, true);
+
+    /// Creates an ID from the endpoint number (0..=15) and the direction.
+    pub(crate) const fn new(number: u8, dir_in: bool) -> Self {
+        Self {
+            address: number << 1 | dir_in as u8,
+        }
+    }
+
+    /// Creates an ID from the address (0..=31), which is also the device context index.
+    pub(crate) const fn from_address(address: u8) -> Self {
+        Self { address }
+    }
+
+    pub(crate) fn address(self) -> u8 {
+        self.address
+    }
+
+    pub(crate) fn number(self) -> u8 {
+        self.address >> 1
+    }
+
+    pub(crate) fn is_in(self) -> bool {
+        self.address & 1 != 0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct EndpointConfig {
+    pub(crate) ep_id: EndpointId,
+    pub(crate) ep_type: EndpointType,
+    pub(crate) max_packet_size: u16,
+    /// The polling interval, encoded as in the endpoint descriptor.
+    pub(crate) interval: u8,
+}
+
+/// A driver of an interface of USB devices.
+///
+/// The callbacks are called from the event processing of the host controller, and issue further
+/// transfers through `dev`.
+pub(crate) trait ClassDriver: Send {
+    /// The name of the driver, which is shown in `lsusb`.
+    fn name(&self) -> &'static str;
+
+    /// Assigns an endpoint of the interface, which is called before the device is configured.
+    fn set_endpoint(&mut self, config: &EndpointConfig);
+
+    fn on_endpoints_configured(&mut self, dev: &mut DeviceHandle<'_>) -> Result<()>;
+
+    /// Called when a control transfer issued by this driver is completed. `data` is the data
+    /// received by an IN transfer.
+    fn on_control_completed(
+        &mut self,
+        dev: &mut DeviceHandle<'_>,
+        setup: SetupData,
+        data: &[u8],
+    ) -> Result<()>;
+
+    fn on_interrupt_completed(
+        &mut self,
+        dev: &mut DeviceHandle<'_>,
+        ep_id: EndpointId,
+        data: &[u8],
+    ) -> Result<()>;
+
+    /// Called when an isochronous transfer issued by this driver is completed, even if the packet
+    /// is lost.
+    fn on_isoch_completed(
+        &mut self,
+        _dev: &mut DeviceHandle<'_>,
+        _ep_id: EndpointId,
+    ) -> Result<()> {
+        bail!(ErrorKind::NotImplemented)
+    }
+}
+
+/// Creates the class driver of `interface`, if any driver supports it.
+pub(crate) fn new_class_driver(interface: &InterfaceDescriptor) -> Option<Box<dyn ClassDriver>> {
+    hid::new_class_driver(interface).or_else(|| audio::new_class_driver(interface))
+}
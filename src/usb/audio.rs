@@ -0,0 +1,234 @@
+//! USB Audio Class 1.0 output.
+//!
+//! The driver selects the alternate setting of an AudioStreaming interface with an isochronous
+//! OUT endpoint, and sends a packet every 1 ms frame. The samples are taken from a ring buffer
+//! filled by [`write`], and silence is sent while the buffer is empty.
+//!
+//! The stream format is not negotiated: the device is assumed to accept 16-bit stereo PCM at
+//! 48 kHz, which is the format of QEMU's usb-audio device.
+
+use super::{
+    descriptor::InterfaceDescriptor, request, request_type, xhci::DeviceHandle, ClassDriver,
+    EndpointConfig, EndpointId, EndpointType, SetupData,
+};
+use crate::{
+    prelude::*,
+    sync::{Semaphore, SpinMutex},
+};
+use alloc::{boxed::Box, vec::Vec};
+use core::sync::atomic::{AtomicBool, Ordering};
+use x86_64::instructions::interrupts;
+
+const CLASS_AUDIO: u8 = 1;
+const SUB_CLASS_AUDIO_STREAMING: u8 = 2;
+
+pub(crate) const SAMPLE_RATE: usize = 48000;
+pub(crate) const CHANNELS: usize = 2;
+const BYTES_PER_SAMPLE: usize = 2;
+/// The number of frames (samples of all channels) sent in a 1 ms packet.
+const FRAMES_PER_PACKET: usize = SAMPLE_RATE / 1000;
+/// The number of packets queued to the controller, which is the latency of the output in ms.
+const PACKETS_IN_FLIGHT: usize = 8;
+/// The length of the ring buffer in samples, which holds 500 ms.
+const BUFFER_LEN: usize = SAMPLE_RATE * CHANNELS / 2;
+
+static BUFFER: SpinMutex<PcmBuffer<BUFFER_LEN>> = SpinMutex::new(PcmBuffer::new());
+static STREAMING: AtomicBool = AtomicBool::new(false);
+
+/// Returns `true` if an audio device is playing the samples written.
+pub(crate) fn is_streaming() -> bool {
+    STREAMING.load(Ordering::Acquire)
+}
+
+/// Writes interleaved stereo samples to the ring buffer, and returns the number of samples
+/// written, which is less than `samples.len()` if the buffer is full.
+pub(crate) fn write(samples: &[i16]) -> usize {
+    interrupts::without_interrupts(|| BUFFER.lock().write(samples))
+}
+
+/// A ring buffer of `N` samples.
+#[derive(Debug)]
+struct PcmBuffer<const N: usize> {
+    samples: [i16; N],
+    read_pos: usize,
+    len: usize,
+}
+
+impl<const N: usize> PcmBuffer<N> {
+    const fn new() -> Self {
+        Self {
+            samples: [0; N],
+            read_pos: 0,
+            len: 0,
+        }
+    }
+
+    fn write(&mut self, samples: &[i16]) -> usize {
+        let count = usize::min(samples.len(), N - self.len);
+        for (i, sample) in samples[..count].iter().enumerate() {
+            self.samples[(self.read_pos + self.len + i) % N] = *sample;
+        }
+        self.len += count;
+        count
+    }
+
+    /// Reads samples into `buf` as little-endian bytes, and fills the rest with silence.
+    fn read_bytes(&mut self, buf: &mut [u8]) {
+        let count = usize::min(buf.len() / BYTES_PER_SAMPLE, self.len);
+        let (data, silence) = buf.split_at_mut(count * BYTES_PER_SAMPLE);
+        for bytes in data.chunks_exact_mut(BYTES_PER_SAMPLE) {
+            bytes.copy_from_slice(&self.samples[self.read_pos].to_le_bytes());
+            self.read_pos = (self.read_pos + 1) % N;
+        }
+        self.len -= count;
+        silence.fill(0);
+    }
+}
+
+/// Creates the driver of `interface`, if it is an alternate setting of an AudioStreaming
+/// interface with endpoints.
+pub(crate) fn new_class_driver(interface: &InterfaceDescriptor) -> Option<Box<dyn ClassDriver>> {
+    if interface.interface_class != CLASS_AUDIO
+        || interface.interface_sub_class != SUB_CLASS_AUDIO_STREAMING
+        || interface.alternate_setting == 0
+        || interface.num_endpoints == 0
+    {
+        return None;
+    }
+    Some(Box::new(AudioDriver {
+        interface_number: interface.interface_number,
+        alternate_setting: interface.alternate_setting,
+        isoch_out: None,
+        phase: Phase::NotConfigured,
+        packet: Vec::new(),
+        in_flight: Semaphore::new(PACKETS_IN_FLIGHT),
+    }))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    NotConfigured,
+    SettingInterface,
+    Streaming,
+}
+
+#[derive(Debug)]
+struct AudioDriver {
+    interface_number: u8,
+    alternate_setting: u8,
+    isoch_out: Option<EndpointConfig>,
+    phase: Phase,
+    /// The buffer of the packet being sent.
+    packet: Vec<u8>,
+    /// Permits for the packets queued to the controller, returned when their transfers complete.
+    in_flight: Semaphore,
+}
+
+impl AudioDriver {
+    fn send_packet(&mut self, dev: &mut DeviceHandle<'_>) -> Result<()> {
+        let config = self.isoch_out.ok_or(ErrorKind::InvalidPhase)?;
+        let permit = self.in_flight.try_acquire()?;
+        let len = usize::min(
+            FRAMES_PER_PACKET * CHANNELS * BYTES_PER_SAMPLE,
+            usize::from(config.max_packet_size),
+        );
+        self.packet.resize(len, 0);
+        let packet = &mut self.packet;
+        interrupts::without_interrupts(|| BUFFER.lock().read_bytes(packet));
+        dev.isoch_out(config.ep_id, &self.packet)?;
+        // the permit is returned when the transfer completes
+        permit.forget();
+        Ok(())
+    }
+}
+
+impl Drop for AudioDriver {
+    fn drop(&mut self) {
+        if self.phase == Phase::Streaming {
+            STREAMING.store(false, Ordering::Release);
+        }
+    }
+}
+
+impl ClassDriver for AudioDriver {
+    fn name(&self) -> &'static str {
+        "usb-audio"
+    }
+
+    fn set_endpoint(&mut self, config: &EndpointConfig) {
+        if config.ep_type == EndpointType::Isochronous && !config.ep_id.is_in() {
+            self.isoch_out = Some(*config);
+        }
+    }
+
+    fn on_endpoints_configured(&mut self, dev: &mut DeviceHandle<'_>) -> Result<()> {
+        if self.isoch_out.is_none() {
+            bail!(ErrorKind::NotImplemented);
+        }
+        let setup = SetupData {
+            request_type: request_type::DIR_OUT
+                | request_type::TYPE_STANDARD
+                | request_type::RECIPIENT_INTERFACE,
+            request: request::SET_INTERFACE,
+            value: u16::from(self.alternate_setting),
+            index: u16::from(self.interface_number),
+            length: 0,
+        };
+        self.phase = Phase::SettingInterface;
+        dev.control_out(setup, &[])
+    }
+
+    fn on_control_completed(
+        &mut self,
+        dev: &mut DeviceHandle<'_>,
+        setup: SetupData,
+        _data: &[u8],
+    ) -> Result<()> {
+        if (self.phase, setup.request) != (Phase::SettingInterface, request::SET_INTERFACE) {
+            bail!(ErrorKind::InvalidPhase);
+        }
+        self.phase = Phase::Streaming;
+        STREAMING.store(true, Ordering::Release);
+        info!("usb: audio output started");
+        for _ in 0..PACKETS_IN_FLIGHT {
+            self.send_packet(dev)?;
+        }
+        Ok(())
+    }
+
+    fn on_interrupt_completed(
+        &mut self,
+        _dev: &mut DeviceHandle<'_>,
+        _ep_id: EndpointId,
+        _data: &[u8],
+    ) -> Result<()> {
+        bail!(ErrorKind::NotImplemented)
+    }
+
+    fn on_isoch_completed(&mut self, dev: &mut DeviceHandle<'_>, _ep_id: EndpointId) -> Result<()> {
+        self.in_flight.add_permits(1);
+        self.send_packet(dev)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn pcm_buffer() {
+        let mut buffer = PcmBuffer::<4>::new();
+        assert_eq!(buffer.write(&[1, -1]), 2);
+        let mut bytes = [0xff; 6];
+        buffer.read_bytes(&mut bytes);
+        assert_eq!(bytes, [1, 0, 0xff, 0xff, 0, 0]);
+        assert_eq!(buffer.len, 0);
+
+        // wraps around the end
+        assert_eq!(buffer.write(&[1, 2, 3, 4, 5]), 4);
+        assert_eq!(buffer.write(&[5]), 0);
+        let mut bytes = [0; 8];
+        buffer.read_bytes(&mut bytes);
+        assert_eq!(bytes, [1, 0, 2, 0, 3, 0, 4, 0]);
+    }
+}
@@ -0,0 +1,207 @@
+//! USB descriptors.
+//!
+//! Descriptors are parsed from the bytes received from devices, so malformed descriptors are
+//! reported as `None` instead of being read out of bounds.
+
+use super::{EndpointConfig, EndpointId, EndpointType};
+
+pub(crate) const DEVICE: u8 = 1;
+pub(crate) const CONFIGURATION: u8 = 2;
+pub(crate) const INTERFACE: u8 = 4;
+pub(crate) const ENDPOINT: u8 = 5;
+
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+/// Checks the length and the type of a descriptor.
+fn check(bytes: &[u8], desc_type: u8, len: usize) -> Option<&[u8]> {
+    if bytes.len() < len || usize::from(bytes[0]) < len || bytes[1] != desc_type {
+        return None;
+    }
+    Some(bytes)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct DeviceDescriptor {
+    pub(crate) usb_release: u16,
+    pub(crate) device_class: u8,
+    pub(crate) device_sub_class: u8,
+    pub(crate) device_protocol: u8,
+    pub(crate) vendor_id: u16,
+    pub(crate) product_id: u16,
+    pub(crate) num_configurations: u8,
+}
+
+impl DeviceDescriptor {
+    pub(crate) const LEN: usize = 18;
+
+    pub(crate) fn parse(bytes: &[u8]) -> Option<Self> {
+        let bytes = check(bytes, DEVICE, Self::LEN)?;
+        Some(Self {
+            usb_release: u16_at(bytes, 2),
+            device_class: bytes[4],
+            device_sub_class: bytes[5],
+            device_protocol: bytes[6],
+            vendor_id: u16_at(bytes, 8),
+            product_id: u16_at(bytes, 10),
+            num_configurations: bytes[17],
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ConfigurationDescriptor {
+    /// The length of the configuration descriptor and all descriptors following it.
+    pub(crate) total_length: u16,
+    pub(crate) num_interfaces: u8,
+    pub(crate) configuration_value: u8,
+}
+
+impl ConfigurationDescriptor {
+    pub(crate) const LEN: usize = 9;
+
+    pub(crate) fn parse(bytes: &[u8]) -> Option<Self> {
+        let bytes = check(bytes, CONFIGURATION, Self::LEN)?;
+        Some(Self {
+            total_length: u16_at(bytes, 2),
+            num_interfaces: bytes[4],
+            configuration_value: bytes[5],
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct InterfaceDescriptor {
+    pub(crate) interface_number: u8,
+    pub(crate) alternate_setting: u8,
+    pub(crate) num_endpoints: u8,
+    pub(crate) interface_class: u8,
+    pub(crate) interface_sub_class: u8,
+    pub(crate) interface_protocol: u8,
+}
+
+impl InterfaceDescriptor {
+    pub(crate) const LEN: usize = 9;
+
+    pub(crate) fn parse(bytes: &[u8]) -> Option<Self> {
+        let bytes = check(bytes, INTERFACE, Self::LEN)?;
+        Some(Self {
+            interface_number: bytes[2],
+            alternate_setting: bytes[3],
+            num_endpoints: bytes[4],
+            interface_class: bytes[5],
+            interface_sub_class: bytes[6],
+            interface_protocol: bytes[7],
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct EndpointDescriptor {
+    pub(crate) endpoint_address: u8,
+    pub(crate) attributes: u8,
+    pub(crate) max_packet_size: u16,
+    pub(crate) interval: u8,
+}
+
+impl EndpointDescriptor {
+    pub(crate) const LEN: usize = 7;
+
+    pub(crate) fn parse(bytes: &[u8]) -> Option<Self> {
+        let bytes = check(bytes, ENDPOINT, Self::LEN)?;
+        Some(Self {
+            endpoint_address: bytes[2],
+            attributes: bytes[3],
+            max_packet_size: u16_at(bytes, 4),
+            interval: bytes[6],
+        })
+    }
+
+    pub(crate) fn config(&self) -> EndpointConfig {
+        EndpointConfig {
+            ep_id: EndpointId::new(
+                self.endpoint_address & 0x0f,
+                self.endpoint_address & 0x80 != 0,
+            ),
+            ep_type: EndpointType::from_attributes(self.attributes),
+            // bits 11..13 are the number of additional transactions of high-bandwidth endpoints
+            max_packet_size: self.max_packet_size & 0x7ff,
+            interval: self.interval,
+        }
+    }
+}
+
+/// Iterator over the descriptors concatenated in the response of `GET_DESCRIPTOR`.
+///
+/// Each item is the bytes of a descriptor, starting with its length and type.
+#[derive(Debug, Clone)]
+pub(crate) struct Descriptors<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Descriptors<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+}
+
+impl<'a> Iterator for Descriptors<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let len = usize::from(*self.bytes.first()?);
+        // a descriptor has at least the length and the type
+        if len < 2 || len > self.bytes.len() {
+            self.bytes = &[];
+            return None;
+        }
+        let (desc, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Some(desc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn boot_keyboard_configuration() {
+        let bytes = [
+            9, 2, 34, 0, 1, 1, 0, 0xa0, 50, // configuration
+            9, 4, 0, 0, 1, 3, 1, 1, 0, // interface: HID boot keyboard
+            9, 0x21, 0x11, 0x01, 0, 1, 0x22, 63, 0, // HID
+            7, 5, 0x81, 3, 8, 0, 10, // endpoint: interrupt IN 1
+        ];
+        let descs = Descriptors::new(&bytes).collect::<alloc::vec::Vec<_>>();
+        assert_eq!(descs.len(), 4);
+
+        let config = ConfigurationDescriptor::parse(descs[0]);
+        assert_eq!(config.map(|config| config.total_length), Some(34));
+        let interface = InterfaceDescriptor::parse(descs[1]);
+        assert_eq!(
+            interface.map(|interface| (interface.interface_class, interface.interface_protocol)),
+            Some((3, 1))
+        );
+        assert_eq!(InterfaceDescriptor::parse(descs[2]), None);
+        let endpoint = EndpointDescriptor::parse(descs[3]).map(|endpoint| endpoint.config());
+        assert_eq!(
+            endpoint,
+            Some(EndpointConfig {
+                ep_id: EndpointId::new(1, true),
+                ep_type: EndpointType::Interrupt,
+                max_packet_size: 8,
+                interval: 10,
+            })
+        );
+        assert_eq!(endpoint.map(|endpoint| endpoint.ep_id.address()), Some(3));
+    }
+
+    #[test_case]
+    fn truncated_descriptors() {
+        let bytes = [9, 2, 34, 0, 1, 1, 0, 0xa0, 50, 9, 4, 0];
+        assert_eq!(Descriptors::new(&bytes).count(), 1);
+        assert_eq!(DeviceDescriptor::parse(&[18, 1, 0]), None);
+    }
+}
@@ -0,0 +1,302 @@
+//! HID class drivers for boot-protocol keyboards and mice.
+//!
+//! The drivers switch the interface to the boot protocol, and poll the interrupt IN endpoint.
+//! Reports are passed to the observers set by [`set_keyboard_observer`] and
+//! [`set_mouse_observer`]. The keyboard driver also keeps the state of the lock keys, and sends it
+//! to the LEDs of the keyboard with `SET_REPORT`.
+
+use super::{
+    descriptor::InterfaceDescriptor, request, request_type, xhci::DeviceHandle, ClassDriver,
+    EndpointConfig, EndpointId, EndpointType, SetupData,
+};
+use crate::{prelude::*, sync::OnceCell};
+use alloc::{boxed::Box, vec, vec::Vec};
+
+const CLASS_HID: u8 = 3;
+const SUB_CLASS_BOOT: u8 = 1;
+const PROTOCOL_KEYBOARD: u8 = 1;
+const PROTOCOL_MOUSE: u8 = 2;
+
+// usages of the keyboard page
+const KEY_ERROR_ROLL_OVER: u8 = 0x01;
+const KEY_CAPS_LOCK: u8 = 0x39;
+const KEY_SCROLL_LOCK: u8 = 0x47;
+const KEY_NUM_LOCK: u8 = 0x53;
+/// The usage of the left control key. The modifier keys are `0xe0..=0xe7`, in the order of the
+/// bits of the modifier byte.
+const KEY_LEFT_CONTROL: u8 = 0xe0;
+
+// bits of the LED output report
+const LED_NUM_LOCK: u8 = 1 << 0;
+const LED_CAPS_LOCK: u8 = 1 << 1;
+const LED_SCROLL_LOCK: u8 = 1 << 2;
+
+/// Output report type of `SET_REPORT`
+const REPORT_TYPE_OUTPUT: u16 = 2;
+
+/// Called on each key press and release. `leds` is the state of the lock keys after the event.
+pub(crate) type KeyboardObserver = fn(modifier: u8, keycode: u8, press: bool, leds: u8);
+/// Called on each report of a mouse. `wheel` is 0 if the mouse has no wheel.
+pub(crate) type MouseObserver = fn(buttons: u8, displacement_x: i8, displacement_y: i8, wheel: i8);
+
+static KEYBOARD_OBSERVER: OnceCell<KeyboardObserver> = OnceCell::uninit();
+static MOUSE_OBSERVER: OnceCell<MouseObserver> = OnceCell::uninit();
+
+/// Sets the observer of keyboards attached from now on.
+pub(crate) fn set_keyboard_observer(observer: KeyboardObserver) {
+    KEYBOARD_OBSERVER.init_once(|| observer);
+}
+
+/// Sets the observer of mice attached from now on.
+pub(crate) fn set_mouse_observer(observer: MouseObserver) {
+    MOUSE_OBSERVER.init_once(|| observer);
+}
+
+/// Creates the driver of `interface`, if it is a boot-protocol keyboard or mouse.
+pub(crate) fn new_class_driver(interface: &InterfaceDescriptor) -> Option<Box<dyn ClassDriver>> {
+    if interface.interface_class != CLASS_HID || interface.interface_sub_class != SUB_CLASS_BOOT {
+        return None;
+    }
+    let number = interface.interface_number;
+    match interface.interface_protocol {
+        PROTOCOL_KEYBOARD => {
+            let keyboard = Keyboard::new(KEYBOARD_OBSERVER.try_get().ok().copied());
+            Some(Box::new(HidDriver::new(number, keyboard)))
+        }
+        PROTOCOL_MOUSE => {
+            let mouse = Mouse {
+                observer: MOUSE_OBSERVER.try_get().ok().copied(),
+            };
+            Some(Box::new(HidDriver::new(number, mouse)))
+        }
+        _ => None,
+    }
+}
+
+/// Handles the input reports of a HID device.
+trait ReportHandler: Send {
+    /// The name of the class driver.
+    const NAME: &'static str;
+    /// The length of the report of the boot protocol.
+    const REPORT_LEN: usize;
+
+    /// Handles `report`, which is at least `REPORT_LEN` bytes.
+    ///
+    /// Returns the output report to be sent to the device, if any.
+    fn on_report(&mut self, report: &[u8]) -> Option<Vec<u8>>;
+}
+
+#[derive(Debug)]
+struct Keyboard {
+    observer: Option<KeyboardObserver>,
+    modifier: u8,
+    keys: [u8; 6],
+    leds: u8,
+}
+
+impl Keyboard {
+    fn new(observer: Option<KeyboardObserver>) -> Self {
+        Self {
+            observer,
+            modifier: 0,
+            keys: [0; 6],
+            leds: 0,
+        }
+    }
+
+    fn notify(&self, keycode: u8, press: bool) {
+        if let Some(observer) = self.observer {
+            observer(self.modifier, keycode, press, self.leds);
+        }
+    }
+}
+
+impl ReportHandler for Keyboard {
+    const NAME: &'static str = "usb-hid-keyboard";
+    const REPORT_LEN: usize = 8;
+
+    fn on_report(&mut self, report: &[u8]) -> Option<Vec<u8>> {
+        // modifier keys, reserved and up to six keys pressed
+        let modifier = report[0];
+        let mut keys = [0; 6];
+        keys.copy_from_slice(&report[2..Self::REPORT_LEN]);
+        if keys.contains(&KEY_ERROR_ROLL_OVER) {
+            // too many keys are pressed, and the keys in the report are not reliable
+            return None;
+        }
+
+        let old_modifier = self.modifier;
+        let old_keys = self.keys;
+        let old_leds = self.leds;
+        self.modifier = modifier;
+        self.keys = keys;
+
+        for bit in 0..8 {
+            let mask = 1 << bit;
+            if (old_modifier ^ modifier) & mask != 0 {
+                self.notify(KEY_LEFT_CONTROL + bit, modifier & mask != 0);
+            }
+        }
+        for key in old_keys {
+            if key != 0 && !keys.contains(&key) {
+                self.notify(key, false);
+            }
+        }
+        for key in keys {
+            if key != 0 && !old_keys.contains(&key) {
+                self.leds ^= match key {
+                    KEY_NUM_LOCK => LED_NUM_LOCK,
+                    KEY_CAPS_LOCK => LED_CAPS_LOCK,
+                    KEY_SCROLL_LOCK => LED_SCROLL_LOCK,
+                    _ => 0,
+                };
+                self.notify(key, true);
+            }
+        }
+
+        (self.leds != old_leds).then(|| vec![self.leds])
+    }
+}
+
+#[derive(Debug)]
+struct Mouse {
+    observer: Option<MouseObserver>,
+}
+
+impl ReportHandler for Mouse {
+    const NAME: &'static str = "usb-hid-mouse";
+    const REPORT_LEN: usize = 3;
+
+    fn on_report(&mut self, report: &[u8]) -> Option<Vec<u8>> {
+        // the boot protocol defines only the first three bytes, but most mice with a wheel put
+        // the wheel delta in the fourth byte, and the side buttons in the bits 3 and 4 of buttons
+        let buttons = report[0];
+        let displacement_x = report[1] as i8;
+        let displacement_y = report[2] as i8;
+        let wheel = report.get(3).map_or(0, |wheel| *wheel as i8);
+        trace!(
+            "{:02x},({:3},{:3}),{:3}",
+            buttons,
+            displacement_x,
+            displacement_y,
+            wheel
+        );
+        if let Some(observer) = self.observer {
+            observer(buttons, displacement_x, displacement_y, wheel);
+        }
+        None
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    NotConfigured,
+    SettingProtocol,
+    Polling,
+}
+
+#[derive(Debug)]
+struct HidDriver<H> {
+    handler: H,
+    interface_number: u8,
+    interrupt_in: Option<EndpointConfig>,
+    phase: Phase,
+}
+
+impl<H> HidDriver<H>
+where
+    H: ReportHandler,
+{
+    fn new(interface_number: u8, handler: H) -> Self {
+        Self {
+            handler,
+            interface_number,
+            interrupt_in: None,
+            phase: Phase::NotConfigured,
+        }
+    }
+
+    fn poll(&mut self, dev: &mut DeviceHandle<'_>) -> Result<()> {
+        let config = self.interrupt_in.ok_or(ErrorKind::NoInterruptInEndpoint {
+            interface: self.interface_number,
+        })?;
+        dev.interrupt_in(config.ep_id, config.max_packet_size)
+    }
+
+    fn set_report(&mut self, dev: &mut DeviceHandle<'_>, report: &[u8]) -> Result<()> {
+        let setup = SetupData {
+            request_type: request_type::DIR_OUT
+                | request_type::TYPE_CLASS
+                | request_type::RECIPIENT_INTERFACE,
+            request: request::SET_REPORT,
+            // report ID 0
+            value: REPORT_TYPE_OUTPUT << 8,
+            index: u16::from(self.interface_number),
+            length: report.len() as u16,
+        };
+        dev.control_out(setup, report)
+    }
+}
+
+impl<H> ClassDriver for HidDriver<H>
+where
+    H: ReportHandler,
+{
+    fn name(&self) -> &'static str {
+        H::NAME
+    }
+
+    fn set_endpoint(&mut self, config: &EndpointConfig) {
+        if config.ep_type == EndpointType::Interrupt && config.ep_id.is_in() {
+            self.interrupt_in = Some(*config);
+        }
+    }
+
+    fn on_endpoints_configured(&mut self, dev: &mut DeviceHandle<'_>) -> Result<()> {
+        let setup = SetupData {
+            request_type: request_type::DIR_OUT
+                | request_type::TYPE_CLASS
+                | request_type::RECIPIENT_INTERFACE,
+            request: request::SET_PROTOCOL,
+            // boot protocol
+            value: 0,
+            index: u16::from(self.interface_number),
+            length: 0,
+        };
+        self.phase = Phase::SettingProtocol;
+        dev.control_out(setup, &[])
+    }
+
+    fn on_control_completed(
+        &mut self,
+        dev: &mut DeviceHandle<'_>,
+        setup: SetupData,
+        _data: &[u8],
+    ) -> Result<()> {
+        match (self.phase, setup.request) {
+            (Phase::SettingProtocol, request::SET_PROTOCOL) => {
+                self.phase = Phase::Polling;
+                self.poll(dev)
+            }
+            (Phase::Polling, request::SET_REPORT) => Ok(()),
+            _ => bail!(ErrorKind::InvalidPhase),
+        }
+    }
+
+    fn on_interrupt_completed(
+        &mut self,
+        dev: &mut DeviceHandle<'_>,
+        ep_id: EndpointId,
+        data: &[u8],
+    ) -> Result<()> {
+        if !ep_id.is_in() {
+            bail!(ErrorKind::NotImplemented);
+        }
+        if data.len() >= H::REPORT_LEN {
+            if let Some(report) = self.handler.on_report(data) {
+                self.set_report(dev, &report)?;
+            }
+        }
+        self.poll(dev)
+    }
+}
@@ -0,0 +1,671 @@
+//! xHCI host controller driver.
+//!
+//! Devices attached to root hub ports are enumerated one port at a time: a port must be addressed
+//! right after its reset, so the other ports with devices wait until the port being addressed
+//! completes the Address Device command.
+//!
+//! When a device is detached, its slot is disabled, and the device and its class drivers are
+//! dropped when the Disable Slot command completes. Attaches and detaches are sent to the
+//! receivers of [`Controller::subscribe_port_events`].
+
+pub(crate) use self::{
+    device::{Device, DeviceHandle},
+    trb::CompletionCode,
+};
+
+use self::{
+    context::{EndpointContext, SlotContext},
+    registers::*,
+    ring::{EventRing, Ring},
+    trb::Trb,
+};
+use crate::{
+    dma::DmaBuffer,
+    mmio::VolatileMmio,
+    prelude::*,
+    sync::broadcast,
+    usb::{DeviceInfo, EndpointId, EndpointType, PortEvent},
+};
+use alloc::vec::Vec;
+use core::hint;
+
+mod context;
+mod device;
+mod registers;
+mod ring;
+mod trb;
+
+/// The number of device slots enabled.
+const MAX_SLOTS: u8 = 8;
+const COMMAND_RING_LEN: usize = 32;
+const EVENT_RING_LEN: usize = 32;
+const TRANSFER_RING_LEN: usize = 32;
+const PORT_EVENT_CAPACITY: usize = 16;
+
+// Protocol Speed IDs, which are the defaults unless the controller defines its own
+const FULL_SPEED: u8 = 1;
+const LOW_SPEED: u8 = 2;
+const HIGH_SPEED: u8 = 3;
+const SUPER_SPEED: u8 = 4;
+const SUPER_SPEED_PLUS: u8 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ConfigPhase {
+    NotConnected,
+    /// Waiting for another port to be addressed.
+    WaitingAddressed,
+    ResettingPort,
+    EnablingSlot,
+    AddressingDevice,
+    InitializingDevice,
+    ConfiguringEndpoints,
+    Configured,
+    /// The device is detached, and its slot is being disabled.
+    DisablingSlot,
+}
+
+#[derive(Debug)]
+pub(crate) struct Controller {
+    registers: Registers,
+    max_ports: u8,
+    context_size: usize,
+    /// Device Context Base Address Array
+    dcbaa: DmaBuffer,
+    _scratchpad_buffers: Vec<DmaBuffer>,
+    command_ring: Ring,
+    event_ring: EventRing,
+    /// Indexed by slot ID.
+    devices: Vec<Option<Device>>,
+    /// Indexed by port number.
+    port_config_phase: [ConfigPhase; 256],
+    /// The slot enabled for each port, indexed by port number.
+    port_slot: [Option<u8>; 256],
+    /// The port being reset and addressed.
+    addressing_port: Option<u8>,
+    port_events: broadcast::Sender<PortEvent>,
+}
+
+fn wait_until(mut condition: impl FnMut() -> bool) {
+    while !condition() {
+        hint::spin_loop();
+    }
+}
+
+/// Takes the ownership of the controller from BIOS.
+fn request_hc_ownership(registers: &Registers) {
+    let mut next = registers.extended_capabilities();
+    let offset = loop {
+        let offset = match next {
+            Some(offset) => offset,
+            None => return,
+        };
+        let header = registers.read_capability(offset);
+        if header as u8 == EXT_CAP_LEGACY_SUPPORT {
+            break offset;
+        }
+        next = match (header >> 8) & 0xff {
+            0 => None,
+            next => Some(offset + (u64::from(next) << 2)),
+        };
+    };
+
+    let value = registers.read_capability(offset);
+    if value & USBLEGSUP_OS_OWNED != 0 {
+        return;
+    }
+    trace!("waiting until OS owns xHC...");
+    registers.write_capability(offset, value | USBLEGSUP_OS_OWNED);
+    wait_until(|| {
+        let value = registers.read_capability(offset);
+        value & USBLEGSUP_BIOS_OWNED == 0 && value & USBLEGSUP_OS_OWNED != 0
+    });
+    trace!("OS has owned xHC");
+}
+
+fn speed_name(speed: u8) -> &'static str {
+    match speed {
+        FULL_SPEED => "full-speed",
+        LOW_SPEED => "low-speed",
+        HIGH_SPEED => "high-speed",
+        SUPER_SPEED => "SuperSpeed",
+        SUPER_SPEED_PLUS => "SuperSpeedPlus",
+        _ => "unknown speed",
+    }
+}
+
+fn max_packet_size_for_control_pipe(speed: u8) -> u16 {
+    match speed {
+        SUPER_SPEED | SUPER_SPEED_PLUS => 512,
+        HIGH_SPEED => 64,
+        _ => 8,
+    }
+}
+
+/// Converts the interval of an endpoint descriptor into that of an endpoint context, which is
+/// the exponent of 125 us.
+fn convert_interval(speed: u8, ep_type: EndpointType, interval: u8) -> u8 {
+    match (speed, ep_type) {
+        // the isochronous interval is already an exponent
+        (FULL_SPEED | LOW_SPEED, EndpointType::Isochronous) => interval + 2,
+        // in frames of 1 ms
+        (FULL_SPEED | LOW_SPEED, _) => {
+            let msb = 7u32.saturating_sub(interval.leading_zeros());
+            msb as u8 + 3
+        }
+        _ => interval.saturating_sub(1),
+    }
+}
+
+impl Controller {
+    /// Resets and initializes the controller, whose registers are mapped at `mmio`.
+    pub(crate) fn new(mmio: VolatileMmio) -> Result<Self> {
+        let registers = Registers::new(mmio);
+        request_hc_ownership(&registers);
+
+        let mut usbcmd = registers.usbcmd();
+        usbcmd &= !(USBCMD_INTERRUPTER_ENABLE
+            | USBCMD_HOST_SYSTEM_ERROR_ENABLE
+            | USBCMD_ENABLE_WRAP_EVENT);
+        // the controller must be halted before resetting it
+        if registers.usbsts() & USBSTS_HOST_CONTROLLER_HALTED == 0 {
+            usbcmd &= !USBCMD_RUN_STOP;
+        }
+        registers.set_usbcmd(usbcmd);
+        wait_until(|| registers.usbsts() & USBSTS_HOST_CONTROLLER_HALTED != 0);
+
+        registers.set_usbcmd(registers.usbcmd() | USBCMD_HOST_CONTROLLER_RESET);
+        wait_until(|| registers.usbcmd() & USBCMD_HOST_CONTROLLER_RESET == 0);
+        wait_until(|| registers.usbsts() & USBSTS_CONTROLLER_NOT_READY == 0);
+
+        debug!(
+            "xHC version = {:04x}, MaxSlots = {}, MaxPorts = {}",
+            registers.hci_version(),
+            registers.max_device_slots(),
+            registers.max_ports()
+        );
+        let max_slots = u8::min(MAX_SLOTS, registers.max_device_slots());
+        registers.set_max_device_slots_enabled(max_slots);
+
+        let mut dcbaa = DmaBuffer::builder((usize::from(max_slots) + 1) * 8)
+            .below_4gib()
+            .build()?;
+
+        let num_scratchpad_buffers = registers.max_scratchpad_buffers();
+        let mut scratchpad_buffers = Vec::new();
+        if num_scratchpad_buffers > 0 {
+            let page_size = registers.page_size();
+            let mut array = DmaBuffer::builder(num_scratchpad_buffers * 8)
+                .below_4gib()
+                .build()?;
+            for entry in array.as_mut_slice().chunks_exact_mut(8) {
+                let buffer = DmaBuffer::builder(page_size)
+                    .align(page_size)
+                    .below_4gib()
+                    .build()?;
+                entry.copy_from_slice(&buffer.phys_addr().as_u64().to_le_bytes());
+                scratchpad_buffers.push(buffer);
+            }
+            dcbaa.as_mut_slice()[..8].copy_from_slice(&array.phys_addr().as_u64().to_le_bytes());
+            debug!(
+                "{} scratchpad buffers are allocated at {:x}",
+                num_scratchpad_buffers,
+                array.phys_addr().as_u64()
+            );
+            scratchpad_buffers.push(array);
+        }
+        registers.set_dcbaap(dcbaa.phys_addr().as_u64());
+
+        let command_ring = Ring::new(COMMAND_RING_LEN)?;
+        registers.set_crcr(command_ring.phys_addr() | CRCR_RING_CYCLE_STATE);
+        let event_ring = EventRing::new(EVENT_RING_LEN, &registers)?;
+
+        // enable interrupts of the primary interrupter and the controller
+        registers.set_iman(registers.iman() | IMAN_INTERRUPT_PENDING | IMAN_INTERRUPT_ENABLE);
+        registers.set_usbcmd(registers.usbcmd() | USBCMD_INTERRUPTER_ENABLE);
+
+        let mut devices = Vec::new();
+        devices.resize_with(usize::from(max_slots) + 1, || None);
+
+        Ok(Self {
+            registers,
+            max_ports: registers.max_ports(),
+            context_size: registers.context_size(),
+            dcbaa,
+            _scratchpad_buffers: scratchpad_buffers,
+            command_ring,
+            event_ring,
+            devices,
+            port_config_phase: [ConfigPhase::NotConnected; 256],
+            port_slot: [None; 256],
+            addressing_port: None,
+            port_events: broadcast::channel(PORT_EVENT_CAPACITY),
+        })
+    }
+
+    pub(crate) fn run(&mut self) {
+        self.registers
+            .set_usbcmd(self.registers.usbcmd() | USBCMD_RUN_STOP);
+        wait_until(|| self.registers.usbsts() & USBSTS_HOST_CONTROLLER_HALTED == 0);
+    }
+
+    /// Starts the enumeration of the devices already connected.
+    pub(crate) fn configure_connected_ports(&mut self) {
+        for port in 1..=self.max_ports {
+            if self.registers.portsc(port) & PORTSC_CURRENT_CONNECT_STATUS == 0 {
+                continue;
+            }
+            debug!("xhc: port {} is connected", port);
+            if self.port_config_phase[usize::from(port)] == ConfigPhase::NotConnected {
+                if let Err(err) = self.reset_port(port) {
+                    error!("failed to configure port {}: {}", port, err);
+                }
+            }
+        }
+    }
+
+    /// Returns a receiver of the attaches and detaches of devices, which also receives the recent
+    /// events sent before subscribing.
+    pub(crate) fn subscribe_port_events(&self) -> broadcast::Receiver<PortEvent> {
+        self.port_events.subscribe()
+    }
+
+    pub(crate) fn max_ports(&self) -> u8 {
+        self.max_ports
+    }
+
+    /// Returns the devices attached to the root hub ports, in the order of the ports.
+    ///
+    /// The devices being detached are excluded.
+    pub(crate) fn devices(&self) -> Vec<DeviceInfo> {
+        (1..=self.max_ports)
+            .filter(|port| self.phase(*port) != ConfigPhase::DisablingSlot)
+            .filter_map(|port| {
+                let slot_id = self.port_slot[usize::from(port)]?;
+                let dev = self.devices.get(usize::from(slot_id))?.as_ref()?;
+                Some(DeviceInfo {
+                    port,
+                    slot_id,
+                    speed: speed_name(self.registers.port_speed(port)),
+                    descriptor: dev.descriptor(),
+                    interfaces: dev.interfaces().to_vec(),
+                    configured: self.phase(port) == ConfigPhase::Configured,
+                })
+            })
+            .collect()
+    }
+
+    pub(crate) fn has_event(&self) -> bool {
+        self.event_ring.front().is_some()
+    }
+
+    /// Processes the event at the front of the event ring, if any.
+    pub(crate) fn process_event(&mut self) -> Result<()> {
+        let event = match self.event_ring.front() {
+            Some(event) => event,
+            None => return Ok(()),
+        };
+        let res = match event.trb_type() {
+            trb::TRANSFER_EVENT => self.on_transfer_event(&event),
+            trb::COMMAND_COMPLETION_EVENT => self.on_command_completion_event(&event),
+            trb::PORT_STATUS_CHANGE_EVENT => self.on_port_status_change_event(&event),
+            _ => Ok(()),
+        };
+        self.event_ring.pop(&self.registers);
+        res
+    }
+
+    fn phase(&self, port: u8) -> ConfigPhase {
+        self.port_config_phase[usize::from(port)]
+    }
+
+    fn set_phase(&mut self, port: u8, phase: ConfigPhase) {
+        self.port_config_phase[usize::from(port)] = phase;
+    }
+
+    fn push_command(&mut self, trb: Trb) {
+        self.command_ring.push(trb);
+        self.registers.ring_doorbell(0, 0);
+    }
+
+    fn device_mut(&mut self, slot_id: u8) -> Result<&mut Device> {
+        let dev = self
+            .devices
+            .get_mut(usize::from(slot_id))
+            .and_then(Option::as_mut);
+        Ok(dev.ok_or(ErrorKind::InvalidSlotID(slot_id))?)
+    }
+
+    fn reset_port(&mut self, port: u8) -> Result<()> {
+        let portsc = self.registers.portsc(port);
+        trace!(
+            "reset_port: port {} is connected = {}",
+            port,
+            portsc & PORTSC_CURRENT_CONNECT_STATUS != 0
+        );
+        if portsc & PORTSC_CURRENT_CONNECT_STATUS == 0 {
+            return Ok(());
+        }
+
+        if self.addressing_port.is_some() {
+            self.set_phase(port, ConfigPhase::WaitingAddressed);
+            return Ok(());
+        }
+        match self.phase(port) {
+            ConfigPhase::NotConnected | ConfigPhase::WaitingAddressed => {}
+            _ => bail!(ErrorKind::InvalidPhase),
+        }
+        self.addressing_port = Some(port);
+        self.set_phase(port, ConfigPhase::ResettingPort);
+        // write 1 to PR to reset, and to CSC to clear the change
+        let portsc =
+            portsc & PORTSC_PRESERVE_MASK | PORTSC_PORT_RESET | PORTSC_CONNECT_STATUS_CHANGE;
+        self.registers.set_portsc(port, portsc);
+        wait_until(|| self.registers.portsc(port) & PORTSC_PORT_RESET == 0);
+        Ok(())
+    }
+
+    fn enable_slot(&mut self, port: u8) -> Result<()> {
+        let portsc = self.registers.portsc(port);
+        let is_enabled = portsc & PORTSC_PORT_ENABLED != 0;
+        let reset_completed = portsc & PORTSC_PORT_RESET_CHANGE != 0;
+        trace!(
+            "enable_slot: port {} is enabled = {}, reset completed = {}",
+            port,
+            is_enabled,
+            reset_completed
+        );
+        if is_enabled && reset_completed {
+            let portsc = portsc & PORTSC_PRESERVE_MASK | PORTSC_PORT_RESET_CHANGE;
+            self.registers.set_portsc(port, portsc);
+            self.set_phase(port, ConfigPhase::EnablingSlot);
+            self.push_command(Trb::enable_slot_command());
+        }
+        Ok(())
+    }
+
+    fn address_device(&mut self, port: u8, slot_id: u8) -> Result<()> {
+        trace!("address_device: port {}, slot {}", port, slot_id);
+        if slot_id == 0 || usize::from(slot_id) >= self.devices.len() {
+            bail!(ErrorKind::InvalidSlotID(slot_id));
+        }
+        let mut dev = Device::new(slot_id, port, self.registers, self.context_size)?;
+
+        let speed = self.registers.port_speed(port);
+        let mut slot = SlotContext::default();
+        slot.set_route_string(0);
+        slot.set_root_hub_port_num(port);
+        slot.set_context_entries(1);
+        slot.set_speed(speed);
+
+        let ep0_dci = EndpointId::DEFAULT_CONTROL_PIPE.address();
+        let mut ep0 = EndpointContext::default();
+        ep0.set_ep_type(4); // control
+        ep0.set_max_packet_size(max_packet_size_for_control_pipe(speed));
+        ep0.set_transfer_ring(dev.alloc_transfer_ring(ep0_dci, TRANSFER_RING_LEN)?, true);
+        ep0.set_error_count(3);
+
+        let input_context = dev.input_context();
+        input_context.clear_control();
+        input_context.set_slot(slot);
+        input_context.set_endpoint(ep0_dci, ep0);
+        let input_context_addr = input_context.phys_addr();
+
+        let offset = usize::from(slot_id) * 8;
+        let device_context_addr = dev.device_context().phys_addr();
+        self.dcbaa.as_mut_slice()[offset..offset + 8]
+            .copy_from_slice(&device_context_addr.to_le_bytes());
+        self.devices[usize::from(slot_id)] = Some(dev);
+
+        self.set_phase(port, ConfigPhase::AddressingDevice);
+        self.push_command(Trb::address_device_command(input_context_addr, slot_id));
+        Ok(())
+    }
+
+    fn initialize_device(&mut self, port: u8, slot_id: u8) -> Result<()> {
+        trace!("initialize_device: port {}, slot {}", port, slot_id);
+        self.device_mut(slot_id)?.start_initialize()?;
+        self.set_phase(port, ConfigPhase::InitializingDevice);
+        Ok(())
+    }
+
+    fn configure_endpoints(&mut self, slot_id: u8) -> Result<()> {
+        let registers = self.registers;
+        let dev = self.device_mut(slot_id)?;
+        let port = dev.port_id();
+        let speed = registers.port_speed(port);
+        if speed == 0 || speed > SUPER_SPEED_PLUS {
+            bail!(ErrorKind::UnknownXHCISpeedID);
+        }
+
+        let mut slot = dev.device_context().slot();
+        slot.set_context_entries(31);
+        let mut endpoints = Vec::new();
+        for config in dev.endpoint_configs().to_vec() {
+            let dci = config.ep_id.address();
+            let mut ep = EndpointContext::default();
+            let ep_type = match (config.ep_type, config.ep_id.is_in()) {
+                (EndpointType::Control, _) => 4,
+                (EndpointType::Isochronous, false) => 1,
+                (EndpointType::Bulk, false) => 2,
+                (EndpointType::Interrupt, false) => 3,
+                (EndpointType::Isochronous, true) => 5,
+                (EndpointType::Bulk, true) => 6,
+                (EndpointType::Interrupt, true) => 7,
+            };
+            ep.set_ep_type(ep_type);
+            ep.set_max_packet_size(config.max_packet_size);
+            ep.set_interval(convert_interval(speed, config.ep_type, config.interval));
+            ep.set_transfer_ring(dev.alloc_transfer_ring(dci, TRANSFER_RING_LEN)?, true);
+            match config.ep_type {
+                EndpointType::Isochronous => {
+                    // isochronous transfers are never retried
+                    ep.set_error_count(0);
+                    ep.set_average_trb_length(config.max_packet_size);
+                    ep.set_max_esit_payload(config.max_packet_size);
+                }
+                EndpointType::Interrupt => {
+                    ep.set_error_count(3);
+                    ep.set_average_trb_length(1);
+                    ep.set_max_esit_payload(config.max_packet_size);
+                }
+                EndpointType::Control | EndpointType::Bulk => {
+                    ep.set_error_count(3);
+                    ep.set_average_trb_length(1);
+                }
+            }
+            endpoints.push((dci, ep));
+        }
+
+        let input_context = dev.input_context();
+        input_context.clear_control();
+        input_context.set_slot(slot);
+        for (dci, ep) in endpoints {
+            input_context.set_endpoint(dci, ep);
+        }
+        let input_context_addr = input_context.phys_addr();
+
+        self.set_phase(port, ConfigPhase::ConfiguringEndpoints);
+        self.push_command(Trb::configure_endpoint_command(input_context_addr, slot_id));
+        Ok(())
+    }
+
+    fn complete_configuration(&mut self, port: u8, slot_id: u8) -> Result<()> {
+        trace!("complete_configuration: port {}, slot {}", port, slot_id);
+        self.device_mut(slot_id)?.on_endpoints_configured()?;
+        self.set_phase(port, ConfigPhase::Configured);
+        Ok(())
+    }
+
+    /// Starts the next enumeration waiting for `port`, which is no longer being addressed.
+    fn release_addressing_port(&mut self, port: u8) -> Result<()> {
+        if self.addressing_port != Some(port) {
+            return Ok(());
+        }
+        self.addressing_port = None;
+        let waiting_port =
+            (1..=self.max_ports).find(|port| self.phase(*port) == ConfigPhase::WaitingAddressed);
+        if let Some(waiting_port) = waiting_port {
+            self.reset_port(waiting_port)?;
+        }
+        Ok(())
+    }
+
+    fn detach_port(&mut self, port: u8) -> Result<()> {
+        debug!("xhc: port {} is disconnected", port);
+        let portsc = self.registers.portsc(port);
+        self.registers.set_portsc(
+            port,
+            portsc & PORTSC_PRESERVE_MASK | PORTSC_CONNECT_STATUS_CHANGE,
+        );
+
+        match self.phase(port) {
+            ConfigPhase::NotConnected | ConfigPhase::DisablingSlot => Ok(()),
+            ConfigPhase::WaitingAddressed | ConfigPhase::ResettingPort => {
+                self.set_phase(port, ConfigPhase::NotConnected);
+                self.release_addressing_port(port)
+            }
+            // the slot is disabled when the Enable Slot command completes
+            ConfigPhase::EnablingSlot => {
+                self.set_phase(port, ConfigPhase::DisablingSlot);
+                Ok(())
+            }
+            ConfigPhase::AddressingDevice
+            | ConfigPhase::InitializingDevice
+            | ConfigPhase::ConfiguringEndpoints
+            | ConfigPhase::Configured => {
+                let slot_id = self.port_slot[usize::from(port)].ok_or(ErrorKind::InvalidPhase)?;
+                self.set_phase(port, ConfigPhase::DisablingSlot);
+                self.push_command(Trb::disable_slot_command(slot_id));
+                Ok(())
+            }
+        }
+    }
+
+    fn complete_detach(&mut self, slot_id: u8) -> Result<()> {
+        let port = (1..=self.max_ports)
+            .find(|port| self.port_slot[usize::from(*port)] == Some(slot_id))
+            .ok_or(ErrorKind::InvalidSlotID(slot_id))?;
+        trace!("complete_detach: port {}, slot {}", port, slot_id);
+        if self.phase(port) != ConfigPhase::DisablingSlot {
+            bail!(ErrorKind::InvalidPhase);
+        }
+
+        let offset = usize::from(slot_id) * 8;
+        self.dcbaa.as_mut_slice()[offset..offset + 8].fill(0);
+        // this drops the class drivers of the device
+        self.devices[usize::from(slot_id)] = None;
+        self.port_slot[usize::from(port)] = None;
+        self.set_phase(port, ConfigPhase::NotConnected);
+        self.port_events.send(PortEvent::Detached { port });
+        self.release_addressing_port(port)?;
+
+        // a device may be attached again while the slot is disabled
+        if self.registers.portsc(port) & PORTSC_CURRENT_CONNECT_STATUS != 0 {
+            self.reset_port(port)?;
+        }
+        Ok(())
+    }
+
+    fn on_port_status_change_event(&mut self, event: &Trb) -> Result<()> {
+        let port = event.port_id();
+        trace!("port status change event: port {}", port);
+        let portsc = self.registers.portsc(port);
+        if portsc & PORTSC_CONNECT_STATUS_CHANGE != 0 && portsc & PORTSC_CURRENT_CONNECT_STATUS == 0
+        {
+            return self.detach_port(port);
+        }
+        match self.phase(port) {
+            ConfigPhase::NotConnected => self.reset_port(port),
+            ConfigPhase::ResettingPort => self.enable_slot(port),
+            // the port is reset again after the slot is disabled
+            ConfigPhase::DisablingSlot => Ok(()),
+            _ => bail!(ErrorKind::InvalidPhase),
+        }
+    }
+
+    fn on_transfer_event(&mut self, event: &Trb) -> Result<()> {
+        let slot_id = event.slot_id();
+        let port = self.device_mut(slot_id)?.port_id();
+        if self.phase(port) == ConfigPhase::DisablingSlot {
+            // transfers of the detached device may be aborted
+            return Ok(());
+        }
+        let dev = self.device_mut(slot_id)?;
+        dev.on_transfer_event(event)?;
+
+        if dev.is_initialized() && self.phase(port) == ConfigPhase::InitializingDevice {
+            return self.configure_endpoints(slot_id);
+        }
+        Ok(())
+    }
+
+    fn on_command_completion_event(&mut self, event: &Trb) -> Result<()> {
+        let slot_id = event.slot_id();
+        let issuer_type = self
+            .command_ring
+            .read(event.pointer())
+            .map(|trb| trb.trb_type())
+            .ok_or(ErrorKind::InvalidPhase)?;
+        trace!(
+            "command completion event: slot {}, issuer type {}, code {}",
+            slot_id,
+            issuer_type,
+            event.completion_code()
+        );
+        if issuer_type != trb::DISABLE_SLOT_COMMAND {
+            let port = self
+                .port_slot
+                .iter()
+                .position(|slot| *slot == Some(slot_id));
+            if let Some(port) = port {
+                if self.phase(port as u8) == ConfigPhase::DisablingSlot {
+                    // the command was issued before the device was detached
+                    return Ok(());
+                }
+            }
+        }
+        if event.completion_code() != trb::SUCCESS {
+            bail!(ErrorKind::CommandFailed {
+                command: trb::command_name(issuer_type),
+                slot_id,
+                code: event.completion_code(),
+            });
+        }
+
+        match issuer_type {
+            trb::ENABLE_SLOT_COMMAND => {
+                let port = self.addressing_port.ok_or(ErrorKind::InvalidPhase)?;
+                self.port_slot[usize::from(port)] = Some(slot_id);
+                match self.phase(port) {
+                    ConfigPhase::EnablingSlot => self.address_device(port, slot_id),
+                    ConfigPhase::DisablingSlot => {
+                        self.push_command(Trb::disable_slot_command(slot_id));
+                        Ok(())
+                    }
+                    _ => bail!(ErrorKind::InvalidPhase),
+                }
+            }
+            trb::DISABLE_SLOT_COMMAND => self.complete_detach(slot_id),
+            trb::ADDRESS_DEVICE_COMMAND => {
+                let port = self.device_mut(slot_id)?.port_id();
+                if Some(port) != self.addressing_port
+                    || self.phase(port) != ConfigPhase::AddressingDevice
+                {
+                    bail!(ErrorKind::InvalidPhase);
+                }
+
+                self.release_addressing_port(port)?;
+                self.port_events.send(PortEvent::Attached { port, slot_id });
+                self.initialize_device(port, slot_id)
+            }
+            trb::CONFIGURE_ENDPOINT_COMMAND => {
+                let port = self.device_mut(slot_id)?.port_id();
+                if self.phase(port) != ConfigPhase::ConfiguringEndpoints {
+                    bail!(ErrorKind::InvalidPhase);
+                }
+                self.complete_configuration(port, slot_id)
+            }
+            _ => bail!(ErrorKind::InvalidPhase),
+        }
+    }
+}
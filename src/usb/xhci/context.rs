@@ -0,0 +1,157 @@
+//! Device contexts and input contexts.
+//!
+//! The controller reports the size of each context, 32 or 64 bytes. Only the first 32 bytes of a
+//! context are defined, so the contexts are built in [`SlotContext`] and [`EndpointContext`], and
+//! copied to the DMA buffers at the offsets for the context size.
+
+use crate::{dma::DmaBuffer, prelude::*};
+use bit_field::BitField;
+use core::ptr;
+
+/// The number of contexts in a device context: the slot context and 31 endpoint contexts.
+const NUM_CONTEXTS: usize = 32;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[repr(C)]
+pub(super) struct SlotContext {
+    dwords: [u32; 8],
+}
+
+impl SlotContext {
+    pub(super) fn set_route_string(&mut self, route_string: u32) {
+        self.dwords[0].set_bits(0..20, route_string);
+    }
+
+    pub(super) fn set_speed(&mut self, speed: u8) {
+        self.dwords[0].set_bits(20..24, u32::from(speed));
+    }
+
+    /// Sets the index of the last valid endpoint context.
+    pub(super) fn set_context_entries(&mut self, entries: u8) {
+        self.dwords[0].set_bits(27..32, u32::from(entries));
+    }
+
+    pub(super) fn set_root_hub_port_num(&mut self, port: u8) {
+        self.dwords[1].set_bits(16..24, u32::from(port));
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[repr(C)]
+pub(super) struct EndpointContext {
+    dwords: [u32; 8],
+}
+
+impl EndpointContext {
+    pub(super) fn set_interval(&mut self, interval: u8) {
+        self.dwords[0].set_bits(16..24, u32::from(interval));
+    }
+
+    pub(super) fn set_error_count(&mut self, count: u8) {
+        self.dwords[1].set_bits(1..3, u32::from(count));
+    }
+
+    pub(super) fn set_ep_type(&mut self, ep_type: u8) {
+        self.dwords[1].set_bits(3..6, u32::from(ep_type));
+    }
+
+    pub(super) fn set_max_packet_size(&mut self, size: u16) {
+        self.dwords[1].set_bits(16..32, u32::from(size));
+    }
+
+    /// Sets the TR Dequeue Pointer and the Dequeue Cycle State.
+    pub(super) fn set_transfer_ring(&mut self, ring_addr: u64, cycle_state: bool) {
+        self.dwords[2] = (ring_addr as u32 & !0xf) | u32::from(cycle_state);
+        self.dwords[3] = (ring_addr >> 32) as u32;
+    }
+
+    pub(super) fn set_average_trb_length(&mut self, len: u16) {
+        self.dwords[4].set_bits(0..16, u32::from(len));
+    }
+
+    /// Sets the maximum bytes transferred in a service interval of periodic endpoints.
+    pub(super) fn set_max_esit_payload(&mut self, len: u16) {
+        self.dwords[4].set_bits(16..32, u32::from(len));
+    }
+}
+
+fn alloc_contexts(count: usize, context_size: usize) -> Result<DmaBuffer> {
+    DmaBuffer::builder(count * context_size)
+        .below_4gib()
+        .build()
+}
+
+/// The output device context of a slot, which is written by the controller.
+#[derive(Debug)]
+pub(super) struct DeviceContext {
+    buffer: DmaBuffer,
+}
+
+impl DeviceContext {
+    pub(super) fn new(context_size: usize) -> Result<Self> {
+        let buffer = alloc_contexts(NUM_CONTEXTS, context_size)?;
+        Ok(Self { buffer })
+    }
+
+    pub(super) fn phys_addr(&self) -> u64 {
+        self.buffer.phys_addr().as_u64()
+    }
+
+    pub(super) fn slot(&self) -> SlotContext {
+        unsafe { ptr::read_volatile(self.buffer.virt_addr().as_ptr()) }
+    }
+}
+
+/// The input context of a slot, which is passed to commands.
+#[derive(Debug)]
+pub(super) struct InputContext {
+    buffer: DmaBuffer,
+    context_size: usize,
+}
+
+impl InputContext {
+    pub(super) fn new(context_size: usize) -> Result<Self> {
+        // the input control context is followed by a device context
+        let buffer = alloc_contexts(NUM_CONTEXTS + 1, context_size)?;
+        Ok(Self {
+            buffer,
+            context_size,
+        })
+    }
+
+    pub(super) fn phys_addr(&self) -> u64 {
+        self.buffer.phys_addr().as_u64()
+    }
+
+    fn write<T>(&mut self, index: usize, value: T) {
+        let offset = index * self.context_size;
+        let ptr = (self.buffer.virt_addr() + offset).as_mut_ptr();
+        unsafe { ptr::write_volatile(ptr, value) };
+    }
+
+    /// Clears the Drop and Add Context flags of the input control context.
+    pub(super) fn clear_control(&mut self) {
+        self.write(0, [0u32; 2]);
+    }
+
+    fn add_context(&mut self, index: usize) {
+        let flags = self.buffer.as_slice();
+        let add_flags = u32::from_le_bytes([flags[4], flags[5], flags[6], flags[7]]);
+        let add_flags = add_flags | 1 << index;
+        self.buffer.as_mut_slice()[4..8].copy_from_slice(&add_flags.to_le_bytes());
+    }
+
+    /// Writes the slot context, and sets its Add Context flag.
+    pub(super) fn set_slot(&mut self, slot: SlotContext) {
+        self.add_context(0);
+        self.write(1, slot);
+    }
+
+    /// Writes the endpoint context of device context index `dci`, and sets its Add Context flag.
+    pub(super) fn set_endpoint(&mut self, dci: u8, endpoint: EndpointContext) {
+        let dci = usize::from(dci);
+        assert!((1..NUM_CONTEXTS).contains(&dci));
+        self.add_context(dci);
+        self.write(dci + 1, endpoint);
+    }
+}
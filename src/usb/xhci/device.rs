@@ -0,0 +1,532 @@
+//! USB devices attached to device slots.
+//!
+//! After the controller addresses a device, the device is initialized by control transfers on the
+//! default control pipe: the device descriptor and the configuration descriptor are read, class
+//! drivers are created for the supported interface, and the configuration is selected. The
+//! controller then configures the endpoints of the drivers.
+//!
+//! Isochronous transfers are not retried: a packet lost on the bus is completed to the class
+//! driver as usual, so that streams keep running.
+
+use super::{
+    context::{DeviceContext, InputContext},
+    registers::Registers,
+    ring::Ring,
+    trb::{self, Trb},
+};
+use crate::{
+    dma::DmaBuffer,
+    prelude::*,
+    usb::{
+        self,
+        descriptor::{
+            self, ConfigurationDescriptor, Descriptors, DeviceDescriptor, EndpointDescriptor,
+            InterfaceDescriptor,
+        },
+        request, ClassDriver, EndpointConfig, EndpointId, InterfaceInfo, SetupData,
+    },
+};
+use alloc::{boxed::Box, vec::Vec};
+use custom_debug_derive::Debug as CustomDebug;
+
+/// The length of the configuration descriptor requested, including the descriptors following it.
+const CONFIGURATION_BUFFER_LEN: u16 = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum InitPhase {
+    NotStarted,
+    DeviceDescriptor,
+    ConfigurationDescriptor,
+    SetConfiguration,
+    Initialized,
+}
+
+/// A control transfer waiting for its completion.
+#[derive(Debug)]
+struct ControlTransfer {
+    /// The TRB whose completion is reported.
+    trb_addr: u64,
+    setup: SetupData,
+    buffer: Option<DmaBuffer>,
+    /// The class driver which issued the transfer.
+    issuer: Option<usize>,
+}
+
+/// An isochronous transfer waiting for its completion.
+#[derive(Debug)]
+struct IsochTransfer {
+    trb_addr: u64,
+    buffer: DmaBuffer,
+}
+
+#[derive(CustomDebug)]
+pub(crate) struct Device {
+    slot_id: u8,
+    port_id: u8,
+    registers: Registers,
+    input_context: InputContext,
+    device_context: DeviceContext,
+    /// Indexed by device context index - 1.
+    transfer_rings: [Option<Ring>; 31],
+    /// Buffers of interrupt transfers, indexed by device context index - 1.
+    interrupt_buffers: [Option<DmaBuffer>; 31],
+    control_transfers: Vec<ControlTransfer>,
+    isoch_transfers: Vec<IsochTransfer>,
+    /// Buffers of completed isochronous transfers, which are reused by later transfers.
+    isoch_buffers: Vec<DmaBuffer>,
+    phase: InitPhase,
+    descriptor: Option<DeviceDescriptor>,
+    interfaces: Vec<InterfaceInfo>,
+    ep_configs: Vec<EndpointConfig>,
+    #[debug(skip)]
+    class_drivers: Vec<Option<Box<dyn ClassDriver>>>,
+    /// The index of the class driver in charge of each endpoint number.
+    endpoint_drivers: [Option<usize>; 16],
+}
+
+impl Device {
+    pub(super) fn new(
+        slot_id: u8,
+        port_id: u8,
+        registers: Registers,
+        context_size: usize,
+    ) -> Result<Self> {
+        Ok(Self {
+            slot_id,
+            port_id,
+            registers,
+            input_context: InputContext::new(context_size)?,
+            device_context: DeviceContext::new(context_size)?,
+            transfer_rings: Default::default(),
+            interrupt_buffers: Default::default(),
+            control_transfers: Vec::new(),
+            isoch_transfers: Vec::new(),
+            isoch_buffers: Vec::new(),
+            phase: InitPhase::NotStarted,
+            descriptor: None,
+            interfaces: Vec::new(),
+            ep_configs: Vec::new(),
+            class_drivers: Vec::new(),
+            endpoint_drivers: [None; 16],
+        })
+    }
+
+    pub(super) fn input_context(&mut self) -> &mut InputContext {
+        &mut self.input_context
+    }
+
+    pub(super) fn device_context(&self) -> &DeviceContext {
+        &self.device_context
+    }
+
+    /// Returns the root hub port number the device is attached to.
+    pub(super) fn port_id(&self) -> u8 {
+        self.port_id
+    }
+
+    pub(super) fn descriptor(&self) -> Option<DeviceDescriptor> {
+        self.descriptor
+    }
+
+    pub(super) fn interfaces(&self) -> &[InterfaceInfo] {
+        &self.interfaces
+    }
+
+    pub(super) fn is_initialized(&self) -> bool {
+        self.phase == InitPhase::Initialized
+    }
+
+    pub(super) fn endpoint_configs(&self) -> &[EndpointConfig] {
+        &self.ep_configs
+    }
+
+    /// Allocates the transfer ring of device context index `dci`, and returns its address.
+    pub(super) fn alloc_transfer_ring(&mut self, dci: u8, len: usize) -> Result<u64> {
+        let ring = Ring::new(len)?;
+        let addr = ring.phys_addr();
+        self.transfer_rings[usize::from(dci) - 1] = Some(ring);
+        Ok(addr)
+    }
+
+    fn transfer_ring(&mut self, dci: u8) -> Result<&mut Ring> {
+        let ring = self
+            .transfer_rings
+            .get_mut(usize::from(dci).wrapping_sub(1))
+            .and_then(Option::as_mut);
+        Ok(ring.ok_or(ErrorKind::TransferRingNotSet {
+            slot_id: self.slot_id,
+            dci,
+        })?)
+    }
+
+    fn control(
+        &mut self,
+        ep_id: EndpointId,
+        setup: SetupData,
+        data: Option<&[u8]>,
+        issuer: Option<usize>,
+    ) -> Result<()> {
+        if ep_id.number() > 15 {
+            bail!(ErrorKind::InvalidEndpointNumber(ep_id.number()));
+        }
+        let buffer = match (data, setup.length) {
+            (_, 0) => None,
+            (data, len) => {
+                let mut buffer = DmaBuffer::builder(usize::from(len)).below_4gib().build()?;
+                if let Some(data) = data {
+                    let len = usize::min(data.len(), usize::from(len));
+                    buffer.as_mut_slice()[..len].copy_from_slice(&data[..len]);
+                }
+                Some(buffer)
+            }
+        };
+
+        let dci = ep_id.address();
+        let ring = self.transfer_ring(dci)?;
+        ring.push(Trb::setup_stage(&setup, buffer.is_some()));
+        let trb_addr = match &buffer {
+            Some(buffer) => {
+                let addr = ring.push(Trb::data_stage(
+                    buffer.phys_addr().as_u64(),
+                    u32::from(setup.length),
+                    setup.is_in(),
+                ));
+                // the status stage is in the opposite direction of the data stage
+                ring.push(Trb::status_stage(!setup.is_in(), false));
+                addr
+            }
+            None => ring.push(Trb::status_stage(true, true)),
+        };
+        self.control_transfers.push(ControlTransfer {
+            trb_addr,
+            setup,
+            buffer,
+            issuer,
+        });
+        self.registers.ring_doorbell(self.slot_id, dci);
+        Ok(())
+    }
+
+    fn interrupt_in(&mut self, ep_id: EndpointId, len: u16) -> Result<()> {
+        let dci = ep_id.address();
+        let index = usize::from(dci) - 1;
+        let buffer = match &mut self.interrupt_buffers[index] {
+            Some(buffer) if buffer.len() >= usize::from(len) => buffer,
+            buffer => buffer.insert(DmaBuffer::builder(usize::from(len)).below_4gib().build()?),
+        };
+        let trb = Trb::normal(buffer.phys_addr().as_u64(), u32::from(len));
+        self.transfer_ring(dci)?.push(trb);
+        self.registers.ring_doorbell(self.slot_id, dci);
+        Ok(())
+    }
+
+    fn isoch_out(&mut self, ep_id: EndpointId, data: &[u8]) -> Result<()> {
+        let dci = ep_id.address();
+        let mut buffer = match self.isoch_buffers.pop() {
+            Some(buffer) if buffer.len() >= data.len() => buffer,
+            _ => DmaBuffer::builder(data.len()).below_4gib().build()?,
+        };
+        buffer.as_mut_slice()[..data.len()].copy_from_slice(data);
+        let trb = Trb::isoch(buffer.phys_addr().as_u64(), data.len() as u32);
+        let trb_addr = self.transfer_ring(dci)?.push(trb);
+        self.isoch_transfers
+            .push(IsochTransfer { trb_addr, buffer });
+        self.registers.ring_doorbell(self.slot_id, dci);
+        Ok(())
+    }
+
+    /// Starts the initialization by reading the device descriptor.
+    pub(super) fn start_initialize(&mut self) -> Result<()> {
+        self.phase = InitPhase::DeviceDescriptor;
+        let setup = SetupData::get_descriptor(descriptor::DEVICE, 0, DeviceDescriptor::LEN as u16);
+        self.control(EndpointId::DEFAULT_CONTROL_PIPE, setup, None, None)
+    }
+
+    pub(super) fn on_endpoints_configured(&mut self) -> Result<()> {
+        for index in 0..self.class_drivers.len() {
+            self.with_driver(index, |driver, dev| driver.on_endpoints_configured(dev))?;
+        }
+        Ok(())
+    }
+
+    /// Handles a transfer event of this slot.
+    pub(super) fn on_transfer_event(&mut self, event: &Trb) -> Result<()> {
+        let code = event.completion_code();
+        let dci = event.endpoint_id();
+        if code != trb::SUCCESS && code != trb::SHORT_PACKET {
+            let is_isoch = self
+                .transfer_ring(dci)?
+                .read(event.pointer())
+                .map_or(false, |trb| trb.trb_type() == trb::ISOCH);
+            if !is_isoch {
+                bail!(ErrorKind::TransferFailed {
+                    slot_id: self.slot_id,
+                    dci,
+                    code,
+                });
+            }
+            trace!(
+                "usb: isochronous packet lost: slot {}, dci {}, code {}",
+                self.slot_id,
+                dci,
+                code
+            );
+        }
+        let residual_length = event.transfer_length() as usize;
+        let slot_id = self.slot_id;
+        let no_issuer = || ErrorKind::NoCorrespondingTrb {
+            slot_id,
+            dci,
+            trb_addr: event.pointer(),
+        };
+        let issuer_trb = self
+            .transfer_ring(dci)?
+            .read(event.pointer())
+            .ok_or_else(no_issuer)?;
+
+        if issuer_trb.trb_type() == trb::ISOCH {
+            let index = self
+                .isoch_transfers
+                .iter()
+                .position(|transfer| transfer.trb_addr == event.pointer())
+                .ok_or_else(no_issuer)?;
+            let transfer = self.isoch_transfers.swap_remove(index);
+            self.isoch_buffers.push(transfer.buffer);
+            return self.on_isoch_completed(EndpointId::from_address(dci));
+        }
+
+        if issuer_trb.trb_type() == trb::NORMAL {
+            let len = (issuer_trb.transfer_length() as usize).saturating_sub(residual_length);
+            let buffer = self.interrupt_buffers[usize::from(dci) - 1]
+                .as_ref()
+                .ok_or_else(no_issuer)?;
+            let data = buffer.as_slice()[..len].to_vec();
+            return self.on_interrupt_completed(EndpointId::from_address(dci), &data);
+        }
+
+        let index = self
+            .control_transfers
+            .iter()
+            .position(|transfer| transfer.trb_addr == event.pointer())
+            .ok_or_else(no_issuer)?;
+        let transfer = self.control_transfers.swap_remove(index);
+        let data = match (issuer_trb.trb_type(), &transfer.buffer) {
+            (trb::DATA_STAGE, Some(buffer)) => {
+                let len = (issuer_trb.transfer_length() as usize).saturating_sub(residual_length);
+                buffer.as_slice()[..len].to_vec()
+            }
+            (trb::STATUS_STAGE, _) => Vec::new(),
+            _ => bail!(ErrorKind::NotImplemented),
+        };
+        self.on_control_completed(transfer.setup, &data, transfer.issuer)
+    }
+
+    fn with_driver(
+        &mut self,
+        index: usize,
+        f: impl FnOnce(&mut dyn ClassDriver, &mut DeviceHandle<'_>) -> Result<()>,
+    ) -> Result<()> {
+        // the driver is taken out while it is called, so that it can issue transfers
+        let mut driver = self
+            .class_drivers
+            .get_mut(index)
+            .and_then(Option::take)
+            .ok_or(ErrorKind::NoWaiter)?;
+        let res = f(
+            &mut *driver,
+            &mut DeviceHandle {
+                device: self,
+                driver: index,
+            },
+        );
+        self.class_drivers[index] = Some(driver);
+        res
+    }
+
+    fn on_control_completed(
+        &mut self,
+        setup: SetupData,
+        data: &[u8],
+        issuer: Option<usize>,
+    ) -> Result<()> {
+        if self.is_initialized() {
+            let issuer = issuer.ok_or(ErrorKind::NoWaiter)?;
+            return self.with_driver(issuer, |driver, dev| {
+                driver.on_control_completed(dev, setup, data)
+            });
+        }
+
+        match (self.phase, setup.request) {
+            (InitPhase::DeviceDescriptor, request::GET_DESCRIPTOR) => {
+                self.on_device_descriptor_received(data)
+            }
+            (InitPhase::ConfigurationDescriptor, request::GET_DESCRIPTOR) => {
+                self.on_configuration_descriptor_received(data)
+            }
+            (InitPhase::SetConfiguration, request::SET_CONFIGURATION) => {
+                self.on_set_configuration_completed()
+            }
+            _ => bail!(ErrorKind::InvalidPhase),
+        }
+    }
+
+    fn on_device_descriptor_received(&mut self, data: &[u8]) -> Result<()> {
+        let slot_id = self.slot_id;
+        let invalid = || ErrorKind::InvalidDescriptor {
+            slot_id,
+            desc_type: descriptor::DEVICE,
+        };
+        let desc = DeviceDescriptor::parse(data).ok_or_else(invalid)?;
+        debug!(
+            "usb: slot {}: device {:04x}:{:04x}, class {:02x}:{:02x}:{:02x}, USB {:x}.{:02x}",
+            self.slot_id,
+            desc.vendor_id,
+            desc.product_id,
+            desc.device_class,
+            desc.device_sub_class,
+            desc.device_protocol,
+            desc.usb_release >> 8,
+            desc.usb_release & 0xff,
+        );
+        self.descriptor = Some(desc);
+        if desc.num_configurations == 0 {
+            bail!(invalid());
+        }
+
+        self.phase = InitPhase::ConfigurationDescriptor;
+        let setup =
+            SetupData::get_descriptor(descriptor::CONFIGURATION, 0, CONFIGURATION_BUFFER_LEN);
+        self.control(EndpointId::DEFAULT_CONTROL_PIPE, setup, None, None)
+    }
+
+    fn on_configuration_descriptor_received(&mut self, data: &[u8]) -> Result<()> {
+        let config = ConfigurationDescriptor::parse(data).ok_or(ErrorKind::InvalidDescriptor {
+            slot_id: self.slot_id,
+            desc_type: descriptor::CONFIGURATION,
+        })?;
+        let len = usize::min(data.len(), usize::from(config.total_length));
+        let mut descs = Descriptors::new(&data[..len]);
+
+        // the first interface supported by a class driver is used, and the rest are only recorded
+        let mut class_driver = None;
+        while let Some(desc) = descs.next() {
+            let interface = match InterfaceDescriptor::parse(desc) {
+                Some(interface) => interface,
+                None => continue,
+            };
+            debug!(
+                "usb: slot {}: interface {}.{}, class {:02x}:{:02x}:{:02x}",
+                self.slot_id,
+                interface.interface_number,
+                interface.alternate_setting,
+                interface.interface_class,
+                interface.interface_sub_class,
+                interface.interface_protocol,
+            );
+            let driver = if class_driver.is_none() {
+                usb::new_class_driver(&interface)
+            } else {
+                None
+            };
+            self.interfaces.push(InterfaceInfo {
+                descriptor: interface,
+                driver: driver.as_ref().map(|driver| driver.name()),
+            });
+            let driver = match driver {
+                Some(driver) => driver,
+                None => continue,
+            };
+            class_driver = Some(driver);
+            let driver_index = self.class_drivers.len();
+            // class-specific descriptors (e.g. HID) may be placed between endpoint descriptors
+            let endpoints = descs
+                .by_ref()
+                .filter_map(EndpointDescriptor::parse)
+                .take(usize::from(interface.num_endpoints));
+            for endpoint in endpoints {
+                let config = endpoint.config();
+                self.endpoint_drivers[usize::from(config.ep_id.number())] = Some(driver_index);
+                self.ep_configs.push(config);
+            }
+        }
+
+        let class_driver = match class_driver {
+            Some(class_driver) => class_driver,
+            None => {
+                info!(
+                    "usb: slot {}: no class driver for {} interfaces",
+                    self.slot_id, config.num_interfaces
+                );
+                return Ok(());
+            }
+        };
+        self.class_drivers.push(Some(class_driver));
+
+        self.phase = InitPhase::SetConfiguration;
+        let setup = SetupData::set_configuration(config.configuration_value);
+        self.control(EndpointId::DEFAULT_CONTROL_PIPE, setup, None, None)
+    }
+
+    fn on_set_configuration_completed(&mut self) -> Result<()> {
+        for config in &self.ep_configs {
+            let index = self.endpoint_drivers[usize::from(config.ep_id.number())];
+            let class_drivers = &mut self.class_drivers;
+            if let Some(driver) = index.and_then(|index| class_drivers[index].as_mut()) {
+                driver.set_endpoint(config);
+            }
+        }
+        self.phase = InitPhase::Initialized;
+        Ok(())
+    }
+
+    fn on_isoch_completed(&mut self, ep_id: EndpointId) -> Result<()> {
+        let index = self.endpoint_drivers[usize::from(ep_id.number())].ok_or(
+            ErrorKind::EndpointNotInCharge {
+                slot_id: self.slot_id,
+                ep_addr: ep_id.address(),
+            },
+        )?;
+        self.with_driver(index, |driver, dev| driver.on_isoch_completed(dev, ep_id))
+    }
+
+    fn on_interrupt_completed(&mut self, ep_id: EndpointId, data: &[u8]) -> Result<()> {
+        let index = self.endpoint_drivers[usize::from(ep_id.number())].ok_or(
+            ErrorKind::EndpointNotInCharge {
+                slot_id: self.slot_id,
+                ep_addr: ep_id.address(),
+            },
+        )?;
+        self.with_driver(index, |driver, dev| {
+            driver.on_interrupt_completed(dev, ep_id, data)
+        })
+    }
+}
+
+/// The device passed to the callbacks of a class driver.
+///
+/// Control transfers issued through the handle are completed to the driver.
+#[derive(Debug)]
+pub(crate) struct DeviceHandle<'a> {
+    device: &'a mut Device,
+    driver: usize,
+}
+
+impl DeviceHandle<'_> {
+    /// Issues an OUT control transfer with `data` on the default control pipe.
+    pub(crate) fn control_out(&mut self, setup: SetupData, data: &[u8]) -> Result<()> {
+        let ep_id = EndpointId::DEFAULT_CONTROL_PIPE;
+        self.device
+            .control(ep_id, setup, Some(data), Some(self.driver))
+    }
+
+    /// Issues an interrupt IN transfer of at most `len` bytes.
+    pub(crate) fn interrupt_in(&mut self, ep_id: EndpointId, len: u16) -> Result<()> {
+        self.device.interrupt_in(ep_id, len)
+    }
+
+    /// Issues an isochronous OUT transfer of a packet of `data`, which is sent in the next
+    /// service interval available.
+    pub(crate) fn isoch_out(&mut self, ep_id: EndpointId, data: &[u8]) -> Result<()> {
+        self.device.isoch_out(ep_id, data)
+    }
+}
@@ -0,0 +1,212 @@
+//! xHCI registers.
+//!
+//! The capability registers are at the start of the MMIO region, and give the offsets of the
+//! operational, runtime and doorbell registers. Only the primary interrupter is used.
+
+use crate::mmio::VolatileMmio;
+use bit_field::BitField;
+
+// capability registers
+const CAPLENGTH: u64 = 0x00;
+const HCIVERSION: u64 = 0x02;
+const HCSPARAMS1: u64 = 0x04;
+const HCSPARAMS2: u64 = 0x08;
+const HCCPARAMS1: u64 = 0x10;
+const DBOFF: u64 = 0x14;
+const RTSOFF: u64 = 0x18;
+
+// operational registers
+const USBCMD: u64 = 0x00;
+const USBSTS: u64 = 0x04;
+const PAGESIZE: u64 = 0x08;
+const CRCR: u64 = 0x18;
+const DCBAAP: u64 = 0x30;
+const CONFIG: u64 = 0x38;
+const PORT_REGISTER_SETS: u64 = 0x400;
+const PORT_REGISTER_SET_SIZE: u64 = 0x10;
+
+// runtime registers of the primary interrupter
+const IMAN: u64 = 0x20;
+const ERSTSZ: u64 = 0x28;
+const ERSTBA: u64 = 0x30;
+const ERDP: u64 = 0x38;
+
+pub(super) const USBCMD_RUN_STOP: u32 = 1 << 0;
+pub(super) const USBCMD_HOST_CONTROLLER_RESET: u32 = 1 << 1;
+pub(super) const USBCMD_INTERRUPTER_ENABLE: u32 = 1 << 2;
+pub(super) const USBCMD_HOST_SYSTEM_ERROR_ENABLE: u32 = 1 << 3;
+pub(super) const USBCMD_ENABLE_WRAP_EVENT: u32 = 1 << 10;
+
+pub(super) const USBSTS_HOST_CONTROLLER_HALTED: u32 = 1 << 0;
+pub(super) const USBSTS_CONTROLLER_NOT_READY: u32 = 1 << 11;
+
+pub(super) const CRCR_RING_CYCLE_STATE: u64 = 1 << 0;
+
+pub(super) const PORTSC_CURRENT_CONNECT_STATUS: u32 = 1 << 0;
+pub(super) const PORTSC_PORT_ENABLED: u32 = 1 << 1;
+pub(super) const PORTSC_PORT_RESET: u32 = 1 << 4;
+pub(super) const PORTSC_CONNECT_STATUS_CHANGE: u32 = 1 << 17;
+pub(super) const PORTSC_PORT_RESET_CHANGE: u32 = 1 << 21;
+/// The bits of PORTSC which are preserved by writing back the read value. The other bits are
+/// either cleared by writing 1 (RW1C) or have side effects.
+pub(super) const PORTSC_PRESERVE_MASK: u32 = 0x0e00_c3e0;
+
+pub(super) const IMAN_INTERRUPT_PENDING: u32 = 1 << 0;
+pub(super) const IMAN_INTERRUPT_ENABLE: u32 = 1 << 1;
+
+pub(super) const ERDP_EVENT_HANDLER_BUSY: u64 = 1 << 3;
+
+/// USB Legacy Support Capability
+pub(super) const EXT_CAP_LEGACY_SUPPORT: u8 = 1;
+pub(super) const USBLEGSUP_BIOS_OWNED: u32 = 1 << 16;
+pub(super) const USBLEGSUP_OS_OWNED: u32 = 1 << 24;
+
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Registers {
+    capability: VolatileMmio,
+    operational: VolatileMmio,
+    runtime: VolatileMmio,
+    doorbell: VolatileMmio,
+}
+
+fn split_at(mmio: VolatileMmio, offset: u64) -> VolatileMmio {
+    mmio.subrange(offset, mmio.size() - offset)
+}
+
+impl Registers {
+    pub(super) fn new(mmio: VolatileMmio) -> Self {
+        let cap_length = u64::from(mmio.read::<u8>(CAPLENGTH));
+        let dboff = u64::from(mmio.read::<u32>(DBOFF) & !0b11);
+        let rtsoff = u64::from(mmio.read::<u32>(RTSOFF) & !0b1_1111);
+        Self {
+            capability: mmio,
+            operational: split_at(mmio, cap_length),
+            runtime: split_at(mmio, rtsoff),
+            doorbell: split_at(mmio, dboff),
+        }
+    }
+
+    pub(super) fn hci_version(&self) -> u16 {
+        self.capability.read(HCIVERSION)
+    }
+
+    pub(super) fn max_device_slots(&self) -> u8 {
+        self.capability.read::<u32>(HCSPARAMS1).get_bits(0..8) as u8
+    }
+
+    pub(super) fn max_ports(&self) -> u8 {
+        self.capability.read::<u32>(HCSPARAMS1).get_bits(24..32) as u8
+    }
+
+    pub(super) fn max_scratchpad_buffers(&self) -> usize {
+        let params = self.capability.read::<u32>(HCSPARAMS2);
+        (params.get_bits(21..26) << 5 | params.get_bits(27..32)) as usize
+    }
+
+    /// Returns the size of each context in the device and input contexts, 32 or 64 bytes.
+    pub(super) fn context_size(&self) -> usize {
+        if self.capability.read::<u32>(HCCPARAMS1).get_bit(2) {
+            64
+        } else {
+            32
+        }
+    }
+
+    /// Returns the offset of the first extended capability from the MMIO base, if any.
+    pub(super) fn extended_capabilities(&self) -> Option<u64> {
+        match self.capability.read::<u32>(HCCPARAMS1).get_bits(16..32) {
+            0 => None,
+            offset => Some(u64::from(offset) << 2),
+        }
+    }
+
+    pub(super) fn read_capability(&self, offset: u64) -> u32 {
+        self.capability.read(offset)
+    }
+
+    pub(super) fn write_capability(&self, offset: u64, value: u32) {
+        self.capability.write(offset, value)
+    }
+
+    pub(super) fn usbcmd(&self) -> u32 {
+        self.operational.read(USBCMD)
+    }
+
+    pub(super) fn set_usbcmd(&self, value: u32) {
+        self.operational.write(USBCMD, value)
+    }
+
+    pub(super) fn usbsts(&self) -> u32 {
+        self.operational.read(USBSTS)
+    }
+
+    /// Returns the page size of the controller in bytes.
+    pub(super) fn page_size(&self) -> usize {
+        (self.operational.read::<u32>(PAGESIZE) & 0xffff) as usize * 4096
+    }
+
+    pub(super) fn set_crcr(&self, value: u64) {
+        write_u64(&self.operational, CRCR, value)
+    }
+
+    pub(super) fn set_dcbaap(&self, addr: u64) {
+        write_u64(&self.operational, DCBAAP, addr)
+    }
+
+    pub(super) fn set_max_device_slots_enabled(&self, max_slots: u8) {
+        let mut config = self.operational.read::<u32>(CONFIG);
+        config.set_bits(0..8, u32::from(max_slots));
+        self.operational.write(CONFIG, config)
+    }
+
+    fn port_offset(port: u8) -> u64 {
+        assert!(port > 0);
+        PORT_REGISTER_SETS + PORT_REGISTER_SET_SIZE * u64::from(port - 1)
+    }
+
+    /// Returns PORTSC of `port`, which is numbered from 1.
+    pub(super) fn portsc(&self, port: u8) -> u32 {
+        self.operational.read(Self::port_offset(port))
+    }
+
+    /// Returns the Protocol Speed ID of the device attached to `port`.
+    pub(super) fn port_speed(&self, port: u8) -> u8 {
+        self.portsc(port).get_bits(10..14) as u8
+    }
+
+    pub(super) fn set_portsc(&self, port: u8, value: u32) {
+        self.operational.write(Self::port_offset(port), value)
+    }
+
+    pub(super) fn iman(&self) -> u32 {
+        self.runtime.read(IMAN)
+    }
+
+    pub(super) fn set_iman(&self, value: u32) {
+        self.runtime.write(IMAN, value)
+    }
+
+    pub(super) fn set_erstsz(&self, size: u16) {
+        self.runtime.write(ERSTSZ, u32::from(size))
+    }
+
+    pub(super) fn set_erstba(&self, addr: u64) {
+        write_u64(&self.runtime, ERSTBA, addr)
+    }
+
+    pub(super) fn set_erdp(&self, value: u64) {
+        write_u64(&self.runtime, ERDP, value)
+    }
+
+    /// Rings the doorbell of `slot_id` (0 for the host controller) for `target`.
+    pub(super) fn ring_doorbell(&self, slot_id: u8, target: u8) {
+        self.doorbell
+            .write(u64::from(slot_id) * 4, u32::from(target))
+    }
+}
+
+/// Writes a 64-bit register as two dwords, since 64-bit accesses are optional.
+fn write_u64(mmio: &VolatileMmio, offset: u64, value: u64) {
+    mmio.write(offset, value as u32);
+    mmio.write(offset + 4, (value >> 32) as u32);
+}
@@ -0,0 +1,156 @@
+//! Command, transfer and event rings.
+//!
+//! Each ring is placed in its own DMA buffer of a page, so that it never crosses a 64 KiB
+//! boundary.
+
+use super::{
+    registers::{Registers, ERDP_EVENT_HANDLER_BUSY},
+    trb::Trb,
+};
+use crate::{dma::DmaBuffer, memory, prelude::*};
+use core::{
+    convert::TryFrom,
+    mem, ptr,
+    sync::atomic::{self, Ordering},
+};
+
+const TRB_SIZE: usize = mem::size_of::<Trb>();
+/// The largest ring, which fits in a page.
+const MAX_LEN: usize = memory::BYTES_PER_FRAME as usize / TRB_SIZE;
+
+fn alloc_trbs(len: usize) -> Result<DmaBuffer> {
+    assert!(len <= MAX_LEN);
+    DmaBuffer::builder(len * TRB_SIZE).below_4gib().build()
+}
+
+fn read_trb(buffer: &DmaBuffer, index: usize) -> Trb {
+    let ptr = buffer.virt_addr().as_ptr::<Trb>();
+    unsafe { ptr::read_volatile(ptr.add(index)) }
+}
+
+/// A command ring or a transfer ring, which is produced by software.
+#[derive(Debug)]
+pub(super) struct Ring {
+    buffer: DmaBuffer,
+    len: usize,
+    /// Producer Cycle State
+    cycle_bit: bool,
+    /// The index of the next TRB to be written.
+    write_index: usize,
+}
+
+impl Ring {
+    pub(super) fn new(len: usize) -> Result<Self> {
+        Ok(Self {
+            buffer: alloc_trbs(len)?,
+            len,
+            cycle_bit: true,
+            write_index: 0,
+        })
+    }
+
+    pub(super) fn phys_addr(&self) -> u64 {
+        self.buffer.phys_addr().as_u64()
+    }
+
+    /// Writes `trb` at the current position with the cycle bit.
+    fn write(&mut self, mut trb: Trb) {
+        trb.set_cycle_bit(self.cycle_bit);
+        let ptr = unsafe {
+            self.buffer
+                .virt_addr()
+                .as_mut_ptr::<u32>()
+                .add(self.write_index * 4)
+        };
+        // the dword with the cycle bit hands the TRB over to the controller, so it must be the last
+        for (i, dword) in trb.data.iter().enumerate().take(3) {
+            unsafe { ptr::write_volatile(ptr.add(i), *dword) };
+        }
+        atomic::fence(Ordering::Release);
+        unsafe { ptr::write_volatile(ptr.add(3), trb.data[3]) };
+    }
+
+    /// Appends `trb` and returns the physical address of the TRB on the ring.
+    ///
+    /// When the end of the ring is reached, a link TRB to the start is placed and the cycle bit is
+    /// toggled.
+    pub(super) fn push(&mut self, trb: Trb) -> u64 {
+        let addr = self.phys_addr() + (self.write_index * TRB_SIZE) as u64;
+        self.write(trb);
+        self.write_index += 1;
+        if self.write_index == self.len - 1 {
+            self.write(Trb::link(self.phys_addr()));
+            self.write_index = 0;
+            self.cycle_bit = !self.cycle_bit;
+        }
+        addr
+    }
+
+    /// Reads the TRB at the physical address `addr`, which was returned by [`Ring::push`].
+    pub(super) fn read(&self, addr: u64) -> Option<Trb> {
+        let offset = usize::try_from(addr.checked_sub(self.phys_addr())?).ok()?;
+        if offset % TRB_SIZE != 0 || offset / TRB_SIZE >= self.len {
+            return None;
+        }
+        Some(read_trb(&self.buffer, offset / TRB_SIZE))
+    }
+}
+
+/// The event ring of an interrupter, which is produced by the controller.
+///
+/// The ring consists of one segment.
+#[derive(Debug)]
+pub(super) struct EventRing {
+    buffer: DmaBuffer,
+    /// Event Ring Segment Table
+    _erst: DmaBuffer,
+    len: usize,
+    /// Consumer Cycle State
+    cycle_bit: bool,
+    dequeue_index: usize,
+}
+
+impl EventRing {
+    /// Allocates the ring, and registers it to the primary interrupter.
+    pub(super) fn new(len: usize, registers: &Registers) -> Result<Self> {
+        let buffer = alloc_trbs(len)?;
+        let mut erst = DmaBuffer::builder(16).below_4gib().build()?;
+        let entry = erst.as_mut_slice();
+        entry[0..8].copy_from_slice(&buffer.phys_addr().as_u64().to_le_bytes());
+        entry[8..12].copy_from_slice(&(len as u32).to_le_bytes());
+
+        registers.set_erstsz(1);
+        registers.set_erdp(buffer.phys_addr().as_u64());
+        registers.set_erstba(erst.phys_addr().as_u64());
+
+        Ok(Self {
+            buffer,
+            _erst: erst,
+            len,
+            cycle_bit: true,
+            dequeue_index: 0,
+        })
+    }
+
+    /// Returns the event at the dequeue pointer, if the controller has written it.
+    pub(super) fn front(&self) -> Option<Trb> {
+        let trb = read_trb(&self.buffer, self.dequeue_index);
+        if trb.cycle_bit() != self.cycle_bit {
+            return None;
+        }
+        // the other dwords must be read after the cycle bit
+        atomic::fence(Ordering::Acquire);
+        Some(read_trb(&self.buffer, self.dequeue_index))
+    }
+
+    /// Consumes the event at the dequeue pointer, and tells the controller.
+    pub(super) fn pop(&mut self, registers: &Registers) {
+        self.dequeue_index += 1;
+        if self.dequeue_index == self.len {
+            self.dequeue_index = 0;
+            self.cycle_bit = !self.cycle_bit;
+        }
+        let addr = self.buffer.phys_addr().as_u64() + (self.dequeue_index * TRB_SIZE) as u64;
+        registers.set_erdp(addr | ERDP_EVENT_HANDLER_BUSY);
+    }
+}
@@ -0,0 +1,289 @@
+//! Transfer Request Blocks.
+
+use crate::usb::SetupData;
+use bit_field::BitField;
+use core::fmt;
+
+// TRB types
+pub(super) const NORMAL: u8 = 1;
+pub(super) const SETUP_STAGE: u8 = 2;
+pub(super) const DATA_STAGE: u8 = 3;
+pub(super) const STATUS_STAGE: u8 = 4;
+pub(super) const ISOCH: u8 = 5;
+pub(super) const LINK: u8 = 6;
+pub(super) const ENABLE_SLOT_COMMAND: u8 = 9;
+pub(super) const DISABLE_SLOT_COMMAND: u8 = 10;
+pub(super) const ADDRESS_DEVICE_COMMAND: u8 = 11;
+pub(super) const CONFIGURE_ENDPOINT_COMMAND: u8 = 12;
+pub(super) const TRANSFER_EVENT: u8 = 32;
+pub(super) const COMMAND_COMPLETION_EVENT: u8 = 33;
+pub(super) const PORT_STATUS_CHANGE_EVENT: u8 = 34;
+
+// completion codes
+pub(super) const SUCCESS: CompletionCode = CompletionCode(1);
+pub(super) const SHORT_PACKET: CompletionCode = CompletionCode(13);
+
+// Transfer Type of setup stage TRBs
+const NO_DATA_STAGE: u32 = 0;
+const OUT_DATA_STAGE: u32 = 2;
+const IN_DATA_STAGE: u32 = 3;
+
+const TOGGLE_CYCLE: u32 = 1 << 1;
+const INTERRUPT_ON_SHORT_PACKET: u32 = 1 << 2;
+const INTERRUPT_ON_COMPLETION: u32 = 1 << 5;
+const IMMEDIATE_DATA: u32 = 1 << 6;
+const DIR_IN: u32 = 1 << 16;
+const START_ISOCH_ASAP: u32 = 1 << 31;
+
+/// The completion code of an event TRB.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct CompletionCode(u8);
+
+impl CompletionCode {
+    fn name(self) -> Option<&'static str> {
+        let name = match self.0 {
+            1 => "Success",
+            2 => "Data Buffer Error",
+            3 => "Babble Detected Error",
+            4 => "USB Transaction Error",
+            5 => "TRB Error",
+            6 => "Stall Error",
+            7 => "Resource Error",
+            8 => "Bandwidth Error",
+            9 => "No Slots Available Error",
+            10 => "Invalid Stream Type Error",
+            11 => "Slot Not Enabled Error",
+            12 => "Endpoint Not Enabled Error",
+            13 => "Short Packet",
+            14 => "Ring Underrun",
+            15 => "Ring Overrun",
+            16 => "VF Event Ring Full Error",
+            17 => "Parameter Error",
+            18 => "Bandwidth Overrun Error",
+            19 => "Context State Error",
+            20 => "No Ping Response Error",
+            21 => "Event Ring Full Error",
+            22 => "Incompatible Device Error",
+            23 => "Missed Service Error",
+            24 => "Command Ring Stopped",
+            25 => "Command Aborted",
+            26 => "Stopped",
+            27 => "Stopped - Length Invalid",
+            28 => "Stopped - Short Packet",
+            29 => "Max Exit Latency Too Large Error",
+            31 => "Isoch Buffer Overrun",
+            32 => "Event Lost Error",
+            33 => "Undefined Error",
+            34 => "Invalid Stream ID Error",
+            35 => "Secondary Bandwidth Error",
+            36 => "Split Transaction Error",
+            _ => return None,
+        };
+        Some(name)
+    }
+}
+
+impl fmt::Display for CompletionCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name() {
+            Some(name) => write!(f, "{} ({})", self.0, name),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
+
+/// Returns the name of the command TRB of `trb_type`.
+pub(super) fn command_name(trb_type: u8) -> &'static str {
+    match trb_type {
+        ENABLE_SLOT_COMMAND => "Enable Slot",
+        DISABLE_SLOT_COMMAND => "Disable Slot",
+        ADDRESS_DEVICE_COMMAND => "Address Device",
+        CONFIGURE_ENDPOINT_COMMAND => "Configure Endpoint",
+        _ => "unknown command",
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[repr(C, align(16))]
+pub(super) struct Trb {
+    pub(super) data: [u32; 4],
+}
+
+impl Trb {
+    fn new(trb_type: u8) -> Self {
+        let mut trb = Self::default();
+        trb.data[3].set_bits(10..16, u32::from(trb_type));
+        trb
+    }
+
+    fn set_pointer(&mut self, addr: u64) {
+        self.data[0] = addr as u32;
+        self.data[1] = (addr >> 32) as u32;
+    }
+
+    pub(super) fn link(ring_addr: u64) -> Self {
+        let mut trb = Self::new(LINK);
+        trb.set_pointer(ring_addr);
+        trb.data[3] |= TOGGLE_CYCLE;
+        trb
+    }
+
+    pub(super) fn normal(buf_addr: u64, len: u32) -> Self {
+        let mut trb = Self::new(NORMAL);
+        trb.set_pointer(buf_addr);
+        trb.data[2].set_bits(0..17, len);
+        trb.data[3] |= INTERRUPT_ON_SHORT_PACKET | INTERRUPT_ON_COMPLETION;
+        trb
+    }
+
+    pub(super) fn setup_stage(setup: &SetupData, has_data: bool) -> Self {
+        let mut trb = Self::new(SETUP_STAGE);
+        trb.data[0] = u32::from(setup.request_type)
+            | u32::from(setup.request) << 8
+            | u32::from(setup.value) << 16;
+        trb.data[1] = u32::from(setup.index) | u32::from(setup.length) << 16;
+        // the length of the setup packet
+        trb.data[2] = 8;
+        let transfer_type = match (has_data, setup.is_in()) {
+            (false, _) => NO_DATA_STAGE,
+            (true, false) => OUT_DATA_STAGE,
+            (true, true) => IN_DATA_STAGE,
+        };
+        trb.data[3] |= IMMEDIATE_DATA | transfer_type << 16;
+        trb
+    }
+
+    pub(super) fn data_stage(buf_addr: u64, len: u32, dir_in: bool) -> Self {
+        let mut trb = Self::new(DATA_STAGE);
+        trb.set_pointer(buf_addr);
+        trb.data[2].set_bits(0..17, len);
+        trb.data[3] |= INTERRUPT_ON_COMPLETION;
+        if dir_in {
+            trb.data[3] |= DIR_IN;
+        }
+        trb
+    }
+
+    pub(super) fn status_stage(dir_in: bool, interrupt_on_completion: bool) -> Self {
+        let mut trb = Self::new(STATUS_STAGE);
+        if dir_in {
+            trb.data[3] |= DIR_IN;
+        }
+        if interrupt_on_completion {
+            trb.data[3] |= INTERRUPT_ON_COMPLETION;
+        }
+        trb
+    }
+
+    /// Creates an isochronous TRB of a TD with a single packet, which is scheduled as soon as
+    /// possible.
+    pub(super) fn isoch(buf_addr: u64, len: u32) -> Self {
+        let mut trb = Self::new(ISOCH);
+        trb.set_pointer(buf_addr);
+        trb.data[2].set_bits(0..17, len);
+        trb.data[3] |= INTERRUPT_ON_COMPLETION | START_ISOCH_ASAP;
+        trb
+    }
+
+    pub(super) fn enable_slot_command() -> Self {
+        Self::new(ENABLE_SLOT_COMMAND)
+    }
+
+    pub(super) fn disable_slot_command(slot_id: u8) -> Self {
+        let mut trb = Self::new(DISABLE_SLOT_COMMAND);
+        trb.data[3].set_bits(24..32, u32::from(slot_id));
+        trb
+    }
+
+    pub(super) fn address_device_command(input_context_addr: u64, slot_id: u8) -> Self {
+        let mut trb = Self::new(ADDRESS_DEVICE_COMMAND);
+        trb.set_pointer(input_context_addr);
+        trb.data[3].set_bits(24..32, u32::from(slot_id));
+        trb
+    }
+
+    pub(super) fn configure_endpoint_command(input_context_addr: u64, slot_id: u8) -> Self {
+        let mut trb = Self::new(CONFIGURE_ENDPOINT_COMMAND);
+        trb.set_pointer(input_context_addr);
+        trb.data[3].set_bits(24..32, u32::from(slot_id));
+        trb
+    }
+
+    pub(super) fn cycle_bit(&self) -> bool {
+        self.data[3].get_bit(0)
+    }
+
+    pub(super) fn set_cycle_bit(&mut self, cycle_bit: bool) {
+        self.data[3].set_bit(0, cycle_bit);
+    }
+
+    pub(super) fn trb_type(&self) -> u8 {
+        self.data[3].get_bits(10..16) as u8
+    }
+
+    /// Returns the data buffer pointer of transfer TRBs, or the TRB pointer of events.
+    pub(super) fn pointer(&self) -> u64 {
+        u64::from(self.data[0]) | u64::from(self.data[1]) << 32
+    }
+
+    /// Returns the transfer length of transfer TRBs, or the residual length of transfer events.
+    pub(super) fn transfer_length(&self) -> u32 {
+        match self.trb_type() {
+            TRANSFER_EVENT => self.data[2].get_bits(0..24),
+            _ => self.data[2].get_bits(0..17),
+        }
+    }
+
+    pub(super) fn completion_code(&self) -> CompletionCode {
+        CompletionCode(self.data[2].get_bits(24..32) as u8)
+    }
+
+    /// Returns the device context index of transfer events.
+    pub(super) fn endpoint_id(&self) -> u8 {
+        self.data[3].get_bits(16..21) as u8
+    }
+
+    pub(super) fn slot_id(&self) -> u8 {
+        self.data[3].get_bits(24..32) as u8
+    }
+
+    pub(super) fn port_id(&self) -> u8 {
+        self.data[0].get_bits(24..32) as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::format;
+
+    #[test_case]
+    fn setup_stage() {
+        let setup = SetupData::get_descriptor(1, 0, 18);
+        let trb = Trb::setup_stage(&setup, true);
+        assert_eq!(trb.data, [0x0100_0680, 0x0012_0000, 8, 0x0003_0840]);
+
+        let setup = SetupData::set_configuration(1);
+        let trb = Trb::setup_stage(&setup, false);
+        assert_eq!(trb.data, [0x0001_0900, 0, 8, 0x0000_0840]);
+    }
+
+    #[test_case]
+    fn transfer_event() {
+        let trb = Trb {
+            data: [0x1000, 0, 0x0d00_0003, 0x0203_8001],
+        };
+        assert_eq!(trb.trb_type(), TRANSFER_EVENT);
+        assert_eq!(trb.completion_code(), SHORT_PACKET);
+        assert_eq!(trb.transfer_length(), 3);
+        assert_eq!(trb.endpoint_id(), 3);
+        assert_eq!(trb.slot_id(), 2);
+        assert!(trb.cycle_bit());
+    }
+
+    #[test_case]
+    fn completion_code_display() {
+        assert_eq!(format!("{}", CompletionCode(6)), "6 (Stall Error)");
+        assert_eq!(format!("{}", CompletionCode(30)), "30");
+    }
+}
@@ -0,0 +1,274 @@
+//! Virtio devices over the modern PCI transport (virtio 1.x).
+//!
+//! The configuration structures of a device are located through its vendor-specific PCI
+//! capabilities, and live in memory BARs. Device drivers create a [`Transport`], negotiate
+//! features, set up [`Virtqueue`]s and then tell the device that the driver is ready.
+
+pub(crate) use self::queue::Virtqueue;
+
+use crate::{
+    interrupt, local_apic, memory,
+    mmio::VolatileMmio,
+    paging,
+    pci::{self, BarResource, CapabilityId, Device, MsiDeliveryMode, MsiTriggerMode},
+    prelude::*,
+};
+use x86_64::structures::paging::OffsetPageTable;
+
+pub(crate) mod net;
+pub(crate) mod rng;
+
+mod queue;
+
+pub(crate) const VENDOR_ID: u16 = 0x1af4;
+
+/// Returns the PCI device ID of a modern device of virtio device type `device_type`.
+pub(crate) const fn modern_device_id(device_type: u16) -> u16 {
+    0x1040 + device_type
+}
+
+/// The device complies with virtio 1.0 or later, which the modern transport requires.
+pub(crate) const F_VERSION_1: u64 = 1 << 32;
+
+const CFG_TYPE_COMMON: u8 = 1;
+const CFG_TYPE_NOTIFY: u8 = 2;
+const CFG_TYPE_DEVICE: u8 = 4;
+
+// offsets in the common configuration structure
+const COMMON_DEVICE_FEATURE_SELECT: u64 = 0x00;
+const COMMON_DEVICE_FEATURE: u64 = 0x04;
+const COMMON_DRIVER_FEATURE_SELECT: u64 = 0x08;
+const COMMON_DRIVER_FEATURE: u64 = 0x0c;
+const COMMON_DEVICE_STATUS: u64 = 0x14;
+const COMMON_QUEUE_SELECT: u64 = 0x16;
+const COMMON_QUEUE_SIZE: u64 = 0x18;
+const COMMON_QUEUE_MSIX_VECTOR: u64 = 0x1a;
+const COMMON_QUEUE_ENABLE: u64 = 0x1c;
+const COMMON_QUEUE_NOTIFY_OFF: u64 = 0x1e;
+const COMMON_QUEUE_DESC: u64 = 0x20;
+const COMMON_QUEUE_DRIVER: u64 = 0x28;
+const COMMON_QUEUE_DEVICE: u64 = 0x30;
+const COMMON_SIZE: u64 = 0x38;
+
+const STATUS_ACKNOWLEDGE: u8 = 1;
+const STATUS_DRIVER: u8 = 2;
+const STATUS_DRIVER_OK: u8 = 4;
+const STATUS_FEATURES_OK: u8 = 8;
+const STATUS_FAILED: u8 = 128;
+
+/// The MSI-X entry which means no interrupt.
+const NO_VECTOR: u16 = 0xffff;
+
+/// The largest queue set up, which bounds the memory used by a queue.
+const MAX_QUEUE_SIZE: u16 = 256;
+
+/// The configuration structures of a virtio device.
+#[derive(Debug)]
+pub(crate) struct Transport {
+    dev: Device,
+    common: VolatileMmio,
+    notify: VolatileMmio,
+    notify_off_multiplier: u32,
+    device: Option<VolatileMmio>,
+}
+
+fn map_region(
+    dev: &Device,
+    mapper: &mut OffsetPageTable,
+    bar_index: u8,
+    offset: u32,
+    length: u32,
+) -> Result<VolatileMmio> {
+    let (addr, size) = match pci::resources(dev).iter().find(|(i, _)| *i == bar_index) {
+        Some((_, BarResource::Memory { addr, size, .. })) => (*addr, *size),
+        _ => bail!(ErrorKind::InvalidBar),
+    };
+    let (offset, length) = (u64::from(offset), u64::from(length));
+    if offset + length > size {
+        bail!(ErrorKind::InvalidBar);
+    }
+    memory::with_memory_manager(|allocator| {
+        paging::map_mmio(mapper, allocator, addr + offset, length)
+    })
+}
+
+impl Transport {
+    /// Maps the configuration structures of `dev`, and enables its bus mastering.
+    pub(crate) fn new(dev: &Device, mapper: &mut OffsetPageTable) -> Result<Self> {
+        let mut common = None;
+        let mut notify = None;
+        let mut device = None;
+        let caps = dev
+            .capabilities()
+            .filter(|cap| cap.id == CapabilityId::VendorSpecific);
+        for cap in caps {
+            // the notify capability is the largest, with 20 bytes
+            let offset = match cap.offset.checked_add(16) {
+                Some(_) => cap.offset,
+                None => continue,
+            };
+            let cfg_type = (pci::read_conf_reg(dev, offset) >> 24) as u8;
+            let bar_index = (pci::read_conf_reg(dev, offset + 4) & 0xff) as u8;
+            let region_offset = pci::read_conf_reg(dev, offset + 8);
+            let length = pci::read_conf_reg(dev, offset + 12);
+            // the first structure of each type is preferred
+            let slot = match cfg_type {
+                CFG_TYPE_COMMON => &mut common,
+                CFG_TYPE_NOTIFY => &mut notify,
+                CFG_TYPE_DEVICE => &mut device,
+                _ => continue,
+            };
+            if slot.is_some() {
+                continue;
+            }
+            let region = map_region(dev, mapper, bar_index, region_offset, length)?;
+            let multiplier = if cfg_type == CFG_TYPE_NOTIFY {
+                pci::read_conf_reg(dev, offset + 16)
+            } else {
+                0
+            };
+            *slot = Some((region, multiplier));
+        }
+
+        let (common, _) = common.ok_or(ErrorKind::MissingVirtioCapability)?;
+        let (notify, notify_off_multiplier) = notify.ok_or(ErrorKind::MissingVirtioCapability)?;
+        if common.size() < COMMON_SIZE {
+            bail!(ErrorKind::MissingVirtioCapability);
+        }
+        dev.enable_bus_master();
+
+        Ok(Self {
+            dev: *dev,
+            common,
+            notify,
+            notify_off_multiplier,
+            device: device.map(|(region, _)| region),
+        })
+    }
+
+    fn status(&self) -> u8 {
+        self.common.read(COMMON_DEVICE_STATUS)
+    }
+
+    fn add_status(&self, status: u8) {
+        self.common
+            .write(COMMON_DEVICE_STATUS, self.status() | status);
+    }
+
+    /// Resets the device, which stops it from accessing the virtqueues.
+    pub(crate) fn reset(&self) {
+        self.common.write(COMMON_DEVICE_STATUS, 0_u8);
+        while self.status() != 0 {
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Tells the device that the driver gave up.
+    pub(crate) fn fail(&self) {
+        self.add_status(STATUS_FAILED);
+    }
+
+    fn device_features(&self) -> u64 {
+        (0..2).fold(0, |features, select| {
+            self.common
+                .write(COMMON_DEVICE_FEATURE_SELECT, select as u32);
+            let bits = self.common.read::<u32>(COMMON_DEVICE_FEATURE);
+            features | (u64::from(bits) << (select * 32))
+        })
+    }
+
+    fn set_driver_features(&self, features: u64) {
+        for select in 0..2 {
+            self.common
+                .write(COMMON_DRIVER_FEATURE_SELECT, select as u32);
+            self.common
+                .write(COMMON_DRIVER_FEATURE, (features >> (select * 32)) as u32);
+        }
+    }
+
+    /// Resets the device and accepts the features in `supported` which the device offers.
+    ///
+    /// [`F_VERSION_1`] is always negotiated. Returns the accepted features.
+    pub(crate) fn negotiate_features(&self, supported: u64) -> Result<u64> {
+        self.reset();
+        self.add_status(STATUS_ACKNOWLEDGE);
+        self.add_status(STATUS_DRIVER);
+
+        let features = self.device_features() & (supported | F_VERSION_1);
+        if features & F_VERSION_1 == 0 {
+            self.fail();
+            bail!(ErrorKind::VirtioFeaturesNotAccepted);
+        }
+        self.set_driver_features(features);
+        self.add_status(STATUS_FEATURES_OK);
+        // the device clears FEATURES_OK if it cannot work with the features
+        if self.status() & STATUS_FEATURES_OK == 0 {
+            self.fail();
+            bail!(ErrorKind::VirtioFeaturesNotAccepted);
+        }
+        Ok(features)
+    }
+
+    /// Enables MSI-X with a table entry for each handler. Returns the vector of the first entry
+    /// and the number of enabled entries.
+    ///
+    /// Entry `i` is delivered to `handlers[i]`, and is passed to [`Transport::setup_queue`].
+    pub(crate) fn enable_interrupts(
+        &self,
+        mapper: &mut OffsetPageTable,
+        name: &'static str,
+        handlers: &[interrupt::Handler],
+    ) -> Result<(u8, usize)> {
+        pci::enable_msi(
+            &self.dev,
+            mapper,
+            name,
+            u32::from(local_apic::id()),
+            MsiTriggerMode::Edge,
+            MsiDeliveryMode::Fixed,
+            handlers,
+        )
+    }
+
+    /// Sets up the virtqueue `index` after the features are negotiated, with its interrupts
+    /// routed to the MSI-X table entry `msix_entry`.
+    pub(crate) fn setup_queue(&self, index: u16, msix_entry: Option<u16>) -> Result<Virtqueue> {
+        self.common.write(COMMON_QUEUE_SELECT, index);
+        let size = u16::min(self.common.read(COMMON_QUEUE_SIZE), MAX_QUEUE_SIZE);
+        if size == 0 || self.common.read::<u16>(COMMON_QUEUE_ENABLE) != 0 {
+            bail!(ErrorKind::VirtqueueNotAvailable);
+        }
+        let notify_offset = u64::from(self.common.read::<u16>(COMMON_QUEUE_NOTIFY_OFF))
+            * u64::from(self.notify_off_multiplier);
+        let queue = Virtqueue::new(index, size, self.notify.subrange(notify_offset, 2))?;
+
+        self.common.write(COMMON_QUEUE_SIZE, size);
+        // 64-bit fields are written in halves, which every device supports
+        let addrs = [
+            (COMMON_QUEUE_DESC, queue.desc_addr()),
+            (COMMON_QUEUE_DRIVER, queue.driver_addr()),
+            (COMMON_QUEUE_DEVICE, queue.device_addr()),
+        ];
+        for (offset, addr) in addrs {
+            self.common.write(offset, addr.as_u64() as u32);
+            self.common.write(offset + 4, (addr.as_u64() >> 32) as u32);
+        }
+        let entry = msix_entry.unwrap_or(NO_VECTOR);
+        self.common.write(COMMON_QUEUE_MSIX_VECTOR, entry);
+        if self.common.read::<u16>(COMMON_QUEUE_MSIX_VECTOR) != entry {
+            bail!(ErrorKind::NoPciMsi);
+        }
+        self.common.write(COMMON_QUEUE_ENABLE, 1_u16);
+        Ok(queue)
+    }
+
+    /// Tells the device that the driver is ready, after the virtqueues are set up.
+    pub(crate) fn driver_ok(&self) {
+        self.add_status(STATUS_DRIVER_OK);
+    }
+
+    /// Returns the device-specific configuration structure, if the device has one.
+    pub(crate) fn device_config(&self) -> Option<&VolatileMmio> {
+        self.device.as_ref()
+    }
+}
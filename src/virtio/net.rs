@@ -0,0 +1,243 @@
+//! Virtio network device.
+//!
+//! Received frames are copied out of a fixed set of receive buffers by the network softirq, and
+//! the buffers are returned to the device immediately. Transmit buffers are reclaimed lazily
+//! when the next frame is sent, so the transmit queue has no interrupt.
+
+use super::Transport;
+use crate::{
+    dma::DmaBuffer,
+    driver::{Driver, MatchRule, ProbeContext},
+    interrupt::{self, InterruptContextGuard},
+    net::{self, Ipv4Config, Link, MacAddr, NetDevice},
+    pci::Device,
+    prelude::*,
+    random,
+    sync::{OnceCell, SpinMutex},
+    virtio::Virtqueue,
+};
+use alloc::{boxed::Box, vec::Vec};
+use core::convert::TryFrom;
+use x86_64::{instructions::interrupts, structures::idt::InterruptStackFrame, PhysAddr};
+
+const DEVICE_TYPE: u16 = 1;
+const TRANSITIONAL_DEVICE_ID: u16 = 0x1000;
+
+/// The device configuration has the MAC address.
+const F_MAC: u64 = 1 << 5;
+/// The device configuration has the link status.
+const F_STATUS: u64 = 1 << 16;
+
+// offsets in the device configuration structure
+const CONFIG_MAC: u64 = 0x00;
+const CONFIG_STATUS: u64 = 0x06;
+const STATUS_LINK_UP: u16 = 1 << 0;
+
+const RX_QUEUE: u16 = 0;
+const TX_QUEUE: u16 = 1;
+
+/// The size of `struct virtio_net_hdr`, which precedes each frame. All fields are left zero,
+/// since no offloading is negotiated.
+const HEADER_LEN: usize = 12;
+/// Large enough for a header and a full-sized Ethernet frame.
+const BUFFER_SIZE: usize = 2048;
+const NUM_RX_BUFFERS: usize = 32;
+const NUM_TX_BUFFERS: usize = 32;
+
+static VECTOR: OnceCell<u8> = OnceCell::uninit();
+
+pub(crate) static DRIVER: Driver = Driver {
+    name: "virtio-net",
+    rules: &[
+        MatchRule::id(super::VENDOR_ID, super::modern_device_id(DEVICE_TYPE)),
+        // transitional devices also provide the modern interface
+        MatchRule::id(super::VENDOR_ID, TRANSITIONAL_DEVICE_ID),
+    ],
+    max_devices: 1,
+    probe,
+};
+
+/// A set of `BUFFER_SIZE` bytes buffers, and the virtqueue chains which own them.
+#[derive(Debug)]
+struct BufferPool {
+    buffers: DmaBuffer,
+    /// The chain ID and the buffer index of the buffers owned by the device.
+    inflight: Vec<(u16, usize)>,
+    free: Vec<usize>,
+}
+
+impl BufferPool {
+    fn new(count: usize) -> Result<Self> {
+        Ok(Self {
+            buffers: DmaBuffer::builder(count * BUFFER_SIZE).build()?,
+            inflight: Vec::with_capacity(count),
+            free: (0..count).collect(),
+        })
+    }
+
+    fn addr(&self, index: usize) -> PhysAddr {
+        self.buffers.phys_addr() + index * BUFFER_SIZE
+    }
+
+    fn slice(&mut self, index: usize) -> &mut [u8] {
+        &mut self.buffers.as_mut_slice()[index * BUFFER_SIZE..][..BUFFER_SIZE]
+    }
+
+    /// Returns the buffer of the used chain `id` to the free list, and returns its index.
+    fn complete(&mut self, id: u16) -> Option<usize> {
+        let pos = self.inflight.iter().position(|(chain, _)| *chain == id)?;
+        let (_, index) = self.inflight.swap_remove(pos);
+        self.free.push(index);
+        Some(index)
+    }
+}
+
+#[derive(Debug)]
+struct Queues {
+    rx: Virtqueue,
+    tx: Virtqueue,
+    rx_pool: BufferPool,
+    tx_pool: BufferPool,
+}
+
+impl Queues {
+    /// Makes all free receive buffers available to the device.
+    fn fill_rx(&mut self) -> Result<()> {
+        #[allow(clippy::unwrap_used)]
+        let len = u32::try_from(BUFFER_SIZE).unwrap();
+        while let Some(index) = self.rx_pool.free.pop() {
+            match self.rx.add(&[], &[(self.rx_pool.addr(index), len)]) {
+                Ok(id) => self.rx_pool.inflight.push((id, index)),
+                Err(err) => {
+                    self.rx_pool.free.push(index);
+                    return Err(err);
+                }
+            }
+        }
+        self.rx.notify();
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct VirtioNet {
+    transport: Transport,
+    features: u64,
+    queues: SpinMutex<Queues>,
+}
+
+impl NetDevice for VirtioNet {
+    fn transmit(&self, frame: &[u8]) -> Result<()> {
+        if HEADER_LEN + frame.len() > BUFFER_SIZE {
+            bail!(ErrorKind::PacketTooLarge);
+        }
+        interrupts::without_interrupts(|| {
+            let queues = &mut *self.queues.lock();
+            while let Some((id, _)) = queues.tx.pop_used() {
+                let _ = queues.tx_pool.complete(id);
+            }
+            let index = queues.tx_pool.free.pop().ok_or(ErrorKind::Full)?;
+            let buffer = queues.tx_pool.slice(index);
+            buffer[..HEADER_LEN].fill(0);
+            buffer[HEADER_LEN..][..frame.len()].copy_from_slice(frame);
+            let len = u32::try_from(HEADER_LEN + frame.len())?;
+            match queues.tx.add(&[(queues.tx_pool.addr(index), len)], &[]) {
+                Ok(id) => queues.tx_pool.inflight.push((id, index)),
+                Err(err) => {
+                    queues.tx_pool.free.push(index);
+                    return Err(err);
+                }
+            }
+            queues.tx.notify();
+            Ok(())
+        })
+    }
+
+    fn receive(&self) -> Vec<Vec<u8>> {
+        interrupts::without_interrupts(|| {
+            let queues = &mut *self.queues.lock();
+            let mut frames = Vec::new();
+            while let Some((id, written)) = queues.rx.pop_used() {
+                let index = match queues.rx_pool.complete(id) {
+                    Some(index) => index,
+                    None => continue,
+                };
+                let written = usize::min(written as usize, BUFFER_SIZE);
+                if written > HEADER_LEN {
+                    frames.push(queues.rx_pool.slice(index)[HEADER_LEN..written].into());
+                }
+            }
+            if let Err(err) = queues.fill_rx() {
+                warn!("virtio-net: failed to refill receive buffers: {}", err);
+            }
+            frames
+        })
+    }
+
+    fn link_up(&self) -> bool {
+        match self.transport.device_config() {
+            Some(config) if self.features & F_STATUS != 0 => {
+                config.read::<u16>(CONFIG_STATUS) & STATUS_LINK_UP != 0
+            }
+            _ => true,
+        }
+    }
+}
+
+fn read_mac(transport: &Transport, features: u64) -> MacAddr {
+    let mut mac = [0; 6];
+    match transport.device_config() {
+        Some(config) if features & F_MAC != 0 => {
+            for (i, byte) in mac.iter_mut().enumerate() {
+                *byte = config.read(CONFIG_MAC + i as u64);
+            }
+        }
+        _ => {
+            // a locally administered address in the range QEMU uses
+            mac = [0x52, 0x54, 0x00, 0, 0, 0];
+            for byte in &mut mac[3..] {
+                *byte = random::range(0..256) as u8;
+            }
+        }
+    }
+    MacAddr(mac)
+}
+
+fn probe(dev: &Device, cx: &mut ProbeContext<'_>) -> Result<()> {
+    let transport = Transport::new(dev, cx.mapper)?;
+    let features = transport.negotiate_features(F_MAC | F_STATUS)?;
+    let (vector, _) = transport.enable_interrupts(cx.mapper, "virtio-net", &[interrupt_handler])?;
+    VECTOR.init_once(|| vector);
+    let rx = transport.setup_queue(RX_QUEUE, Some(0))?;
+    let tx = transport.setup_queue(TX_QUEUE, None)?;
+
+    let mut queues = Queues {
+        rx,
+        tx,
+        rx_pool: BufferPool::new(NUM_RX_BUFFERS)?,
+        tx_pool: BufferPool::new(NUM_TX_BUFFERS)?,
+    };
+    queues.fill_rx()?;
+    transport.driver_ok();
+
+    let mac = read_mac(&transport, features);
+    let device = VirtioNet {
+        transport,
+        features,
+        queues: SpinMutex::new(queues),
+    };
+    let config = Ipv4Config {
+        addr: net::DEFAULT_ADDR,
+        prefix_len: net::DEFAULT_PREFIX_LEN,
+        gateway: Some(net::DEFAULT_GATEWAY),
+    };
+    let _ = net::register("eth0", Link::Ethernet(mac), Box::new(device), config);
+    Ok(())
+}
+
+extern "x86-interrupt" fn interrupt_handler(_stack_frame: InterruptStackFrame) {
+    interrupt::record(*VECTOR.get());
+    let _guard = InterruptContextGuard::new();
+    net::notify_received();
+    interrupt::notify_end_of_interrupt();
+}
@@ -0,0 +1,198 @@
+//! Split virtqueues.
+//!
+//! A virtqueue consists of the descriptor table, the available ring written by the driver and the
+//! used ring written by the device, which are placed in one DMA buffer.
+
+use crate::{dma::DmaBuffer, mmio::VolatileMmio, prelude::*};
+use core::{
+    mem, ptr,
+    sync::atomic::{self, Ordering},
+};
+use x86_64::PhysAddr;
+
+/// The buffer continues in the descriptor of `next`.
+const DESC_F_NEXT: u16 = 1 << 0;
+/// The buffer is written by the device.
+const DESC_F_WRITE: u16 = 1 << 1;
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct Descriptor {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    /// The next descriptor of the chain, or of the free list.
+    next: u16,
+}
+
+#[derive(Debug)]
+pub(crate) struct Virtqueue {
+    index: u16,
+    size: u16,
+    buffer: DmaBuffer,
+    avail_offset: usize,
+    used_offset: usize,
+    notify: VolatileMmio,
+    free_head: u16,
+    num_free: u16,
+    /// The index of the next entry of the available ring.
+    avail_idx: u16,
+    /// The index of the next entry of the used ring to be consumed.
+    last_used_idx: u16,
+}
+
+impl Virtqueue {
+    pub(super) fn new(index: u16, size: u16, notify: VolatileMmio) -> Result<Self> {
+        let entries = usize::from(size);
+        // flags, idx, ring and used_event/avail_event
+        let avail_offset = entries * mem::size_of::<Descriptor>();
+        let used_offset = (avail_offset + 6 + 2 * entries + 3) & !3;
+        let buffer = DmaBuffer::builder(used_offset + 6 + 8 * entries).build()?;
+        let queue = Self {
+            index,
+            size,
+            buffer,
+            avail_offset,
+            used_offset,
+            notify,
+            free_head: 0,
+            num_free: size,
+            avail_idx: 0,
+            last_used_idx: 0,
+        };
+        for i in 0..size {
+            queue.write_desc(
+                i,
+                Descriptor {
+                    addr: 0,
+                    len: 0,
+                    flags: 0,
+                    next: i + 1,
+                },
+            );
+        }
+        Ok(queue)
+    }
+
+    pub(super) fn desc_addr(&self) -> PhysAddr {
+        self.buffer.phys_addr()
+    }
+
+    pub(super) fn driver_addr(&self) -> PhysAddr {
+        self.buffer.phys_addr() + self.avail_offset
+    }
+
+    pub(super) fn device_addr(&self) -> PhysAddr {
+        self.buffer.phys_addr() + self.used_offset
+    }
+
+    fn ptr<T>(&self, offset: usize) -> *mut T {
+        assert!(offset + mem::size_of::<T>() <= self.buffer.len());
+        (self.buffer.virt_addr() + offset).as_mut_ptr()
+    }
+
+    fn read_desc(&self, index: u16) -> Descriptor {
+        let offset = usize::from(index) * mem::size_of::<Descriptor>();
+        unsafe { ptr::read_volatile(self.ptr(offset)) }
+    }
+
+    fn write_desc(&self, index: u16, desc: Descriptor) {
+        let offset = usize::from(index) * mem::size_of::<Descriptor>();
+        unsafe { ptr::write_volatile(self.ptr(offset), desc) }
+    }
+
+    /// Makes a chain of the device-readable buffers followed by the device-writable buffers
+    /// available to the device. Returns the ID of the chain, which [`Virtqueue::pop_used`]
+    /// returns when the device finished using it.
+    ///
+    /// The buffers must stay valid until then. The device is not notified.
+    pub(crate) fn add(
+        &mut self,
+        readable: &[(PhysAddr, u32)],
+        writable: &[(PhysAddr, u32)],
+    ) -> Result<u16> {
+        let count = readable.len() + writable.len();
+        if count == 0 || count > usize::from(self.num_free) {
+            bail!(ErrorKind::Full);
+        }
+        let head = self.free_head;
+        let mut index = head;
+        let buffers = readable
+            .iter()
+            .map(|buffer| (buffer, 0))
+            .chain(writable.iter().map(|buffer| (buffer, DESC_F_WRITE)));
+        for (i, ((addr, len), flags)) in buffers.enumerate() {
+            let next = self.read_desc(index).next;
+            let flags = if i + 1 < count {
+                flags | DESC_F_NEXT
+            } else {
+                flags
+            };
+            self.write_desc(
+                index,
+                Descriptor {
+                    addr: addr.as_u64(),
+                    len: *len,
+                    flags,
+                    next,
+                },
+            );
+            index = next;
+        }
+        self.free_head = index;
+        self.num_free -= count as u16;
+
+        let slot = usize::from(self.avail_idx % self.size);
+        unsafe { ptr::write_volatile(self.ptr(self.avail_offset + 4 + 2 * slot), head) };
+        // the ring entry must be visible before the index
+        atomic::fence(Ordering::Release);
+        self.avail_idx = self.avail_idx.wrapping_add(1);
+        unsafe { ptr::write_volatile(self.ptr(self.avail_offset + 2), self.avail_idx) };
+        Ok(head)
+    }
+
+    /// Notifies the device that buffers are available.
+    pub(crate) fn notify(&self) {
+        atomic::fence(Ordering::SeqCst);
+        self.notify.write(0, self.index);
+    }
+
+    /// Returns the ID of a chain the device finished using, and the number of bytes written to
+    /// it.
+    pub(crate) fn pop_used(&mut self) -> Option<(u16, u32)> {
+        let used_idx = unsafe { ptr::read_volatile(self.ptr::<u16>(self.used_offset + 2)) };
+        if used_idx == self.last_used_idx {
+            return None;
+        }
+        // the ring entry must be read after the index
+        atomic::fence(Ordering::Acquire);
+        let slot = usize::from(self.last_used_idx % self.size);
+        let elem_offset = self.used_offset + 4 + 8 * slot;
+        let id = unsafe { ptr::read_volatile(self.ptr::<u32>(elem_offset)) } as u16;
+        let len = unsafe { ptr::read_volatile(self.ptr::<u32>(elem_offset + 4)) };
+        self.last_used_idx = self.last_used_idx.wrapping_add(1);
+        self.free_chain(id);
+        Some((id, len))
+    }
+
+    fn free_chain(&mut self, head: u16) {
+        let mut index = head;
+        loop {
+            let desc = self.read_desc(index);
+            self.num_free += 1;
+            if desc.flags & DESC_F_NEXT == 0 {
+                self.write_desc(
+                    index,
+                    Descriptor {
+                        flags: 0,
+                        next: self.free_head,
+                        ..desc
+                    },
+                );
+                break;
+            }
+            index = desc.next;
+        }
+        self.free_head = head;
+    }
+}
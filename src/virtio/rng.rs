@@ -0,0 +1,62 @@
+//! Virtio entropy device.
+//!
+//! The device is read once at probe, to seed the pseudo-random numbers of [`random`] on CPUs
+//! without RDRAND.
+
+use super::Transport;
+use crate::{
+    dma::DmaBuffer,
+    driver::{Driver, MatchRule, ProbeContext},
+    pci::Device,
+    prelude::*,
+    random,
+};
+use core::convert::TryFrom;
+
+const DEVICE_TYPE: u16 = 4;
+const TRANSITIONAL_DEVICE_ID: u16 = 0x1005;
+
+const SEED_SIZE: usize = 32;
+/// The number of polls of the used ring before giving up. QEMU completes requests while
+/// handling the notification.
+const POLL_LIMIT: usize = 1_000_000;
+
+pub(crate) static DRIVER: Driver = Driver {
+    name: "virtio-rng",
+    rules: &[
+        MatchRule::id(super::VENDOR_ID, super::modern_device_id(DEVICE_TYPE)),
+        // transitional devices also provide the modern interface
+        MatchRule::id(super::VENDOR_ID, TRANSITIONAL_DEVICE_ID),
+    ],
+    max_devices: 1,
+    probe,
+};
+
+fn probe(dev: &Device, cx: &mut ProbeContext<'_>) -> Result<()> {
+    let transport = Transport::new(dev, cx.mapper)?;
+    transport.negotiate_features(0)?;
+    let mut queue = transport.setup_queue(0, None)?;
+    transport.driver_ok();
+
+    let buffer = DmaBuffer::builder(SEED_SIZE).build()?;
+    #[allow(clippy::unwrap_used)]
+    let len = u32::try_from(SEED_SIZE).unwrap();
+    queue.add(&[], &[(buffer.phys_addr(), len)])?;
+    queue.notify();
+    let used = (0..POLL_LIMIT).find_map(|_| {
+        core::hint::spin_loop();
+        queue.pop_used()
+    });
+    // the buffers are freed after the device stops
+    transport.reset();
+
+    let (_, written) = used.ok_or(ErrorKind::DeviceTimeout)?;
+    let seed = &buffer.as_slice()[..usize::min(written as usize, SEED_SIZE)];
+    for chunk in seed.chunks(8) {
+        let mut bytes = [0; 8];
+        bytes[..chunk.len()].copy_from_slice(chunk);
+        random::add_entropy(u64::from_le_bytes(bytes));
+    }
+    info!("virtio-rng: seeded with {} bytes", seed.len());
+    Ok(())
+}
@@ -0,0 +1,129 @@
+//! Watchdog detecting critical co-tasks which stop making progress.
+//!
+//! Producers call [`Heartbeat::expect`] when they queue work for a co-task, and the co-task
+//! calls [`Heartbeat::beat`] when it takes the work. The check runs in the timer interrupt
+//! handler, so that it works even if the task running the co-task hangs.
+//!
+//! A stall is reported once, and reported again only after the co-task makes progress and stalls
+//! again. The kernel keeps running, so that the rest of the system can still be inspected.
+
+use crate::{
+    backtrace::Frames,
+    emergency_console,
+    sync::SpinMutex,
+    task::{self, TaskId},
+    time::{self, Instant},
+};
+use arrayvec::ArrayVec;
+use core::{
+    fmt::Write as _,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::Duration,
+};
+use x86_64::instructions::interrupts;
+
+/// Time a co-task may leave queued work untouched.
+const STALL_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_WATCHED: usize = 16;
+const BACKTRACE_DEPTH: usize = 16;
+
+#[derive(Debug)]
+pub(crate) struct Heartbeat {
+    name: &'static str,
+    /// Nanoseconds of the time since when work is waiting for the co-task plus one,
+    /// or zero if no work is waiting.
+    pending_since: AtomicU64,
+    /// Whether the current stall is already reported.
+    reported: AtomicBool,
+}
+
+impl Heartbeat {
+    pub(crate) const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            pending_since: AtomicU64::new(0),
+            reported: AtomicBool::new(false),
+        }
+    }
+
+    /// Notifies that work is queued for the co-task.
+    ///
+    /// This can be called from interrupt handlers.
+    pub(crate) fn expect(&self) {
+        let now = time::read_tsc_ns() + 1;
+        let _ = self
+            .pending_since
+            .compare_exchange(0, now, Ordering::Relaxed, Ordering::Relaxed);
+    }
+
+    /// Notifies that the co-task is making progress.
+    pub(crate) fn beat(&self) {
+        self.pending_since.store(0, Ordering::Relaxed);
+        self.reported.store(false, Ordering::Relaxed);
+    }
+
+    fn stalled_for(&self, now: Instant) -> Option<Duration> {
+        let pending_since = self.pending_since.load(Ordering::Relaxed).checked_sub(1)?;
+        Some(
+            now.since_start()
+                .saturating_sub(Duration::from_nanos(pending_since)),
+        )
+    }
+}
+
+#[derive(Debug)]
+struct Watched {
+    heartbeat: &'static Heartbeat,
+    task_id: TaskId,
+}
+
+static WATCHED: SpinMutex<ArrayVec<Watched, MAX_WATCHED>> = SpinMutex::new(ArrayVec::new_const());
+
+/// Starts watching `heartbeat` of a co-task running in the current task.
+pub(crate) fn register(heartbeat: &'static Heartbeat) {
+    interrupts::without_interrupts(|| {
+        let task_id = task::current().id();
+        #[allow(clippy::expect_used)]
+        WATCHED
+            .lock()
+            .try_push(Watched { heartbeat, task_id })
+            .expect("too many watched co-tasks");
+    });
+}
+
+/// Checks all heartbeats, and dumps the state of a newly stalled co-task if any.
+///
+/// Called from the timer interrupt handler.
+pub(crate) fn check() {
+    let now = Instant::now();
+    let watched = WATCHED.lock();
+    let (stalled, stalled_for) = match watched.iter().find_map(|watched| {
+        let stalled_for = watched.heartbeat.stalled_for(now)?;
+        let reported = watched.heartbeat.reported.load(Ordering::Relaxed);
+        (stalled_for >= STALL_TIMEOUT && !reported).then(|| (watched, stalled_for))
+    }) {
+        Some(stalled) => stalled,
+        None => return,
+    };
+
+    stalled.heartbeat.reported.store(true, Ordering::Relaxed);
+
+    // the task was interrupted by the timer if it is running
+    let frames = match task::saved_frame_pointer(stalled.task_id) {
+        Some(rbp) => Frames::from_frame_pointer(rbp),
+        None => Frames::current(),
+    };
+    emergency_console::with_console(|console| {
+        let _ = writeln!(
+            console,
+            "WATCHDOG: {} (task {}) made no progress for {} s",
+            stalled.heartbeat.name,
+            stalled.task_id,
+            stalled_for.as_secs()
+        );
+        let _ = writeln!(console, "Backtrace:");
+        for addr in frames.take(BACKTRACE_DEPTH) {
+            let _ = writeln!(console, "  {:#x}", addr);
+        }
+    });
+}
@@ -2,25 +2,47 @@ use crate::{
     graphics::{Color, Draw, Point, Rectangle, ScreenInfo, Size},
     keyboard::KeyboardEvent,
     layer::{self, EventSender, Layer, LayerBuffer, LayerId},
+    mouse::MouseEvent,
     prelude::*,
     sync::mpsc,
     triple_buffer::{self, Producer},
 };
+use alloc::string::String;
 
 #[derive(Debug)]
 pub(crate) enum WindowEvent {
     Activated,
     Deactivated,
+    /// The window is requested to be closed by the user.
+    CloseRequested,
+    /// The window is requested to copy the selection to the clipboard.
+    Copy,
+    /// The window is requested to paste the text of the clipboard.
+    Paste,
+    /// The window is requested to redraw itself, because the theme is changed.
+    Redraw,
+    /// The window is requested to resize itself to the size, because it is snapped to a layout.
+    ///
+    /// This is sent only to the resizable windows.
+    Resize(Size<i32>),
     Keyboard(KeyboardEvent),
+    /// Mouse event whose position is relative to the window.
+    Mouse(MouseEvent),
 }
 
 #[derive(Debug, Clone)]
 pub(crate) struct Builder {
     pos: Option<Point<i32>>,
     size: Size<i32>,
-    transparent_color: Option<Color>,
+    alpha: bool,
     height: Option<usize>,
     draggable: Option<bool>,
+    drag_area: Option<Rectangle<i32>>,
+    clickable: bool,
+    always_on_top: bool,
+    resizable: bool,
+    title: Option<String>,
+    owner: Option<LayerId>,
 }
 
 impl Builder {
@@ -28,9 +50,15 @@ impl Builder {
         Self {
             pos: None,
             size: Size::new(0, 0),
-            transparent_color: None,
+            alpha: false,
             height: None,
             draggable: None,
+            drag_area: None,
+            clickable: false,
+            always_on_top: false,
+            resizable: false,
+            title: None,
+            owner: None,
         }
     }
 
@@ -44,8 +72,9 @@ impl Builder {
         self
     }
 
-    pub(crate) fn transparent_color(&mut self, tc: Option<Color>) -> &mut Self {
-        self.transparent_color = tc;
+    /// Enables per-pixel alpha of the window, whose pixels are initially transparent.
+    pub(crate) fn alpha(&mut self, alpha: bool) -> &mut Self {
+        self.alpha = alpha;
         self
     }
 
@@ -59,10 +88,49 @@ impl Builder {
         self
     }
 
+    /// Restricts the area which starts window dragging (relative to the window).
+    pub(crate) fn drag_area(&mut self, area: Rectangle<i32>) -> &mut Self {
+        self.drag_area = Some(area);
+        self
+    }
+
+    /// Delivers clicks to the window which is not draggable.
+    pub(crate) fn clickable(&mut self, clickable: bool) -> &mut Self {
+        self.clickable = clickable;
+        self
+    }
+
+    /// Keeps the window above the normal windows.
+    pub(crate) fn always_on_top(&mut self, always_on_top: bool) -> &mut Self {
+        self.always_on_top = always_on_top;
+        self
+    }
+
+    /// Receives [`WindowEvent::Resize`] when the window is snapped to a layout.
+    pub(crate) fn resizable(&mut self, resizable: bool) -> &mut Self {
+        self.resizable = resizable;
+        self
+    }
+
+    /// Lists the window in the taskbar with `title`.
+    pub(crate) fn title(&mut self, title: String) -> &mut Self {
+        self.title = Some(title);
+        self
+    }
+
+    /// Makes the window a modal dialog of `owner`, which blocks input to the owner until the
+    /// dialog is closed.
+    pub(crate) fn modal_for(&mut self, owner: LayerId) -> &mut Self {
+        self.owner = Some(owner);
+        self
+    }
+
     pub(crate) fn build(&mut self) -> Result<Window> {
         let screen_info = ScreenInfo::get();
         let mut buffer = LayerBuffer::new(self.size, screen_info)?;
-        buffer.set_transparent_color(self.transparent_color);
+        if self.alpha {
+            buffer.enable_alpha()?;
+        }
 
         let (producer, consumer) = triple_buffer::new(buffer.clone());
         let (tx, rx) = mpsc::channel(100);
@@ -78,6 +146,20 @@ impl Builder {
             layer.set_draggable(draggable);
         }
 
+        if let Some(drag_area) = self.drag_area {
+            layer.set_drag_area(drag_area);
+        }
+
+        layer.set_clickable(self.clickable);
+        layer.set_always_on_top(self.always_on_top);
+        layer.set_resizable(self.resizable);
+        if let Some(title) = &self.title {
+            layer.set_title(title.clone());
+        }
+        if let Some(owner) = self.owner {
+            layer.set_owner(owner);
+        }
+
         event_tx.register(layer)?;
 
         if let Some(height) = self.height {
@@ -118,6 +200,49 @@ impl Window {
         self.event_tx.move_to(self.layer_id, pos).await
     }
 
+    /// Moves the window above the other windows, which are always-on-top windows if this is one.
+    pub(crate) fn raise(&self) -> Result<()> {
+        self.event_tx.raise(self.layer_id)
+    }
+
+    /// Moves the window below the other windows.
+    pub(crate) fn lower(&self) -> Result<()> {
+        self.event_tx.lower(self.layer_id)
+    }
+
+    pub(crate) fn set_always_on_top(&self, always_on_top: bool) -> Result<()> {
+        self.event_tx
+            .set_always_on_top(self.layer_id, always_on_top)
+    }
+
+    /// Hides the window, which is not listed in the taskbar until it is shown.
+    pub(crate) fn hide(&self) -> Result<()> {
+        self.event_tx.hide(self.layer_id)
+    }
+
+    pub(crate) fn show(&self) -> Result<()> {
+        self.event_tx.show(self.layer_id)
+    }
+
+    /// Hides the window until it is restored from the taskbar.
+    pub(crate) fn minimize(&self) -> Result<()> {
+        self.event_tx.minimize(self.layer_id)
+    }
+
+    /// Changes the area which starts window dragging (relative to the window).
+    pub(crate) fn set_drag_area(&self, area: Rectangle<i32>) -> Result<()> {
+        self.event_tx.set_drag_area(self.layer_id, area)
+    }
+
+    /// Replaces the buffer with a cleared one of `size`, which is shown by the next flush.
+    pub(crate) fn resize(&mut self, size: Size<i32>) -> Result<()> {
+        self.buffer = self.buffer.resized(size)?;
+        self.redraw_area = RedrawArea::new(size);
+        self.redraw_area
+            .add_rect(Rectangle::new(Point::new(0, 0), size));
+        Ok(())
+    }
+
     pub(crate) async fn flush(&mut self) -> Result<()> {
         if let Some(redraw_area) = self.redraw_area.take() {
             self.producer.with_buffer(|buffer| {
@@ -134,6 +259,14 @@ impl Window {
     }
 }
 
+impl Drop for Window {
+    fn drop(&mut self) {
+        if let Err(err) = self.event_tx.unregister(self.layer_id) {
+            warn!("failed to unregister layer: {}", err);
+        }
+    }
+}
+
 impl Draw for Window {
     fn size(&self) -> Size<i32> {
         self.buffer.size()
@@ -149,6 +282,18 @@ impl Draw for Window {
         self.buffer.move_area(offset, src);
     }
 
+    fn clip_area(&self) -> Rectangle<i32> {
+        self.buffer.clip_area()
+    }
+
+    fn push_clip(&mut self, rect: Rectangle<i32>) {
+        self.buffer.push_clip(rect)
+    }
+
+    fn pop_clip(&mut self) {
+        self.buffer.pop_clip()
+    }
+
     // implement some default methods for faster redraw area computation
     fn fill_rect(&mut self, rect: Rectangle<i32>, c: Color) {
         self.redraw_area.add_rect(rect);
@@ -160,38 +305,35 @@ impl Draw for Window {
         self.buffer.draw_rect(rect, c)
     }
 
-    fn draw_byte_char(&mut self, pos: Point<i32>, byte: u8, color: Color) -> Rectangle<i32>
+    fn draw_char(&mut self, pos: Point<i32>, ch: char, color: Color) -> Rectangle<i32>
     where
         Self: Sized,
     {
-        let rect = self.buffer.draw_byte_char(pos, byte, color);
+        let rect = self.buffer.draw_char(pos, ch, color);
         self.redraw_area.add_rect(rect);
         rect
     }
 
-    fn draw_byte_str(&mut self, pos: Point<i32>, bytes: &[u8], color: Color) -> Rectangle<i32>
-    where
-        Self: Sized,
-    {
-        let rect = self.buffer.draw_byte_str(pos, bytes, color);
+    fn draw_char_bg(&mut self, pos: Point<i32>, ch: char, fg: Color, bg: Color) -> Rectangle<i32> {
+        let rect = self.buffer.draw_char_bg(pos, ch, fg, bg);
         self.redraw_area.add_rect(rect);
         rect
     }
 
-    fn draw_char(&mut self, pos: Point<i32>, ch: char, color: Color) -> Rectangle<i32>
+    fn draw_str(&mut self, pos: Point<i32>, s: &str, color: Color) -> Rectangle<i32>
     where
         Self: Sized,
     {
-        let rect = self.buffer.draw_char(pos, ch, color);
+        let rect = self.buffer.draw_str(pos, s, color);
         self.redraw_area.add_rect(rect);
         rect
     }
 
-    fn draw_str(&mut self, pos: Point<i32>, s: &str, color: Color) -> Rectangle<i32>
+    fn draw_str_bg(&mut self, pos: Point<i32>, s: &str, fg: Color, bg: Color) -> Rectangle<i32>
     where
         Self: Sized,
     {
-        let rect = self.buffer.draw_str(pos, s, color);
+        let rect = self.buffer.draw_str_bg(pos, s, fg, bg);
         self.redraw_area.add_rect(rect);
         rect
     }
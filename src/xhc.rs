@@ -1,90 +1,148 @@
 use crate::{
-    interrupt::{self, InterruptContextGuard, InterruptIndex},
-    keyboard, memory, mouse, paging,
-    pci::{self, Device, MsiDeliveryMode, MsiTriggerMode},
+    device::{self, DeviceId, DeviceState},
+    driver::{Driver, MatchRule, ProbeContext},
+    interrupt::{self, InterruptContextGuard},
+    keyboard, local_apic, memory,
+    mmio::VolatileMmio,
+    mouse, paging,
+    pci::{self, BarResource, Device, MsiDeliveryMode, MsiTriggerMode},
     prelude::*,
+    softirq::{self, SoftIrq},
     sync::{OnceCell, SpinMutex},
+    usb::{self, xhci::Controller, DeviceInfo, PortEvent},
 };
-use core::{
-    pin::Pin,
-    sync::atomic::{AtomicBool, Ordering},
-    task::{Context, Poll},
+use alloc::{collections::BTreeMap, format, vec::Vec};
+use core::future::Future;
+use x86_64::{
+    instructions::interrupts,
+    structures::{idt::InterruptStackFrame, paging::OffsetPageTable},
+    PhysAddr,
 };
-use futures_util::{task::AtomicWaker, Stream};
-use mikanos_usb as usb;
-use x86_64::structures::{idt::InterruptStackFrame, paging::OffsetPageTable};
-
-static XHC: OnceCell<SpinMutex<&'static mut usb::xhci::Controller>> = OnceCell::uninit();
-
-pub(crate) fn init(devices: &[Device], mapper: &mut OffsetPageTable) -> Result<()> {
-    let mut xhc_dev = None;
-    for dev in devices {
-        // is the device is xHC?
-        if dev.class_code.test3(0x0c, 0x03, 0x30) {
-            xhc_dev = Some(dev);
-
-            if dev.vendor_id == 0x8086 {
-                // prefer Intel's xHC
-                break;
-            }
-        }
-    }
 
-    let xhc_dev = xhc_dev.ok_or(ErrorKind::XhcNotFound)?;
-    info!("xHC has been found: {}", xhc_dev);
+static XHC: OnceCell<SpinMutex<Controller>> = OnceCell::uninit();
+/// The registry entry of the xHC, which is the parent of USB devices.
+static DEVICE_ID: OnceCell<DeviceId> = OnceCell::uninit();
+static VECTOR: OnceCell<u8> = OnceCell::uninit();
+
+pub(crate) static DRIVER: Driver = Driver {
+    name: "xhc",
+    // prefer Intel's xHC
+    rules: &[
+        MatchRule::class(0x0c, 0x03, 0x30).vendor(0x8086),
+        MatchRule::class(0x0c, 0x03, 0x30),
+    ],
+    max_devices: 1,
+    probe,
+};
 
-    let bsp_local_apic_id = unsafe { *(0xfee00020 as *const u32) } >> 24;
-    pci::configure_msi_fixed_destination(
+fn probe(xhc_dev: &Device, cx: &mut ProbeContext<'_>) -> Result<()> {
+    let mapper = &mut *cx.mapper;
+    xhc_dev.enable_bus_master();
+
+    let bsp_local_apic_id = u32::from(local_apic::id());
+    let (vector, _) = pci::enable_msi(
         xhc_dev,
+        mapper,
+        "xhci",
         bsp_local_apic_id,
         MsiTriggerMode::Level,
         MsiDeliveryMode::Fixed,
-        InterruptIndex::Xhci,
-        0,
+        &[interrupt_handler],
     )?;
+    VECTOR.init_once(|| vector);
 
     let xhc_bar = pci::read_bar(xhc_dev, 0)?;
-    debug!("xHC BAR0 = {:08x}", xhc_bar);
-    let xhc_mmio_base = xhc_bar & !0xf;
-    debug!("xHC mmio_base = {:08x}", xhc_mmio_base);
-
-    map_xhc_mmio(mapper, xhc_mmio_base)?;
-    alloc_memory_pool(mapper)?;
+    debug!("xHC BAR0 = {:x?}", xhc_bar);
+    let (xhc_mmio_base, xhc_mmio_size) = match xhc_bar {
+        Some(BarResource::Memory { addr, size, .. }) => (addr, size),
+        _ => bail!(ErrorKind::InvalidBar),
+    };
 
-    let xhc = unsafe { usb::xhci::Controller::new(xhc_mmio_base) };
+    let xhc_mmio = map_xhc_mmio(mapper, xhc_mmio_base, xhc_mmio_size)?;
 
     if xhc_dev.vendor_id == 0x8086 {
-        switch_ehci_to_xhci(devices, xhc_dev);
+        switch_ehci_to_xhci(cx.devices, xhc_dev);
     }
 
-    xhc.init();
+    let mut xhc = Controller::new(xhc_mmio)?;
     debug!("xhc starting");
-    xhc.run()?;
+    xhc.run();
 
-    usb::HidMouseDriver::set_default_observer(mouse::observer);
-    usb::HidKeyboardDriver::set_default_observer(keyboard::observer);
+    usb::hid::set_mouse_observer(mouse::observer);
+    usb::hid::set_keyboard_observer(keyboard::observer);
 
     xhc.configure_connected_ports();
 
     XHC.init_once(move || SpinMutex::new(xhc));
+    DEVICE_ID.init_once(|| cx.device_id);
+    softirq::register(SoftIrq::Xhci, process_events);
 
     Ok(())
 }
 
-fn map_xhc_mmio(mapper: &mut OffsetPageTable, xhc_mmio_base: u64) -> Result<()> {
-    // Map [xhc_mmio_base..(xhc_mmio_base+64kib)] as identity map
-    let mut allocator = memory::lock_memory_manager();
-    paging::make_identity_mapping(mapper, &mut *allocator, xhc_mmio_base, 16)
+/// Registers a USB device handled by the class driver `driver`.
+///
+/// Class drivers register their device when it first reports an event.
+pub(crate) fn register_usb_device(name: &'static str, driver: &'static str) -> DeviceId {
+    let parent = DEVICE_ID.try_get().ok().copied();
+    device::register(parent, name, Some(driver), DeviceState::Bound)
 }
 
-fn alloc_memory_pool(mapper: &mut OffsetPageTable) -> Result<()> {
-    let num_frames = 32;
-    let mut allocator = memory::lock_memory_manager();
-    let frame_range = allocator.allocate(num_frames)?;
-    let base_addr = frame_range.start.start_address().as_u64();
-    paging::make_identity_mapping(mapper, &mut *allocator, base_addr, num_frames)?;
-    unsafe { usb::set_memory_pool(base_addr, num_frames * (memory::BYTES_PER_FRAME as usize)) };
-    Ok(())
+/// Returns the number of the root hub ports and the devices attached to them, or `None` if no xHC
+/// is found.
+pub(crate) fn devices() -> Option<(u8, Vec<DeviceInfo>)> {
+    let xhc = XHC.try_get().ok()?;
+    // the controller is also locked by the bottom half of the interrupt handler
+    interrupts::without_interrupts(|| {
+        let xhc = xhc.lock();
+        Some((xhc.max_ports(), xhc.devices()))
+    })
+}
+
+/// Records the devices attached to the root hub ports in the device registry, and marks them
+/// detached when they are unplugged.
+pub(crate) fn hotplug_task() -> impl Future<Output = Result<()>> {
+    // Subscribe before co-task starts, so that the attaches found by probe are not missed
+    let rx = XHC
+        .try_get()
+        .ok()
+        .map(|xhc| xhc.lock().subscribe_port_events());
+
+    async move {
+        let mut rx = match rx {
+            Some(rx) => rx,
+            None => return Ok(()),
+        };
+        let parent = DEVICE_ID.try_get().ok().copied();
+        let mut port_devices = BTreeMap::new();
+        while let Some(event) = rx.next().await {
+            match event {
+                PortEvent::Attached { port, slot_id } => {
+                    info!("usb: device attached to port {} (slot {})", port, slot_id);
+                    let name = format!("USB device (port {})", port);
+                    let id = device::register(parent, name, None, DeviceState::Bound);
+                    port_devices.insert(port, id);
+                }
+                PortEvent::Detached { port } => {
+                    info!("usb: device detached from port {}", port);
+                    if let Some(id) = port_devices.remove(&port) {
+                        device::set_state(id, None, DeviceState::Detached);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn map_xhc_mmio(
+    mapper: &mut OffsetPageTable,
+    xhc_mmio_base: PhysAddr,
+    xhc_mmio_size: u64,
+) -> Result<VolatileMmio> {
+    memory::with_memory_manager(|allocator| {
+        paging::map_mmio(mapper, allocator, xhc_mmio_base, xhc_mmio_size)
+    })
 }
 
 fn switch_ehci_to_xhci(devices: &[Device], xhc_dev: &Device) {
@@ -106,54 +164,19 @@ fn switch_ehci_to_xhci(devices: &[Device], xhc_dev: &Device) {
     );
 }
 
-static INTERRUPTED_FLAG: AtomicBool = AtomicBool::new(false);
-static WAKER: AtomicWaker = AtomicWaker::new();
-
-#[derive(Debug)]
-struct InterruptStream {
-    _private: (),
-}
-
-impl InterruptStream {
-    fn new() -> Self {
-        Self { _private: () }
-    }
-}
-
-impl Stream for InterruptStream {
-    type Item = ();
-
-    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        // fast path
-        if INTERRUPTED_FLAG.swap(false, Ordering::Relaxed) {
-            return Poll::Ready(Some(()));
-        }
-
-        WAKER.register(cx.waker());
-        if INTERRUPTED_FLAG.swap(false, Ordering::Relaxed) {
-            WAKER.take();
-            Poll::Ready(Some(()))
-        } else {
-            Poll::Pending
-        }
-    }
-}
-
-pub(crate) extern "x86-interrupt" fn interrupt_handler(_stack_frame: InterruptStackFrame) {
+extern "x86-interrupt" fn interrupt_handler(_stack_frame: InterruptStackFrame) {
+    interrupt::record(*VECTOR.get());
     let _guard = InterruptContextGuard::new();
-    INTERRUPTED_FLAG.store(true, Ordering::Relaxed);
-    WAKER.wake();
+    softirq::raise(SoftIrq::Xhci);
     interrupt::notify_end_of_interrupt();
 }
 
-pub(crate) async fn handler_task() {
-    let mut interrupts = InterruptStream::new();
-    while let Some(()) = interrupts.next().await {
-        let mut xhc = XHC.get().lock();
-        while xhc.has_event() {
-            if let Err(err) = xhc.process_event().map_err(Error::from) {
-                error!("error while process_event: {}", err);
-            }
+/// Processes the events of xHC. This runs as the bottom half of [`interrupt_handler`].
+fn process_events() {
+    let mut xhc = XHC.get().lock();
+    while xhc.has_event() {
+        if let Err(err) = xhc.process_event() {
+            error!("error while process_event: {}", err);
         }
     }
 }